@@ -0,0 +1,73 @@
+/// Short names of common commands, known from other music bots. Every alias is registered
+/// globally and hidden in /help, each guild decides which ones can be used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum CommandAlias {
+    #[name = "/p → /play"]
+    P,
+    #[name = "/np → /now_playing"]
+    Np,
+    #[name = "/q → /queue"]
+    Q,
+    #[name = "/s → /skip"]
+    S,
+}
+
+impl CommandAlias {
+    pub const ALL: [CommandAlias; 4] = [
+        CommandAlias::P,
+        CommandAlias::Np,
+        CommandAlias::Q,
+        CommandAlias::S,
+    ];
+
+    /// Name of the alias command
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandAlias::P => "p",
+            CommandAlias::Np => "np",
+            CommandAlias::Q => "q",
+            CommandAlias::S => "s",
+        }
+    }
+
+    /// Name of the command the alias runs
+    pub fn target(&self) -> &'static str {
+        match self {
+            CommandAlias::P => "play",
+            CommandAlias::Np => "now_playing",
+            CommandAlias::Q => "queue",
+            CommandAlias::S => "skip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|alias| alias.name() == name)
+    }
+
+    fn bit(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+/// Aliases enabled in a guild, none by default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AliasSet(u8);
+
+impl AliasSet {
+    pub fn contains(&self, alias: CommandAlias) -> bool {
+        self.0 & alias.bit() != 0
+    }
+
+    pub fn set(&mut self, alias: CommandAlias, enabled: bool) {
+        match enabled {
+            true => self.0 |= alias.bit(),
+            false => self.0 &= !alias.bit(),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = CommandAlias> + '_ {
+        CommandAlias::ALL
+            .into_iter()
+            .filter(|alias| self.contains(*alias))
+    }
+}
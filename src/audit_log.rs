@@ -0,0 +1,275 @@
+use crate::lifecycle::GuildPersisted;
+use crate::persistence::{PersistedFile, PersistenceHealth};
+use crate::title_sanitize::escape_invisible;
+use serde::{Deserialize, Serialize};
+use serenity::all::{GuildId, UserId};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Number of remembered actions per guild
+const AUDIT_LOG_SIZE: usize = 200;
+/// Original titles are cut in the rendered log, the entry keeps them in full
+const ORIGINAL_TITLE_CHARS: usize = 300;
+
+/// How a track got into the queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EnqueueOrigin {
+    Play,
+    Playlist,
+    Staged,
+    Restore,
+    Scheduled,
+    Autoplay,
+    Import,
+}
+
+impl EnqueueOrigin {
+    pub fn describe(self) -> &'static str {
+        match self {
+            EnqueueOrigin::Play => "/play",
+            EnqueueOrigin::Playlist => "Playlist",
+            EnqueueOrigin::Staged => "vorgemerkt",
+            EnqueueOrigin::Restore => "wiederhergestellt",
+            EnqueueOrigin::Scheduled => "geplant",
+            EnqueueOrigin::Autoplay => "Autoplay",
+            EnqueueOrigin::Import => "Import",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum AuditAction {
+    Enqueued {
+        origin: EnqueueOrigin,
+        title: String,
+        url: String,
+        /// Title before it was sanitized, if that changed it
+        original_title: Option<String>,
+    },
+    Skipped {
+        title: String,
+    },
+    Stopped {
+        removed: usize,
+    },
+    Removed {
+        count: usize,
+    },
+    Reported {
+        title: String,
+        url: String,
+        reason: String,
+    },
+    Blocked {
+        entry: String,
+    },
+}
+
+impl AuditAction {
+    pub fn describe(&self) -> String {
+        match self {
+            AuditAction::Enqueued {
+                origin,
+                title,
+                url,
+                original_title,
+            } => {
+                let added = format!("hat [{title}]({url}) hinzugefügt ({})", origin.describe());
+                match original_title {
+                    Some(original) => format!(
+                        "{added}, Originaltitel: `{}`",
+                        escape_invisible(original, ORIGINAL_TITLE_CHARS).replace('`', "'")
+                    ),
+                    None => added,
+                }
+            }
+            AuditAction::Skipped { title } => format!("hat `{title}` übersprungen"),
+            AuditAction::Stopped { removed } => {
+                format!("hat die Wiedergabe gestoppt ({removed} Einträge entfernt)")
+            }
+            AuditAction::Removed { count } => format!("hat {count} Einträge entfernt"),
+            AuditAction::Reported { title, url, reason } => {
+                format!(
+                    "hat [{title}]({url}) gemeldet: {}",
+                    reason.replace('`', "'")
+                )
+            }
+            AuditAction::Blocked { entry } => format!("hat {entry} gesperrt"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub at: SystemTime,
+    pub actor: Option<UserId>,
+    pub action: AuditAction,
+}
+
+/// An entry as it is written to the file
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    guild_id: GuildId,
+    #[serde(flatten)]
+    entry: AuditEntry,
+}
+
+/// Who changed the queue of a guild and how, so moderators can look it up after the fact.
+/// Newest first, written to a file if one is configured.
+pub struct AuditLog {
+    guilds: Mutex<HashMap<GuildId, VecDeque<AuditEntry>>>,
+    file: PersistedFile,
+}
+
+impl AuditLog {
+    /// Loads the log from the file, starts empty if it does not exist yet or can not be read
+    pub fn load(file: Option<PathBuf>, health: Arc<PersistenceHealth>) -> Self {
+        let file = PersistedFile::new("audit log", file, health);
+        let mut guilds = HashMap::<_, VecDeque<_>>::new();
+        // The file is newest first as well
+        for stored in file.load::<StoredEntry>() {
+            let log = guilds.entry(stored.guild_id).or_default();
+            if log.len() < AUDIT_LOG_SIZE {
+                log.push_back(stored.entry);
+            }
+        }
+        Self {
+            guilds: Mutex::new(guilds),
+            file,
+        }
+    }
+
+    fn persist(&self, guilds: &HashMap<GuildId, VecDeque<AuditEntry>>) {
+        let stored = guilds
+            .iter()
+            .flat_map(|(guild_id, log)| {
+                log.iter().map(|entry| StoredEntry {
+                    guild_id: *guild_id,
+                    entry: entry.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        self.file.save(&stored);
+    }
+
+    pub fn record(&self, guild_id: GuildId, actor: Option<UserId>, action: AuditAction) {
+        let mut guilds = self.guilds.lock().unwrap();
+        let log = guilds.entry(guild_id).or_default();
+
+        log.truncate(AUDIT_LOG_SIZE - 1);
+        log.push_front(AuditEntry {
+            at: SystemTime::now(),
+            actor,
+            action,
+        });
+        self.persist(&guilds);
+    }
+
+    /// Entries of the guild, optionally only those of one user
+    pub fn entries(&self, guild_id: GuildId, actor: Option<UserId>) -> Vec<AuditEntry> {
+        let guilds = self.guilds.lock().unwrap();
+        let Some(log) = guilds.get(&guild_id) else {
+            return vec![];
+        };
+
+        log.iter()
+            .filter(|entry| actor.is_none() || entry.actor == actor)
+            .cloned()
+            .collect()
+    }
+}
+
+impl GuildPersisted for AuditLog {
+    fn purge(&self, guild_id: GuildId) {
+        let mut guilds = self.guilds.lock().unwrap();
+        if guilds.remove(&guild_id).is_some() {
+            self.persist(&guilds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const USER: UserId = UserId::new(10);
+
+    fn skipped(title: &str) -> AuditAction {
+        AuditAction::Skipped {
+            title: title.to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn log_survives_a_restart() {
+        let path =
+            std::env::temp_dir().join(format!("gerbot-audit-log-{}.json", std::process::id()));
+        let health = Arc::new(PersistenceHealth::default());
+
+        let log = AuditLog::load(Some(path.clone()), health.clone());
+        log.record(
+            GUILD,
+            Some(USER),
+            AuditAction::Enqueued {
+                origin: EnqueueOrigin::Playlist,
+                title: "Song".to_owned(),
+                url: "https://www.youtube.com/watch?v=aaaaaaaaaaa".to_owned(),
+                original_title: None,
+            },
+        );
+        log.record(GUILD, None, AuditAction::Stopped { removed: 3 });
+
+        let reloaded = AuditLog::load(Some(path.clone()), health);
+        std::fs::remove_file(&path).unwrap();
+        let entries = reloaded.entries(GUILD, None);
+        assert_eq!(entries.len(), 2);
+        // Newest first
+        assert!(matches!(
+            entries[0].action,
+            AuditAction::Stopped { removed: 3 }
+        ));
+        assert_eq!(entries[1].actor, Some(USER));
+        assert_eq!(
+            entries[1].action.describe(),
+            log.entries(GUILD, None)[1].action.describe()
+        );
+    }
+
+    #[test]
+    fn retention_is_enforced_on_write() {
+        let log = AuditLog::load(None, Arc::default());
+        for i in 0..AUDIT_LOG_SIZE + 5 {
+            log.record(GUILD, None, skipped(&i.to_string()));
+        }
+        let entries = log.entries(GUILD, None);
+        assert_eq!(entries.len(), AUDIT_LOG_SIZE);
+        assert_eq!(
+            entries[0].action.describe(),
+            skipped(&(AUDIT_LOG_SIZE + 4).to_string()).describe()
+        );
+    }
+
+    #[test]
+    fn entries_can_be_filtered_by_actor() {
+        let log = AuditLog::load(None, Arc::default());
+        log.record(GUILD, Some(USER), skipped("a"));
+        log.record(GUILD, Some(UserId::new(11)), skipped("b"));
+        log.record(GUILD, None, skipped("c"));
+        assert_eq!(log.entries(GUILD, Some(USER)).len(), 1);
+        assert_eq!(log.entries(GUILD, None).len(), 3);
+    }
+
+    #[test]
+    fn purge_drops_only_the_guild() {
+        let log = AuditLog::load(None, Arc::default());
+        let other = GuildId::new(2);
+        log.record(GUILD, None, skipped("a"));
+        log.record(other, None, skipped("b"));
+        log.purge(GUILD);
+        assert!(log.entries(GUILD, None).is_empty());
+        assert_eq!(log.entries(other, None).len(), 1);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use log::info;
+use serenity::all::GuildId;
+use songbird::tracks::{PlayMode, TrackQueue};
+use std::collections::HashMap;
+
+/// Why the bot paused playback by itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AutoPauseReason {
+    /// Everyone in the channel is deafened
+    NobodyListening,
+}
+
+impl AutoPauseReason {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            AutoPauseReason::NobodyListening => "pausiert — niemand hört zu",
+        }
+    }
+}
+
+/// Guilds whose playback the bot paused by itself and resumes by itself, and guilds waiting to
+/// be paused after a delay
+#[derive(Default)]
+pub struct AutoPauses {
+    paused: GuildStateMap<AutoPauseReason>,
+    pending: GuildStateMap<()>,
+}
+
+impl AutoPauses {
+    pub fn reason(&self, guild_id: GuildId) -> Option<AutoPauseReason> {
+        self.paused.get(guild_id)
+    }
+
+    /// Returns false if a pause is already waiting for its delay
+    pub fn schedule(&self, guild_id: GuildId) -> bool {
+        self.pending.insert(guild_id, ()).is_none()
+    }
+
+    pub fn cancel(&self, guild_id: GuildId) {
+        self.pending.remove(guild_id);
+    }
+
+    /// Pauses the current track if it is playing and nobody cancelled the pause in the meantime.
+    /// Returns whether it paused.
+    pub async fn pause(
+        &self,
+        guild_id: GuildId,
+        queue: &TrackQueue,
+        reason: AutoPauseReason,
+    ) -> bool {
+        if self.pending.remove(guild_id).is_none() {
+            return false;
+        }
+        let Some(current) = queue.current() else {
+            return false;
+        };
+        let playing = current
+            .get_info()
+            .await
+            .is_ok_and(|info| info.playing == PlayMode::Play);
+        if playing && queue.pause().is_ok() {
+            info!("Paused playback in guild {guild_id}: {reason:?}");
+            self.paused.insert(guild_id, reason);
+            return true;
+        }
+        false
+    }
+
+    /// Resumes playback that was paused by the bot, playback paused by a user stays paused.
+    /// Returns whether it resumed.
+    pub fn resume(&self, guild_id: GuildId, queue: &TrackQueue) -> bool {
+        self.cancel(guild_id);
+        let Some(reason) = self.paused.remove(guild_id) else {
+            return false;
+        };
+        info!("Resumed playback in guild {guild_id} after {reason:?}");
+        queue.resume().is_ok()
+    }
+}
+
+impl GuildScoped for AutoPauses {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::AutoPauses
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.paused.entry_counts(|_| 1)
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.paused.remove_on_leave(guild_id);
+        self.pending.remove_on_leave(guild_id);
+    }
+}
@@ -0,0 +1,240 @@
+use crate::blocklist::Blocklist;
+use crate::canonical_url::{canonical_url, same_track};
+use crate::commands::util::{
+    get_blocklist, get_guild_settings, get_history, get_metadata, get_playback_events,
+    get_playback_modes, get_youtube_client,
+};
+use crate::events::PlaybackEvent;
+use crate::guild_settings::{DurationVerdict, GuildSettings};
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use crate::history::PlayHistory;
+use crate::metadata::TrackMetadata;
+use crate::youtube::{YoutubeClient, YtResourceId, YtSearchFilter};
+use log::{info, warn};
+use serenity::all::{Context, GuildId};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+/// Search results that are considered per pick
+const SEARCH_RESULTS: u8 = 10;
+/// Tracks played this recently are not suggested again
+const RECENT_TRACKS: usize = 20;
+
+/// Track that plays when the current one ends and the queue is still empty
+#[derive(Clone)]
+pub struct AutoplaySuggestion {
+    /// The track that was playing when the suggestion was picked
+    pub after: Uuid,
+    pub metadata: Arc<TrackMetadata>,
+}
+
+impl AutoplaySuggestion {
+    /// Dimmed line below a listing of the queue or the current track
+    pub fn render(&self) -> String {
+        format!(
+            "-# Autoplay-Vorschlag: [{}]({})",
+            self.metadata.title, self.metadata.source_url
+        )
+    }
+}
+
+#[derive(Default)]
+struct AutoplayState {
+    suggestion: Option<AutoplaySuggestion>,
+    /// Canonical urls of rejected suggestions, until playback stops
+    rejected: HashSet<String>,
+}
+
+/// The pre-selected autoplay track of every guild
+#[derive(Default)]
+pub struct Autoplay {
+    guilds: GuildStateMap<AutoplayState>,
+}
+
+impl Autoplay {
+    pub fn suggestion(&self, guild_id: GuildId) -> Option<AutoplaySuggestion> {
+        self.guilds
+            .with(guild_id, |state| state.and_then(|s| s.suggestion.clone()))
+    }
+
+    fn set_suggestion(&self, guild_id: GuildId, suggestion: Option<AutoplaySuggestion>) {
+        self.guilds
+            .with_mut(guild_id, |state| state.suggestion = suggestion);
+    }
+
+    /// Removes the suggestion to play it
+    pub fn take(&self, guild_id: GuildId) -> Option<AutoplaySuggestion> {
+        self.guilds
+            .with_mut(guild_id, |state| state.suggestion.take())
+    }
+
+    /// Removes the suggestion and never suggests its track again until playback stops
+    pub fn reject(&self, guild_id: GuildId) -> Option<AutoplaySuggestion> {
+        self.guilds.with_mut(guild_id, |state| {
+            let rejected = state.suggestion.take()?;
+            state
+                .rejected
+                .insert(canonical_url(&rejected.metadata.source_url));
+            Some(rejected)
+        })
+    }
+
+    fn is_rejected(&self, guild_id: GuildId, url: &str) -> bool {
+        self.guilds.with(guild_id, |state| {
+            state.is_some_and(|s| s.rejected.iter().any(|rejected| same_track(rejected, url)))
+        })
+    }
+
+    /// Playback stopped, rejected tracks can be suggested again
+    fn end_session(&self, guild_id: GuildId) {
+        self.guilds.remove(guild_id);
+    }
+}
+
+impl GuildScoped for Autoplay {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::Autoplay
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.guilds
+            .entry_counts(|state| usize::from(state.suggestion.is_some()) + state.rejected.len())
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.guilds.remove_on_leave(guild_id);
+    }
+}
+
+/// Keeps the suggestion of every guild up to date with its queue. Runs until the process exits.
+pub async fn run_autoplay(ctx: Context, autoplay: Arc<Autoplay>) {
+    let mut events = get_playback_events(&ctx).await.subscribe();
+    loop {
+        match events.recv().await {
+            Ok((guild_id, PlaybackEvent::Stopped)) => autoplay.end_session(guild_id),
+            // Pausing does not change what plays next
+            Ok((_, PlaybackEvent::PlayStateChanged)) => {}
+            Ok((guild_id, _)) => refresh(&ctx, &autoplay, guild_id).await,
+            // Missed guilds are refreshed with their next event
+            Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Picks a suggestion if autoplay is on and nothing follows the current track, and drops the
+/// suggestion as soon as something does. A rejected suggestion is replaced right away.
+pub async fn refresh(ctx: &Context, autoplay: &Autoplay, guild_id: GuildId) {
+    if !get_playback_modes(ctx).await.get(guild_id).autoplay {
+        autoplay.set_suggestion(guild_id, None);
+        return;
+    }
+    let Some(call) = songbird::get(ctx)
+        .await
+        .and_then(|songbird| songbird.get(guild_id))
+    else {
+        return;
+    };
+    let (current, has_upcoming) = {
+        let call = call.lock().await;
+        (call.queue().current(), call.queue().len() > 1)
+    };
+    if has_upcoming {
+        autoplay.set_suggestion(guild_id, None);
+        return;
+    }
+    let Some(current) = current else {
+        return;
+    };
+    if autoplay
+        .suggestion(guild_id)
+        .is_some_and(|s| s.after == current.uuid())
+    {
+        return;
+    }
+
+    let metadata = get_metadata(&current).await;
+    let settings = get_guild_settings(ctx).await.get(guild_id);
+    let picked = pick(
+        &get_youtube_client(ctx).await,
+        &*get_history(ctx).await,
+        |url| same_track(url, metadata.source_url.as_str()) || autoplay.is_rejected(guild_id, url),
+        &settings,
+        &*get_blocklist(ctx).await,
+        guild_id,
+        &metadata,
+    )
+    .await;
+    match &picked {
+        Some(picked) => info!("Autoplay suggestion for guild {guild_id}: {}", picked.title),
+        None => info!("No autoplay suggestion found for guild {guild_id}"),
+    }
+    autoplay.set_suggestion(
+        guild_id,
+        picked.map(|metadata| AutoplaySuggestion {
+            after: current.uuid(),
+            metadata,
+        }),
+    );
+}
+
+/// A video of the artist of the current track that was not played recently. Without a usable
+/// search, for example with an exhausted quota, an older track of the history is picked instead.
+async fn pick(
+    youtube_client: &YoutubeClient,
+    history: &PlayHistory,
+    excluded: impl Fn(&str) -> bool,
+    settings: &GuildSettings,
+    blocklist: &Blocklist,
+    guild_id: GuildId,
+    current: &TrackMetadata,
+) -> Option<Arc<TrackMetadata>> {
+    let recent = history.recent(guild_id, RECENT_TRACKS);
+    let is_new =
+        |url: &str| !excluded(url) && !recent.iter().any(|entry| same_track(&entry.url, url));
+
+    if !youtube_client.autocomplete_degraded() {
+        let clean = current.clean_title();
+        let query = clean.artist.unwrap_or(clean.title);
+        match youtube_client
+            .search(&query, YtSearchFilter::Videos, SEARCH_RESULTS)
+            .await
+        {
+            Ok(results) => {
+                for result in results {
+                    let YtResourceId::Video(video_id) = &result.id else {
+                        continue;
+                    };
+                    if !is_new(result.get_yt_url().as_str()) {
+                        continue;
+                    }
+                    let Ok(video) = youtube_client.get_video(video_id).await else {
+                        continue;
+                    };
+                    let metadata = TrackMetadata::from(video);
+                    if settings.duration_verdict(metadata.duration) == DurationVerdict::Allowed
+                        && blocklist.blocking(guild_id, &metadata).is_none()
+                    {
+                        return Some(Arc::new(metadata));
+                    }
+                }
+            }
+            Err(e) => warn!("Autoplay search for `{query}` failed: {e}"),
+        }
+    }
+
+    history
+        .recent(guild_id, usize::MAX)
+        .into_iter()
+        .skip(RECENT_TRACKS)
+        .filter(|entry| !excluded(&entry.url))
+        .map(|entry| {
+            let mut metadata = TrackMetadata::unresolved(&entry.url);
+            metadata.title = entry.title;
+            metadata
+        })
+        .find(|metadata| blocklist.blocking(guild_id, metadata).is_none())
+        .map(Arc::new)
+}
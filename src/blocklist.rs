@@ -0,0 +1,114 @@
+use crate::canonical_url::canonical_url;
+use crate::lifecycle::GuildPersisted;
+use crate::metadata::TrackMetadata;
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Something that cannot be played in a guild
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum BlockedEntry {
+    /// A track by its url as returned by [canonical_url]
+    Url(String),
+    /// Every track of a channel, compared case insensitively
+    Channel(String),
+}
+
+impl BlockedEntry {
+    pub fn describe(&self) -> String {
+        match self {
+            BlockedEntry::Url(url) => format!("Link `{url}`"),
+            BlockedEntry::Channel(channel) => format!("Kanal `{channel}`"),
+        }
+    }
+
+    /// Identifies the entry in autocomplete values
+    pub fn key(&self) -> String {
+        match self {
+            BlockedEntry::Url(url) => format!("url:{url}"),
+            BlockedEntry::Channel(channel) => format!("channel:{channel}"),
+        }
+    }
+
+    /// The blocked url or channel name
+    pub fn value(&self) -> &str {
+        match self {
+            BlockedEntry::Url(url) => url,
+            BlockedEntry::Channel(channel) => channel,
+        }
+    }
+
+    fn matches(&self, url: &str, channel: &str) -> bool {
+        match self {
+            BlockedEntry::Url(blocked) => blocked == url,
+            BlockedEntry::Channel(blocked) => blocked.eq_ignore_ascii_case(channel),
+        }
+    }
+}
+
+/// Tracks and channels moderators blocked from being played, per guild
+#[derive(Default)]
+pub struct Blocklist {
+    guilds: Mutex<HashMap<GuildId, Vec<BlockedEntry>>>,
+}
+
+impl Blocklist {
+    /// Returns false if the entry was already blocked
+    pub fn add(&self, guild_id: GuildId, entry: BlockedEntry) -> bool {
+        let mut guilds = self.guilds.lock().unwrap();
+        let entries = guilds.entry(guild_id).or_default();
+        if entries.contains(&entry) {
+            return false;
+        }
+        entries.push(entry);
+        true
+    }
+
+    /// Returns false if the entry was not blocked
+    pub fn remove(&self, guild_id: GuildId, entry: &BlockedEntry) -> bool {
+        let mut guilds = self.guilds.lock().unwrap();
+        let Some(entries) = guilds.get_mut(&guild_id) else {
+            return false;
+        };
+        let before = entries.len();
+        entries.retain(|e| e != entry);
+        entries.len() != before
+    }
+
+    /// The entry with the key, or with the url or channel name if it was typed in
+    pub fn find(&self, guild_id: GuildId, input: &str) -> Option<BlockedEntry> {
+        self.entries(guild_id)
+            .into_iter()
+            .find(|entry| entry.key() == input || entry.value() == input)
+    }
+
+    pub fn entries(&self, guild_id: GuildId) -> Vec<BlockedEntry> {
+        let guilds = self.guilds.lock().unwrap();
+        guilds.get(&guild_id).cloned().unwrap_or_default()
+    }
+
+    /// Replaces all entries of a guild, for /settings import
+    pub fn replace(&self, guild_id: GuildId, entries: Vec<BlockedEntry>) {
+        self.guilds.lock().unwrap().insert(guild_id, entries);
+    }
+
+    /// The entry that keeps the track from being played, if any
+    pub fn blocking(&self, guild_id: GuildId, metadata: &TrackMetadata) -> Option<BlockedEntry> {
+        // Playlist, timestamp and tracking parameters do not get around a block
+        let url = canonical_url(&metadata.source_url);
+        let guilds = self.guilds.lock().unwrap();
+        guilds
+            .get(&guild_id)?
+            .iter()
+            .find(|entry| entry.matches(&url, &metadata.author))
+            .cloned()
+    }
+}
+
+impl GuildPersisted for Blocklist {
+    fn purge(&self, guild_id: GuildId) {
+        self.guilds.lock().unwrap().remove(&guild_id);
+    }
+}
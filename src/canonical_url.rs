@@ -0,0 +1,266 @@
+use reqwest::Url;
+
+/// Hosts that serve the same videos under `/watch?v=<id>` and the path forms below
+const YOUTUBE_HOSTS: [&str; 6] = [
+    "youtube.com",
+    "www.youtube.com",
+    "m.youtube.com",
+    "music.youtube.com",
+    "youtube-nocookie.com",
+    "www.youtube-nocookie.com",
+];
+/// Paths with the video id as their second segment, like `/shorts/<id>`
+const YOUTUBE_ID_PATHS: [&str; 4] = ["shorts", "embed", "live", "v"];
+
+/// One string per track, for everything that has to know whether two links play the same thing:
+/// blocklists, reports, statistics, history and autoplay.
+///
+/// YouTube links become `https://www.youtube.com/watch?v=<id>`, whatever host, path form,
+/// playlist, index, timestamp or share parameter they had. Other links keep their host
+/// (lowercased, without default port by the parser), lose their fragment, a trailing slash,
+/// tracking parameters and differences in percent-encoding. SoundCloud links lose their whole
+/// query, it only tells where the track was found.
+pub fn canonical_url(url: &Url) -> String {
+    if let Some(video_id) = youtube_video_id(url) {
+        return format!("https://www.youtube.com/watch?v={video_id}");
+    }
+
+    let mut canonical = url.clone();
+    canonical.set_fragment(None);
+    canonical.set_path(&normalize_path(url.path()));
+    let soundcloud = url
+        .host_str()
+        .is_some_and(|host| host == "soundcloud.com" || host.ends_with(".soundcloud.com"));
+    let query = match soundcloud {
+        true => vec![],
+        false => url
+            .query_pairs()
+            .filter(|(key, _)| !is_tracking_param(key))
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect(),
+    };
+    match query.is_empty() {
+        true => canonical.set_query(None),
+        // Encoded again, so `%20` and `+` or `%7e` and `~` end up the same
+        false => {
+            canonical.query_pairs_mut().clear().extend_pairs(query);
+        }
+    }
+    canonical.to_string()
+}
+
+/// Whether two links play the same track as far as [canonical_url] can tell. Text that is no
+/// link is only the same as itself.
+pub fn same_track(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    match (Url::parse(a), Url::parse(b)) {
+        (Ok(a), Ok(b)) => canonical_url(&a) == canonical_url(&b),
+        _ => false,
+    }
+}
+
+fn youtube_video_id(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+    let id = if host == "youtu.be" {
+        url.path_segments()?.next()?.to_owned()
+    } else if YOUTUBE_HOSTS.contains(&host) {
+        let mut segments = url.path_segments()?;
+        match segments.next()? {
+            "watch" => url
+                .query_pairs()
+                .find(|(key, _)| key == "v")
+                .map(|(_, value)| value.into_owned())?,
+            kind if YOUTUBE_ID_PATHS.contains(&kind) => segments.next()?.to_owned(),
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    let valid = !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then_some(id)
+}
+
+/// Parameters that only tell where a link was shared
+fn is_tracking_param(key: &str) -> bool {
+    key.starts_with("utm_") || matches!(key, "si" | "fbclid" | "gclid")
+}
+
+/// Decodes escaped characters that never need escaping, uppercases the remaining escapes and
+/// drops a trailing slash. Paths of parsed urls are always ASCII.
+fn normalize_path(path: &str) -> String {
+    let path = path
+        .strip_suffix('/')
+        .filter(|path| !path.is_empty())
+        .unwrap_or(path);
+    let mut normalized = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(c) = rest.chars().next() {
+        let escaped = rest
+            .get(1..3)
+            .filter(|hex| c == '%' && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) => {
+                normalized.push(byte as char);
+                rest = &rest[3..];
+            }
+            Some(byte) => {
+                normalized.push_str(&format!("%{byte:02X}"));
+                rest = &rest[3..];
+            }
+            None => {
+                normalized.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIDEO: &str = "https://www.youtube.com/watch?v=dQw4w9WgXcQ";
+
+    fn canonical(url: &str) -> String {
+        canonical_url(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn youtube_links_become_the_watch_link() {
+        let table = [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com/watch?v=dQw4w9WgXcQ",
+            "http://WWW.YouTube.com/watch?v=dQw4w9WgXcQ",
+            "https://m.youtube.com/watch?v=dQw4w9WgXcQ&feature=share",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ&list=RDAMVMdQw4w9WgXcQ",
+            "https://www.youtube.com/watch?list=PL123&index=4&v=dQw4w9WgXcQ&t=42s",
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ?si=abcdef&t=10",
+            "https://www.youtube.com/shorts/dQw4w9WgXcQ",
+            "https://youtube.com/shorts/dQw4w9WgXcQ/",
+            "https://www.youtube.com/embed/dQw4w9WgXcQ?autoplay=1",
+            "https://www.youtube-nocookie.com/embed/dQw4w9WgXcQ",
+            "https://youtube-nocookie.com/v/dQw4w9WgXcQ",
+            "https://www.youtube.com/live/dQw4w9WgXcQ?feature=shared",
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ#t=30",
+        ];
+        for url in table {
+            assert_eq!(canonical(url), VIDEO, "{url}");
+        }
+    }
+
+    #[test]
+    fn youtube_links_without_a_valid_video_stay_links() {
+        let table = [
+            (
+                "https://www.youtube.com/playlist?list=PL123",
+                "https://www.youtube.com/playlist?list=PL123",
+            ),
+            ("https://youtu.be/", "https://youtu.be/"),
+            (
+                "https://www.youtube.com/watch?v=bad%20id",
+                "https://www.youtube.com/watch?v=bad+id",
+            ),
+            (
+                "https://www.youtube.com/@channel/",
+                "https://www.youtube.com/@channel",
+            ),
+            // Not a YouTube host, even though it ends like one
+            (
+                "https://notyoutube.com/watch?v=dQw4w9WgXcQ",
+                "https://notyoutube.com/watch?v=dQw4w9WgXcQ",
+            ),
+        ];
+        for (url, expected) in table {
+            assert_eq!(canonical(url), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn other_links_are_normalized() {
+        let table = [
+            // Mixed case hosts, default ports and fragments
+            (
+                "HTTPS://CDN.Example.COM:443/Audio/Song.mp3#intro",
+                "https://cdn.example.com/Audio/Song.mp3",
+            ),
+            // Trailing slashes, but not the root
+            ("https://example.com/music/", "https://example.com/music"),
+            ("https://example.com/", "https://example.com/"),
+            // Percent-encoding of characters that never need it, and lowercase escapes
+            (
+                "https://example.com/%7Euser/%61bc%2fdef",
+                "https://example.com/~user/abc%2Fdef",
+            ),
+            (
+                "https://example.com/a%20b.mp3",
+                "https://example.com/a%20b.mp3",
+            ),
+            // Query encodings
+            (
+                "https://example.com/play?name=a%20b&x=%7e",
+                "https://example.com/play?name=a+b&x=%7E",
+            ),
+            (
+                "https://example.com/play?name=a+b&x=~",
+                "https://example.com/play?name=a+b&x=%7E",
+            ),
+        ];
+        for (url, expected) in table {
+            assert_eq!(canonical(url), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn tracking_parameters_are_dropped() {
+        let table = [
+            (
+                "https://example.com/song.mp3?utm_source=discord&utm_medium=share",
+                "https://example.com/song.mp3",
+            ),
+            (
+                "https://example.com/song.mp3?id=7&si=abc&fbclid=x&gclid=y",
+                "https://example.com/song.mp3?id=7",
+            ),
+            // Other parameters keep their order
+            (
+                "https://example.com/stream?b=2&utm_campaign=x&a=1",
+                "https://example.com/stream?b=2&a=1",
+            ),
+            // The whole query of SoundCloud links only tells where they were found
+            (
+                "https://soundcloud.com/artist/track?in=artist/sets/album&si=abc",
+                "https://soundcloud.com/artist/track",
+            ),
+            (
+                "https://m.soundcloud.com/artist/track/?utm_source=clipboard",
+                "https://m.soundcloud.com/artist/track",
+            ),
+        ];
+        for (url, expected) in table {
+            assert_eq!(canonical(url), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn same_track_compares_canonical_links() {
+        assert!(same_track(VIDEO, "https://youtu.be/dQw4w9WgXcQ?t=5"));
+        assert!(same_track(
+            "https://Example.com/a/",
+            "https://example.com/a?utm_source=x"
+        ));
+        assert!(!same_track(VIDEO, "https://youtu.be/9bZkp7q19f0"));
+        // Searches are only the same as themselves
+        assert!(same_track("never gonna", "never gonna"));
+        assert!(!same_track("never gonna", "Never Gonna"));
+        assert!(!same_track("never gonna", VIDEO));
+    }
+}
@@ -0,0 +1,402 @@
+use poise::serenity_prelude::{ResolvedOption, ResolvedValue};
+use serenity::all::{
+    Command as RegisteredCommand, CommandId, CommandOptionType, CreateCommand, CreateCommandOption,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::{CommandError, GlobalData};
+
+/// Version of the command signatures. Bump it whenever an option is renamed, removed or changes
+/// its type, and add an adapter for the previous shape to [`RENAMED_OPTIONS`].
+pub const COMMAND_SCHEMA: u32 = 1;
+
+/// Appended to every command description, so the schema of a registration can be read back from
+/// what Discord stores. Command ids stay the same when a registration is overwritten.
+const SCHEMA_MARKER: &str = " ·v";
+
+/// Discord's limit for command descriptions, counted in characters
+const MAX_DESCRIPTION: usize = 100;
+
+/// An option that was renamed in a previous schema. Kept for at least one release, because Discord
+/// clients can use cached registrations for a while after an update.
+struct RenamedOption {
+    command: &'static str,
+    old_name: &'static str,
+    new_name: &'static str,
+}
+
+/// No option was renamed since schema 1. Single renames without a type change are also handled
+/// without an entry here, see [`adapt_args`].
+const RENAMED_OPTIONS: &[RenamedOption] = &[];
+
+/// Remembers with which schema the commands were registered and how often the fallback was needed
+#[derive(Default)]
+pub struct CommandSchemas {
+    registered: Mutex<HashMap<CommandId, u32>>,
+    fallbacks: Mutex<HashMap<String, usize>>,
+}
+
+impl CommandSchemas {
+    /// Remembers the schema of the commands Discord returned for a registration
+    pub fn record(&self, commands: &[RegisteredCommand]) {
+        let mut registered = self.registered.lock().unwrap();
+        for command in commands {
+            match schema_of(&command.description) {
+                Some(schema) => registered.insert(command.id, schema),
+                None => registered.remove(&command.id),
+            };
+        }
+    }
+
+    /// Whether Discord stores the command with the current schema. Commands without a recorded
+    /// registration count as outdated.
+    pub fn is_current(&self, id: CommandId) -> bool {
+        self.registered.lock().unwrap().get(&id) == Some(&COMMAND_SCHEMA)
+    }
+
+    /// Counts a fired fallback for the command and returns a summary of all fallbacks so far
+    pub fn count_fallback(&self, command: &str) -> String {
+        let mut fallbacks = self.fallbacks.lock().unwrap();
+        *fallbacks.entry(command.to_owned()).or_default() += 1;
+
+        let total: usize = fallbacks.values().sum();
+        let mut per_command = fallbacks
+            .iter()
+            .map(|(name, count)| format!("/{name}: {count}"))
+            .collect::<Vec<String>>();
+        per_command.sort();
+        format!("{total} since start ({})", per_command.join(", "))
+    }
+}
+
+/// Builds the registration like `poise::builtins::create_application_commands`, with the schema
+/// marker appended to every slash command description
+pub fn registration(commands: &[poise::Command<GlobalData, CommandError>]) -> Vec<CreateCommand> {
+    fn add_context_menu_commands(
+        registration: &mut Vec<CreateCommand>,
+        command: &poise::Command<GlobalData, CommandError>,
+    ) {
+        registration.extend(command.create_as_context_menu_command());
+        for subcommand in &command.subcommands {
+            add_context_menu_commands(registration, subcommand);
+        }
+    }
+
+    let mut registration = Vec::new();
+    for command in commands {
+        if let Some(slash_command) = command.create_as_slash_command() {
+            let description = command.description.as_deref().unwrap_or("A slash command");
+            registration.push(slash_command.description(marked(description)));
+        }
+        add_context_menu_commands(&mut registration, command);
+    }
+    registration
+}
+
+/// Appends the schema marker, shortening the description if it would get too long
+fn marked(description: &str) -> String {
+    let marker = format!("{SCHEMA_MARKER}{COMMAND_SCHEMA}");
+    let available = MAX_DESCRIPTION - marker.chars().count();
+    let description = description.chars().take(available).collect::<String>();
+    format!("{}{marker}", description.trim_end())
+}
+
+/// Reads the schema marker back from a registered description
+fn schema_of(description: &str) -> Option<u32> {
+    description.rsplit_once(SCHEMA_MARKER)?.1.parse().ok()
+}
+
+/// Whether the arguments fit the current signature. Interactions are built from the registration
+/// the client knows, so arguments that don't fit come from an older one.
+pub fn matches_current(
+    command: &poise::Command<GlobalData, CommandError>,
+    args: &[ResolvedOption],
+) -> bool {
+    let known = args.iter().all(|arg| {
+        command.parameters.iter().any(|p| {
+            p.name == arg.name
+                && match (value_type(&arg.value), parameter_type(p)) {
+                    (Some(arg_type), Some(parameter_type)) => arg_type == parameter_type,
+                    _ => true,
+                }
+        })
+    });
+    let complete = command
+        .parameters
+        .iter()
+        .filter(|p| p.required)
+        .all(|p| args.iter().any(|arg| arg.name == p.name));
+    known && complete
+}
+
+/// Translates arguments of the previous signature to the current one. Returns None if nothing
+/// could be adapted, so retrying the command would fail the same way.
+pub fn adapt_args<'a>(
+    command: &'a poise::Command<GlobalData, CommandError>,
+    args: &[ResolvedOption<'a>],
+) -> Option<Vec<ResolvedOption<'a>>> {
+    adapt_with(RENAMED_OPTIONS, command, args)
+}
+
+fn adapt_with<'a>(
+    renamed_options: &[RenamedOption],
+    command: &'a poise::Command<GlobalData, CommandError>,
+    args: &[ResolvedOption<'a>],
+) -> Option<Vec<ResolvedOption<'a>>> {
+    let mut adapted = false;
+    let mut args = args.to_vec();
+
+    for arg in &mut args {
+        if let Some(renamed) = renamed_options
+            .iter()
+            .find(|r| r.command == command.qualified_name && r.old_name == arg.name)
+        {
+            arg.name = renamed.new_name;
+            adapted = true;
+        }
+    }
+
+    // A single renamed option can be matched up without an adapter if its type did not change
+    let unknown = args
+        .iter()
+        .enumerate()
+        .filter(|(_, arg)| !command.parameters.iter().any(|p| p.name == arg.name))
+        .map(|(i, _)| i)
+        .collect::<Vec<usize>>();
+    let missing = command
+        .parameters
+        .iter()
+        .filter(|p| p.required && !args.iter().any(|arg| arg.name == p.name))
+        .collect::<Vec<_>>();
+    if let ([unknown], [missing]) = (unknown.as_slice(), missing.as_slice()) {
+        let arg_type = value_type(&args[*unknown].value);
+        if arg_type.is_some() && arg_type == parameter_type(missing) {
+            args[*unknown].name = &missing.name;
+            adapted = true;
+        }
+    }
+
+    adapted.then_some(args)
+}
+
+fn value_type(value: &ResolvedValue) -> Option<CommandOptionType> {
+    Some(match value {
+        ResolvedValue::Autocomplete { kind, .. } => *kind,
+        ResolvedValue::Boolean(_) => CommandOptionType::Boolean,
+        ResolvedValue::Integer(_) => CommandOptionType::Integer,
+        ResolvedValue::Number(_) => CommandOptionType::Number,
+        ResolvedValue::String(_) => CommandOptionType::String,
+        ResolvedValue::Attachment(_) => CommandOptionType::Attachment,
+        ResolvedValue::Channel(_) => CommandOptionType::Channel,
+        ResolvedValue::Role(_) => CommandOptionType::Role,
+        ResolvedValue::User(..) => CommandOptionType::User,
+        _ => return None,
+    })
+}
+
+/// The option type is only available through the builder poise fills in for the registration
+fn parameter_type(
+    parameter: &poise::CommandParameter<GlobalData, CommandError>,
+) -> Option<CommandOptionType> {
+    let option = (parameter.type_setter?)(CreateCommandOption::new(
+        CommandOptionType::Unknown(0),
+        "",
+        "",
+    ));
+    let kind = serde_json::to_value(option).ok()?.get("type")?.as_u64()?;
+    Some(CommandOptionType::from(u8::try_from(kind).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CommandContext;
+    use serenity::all::CommandData;
+
+    /// Adds a song, the signature the tests adapt to
+    #[poise::command(slash_command)]
+    async fn enqueue(
+        _ctx: CommandContext<'_>,
+        #[description = "Link"] query: String,
+        #[description = "Position"] position: Option<i64>,
+        #[description = "Shuffle"] shuffle: Option<bool>,
+    ) -> Result<(), CommandError> {
+        _ = (query, position, shuffle);
+        Ok(())
+    }
+
+    /// Interaction data as Discord sends it for `/enqueue`
+    fn interaction(options: serde_json::Value) -> CommandData {
+        serde_json::from_value(serde_json::json!({
+            "id": "1",
+            "name": "enqueue",
+            "type": 1,
+            "options": options,
+        }))
+        .unwrap()
+    }
+
+    fn names<'a>(args: &'a [ResolvedOption]) -> Vec<&'a str> {
+        args.iter().map(|arg| arg.name).collect()
+    }
+
+    #[test]
+    fn parameter_types_come_from_the_registration() {
+        let command = enqueue();
+        let types = command
+            .parameters
+            .iter()
+            .map(parameter_type)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                Some(CommandOptionType::String),
+                Some(CommandOptionType::Integer),
+                Some(CommandOptionType::Boolean),
+            ]
+        );
+    }
+
+    #[test]
+    fn renamed_options_are_adapted() {
+        let command = enqueue();
+        let renamed = [RenamedOption {
+            command: "enqueue",
+            old_name: "at",
+            new_name: "position",
+        }];
+        let data = interaction(serde_json::json!([
+            { "name": "query", "type": 3, "value": "song" },
+            { "name": "at", "type": 4, "value": 3 },
+        ]));
+        let args = data.options();
+
+        let adapted = adapt_with(&renamed, &command, &args).unwrap();
+        assert_eq!(names(&adapted), ["query", "position"]);
+        assert!(matches_current(&command, &adapted));
+    }
+
+    #[test]
+    fn renames_of_other_commands_are_ignored() {
+        let command = enqueue();
+        let renamed = [RenamedOption {
+            command: "play",
+            old_name: "at",
+            new_name: "position",
+        }];
+        let data = interaction(serde_json::json!([
+            { "name": "query", "type": 3, "value": "song" },
+            { "name": "at", "type": 4, "value": 3 },
+        ]));
+
+        assert!(adapt_with(&renamed, &command, &data.options()).is_none());
+    }
+
+    #[test]
+    fn single_renamed_option_is_matched_by_type() {
+        let command = enqueue();
+        let data = interaction(serde_json::json!([
+            { "name": "link", "type": 3, "value": "song" },
+            { "name": "shuffle", "type": 5, "value": true },
+        ]));
+        let args = data.options();
+        assert!(!matches_current(&command, &args));
+
+        let adapted = adapt_args(&command, &args).unwrap();
+        assert_eq!(names(&adapted), ["query", "shuffle"]);
+        assert!(matches_current(&command, &adapted));
+    }
+
+    #[test]
+    fn renamed_option_with_another_type_is_not_adapted() {
+        let command = enqueue();
+        let data = interaction(serde_json::json!([
+            { "name": "link", "type": 4, "value": 5 },
+        ]));
+
+        assert!(adapt_args(&command, &data.options()).is_none());
+    }
+
+    #[test]
+    fn current_arguments_need_no_adapter() {
+        let command = enqueue();
+        let data = interaction(serde_json::json!([
+            { "name": "query", "type": 3, "value": "song" },
+            { "name": "position", "type": 4, "value": 2 },
+        ]));
+        let args = data.options();
+
+        assert!(matches_current(&command, &args));
+        assert!(adapt_args(&command, &args).is_none());
+    }
+
+    #[test]
+    fn changed_option_types_do_not_match() {
+        let command = enqueue();
+        let data = interaction(serde_json::json!([
+            { "name": "query", "type": 3, "value": "song" },
+            { "name": "position", "type": 3, "value": "2" },
+        ]));
+
+        assert!(!matches_current(&command, &data.options()));
+    }
+
+    #[test]
+    fn schema_is_read_back_from_the_description() {
+        let description = marked("Spielt ein Lied");
+        assert_eq!(description, format!("Spielt ein Lied ·v{COMMAND_SCHEMA}"));
+        assert_eq!(schema_of(&description), Some(COMMAND_SCHEMA));
+        assert_eq!(schema_of("Spielt ein Lied ·v0"), Some(0));
+        assert_eq!(schema_of("Spielt ein Lied"), None);
+        assert_eq!(schema_of("Spielt ein Lied ·vneu"), None);
+    }
+
+    #[test]
+    fn long_descriptions_are_shortened_for_the_marker() {
+        let description = marked(&"ä".repeat(MAX_DESCRIPTION));
+        assert_eq!(description.chars().count(), MAX_DESCRIPTION);
+        assert_eq!(schema_of(&description), Some(COMMAND_SCHEMA));
+    }
+
+    #[test]
+    fn registration_carries_the_marker() {
+        let registration = registration(&[enqueue()]);
+        let command = serde_json::to_value(&registration[0]).unwrap();
+        assert_eq!(
+            command["description"],
+            format!("Adds a song, the signature the tests adapt to ·v{COMMAND_SCHEMA}")
+        );
+    }
+
+    #[test]
+    fn recorded_commands_are_current_only_with_the_marker() {
+        let command = |id: u64, description: String| -> RegisteredCommand {
+            serde_json::from_value(serde_json::json!({
+                "id": id.to_string(),
+                "type": 1,
+                "application_id": "1",
+                "name": "enqueue",
+                "description": description,
+                "version": "1",
+            }))
+            .unwrap()
+        };
+        let schemas = CommandSchemas::default();
+        schemas.record(&[
+            command(1, marked("Neu")),
+            command(2, "Alt".to_owned()),
+            command(3, format!("Alt{SCHEMA_MARKER}{}", COMMAND_SCHEMA + 1)),
+        ]);
+
+        assert!(schemas.is_current(CommandId::new(1)));
+        assert!(!schemas.is_current(CommandId::new(2)));
+        assert!(!schemas.is_current(CommandId::new(3)));
+        assert!(!schemas.is_current(CommandId::new(4)));
+
+        // Overwriting a registration keeps its id
+        schemas.record(&[command(1, "Alt".to_owned())]);
+        assert!(!schemas.is_current(CommandId::new(1)));
+    }
+}
@@ -0,0 +1,630 @@
+use poise::CreateReply;
+use serenity::all::{ComponentInteractionCollector, CreateAttachment, GuildId, User};
+use serenity::builder::{
+    CreateActionRow, CreateAllowedMentions, CreateButton, CreateInteractionResponse,
+    CreateInteractionResponseMessage,
+};
+use serenity::prelude::Mentionable;
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::audit_log::AuditEntry;
+use crate::command_schema::{self, COMMAND_SCHEMA};
+use crate::commands::util::{
+    get_audit_log, get_command_schemas, get_driver_diagnostics, get_error_rates,
+    get_guild_lifecycle, get_guild_settings, get_metadata, get_persistence_health,
+    get_playback_events, get_playback_modes, get_resolution_telemetry, get_voice_sessions,
+    get_youtube_client, get_ytdlp_config, respond_success, QUEUE_PAGE_SIZE,
+};
+use crate::error_rates::{ErrorRates, ErrorSource, LONG_WINDOW, SHORT_WINDOW};
+use crate::persistence::PersistenceHealth;
+use crate::plain_text::EmbedMode;
+use crate::response::BotResponse;
+use crate::youtube::quota::QuotaMode;
+use crate::youtube::YtOperation;
+use crate::{
+    CommandContext, CommandError, DeparturesKey, GuildStateKey, LoadGuardKey, TrackValidatorKey,
+    VoiceDebouncerKey,
+};
+
+// ======== Commands ========
+
+/// Operator tools for the YouTube authentication of yt-dlp
+#[poise::command(
+    slash_command,
+    owners_only,
+    hide_in_help,
+    default_member_permissions = "ADMINISTRATOR",
+    subcommands("ytauth_status"),
+    subcommand_required
+)]
+pub async fn ytauth(_ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    Ok(())
+}
+
+/// Shows which YouTube credentials are configured for yt-dlp
+#[poise::command(
+    rename = "status",
+    slash_command,
+    owners_only,
+    description_localized(
+        "de",
+        "Zeigt, welche YouTube-Zugangsdaten für yt-dlp konfiguriert sind"
+    )
+)]
+pub async fn ytauth_status(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let config = get_ytdlp_config(ctx.serenity_context()).await;
+
+    let cookies = match &config.cookies_file {
+        Some(path) => match std::fs::metadata(path).and_then(|m| m.modified()) {
+            Ok(modified) => {
+                let unix_secs = modified
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                format!("konfiguriert, zuletzt geändert <t:{unix_secs}:R>")
+            }
+            Err(_) => "konfiguriert, aber nicht lesbar".to_owned(),
+        },
+        None => "nicht konfiguriert".to_owned(),
+    };
+    let po_token = if config.po_token.is_some() {
+        "konfiguriert"
+    } else {
+        "nicht konfiguriert"
+    };
+
+    let response_details = format!("`Cookies`: {cookies}\n`PO-Token`: {po_token}");
+    _ = respond_success(&ctx, "YouTube-Authentifizierung", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Shows the state of the bot for operators
+#[poise::command(
+    slash_command,
+    owners_only,
+    hide_in_help,
+    default_member_permissions = "ADMINISTRATOR",
+    description_localized("de", "Zeigt den Zustand des Bots für Betreiber")
+)]
+pub async fn status(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let youtube_client = get_youtube_client(ctx.serenity_context()).await;
+
+    let order = youtube_client
+        .provider_order()
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<String>>()
+        .join(" → ");
+    let recent = youtube_client
+        .recent_providers()
+        .iter()
+        .map(|(operation, provider)| {
+            let operation = match operation {
+                YtOperation::Search => "Suche",
+                YtOperation::Video => "Video",
+                YtOperation::Playlist => "Playlist",
+            };
+            format!("{operation}: {provider}")
+        })
+        .collect::<Vec<String>>();
+    let recent = if recent.is_empty() {
+        "noch keine Anfragen".to_owned()
+    } else {
+        recent.join(", ")
+    };
+
+    let gauge = ctx
+        .serenity_context()
+        .data
+        .read()
+        .await
+        .get::<GuildStateKey>()
+        .expect("Guaranteed to exist in the typemap")
+        .gauge();
+    let per_store = gauge
+        .per_store
+        .iter()
+        .map(|(name, entries)| format!("{name}: {entries}"))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let diagnostics = get_driver_diagnostics(ctx.serenity_context())
+        .await
+        .totals();
+    let last_error = match diagnostics.last_error {
+        Some((error, at)) => {
+            let unix_secs = at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            format!("{error} (<t:{unix_secs}:R>)")
+        }
+        None => "keiner".to_owned(),
+    };
+
+    let lifecycle = get_guild_lifecycle(ctx.serenity_context()).await;
+    let lifecycle_stats = &lifecycle.stats;
+
+    let resolutions = get_resolution_telemetry(ctx.serenity_context())
+        .await
+        .counts()
+        .iter()
+        .map(|(path, count)| format!("{} {count}", path.name()))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    // Oldest sessions first, so long-running guilds stand out
+    let sessions = get_voice_sessions(ctx.serenity_context()).await;
+    let active = sessions.active(Instant::now());
+    let limit = sessions
+        .limit()
+        .map_or("unbegrenzt".to_owned(), |limit| limit.to_string());
+    let oldest = active
+        .iter()
+        .take(STATUS_SESSIONS)
+        .map(|(guild_id, age)| {
+            let name = ctx
+                .serenity_context()
+                .cache
+                .guild(*guild_id)
+                .map_or(guild_id.to_string(), |guild| guild.name.clone());
+            format!("{name} ({} min)", age.as_secs() / 60)
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    let oldest = match oldest.is_empty() {
+        true => String::new(),
+        false => format!("(älteste zuerst: {oldest})"),
+    };
+
+    let error_rates = get_error_rates(ctx.serenity_context()).await;
+    let error_rates = ErrorSource::ALL
+        .iter()
+        .map(|source| render_error_rate(&error_rates, *source))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    let persistence = get_persistence_health(ctx.serenity_context()).await;
+    let storage = render_storage(&persistence);
+
+    let response_details = format!(
+        "`YouTube-Anbieter`: {order}\n`Zuletzt genutzt` (neueste zuerst): {recent}\n`Server mit Zustand`: {} ({} Einträge; {per_store})\n`Sprachverbindungen`: {} Reconnects, {} Aussetzer, {} Mal gepuffert, letzter Fehler: {last_error}\n`Server seit Start`: {} beigetreten, {} verlassen, {} gelöscht, {} warten auf Löschung\n`Auflösung von /play`: {resolutions}\n`Aktive Sprachverbindungen`: {}/{limit} {oldest}\n`Speicher`: {storage}\n**Fehlerraten** (5 min, 30 min, je Minute)\n{error_rates}",
+        gauge.guilds,
+        gauge.entries,
+        diagnostics.reconnects,
+        diagnostics.playback_gaps,
+        diagnostics.stalls,
+        lifecycle_stats.joined.load(Ordering::Relaxed),
+        lifecycle_stats.removed.load(Ordering::Relaxed),
+        lifecycle_stats.purged.load(Ordering::Relaxed),
+        lifecycle.pending_purges(),
+        active.len()
+    );
+    _ = respond_success(&ctx, "Status", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Voice sessions listed by /status
+const STATUS_SESSIONS: usize = 10;
+
+/// The stores backed by a file that could not be loaded or written
+fn render_storage(persistence: &PersistenceHealth) -> String {
+    let problems = persistence
+        .stores()
+        .into_iter()
+        .filter_map(
+            |(name, health)| match (&health.unavailable, &health.last_error) {
+                (Some(e), _) => Some(format!("{name} nicht geladen ({e})")),
+                (None, Some(e)) => Some(format!(
+                    "{name}: {} fehlgeschlagene Schreibversuche ({e})",
+                    health.failed_writes
+                )),
+                (None, None) => None,
+            },
+        )
+        .collect::<Vec<String>>();
+    match (persistence.is_degraded(), problems.is_empty()) {
+        (_, true) => "ok".to_owned(),
+        (true, false) => format!("**eingeschränkt**, {}", problems.join(", ")),
+        (false, false) => problems.join(", "),
+    }
+}
+
+/// Both windows and the per-minute history of one source, with its open incident
+fn render_error_rate(error_rates: &ErrorRates, source: ErrorSource) -> String {
+    let percent = |window| {
+        let count = error_rates.count(source, window);
+        match count.rate() {
+            Some(rate) => format!("{:.0} % ({}/{})", rate * 100.0, count.failures, count.calls),
+            None => "–".to_owned(),
+        }
+    };
+    let incident = match error_rates.incident(source) {
+        Some(incident) => {
+            let unix_secs = incident
+                .since
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            format!(" **Störung seit <t:{unix_secs}:R>**")
+        }
+        None => String::new(),
+    };
+    format!(
+        "`{}`: {}, {} `{}`{incident}",
+        source.name(),
+        percent(SHORT_WINDOW),
+        percent(LONG_WINDOW),
+        error_rates.sparkline(source)
+    )
+}
+
+/// Shows the estimated YouTube api quota and whether autocomplete still searches
+#[poise::command(
+    slash_command,
+    owners_only,
+    hide_in_help,
+    default_member_permissions = "ADMINISTRATOR",
+    description_localized(
+        "de",
+        "Zeigt das geschätzte YouTube-Kontingent und ob die Autovervollständigung noch sucht"
+    )
+)]
+pub async fn quota(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let Some(status) = get_youtube_client(ctx.serenity_context())
+        .await
+        .quota_status()
+    else {
+        _ = respond_success(
+            &ctx,
+            "Kontingent",
+            "Die YouTube-API ist nicht eingerichtet, es wird kein Kontingent verbraucht",
+            true,
+        )
+        .await?;
+        return Ok(());
+    };
+
+    let mode = match status.mode {
+        QuotaMode::Normal => "Normal",
+        QuotaMode::AutocompleteDegraded => "Autovervollständigung eingeschränkt (nur Verlauf)",
+    };
+    let changed = match status.mode_changed_at {
+        Some(at) => format!("<t:{}:R>", at.unix_timestamp()),
+        None => "seit Start unverändert".to_owned(),
+    };
+    let response_details = format!(
+        "`Verbraucht` (geschätzt, seit Mitternacht UTC): {} von {}\n`Schwelle für Autovervollständigung`: {} %\n`Modus`: {mode}\n`Letzter Wechsel`: {changed}",
+        status.used, status.daily_limit, status.threshold_percent
+    );
+    _ = respond_success(&ctx, "Kontingent", response_details, true).await?;
+
+    Ok(())
+}
+
+const AUDIT_LOG_BUTTON_TIMEOUT: Duration = Duration::from_secs(180);
+
+fn render_audit_log_page(entries: &[AuditEntry], page: usize) -> BotResponse {
+    let page_count = entries.len().div_ceil(QUEUE_PAGE_SIZE).max(1);
+    let description = if entries.is_empty() {
+        "Keine Einträge vorhanden".to_owned()
+    } else {
+        entries
+            .iter()
+            .skip(page * QUEUE_PAGE_SIZE)
+            .take(QUEUE_PAGE_SIZE)
+            .map(|entry| {
+                let unix_secs = entry
+                    .at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let actor = match entry.actor {
+                    Some(user_id) => user_id.mention().to_string(),
+                    None => "Unbekannt".to_owned(),
+                };
+                format!("<t:{unix_secs}:t> {actor} {}", entry.action.describe())
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    BotResponse::success("Protokoll")
+        .description(description)
+        .footer(format!("Seite {}/{page_count}", page + 1))
+}
+
+fn audit_log_buttons(id_prefix: &str, page: usize, page_count: usize) -> Vec<CreateActionRow> {
+    vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("{id_prefix}prev"))
+            .label("◀")
+            .disabled(page == 0),
+        CreateButton::new(format!("{id_prefix}next"))
+            .label("▶")
+            .disabled(page + 1 >= page_count),
+    ])]
+}
+
+/// Shows who added, skipped or removed tracks recently
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    required_permissions = "MANAGE_GUILD",
+    description_localized(
+        "de",
+        "Zeigt, wer zuletzt Lieder hinzugefügt, übersprungen oder entfernt hat"
+    )
+)]
+pub async fn auditlog(
+    ctx: CommandContext<'_>,
+    #[description = "Only show the actions of this user"]
+    #[description_localized("de", "Nur die Aktionen dieses Nutzers anzeigen")]
+    user: Option<User>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let entries = get_audit_log(ctx.serenity_context())
+        .await
+        .entries(guild_id, user.map(|u| u.id));
+    let page_count = entries.len().div_ceil(QUEUE_PAGE_SIZE).max(1);
+    let id_prefix = ctx.id().to_string();
+    let mut page = 0;
+    let embed_mode = EmbedMode::of_reply(ctx, true).await;
+
+    let reply = ctx
+        .send(
+            embed_mode
+                .reply(
+                    CreateReply::default(),
+                    render_audit_log_page(&entries, page).embed(ctx),
+                )
+                .components(audit_log_buttons(&id_prefix, page, page_count))
+                .ephemeral(true)
+                .allowed_mentions(CreateAllowedMentions::new().empty_users()),
+        )
+        .await?;
+
+    while let Some(press) = ComponentInteractionCollector::new(ctx.serenity_context())
+        .filter({
+            let id_prefix = id_prefix.clone();
+            move |press| press.data.custom_id.starts_with(&id_prefix)
+        })
+        .timeout(AUDIT_LOG_BUTTON_TIMEOUT)
+        .await
+    {
+        match &press.data.custom_id[id_prefix.len()..] {
+            "prev" => page = page.saturating_sub(1),
+            "next" => page = (page + 1).min(page_count - 1),
+            _ => {}
+        }
+
+        press
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    embed_mode
+                        .message(
+                            CreateInteractionResponseMessage::new(),
+                            render_audit_log_page(&entries, page).embed(ctx),
+                        )
+                        .components(audit_log_buttons(&id_prefix, page, page_count)),
+                ),
+            )
+            .await?;
+    }
+
+    // Disable the buttons once nobody listens for them anymore
+    reply
+        .edit(
+            ctx,
+            embed_mode
+                .reply(
+                    CreateReply::default(),
+                    render_audit_log_page(&entries, page).embed(ctx),
+                )
+                .components(vec![]),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Registers the commands in this server again, for clients stuck on an outdated version
+#[poise::command(
+    slash_command,
+    guild_only,
+    owners_only,
+    hide_in_help,
+    default_member_permissions = "ADMINISTRATOR",
+    description_localized(
+        "de",
+        "Registriert die Commands in diesem Server neu, falls Clients eine veraltete Version nutzen"
+    )
+)]
+pub async fn reregister(
+    ctx: CommandContext<'_>,
+    #[description = "Removes the server registration again, so only the global one is used"]
+    #[description_localized(
+        "de",
+        "Entfernt die Server-Registrierung wieder, sodass nur die globale genutzt wird"
+    )]
+    remove: Option<bool>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+
+    let response_details = if remove.unwrap_or(false) {
+        guild_id.set_commands(ctx, Vec::new()).await?;
+        "Die Server-Registrierung wurde entfernt".to_owned()
+    } else {
+        let registered = guild_id
+            .set_commands(
+                ctx,
+                command_schema::registration(&ctx.framework().options().commands),
+            )
+            .await?;
+        get_command_schemas(ctx.serenity_context())
+            .await
+            .record(&registered);
+        format!(
+            "{} Commands wurden mit Schema {COMMAND_SCHEMA} in diesem Server registriert",
+            registered.len()
+        )
+    };
+    _ = respond_success(&ctx, "Registrierung", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Dumps the playback state of a guild for debugging
+#[poise::command(
+    slash_command,
+    owners_only,
+    hide_in_help,
+    default_member_permissions = "ADMINISTRATOR",
+    description_localized("de", "Gibt den Wiedergabezustand eines Servers zur Fehlersuche aus")
+)]
+pub async fn debug(
+    ctx: CommandContext<'_>,
+    #[description = "Id of the server, defaults to the current one"]
+    #[description_localized("de", "Id des Servers, standardmäßig der aktuelle")]
+    guild: Option<String>,
+) -> Result<(), CommandError> {
+    let guild_id = match guild {
+        Some(guild) => match guild.trim().parse::<u64>() {
+            Ok(id) if id != 0 => GuildId::new(id),
+            _ => {
+                _ = respond_success(&ctx, "Debug", "Ungültige Server-Id", true).await?;
+                return Ok(());
+            }
+        },
+        None => ctx.guild_id().ok_or(CommandError::NotInGuild)?,
+    };
+
+    let report = debug_report(ctx, guild_id).await;
+    ctx.send(
+        CreateReply::default()
+            .content(format!("Zustand von Server {guild_id}"))
+            .attachment(CreateAttachment::bytes(
+                report,
+                format!("debug-{guild_id}.txt"),
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Plain text dump of everything known about the playback of a guild. Must never contain
+/// secrets like tokens or keys.
+async fn debug_report(ctx: CommandContext<'_>, guild_id: GuildId) -> String {
+    let serenity_ctx = ctx.serenity_context();
+    let mut report = String::new();
+
+    let call = match songbird::get(serenity_ctx).await {
+        Some(songbird) => songbird.get(guild_id),
+        None => None,
+    };
+    match call {
+        Some(call) => {
+            let (channel, queue) = {
+                let call = call.lock().await;
+                (call.current_channel(), call.queue().current_queue())
+            };
+            _ = writeln!(report, "Channel: {channel:?}");
+            _ = writeln!(report, "Queue ({} entries):", queue.len());
+            for (i, handle) in queue.iter().enumerate() {
+                let metadata = get_metadata(handle).await;
+                let info = match handle.get_info().await {
+                    Ok(info) => format!(
+                        "{:?}, loops {:?}, position {:?}",
+                        info.playing, info.loops, info.position
+                    ),
+                    Err(e) => format!("no info ({e})"),
+                };
+                _ = writeln!(
+                    report,
+                    "  {i}: {} <{}> [{}] {info}, {:?}",
+                    metadata.title,
+                    metadata.source_url,
+                    handle.uuid(),
+                    metadata.playability()
+                );
+            }
+        }
+        None => _ = writeln!(report, "No active call"),
+    }
+
+    let data = serenity_ctx.data.read().await;
+    _ = writeln!(
+        report,
+        "\nMode: {:?}",
+        get_playback_modes(serenity_ctx).await.get(guild_id)
+    );
+    _ = writeln!(
+        report,
+        "Settings: {:?}",
+        get_guild_settings(serenity_ctx).await.get(guild_id)
+    );
+    _ = writeln!(
+        report,
+        "Validator running: {}",
+        data.get::<TrackValidatorKey>()
+            .expect("Guaranteed to exist in the typemap")
+            .is_running(guild_id)
+    );
+    _ = writeln!(
+        report,
+        "Playlist loading: {}",
+        data.get::<LoadGuardKey>()
+            .expect("Guaranteed to exist in the typemap")
+            .is_loading(guild_id)
+    );
+    _ = writeln!(
+        report,
+        "Occupancy check pending: {}",
+        data.get::<VoiceDebouncerKey>()
+            .expect("Guaranteed to exist in the typemap")
+            .is_pending(guild_id)
+    );
+    let departure = data
+        .get::<DeparturesKey>()
+        .expect("Guaranteed to exist in the typemap")
+        .get(guild_id);
+    _ = writeln!(report, "Last departure: {departure:?}");
+    let stored = data
+        .get::<GuildStateKey>()
+        .expect("Guaranteed to exist in the typemap")
+        .entries_of(guild_id)
+        .iter()
+        .map(|(kind, entries)| format!("{kind:?} {entries}"))
+        .collect::<Vec<String>>()
+        .join(", ");
+    _ = writeln!(report, "Stored state: {stored}");
+    drop(data);
+
+    let diagnostics = get_driver_diagnostics(serenity_ctx).await.get(guild_id);
+    _ = writeln!(
+        report,
+        "Driver: {} reconnects, {} playback gaps, {} stalls, last error: {:?}",
+        diagnostics.reconnects,
+        diagnostics.playback_gaps,
+        diagnostics.stalls,
+        diagnostics.last_error
+    );
+
+    _ = writeln!(report, "\nRecent events (newest first):");
+    for (at, event) in get_playback_events(serenity_ctx).await.recent(guild_id) {
+        let unix_secs = at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        _ = writeln!(report, "  {unix_secs}: {event:?}");
+    }
+
+    report
+}
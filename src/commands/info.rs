@@ -0,0 +1,595 @@
+use poise::CreateReply;
+use serenity::all::ComponentInteractionCollector;
+use serenity::builder::{
+    CreateActionRow, CreateAllowedMentions, CreateButton, CreateEmbedAuthor,
+    CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use serenity::prelude::Mentionable;
+use std::time::{Duration, SystemTime};
+
+use crate::alias::CommandAlias;
+use crate::autoplay::AutoplaySuggestion;
+use crate::commands::util::{
+    autoplay_buttons, get_auto_pauses, get_autoplay, get_call, get_guild_settings, get_history,
+    get_locale, get_metadata, get_party_modes, get_playback_modes, get_position_cache, get_stats,
+    get_track_reports, get_user_preferences, get_yt_id_from_url, info_is_ephemeral,
+    live_current_track, press_autoplay_button, respond_success,
+};
+use crate::end_reason::EndReason;
+use crate::history::TrackEnd;
+use crate::locale::Locale;
+use crate::metadata::TrackMetadata;
+use crate::party_mode::describe_party;
+use crate::plain_text::EmbedMode;
+use crate::report::{report_button, ReportTarget};
+use crate::response::{is_ephemeral, BotResponse};
+use crate::stats::TrackStats;
+use crate::{CommandContext, CommandError, GlobalData};
+
+/// Entries listed by /history, enough to spot a flaky source without hitting the embed limit
+const HISTORY_LIST_LENGTH: usize = 15;
+
+// ======== Commands ========
+
+/// Infos about the available commands
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized("de", "Infos zu den verfügbaren Commands")
+)]
+pub async fn help(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let aliases = match ctx.guild_id() {
+        Some(guild_id) => get_guild_settings(ctx.serenity_context())
+            .await
+            .get(guild_id)
+            .aliases
+            .iter()
+            .collect::<Vec<_>>(),
+        None => Vec::new(),
+    };
+    let response = help_response(&ctx.framework().options.commands, ctx.locale(), &aliases);
+
+    let ephemeral = info_is_ephemeral(ctx).await;
+    _ = response
+        .ephemeral(ephemeral)
+        .requested_public(!ephemeral)
+        .send(&ctx)
+        .await?;
+
+    Ok(())
+}
+
+/// One field per command listed in /help, with the description in `locale` if there is one,
+/// followed by the aliases enabled in the guild
+fn help_response(
+    commands: &[poise::Command<GlobalData, CommandError>],
+    locale: Option<&str>,
+    aliases: &[CommandAlias],
+) -> BotResponse {
+    let listed_commands = commands.iter().filter(|c| !c.hide_in_help);
+    let mut response = listed_commands.fold(BotResponse::success("Help"), |response, c| {
+        response.field(
+            format!("`/{}`", c.name),
+            locale
+                .and_then(|l| c.description_localizations.get(l).map(|l| l.as_str()))
+                .unwrap_or(c.description.as_deref().unwrap_or_default()),
+            false,
+        )
+    });
+    if !aliases.is_empty() {
+        let aliases = aliases
+            .iter()
+            .map(|alias| format!("`/{}` → `/{}`", alias.name(), alias.target()))
+            .collect::<Vec<_>>();
+        response = response.field("Kurzformen", aliases.join("\n"), false);
+    }
+    //.field("`Weitere Infos`", "Die Warteschlange wird auch gelöscht, wenn der Bot manuell aus einem Sprachkanal entfernt wird oder den Sprachkanal wechselt", false)
+    response
+}
+
+/// Shows information about the currently playing track
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized("de", "Zeigt informationen über den aktuellen Track")
+)]
+pub async fn now_playing(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let (_channel_id, call) = get_call(ctx).await?;
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+
+    let (track, playback_info) = live_current_track(&call).await?;
+    let metadata = get_metadata(&track).await;
+    let mode = get_playback_modes(ctx.serenity_context())
+        .await
+        .get(guild_id)
+        .describe();
+    let locale = get_locale(ctx).await;
+    let public_requested = !info_is_ephemeral(ctx).await;
+    // Quiet guilds keep every response private, which also rules out sharing
+    let public_allowed = !is_ephemeral(ctx, false).await;
+    let ephemeral = !(public_requested && public_allowed);
+    let embed_mode = EmbedMode::of_reply(ctx, ephemeral).await;
+    let requesters = get_user_preferences(ctx.serenity_context())
+        .await
+        .requesters(metadata.requested_by);
+
+    let track_details = |public: bool| {
+        format!(
+            "`Titel`: {} {}\n`Autor`: {}\n`Quelle`: {} ({})\n`Angefordert von`: {}",
+            metadata.source.icon(),
+            metadata.title,
+            metadata.author,
+            metadata.source_url,
+            metadata.source.name(),
+            requesters.render(metadata.requested_by, public),
+        )
+    };
+    let status = match get_auto_pauses(ctx.serenity_context())
+        .await
+        .reason(guild_id)
+    {
+        Some(reason) => format!("\n`Status`: {}", reason.describe()),
+        None if get_position_cache(ctx.serenity_context())
+            .await
+            .is_buffering(guild_id) =>
+        {
+            "\n`Status`: ⏳ puffert…".to_owned()
+        }
+        None => String::new(),
+    };
+    let party = match get_party_modes(ctx.serenity_context())
+        .await
+        .until(guild_id)
+    {
+        Some(until) => format!("\n{}", describe_party(until)),
+        None => String::new(),
+    };
+    let response_details = |suggestion: Option<&AutoplaySuggestion>| {
+        let mut details = match embed_mode {
+            // One sentence for the track, the position in words instead of digits
+            EmbedMode::Accessible => accessible_now_playing(
+                &metadata,
+                &requesters.render(metadata.requested_by, false),
+                playback_info.position,
+                locale,
+                &format!("{mode}{status}{party}"),
+            ),
+            _ => format!(
+                "{}\n`Position`: {}/{}\n`Modus`: {mode}{status}{party}",
+                track_details(!ephemeral),
+                locale.format_duration(playback_info.position),
+                locale.format_duration(metadata.duration),
+            ),
+        };
+        if let Some(suggestion) = suggestion {
+            details += &format!("\n{}", suggestion.render());
+        }
+        details
+    };
+    let now_playing_response = |details: String| {
+        let response = BotResponse::success("Now playing")
+            .description(details)
+            .url(metadata.source_url.as_str());
+        match get_yt_id_from_url(metadata.source_url.as_str()).video_id {
+            Some(video_id) => {
+                response.thumbnail(format!("https://i.ytimg.com/vi/{video_id}/hqdefault.jpg"))
+            }
+            None => response,
+        }
+    };
+    let autoplay = get_autoplay(ctx.serenity_context()).await;
+    let mut suggestion = autoplay.suggestion(guild_id);
+    let response = now_playing_response(response_details(suggestion.as_ref()))
+        .ephemeral(ephemeral)
+        .requested_public(public_requested);
+
+    // A public response does not need to be shared anymore
+    let shareable = ephemeral
+        && public_allowed
+        && get_guild_settings(ctx.serenity_context())
+            .await
+            .get(guild_id)
+            .share_button;
+    let mut can_share = shareable;
+    if !shareable && suggestion.is_none() {
+        response.send(&ctx).await?;
+        return Ok(());
+    }
+
+    let id_prefix = ctx.id().to_string();
+    let buttons = |can_share: bool, suggestion: bool, disabled: bool| {
+        let mut rows = Vec::new();
+        if shareable {
+            rows.push(CreateActionRow::Buttons(vec![CreateButton::new(format!(
+                "{id_prefix}share"
+            ))
+            .label("Teilen")
+            .disabled(disabled || !can_share)]));
+        }
+        if suggestion {
+            rows.push(autoplay_buttons(&id_prefix, disabled));
+        }
+        rows
+    };
+
+    let reply =
+        ctx.send(response.reply(ctx).await.components(buttons(
+            can_share,
+            suggestion.is_some(),
+            false,
+        )))
+        .await?;
+
+    let author_id = ctx.author().id;
+    while let Some(press) = ComponentInteractionCollector::new(ctx.serenity_context())
+        .filter({
+            let id_prefix = id_prefix.clone();
+            move |press| press.data.custom_id.starts_with(&id_prefix) && press.user.id == author_id
+        })
+        .timeout(NOW_PLAYING_SHARE_TIMEOUT)
+        .await
+    {
+        let button = &press.data.custom_id[id_prefix.len()..];
+        if button == "share" && can_share {
+            // Single use: The button is disabled after the first press
+            can_share = false;
+            reply
+                .edit(
+                    ctx,
+                    embed_mode
+                        .reply(
+                            CreateReply::default(),
+                            now_playing_response(response_details(suggestion.as_ref())).embed(ctx),
+                        )
+                        .components(buttons(can_share, suggestion.is_some(), false)),
+                )
+                .await?;
+
+            // The position would be outdated right away, so the public card shows when it was shared
+            let public_embed = now_playing_response(track_details(true)).embed(ctx).author(
+                CreateEmbedAuthor::new(format!("Geteilt von {}", press.user.display_name()))
+                    .icon_url(press.user.face()),
+            );
+
+            press
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::Message(
+                        embed_mode
+                            .message(CreateInteractionResponseMessage::new(), public_embed)
+                            .allowed_mentions(CreateAllowedMentions::new().empty_users())
+                            .components(vec![report_button()]),
+                    ),
+                )
+                .await?;
+            let card = press.get_response(ctx).await?;
+            get_track_reports(ctx.serenity_context()).await.register(
+                guild_id,
+                card.id,
+                ReportTarget::of(&metadata),
+            );
+            continue;
+        }
+
+        press_autoplay_button(ctx, button, press.user.id).await?;
+        suggestion = autoplay.suggestion(guild_id);
+        press
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    embed_mode
+                        .message(
+                            CreateInteractionResponseMessage::new(),
+                            now_playing_response(response_details(suggestion.as_ref())).embed(ctx),
+                        )
+                        .components(buttons(can_share, suggestion.is_some(), false)),
+                ),
+            )
+            .await?;
+    }
+
+    // Disable the buttons once nobody listens for them anymore
+    reply
+        .edit(
+            ctx,
+            embed_mode
+                .reply(
+                    CreateReply::default(),
+                    now_playing_response(response_details(suggestion.as_ref())).embed(ctx),
+                )
+                .components(buttons(can_share, suggestion.is_some(), true)),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Ephemeral messages can only be edited as long as the interaction token is valid
+const NOW_PLAYING_SHARE_TIMEOUT: Duration = Duration::from_secs(14 * 60);
+
+const LEADERBOARD_SIZE: usize = 10;
+
+/// The track of /now_playing as plain sentences, the position in words instead of digits
+fn accessible_now_playing(
+    metadata: &TrackMetadata,
+    requester: &str,
+    position: Duration,
+    locale: Locale,
+    modes: &str,
+) -> String {
+    format!(
+        "{} von {}, angefordert von {requester}.\nQuelle: {}.\nPosition: {} von {}.\nModus: {modes}",
+        metadata.title,
+        metadata.author,
+        metadata.source.name(),
+        locale.format_duration_words(position),
+        locale.format_duration_words(metadata.duration),
+    )
+}
+
+fn render_leaderboard(
+    locale: Locale,
+    title: &str,
+    entries: &[TrackStats],
+    count: fn(&TrackStats) -> u32,
+) -> BotResponse {
+    let description = if entries.is_empty() {
+        "Noch keine Daten vorhanden".to_owned()
+    } else {
+        entries
+            .iter()
+            .enumerate()
+            .map(|(i, s)| {
+                format!(
+                    "`{}` [{}]({}) ({}×)",
+                    i + 1,
+                    s.title,
+                    s.url,
+                    locale.format_number(count(s).into())
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    BotResponse::success(title).description(description)
+}
+
+/// Play statistics of this server
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("stats_bangers", "stats_skipped"),
+    subcommand_required
+)]
+pub async fn stats(_ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    Ok(())
+}
+
+/// Shows the tracks that were played through the most
+#[poise::command(
+    rename = "bangers",
+    slash_command,
+    guild_only,
+    description_localized("de", "Zeigt die Tracks, die am häufigsten ganz gehört wurden")
+)]
+pub async fn stats_bangers(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let bangers = get_stats(ctx.serenity_context())
+        .await
+        .bangers(guild_id, LEADERBOARD_SIZE);
+
+    let ephemeral = info_is_ephemeral(ctx).await;
+    render_leaderboard(get_locale(ctx).await, "Banger", &bangers, |s| {
+        s.played_through
+    })
+    .ephemeral(ephemeral)
+    .requested_public(!ephemeral)
+    .send(&ctx)
+    .await?;
+
+    Ok(())
+}
+
+/// Shows the tracks that were skipped early the most
+#[poise::command(
+    rename = "skipped",
+    slash_command,
+    guild_only,
+    description_localized("de", "Zeigt die Tracks, die am häufigsten früh übersprungen wurden")
+)]
+pub async fn stats_skipped(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let skipped = get_stats(ctx.serenity_context())
+        .await
+        .most_skipped(guild_id, LEADERBOARD_SIZE);
+
+    let ephemeral = info_is_ephemeral(ctx).await;
+    render_leaderboard(get_locale(ctx).await, "Oft übersprungen", &skipped, |s| {
+        s.skipped
+    })
+    .ephemeral(ephemeral)
+    .requested_public(!ephemeral)
+    .send(&ctx)
+    .await?;
+
+    Ok(())
+}
+
+/// Shows the recently played tracks and how each of them ended
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Zeigt die zuletzt gespielten Lieder und wie sie jeweils geendet haben"
+    )
+)]
+pub async fn history(
+    ctx: CommandContext<'_>,
+    #[description = "Only show tracks that ended like this, for example only errors"]
+    #[description_localized(
+        "de",
+        "Nur Lieder anzeigen, die so geendet haben, zum Beispiel nur Fehler"
+    )]
+    reason: Option<EndReason>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let locale = get_locale(ctx).await;
+    let entries = get_history(ctx.serenity_context())
+        .await
+        .recent(guild_id, usize::MAX)
+        .into_iter()
+        .filter(|entry| reason.is_none() || entry.ended.as_ref().map(|end| end.reason) == reason)
+        .take(HISTORY_LIST_LENGTH)
+        .collect::<Vec<_>>();
+
+    let response_details = match entries.is_empty() {
+        true if reason.is_some() => "Keine passenden Lieder seit dem letzten Neustart".to_owned(),
+        true => "Seit dem letzten Neustart wurde nichts gespielt".to_owned(),
+        false => entries
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                format!(
+                    "`{}` [{}]({}) · {}",
+                    i + 1,
+                    entry.title,
+                    entry.url,
+                    describe_end(entry.ended.as_ref(), locale)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+    let ephemeral = info_is_ephemeral(ctx).await;
+    _ = respond_success(&ctx, "Verlauf", response_details, ephemeral).await?;
+
+    Ok(())
+}
+
+/// How a track of the history ended, like "übersprungen von @user nach 1:12"
+fn describe_end(end: Option<&TrackEnd>, locale: Locale) -> String {
+    let Some(end) = end else {
+        return "läuft gerade".to_owned();
+    };
+    let by = match end.actor {
+        Some(actor) => format!(" von {}", actor.mention()),
+        None => String::new(),
+    };
+    let at = locale.format_duration(end.position);
+    match end.reason {
+        EndReason::Finished => "zu Ende gespielt".to_owned(),
+        EndReason::Skipped => format!("übersprungen{by} nach {at}"),
+        EndReason::Stopped => format!("gestoppt{by} nach {at}"),
+        EndReason::Errored => format!("Fehler: {}", end.error.unwrap_or("unbekannt")),
+    }
+}
+
+/// Tells why the bot left the voice channel the last time
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized("de", "Zeigt, warum der Bot den Sprachkanal zuletzt verlassen hat")
+)]
+pub async fn whyleft(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let departure = ctx
+        .serenity_context()
+        .data
+        .read()
+        .await
+        .get::<crate::DeparturesKey>()
+        .expect("Guaranteed to exist in the typemap")
+        .get(guild_id);
+
+    let response_details = match departure {
+        Some(departure) => {
+            let unix_secs = departure
+                .at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            format!(
+                "Der Bot hat den Kanal <t:{unix_secs}:R> verlassen, weil {}",
+                departure.reason.describe()
+            )
+        }
+        None => "Der Bot hat seit dem letzten Neustart keinen Kanal verlassen".to_owned(),
+    };
+    let ephemeral = info_is_ephemeral(ctx).await;
+    _ = respond_success(&ctx, "Verlassen", response_details, ephemeral).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plain_text::{accessible_text, assert_accessible};
+    use serenity::all::{Timestamp, UserId};
+    use time::OffsetDateTime;
+
+    fn accessible(response: &BotResponse) -> String {
+        let timestamp = Timestamp::from_unix_timestamp(0).unwrap();
+        accessible_text(&response.to_embed("Gerbot", timestamp))
+    }
+
+    #[test]
+    fn accessible_help_is_plain_text() {
+        let commands = crate::commands::all();
+        for locale in [Some("de"), None] {
+            let text = accessible(&help_response(&commands, locale, &CommandAlias::ALL));
+            assert_accessible(&text);
+            assert!(text.contains("/play: "), "{text}");
+            assert!(text.contains("/p steht für /play"), "{text}");
+        }
+    }
+
+    #[test]
+    fn help_lists_only_visible_commands() {
+        let commands = crate::commands::all();
+        let help = help_response(&commands, Some("de"), &[]);
+        let text = accessible(&help);
+        let listed = commands.iter().filter(|c| !c.hide_in_help).count();
+        assert_eq!(text.matches(": ").count(), listed, "{text}");
+        assert!(!text.contains("Kurzformen"));
+        assert!(!text.contains("/debug"));
+    }
+
+    #[test]
+    fn accessible_now_playing_is_plain_text() {
+        let mut metadata = TrackMetadata::unresolved("https://www.youtube.com/watch?v=aaaaaaaaaaa");
+        metadata.title = "Lofi 🎧 beats *remastered*".to_owned();
+        metadata.author = "Sanitized_Channel".to_owned();
+        metadata.duration = Duration::from_secs(192);
+        metadata.requested_by = Some(UserId::new(1));
+        let party = describe_party(OffsetDateTime::UNIX_EPOCH);
+        let modes = format!("Lied wiederholen\n`Status`: ⏳ puffert…\n{party}");
+
+        let details = accessible_now_playing(
+            &metadata,
+            "<@1>",
+            Duration::from_secs(61),
+            Locale::German,
+            &modes,
+        );
+        let text = accessible(
+            &BotResponse::success("Now playing")
+                .description(details)
+                .url(metadata.source_url.as_str()),
+        );
+        assert_accessible(&text);
+        assert_eq!(
+            text,
+            "Now playing\n\
+             Lofi beats remastered von Sanitized_Channel, angefordert von <@1>.\n\
+             Quelle: YouTube.\n\
+             Position: 1 Minute 1 Sekunde von 3 Minuten 12 Sekunden.\n\
+             Modus: Lied wiederholen\n\
+             Status: puffert…\n\
+             Party-Modus bis <t:0:t>\n\
+             Gerbot"
+        );
+    }
+}
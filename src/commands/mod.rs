@@ -0,0 +1,125 @@
+use crate::alias::CommandAlias;
+use crate::{CommandContext, CommandError, GlobalData};
+use poise::BoxFuture;
+use util::get_guild_settings;
+
+mod admin;
+mod info;
+mod playback;
+mod playlists;
+mod queue;
+mod schedule;
+mod settings;
+pub mod util;
+
+/// All commands of the bot in the order they are listed in /help, followed by the aliases
+pub fn all() -> Vec<poise::Command<GlobalData, CommandError>> {
+    let mut commands = vec![
+        info::help(),
+        playback::play(),
+        playback::preview(),
+        playback::playlist(),
+        playback::playlistsync(),
+        playlists::playlists(),
+        playlists::import(),
+        playback::start(),
+        queue::staging(),
+        info::now_playing(),
+        queue::queue(),
+        queue::refreshmeta(),
+        queue::remove(),
+        queue::clear(),
+        queue::move_command(),
+        queue::shuffle(),
+        queue::undo(),
+        playback::loop_command(),
+        playback::loop_queue(),
+        playback::fair(),
+        playback::autoplay(),
+        playback::skip(),
+        playback::stop(),
+        playback::leave(),
+        playback::resumesession(),
+        playback::notifyfree(),
+        schedule::schedule(),
+        info::history(),
+        info::whyleft(),
+        admin::ytauth(),
+        info::stats(),
+        admin::status(),
+        admin::quota(),
+        admin::debug(),
+        admin::auditlog(),
+        admin::reregister(),
+        settings::overlay(),
+        settings::settings(),
+        settings::setup(),
+        settings::partymode(),
+        settings::preferences(),
+    ];
+
+    let aliases = CommandAlias::ALL.map(|alias| alias_command(&commands, alias));
+    commands.extend(aliases);
+    commands
+}
+
+/// Copy of the target command under the name of the alias. Aliases are registered globally for
+/// every guild, so a check rejects them where they are not enabled. Disabling an alias takes
+/// effect with the next invocation, but Discord keeps suggesting it.
+fn alias_command(
+    commands: &[poise::Command<GlobalData, CommandError>],
+    alias: CommandAlias,
+) -> poise::Command<GlobalData, CommandError> {
+    assert!(
+        commands.iter().all(|c| c.name != alias.name()),
+        "Alias /{} collides with a command",
+        alias.name()
+    );
+    let target = match alias {
+        CommandAlias::P => playback::play(),
+        CommandAlias::Np => info::now_playing(),
+        CommandAlias::Q => queue::queue(),
+        CommandAlias::S => playback::skip(),
+    };
+    assert_eq!(
+        target.name,
+        alias.target(),
+        "Alias /{} has the wrong target",
+        alias.name()
+    );
+
+    let mut command = poise::Command {
+        name: alias.name().to_owned(),
+        qualified_name: alias.name().to_owned(),
+        identifying_name: format!("alias_{}", alias.name()),
+        name_localizations: Default::default(),
+        hide_in_help: true,
+        description: Some(format!("Short for /{}", alias.target())),
+        description_localizations: [("de".to_owned(), format!("Kurzform von /{}", alias.target()))]
+            .into(),
+        ..target
+    };
+    command.checks.push(alias_enabled);
+    command
+}
+
+fn alias_enabled(ctx: CommandContext<'_>) -> BoxFuture<'_, Result<bool, CommandError>> {
+    Box::pin(async move {
+        let Some(alias) = CommandAlias::from_name(&ctx.command().name) else {
+            return Ok(true);
+        };
+        let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+        let enabled = get_guild_settings(ctx.serenity_context())
+            .await
+            .get(guild_id)
+            .aliases
+            .contains(alias);
+        match enabled {
+            true => Ok(true),
+            false => Err(CommandError::AliasDisabled {
+                alias: alias.name(),
+                target: alias.target(),
+            }),
+        }
+    })
+}
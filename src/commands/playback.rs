@@ -0,0 +1,2133 @@
+use log::{error, warn};
+use poise::CreateReply;
+use rand::prelude::SliceRandom;
+use rand::thread_rng;
+use reqwest::Url;
+use serenity::all::{
+    ButtonStyle, ChannelId, ComponentInteractionCollector, CreateAttachment, GuildId,
+};
+use serenity::builder::{
+    AutocompleteChoice, CreateActionRow, CreateButton, CreateInteractionResponse,
+    CreateInteractionResponseMessage, EditMessage,
+};
+use serenity::futures::future::join_all;
+use serenity::prelude::Mentionable;
+use songbird::tracks::TrackQueue;
+use songbird::Call;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::audit_log::{AuditAction, EnqueueOrigin};
+use crate::autoplay;
+use crate::commands::util::GetCallError::SongbirdNotFound;
+use crate::commands::util::{
+    enqueue_resolved, enqueue_track, get_audit_log, get_author_voice_state, get_autoplay, get_call,
+    get_end_markers, get_guild_settings, get_history, get_locale, get_metadata, get_outbound,
+    get_playback_events, get_playback_modes, get_playlist_syncs, get_position_cache,
+    get_resolution_telemetry, get_resume_points, get_staging, get_start_latency, get_track_reports,
+    get_user_preferences, get_youtube_client, get_yt_id_from_url, get_ytdlp_config,
+    get_ytdlp_permits, has_dj_rights, join_voice, live_current_track, queue_capacity,
+    resolve_track, respond_success, skip_current, start_track_validator, stop_queue,
+    truncate_chars, with_queue_lock, yt_suggestions, YtSuggestions, AUTOCOMPLETE_NAME_CHARS,
+    QUEUE_PAGE_SIZE,
+};
+use crate::confirm::confirm_removal;
+use crate::departures::{leave_with_reason, LeaveReason};
+use crate::end_reason::EndReason;
+use crate::events::PlaybackEvent;
+use crate::free_notices::register_free_notice;
+use crate::locale::Locale;
+use crate::metadata::TrackMetadata;
+use crate::notice_channel::InvokingChannel;
+use crate::plain_text::EmbedMode;
+use crate::playback_mode::ModeChange;
+use crate::preview::render_clip;
+use crate::queue_ops;
+use crate::report::{report_button, ReportTarget};
+use crate::resolution::ResolutionPath;
+use crate::response::BotResponse;
+use crate::saved_playlists::LoadAnnouncement;
+use crate::staging::{MAX_STAGED_TRACKS, STAGING_TTL};
+use crate::start_latency::PendingStart;
+use crate::title_sanitize::sanitize_title;
+use crate::youtube::{
+    YtApiError, YtPlaylist, YtPlaylistTruncation, YtResource, YtResourceId, YtSearchFilter,
+};
+use crate::CommandError::{LeaveVoice, QueueEmpty, UserNotInVoice};
+use crate::{CommandContext, CommandError, LoadGuardKey};
+
+// ======== Commands ========
+
+async fn autocomplete_yt_video_search(
+    ctx: CommandContext<'_>,
+    partial: &str,
+) -> Vec<AutocompleteChoice> {
+    let youtube_client = get_youtube_client(ctx.serenity_context()).await;
+    match yt_suggestions(&youtube_client, partial, YtSearchFilter::Videos).await {
+        YtSuggestions::Choices(choices) => choices,
+        YtSuggestions::Results(results) => {
+            get_resolution_telemetry(ctx.serenity_context())
+                .await
+                .offer(
+                    ctx.author().id,
+                    results.iter().map(|video| video.get_yt_url().to_string()),
+                );
+            results
+                .into_iter()
+                .map(|video| {
+                    AutocompleteChoice::new(
+                        truncate_chars(&video.title, AUTOCOMPLETE_NAME_CHARS),
+                        video.get_yt_url().as_str(),
+                    )
+                })
+                .collect()
+        }
+        YtSuggestions::Unavailable(e) => {
+            let notice = search_unavailable_notice(e.as_ref(), get_locale(ctx).await);
+            history_suggestions(ctx, partial, notice).await
+        }
+    }
+}
+
+/// First autocomplete choice when search does not work, so users know that it is not their
+/// input. At most 100 characters, the limit of choice names.
+fn search_unavailable_notice(error: Option<&YtApiError>, locale: Locale) -> &'static str {
+    match (error, locale) {
+        (None, Locale::German) => {
+            "Suchvorschläge pausiert (Kontingent knapp) – Link einfügen funktioniert weiterhin"
+        }
+        (None, Locale::English) => "Suggestions paused (quota low) – pasting a link still works",
+        (Some(YtApiError::QuotaExceeded), Locale::German) => {
+            "Suche momentan nicht verfügbar (Tageslimit) – Link einfügen funktioniert weiterhin"
+        }
+        (Some(YtApiError::QuotaExceeded), Locale::English) => {
+            "Search unavailable right now (daily limit) – pasting a link still works"
+        }
+        (Some(YtApiError::InvalidKey), Locale::German) => {
+            "Suche nicht verfügbar (API-Schlüssel ungültig) – Link einfügen funktioniert weiterhin"
+        }
+        (Some(YtApiError::InvalidKey), Locale::English) => {
+            "Search unavailable (invalid API key) – pasting a link still works"
+        }
+        (Some(YtApiError::Request(_)), Locale::German) => {
+            "Suche nicht erreichbar (Verbindungsfehler) – Link einfügen funktioniert weiterhin"
+        }
+        (Some(YtApiError::Request(_)), Locale::English) => {
+            "Search unreachable (connection error) – pasting a link still works"
+        }
+        (Some(_), Locale::German) => "Suche fehlgeschlagen – Link einfügen funktioniert weiterhin",
+        (Some(_), Locale::English) => "Search failed – pasting a link still works",
+    }
+}
+
+/// Marks suggestions from the history, ASCII so its length is its number of characters
+const HISTORY_SUFFIX: &str = " (Verlauf)";
+
+/// Degraded autocomplete without search: The notice why, which keeps the input as its value,
+/// followed by recently played tracks
+async fn history_suggestions(
+    ctx: CommandContext<'_>,
+    partial: &str,
+    notice: &str,
+) -> Vec<AutocompleteChoice> {
+    let suggestions = match ctx.guild_id() {
+        Some(guild_id) => get_history(ctx.serenity_context())
+            .await
+            .suggest(guild_id, partial, 5),
+        None => vec![],
+    };
+
+    let notice = AutocompleteChoice::new(notice, partial);
+    if suggestions.is_empty() {
+        return vec![notice];
+    }
+    get_resolution_telemetry(ctx.serenity_context())
+        .await
+        .offer(
+            ctx.author().id,
+            suggestions.iter().map(|entry| entry.url.clone()),
+        );
+    let history = suggestions.into_iter().map(|entry| {
+        // The suffix has to fit into the choice name as well
+        let title = truncate_chars(&entry.title, AUTOCOMPLETE_NAME_CHARS - HISTORY_SUFFIX.len());
+        AutocompleteChoice::new(format!("{title}{HISTORY_SUFFIX}"), entry.url)
+    });
+    std::iter::once(notice).chain(history).collect()
+}
+
+//TODO: Help command text
+//Spielt ein Lied im momentanen Sprachkanal ab. Als Quelle geht ein Suchbegriff für Youtube oder ein direkter Link zu allen von yt-dlp unterstützten [Platformen](https://github.com/yt-dlp/yt-dlp/blob/master/supportedsites.md). Standardmäßig wird das Lied hinten in die Warteschlange eingereiht. Mit skip_queue true (TAB drücken nach Commandeingabe) wird es vorne eingereiht und sofort abgespielt (überspringt das momentane Lied)
+
+/// Plays a song in your current voice channel
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized("de", "Spielt ein Lied im momentanen Sprachkanal ab"),
+    required_bot_permissions = "VIEW_CHANNEL | CONNECT | SPEAK"
+)]
+pub async fn play(
+    ctx: CommandContext<'_>,
+    #[description = "YouTube search or direct link to all platforms supported by yt-dlp"]
+    #[description_localized(
+        "de",
+        "YouTube-Suche oder Direktlink zu allen von yt-dlp unterstützten Platformen"
+    )]
+    #[autocomplete = "autocomplete_yt_video_search"]
+    source: String,
+    #[description = "Whether the queue should be skipped"]
+    #[description_localized("de", "Ob die Warteschlange übersprungen werden soll")]
+    skip_queue: Option<bool>,
+    #[description = "Whether the track should be staged until you join a voice channel"]
+    #[description_localized(
+        "de",
+        "Ob das Lied vorgemerkt werden soll, bis du einem Sprachkanal beitrittst"
+    )]
+    stage: Option<bool>,
+    #[description = "Play the newest video of the channel that is searched or linked"]
+    #[description_localized(
+        "de",
+        "Spielt das neueste Video des gesuchten oder verlinkten Kanals ab"
+    )]
+    latest_from_channel: Option<bool>,
+) -> Result<(), CommandError> {
+    let requested_at = Instant::now();
+    let latest = match latest_from_channel.is_some_and(|l| l) {
+        true => Some(find_latest_upload(ctx, &source).await?),
+        false => None,
+    };
+    let sources = match &latest {
+        Some(latest) => vec![latest.url.as_str()],
+        None => split_sources(&source).ok_or(CommandError::MixedSources)?,
+    };
+
+    // ======== Join the right voice channel or return ========
+
+    // Get user's current voice channel
+    let (user_guild, user_channel) = get_author_voice_state(ctx);
+
+    if stage.is_some_and(|s| s) {
+        for source in sources {
+            stage_track(ctx, user_guild, source).await?;
+        }
+        return Ok(());
+    }
+
+    // Return if user not in a voice channel
+    let connect_to = user_channel.ok_or(UserNotInVoice)?;
+
+    // A link to a video within a playlist could mean either, so the author is asked
+    if let [source] = sources.as_slice() {
+        let ids = get_yt_id_from_url(source);
+        if let (Some(video_id), Some(_)) = (ids.video_id, &ids.playlist_id) {
+            let selection = match ask_playlist_choice(ctx).await? {
+                PlaylistChoice::Video => None,
+                PlaylistChoice::Playlist => Some(PlaylistSelection::default()),
+                PlaylistChoice::FromVideo => Some(PlaylistSelection {
+                    offset: ids.playlist_index.unwrap_or(1).saturating_sub(1),
+                    start_video: Some(video_id),
+                    ..Default::default()
+                }),
+            };
+            if let Some(selection) = selection {
+                return load_playlist_selection(ctx, source, selection).await;
+            }
+        }
+    }
+
+    let songbird = songbird::get(ctx.serenity_context())
+        .await
+        .ok_or(SongbirdNotFound)?;
+
+    let joined_voice = match songbird.get(user_guild) {
+        Some(call) => call.lock().await.current_channel() != Some(connect_to.into()),
+        None => true,
+    };
+
+    // Make sure the bot is in the right channel
+    let call = join_voice(
+        ctx.serenity_context(),
+        songbird.clone(),
+        user_guild,
+        connect_to,
+    )
+    .await?;
+    start_track_validator(ctx, songbird, user_guild).await;
+
+    // ======== Play track ========
+
+    // Tracks staged before joining play first, in the order they were staged
+    let staged = enqueue_staged(ctx, call.clone(), user_guild).await?;
+    let staged_note = match staged {
+        0 => String::new(),
+        n => format!("\n{n} vorgemerkte Lieder wurden davor hinzugefügt"),
+    };
+    let staged_note = match &latest {
+        Some(latest) => format!("\nNeuestes Video von `{}`{staged_note}", latest.channel),
+        None => staged_note,
+    };
+
+    // A failing link should not keep the others from being added
+    let mut added = Vec::new();
+    let mut failed = Vec::new();
+    for source in &sources {
+        match enqueue_track(ctx, call.clone(), source, EnqueueOrigin::Play).await {
+            Ok(metadata) => {
+                if added.is_empty() && !skip_queue.is_some_and(|v| v) {
+                    measure_start(ctx, &call, &metadata, requested_at, joined_voice).await;
+                }
+                added.push(metadata);
+            }
+            // A single source fails the same way as before
+            Err(e) if sources.len() == 1 => return Err(e),
+            Err(e) => {
+                error!("Failed to enqueue {source} from a bulk /play: {e}");
+                failed.push(*source);
+            }
+        }
+    }
+    if added.is_empty() {
+        let response_details = format!(
+            "Keiner der Links konnte geladen werden:\n{}",
+            failed.join("\n")
+        );
+        _ = respond_success(&ctx, "Track Found", response_details, true).await?;
+        return Ok(());
+    }
+
+    // skip_queue -> Move to the front and skip current track
+    let skip_queue = skip_queue.is_some_and(|v| v);
+    if skip_queue {
+        let end_markers = get_end_markers(ctx.serenity_context()).await;
+        with_queue_lock(ctx, &call, |queue| {
+            if queue.len() > added.len() {
+                queue.modify_queue(|raw_queue| {
+                    queue_ops::move_back_to_front(raw_queue, added.len());
+                    if let Some(skipped) = raw_queue.front() {
+                        end_markers.mark_by(
+                            user_guild,
+                            skipped.uuid(),
+                            EndReason::Skipped,
+                            Some(ctx.author().id),
+                        );
+                        // A dead track already ended on its own
+                        _ = skipped.stop();
+                    }
+                });
+            }
+        })
+        .await?;
+        measure_start(ctx, &call, &added[0], requested_at, joined_voice).await;
+    }
+
+    let channel = connect_to.to_channel(ctx).await?.mention();
+    let soft_limit = get_guild_settings(ctx.serenity_context())
+        .await
+        .get(user_guild)
+        .max_track_duration;
+    let response_details = match added.as_slice() {
+        [metadata] if skip_queue => format!(
+            "`{}` wird jetzt in {channel} abgespielt{}{}{staged_note}",
+            metadata.title,
+            search_match_note(metadata),
+            soft_limit_note(metadata, soft_limit),
+        ),
+        [metadata] => format!(
+            "`{}` zur Warteschlange für {channel} hinzugefügt{}{}{staged_note}",
+            metadata.title,
+            search_match_note(metadata),
+            soft_limit_note(metadata, soft_limit),
+        ),
+        added => {
+            let titles = added
+                .iter()
+                .map(|metadata| match metadata.over_soft_limit {
+                    true => format!("- `{}` :hourglass:", metadata.title),
+                    false => format!("- `{}`", metadata.title),
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            let failed_note = match failed.as_slice() {
+                [] => String::new(),
+                failed => format!("\nNicht geladen:\n{}", failed.join("\n")),
+            };
+            format!(
+                "{} Titel hinzugefügt für {channel}:\n{titles}{failed_note}{staged_note}",
+                added.len(),
+            )
+        }
+    };
+    let mut response = BotResponse::success("Track Found").description(response_details);
+    if let Some(footer) = mini_queue(&call).await {
+        response = response.footer(footer);
+    }
+    // A single track can be reported right from its announcement
+    let [metadata] = added.as_slice() else {
+        response.send(&ctx).await?;
+        return Ok(());
+    };
+    let reply = ctx
+        .send(response.reply(ctx).await.components(vec![report_button()]))
+        .await?;
+    get_track_reports(ctx.serenity_context()).await.register(
+        user_guild,
+        reply.message().await?.id,
+        ReportTarget::of(metadata),
+    );
+
+    Ok(())
+}
+
+/// Titles shown by the mini queue
+const MINI_QUEUE_LENGTH: usize = 3;
+/// Longer titles are cut in the mini queue, so all of them fit the footer
+const MINI_QUEUE_TITLE_LENGTH: usize = 40;
+
+/// Footer with the next tracks and the length of the queue, to save a /queue after adding
+/// tracks. `None` if the queue is empty.
+async fn mini_queue(call: &Mutex<Call>) -> Option<String> {
+    let handles = call.lock().await.queue().current_queue();
+    let upcoming = join_all(
+        handles
+            .iter()
+            .skip(1)
+            .take(MINI_QUEUE_LENGTH)
+            .map(get_metadata),
+    )
+    .await;
+    let titles = upcoming
+        .iter()
+        .map(|metadata| metadata.title.as_str())
+        .collect::<Vec<&str>>();
+    render_mini_queue(&titles, handles.len())
+}
+
+fn render_mini_queue(upcoming: &[&str], total: usize) -> Option<String> {
+    match total {
+        0 => None,
+        1 => Some("Die Wiedergabe startet sofort".to_owned()),
+        total => {
+            let titles = upcoming
+                .iter()
+                .enumerate()
+                .map(|(i, title)| {
+                    let mut chars = title.chars();
+                    let mut short = chars
+                        .by_ref()
+                        .take(MINI_QUEUE_TITLE_LENGTH)
+                        .collect::<String>();
+                    if chars.next().is_some() {
+                        short.push('…');
+                    }
+                    format!("{}. {short}", i + 1)
+                })
+                .collect::<Vec<String>>()
+                .join(" ");
+            Some(format!(
+                "Als Nächstes: {titles} · {total} in der Warteschlange"
+            ))
+        }
+    }
+}
+
+/// Measures how long the track takes to start if it is the one that plays next. Tracks behind
+/// others in the queue would only measure the wait for the queue.
+async fn measure_start(
+    ctx: CommandContext<'_>,
+    call: &Arc<Mutex<Call>>,
+    metadata: &Arc<TrackMetadata>,
+    requested_at: Instant,
+    joined_voice: bool,
+) {
+    let Some(current) = call.lock().await.queue().current() else {
+        return;
+    };
+    if !Arc::ptr_eq(&get_metadata(&current).await, metadata) {
+        return;
+    }
+    get_start_latency(ctx.serenity_context()).await.begin(
+        current.uuid(),
+        PendingStart {
+            requested_at,
+            path: metadata.resolution,
+            joined_voice,
+            source_url: metadata.source_url.to_string(),
+        },
+    );
+}
+
+/// Shows which video a search without a picked suggestion matched, so a wrong match is noticed
+fn search_match_note(metadata: &TrackMetadata) -> String {
+    match metadata.resolution {
+        Some(ResolutionPath::RawSearch) => format!(
+            "\nGefunden per Suche: [{}]({}) von `{}`",
+            metadata.title, metadata.source_url, metadata.author
+        ),
+        _ => String::new(),
+    }
+}
+
+/// Warns about a track that is only allowed because the duration limit of the server is soft
+fn soft_limit_note(metadata: &TrackMetadata, limit: Option<Duration>) -> String {
+    match (metadata.over_soft_limit, limit) {
+        (true, Some(limit)) => format!(
+            "\n:hourglass: Länger als die empfohlenen {} Minuten, ein DJ kann es mit /remove entfernen",
+            limit.as_secs() / 60
+        ),
+        _ => String::new(),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PlaylistChoice {
+    Video,
+    Playlist,
+    FromVideo,
+}
+
+const PLAYLIST_CHOICE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Asks whether a link to a video within a playlist should play the video or the playlist.
+/// Without an answer, only the video is played.
+async fn ask_playlist_choice(ctx: CommandContext<'_>) -> Result<PlaylistChoice, CommandError> {
+    let id_prefix = ctx.id().to_string();
+    let options = [
+        (PlaylistChoice::Video, "video", "Nur dieses Video"),
+        (PlaylistChoice::Playlist, "playlist", "Ganze Playlist"),
+        (
+            PlaylistChoice::FromVideo,
+            "fromvideo",
+            "Playlist ab diesem Video",
+        ),
+    ];
+    let buttons = vec![CreateActionRow::Buttons(
+        options
+            .iter()
+            .map(|(_, id, label)| {
+                CreateButton::new(format!("{id_prefix}{id}"))
+                    .label(*label)
+                    .style(ButtonStyle::Secondary)
+            })
+            .collect(),
+    )];
+    let embed_mode = EmbedMode::of_reply(ctx, true).await;
+    let prompt = BotResponse::success("Video oder Playlist?")
+        .description(
+            "Der Link gehört zu einem Video in einer Playlist. Was soll abgespielt werden?",
+        )
+        .embed(ctx);
+    let reply = ctx
+        .send(
+            embed_mode
+                .reply(CreateReply::default(), prompt)
+                .components(buttons)
+                .ephemeral(true),
+        )
+        .await?;
+
+    let author_id = ctx.author().id;
+    let press = ComponentInteractionCollector::new(ctx.serenity_context())
+        .filter({
+            let id_prefix = id_prefix.clone();
+            move |press| press.user.id == author_id && press.data.custom_id.starts_with(&id_prefix)
+        })
+        .timeout(PLAYLIST_CHOICE_TIMEOUT)
+        .await;
+
+    let (choice, label) = press
+        .as_ref()
+        .and_then(|press| {
+            options
+                .iter()
+                .find(|(_, id, _)| press.data.custom_id == format!("{id_prefix}{id}"))
+        })
+        .map_or(
+            (
+                PlaylistChoice::Video,
+                "Keine Auswahl, nur das Video wird abgespielt",
+            ),
+            |(choice, _, label)| (*choice, *label),
+        );
+    let outcome = BotResponse::success("Video oder Playlist?")
+        .description(label)
+        .embed(ctx);
+    match press {
+        Some(press) => {
+            press
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::UpdateMessage(
+                        embed_mode
+                            .message(CreateInteractionResponseMessage::new(), outcome)
+                            .components(vec![]),
+                    ),
+                )
+                .await?
+        }
+        None => {
+            reply
+                .edit(
+                    ctx,
+                    embed_mode
+                        .reply(CreateReply::default(), outcome)
+                        .components(vec![]),
+                )
+                .await?
+        }
+    }
+
+    Ok(choice)
+}
+
+/// Splits the input of /play into multiple links. Free text is only allowed on its own, because
+/// a search can not be told apart from the links around it. Returns None for such mixed input.
+fn split_sources(source: &str) -> Option<Vec<&str>> {
+    let tokens = source.split_whitespace().collect::<Vec<&str>>();
+    let is_link = |token: &str| Url::parse(token).is_ok_and(|url| url.has_host());
+
+    if tokens.len() <= 1 || !tokens.iter().any(|token| is_link(token)) {
+        Some(vec![source.trim()])
+    } else if tokens.iter().all(|token| is_link(token)) {
+        Some(tokens)
+    } else {
+        None
+    }
+}
+
+/// How a channel was given to `latest_from_channel`
+enum ChannelSource {
+    Id(String),
+    Search(String),
+}
+
+fn parse_channel_source(source: &str) -> ChannelSource {
+    let url = Url::parse(source.trim())
+        .ok()
+        .filter(|url| url.domain().is_some_and(|d| d.ends_with("youtube.com")));
+    let mut segments = url
+        .as_ref()
+        .and_then(|url| url.path_segments())
+        .into_iter()
+        .flatten();
+
+    match (segments.next(), segments.next()) {
+        (Some("channel"), Some(id)) => ChannelSource::Id(id.to_owned()),
+        // Handles are found by the channel search
+        (Some(handle), _) if handle.starts_with('@') => ChannelSource::Search(handle.to_owned()),
+        _ => ChannelSource::Search(source.trim().to_owned()),
+    }
+}
+
+/// Channel and video chosen by `latest_from_channel`
+struct LatestUpload {
+    channel: String,
+    url: String,
+}
+
+/// Finds the channel of a link, handle or search and returns its newest upload
+async fn find_latest_upload(
+    ctx: CommandContext<'_>,
+    source: &str,
+) -> Result<LatestUpload, CommandError> {
+    let youtube_client = get_youtube_client(ctx.serenity_context()).await;
+
+    let (channel_id, channel_title) = match parse_channel_source(source) {
+        ChannelSource::Id(id) => (id, None),
+        ChannelSource::Search(query) => {
+            let found = youtube_client
+                .search(&query, YtSearchFilter::Channels, 1)
+                .await
+                .map_err(|e| {
+                    error!("YT channel search for {query} failed: {e:?}");
+                    CommandError::ChannelNotFound
+                })?;
+            match found.into_iter().next() {
+                Some(YtResource {
+                    id: YtResourceId::Channel(id),
+                    title,
+                    ..
+                }) => (id, Some(title)),
+                _ => return Err(CommandError::ChannelNotFound),
+            }
+        }
+    };
+
+    let upload = youtube_client
+        .latest_upload(&channel_id)
+        .await
+        .map_err(|e| {
+            error!("Loading the uploads of channel {channel_id} failed: {e:?}");
+            CommandError::ChannelNotFound
+        })?;
+    let Some(upload) = upload else {
+        return Err(CommandError::NoPublicUploads {
+            channel: channel_title.unwrap_or(channel_id),
+        });
+    };
+
+    let channel = match channel_title {
+        Some(title) => title,
+        None if !upload.channel_title.is_empty() => upload.channel_title.clone(),
+        None => channel_id,
+    };
+    Ok(LatestUpload {
+        channel,
+        url: upload.get_yt_url().to_string(),
+    })
+}
+
+/// Resolves the track and stages it for the author instead of playing it
+async fn stage_track(
+    ctx: CommandContext<'_>,
+    guild_id: GuildId,
+    source: &str,
+) -> Result<(), CommandError> {
+    let (_, metadata) = resolve_track(ctx, source).await?;
+
+    let response_details = if get_staging(ctx.serenity_context()).await.stage(
+        guild_id,
+        ctx.author().id,
+        metadata.clone(),
+    ) {
+        format!(
+            "`{}` vorgemerkt. Es wird hinzugefügt, sobald du in einem Sprachkanal /play oder /start nutzt. Vorgemerkte Lieder verfallen nach {} Minuten.",
+            metadata.title,
+            STAGING_TTL.as_secs() / 60
+        )
+    } else {
+        format!("Du kannst höchstens {MAX_STAGED_TRACKS} Lieder vormerken")
+    };
+    _ = respond_success(&ctx, "Vorgemerkt", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Enqueues all tracks the author staged and returns how many there were
+async fn enqueue_staged(
+    ctx: CommandContext<'_>,
+    call: Arc<Mutex<Call>>,
+    guild_id: GuildId,
+) -> Result<usize, CommandError> {
+    let staged = get_staging(ctx.serenity_context())
+        .await
+        .take(guild_id, ctx.author().id);
+    let count = staged.len();
+    for track in staged {
+        enqueue_resolved(ctx, call.clone(), track.metadata, EnqueueOrigin::Staged).await?;
+    }
+
+    Ok(count)
+}
+
+/// Shows a track and a short clip of it only to you, before playing it for everyone
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Zeigt ein Lied mit einer kurzen Hörprobe nur dir, bevor es alle hören"
+    )
+)]
+pub async fn preview(
+    ctx: CommandContext<'_>,
+    #[description = "YouTube search or direct link to all platforms supported by yt-dlp"]
+    #[description_localized(
+        "de",
+        "YouTube-Suche oder Direktlink zu allen von yt-dlp unterstützten Platformen"
+    )]
+    #[autocomplete = "autocomplete_yt_video_search"]
+    source: String,
+) -> Result<(), CommandError> {
+    ctx.defer_ephemeral().await?;
+    let (_, metadata) = resolve_track(ctx, &source).await?;
+    let config = get_ytdlp_config(ctx.serenity_context()).await;
+
+    let locale = get_locale(ctx).await;
+    let response_details = format!(
+        "`Titel`: {} {}\n`Autor`: {}\n`Dauer`: {}\n`Quelle`: {} ({})",
+        metadata.source.icon(),
+        metadata.title,
+        metadata.author,
+        locale.format_duration(metadata.duration),
+        metadata.source_url,
+        metadata.source.name(),
+    );
+    let mut response = BotResponse::success("Vorschau")
+        .description(response_details)
+        .url(metadata.source_url.as_str())
+        .ephemeral(true);
+    if let Some(video_id) = get_yt_id_from_url(metadata.source_url.as_str()).video_id {
+        response = response.thumbnail(format!("https://i.ytimg.com/vi/{video_id}/hqdefault.jpg"));
+    }
+    if !config.preview_clips {
+        response = response.footer("Hörproben sind auf diesem Bot deaktiviert");
+        response.send(&ctx).await?;
+        return Ok(());
+    }
+    response
+        .footer("Die Hörprobe wird erstellt…")
+        .send(&ctx)
+        .await?;
+
+    // The clip is a follow-up, so the details show while it is rendered
+    let clip = {
+        let _permit = get_ytdlp_permits(ctx.serenity_context())
+            .await
+            .acquire_owned()
+            .await
+            .expect("The semaphore is never closed");
+        render_clip(&config, metadata.source_url.as_str()).await
+    };
+    let reply = match clip {
+        Ok(clip) => CreateReply::default()
+            .content(format!("Hörprobe von `{}`", metadata.title))
+            .attachment(CreateAttachment::bytes(clip, "preview.mp3")),
+        Err(e) => {
+            warn!("No preview clip for {}: {e}", metadata.source_url);
+            CreateReply::default().content("Die Hörprobe konnte nicht erstellt werden")
+        }
+    };
+    ctx.send(reply.ephemeral(true)).await?;
+
+    Ok(())
+}
+
+/// Joins your voice channel and plays the tracks you staged
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Tritt deinem Sprachkanal bei und spielt die Lieder ab, die du vorgemerkt hast"
+    )
+)]
+pub async fn start(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let (user_guild, user_channel) = get_author_voice_state(ctx);
+    let connect_to = user_channel.ok_or(UserNotInVoice)?;
+
+    if get_staging(ctx.serenity_context())
+        .await
+        .list(user_guild, ctx.author().id)
+        .is_empty()
+    {
+        _ = respond_success(
+            &ctx,
+            "Vorgemerkt",
+            "Du hast keine vorgemerkten Lieder. Merke Lieder mit `/play stage: True` vor.",
+            true,
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let songbird = songbird::get(ctx.serenity_context())
+        .await
+        .ok_or(SongbirdNotFound)?;
+    let call = join_voice(
+        ctx.serenity_context(),
+        songbird.clone(),
+        user_guild,
+        connect_to,
+    )
+    .await?;
+    start_track_validator(ctx, songbird, user_guild).await;
+
+    let staged = enqueue_staged(ctx, call, user_guild).await?;
+    let response_details = format!(
+        "{staged} vorgemerkte Lieder zur Warteschlange für {} hinzugefügt",
+        connect_to.to_channel(ctx).await?.mention()
+    );
+    _ = respond_success(&ctx, "Track Found", response_details, false).await?;
+
+    Ok(())
+}
+
+async fn autocomplete_yt_playlist_search(
+    ctx: CommandContext<'_>,
+    partial: &str,
+) -> Vec<AutocompleteChoice> {
+    let youtube_client = get_youtube_client(ctx.serenity_context()).await;
+    match yt_suggestions(&youtube_client, partial, YtSearchFilter::Playlists).await {
+        YtSuggestions::Choices(choices) => choices,
+        YtSuggestions::Results(results) => results
+            .into_iter()
+            .map(|playlist| {
+                AutocompleteChoice::new(
+                    truncate_chars(&playlist.title, AUTOCOMPLETE_NAME_CHARS),
+                    playlist.get_yt_url().as_str(),
+                )
+            })
+            .collect(),
+        YtSuggestions::Unavailable(e) => {
+            let notice = search_unavailable_notice(e.as_ref(), get_locale(ctx).await);
+            vec![AutocompleteChoice::new(notice, partial)]
+        }
+    }
+}
+
+/// Loads a whole YouTube playlist into the queue
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized("de", "Lädt eine ganze Youtube-Playlist in die Warteschlange"),
+    required_bot_permissions = "VIEW_CHANNEL | CONNECT | SPEAK"
+)]
+pub async fn playlist(
+    ctx: CommandContext<'_>,
+    #[description = "YouTube search or direct link to a YouTube playlist"]
+    #[description_localized("de", "Youtube-Suche oder Direktlink zu einer YouTube Playlist")]
+    #[autocomplete = "autocomplete_yt_playlist_search"]
+    source: String,
+    #[description = "Whether the tracks should be added in a randomized order"]
+    #[description_localized(
+        "de",
+        "Ob die Lieder in einer zufälligen Reihenfolge hinzugefügt werden sollen"
+    )]
+    shuffle: Option<bool>,
+    #[description = "Shuffles so that tracks of the same channel do not follow each other"]
+    #[description_localized(
+        "de",
+        "Mischt so, dass Lieder desselben Kanals nicht direkt aufeinander folgen"
+    )]
+    smart_shuffle: Option<bool>,
+    #[description = "Number of tracks to skip at the start of the playlist"]
+    #[description_localized(
+        "de",
+        "Anzahl der Lieder, die am Anfang der Playlist übersprungen werden"
+    )]
+    offset: Option<u32>,
+    #[description = "Maximum number of tracks to add"]
+    #[description_localized("de", "Maximale Anzahl der Lieder, die hinzugefügt werden")]
+    #[min = 1]
+    count: Option<u32>,
+    #[description = "Only show which tracks would be added"]
+    #[description_localized("de", "Nur anzeigen, welche Lieder hinzugefügt würden")]
+    preview: Option<bool>,
+) -> Result<(), CommandError> {
+    let smart_shuffle = smart_shuffle.unwrap_or(false);
+    let selection = PlaylistSelection {
+        shuffle: shuffle.unwrap_or(false) || smart_shuffle,
+        smart_shuffle,
+        offset: offset.unwrap_or_default() as usize,
+        count: count.map(|c| c as usize),
+        start_video: None,
+        preview: preview.unwrap_or(false),
+        announce: LoadAnnouncement::default(),
+    };
+    load_playlist_selection(ctx, &source, selection).await
+}
+
+/// Which part of a playlist is loaded and how
+#[derive(Default)]
+pub(super) struct PlaylistSelection {
+    pub shuffle: bool,
+    /// Keeps tracks of the same channel apart when shuffling
+    pub smart_shuffle: bool,
+    pub offset: usize,
+    pub count: Option<usize>,
+    /// Starts at this video instead of the offset if it is part of the playlist
+    pub start_video: Option<String>,
+    pub preview: bool,
+    pub announce: LoadAnnouncement,
+}
+
+/// Fetches the playlist of a link or search and loads or previews the selected part
+pub(super) async fn load_playlist_selection(
+    ctx: CommandContext<'_>,
+    source: &str,
+    selection: PlaylistSelection,
+) -> Result<(), CommandError> {
+    // Get user's current voice channel
+    let (user_guild, user_channel) = get_author_voice_state(ctx);
+
+    // Return if user not in a voice channel
+    let connect_to = user_channel.ok_or(UserNotInVoice)?;
+
+    let youtube_client = get_youtube_client(ctx.serenity_context()).await;
+
+    // Get playlist id
+    let playlist_id = match get_yt_id_from_url(source).playlist_id {
+        Some(id) => id,
+        None => match youtube_client
+            .search(source, YtSearchFilter::Playlists, 1)
+            .await?
+            .pop()
+            .map(|r| r.id)
+        {
+            Some(YtResourceId::Playlist(id)) => id,
+            _ => return Err(CommandError::PlaylistNotFound),
+        },
+    };
+
+    // Pages after the selected range are not fetched
+    let count = selection.count;
+    let mut playlist = youtube_client
+        .get_playlist(&playlist_id, count.map(|c| selection.offset + c))
+        .await
+        .map_err(|e| match e {
+            YtApiError::InvalidId => CommandError::PlaylistNotFound,
+            e => CommandError::YouTube(e),
+        })?;
+    // Unavailable items shift the positions, so the video is looked up by id if possible
+    let offset = selection
+        .start_video
+        .and_then(|start_video| {
+            playlist
+                .videos
+                .iter()
+                .position(|v| matches!(&v.id, YtResourceId::Video(id) if *id == start_video))
+        })
+        .unwrap_or(selection.offset);
+
+    let total = playlist
+        .item_count
+        .map(|c| c as usize)
+        .unwrap_or(playlist.videos.len());
+    if offset >= total {
+        return Err(CommandError::OffsetOutOfRange { offset, total });
+    }
+    let mut notes = Vec::new();
+    if offset > 0 || count.is_some() {
+        let last = count.map_or(total, |c| (offset + c).min(total));
+        notes.push(format!("`Auswahl`: Lieder {}–{}", offset + 1, last));
+    }
+    // Unavailable items are missing from a completely loaded playlist
+    let unavailable = total.saturating_sub(playlist.videos.len());
+    if playlist.truncated.is_none() && count.is_none() && unavailable > 0 {
+        notes.push(format!("`Nicht verfügbar`: {unavailable}"));
+    }
+    notes.extend(truncation_note(&playlist, total));
+
+    playlist.videos = playlist
+        .videos
+        .into_iter()
+        .skip(offset)
+        .take(count.unwrap_or(usize::MAX))
+        .collect();
+    // Only the selected range is shuffled
+    if selection.smart_shuffle {
+        let authors = playlist
+            .videos
+            .iter()
+            .map(|video| video.channel_title.clone())
+            .enumerate()
+            .collect();
+        let order = queue_ops::spread_shuffle(authors, &mut thread_rng());
+        playlist.videos = queue_ops::apply_order(playlist.videos, &order);
+    } else if selection.shuffle {
+        playlist.videos.shuffle(&mut thread_rng());
+    }
+
+    if selection.preview {
+        preview_playlist(
+            ctx,
+            playlist,
+            notes,
+            selection.announce,
+            user_guild,
+            connect_to,
+        )
+        .await
+    } else {
+        load_playlist(
+            ctx,
+            playlist,
+            notes,
+            selection.announce,
+            user_guild,
+            connect_to,
+        )
+        .await
+    }
+}
+
+/// Explains why a playlist was not loaded completely
+fn truncation_note(playlist: &YtPlaylist, total: usize) -> Option<String> {
+    let reason = match playlist.truncated? {
+        YtPlaylistTruncation::QuotaExceeded => "YouTube-Kontingent aufgebraucht",
+        YtPlaylistTruncation::PageLimit => "Playlist zu lang",
+        YtPlaylistTruncation::Failed => "Fehler beim Laden",
+    };
+    Some(format!(
+        "Nur die ersten {} von {} Titeln konnten geladen werden ({reason})",
+        playlist.videos.len(),
+        total
+    ))
+}
+
+/// Playlist that grows over time, of which only the new videos are added
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("playlistsync_run", "playlistsync_reset"),
+    subcommand_required
+)]
+pub async fn playlistsync(_ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    Ok(())
+}
+
+/// Adds the videos of a playlist that earlier syncs did not add yet
+#[poise::command(
+    rename = "run",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Fügt die Lieder einer Playlist hinzu, die frühere Synchronisierungen noch nicht hinzugefügt haben"
+    ),
+    required_bot_permissions = "VIEW_CHANNEL | CONNECT | SPEAK"
+)]
+pub async fn playlistsync_run(
+    ctx: CommandContext<'_>,
+    #[description = "Link to a YouTube playlist"]
+    #[description_localized("de", "Link zu einer YouTube-Playlist")]
+    url: String,
+) -> Result<(), CommandError> {
+    // Without the videos of earlier syncs everything would be added again
+    get_playlist_syncs(ctx.serenity_context())
+        .await
+        .check_available()?;
+    let Some(playlist_id) = get_yt_id_from_url(&url).playlist_id else {
+        let response_details = format!("`{url}` ist kein Link zu einer YouTube-Playlist");
+        _ = respond_success(&ctx, "Playlist synchronisieren", response_details, true).await?;
+        return Ok(());
+    };
+    let (user_guild, user_channel) = get_author_voice_state(ctx);
+    let connect_to = user_channel.ok_or(UserNotInVoice)?;
+
+    // Loading a long playlist can take longer than an interaction may stay unanswered
+    ctx.defer().await?;
+    let playlist = get_youtube_client(ctx.serenity_context())
+        .await
+        .get_playlist(&playlist_id, None)
+        .await
+        .map_err(|e| match e {
+            YtApiError::InvalidId => CommandError::PlaylistNotFound,
+            e => CommandError::YouTube(e),
+        })?;
+    let total = playlist
+        .item_count
+        .map(|c| c as usize)
+        .unwrap_or(playlist.videos.len());
+
+    let syncs = get_playlist_syncs(ctx.serenity_context()).await;
+    let seen = syncs.seen(user_guild, &playlist_id);
+    let (known, new): (Vec<_>, Vec<_>) = playlist
+        .videos
+        .iter()
+        .partition(|v| matches!(&v.id, YtResourceId::Video(id) if seen.contains(id)));
+
+    let mut response_details = match new.is_empty() {
+        true => format!(
+            "`{}`: Keine neuen Titel, {} bereits bekannt",
+            playlist.title,
+            known.len()
+        ),
+        false => {
+            let (added, failed, capacity_notice) =
+                enqueue_synced(ctx, &playlist_id, &new, user_guild, connect_to).await?;
+            let mut details = format!(
+                "`{}`: {added} neue Titel hinzugefügt, {} bereits bekannt",
+                playlist.title,
+                known.len()
+            );
+            if failed > 0 {
+                details += &format!("\n{failed} Titel konnten nicht geladen werden");
+            }
+            // Left out titles are not remembered, so the next sync adds them
+            if let Some(notice) = capacity_notice {
+                details += &format!("\n{notice}");
+            }
+            details
+        }
+    };
+    if let Some(note) = truncation_note(&playlist, total) {
+        response_details += &format!("\n{note}");
+    }
+    _ = respond_success(&ctx, "Playlist synchronisieren", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Enqueues the new videos of a synced playlist and remembers them. Returns how many were added,
+/// how many failed and why some were left out for lack of space.
+async fn enqueue_synced(
+    ctx: CommandContext<'_>,
+    playlist_id: &str,
+    videos: &[&YtResource],
+    user_guild: GuildId,
+    connect_to: ChannelId,
+) -> Result<(usize, usize, Option<String>), CommandError> {
+    let songbird = songbird::get(ctx.serenity_context())
+        .await
+        .ok_or(SongbirdNotFound)?;
+    let call = join_voice(
+        ctx.serenity_context(),
+        songbird.clone(),
+        user_guild,
+        connect_to,
+    )
+    .await?;
+    start_track_validator(ctx, songbird, user_guild).await;
+    // Held until all tracks are enqueued, so loads in the same guild do not interleave
+    let _permit = ctx
+        .serenity_context()
+        .data
+        .read()
+        .await
+        .get::<LoadGuardKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+        .acquire(user_guild)
+        .await?;
+
+    let capacity = queue_capacity(ctx.serenity_context(), &call).await;
+    let capacity_notice = capacity.notice(videos.len());
+    let videos = &videos[..capacity.fit(videos.len())];
+
+    let syncs = get_playlist_syncs(ctx.serenity_context()).await;
+    let mut added = Vec::new();
+    let mut failed = 0;
+    for video in videos {
+        let YtResourceId::Video(video_id) = &video.id else {
+            continue;
+        };
+        match enqueue_track(
+            ctx,
+            call.clone(),
+            video.get_yt_url().as_str(),
+            EnqueueOrigin::Playlist,
+        )
+        .await
+        {
+            Ok(_) => added.push(video_id.clone()),
+            // Failed videos are tried again by the next sync
+            Err(
+                CommandError::YtDlp(_)
+                | CommandError::TrackTooLong { .. }
+                | CommandError::Blocked { .. },
+            ) => failed += 1,
+            Err(e) => {
+                syncs.mark_seen(user_guild, playlist_id, added);
+                return Err(e);
+            }
+        }
+    }
+
+    let count = added.len();
+    syncs.mark_seen(user_guild, playlist_id, added);
+    Ok((count, failed, capacity_notice))
+}
+
+/// Forgets which videos of a playlist were already added
+#[poise::command(
+    rename = "reset",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Vergisst, welche Lieder einer Playlist schon hinzugefügt wurden"
+    )
+)]
+pub async fn playlistsync_reset(
+    ctx: CommandContext<'_>,
+    #[description = "Link to a YouTube playlist"]
+    #[description_localized("de", "Link zu einer YouTube-Playlist")]
+    url: String,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let syncs = get_playlist_syncs(ctx.serenity_context()).await;
+    syncs.check_available()?;
+    let response_details = match get_yt_id_from_url(&url).playlist_id {
+        Some(playlist_id) => {
+            match syncs.reset(guild_id, &playlist_id) {
+                0 => "Diese Playlist wurde noch nicht synchronisiert".to_owned(),
+                n => format!("{n} bekannte Titel vergessen, die nächste Synchronisierung fügt die ganze Playlist hinzu"),
+            }
+        }
+        None => format!("`{url}` ist kein Link zu einer YouTube-Playlist"),
+    };
+    _ = respond_success(&ctx, "Playlist synchronisieren", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Joins the channel and replaces the queue with the playlist
+async fn load_playlist(
+    ctx: CommandContext<'_>,
+    mut playlist: YtPlaylist,
+    mut notes: Vec<String>,
+    announce: LoadAnnouncement,
+    user_guild: GuildId,
+    connect_to: ChannelId,
+) -> Result<(), CommandError> {
+    let songbird = songbird::get(ctx.serenity_context())
+        .await
+        .ok_or(SongbirdNotFound)?;
+
+    // Make sure the bot is in the right channel
+    let call = join_voice(
+        ctx.serenity_context(),
+        songbird.clone(),
+        user_guild,
+        connect_to,
+    )
+    .await?;
+    start_track_validator(ctx, songbird, user_guild).await;
+
+    let replaced = call.lock().await.queue().len();
+    if !confirm_removal(ctx, replaced, "/playlist").await? {
+        return Ok(());
+    }
+    // Held until all tracks are enqueued, so loads in the same guild do not interleave
+    let _permit = ctx
+        .serenity_context()
+        .data
+        .read()
+        .await
+        .get::<LoadGuardKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+        .acquire(user_guild)
+        .await?;
+    with_queue_lock(ctx, &call, TrackQueue::stop).await?;
+    let capacity = queue_capacity(ctx.serenity_context(), &call).await;
+    if let Some(notice) = capacity.notice(playlist.videos.len()) {
+        notes.push(notice);
+        playlist
+            .videos
+            .truncate(capacity.fit(playlist.videos.len()));
+    }
+
+    let requested = playlist.videos.len();
+    let channel = connect_to.to_channel(ctx).await?.mention();
+    let embed_mode = EmbedMode::of(ctx).await;
+    let progress_embed = |enqueued: usize, failed: usize| {
+        BotResponse::success("Playlist wird geladen").description(format!(
+            "`{}` für {channel}\n{}",
+            playlist.title,
+            render_progress(enqueued + failed, requested, failed)
+        ))
+    };
+
+    let cancel_id = format!("{}stopload", ctx.id());
+    let cancel_button = vec![CreateActionRow::Buttons(vec![CreateButton::new(
+        cancel_id.clone(),
+    )
+    .label("Abbrechen")
+    .style(ButtonStyle::Danger)])];
+    let reply = ctx
+        .send(
+            embed_mode
+                .reply(CreateReply::default(), progress_embed(0, 0).embed(ctx))
+                .components(cancel_button.clone()),
+        )
+        .await?;
+    let progress_message = reply.message().await?;
+    let outbound = get_outbound(ctx.serenity_context()).await;
+    let http = ctx.serenity_context().http.clone();
+    let edit_progress = |edit: EditMessage| {
+        outbound.edit(
+            &http,
+            user_guild,
+            progress_message.channel_id,
+            progress_message.id,
+            None,
+            edit,
+        )
+    };
+
+    // The invoker and DJs can stop the load, what is queued stays
+    let dj_role = get_guild_settings(ctx.serenity_context())
+        .await
+        .get(user_guild)
+        .dj_role;
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let cancel_listener = tokio::spawn({
+        let collector = ComponentInteractionCollector::new(ctx.serenity_context()).filter({
+            let author_id = ctx.author().id;
+            move |press| {
+                press.data.custom_id == cancel_id
+                    && (press.user.id == author_id || has_dj_rights(press.member.as_ref(), dj_role))
+            }
+        });
+        let cancelled = cancelled.clone();
+        let http = http.clone();
+        async move {
+            if let Some(press) = collector.await {
+                cancelled.store(true, Ordering::Relaxed);
+                _ = press
+                    .create_response(&http, CreateInteractionResponse::Acknowledge)
+                    .await;
+            }
+        }
+    });
+
+    let mut enqueued = 0;
+    let mut failed = 0;
+    let mut added_titles = Vec::new();
+    for (i, video) in playlist.videos.iter().enumerate() {
+        if cancelled.load(Ordering::Relaxed) {
+            break;
+        }
+        match enqueue_track(
+            ctx,
+            call.clone(),
+            video.get_yt_url().as_str(),
+            EnqueueOrigin::Playlist,
+        )
+        .await
+        {
+            Ok(_) => {
+                enqueued += 1;
+                added_titles.push(sanitize_title(&video.title));
+            }
+            // A single broken video should not stop the rest of the playlist
+            Err(CommandError::YtDlp(failure)) => {
+                failed += 1;
+                error!(
+                    "Skipped playlist item {}: {:?}",
+                    video.get_yt_url(),
+                    failure
+                )
+            }
+            Err(CommandError::TrackTooLong { .. } | CommandError::Blocked { .. }) => failed += 1,
+            Err(e) => {
+                cancel_listener.abort();
+                return Err(e);
+            }
+        }
+
+        // The scheduler coalesces edits that come faster than the rate limit allows
+        if (i + 1) % PROGRESS_EVERY == 0 {
+            edit_progress(
+                embed_mode
+                    .edit(
+                        EditMessage::new(),
+                        progress_embed(enqueued, failed).embed(ctx),
+                    )
+                    .components(cancel_button.clone()),
+            );
+        }
+    }
+    cancel_listener.abort();
+
+    let mut response_details = format!("`{}` wird jetzt in {channel} abgespielt", playlist.title);
+    for note in notes {
+        response_details += &format!("\n{note}");
+    }
+    if cancelled.load(Ordering::Relaxed) {
+        response_details += &format!(
+            "\nAbgebrochen nach {} von {requested} Liedern, {enqueued} wurden hinzugefügt",
+            enqueued + failed
+        );
+    } else if enqueued != requested {
+        response_details += &format!("\n{enqueued} von {requested} Liedern hinzugefügt");
+    }
+    if announce == LoadAnnouncement::EachTrack {
+        response_details += &render_added_titles(&added_titles);
+    }
+    let mut summary = BotResponse::success("Track Found").description(response_details);
+    if let Some(footer) = mini_queue(&call).await {
+        summary = summary.footer(footer);
+    }
+    // Goes through the scheduler as well, so it can not be overwritten by a pending progress edit
+    edit_progress(
+        embed_mode
+            .edit(EditMessage::new(), summary.embed(ctx))
+            .components(vec![]),
+    );
+
+    Ok(())
+}
+
+/// Titles listed in the summary of a playlist announced with each track
+const ANNOUNCED_TITLES: usize = 25;
+
+/// Numbered list of the added tracks for the summary, cut to stay within the embed limits
+fn render_added_titles(titles: &[String]) -> String {
+    let mut list = titles
+        .iter()
+        .take(ANNOUNCED_TITLES)
+        .enumerate()
+        .map(|(i, title)| format!("\n{}. {title}", i + 1))
+        .collect::<String>();
+    if titles.len() > ANNOUNCED_TITLES {
+        list += &format!("\n… und {} weitere", titles.len() - ANNOUNCED_TITLES);
+    }
+    list
+}
+
+/// Tracks between two progress updates of a loading playlist
+const PROGRESS_EVERY: usize = 10;
+const PROGRESS_BAR_WIDTH: usize = 10;
+
+/// Progress bar like `█████░░░░░ 120/300, 2 Fehler`
+fn render_progress(done: usize, total: usize, failed: usize) -> String {
+    let filled = (done * PROGRESS_BAR_WIDTH)
+        .checked_div(total)
+        .unwrap_or(PROGRESS_BAR_WIDTH);
+    let mut progress = format!(
+        "{}{} {done}/{total}",
+        "█".repeat(filled),
+        "░".repeat(PROGRESS_BAR_WIDTH - filled)
+    );
+    match failed {
+        0 => {}
+        1 => progress += ", 1 Fehler",
+        n => progress += &format!(", {n} Fehler"),
+    }
+    progress
+}
+
+const PLAYLIST_PREVIEW_TIMEOUT: Duration = Duration::from_secs(120);
+
+fn render_playlist_preview(
+    locale: Locale,
+    playlist: &YtPlaylist,
+    notes: &[String],
+    page: usize,
+) -> BotResponse {
+    let page_count = playlist.videos.len().div_ceil(QUEUE_PAGE_SIZE).max(1);
+
+    let mut summary = format!("`Titel`: {}", playlist.videos.len());
+    for note in notes {
+        summary += &format!("\n{note}");
+    }
+    // Only shown if every item has a duration, a partial sum would be misleading
+    let total_duration = playlist
+        .videos
+        .iter()
+        .map(|v| v.duration)
+        .sum::<Option<Duration>>();
+    if let Some(total_duration) = total_duration.filter(|_| !playlist.videos.is_empty()) {
+        summary += &format!(
+            "\n`Gesamtdauer`: {}",
+            locale.format_duration(total_duration)
+        );
+    }
+    let lines = playlist
+        .videos
+        .iter()
+        .enumerate()
+        .skip(page * QUEUE_PAGE_SIZE)
+        .take(QUEUE_PAGE_SIZE)
+        .map(|(i, video)| format!("`{}` [{}]({})", i + 1, video.title, video.get_yt_url()))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    BotResponse::success(format!("Vorschau: {}", playlist.title))
+        .description(format!("{summary}\n\n{lines}"))
+        .footer(format!("Seite {}/{}", page + 1, page_count))
+}
+
+fn playlist_preview_buttons(
+    id_prefix: &str,
+    page: usize,
+    page_count: usize,
+    disabled: bool,
+) -> Vec<CreateActionRow> {
+    vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("{id_prefix}prev"))
+            .label("◀")
+            .disabled(disabled || page == 0),
+        CreateButton::new(format!("{id_prefix}next"))
+            .label("▶")
+            .disabled(disabled || page + 1 >= page_count),
+        CreateButton::new(format!("{id_prefix}load"))
+            .label("Jetzt laden")
+            .style(ButtonStyle::Primary)
+            .disabled(disabled),
+    ])]
+}
+
+/// Shows the playlist without loading anything, with a button to load it after all
+async fn preview_playlist(
+    ctx: CommandContext<'_>,
+    playlist: YtPlaylist,
+    notes: Vec<String>,
+    announce: LoadAnnouncement,
+    user_guild: GuildId,
+    connect_to: ChannelId,
+) -> Result<(), CommandError> {
+    let locale = get_locale(ctx).await;
+    let id_prefix = ctx.id().to_string();
+    let page_count = playlist.videos.len().div_ceil(QUEUE_PAGE_SIZE).max(1);
+    let mut page = 0;
+    let embed_mode = EmbedMode::of_reply(ctx, true).await;
+
+    let reply = ctx
+        .send(
+            embed_mode
+                .reply(
+                    CreateReply::default(),
+                    render_playlist_preview(locale, &playlist, &notes, page).embed(ctx),
+                )
+                .components(playlist_preview_buttons(
+                    &id_prefix, page, page_count, false,
+                ))
+                .ephemeral(true),
+        )
+        .await?;
+
+    let author_id = ctx.author().id;
+    while let Some(press) = ComponentInteractionCollector::new(ctx.serenity_context())
+        .filter({
+            let id_prefix = id_prefix.clone();
+            move |press| press.data.custom_id.starts_with(&id_prefix) && press.user.id == author_id
+        })
+        .timeout(PLAYLIST_PREVIEW_TIMEOUT)
+        .await
+    {
+        let load = match &press.data.custom_id[id_prefix.len()..] {
+            "prev" => {
+                page = page.saturating_sub(1);
+                false
+            }
+            "next" => {
+                page = (page + 1).min(page_count - 1);
+                false
+            }
+            "load" => true,
+            _ => false,
+        };
+
+        press
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    embed_mode
+                        .message(
+                            CreateInteractionResponseMessage::new(),
+                            render_playlist_preview(locale, &playlist, &notes, page).embed(ctx),
+                        )
+                        .components(playlist_preview_buttons(&id_prefix, page, page_count, load)),
+                ),
+            )
+            .await?;
+
+        if load {
+            return load_playlist(ctx, playlist, notes, announce, user_guild, connect_to).await;
+        }
+    }
+
+    // Disable the buttons once nobody listens for them anymore
+    reply
+        .edit(
+            ctx,
+            embed_mode
+                .reply(
+                    CreateReply::default(),
+                    render_playlist_preview(locale, &playlist, &notes, page).embed(ctx),
+                )
+                .components(playlist_preview_buttons(&id_prefix, page, page_count, true)),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Loops the current track until loop is deactivated again, or the track is skipped
+#[poise::command(
+    rename = "loop",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Wiederholt das aktuelle Lied bis loop wieder deaktiviert oder es übersprungen wird"
+    )
+)]
+pub async fn loop_command(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let (channel_id, call) = get_call(ctx).await?;
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+
+    let (current_track, _) = live_current_track(&call).await?;
+
+    let modes = get_playback_modes(ctx.serenity_context()).await;
+    let was_looping = !modes
+        .apply(guild_id, ModeChange::ToggleLoopTrack)
+        .loop_track;
+
+    if was_looping {
+        _ = current_track.disable_loop()
+    } else {
+        _ = current_track.enable_loop()
+    }
+    // The cached loop state of the track is outdated now
+    get_position_cache(ctx.serenity_context())
+        .await
+        .invalidate(guild_id);
+
+    let response_details = format!(
+        "Wiederholung für `{}` in {} {}",
+        get_metadata(&current_track).await.title,
+        channel_id.to_channel(ctx).await?.mention(),
+        if was_looping {
+            "deaktiviert"
+        } else {
+            "aktiviert"
+        }
+    );
+
+    _ = respond_success(&ctx, "Loop", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Repeats the whole queue by adding finished tracks to its end again
+#[poise::command(
+    rename = "loopqueue",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Wiederholt die ganze Warteschlange, indem fertige Lieder wieder hinten eingereiht werden"
+    )
+)]
+pub async fn loop_queue(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let (channel_id, _) = get_call(ctx).await?;
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+
+    let modes = get_playback_modes(ctx.serenity_context()).await;
+    let mode = modes.apply(guild_id, ModeChange::ToggleLoopQueue);
+
+    let response_details = format!(
+        "Wiederholung der Warteschlange in {} {}\n`Modus`: {}",
+        channel_id.to_channel(ctx).await?.mention(),
+        if mode.loop_queue {
+            "aktiviert"
+        } else {
+            "deaktiviert"
+        },
+        mode.describe()
+    );
+
+    _ = respond_success(&ctx, "Loop", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Inserts new tracks so that everyone takes turns instead of at the end of the queue
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Reiht neue Lieder abwechselnd nach Person ein statt am Ende der Warteschlange"
+    )
+)]
+pub async fn fair(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let (channel_id, _) = get_call(ctx).await?;
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+
+    let modes = get_playback_modes(ctx.serenity_context()).await;
+    let mode = modes.apply(guild_id, ModeChange::ToggleFair);
+
+    let response_details = format!(
+        "Faire Warteschlange in {} {}\n`Modus`: {}",
+        channel_id.to_channel(ctx).await?.mention(),
+        if mode.fair {
+            "aktiviert"
+        } else {
+            "deaktiviert"
+        },
+        mode.describe()
+    );
+
+    _ = respond_success(&ctx, "Fair", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Plays suggested tracks when the queue runs out
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Spielt vorgeschlagene Lieder ab, wenn die Warteschlange leer wird"
+    )
+)]
+pub async fn autoplay(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let (channel_id, _) = get_call(ctx).await?;
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+
+    let modes = get_playback_modes(ctx.serenity_context()).await;
+    let mode = modes.apply(guild_id, ModeChange::ToggleAutoplay);
+    // Picks the first suggestion right away instead of with the next track
+    autoplay::refresh(
+        ctx.serenity_context(),
+        &*get_autoplay(ctx.serenity_context()).await,
+        guild_id,
+    )
+    .await;
+
+    let response_details = format!(
+        "Autoplay in {} {}\n`Modus`: {}",
+        channel_id.to_channel(ctx).await?.mention(),
+        if mode.autoplay {
+            "aktiviert"
+        } else {
+            "deaktiviert"
+        },
+        mode.describe()
+    );
+
+    _ = respond_success(&ctx, "Autoplay", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Skips the currently playing track
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized("de", "Überspringt das aktuelle Lied")
+)]
+pub async fn skip(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let (channel_id, call) = get_call(ctx).await?;
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let end_markers = get_end_markers(ctx.serenity_context()).await;
+    let modes = get_playback_modes(ctx.serenity_context()).await;
+    let looping = modes.get(guild_id).loop_track;
+
+    let (skipped, next) = with_queue_lock(ctx, &call, |queue| {
+        let skipped = skip_current(
+            queue,
+            guild_id,
+            &end_markers,
+            Some(ctx.author().id),
+            looping,
+        );
+        (skipped, queue.current())
+    })
+    .await?;
+    let skipped = get_metadata(&skipped.ok_or(QueueEmpty)?).await;
+    // The loop belonged to the skipped track, it would otherwise stay active without a next one
+    if looping {
+        modes.apply(guild_id, ModeChange::SetLoopTrack(false));
+        get_position_cache(ctx.serenity_context())
+            .await
+            .invalidate(guild_id);
+    }
+    get_audit_log(ctx.serenity_context()).await.record(
+        guild_id,
+        Some(ctx.author().id),
+        AuditAction::Skipped {
+            title: skipped.title.clone(),
+        },
+    );
+
+    let channel = channel_id.to_channel(ctx).await?.mention().to_string();
+    let next = match next {
+        Some(next) => {
+            let next = get_metadata(&next).await;
+            let requester = get_user_preferences(ctx.serenity_context())
+                .await
+                .requesters(next.requested_by)
+                .render(next.requested_by, true);
+            Some((next, requester))
+        }
+        None => None,
+    };
+    let response_details = skip_details(
+        &skipped,
+        &channel,
+        looping,
+        next.as_ref()
+            .map(|(next, requester)| (&**next, requester.as_str())),
+        get_locale(ctx).await,
+    );
+
+    _ = respond_success(&ctx, "Skipped", response_details, false).await?;
+
+    Ok(())
+}
+
+/// The skipped track with its title and author, and the track that plays now with its requester
+fn skip_details(
+    skipped: &TrackMetadata,
+    channel: &str,
+    looping: bool,
+    next: Option<(&TrackMetadata, &str)>,
+    locale: Locale,
+) -> String {
+    let mut details = format!(
+        "`{}` von {} in Kanal {channel} übersprungen",
+        skipped.title, skipped.author,
+    );
+    if looping {
+        details += ", die Wiederholung ist beendet";
+    }
+    if let Some((next, requester)) = next {
+        details += &format!(
+            "\n`{}` von {} wird jetzt abgespielt ({}, angefordert von {requester})",
+            next.title,
+            next.author,
+            match next.duration.is_zero() {
+                true => "Länge unbekannt".to_owned(),
+                false => locale.format_duration(next.duration),
+            },
+        );
+    }
+    details
+}
+
+/// Stops playback and clears the queue
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized("de", "Stoppt die aktive Wiedergabe und leert die Warteschlange")
+)]
+pub async fn stop(
+    ctx: CommandContext<'_>,
+    #[description = "Whether the current track should play to the end"]
+    #[description_localized("de", "Ob das aktuelle Lied noch zu Ende gespielt werden soll")]
+    finish_current: Option<bool>,
+) -> Result<(), CommandError> {
+    let (channel_id, call) = get_call(ctx).await?;
+    let finish_current = finish_current.unwrap_or(false);
+
+    let queue_len = call.lock().await.queue().len();
+    if queue_len == 0 {
+        return Err(QueueEmpty);
+    };
+    let removed = if finish_current {
+        queue_len - 1
+    } else {
+        queue_len
+    };
+    // The call is not locked while waiting, so playback continues during the prompt
+    if !confirm_removal(ctx, removed, "/stop").await? {
+        return Ok(());
+    }
+
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    // Loop modes are cleared either way, a looping current track would never finish
+    get_playback_modes(ctx.serenity_context())
+        .await
+        .apply(guild_id, ModeChange::stop(finish_current));
+    let channel = channel_id.to_channel(ctx).await?.mention();
+    get_audit_log(ctx.serenity_context()).await.record(
+        guild_id,
+        Some(ctx.author().id),
+        AuditAction::Stopped { removed },
+    );
+
+    let end_markers = get_end_markers(ctx.serenity_context()).await;
+    let event = with_queue_lock(ctx, &call, |queue| {
+        stop_queue(
+            queue,
+            guild_id,
+            &end_markers,
+            Some(ctx.author().id),
+            finish_current,
+        )
+    })
+    .await?;
+    get_playback_events(ctx.serenity_context())
+        .await
+        .publish(guild_id, event);
+
+    let response_details = if finish_current {
+        format!(
+            "Warteliste in Kanal {channel} geleert, das aktuelle Lied wird noch zu Ende gespielt"
+        )
+    } else {
+        format!("Wiedergabe in Kanal {channel} gestoppt und Warteliste geleert")
+    };
+
+    _ = respond_success(&ctx, "Stopped", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Leaves the current channel
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized("de", "Verlässt den aktuellen Channel")
+)]
+pub async fn leave(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let (channel_id, call) = get_call(ctx).await?;
+    let mut call = call.lock().await;
+
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    leave_with_reason(
+        &ctx.serenity_context().data,
+        guild_id,
+        &mut call,
+        LeaveReason::ManualLeave,
+    )
+    .await
+    .map_err(|_| LeaveVoice)?;
+
+    let response_details = format!("{} verlassen", channel_id.to_channel(ctx).await?.mention());
+    _ = respond_success(&ctx, "Left", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Rejoins and continues the queue where it was interrupted by /leave or a disconnect
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Tritt deinem Sprachkanal bei und setzt die Warteschlange fort, die beim Verlassen unterbrochen wurde"
+    )
+)]
+pub async fn resumesession(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let (user_guild, user_channel) = get_author_voice_state(ctx);
+    let connect_to = user_channel.ok_or(UserNotInVoice)?;
+
+    let resume_points = get_resume_points(ctx.serenity_context()).await;
+    let Some(point) = resume_points.take(user_guild) else {
+        let response_details = "Es gibt keine unterbrochene Wiedergabe, die fortgesetzt werden kann. Sie verfällt, sobald wieder etwas abgespielt wird.";
+        _ = respond_success(&ctx, "Fortsetzen", response_details, true).await?;
+        return Ok(());
+    };
+
+    let songbird = songbird::get(ctx.serenity_context())
+        .await
+        .ok_or(SongbirdNotFound)?;
+    let call = match join_voice(
+        ctx.serenity_context(),
+        songbird.clone(),
+        user_guild,
+        connect_to,
+    )
+    .await
+    {
+        Ok(call) => call,
+        Err(e) => {
+            // Nothing was resumed, so it can be tried again
+            resume_points.save(user_guild, point);
+            return Err(e.into());
+        }
+    };
+    start_track_validator(ctx, songbird, user_guild).await;
+
+    let capacity = queue_capacity(ctx.serenity_context(), &call).await;
+    let restored = capacity.fit(point.tracks.len());
+    // Something queued in the meantime keeps playing, the interrupted track waits behind it
+    let was_empty = capacity.free == capacity.limit;
+    for metadata in &point.tracks[..restored] {
+        enqueue_resolved(ctx, call.clone(), metadata.clone(), EnqueueOrigin::Restore).await?;
+    }
+    // Loads the first track right away, like a restart after a stall
+    let current = call.lock().await.queue().current();
+    let seeked = match current {
+        Some(current) if was_empty && point.can_seek() && !point.position.is_zero() => {
+            _ = current.seek(point.position);
+            true
+        }
+        _ => false,
+    };
+    get_playback_events(ctx.serenity_context())
+        .await
+        .publish(user_guild, PlaybackEvent::QueueChanged);
+
+    let first = &point.tracks[0];
+    let mut response_details = format!(
+        "{restored} Lieder in {} wiederhergestellt, [{}]({}) ",
+        connect_to.to_channel(ctx).await?.mention(),
+        first.title,
+        first.source_url
+    );
+    if seeked {
+        let position = get_locale(ctx).await.format_duration(point.position);
+        response_details += &format!("läuft ab {position} weiter");
+    } else if point.position.is_zero() {
+        response_details += "beginnt von vorn";
+    } else {
+        response_details += "beginnt von vorn, weil an dieser Quelle nicht gespult werden kann";
+    }
+    if let Some(notice) = capacity.notice(point.tracks.len()) {
+        response_details += &format!("\n{notice}");
+    }
+    _ = respond_success(&ctx, "Fortgesetzt", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Mentions you in this channel once the bot is free again
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Erwähnt dich in diesem Kanal, sobald der Bot nichts mehr spielt oder den Sprachkanal verlässt"
+    )
+)]
+pub async fn notifyfree(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let response_details = register_free_notice(
+        ctx.serenity_context(),
+        guild_id,
+        ctx.author().id,
+        &InvokingChannel::of_command(ctx),
+    )
+    .await;
+    _ = respond_success(&ctx, "Benachrichtigung", response_details, true).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(title: &str, author: &str, secs: u64) -> TrackMetadata {
+        let mut metadata = TrackMetadata::unresolved("https://www.youtube.com/watch?v=aaaaaaaaaaa");
+        metadata.title = title.to_owned();
+        metadata.author = author.to_owned();
+        metadata.duration = Duration::from_secs(secs);
+        metadata
+    }
+
+    #[test]
+    fn skip_names_the_title_and_the_author() {
+        let skipped = track("Never Gonna Give You Up", "Rick Astley", 213);
+        assert_eq!(
+            skip_details(&skipped, "#musik", false, None, Locale::German),
+            "`Never Gonna Give You Up` von Rick Astley in Kanal #musik übersprungen"
+        );
+    }
+
+    #[test]
+    fn skip_shows_the_next_track() {
+        let skipped = track("Song A", "Band A", 100);
+        let next = track("Song B", "Band B", 192);
+        assert_eq!(
+            skip_details(
+                &skipped,
+                "#musik",
+                false,
+                Some((&next, "<@1>")),
+                Locale::German
+            ),
+            "`Song A` von Band A in Kanal #musik übersprungen\n\
+             `Song B` von Band B wird jetzt abgespielt (03:12, angefordert von <@1>)"
+        );
+        let live = track("Radio", "Sender", 0);
+        assert!(skip_details(
+            &skipped,
+            "#musik",
+            false,
+            Some((&live, "ein Nutzer")),
+            Locale::German
+        )
+        .ends_with("(Länge unbekannt, angefordert von ein Nutzer)"));
+    }
+
+    #[test]
+    fn skipping_a_looping_track_ends_the_loop() {
+        let skipped = track("Song A", "Band A", 100);
+        assert_eq!(
+            skip_details(&skipped, "#musik", true, None, Locale::German),
+            "`Song A` von Band A in Kanal #musik übersprungen, die Wiederholung ist beendet"
+        );
+    }
+}
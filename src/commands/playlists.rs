@@ -0,0 +1,497 @@
+use log::error;
+use serenity::all::{Attachment, AutocompleteChoice, GuildId};
+
+use crate::audit_log::EnqueueOrigin;
+use crate::commands::playback::{load_playlist_selection, PlaylistSelection};
+use crate::commands::util::GetCallError::SongbirdNotFound;
+use crate::commands::util::{
+    enqueue_track, get_author_voice_state, get_saved_playlists, get_yt_id_from_url, join_voice,
+    queue_capacity, respond_success, start_track_validator, truncate_chars,
+};
+use crate::import::{parse_import, ImportProblem, ImportedRow, SUPPORTED_FORMATS};
+use crate::saved_playlists::{
+    LoadAnnouncement, PlaylistOptions, SaveOutcome, SavedPlaylist, MAX_SAVED_PER_GUILD,
+};
+use crate::CommandError::UserNotInVoice;
+use crate::{CommandContext, CommandError, LoadGuardKey};
+
+/// Larger files are not downloaded
+const MAX_IMPORT_BYTES: u32 = 1024 * 1024;
+/// Rows of a file that are imported, the rest is reported
+const MAX_IMPORT_ROWS: usize = 100;
+/// Skipped rows listed in the response
+const LISTED_PROBLEMS: usize = 15;
+/// Like the name parameter of /playlists save
+const MAX_NAME_CHARS: usize = 50;
+
+// ======== Commands ========
+
+/// YouTube playlists saved under a name, with how they are played
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands(
+        "playlists_save",
+        "playlists_load",
+        "playlists_info",
+        "playlists_delete"
+    ),
+    subcommand_required
+)]
+pub async fn playlists(_ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    Ok(())
+}
+
+/// Saves a YouTube playlist under a name
+#[poise::command(
+    rename = "save",
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    description_localized("de", "Speichert eine YouTube-Playlist unter einem Namen")
+)]
+pub async fn playlists_save(
+    ctx: CommandContext<'_>,
+    #[description = "Name to load the playlist with"]
+    #[description_localized("de", "Name, unter dem die Playlist geladen wird")]
+    #[max_length = 50]
+    name: String,
+    #[description = "Link to a YouTube playlist"]
+    #[description_localized("de", "Link zu einer YouTube-Playlist")]
+    url: String,
+    #[description = "Whether the playlist is shuffled when loaded"]
+    #[description_localized("de", "Ob die Playlist beim Laden gemischt wird")]
+    shuffle: Option<bool>,
+    #[description = "What is announced when the playlist is loaded"]
+    #[description_localized("de", "Was beim Laden der Playlist angekündigt wird")]
+    announce: Option<LoadAnnouncement>,
+    #[description = "Maximum number of tracks to load"]
+    #[description_localized("de", "Maximale Anzahl der Lieder, die geladen werden")]
+    #[min = 1]
+    max_items: Option<u32>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let saved_playlists = get_saved_playlists(ctx.serenity_context()).await;
+    saved_playlists.check_available()?;
+    let Some(playlist_id) = get_yt_id_from_url(&url).playlist_id else {
+        let response_details = format!("`{url}` ist kein Link zu einer YouTube-Playlist");
+        _ = respond_success(&ctx, "Playlist speichern", response_details, true).await?;
+        return Ok(());
+    };
+    let name = name.trim().to_owned();
+    let options = PlaylistOptions {
+        shuffle: shuffle.unwrap_or(false),
+        announce: announce.unwrap_or_default(),
+        max_items,
+    };
+
+    let outcome = saved_playlists.save(SavedPlaylist {
+        guild_id,
+        name: name.clone(),
+        playlist_id,
+        options,
+    });
+    let response_details = match outcome {
+        SaveOutcome::Created => format!(
+            "Gespeichert als `{name}`\n{}",
+            render_options(&options)
+        ),
+        SaveOutcome::Replaced => format!(
+            "`{name}` wurde überschrieben\n{}",
+            render_options(&options)
+        ),
+        SaveOutcome::LimitReached => format!(
+            "Es können höchstens {MAX_SAVED_PER_GUILD} Playlists gespeichert werden. Lösche zuerst eine mit `/playlists delete`"
+        ),
+    };
+    _ = respond_success(&ctx, "Playlist speichern", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Loads a saved playlist into the queue
+#[poise::command(
+    rename = "load",
+    slash_command,
+    guild_only,
+    description_localized("de", "Lädt eine gespeicherte Playlist in die Warteschlange"),
+    required_bot_permissions = "VIEW_CHANNEL | CONNECT | SPEAK"
+)]
+pub async fn playlists_load(
+    ctx: CommandContext<'_>,
+    #[description = "Name of the saved playlist"]
+    #[description_localized("de", "Name der gespeicherten Playlist")]
+    #[autocomplete = "autocomplete_saved_playlist"]
+    name: String,
+    #[description = "Whether the tracks are shuffled, the saved option if empty"]
+    #[description_localized(
+        "de",
+        "Ob die Lieder gemischt werden, leer für die gespeicherte Einstellung"
+    )]
+    shuffle: Option<bool>,
+    #[description = "What is announced, the saved option if empty"]
+    #[description_localized("de", "Was angekündigt wird, leer für die gespeicherte Einstellung")]
+    announce: Option<LoadAnnouncement>,
+    #[description = "Maximum number of tracks to add, the saved option if empty"]
+    #[description_localized(
+        "de",
+        "Maximale Anzahl der Lieder, leer für die gespeicherte Einstellung"
+    )]
+    #[min = 1]
+    count: Option<u32>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let saved_playlists = get_saved_playlists(ctx.serenity_context()).await;
+    saved_playlists.check_available()?;
+    let Some(saved) = saved_playlists.get(guild_id, &name) else {
+        return respond_unknown(ctx, &name).await;
+    };
+
+    let selection = PlaylistSelection {
+        shuffle: shuffle.unwrap_or(saved.options.shuffle),
+        count: count.or(saved.options.max_items).map(|c| c as usize),
+        announce: announce.unwrap_or(saved.options.announce),
+        ..Default::default()
+    };
+    let url = format!(
+        "https://www.youtube.com/playlist?list={}",
+        saved.playlist_id
+    );
+    load_playlist_selection(ctx, &url, selection).await
+}
+
+/// Shows a saved playlist and its options
+#[poise::command(
+    rename = "info",
+    slash_command,
+    guild_only,
+    description_localized("de", "Zeigt eine gespeicherte Playlist und ihre Einstellungen")
+)]
+pub async fn playlists_info(
+    ctx: CommandContext<'_>,
+    #[description = "Name of the saved playlist, empty to list all"]
+    #[description_localized("de", "Name der gespeicherten Playlist, leer für alle")]
+    #[autocomplete = "autocomplete_saved_playlist"]
+    name: Option<String>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let saved_playlists = get_saved_playlists(ctx.serenity_context()).await;
+    saved_playlists.check_available()?;
+
+    let response_details = match name {
+        Some(name) => match saved_playlists.get(guild_id, &name) {
+            Some(saved) => format!(
+                "`{}`: https://www.youtube.com/playlist?list={}\n{}",
+                saved.name,
+                saved.playlist_id,
+                render_options(&saved.options)
+            ),
+            None => return respond_unknown(ctx, &name).await,
+        },
+        None => match saved_playlists.names(guild_id).as_slice() {
+            [] => "Es sind keine Playlists gespeichert".to_owned(),
+            names => names
+                .iter()
+                .map(|name| format!("`{name}`"))
+                .collect::<Vec<_>>()
+                .join(", "),
+        },
+    };
+    _ = respond_success(&ctx, "Gespeicherte Playlists", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Deletes a saved playlist
+#[poise::command(
+    rename = "delete",
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    description_localized("de", "Löscht eine gespeicherte Playlist")
+)]
+pub async fn playlists_delete(
+    ctx: CommandContext<'_>,
+    #[description = "Name of the saved playlist"]
+    #[description_localized("de", "Name der gespeicherten Playlist")]
+    #[autocomplete = "autocomplete_saved_playlist"]
+    name: String,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let saved_playlists = get_saved_playlists(ctx.serenity_context()).await;
+    saved_playlists.check_available()?;
+    if !saved_playlists.delete(guild_id, &name) {
+        return respond_unknown(ctx, &name).await;
+    }
+    let response_details = format!("`{name}` wurde gelöscht");
+    _ = respond_success(&ctx, "Gespeicherte Playlists", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Where the rows of an imported file go
+#[derive(Clone, Copy, Debug, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum ImportTarget {
+    #[name = "Warteschlange"]
+    Queue,
+    #[name = "Gespeicherte Playlists"]
+    SavedPlaylists,
+}
+
+/// Imports tracks or playlists exported from this or other bots
+#[poise::command(
+    slash_command,
+    guild_only,
+    required_bot_permissions = "VIEW_CHANNEL | CONNECT | SPEAK",
+    description_localized(
+        "de",
+        "Importiert Lieder oder Playlists, die aus diesem oder anderen Bots exportiert wurden"
+    )
+)]
+pub async fn import(
+    ctx: CommandContext<'_>,
+    #[description = "Exported file, a list of links or JSON"]
+    #[description_localized("de", "Exportierte Datei, eine Liste von Links oder JSON")]
+    file: Attachment,
+    #[description = "Whether the rows are queued or saved as playlists"]
+    #[description_localized(
+        "de",
+        "Ob die Einträge in die Warteschlange kommen oder als Playlists gespeichert werden"
+    )]
+    target: ImportTarget,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    if target == ImportTarget::SavedPlaylists {
+        get_saved_playlists(ctx.serenity_context())
+            .await
+            .check_available()?;
+        // Same permission as /playlists save
+        let may_save = ctx
+            .author_member()
+            .await
+            .and_then(|member| member.permissions)
+            .is_some_and(|permissions| permissions.manage_guild());
+        if !may_save {
+            let response_details =
+                "Nur Mitglieder mit der Berechtigung „Server verwalten“ können Playlists speichern";
+            _ = respond_success(&ctx, "Import", response_details, true).await?;
+            return Ok(());
+        }
+    }
+    if file.size > MAX_IMPORT_BYTES {
+        let response_details = format!(
+            "Die Datei ist zu groß, es werden höchstens {} KB importiert",
+            MAX_IMPORT_BYTES / 1024
+        );
+        _ = respond_success(&ctx, "Import", response_details, true).await?;
+        return Ok(());
+    }
+
+    ctx.defer().await?;
+    let parsed = match String::from_utf8(file.download().await?) {
+        Ok(content) => parse_import(&content),
+        Err(_) => {
+            let response_details = "Die Datei ist keine Textdatei";
+            _ = respond_success(&ctx, "Import", response_details, true).await?;
+            return Ok(());
+        }
+    };
+    let Ok(mut parsed) = parsed else {
+        let response_details = format!(
+            "Das Format der Datei wird nicht unterstützt. Unterstützt werden:\n{SUPPORTED_FORMATS}"
+        );
+        _ = respond_success(&ctx, "Import", response_details, true).await?;
+        return Ok(());
+    };
+    if parsed.rows.len() > MAX_IMPORT_ROWS {
+        let ignored = parsed.rows.split_off(MAX_IMPORT_ROWS);
+        parsed
+            .problems
+            .extend(ignored.into_iter().map(|row| ImportProblem {
+                row: row.row,
+                reason: format!("übersteigt die Grenze von {MAX_IMPORT_ROWS} Einträgen"),
+            }));
+    }
+
+    let (imported, mut problems) = match target {
+        ImportTarget::Queue => import_into_queue(ctx, parsed.rows).await?,
+        ImportTarget::SavedPlaylists => import_as_playlists(ctx, guild_id, parsed.rows).await,
+    };
+    problems.extend(parsed.problems);
+    problems.sort_by_key(|problem| problem.row);
+
+    let mut response_details = format!(
+        "`Format`: {}\n{}",
+        parsed.format.describe(),
+        match target {
+            ImportTarget::Queue => format!("{imported} Lieder hinzugefügt"),
+            ImportTarget::SavedPlaylists => format!("{imported} Playlists gespeichert"),
+        }
+    );
+    if !problems.is_empty() {
+        response_details += &format!("\n{} Einträge übersprungen:", problems.len());
+        for problem in problems.iter().take(LISTED_PROBLEMS) {
+            response_details += &format!(
+                "\n{} {}: {}",
+                parsed.format.row_name(),
+                problem.row,
+                problem.reason
+            );
+        }
+        if problems.len() > LISTED_PROBLEMS {
+            response_details += &format!("\nund {} weitere", problems.len() - LISTED_PROBLEMS);
+        }
+    }
+    _ = respond_success(&ctx, "Import", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Adds the tracks to the queue like /play, playlists have to be loaded with /playlist
+async fn import_into_queue(
+    ctx: CommandContext<'_>,
+    mut rows: Vec<ImportedRow>,
+) -> Result<(usize, Vec<ImportProblem>), CommandError> {
+    let (user_guild, user_channel) = get_author_voice_state(ctx);
+    let connect_to = user_channel.ok_or(UserNotInVoice)?;
+    let songbird = songbird::get(ctx.serenity_context())
+        .await
+        .ok_or(SongbirdNotFound)?;
+    let call = join_voice(
+        ctx.serenity_context(),
+        songbird.clone(),
+        user_guild,
+        connect_to,
+    )
+    .await?;
+    start_track_validator(ctx, songbird, user_guild).await;
+    // Held until all tracks are enqueued, like a playlist load
+    let _permit = ctx
+        .serenity_context()
+        .data
+        .read()
+        .await
+        .get::<LoadGuardKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+        .acquire(user_guild)
+        .await?;
+
+    let capacity = queue_capacity(ctx.serenity_context(), &call).await;
+    let mut problems = rows
+        .split_off(capacity.fit(rows.len()))
+        .into_iter()
+        .map(|row| ImportProblem {
+            row: row.row,
+            reason: format!(
+                "passt nicht mehr in die Warteschlange mit höchstens {} Einträgen",
+                capacity.limit
+            ),
+        })
+        .collect::<Vec<_>>();
+    let mut enqueued = 0;
+    for row in rows {
+        let reason = if row.playlist_id().is_some()
+            && get_yt_id_from_url(row.url.as_str()).video_id.is_none()
+        {
+            Some("ist eine Playlist, lade sie mit /playlist".to_owned())
+        } else {
+            match enqueue_track(ctx, call.clone(), row.url.as_str(), EnqueueOrigin::Import).await {
+                Ok(_) => None,
+                Err(CommandError::TrackTooLong { .. }) => Some("ist zu lang".to_owned()),
+                Err(CommandError::Blocked { .. }) => {
+                    Some("ist auf diesem Server gesperrt".to_owned())
+                }
+                Err(e) => {
+                    error!("Failed to import {}: {e}", row.url);
+                    Some("konnte nicht geladen werden".to_owned())
+                }
+            }
+        };
+        match reason {
+            Some(reason) => problems.push(ImportProblem {
+                row: row.row,
+                reason,
+            }),
+            None => enqueued += 1,
+        }
+    }
+
+    Ok((enqueued, problems))
+}
+
+/// Saves the YouTube playlists under their exported names, or their titles
+async fn import_as_playlists(
+    ctx: CommandContext<'_>,
+    guild_id: GuildId,
+    rows: Vec<ImportedRow>,
+) -> (usize, Vec<ImportProblem>) {
+    let saved_playlists = get_saved_playlists(ctx.serenity_context()).await;
+    let mut saved = 0;
+    let mut problems = vec![];
+    for row in rows {
+        let Some(playlist_id) = row.playlist_id() else {
+            problems.push(ImportProblem {
+                row: row.row,
+                reason: "ist keine YouTube-Playlist".to_owned(),
+            });
+            continue;
+        };
+        let name = row
+            .name
+            .or(row.title)
+            .unwrap_or_else(|| format!("Import {}", row.row));
+        let outcome = saved_playlists.save(SavedPlaylist {
+            guild_id,
+            name: truncate_chars(name.trim(), MAX_NAME_CHARS).into_owned(),
+            playlist_id,
+            options: row.options,
+        });
+        match outcome {
+            SaveOutcome::Created | SaveOutcome::Replaced => saved += 1,
+            SaveOutcome::LimitReached => problems.push(ImportProblem {
+                row: row.row,
+                reason: format!("es sind bereits {MAX_SAVED_PER_GUILD} Playlists gespeichert"),
+            }),
+        }
+    }
+
+    (saved, problems)
+}
+
+// ======== Helpers ========
+
+async fn autocomplete_saved_playlist(
+    ctx: CommandContext<'_>,
+    partial: &str,
+) -> Vec<AutocompleteChoice> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return vec![];
+    };
+    let partial = partial.to_lowercase();
+    get_saved_playlists(ctx.serenity_context())
+        .await
+        .names(guild_id)
+        .into_iter()
+        .filter(|name| name.to_lowercase().contains(&partial))
+        .take(25)
+        .map(|name| AutocompleteChoice::new(name.clone(), name))
+        .collect()
+}
+
+async fn respond_unknown(ctx: CommandContext<'_>, name: &str) -> Result<(), CommandError> {
+    let response_details = format!("Es ist keine Playlist `{name}` gespeichert");
+    _ = respond_success(&ctx, "Gespeicherte Playlists", response_details, true).await?;
+    Ok(())
+}
+
+fn render_options(options: &PlaylistOptions) -> String {
+    format!(
+        "`Mischen`: {}\n`Ankündigung`: {}\n`Maximale Anzahl`: {}",
+        if options.shuffle { "Ja" } else { "Nein" },
+        options.announce.describe(),
+        options
+            .max_items
+            .map_or("Alle".to_owned(), |max| max.to_string())
+    )
+}
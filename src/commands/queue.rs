@@ -0,0 +1,1366 @@
+use poise::CreateReply;
+use rand::thread_rng;
+use reqwest::Url;
+use serenity::all::{
+    ComponentInteractionCollector, CreateInteractionResponseFollowup, CreateQuickModal, GuildId,
+    Mentionable,
+};
+use serenity::builder::{
+    CreateActionRow, CreateAllowedMentions, CreateButton, CreateInteractionResponse,
+    CreateInteractionResponseMessage,
+};
+use serenity::futures::future::join_all;
+use serenity::futures::stream::{self, StreamExt};
+use songbird::tracks::TrackHandle;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::audit_log::{AuditAction, EnqueueOrigin};
+use crate::autoplay::AutoplaySuggestion;
+use crate::canonical_url::same_track;
+use crate::commands::util::{
+    autoplay_buttons, clamp_page, clear_upcoming, enqueue_resolved, get_audit_log, get_autoplay,
+    get_call, get_end_markers, get_http_client, get_locale, get_metadata, get_playback_events,
+    get_position_cache, get_staging, get_undo_slots, get_youtube_client, get_yt_id_from_url,
+    get_ytdlp_config, get_ytdlp_permits, info_is_ephemeral, live_current_track, page_count,
+    preload_new_next, press_autoplay_button, queue_capacity, respond_success, truncate_chars,
+    with_queue_lock, QUEUE_PAGE_SIZE,
+};
+use crate::confirm::confirm_removal;
+use crate::events::PlaybackEvent;
+use crate::locale::Locale;
+use crate::metadata::{Playability, TrackMetadata, TrackMetadataKey};
+use crate::plain_text::EmbedMode;
+use crate::position_cache::PositionCache;
+use crate::queue_ops;
+use crate::response::{is_ephemeral, BotResponse, QUIET_NOTE};
+use crate::staging::STAGING_TTL;
+use crate::ytdlp::YtDlpInput;
+use crate::CommandError::{NothingUpcoming, QueueEmpty, TooFewToShuffle};
+use crate::{CommandContext, CommandError, MIN_SHUFFLE_ENTRIES};
+
+// ======== Commands ========
+
+/// Entry of a queue listing compared to the snapshot the listing was created from
+enum QueueDiffEntry {
+    Unchanged(usize, Arc<TrackMetadata>),
+    Added(usize, Arc<TrackMetadata>),
+    Removed(Arc<TrackMetadata>),
+}
+
+/// Queue entries identified by their track, so refreshed metadata is not seen as a new entry
+type QueueSnapshot = Vec<(Uuid, Arc<TrackMetadata>)>;
+
+/// Compares two queue snapshots by track identity. Entries of `new` keep their queue index,
+/// removed entries are placed where they were in `old`.
+fn diff_queue(old: &QueueSnapshot, new: &QueueSnapshot) -> Vec<QueueDiffEntry> {
+    let mut entries = Vec::with_capacity(new.len());
+    let mut old_index = 0;
+
+    let is_removed = |id: &Uuid| !new.iter().any(|(n, _)| n == id);
+
+    for (i, (id, meta)) in new.iter().enumerate() {
+        match old.iter().position(|(o, _)| o == id) {
+            Some(pos) => {
+                if pos >= old_index {
+                    entries.extend(
+                        old[old_index..pos]
+                            .iter()
+                            .filter(|(o, _)| is_removed(o))
+                            .map(|(_, meta)| QueueDiffEntry::Removed(meta.clone())),
+                    );
+                    old_index = pos + 1;
+                }
+                entries.push(QueueDiffEntry::Unchanged(i, meta.clone()));
+            }
+            None => entries.push(QueueDiffEntry::Added(i, meta.clone())),
+        }
+    }
+
+    entries.extend(
+        old[old_index.min(old.len())..]
+            .iter()
+            .filter(|(o, _)| is_removed(o))
+            .map(|(_, meta)| QueueDiffEntry::Removed(meta.clone())),
+    );
+
+    entries
+}
+
+const QUEUE_BUTTON_TIMEOUT: Duration = Duration::from_secs(180);
+const PAGE_MODAL_TIMEOUT: Duration = Duration::from_secs(60);
+/// Limit of Discord for the description of an embed
+const QUEUE_DESCRIPTION_CHARS: usize = 4096;
+/// Metadata reads in flight while listing the queue, long queues would otherwise lock every
+/// track at once
+const METADATA_READS: usize = 8;
+
+/// Notice for a page number that does not exist
+fn clamped_notice(requested: usize, page: usize) -> String {
+    format!(
+        "Seite {requested} gibt es nicht, es wird Seite {} angezeigt",
+        page + 1
+    )
+}
+
+/// The page to show after a button press on a list that now has `len` entries, with a notice if
+/// it is not the one that was asked for. `typed` is the input of the page modal, None if the
+/// modal was not used and `Some(None)` if it was not a number. The list may have shrunk since
+/// the last render or while the modal was open.
+fn page_after_refresh(
+    page: usize,
+    typed: Option<Option<usize>>,
+    len: usize,
+) -> (usize, Option<String>) {
+    let last = page_count(len) - 1;
+    match typed {
+        Some(Some(requested)) => {
+            let (page, clamped) = clamp_page(requested, len);
+            (page, clamped.then(|| clamped_notice(requested, page)))
+        }
+        Some(None) => (
+            page.min(last),
+            Some("Das ist keine gültige Seitenzahl".to_owned()),
+        ),
+        None => (page.min(last), None),
+    }
+}
+
+/// Reads the live queue of a guild, which is empty if the bot is not in a call
+async fn read_queue(
+    ctx: CommandContext<'_>,
+    guild_id: GuildId,
+) -> (Vec<TrackHandle>, QueueSnapshot) {
+    let Some(songbird) = songbird::get(ctx.serenity_context()).await else {
+        return (vec![], vec![]);
+    };
+    let Some(call) = songbird.get(guild_id) else {
+        return (vec![], vec![]);
+    };
+
+    let handles = call.lock().await.queue().current_queue();
+    snapshot_queue(handles).await
+}
+
+/// The metadata of the queued tracks without the spoken announcements. Only the cached metadata
+/// is read, nothing here waits on the driver.
+async fn snapshot_queue(handles: Vec<TrackHandle>) -> (Vec<TrackHandle>, QueueSnapshot) {
+    // Owned handles, borrowed ones make the future of the command not general enough for poise
+    let snapshot: QueueSnapshot = stream::iter(handles.clone())
+        .map(|handle| async move { (handle.uuid(), get_metadata(&handle).await) })
+        .buffered(METADATA_READS)
+        .collect()
+        .await;
+    // Spoken announcements are only in the queue until they ended
+    let announcements: Vec<_> = snapshot
+        .iter()
+        .filter(|(_, metadata)| metadata.announcement)
+        .map(|(uuid, _)| *uuid)
+        .collect();
+    let handles = handles
+        .into_iter()
+        .filter(|handle| !announcements.contains(&handle.uuid()))
+        .collect();
+    let snapshot = snapshot
+        .into_iter()
+        .filter(|(_, metadata)| !metadata.announcement)
+        .collect();
+    (handles, snapshot)
+}
+
+/// The current track if it loops, read from the position cache
+async fn looping_track(
+    positions: &PositionCache,
+    guild_id: GuildId,
+    handles: &[TrackHandle],
+) -> Option<Uuid> {
+    let current = handles.first()?;
+    let sample = positions.read(guild_id, current).await?;
+    sample.looping.then_some(sample.track)
+}
+
+fn render_queue_page(
+    entries: &[QueueDiffEntry],
+    handles: &[TrackHandle],
+    looping_track: Option<Uuid>,
+    suggestion: Option<&AutoplaySuggestion>,
+    page: usize,
+    accessible: Option<Locale>,
+) -> BotResponse {
+    let page_count = page_count(entries.len());
+
+    // Long links could push a page over the limit of the description, they are left out then
+    let render_lines = |links: bool| {
+        entries
+            .iter()
+            .skip(page * QUEUE_PAGE_SIZE)
+            .take(QUEUE_PAGE_SIZE)
+            .map(|entry| render_queue_line(entry, handles, looping_track, accessible, links))
+            .collect::<Vec<String>>()
+    };
+    let mut lines = render_lines(true);
+    if lines
+        .iter()
+        .map(|line| line.chars().count() + 1)
+        .sum::<usize>()
+        > QUEUE_DESCRIPTION_CHARS
+    {
+        lines = render_lines(false);
+    }
+
+    let mut description = if handles.is_empty() && lines.is_empty() {
+        "Die Warteschlange ist leer".to_owned()
+    } else if handles.is_empty() {
+        format!("Die Warteschlange ist leer\n{}", lines.join("\n"))
+    } else {
+        lines.join("\n")
+    };
+    // The suggestion follows the last entry
+    if let (Some(suggestion), true) = (suggestion, page + 1 >= page_count) {
+        description += &format!("\n{}", suggestion.render());
+    }
+
+    let total = match handles.len() {
+        1 => "1 Track".to_owned(),
+        n => format!("{n} Tracks"),
+    };
+    BotResponse::success("Queue")
+        .description(truncate_chars(&description, QUEUE_DESCRIPTION_CHARS))
+        .footer(match accessible {
+            Some(_) => format!("Seite {} von {page_count}, {total} insgesamt", page + 1),
+            None => format!("Seite {}/{page_count} – {total} insgesamt", page + 1),
+        })
+}
+
+/// One entry of the queue listing. The current track is highlighted.
+fn render_queue_line(
+    entry: &QueueDiffEntry,
+    handles: &[TrackHandle],
+    looping_track: Option<Uuid>,
+    accessible: Option<Locale>,
+    links: bool,
+) -> String {
+    let linked = |meta: &TrackMetadata| match links {
+        true => format!("[{}]({})", meta.title, meta.source_url),
+        false => meta.title.clone(),
+    };
+    let (i, meta, suffix) = match entry {
+        QueueDiffEntry::Removed(meta) if accessible.is_some() => {
+            return format!("Entfernt: {} von {}.", meta.title, meta.author);
+        }
+        QueueDiffEntry::Removed(meta) => {
+            return format!("~~{}~~ *(entfernt)*", linked(meta));
+        }
+        QueueDiffEntry::Unchanged(i, meta) => (*i, meta, ""),
+        QueueDiffEntry::Added(i, meta) => (*i, meta, " *(neu)*"),
+    };
+
+    // Only the current track can loop
+    let looping = handles
+        .get(i)
+        .is_some_and(|handle| Some(handle.uuid()) == looping_track);
+    let current = i == 0 && !handles.is_empty();
+    // One sentence per track, with words instead of icons
+    if let Some(locale) = accessible {
+        let states = [
+            (current, "läuft gerade"),
+            (
+                meta.playability() == Playability::Unplayable,
+                "nicht abspielbar",
+            ),
+            (meta.over_soft_limit, "über der Längengrenze"),
+            (looping, "wird wiederholt"),
+            (!suffix.is_empty(), "neu"),
+        ]
+        .into_iter()
+        .filter(|(active, _)| *active)
+        .map(|(_, state)| format!(", {state}"))
+        .collect::<String>();
+        let duration = match meta.duration.is_zero() {
+            true => "Länge unbekannt".to_owned(),
+            false => locale.format_duration_words(meta.duration),
+        };
+        return format!(
+            "{}. {} von {}, {duration}{states}.",
+            i + 1,
+            meta.title,
+            meta.author
+        );
+    }
+    let icon = if meta.playability() == Playability::Unplayable {
+        ":warning:"
+    } else if meta.over_soft_limit {
+        ":hourglass:"
+    } else if looping {
+        ":repeat:"
+    } else {
+        ""
+    };
+
+    match current {
+        true => format!(
+            "`{}` {} {icon} **{}** · *läuft gerade*{suffix}",
+            i + 1,
+            meta.source.icon(),
+            linked(meta)
+        ),
+        false => format!(
+            "`{}` {} {icon} {}{suffix}",
+            i + 1,
+            meta.source.icon(),
+            linked(meta)
+        ),
+    }
+}
+
+fn queue_page_buttons(
+    id_prefix: &str,
+    page: usize,
+    page_count: usize,
+    suggestion: bool,
+    disabled: bool,
+) -> Vec<CreateActionRow> {
+    let mut rows = vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("{id_prefix}prev"))
+            .label("◀")
+            .disabled(disabled || page == 0),
+        CreateButton::new(format!("{id_prefix}next"))
+            .label("▶")
+            .disabled(disabled || page + 1 >= page_count),
+        CreateButton::new(format!("{id_prefix}goto"))
+            .label("Gehe zu Seite…")
+            .disabled(disabled || page_count == 1),
+        CreateButton::new(format!("{id_prefix}refresh"))
+            .label("🔄")
+            .disabled(disabled),
+    ])];
+    if suggestion {
+        rows.push(autoplay_buttons(id_prefix, disabled));
+    }
+    rows
+}
+
+/// Shows the current queue
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized("de", "Zeigt die aktuelle Warteschlange")
+)]
+pub async fn queue(
+    ctx: CommandContext<'_>,
+    #[description = "Page to open, defaults to the first"]
+    #[description_localized("de", "Anzuzeigende Seite, standardmäßig die erste")]
+    #[min = 1]
+    page: Option<u32>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let (_, call) = get_call(ctx).await?;
+    // Dead handles are cleared before listing, an empty queue is listed as such
+    match live_current_track(&call).await {
+        Ok(_) | Err(QueueEmpty) => {}
+        Err(e) => return Err(e),
+    }
+
+    // The snapshot of the first render is kept to mark changes on refresh
+    let (mut handles, snapshot) = read_queue(ctx, guild_id).await;
+    let public_requested = !info_is_ephemeral(ctx).await;
+    let ephemeral = is_ephemeral(ctx, !public_requested).await;
+    if snapshot.is_empty() {
+        _ = respond_success(&ctx, "Queue", "Die Warteschlange ist leer", ephemeral).await?;
+        return Ok(());
+    };
+
+    let id_prefix = ctx.id().to_string();
+    let mut entries = diff_queue(&snapshot, &snapshot);
+    let autoplay = get_autoplay(ctx.serenity_context()).await;
+    let positions = get_position_cache(ctx.serenity_context()).await;
+    let mut suggestion = autoplay.suggestion(guild_id);
+    let requested = page.map(|page| page as usize);
+    let (mut page, clamped) = clamp_page(requested.unwrap_or(1), entries.len());
+    let embed_mode = EmbedMode::of_reply(ctx, ephemeral).await;
+    let accessible = match embed_mode {
+        EmbedMode::Accessible => Some(get_locale(ctx).await),
+        _ => None,
+    };
+
+    let reply = ctx
+        .send(
+            embed_mode
+                .reply(
+                    CreateReply::default(),
+                    render_queue_page(
+                        &entries,
+                        &handles,
+                        looping_track(&positions, guild_id, &handles).await,
+                        suggestion.as_ref(),
+                        page,
+                        accessible,
+                    )
+                    .embed(ctx),
+                )
+                .components(queue_page_buttons(
+                    &id_prefix,
+                    page,
+                    page_count(entries.len()),
+                    suggestion.is_some(),
+                    false,
+                ))
+                .ephemeral(ephemeral)
+                .allowed_mentions(CreateAllowedMentions::new().empty_users()),
+        )
+        .await?;
+    if public_requested && ephemeral {
+        ctx.send(CreateReply::default().content(QUIET_NOTE).ephemeral(true))
+            .await?;
+    }
+    if let (Some(requested), true) = (requested, clamped) {
+        ctx.send(
+            CreateReply::default()
+                .content(clamped_notice(requested, page))
+                .ephemeral(true),
+        )
+        .await?;
+    }
+
+    while let Some(press) = ComponentInteractionCollector::new(ctx.serenity_context())
+        .filter({
+            let id_prefix = id_prefix.clone();
+            move |press| press.data.custom_id.starts_with(&id_prefix)
+        })
+        .timeout(QUEUE_BUTTON_TIMEOUT)
+        .await
+    {
+        // Set when the page was picked in the modal, which then has to be answered instead
+        let mut modal = None;
+        let mut requested = None;
+        let refresh = match &press.data.custom_id[id_prefix.len()..] {
+            "prev" => {
+                page = page.saturating_sub(1);
+                false
+            }
+            "next" => {
+                page += 1;
+                false
+            }
+            "refresh" => true,
+            "goto" => {
+                let page_modal = CreateQuickModal::new("Gehe zu Seite")
+                    .timeout(PAGE_MODAL_TIMEOUT)
+                    .short_field(format!("Seite (1-{})", page_count(entries.len())));
+                let Some(response) = press
+                    .quick_modal(ctx.serenity_context(), page_modal)
+                    .await?
+                else {
+                    continue;
+                };
+                requested = response
+                    .inputs
+                    .first()
+                    .and_then(|input| input.trim().parse::<usize>().ok());
+                modal = Some(response.interaction);
+                // The queue may have changed while the modal was open
+                true
+            }
+            // Taking or replacing the suggestion changes the listing as well
+            button => press_autoplay_button(ctx, button, press.user.id).await?,
+        };
+        if refresh {
+            let (new_handles, new_metadata) = read_queue(ctx, guild_id).await;
+            entries = diff_queue(&snapshot, &new_metadata);
+            handles = new_handles;
+            suggestion = autoplay.suggestion(guild_id);
+        }
+        let notice;
+        (page, notice) = page_after_refresh(page, modal.as_ref().map(|_| requested), entries.len());
+
+        let update = CreateInteractionResponse::UpdateMessage(
+            embed_mode
+                .message(
+                    CreateInteractionResponseMessage::new(),
+                    render_queue_page(
+                        &entries,
+                        &handles,
+                        looping_track(&positions, guild_id, &handles).await,
+                        suggestion.as_ref(),
+                        page,
+                        accessible,
+                    )
+                    .embed(ctx),
+                )
+                .components(queue_page_buttons(
+                    &id_prefix,
+                    page,
+                    page_count(entries.len()),
+                    suggestion.is_some(),
+                    false,
+                )),
+        );
+        match modal {
+            Some(modal) => {
+                modal.create_response(ctx, update).await?;
+                if let Some(notice) = notice {
+                    modal
+                        .create_followup(
+                            ctx,
+                            CreateInteractionResponseFollowup::new()
+                                .content(notice)
+                                .ephemeral(true),
+                        )
+                        .await?;
+                }
+            }
+            None => press.create_response(ctx, update).await?,
+        }
+    }
+
+    // Disable the buttons once nobody listens for them anymore. The reply may already be
+    // dismissed or deleted, which leaves nothing to disable.
+    _ = reply
+        .edit(
+            ctx,
+            embed_mode
+                .reply(
+                    CreateReply::default(),
+                    render_queue_page(
+                        &entries,
+                        &handles,
+                        looping_track(&positions, guild_id, &handles).await,
+                        suggestion.as_ref(),
+                        page,
+                        accessible,
+                    )
+                    .embed(ctx),
+                )
+                .components(queue_page_buttons(
+                    &id_prefix,
+                    page,
+                    page_count(entries.len()),
+                    suggestion.is_some(),
+                    true,
+                )),
+        )
+        .await;
+
+    Ok(())
+}
+
+/// Loads the metadata of a track url again through the provider chain, or yt-dlp for other sites
+async fn resolve_metadata(ctx: CommandContext<'_>, url: &Url) -> Option<TrackMetadata> {
+    if let Some(video_id) = get_yt_id_from_url(url.as_str()).video_id {
+        return get_youtube_client(ctx.serenity_context())
+            .await
+            .get_video(&video_id)
+            .await
+            .ok()
+            .map(TrackMetadata::from);
+    }
+
+    let mut input = YtDlpInput::new(
+        get_http_client(ctx.serenity_context()).await,
+        get_ytdlp_config(ctx.serenity_context()).await,
+        url.to_string(),
+    );
+    let _permit = get_ytdlp_permits(ctx.serenity_context())
+        .await
+        .acquire_owned()
+        .await
+        .expect("The semaphore is never closed");
+    input.metadata().await.ok().map(TrackMetadata::from)
+}
+
+/// Loads the metadata of a queue entry again
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized("de", "Lädt die Infos eines Eintrags der Warteschlange neu")
+)]
+pub async fn refreshmeta(
+    ctx: CommandContext<'_>,
+    #[description = "Position in the queue, defaults to the current track"]
+    #[description_localized(
+        "de",
+        "Position in der Warteschlange, standardmäßig das aktuelle Lied"
+    )]
+    #[min = 1]
+    position: Option<u32>,
+) -> Result<(), CommandError> {
+    let (_, call) = get_call(ctx).await?;
+    let position = position.unwrap_or(1) as usize;
+
+    let handle = {
+        let call = call.lock().await;
+        let queue = call.queue();
+        if queue.is_empty() {
+            return Err(QueueEmpty);
+        }
+        queue.current_queue().get(position - 1).cloned().ok_or(
+            CommandError::PositionOutOfRange {
+                position,
+                total: queue.len(),
+            },
+        )?
+    };
+
+    ctx.defer().await?;
+    let old = get_metadata(&handle).await;
+    let Some(mut new) = resolve_metadata(ctx, &old.source_url).await else {
+        let response_details = format!(
+            "Die Infos zu `{}` konnten nicht neu geladen werden",
+            old.title
+        );
+        _ = respond_success(&ctx, "Infos", response_details, false).await?;
+        return Ok(());
+    };
+
+    new.requested_by = old.requested_by;
+    new.set_playability(old.playability());
+    // The track may have ended while loading, then the new metadata is simply dropped with it
+    handle
+        .typemap()
+        .write()
+        .await
+        .insert::<TrackMetadataKey>(Arc::new(new.clone()));
+    get_playback_events(ctx.serenity_context()).await.publish(
+        ctx.guild_id().ok_or(CommandError::NotInGuild)?,
+        PlaybackEvent::QueueChanged,
+    );
+
+    let response_details = format!("Vorher: `{}`\nNachher: `{}`", old.title, new.title);
+    _ = respond_success(&ctx, "Infos", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Parses a range of queue positions like `3-10` or a single position like `5`. Reversed ranges
+/// are accepted, position 0 does not exist.
+fn parse_range(range: &str) -> Option<RangeInclusive<usize>> {
+    let (from, to): (usize, usize) = match range.split_once('-') {
+        Some((from, to)) => (from.trim().parse().ok()?, to.trim().parse().ok()?),
+        None => {
+            let position = range.trim().parse().ok()?;
+            (position, position)
+        }
+    };
+    if from == 0 || to == 0 {
+        return None;
+    }
+
+    Some(from.min(to)..=from.max(to))
+}
+
+/// Removes a contiguous block of entries from the queue
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Entfernt einen zusammenhängenden Bereich von Einträgen aus der Warteschlange"
+    )
+)]
+pub async fn remove(
+    ctx: CommandContext<'_>,
+    #[description = "Positions to remove, like 3-10 or 5"]
+    #[description_localized("de", "Zu entfernende Positionen, z.B. 3-10 oder 5")]
+    range: String,
+) -> Result<(), CommandError> {
+    let (_, call) = get_call(ctx).await?;
+
+    let range = parse_range(&range).ok_or(CommandError::InvalidRange(range))?;
+    if *range.start() == 1 {
+        return Err(CommandError::CurrentTrackPosition { removal: true });
+    }
+
+    let total = call.lock().await.queue().len();
+    if *range.end() > total {
+        return Err(CommandError::PositionOutOfRange {
+            position: *range.end(),
+            total,
+        });
+    }
+    if !confirm_removal(ctx, range.clone().count(), "/remove").await? {
+        return Ok(());
+    }
+
+    // The queue may have changed while waiting for the confirmation
+    let removed = with_queue_lock(ctx, &call, |queue| {
+        queue
+            .modify_queue(|raw_queue| queue_ops::remove_range(raw_queue, &range))
+            .ok_or(queue.len())
+    })
+    .await?
+    .map_err(|total| CommandError::PositionOutOfRange {
+        position: *range.end(),
+        total,
+    })?;
+
+    let first = get_metadata(removed.first().expect("The range is never empty")).await;
+    let last = get_metadata(removed.last().expect("The range is never empty")).await;
+    for track in &removed {
+        _ = track.stop();
+    }
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    get_audit_log(ctx.serenity_context()).await.record(
+        guild_id,
+        Some(ctx.author().id),
+        AuditAction::Removed {
+            count: removed.len(),
+        },
+    );
+    get_playback_events(ctx.serenity_context())
+        .await
+        .publish(guild_id, PlaybackEvent::QueueChanged);
+
+    let response_details = match removed.len() {
+        1 => format!("`{}` wurde entfernt", first.title),
+        n => format!(
+            "{n} Einträge wurden entfernt, von `{}` bis `{}`",
+            first.title, last.title
+        ),
+    };
+    _ = respond_success(&ctx, "Entfernen", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Removes all upcoming tracks, the current one plays to the end
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Entfernt alle kommenden Lieder, das aktuelle wird zu Ende gespielt"
+    )
+)]
+pub async fn clear(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let (_, call) = get_call(ctx).await?;
+
+    let upcoming = match call.lock().await.queue().len() {
+        0 => return Err(QueueEmpty),
+        1 => return Err(NothingUpcoming),
+        len => len - 1,
+    };
+    // The call is not locked while waiting, so playback continues during the prompt
+    if !confirm_removal(ctx, upcoming, "/clear").await? {
+        return Ok(());
+    }
+
+    let end_markers = get_end_markers(ctx.serenity_context()).await;
+    let removed = with_queue_lock(ctx, &call, |queue| {
+        clear_upcoming(queue, guild_id, &end_markers, Some(ctx.author().id))
+    })
+    .await?;
+    get_audit_log(ctx.serenity_context()).await.record(
+        guild_id,
+        Some(ctx.author().id),
+        AuditAction::Removed {
+            count: removed.len(),
+        },
+    );
+    get_playback_events(ctx.serenity_context())
+        .await
+        .publish(guild_id, PlaybackEvent::QueueChanged);
+
+    let response_details = match removed.len() {
+        1 => "1 Lied wurde entfernt, das aktuelle läuft weiter".to_owned(),
+        n => format!("{n} Lieder wurden entfernt, das aktuelle läuft weiter"),
+    };
+    _ = respond_success(&ctx, "Leeren", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Moves a track to another position in the queue
+#[poise::command(
+    rename = "move",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Verschiebt ein Lied an eine andere Position in der Warteschlange"
+    )
+)]
+pub async fn move_command(
+    ctx: CommandContext<'_>,
+    #[description = "Position of the track, as shown by /queue"]
+    #[description_localized("de", "Position des Liedes, wie in /queue angezeigt")]
+    #[min = 1]
+    from: usize,
+    #[description = "New position of the track, 2 plays it next"]
+    #[description_localized("de", "Neue Position des Liedes, 2 spielt es als nächstes")]
+    #[min = 1]
+    to: usize,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let (_, call) = get_call(ctx).await?;
+
+    // The current track keeps playing, moving it would restart it
+    if from <= 1 || to <= 1 {
+        return Err(CommandError::CurrentTrackPosition { removal: false });
+    }
+    let total = call.lock().await.queue().len();
+    if from.max(to) > total {
+        return Err(CommandError::PositionOutOfRange {
+            position: from.max(to),
+            total,
+        });
+    }
+
+    let moved = with_queue_lock(ctx, &call, |queue| {
+        queue.modify_queue(
+            |raw_queue| match queue_ops::move_entry(raw_queue, from - 1, to - 1) {
+                true => Ok((
+                    raw_queue[to - 1].handle(),
+                    raw_queue.get(1).map(|next| next.handle()),
+                )),
+                false => Err(raw_queue.len()),
+            },
+        )
+    })
+    .await?;
+    // The queue may have changed since it was checked
+    let (track, next) = moved.map_err(|total| CommandError::PositionOutOfRange {
+        position: from.max(to),
+        total,
+    })?;
+
+    if let Some(next) = next {
+        preload_new_next(&call, &next).await;
+    }
+    get_playback_events(ctx.serenity_context())
+        .await
+        .publish(guild_id, PlaybackEvent::QueueChanged);
+
+    let response_details = format!(
+        "`{}` ist jetzt an Position {to}",
+        get_metadata(&track).await.title
+    );
+    _ = respond_success(&ctx, "Verschieben", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Shuffles the upcoming tracks of the queue
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Mischt die kommenden Lieder der Warteschlange, das aktuelle spielt weiter"
+    )
+)]
+pub async fn shuffle(
+    ctx: CommandContext<'_>,
+    #[description = "Shuffles so that tracks of the same channel do not follow each other"]
+    #[description_localized(
+        "de",
+        "Mischt so, dass Lieder desselben Kanals nicht direkt aufeinander folgen"
+    )]
+    smart: Option<bool>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let (_, call) = get_call(ctx).await?;
+
+    let handles = call.lock().await.queue().current_queue();
+    // Checked before the lock as well, so a refused shuffle does not replace the undo slot
+    if handles.len() < MIN_SHUFFLE_ENTRIES {
+        return Err(TooFewToShuffle {
+            total: handles.len(),
+        });
+    }
+    let authors = join_all(
+        handles
+            .iter()
+            .map(|handle| async { (handle.uuid(), get_metadata(handle).await.author.clone()) }),
+    )
+    .await
+    .into_iter()
+    .collect::<HashMap<_, _>>();
+
+    // The queue may have changed while the authors were read
+    let (shuffled, next) = with_queue_lock(ctx, &call, |queue| {
+        queue.modify_queue(|raw_queue| {
+            if raw_queue.len() < MIN_SHUFFLE_ENTRIES {
+                return Err(raw_queue.len());
+            }
+            let rng = &mut thread_rng();
+            match smart.unwrap_or(false) {
+                // Tracks added in the meantime count as one more channel
+                true => queue_ops::spread_shuffle_upcoming(
+                    raw_queue,
+                    |track| authors.get(&track.uuid()).cloned(),
+                    rng,
+                ),
+                false => queue_ops::shuffle_upcoming(raw_queue, rng),
+            }
+            Ok((
+                raw_queue.len() - 1,
+                raw_queue.get(1).map(|next| next.handle()),
+            ))
+        })
+    })
+    .await?
+    .map_err(|total| TooFewToShuffle { total })?;
+
+    if let Some(next) = next {
+        preload_new_next(&call, &next).await;
+    }
+    get_playback_events(ctx.serenity_context())
+        .await
+        .publish(guild_id, PlaybackEvent::QueueChanged);
+
+    let response_details = format!("{shuffled} Lieder wurden gemischt");
+    _ = respond_success(&ctx, "Mischen", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Restores the queue from before the last command that changed it
+#[poise::command(
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Stellt die Warteschlange vor dem letzten Befehl wieder her, der sie verändert hat"
+    )
+)]
+pub async fn undo(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let (channel_id, call) = get_call(ctx).await?;
+
+    let Some(snapshot) = get_undo_slots(ctx.serenity_context()).await.take(guild_id) else {
+        let response_details =
+            "Es gibt keine Änderung der Warteschlange, die rückgängig gemacht werden kann";
+        _ = respond_success(&ctx, "Rückgängig", response_details, true).await?;
+        return Ok(());
+    };
+
+    // The current track keeps playing if the snapshot started with it
+    let current = call.lock().await.queue().current();
+    let keep_current = match (current, snapshot.tracks.first()) {
+        (Some(current), Some(first)) => same_track(
+            get_metadata(&current).await.source_url.as_str(),
+            first.source_url.as_str(),
+        ),
+        _ => false,
+    };
+    let restored = match keep_current {
+        true => &snapshot.tracks[1..],
+        false => &snapshot.tracks[..],
+    };
+
+    // This saves the queue again, so another /undo reverts the restore
+    with_queue_lock(ctx, &call, |queue| {
+        if keep_current {
+            let upcoming = queue.modify_queue(queue_ops::drain_upcoming);
+            for track in upcoming {
+                _ = track.stop();
+            }
+        } else {
+            queue.stop();
+        }
+    })
+    .await?;
+    let capacity = queue_capacity(ctx.serenity_context(), &call).await;
+    for metadata in restored.iter().take(capacity.fit(restored.len())) {
+        enqueue_resolved(ctx, call.clone(), metadata.clone(), EnqueueOrigin::Restore).await?;
+    }
+    get_playback_events(ctx.serenity_context())
+        .await
+        .publish(guild_id, PlaybackEvent::QueueChanged);
+
+    let mut response_details = format!(
+        "Warteschlange in Kanal {} auf den Stand vor `/{}` zurückgesetzt ({} Einträge)",
+        channel_id.to_channel(ctx).await?.mention(),
+        snapshot.command,
+        snapshot.tracks.len()
+    );
+    if let Some(notice) = capacity.notice(restored.len()) {
+        response_details += &format!("\n{notice}");
+    }
+    _ = respond_success(&ctx, "Rückgängig", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Tracks you staged before joining a voice channel
+#[poise::command(
+    slash_command,
+    guild_only,
+    subcommands("staging_list", "staging_clear"),
+    subcommand_required
+)]
+pub async fn staging(_ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    Ok(())
+}
+
+/// Lists the tracks you staged
+#[poise::command(
+    rename = "list",
+    slash_command,
+    guild_only,
+    description_localized("de", "Zeigt die Lieder, die du vorgemerkt hast")
+)]
+pub async fn staging_list(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let staged = get_staging(ctx.serenity_context())
+        .await
+        .list(guild_id, ctx.author().id);
+
+    let response_details = if staged.is_empty() {
+        "Du hast keine vorgemerkten Lieder".to_owned()
+    } else {
+        staged
+            .iter()
+            .enumerate()
+            .map(|(i, track)| {
+                let expires_in = STAGING_TTL.saturating_sub(track.staged_at.elapsed());
+                format!(
+                    "`{}` [{}]({}) *(verfällt in {} min)*",
+                    i + 1,
+                    track.metadata.title,
+                    track.metadata.source_url,
+                    expires_in.as_secs().div_ceil(60)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    _ = respond_success(&ctx, "Vorgemerkt", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Removes all tracks you staged
+#[poise::command(
+    rename = "clear",
+    slash_command,
+    guild_only,
+    description_localized("de", "Entfernt alle Lieder, die du vorgemerkt hast")
+)]
+pub async fn staging_clear(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let cleared = get_staging(ctx.serenity_context())
+        .await
+        .clear(guild_id, ctx.author().id);
+
+    let response_details = format!("{cleared} vorgemerkte Lieder entfernt");
+    _ = respond_success(&ctx, "Vorgemerkt", response_details, true).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plain_text::{accessible_text, assert_accessible};
+    use serde_json::json;
+    use serenity::all::Timestamp;
+
+    const GUILD: GuildId = GuildId::new(1);
+    /// Pages of [fake_queue]
+    const PAGES: usize = 50;
+
+    /// A queue of 500 tracks with metadata. The driver is kept alive without a connection, so
+    /// asking it for the state of a track would never return, unless `dead` drops it.
+    async fn fake_queue(dead: bool) -> (Vec<TrackHandle>, Option<songbird::Driver>) {
+        let queue = songbird::tracks::TrackQueue::new();
+        let mut driver = songbird::Driver::default();
+        for i in 0..PAGES * QUEUE_PAGE_SIZE {
+            let handle = queue
+                .add_source(songbird::input::Input::from(vec![0u8; 16]), &mut driver)
+                .await;
+            let metadata = TrackMetadata::unresolved(&format!("https://example.com/{i}"));
+            handle
+                .typemap()
+                .write()
+                .await
+                .insert::<TrackMetadataKey>(Arc::new(metadata));
+        }
+        let driver = match dead {
+            true => {
+                drop(driver);
+                // The handles only fail once the mixer of the driver has shut down
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                None
+            }
+            false => Some(driver),
+        };
+        (queue.current_queue(), driver)
+    }
+
+    /// Queue entry with a fixed track id, titled like the entry
+    fn entry(id: u128, title: &str) -> (Uuid, Arc<TrackMetadata>) {
+        let mut meta = TrackMetadata::unresolved(&format!("https://example.com/{id}"));
+        meta.title = title.to_owned();
+        (Uuid::from_u128(id), Arc::new(meta))
+    }
+
+    fn snapshot(titles: &[&str]) -> QueueSnapshot {
+        titles
+            .iter()
+            .map(|title| entry(title.as_bytes()[0] as u128, title))
+            .collect()
+    }
+
+    /// The diff as `=index title` for unchanged, `+index title` for added and `-title` for
+    /// removed entries
+    fn describe(entries: &[QueueDiffEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| match entry {
+                QueueDiffEntry::Unchanged(i, meta) => format!("={i} {}", meta.title),
+                QueueDiffEntry::Added(i, meta) => format!("+{i} {}", meta.title),
+                QueueDiffEntry::Removed(meta) => format!("-{}", meta.title),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unchanged_queue_has_no_changes() {
+        let queue = snapshot(&["a", "b", "c"]);
+        assert_eq!(
+            describe(&diff_queue(&queue, &queue)),
+            ["=0 a", "=1 b", "=2 c"]
+        );
+    }
+
+    #[test]
+    fn appended_tracks_are_added() {
+        let old = snapshot(&["a", "b"]);
+        let new = snapshot(&["a", "b", "c"]);
+        assert_eq!(describe(&diff_queue(&old, &new)), ["=0 a", "=1 b", "+2 c"]);
+    }
+
+    #[test]
+    fn removed_entries_stay_where_they_were() {
+        let old = snapshot(&["a", "b", "c", "d"]);
+        let new = snapshot(&["a", "c", "d"]);
+        assert_eq!(
+            describe(&diff_queue(&old, &new)),
+            ["=0 a", "-b", "=1 c", "=2 d"]
+        );
+
+        let new = snapshot(&["a", "x", "d"]);
+        assert_eq!(
+            describe(&diff_queue(&old, &new)),
+            ["=0 a", "+1 x", "-b", "-c", "=2 d"]
+        );
+    }
+
+    #[test]
+    fn removals_at_the_tail_come_last() {
+        let old = snapshot(&["a", "b", "c", "d"]);
+        let new = snapshot(&["a", "b"]);
+        assert_eq!(
+            describe(&diff_queue(&old, &new)),
+            ["=0 a", "=1 b", "-c", "-d"]
+        );
+
+        assert_eq!(
+            describe(&diff_queue(&old, &Vec::new())),
+            ["-a", "-b", "-c", "-d"]
+        );
+    }
+
+    #[test]
+    fn moved_tracks_keep_their_new_index() {
+        // d was moved forward, the entries it passed are seen after it
+        let old = snapshot(&["a", "b", "c", "d", "e"]);
+        let new = snapshot(&["a", "d", "b", "c"]);
+        assert_eq!(
+            describe(&diff_queue(&old, &new)),
+            ["=0 a", "=1 d", "=2 b", "=3 c", "-e"]
+        );
+
+        // b was moved back, behind entries that come later in the old queue
+        let new = snapshot(&["a", "c", "d", "b", "e"]);
+        assert_eq!(
+            describe(&diff_queue(&old, &new)),
+            ["=0 a", "=1 c", "=2 d", "=3 b", "=4 e"]
+        );
+    }
+
+    #[test]
+    fn refreshed_metadata_is_not_a_new_entry() {
+        let old = vec![entry(1, "Lädt…"), entry(2, "b")];
+        let new = vec![entry(1, "Titel"), entry(2, "b")];
+        assert_eq!(describe(&diff_queue(&old, &new)), ["=0 Titel", "=1 b"]);
+    }
+
+    /// Renders every page like /queue does
+    async fn render_every_page(positions: &PositionCache, handles: Vec<TrackHandle>) {
+        let (handles, snapshot) = snapshot_queue(handles).await;
+        let entries = diff_queue(&snapshot, &snapshot);
+        assert_eq!(page_count(entries.len()), PAGES);
+        for page in 0..PAGES {
+            let looping = looping_track(positions, GUILD, &handles).await;
+            render_queue_page(&entries, &handles, looping, None, page, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_of_a_long_queue_are_rendered_from_the_cache() {
+        let (handles, _driver) = fake_queue(false).await;
+        let positions = PositionCache::default();
+        positions.record(
+            GUILD,
+            crate::position_cache::PositionSample {
+                track: handles[0].uuid(),
+                position: Duration::ZERO,
+                playing: true,
+                looping: true,
+                sampled_at: std::time::Instant::now(),
+            },
+        );
+        // A single request to the driver would never return
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            render_every_page(&positions, handles),
+        )
+        .await
+        .expect("Rendering waited on the driver");
+        assert_eq!(positions.driver_reads(), 0);
+    }
+
+    #[tokio::test]
+    async fn pages_without_a_cached_position_ask_the_driver_once() {
+        let (handles, _) = fake_queue(true).await;
+        let positions = PositionCache::default();
+        render_every_page(&positions, handles).await;
+        // Only for the loop state of the current track, never for the listed ones
+        assert_eq!(positions.driver_reads(), PAGES as u64);
+    }
+
+    #[test]
+    fn buttons_keep_the_page_of_an_unchanged_queue() {
+        assert_eq!(page_after_refresh(2, None, 25), (2, None));
+    }
+
+    #[test]
+    fn queue_shrinking_under_the_buttons_shows_the_last_page() {
+        // Page 3 of 25 entries, then the queue ran down to 12
+        assert_eq!(page_after_refresh(2, None, 12), (1, None));
+        assert_eq!(page_after_refresh(2, None, 0), (0, None));
+    }
+
+    #[test]
+    fn modal_page_is_checked_against_the_queue_after_the_modal() {
+        // The modal offered pages 1-3, the queue shrank to 12 entries while it was open
+        assert_eq!(
+            page_after_refresh(0, Some(Some(3)), 12),
+            (1, Some(clamped_notice(3, 1)))
+        );
+        // It grew instead, so a page beyond the old range exists now
+        assert_eq!(page_after_refresh(0, Some(Some(4)), 31), (3, None));
+        assert_eq!(
+            page_after_refresh(1, Some(Some(0)), 25),
+            (0, Some(clamped_notice(0, 0)))
+        );
+    }
+
+    #[test]
+    fn invalid_modal_input_keeps_the_page() {
+        let (page, notice) = page_after_refresh(2, Some(None), 25);
+        assert_eq!(page, 2);
+        assert!(notice.is_some());
+        // Unless the page is gone by now
+        assert_eq!(page_after_refresh(2, Some(None), 5).0, 0);
+    }
+
+    #[test]
+    fn empty_queue_snapshot() {
+        let timestamp = Timestamp::from_unix_timestamp(0).unwrap();
+        let render = |accessible| {
+            let page = render_queue_page(&[], &[], None, None, 0, accessible);
+            serde_json::to_value(page.to_embed("Gerbot", timestamp)).unwrap()
+        };
+        assert_eq!(
+            render(None),
+            json!({
+                "type": "rich",
+                "title": "Queue",
+                "description": "Die Warteschlange ist leer",
+                "timestamp": "1970-01-01T00:00:00Z",
+                "color": 0x7289DA,
+                "footer": { "text": "Seite 1/1 – 0 Tracks insgesamt · Gerbot" },
+            })
+        );
+        assert_eq!(
+            render(Some(Locale::German))["footer"]["text"],
+            "Seite 1 von 1, 0 Tracks insgesamt · Gerbot"
+        );
+    }
+
+    #[tokio::test]
+    async fn accessible_queue_is_plain_text() {
+        let (handles, _driver) = fake_queue(true).await;
+        let track = |i: usize, title: &str| {
+            let mut meta = TrackMetadata::unresolved(&format!("https://example.com/{i}"));
+            meta.title = title.to_owned();
+            meta.author = "Künstler_in".to_owned();
+            meta.duration = Duration::from_secs(60 * i as u64 + 5);
+            meta
+        };
+        let unplayable = track(2, "Gelöscht");
+        unplayable.set_playability(Playability::Unplayable);
+        let mut long = track(3, "Sehr ~~lang~~");
+        long.over_soft_limit = true;
+        let entries = [
+            QueueDiffEntry::Unchanged(0, Arc::new(track(0, "Lofi 🎧 [beats]"))),
+            QueueDiffEntry::Added(1, Arc::new(track(1, "Neu **fett**"))),
+            QueueDiffEntry::Unchanged(2, Arc::new(unplayable)),
+            QueueDiffEntry::Unchanged(3, Arc::new(long)),
+            QueueDiffEntry::Removed(Arc::new(track(4, "Weg"))),
+        ];
+        let looping = Some(handles[0].uuid());
+
+        let timestamp = Timestamp::from_unix_timestamp(0).unwrap();
+        let page = render_queue_page(
+            &entries,
+            &handles[..4],
+            looping,
+            None,
+            0,
+            Some(Locale::German),
+        );
+        let text = accessible_text(&page.to_embed("Gerbot", timestamp));
+        assert_accessible(&text);
+        assert_eq!(
+            text,
+            "Queue\n\
+             1. Lofi [beats] von Künstler_in, 5 Sekunden, läuft gerade, wird wiederholt.\n\
+             2. Neu fett von Künstler_in, 1 Minute 5 Sekunden, neu.\n\
+             3. Gelöscht von Künstler_in, 2 Minuten 5 Sekunden, nicht abspielbar.\n\
+             4. Sehr lang von Künstler_in, 3 Minuten 5 Sekunden, über der Längengrenze.\n\
+             Entfernt: Weg von Künstler_in.\n\
+             Seite 1 von 1, 4 Tracks insgesamt · Gerbot"
+        );
+    }
+
+    #[test]
+    fn ranges_and_single_positions() {
+        assert_eq!(parse_range("3-10"), Some(3..=10));
+        assert_eq!(parse_range(" 3 - 10 "), Some(3..=10));
+        assert_eq!(parse_range("5"), Some(5..=5));
+        assert_eq!(parse_range("5-5"), Some(5..=5));
+    }
+
+    #[test]
+    fn reversed_ranges_are_turned_around() {
+        assert_eq!(parse_range("10-3"), Some(3..=10));
+        assert_eq!(parse_range("2-1"), Some(1..=2));
+    }
+
+    #[test]
+    fn invalid_ranges_are_refused() {
+        for range in [
+            "", "0", "0-4", "4-0", "-3", "3-", "a-b", "1-2-3", "-1", "2.5",
+        ] {
+            assert_eq!(parse_range(range), None, "{range}");
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use serenity::all::{GuildChannel, Mentionable};
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::commands::util::{
+    get_author_voice_state, get_guild_settings, get_schedules, respond_success,
+};
+use crate::notice_channel::InvokingChannel;
+use crate::schedule::{parse_when, ScheduledJob};
+use crate::{CommandContext, CommandError};
+
+// ======== Commands ========
+
+/// Playback that starts by itself at a set time
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    subcommands("schedule_add", "schedule_list", "schedule_remove"),
+    subcommand_required
+)]
+pub async fn schedule(_ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    Ok(())
+}
+
+/// Plays a track or playlist at a set time
+#[poise::command(
+    rename = "add",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Spielt ein Lied oder eine Playlist zu einer festgelegten Zeit ab"
+    )
+)]
+pub async fn schedule_add(
+    ctx: CommandContext<'_>,
+    #[description = "Time like `20:00`, `fri 20:00` or `24.12.2026 18:30`"]
+    #[description_localized("de", "Zeit wie `20:00`, `fr 20:00` oder `24.12.2026 18:30`")]
+    when: String,
+    #[description = "Link or search like for /play, playlists as a link"]
+    #[description_localized("de", "Link oder Suche wie bei /play, Playlists als Link")]
+    source: String,
+    #[description = "Voice channel to play in, your current one if empty"]
+    #[description_localized(
+        "de",
+        "Sprachkanal für die Wiedergabe, leer für deinen aktuellen Kanal"
+    )]
+    #[channel_types("Voice", "Stage")]
+    channel: Option<GuildChannel>,
+    #[description = "Whether the playback repeats every week"]
+    #[description_localized("de", "Ob die Wiedergabe jede Woche wiederholt wird")]
+    weekly: Option<bool>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let schedules = get_schedules(ctx.serenity_context()).await;
+    schedules.check_available()?;
+    let offset = get_guild_settings(ctx.serenity_context())
+        .await
+        .get(guild_id)
+        .utc_offset
+        .unwrap_or(UtcOffset::UTC);
+
+    let Some(next_run) = parse_when(&when, offset, OffsetDateTime::now_utc()) else {
+        let response_details = format!(
+            "`{when}` ist keine gültige Zeit in der Zukunft. Beispiele: `20:00`, `fr 20:00`, `24.12.2026 18:30`"
+        );
+        _ = respond_success(&ctx, "Planen", response_details, true).await?;
+        return Ok(());
+    };
+    let voice_channel = match channel {
+        Some(channel) => channel.id,
+        None => get_author_voice_state(ctx)
+            .1
+            .ok_or(CommandError::UserNotInVoice)?,
+    };
+    let weekly = weekly.unwrap_or(false);
+    // The announce channel is looked up when the job runs, it may change until then
+    let invoking = InvokingChannel::of_command(ctx);
+
+    let id = schedules.add(ScheduledJob {
+        id: 0,
+        guild_id,
+        voice_channel,
+        notice_channel: invoking.can_receive().then_some(invoking.id),
+        source: source.clone(),
+        next_run,
+        weekly,
+        created_by: ctx.author().id,
+    });
+
+    let response_details = format!(
+        "`{source}` wird <t:{}:F> in {} abgespielt{}\nEntfernen mit `/schedule remove {id}`",
+        next_run.unix_timestamp(),
+        voice_channel.mention(),
+        if weekly { ", danach jede Woche" } else { "" }
+    );
+    _ = respond_success(&ctx, "Planen", response_details, false).await?;
+
+    Ok(())
+}
+
+/// Lists the scheduled playbacks of this server
+#[poise::command(
+    rename = "list",
+    slash_command,
+    guild_only,
+    description_localized("de", "Zeigt die geplanten Wiedergaben dieses Servers")
+)]
+pub async fn schedule_list(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let schedules = get_schedules(ctx.serenity_context()).await;
+    schedules.check_available()?;
+    let jobs = schedules.list(guild_id);
+
+    let response_details = if jobs.is_empty() {
+        "Es sind keine Wiedergaben geplant".to_owned()
+    } else {
+        jobs.iter()
+            .map(|job| {
+                format!(
+                    "`{}` <t:{}:F> in {}: `{}`{}",
+                    job.id,
+                    job.next_run.unix_timestamp(),
+                    job.voice_channel.mention(),
+                    job.source,
+                    if job.weekly { " (wöchentlich)" } else { "" }
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+    _ = respond_success(&ctx, "Geplante Wiedergaben", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Removes a scheduled playback
+#[poise::command(
+    rename = "remove",
+    slash_command,
+    guild_only,
+    description_localized("de", "Entfernt eine geplante Wiedergabe")
+)]
+pub async fn schedule_remove(
+    ctx: CommandContext<'_>,
+    #[description = "Number of the playback from /schedule list"]
+    #[description_localized("de", "Nummer der Wiedergabe aus /schedule list")]
+    id: u32,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let schedules = get_schedules(ctx.serenity_context()).await;
+    schedules.check_available()?;
+
+    let response_details = match schedules.remove(guild_id, id) {
+        Some(job) => format!(
+            "Die geplante Wiedergabe von `{}` wurde entfernt",
+            job.source
+        ),
+        None => format!("Es gibt keine geplante Wiedergabe mit der Nummer `{id}`"),
+    };
+    _ = respond_success(&ctx, "Planen", response_details, true).await?;
+
+    Ok(())
+}
@@ -0,0 +1,1116 @@
+use poise::CreateReply;
+use serenity::all::{
+    Attachment, AutocompleteChoice, ButtonStyle, ChannelId, ChannelType, ComponentInteraction,
+    ComponentInteractionCollector, ComponentInteractionDataKind, CreateAttachment, GuildId,
+    Mentionable, RoleId,
+};
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateInteractionResponse, CreateInteractionResponseMessage,
+    CreateSelectMenu, CreateSelectMenuKind, CreateSelectMenuOption,
+};
+use std::time::Duration;
+use time::OffsetDateTime;
+
+use crate::alias::CommandAlias;
+use crate::commands::util::{
+    get_blocklist, get_guild_settings, get_max_queue_length, get_party_modes, get_saved_playlists,
+    get_tts, get_user_preferences, respond_success, truncate_chars, AUTOCOMPLETE_NAME_CHARS,
+};
+use crate::confirm::confirm;
+use crate::guild_settings::{
+    GuildSettings, GuildSettingsStore, QuietLevel, SettingsConflict, VersionedSettings,
+};
+use crate::locale::Locale;
+use crate::party_mode::{describe_party, parse_party_change, PartyChange};
+use crate::plain_text::EmbedMode;
+use crate::response::BotResponse;
+use crate::schedule::parse_utc_offset;
+use crate::settings_transfer::{GuildConfig, SettingsExport};
+use crate::{CommandContext, CommandError, OverlayTokensKey};
+
+/// Larger files are not downloaded by /settings import
+const MAX_SETTINGS_BYTES: u32 = 256 * 1024;
+
+// ======== Commands ========
+
+/// Settings for the now-playing overlay of streamers
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    subcommands("overlay_token"),
+    subcommand_required
+)]
+pub async fn overlay(_ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    Ok(())
+}
+
+/// Creates a new access token for the now-playing overlay, invalidating the old one
+#[poise::command(
+    rename = "token",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Erstellt einen neuen Zugangstoken für das Now-Playing-Overlay und ersetzt den alten"
+    )
+)]
+pub async fn overlay_token(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let token = ctx
+        .serenity_context()
+        .data
+        .read()
+        .await
+        .get::<OverlayTokensKey>()
+        .expect("Guaranteed to exist in the typemap")
+        .regenerate(guild_id);
+
+    let response_details = format!(
+        "Pfad für das Overlay: `/guilds/{guild_id}/now?token={token}`\nDer vorherige Token ist nicht mehr gültig."
+    );
+    _ = respond_success(&ctx, "Overlay", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Server settings of the bot
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    subcommands(
+        "settings_share",
+        "settings_language",
+        "settings_timezone",
+        "settings_duration",
+        "settings_deafenedpause",
+        "settings_tts",
+        "settings_quiet",
+        "settings_alias",
+        "settings_unblock",
+        "settings_export",
+        "settings_import"
+    ),
+    subcommand_required
+)]
+pub async fn settings(_ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    Ok(())
+}
+
+/// Enables or disables the share button of /now_playing
+#[poise::command(
+    rename = "share",
+    slash_command,
+    guild_only,
+    description_localized("de", "Aktiviert oder deaktiviert den Teilen-Knopf von /now_playing")
+)]
+pub async fn settings_share(
+    ctx: CommandContext<'_>,
+    #[description = "Whether the button is shown"]
+    #[description_localized("de", "Ob der Knopf angezeigt wird")]
+    enabled: bool,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    get_guild_settings(ctx.serenity_context())
+        .await
+        .update(guild_id, |settings| settings.share_button = enabled);
+
+    let response_details = if enabled {
+        "Der Teilen-Knopf von /now_playing ist aktiviert"
+    } else {
+        "Der Teilen-Knopf von /now_playing ist deaktiviert"
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Sets the language of the bot on this server
+#[poise::command(
+    rename = "language",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Legt die Sprache des Bots auf diesem Server fest, auch für automatische Nachrichten"
+    )
+)]
+pub async fn settings_language(
+    ctx: CommandContext<'_>,
+    #[description = "Language of the server, empty to use the language of each user"]
+    #[description_localized(
+        "de",
+        "Sprache des Servers, leer um die Sprache der einzelnen Nutzer zu verwenden"
+    )]
+    language: Option<Locale>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    get_guild_settings(ctx.serenity_context())
+        .await
+        .update(guild_id, |settings| settings.locale = language);
+
+    let response_details = match language {
+        Some(locale) => format!("Die Sprache des Servers ist jetzt {}", locale.name()),
+        None => "Es wird die Sprache der einzelnen Nutzer verwendet".to_owned(),
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Sets the time offset of the server for times entered in commands
+#[poise::command(
+    rename = "timezone",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Legt die Zeitverschiebung des Servers für Zeitangaben wie bei /schedule fest"
+    )
+)]
+pub async fn settings_timezone(
+    ctx: CommandContext<'_>,
+    #[description = "Offset to UTC like `+01:00` or `UTC+2`, empty for UTC"]
+    #[description_localized("de", "Abstand zu UTC wie `+01:00` oder `UTC+2`, leer für UTC")]
+    offset: Option<String>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let Some(offset) = parse_utc_offset(offset.as_deref().unwrap_or_default()) else {
+        let response_details =
+            "Die Zeitverschiebung konnte nicht gelesen werden. Beispiele: `+01:00`, `UTC+2`, `-5`";
+        _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+        return Ok(());
+    };
+    get_guild_settings(ctx.serenity_context())
+        .await
+        .update(guild_id, |settings| settings.utc_offset = Some(offset));
+
+    // Fixed offsets have no daylight saving time
+    let response_details = format!(
+        "Zeitangaben auf diesem Server gelten jetzt in UTC{:+03}:{:02}. Zur Sommerzeit muss sie angepasst werden.",
+        offset.whole_hours(),
+        offset.minutes_past_hour().abs()
+    );
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Sets how long tracks may be
+#[poise::command(
+    rename = "duration",
+    slash_command,
+    guild_only,
+    description_localized("de", "Legt fest, wie lang Lieder sein dürfen")
+)]
+pub async fn settings_duration(
+    ctx: CommandContext<'_>,
+    #[description = "Maximum length in minutes, empty for no limit"]
+    #[description_localized("de", "Maximale Länge in Minuten, leer für keine Grenze")]
+    #[min = 1]
+    limit: Option<u32>,
+    #[description = "Whether longer tracks are still added with a warning"]
+    #[description_localized(
+        "de",
+        "Ob längere Lieder trotzdem mit einer Warnung hinzugefügt werden"
+    )]
+    soft: Option<bool>,
+    #[description = "With a soft limit: Length in minutes above which tracks are rejected anyway"]
+    #[description_localized(
+        "de",
+        "Bei einer weichen Grenze: Länge in Minuten, ab der Lieder trotzdem abgelehnt werden"
+    )]
+    #[min = 1]
+    hard_cap: Option<u32>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    if let (Some(limit), Some(hard_cap)) = (limit, hard_cap) {
+        if hard_cap < limit {
+            let response_details = "Die harte Grenze muss mindestens so hoch sein wie die Grenze";
+            _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+            return Ok(());
+        }
+    }
+    let minutes = |m: u32| Duration::from_secs(u64::from(m) * 60);
+    let settings = get_guild_settings(ctx.serenity_context())
+        .await
+        .update(guild_id, |settings| {
+            settings.max_track_duration = limit.map(minutes);
+            settings.soft_duration_limit = soft.unwrap_or(false);
+            settings.duration_hard_cap = hard_cap.map(minutes);
+        });
+
+    let response_details = match (settings.max_track_duration, settings.soft_duration_limit) {
+        (None, _) => "Lieder dürfen beliebig lang sein".to_owned(),
+        (Some(limit), false) => format!(
+            "Lieder über {} Minuten werden abgelehnt",
+            limit.as_secs() / 60
+        ),
+        (Some(limit), true) => {
+            let cap = match settings.duration_hard_cap {
+                Some(cap) => format!(", über {} Minuten werden sie abgelehnt", cap.as_secs() / 60),
+                None => String::new(),
+            };
+            format!(
+                "Lieder über {} Minuten werden mit einer Warnung hinzugefügt{cap}",
+                limit.as_secs() / 60
+            )
+        }
+    };
+    let response_details = match get_party_modes(ctx.serenity_context())
+        .await
+        .until(guild_id)
+    {
+        Some(until) => format!(
+            "{response_details}\n{}, bis dahin gilt keine Grenze",
+            describe_party(until)
+        ),
+        None => response_details,
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Pauses playback while everyone in the channel is deafened
+#[poise::command(
+    rename = "deafenedpause",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Pausiert die Wiedergabe, solange alle im Sprachkanal taub geschaltet sind"
+    )
+)]
+pub async fn settings_deafenedpause(
+    ctx: CommandContext<'_>,
+    #[description = "Seconds until playback pauses, empty to keep playing"]
+    #[description_localized("de", "Sekunden bis zur Pause, leer um weiter abzuspielen")]
+    #[min = 0]
+    #[max = 3600]
+    delay: Option<u32>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let delay = delay.map(|d| Duration::from_secs(d.into()));
+    get_guild_settings(ctx.serenity_context())
+        .await
+        .update(guild_id, |settings| settings.deafened_pause_delay = delay);
+
+    let response_details = match delay {
+        Some(delay) => format!(
+            "Die Wiedergabe pausiert {} Sekunden nachdem alle taub geschaltet sind und geht weiter, sobald jemand wieder zuhört",
+            delay.as_secs()
+        ),
+        None => "Die Wiedergabe läuft weiter, auch wenn alle taub geschaltet sind".to_owned(),
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Enables or disables spoken announcements of the next track
+#[poise::command(
+    rename = "tts",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Aktiviert oder deaktiviert Sprachansagen des nächsten Lieds zwischen zwei Liedern"
+    )
+)]
+pub async fn settings_tts(
+    ctx: CommandContext<'_>,
+    #[description = "Whether the next track is announced"]
+    #[description_localized("de", "Ob das nächste Lied angesagt wird")]
+    enabled: bool,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    get_guild_settings(ctx.serenity_context())
+        .await
+        .update(guild_id, |settings| settings.tts_announcements = enabled);
+
+    let mut response_details = if enabled {
+        "Das nächste Lied wird vor dem Start im Sprachkanal angesagt".to_owned()
+    } else {
+        "Lieder werden nicht mehr angesagt".to_owned()
+    };
+    if enabled && get_tts(ctx.serenity_context()).await.is_none() {
+        response_details +=
+            "\nDer Host des Bots hat keine Sprachausgabe eingerichtet, bis dahin bleibt es still";
+    }
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Sets whether the bot writes visibly in text channels
+#[poise::command(
+    rename = "quiet",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Legt fest, ob der Bot für alle sichtbar in Textkanälen schreibt"
+    )
+)]
+pub async fn settings_quiet(
+    ctx: CommandContext<'_>,
+    #[description = "Quiet: only private responses, silent: also no announcements. Empty shows the level"]
+    #[description_localized(
+        "de",
+        "Leise: nur private Antworten, Stumm: auch keine Ankündigungen. Leer zeigt die Stufe"
+    )]
+    level: Option<QuietLevel>,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let store = get_guild_settings(ctx.serenity_context()).await;
+    let response_details = match level {
+        Some(level) => {
+            store.update(guild_id, |settings| settings.quiet = level);
+            format!("Ruhemodus: {}", level.describe())
+        }
+        None => format!(
+            "Aktueller Ruhemodus: {}",
+            store.get(guild_id).quiet.describe()
+        ),
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Enables or disables a short name of a command
+#[poise::command(
+    rename = "alias",
+    slash_command,
+    guild_only,
+    description_localized("de", "Aktiviert oder deaktiviert eine Kurzform eines Commands")
+)]
+pub async fn settings_alias(
+    ctx: CommandContext<'_>,
+    #[description = "Short name and the command it runs"]
+    #[description_localized("de", "Kurzform und der Command, den sie ausführt")]
+    alias: CommandAlias,
+    #[description = "Whether the short name can be used"]
+    #[description_localized("de", "Ob die Kurzform verwendet werden kann")]
+    enabled: bool,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    get_guild_settings(ctx.serenity_context())
+        .await
+        .update(guild_id, |settings| settings.aliases.set(alias, enabled));
+
+    let response_details = if enabled {
+        format!(
+            "`/{}` kann jetzt anstelle von `/{}` verwendet werden",
+            alias.name(),
+            alias.target()
+        )
+    } else {
+        format!("`/{}` ist deaktiviert", alias.name())
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Removes a track or channel from the blocklist of the server
+#[poise::command(
+    rename = "unblock",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Entfernt ein Lied oder einen Kanal von der Sperrliste des Servers"
+    )
+)]
+pub async fn settings_unblock(
+    ctx: CommandContext<'_>,
+    #[description = "Blocked track or channel"]
+    #[description_localized("de", "Gesperrtes Lied oder gesperrter Kanal")]
+    #[autocomplete = "autocomplete_blocked"]
+    entry: String,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let blocklist = get_blocklist(ctx.serenity_context()).await;
+    let response_details = match blocklist.find(guild_id, &entry) {
+        Some(entry) if blocklist.remove(guild_id, &entry) => {
+            format!("{} ist nicht mehr gesperrt", entry.describe())
+        }
+        _ => "Dieser Eintrag ist nicht gesperrt".to_owned(),
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Exports the settings, saved playlists and blocklist of the server as a file
+#[poise::command(
+    rename = "export",
+    slash_command,
+    guild_only,
+    description_localized(
+        "de",
+        "Exportiert Einstellungen, gespeicherte Playlists und Sperrliste des Servers als Datei"
+    )
+)]
+pub async fn settings_export(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let (config, _) = read_guild_config(ctx, guild_id).await?;
+    let export = SettingsExport::new(&config);
+
+    ctx.send(
+        CreateReply::default()
+            .content("Mit /settings import lassen sich die Einstellungen auf einem anderen Server übernehmen")
+            .attachment(CreateAttachment::bytes(
+                export.to_json(),
+                format!("settings-{guild_id}.json"),
+            ))
+            .ephemeral(true),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Replaces the settings, saved playlists and blocklist with an export
+#[poise::command(
+    rename = "import",
+    slash_command,
+    guild_only,
+    required_permissions = "MANAGE_GUILD",
+    description_localized(
+        "de",
+        "Ersetzt Einstellungen, gespeicherte Playlists und Sperrliste durch einen Export"
+    )
+)]
+pub async fn settings_import(
+    ctx: CommandContext<'_>,
+    #[description = "File from /settings export"]
+    #[description_localized("de", "Datei von /settings export")]
+    file: Attachment,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    if file.size > MAX_SETTINGS_BYTES {
+        return Err(CommandError::SettingsFileTooLarge {
+            limit: MAX_SETTINGS_BYTES,
+        });
+    }
+
+    ctx.defer_ephemeral().await?;
+    let content =
+        String::from_utf8(file.download().await?).map_err(|_| CommandError::SettingsFileNotText)?;
+    let export = SettingsExport::parse(&content)?;
+    // The cache reference must not be held across an await
+    let (imported, notices) = {
+        let guild = ctx.guild().expect("Guild not in cache");
+        export.into_config(
+            guild_id,
+            |channel| guild.channels.contains_key(&channel),
+            |role| guild.roles.contains_key(&role),
+        )?
+    };
+
+    let (current, shown) = read_guild_config(ctx, guild_id).await?;
+    let changes = current.changes(&imported);
+    let mut notes = notices.join("\n");
+    if changes.is_empty() {
+        if !notes.is_empty() {
+            notes.insert(0, '\n');
+        }
+        let response_details = format!("Der Export stimmt mit diesem Server überein{notes}");
+        _ = respond_success(&ctx, "Import", response_details, true).await?;
+        return Ok(());
+    }
+
+    let mut preview = format!("Der Import ersetzt:\n- {}", changes.join("\n- "));
+    if !notes.is_empty() {
+        preview += &format!("\n\n{notes}");
+    }
+    let prompt = BotResponse::success("Import").description(preview);
+    if !confirm(ctx, prompt).await? {
+        return Ok(());
+    }
+
+    // Settings changed since the preview would be overwritten without being shown
+    get_guild_settings(ctx.serenity_context())
+        .await
+        .compare_and_update(guild_id, shown, |settings| *settings = imported.settings)
+        .map_err(|_| CommandError::SettingsConflict)?;
+    get_saved_playlists(ctx.serenity_context())
+        .await
+        .replace(guild_id, imported.playlists);
+    get_blocklist(ctx.serenity_context())
+        .await
+        .replace(guild_id, imported.blocklist);
+
+    let response_details = format!("{} Änderungen übernommen", changes.len());
+    _ = respond_success(&ctx, "Import", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Everything the settings transfer covers, with the version of the settings. Fails while the
+/// saved playlists are unavailable, an export would miss them and an import could not save them.
+async fn read_guild_config(
+    ctx: CommandContext<'_>,
+    guild_id: GuildId,
+) -> Result<(GuildConfig, u64), CommandError> {
+    let shown = get_guild_settings(ctx.serenity_context())
+        .await
+        .get_versioned(guild_id);
+    let saved_playlists = get_saved_playlists(ctx.serenity_context()).await;
+    saved_playlists.check_available()?;
+    let config = GuildConfig {
+        settings: shown.settings,
+        playlists: saved_playlists.all(guild_id),
+        blocklist: get_blocklist(ctx.serenity_context())
+            .await
+            .entries(guild_id),
+    };
+    Ok((config, shown.version))
+}
+
+async fn autocomplete_blocked(ctx: CommandContext<'_>, partial: &str) -> Vec<AutocompleteChoice> {
+    let Some(guild_id) = ctx.guild_id() else {
+        return vec![];
+    };
+    let partial = partial.to_lowercase();
+    get_blocklist(ctx.serenity_context())
+        .await
+        .entries(guild_id)
+        .into_iter()
+        .map(|entry| (entry.describe(), entry.key()))
+        // Longer values are rejected by Discord, those entries can still be typed in
+        .filter(|(name, key)| key.len() <= 100 && name.to_lowercase().contains(&partial))
+        .take(25)
+        .map(|(name, key)| {
+            AutocompleteChoice::new(truncate_chars(&name, AUTOCOMPLETE_NAME_CHARS), key)
+        })
+        .collect()
+}
+
+/// Time for each step of /setup, steps answered before stay saved
+const SETUP_STEP_TIMEOUT: Duration = Duration::from_secs(120);
+/// Track length limits offered by /setup, in minutes
+const SETUP_DURATION_CHOICES: [u64; 4] = [10, 30, 60, 180];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SetupStep {
+    AnnounceChannel,
+    DjRole,
+    Language,
+    MaxTrackDuration,
+    AlwaysOnChannel,
+}
+
+const SETUP_STEPS: [SetupStep; 5] = [
+    SetupStep::AnnounceChannel,
+    SetupStep::DjRole,
+    SetupStep::Language,
+    SetupStep::MaxTrackDuration,
+    SetupStep::AlwaysOnChannel,
+];
+
+#[derive(Clone)]
+enum SetupAnswer {
+    Skip,
+    Clear,
+    Channel(ChannelId),
+    Role(RoleId),
+    Value(String),
+}
+
+impl SetupAnswer {
+    fn of(press: &ComponentInteraction) -> Self {
+        match &press.data.kind {
+            ComponentInteractionDataKind::ChannelSelect { values } => values
+                .first()
+                .copied()
+                .map_or(SetupAnswer::Skip, SetupAnswer::Channel),
+            ComponentInteractionDataKind::RoleSelect { values } => values
+                .first()
+                .copied()
+                .map_or(SetupAnswer::Skip, SetupAnswer::Role),
+            ComponentInteractionDataKind::StringSelect { values } => values
+                .first()
+                .cloned()
+                .map_or(SetupAnswer::Skip, SetupAnswer::Value),
+            _ if press.data.custom_id.ends_with("setupclear") => SetupAnswer::Clear,
+            _ => SetupAnswer::Skip,
+        }
+    }
+}
+
+fn apply_setup_answer(step: SetupStep, settings: &mut GuildSettings, answer: SetupAnswer) {
+    match (step, answer) {
+        (_, SetupAnswer::Skip) => {}
+        (SetupStep::AnnounceChannel, SetupAnswer::Channel(channel)) => {
+            settings.announce_channel = Some(channel)
+        }
+        (SetupStep::AnnounceChannel, SetupAnswer::Clear) => settings.announce_channel = None,
+        (SetupStep::DjRole, SetupAnswer::Role(role)) => settings.dj_role = Some(role),
+        (SetupStep::DjRole, SetupAnswer::Clear) => settings.dj_role = None,
+        (SetupStep::Language, SetupAnswer::Value(value)) => {
+            settings.locale = match value.as_str() {
+                "de" => Some(Locale::German),
+                "en" => Some(Locale::English),
+                _ => None,
+            }
+        }
+        (SetupStep::Language, SetupAnswer::Clear) => settings.locale = None,
+        (SetupStep::MaxTrackDuration, SetupAnswer::Value(value)) => {
+            settings.max_track_duration = value
+                .parse::<u64>()
+                .ok()
+                .map(|minutes| Duration::from_secs(minutes * 60))
+        }
+        (SetupStep::MaxTrackDuration, SetupAnswer::Clear) => settings.max_track_duration = None,
+        (SetupStep::AlwaysOnChannel, SetupAnswer::Channel(channel)) => {
+            settings.always_on_channel = Some(channel)
+        }
+        (SetupStep::AlwaysOnChannel, SetupAnswer::Clear) => settings.always_on_channel = None,
+        // Answers from a component of another step
+        _ => {}
+    }
+}
+
+impl SetupStep {
+    /// Whether the setting asked for in this step is the same in both
+    fn same_value(&self, a: &GuildSettings, b: &GuildSettings) -> bool {
+        match self {
+            SetupStep::AnnounceChannel => a.announce_channel == b.announce_channel,
+            SetupStep::DjRole => a.dj_role == b.dj_role,
+            SetupStep::Language => a.locale == b.locale,
+            SetupStep::MaxTrackDuration => a.max_track_duration == b.max_track_duration,
+            SetupStep::AlwaysOnChannel => a.always_on_channel == b.always_on_channel,
+        }
+    }
+}
+
+/// Saves the answer to a step that was shown with the settings `shown`. Changes to other
+/// settings since then are kept by retrying once on the re-read settings, a change to the
+/// setting of the step itself would be overwritten and is a conflict.
+fn save_setup_answer(
+    store: &GuildSettingsStore,
+    guild_id: GuildId,
+    step: SetupStep,
+    shown: VersionedSettings,
+    answer: SetupAnswer,
+) -> Result<VersionedSettings, SettingsConflict> {
+    let apply = |answer: SetupAnswer| {
+        move |settings: &mut GuildSettings| apply_setup_answer(step, settings, answer)
+    };
+    match store.compare_and_update(guild_id, shown.version, apply(answer.clone())) {
+        Ok(saved) => Ok(saved),
+        Err(SettingsConflict { current })
+            if step.same_value(&shown.settings, &current.settings) =>
+        {
+            store.compare_and_update(guild_id, current.version, apply(answer))
+        }
+        Err(conflict) => Err(conflict),
+    }
+}
+
+/// Question and components of a step, with the current value preselected
+fn render_setup_step(
+    step: SetupStep,
+    settings: &GuildSettings,
+    id_prefix: &str,
+) -> (BotResponse, Vec<CreateActionRow>) {
+    let select_id = format!("{id_prefix}setupselect");
+    let (question, menu) = match step {
+        SetupStep::AnnounceChannel => (
+            "In welchem Kanal sollen Nachrichten ohne Befehl erscheinen, z.B. Hinweise auf nicht abspielbare Lieder? Ohne Kanal wird der Kanal des letzten Befehls verwendet.",
+            CreateSelectMenuKind::Channel {
+                channel_types: Some(vec![ChannelType::Text]),
+                default_channels: settings.announce_channel.map(|c| vec![c]),
+            },
+        ),
+        SetupStep::DjRole => (
+            "Welche Rolle darf die Wiedergabe anderer steuern? Mitglieder mit der Berechtigung „Mitglieder verschieben“ dürfen das immer.",
+            CreateSelectMenuKind::Role {
+                default_roles: settings.dj_role.map(|r| vec![r]),
+            },
+        ),
+        SetupStep::Language => (
+            "In welcher Sprache soll der Bot antworten?",
+            CreateSelectMenuKind::String {
+                options: [
+                    ("Deutsch", "de", Some(Locale::German)),
+                    ("English", "en", Some(Locale::English)),
+                    ("Sprache der einzelnen Nutzer", "none", None),
+                ]
+                .into_iter()
+                .map(|(label, value, locale)| {
+                    CreateSelectMenuOption::new(label, value)
+                        .default_selection(settings.locale == locale)
+                })
+                .collect(),
+            },
+        ),
+        SetupStep::MaxTrackDuration => (
+            "Wie lang dürfen Lieder höchstens sein?",
+            CreateSelectMenuKind::String {
+                options: SETUP_DURATION_CHOICES
+                    .into_iter()
+                    .map(|minutes| {
+                        CreateSelectMenuOption::new(format!("{minutes} Minuten"), minutes.to_string())
+                            .default_selection(
+                                settings.max_track_duration
+                                    == Some(Duration::from_secs(minutes * 60)),
+                            )
+                    })
+                    .collect(),
+            },
+        ),
+        SetupStep::AlwaysOnChannel => (
+            "In welchem Sprachkanal soll der Bot bleiben, auch wenn niemand zuhört (24/7)?",
+            CreateSelectMenuKind::Channel {
+                channel_types: Some(vec![ChannelType::Voice, ChannelType::Stage]),
+                default_channels: settings.always_on_channel.map(|c| vec![c]),
+            },
+        ),
+    };
+
+    let index = SETUP_STEPS
+        .iter()
+        .position(|s| *s == step)
+        .expect("Every step is listed");
+    let embed = BotResponse::success(format!("Einrichtung ({}/{})", index + 1, SETUP_STEPS.len()))
+        .description(question);
+    let components = vec![
+        CreateActionRow::SelectMenu(CreateSelectMenu::new(select_id, menu)),
+        CreateActionRow::Buttons(vec![
+            CreateButton::new(format!("{id_prefix}setupskip"))
+                .label("Überspringen")
+                .style(ButtonStyle::Secondary),
+            CreateButton::new(format!("{id_prefix}setupclear"))
+                .label("Zurücksetzen")
+                .style(ButtonStyle::Danger),
+        ]),
+    ];
+    (embed, components)
+}
+
+fn render_setup_summary(settings: &GuildSettings, note: &str) -> BotResponse {
+    let unset = || "nicht festgelegt".to_owned();
+    BotResponse::success("Einrichtung")
+        .description(note)
+        .field(
+            "Ankündigungskanal",
+            settings
+                .announce_channel
+                .map_or_else(unset, |c| c.mention().to_string()),
+            true,
+        )
+        .field(
+            "DJ-Rolle",
+            settings
+                .dj_role
+                .map_or_else(unset, |r| r.mention().to_string()),
+            true,
+        )
+        .field(
+            "Sprache",
+            settings
+                .locale
+                .map_or("Sprache der einzelnen Nutzer", |l| l.name()),
+            true,
+        )
+        .field(
+            "Maximale Länge",
+            settings
+                .max_track_duration
+                .map_or_else(unset, |d| format!("{} Minuten", d.as_secs() / 60)),
+            true,
+        )
+        .field(
+            "24/7-Kanal",
+            settings
+                .always_on_channel
+                .map_or_else(unset, |c| c.mention().to_string()),
+            true,
+        )
+        .field("Ruhemodus", settings.quiet.describe(), true)
+}
+
+/// Lifts the duration limits for a while, for events
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    required_permissions = "MANAGE_GUILD",
+    description_localized(
+        "de",
+        "Hebt die Längengrenzen für Lieder eine Zeit lang auf, z.B. für Events"
+    )
+)]
+pub async fn partymode(
+    ctx: CommandContext<'_>,
+    #[description = "Duration like `3h`, `90m` or `1h30m`, `off` to end it early"]
+    #[description_localized(
+        "de",
+        "Dauer wie `3h`, `90m` oder `1h30m`, `off` zum vorzeitigen Beenden"
+    )]
+    duration: String,
+) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let party_modes = get_party_modes(ctx.serenity_context()).await;
+
+    let response_details = match parse_party_change(&duration) {
+        Some(PartyChange::Start(duration)) => {
+            let until = OffsetDateTime::now_utc() + duration;
+            party_modes.start(guild_id, until);
+            // Only the limits of the guild are lifted, the host's limit protects its memory
+            format!(
+                "{}\nLieder dürfen bis dahin beliebig lang sein. Die Warteschlange hat weiterhin höchstens {} Einträge.",
+                describe_party(until),
+                get_max_queue_length(ctx.serenity_context()).await
+            )
+        }
+        Some(PartyChange::End) => match party_modes.end(guild_id) {
+            true => "Der Party-Modus ist beendet, die Grenzen gelten wieder".to_owned(),
+            false => "Es läuft gerade kein Party-Modus".to_owned(),
+        },
+        None => format!(
+            "`{duration}` ist keine gültige Dauer. Beispiele: `3h`, `90m`, `1h30m` oder `off`, höchstens 24 Stunden"
+        ),
+    };
+    _ = respond_success(&ctx, "Party-Modus", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Walks through the most important server settings
+#[poise::command(
+    slash_command,
+    guild_only,
+    default_member_permissions = "MANAGE_GUILD",
+    description_localized(
+        "de",
+        "Führt durch die wichtigsten Einstellungen des Bots auf diesem Server"
+    )
+)]
+pub async fn setup(ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let store = get_guild_settings(ctx.serenity_context()).await;
+    let embed_mode = EmbedMode::of_reply(ctx, true).await;
+    let id_prefix = ctx.id().to_string();
+
+    let mut shown = store.get_versioned(guild_id);
+    let (embed, components) = render_setup_step(SETUP_STEPS[0], &shown.settings, &id_prefix);
+    let reply = ctx
+        .send(
+            embed_mode
+                .reply(CreateReply::default(), embed.embed(ctx))
+                .components(components)
+                .ephemeral(true),
+        )
+        .await?;
+
+    for (i, step) in SETUP_STEPS.iter().enumerate() {
+        let press = ComponentInteractionCollector::new(ctx.serenity_context())
+            .filter({
+                let id_prefix = id_prefix.clone();
+                let author_id = ctx.author().id;
+                move |press| {
+                    press.data.custom_id.starts_with(&id_prefix) && press.user.id == author_id
+                }
+            })
+            .timeout(SETUP_STEP_TIMEOUT)
+            .await;
+
+        // Each answer was saved right away, so a timeout only ends the walkthrough
+        let Some(press) = press else {
+            let summary = render_setup_summary(
+                &store.get(guild_id),
+                "Die Zeit ist abgelaufen. Die bisherigen Antworten sind gespeichert, mit /setup geht es erneut los.",
+            ).embed(ctx);
+            reply
+                .edit(
+                    ctx,
+                    embed_mode
+                        .reply(CreateReply::default(), summary)
+                        .components(vec![]),
+                )
+                .await?;
+            return Ok(());
+        };
+
+        shown = match save_setup_answer(&store, guild_id, *step, shown, SetupAnswer::of(&press)) {
+            Ok(saved) => saved,
+            Err(_) => {
+                _ = press
+                    .create_response(ctx, CreateInteractionResponse::Acknowledge)
+                    .await;
+                return Err(CommandError::SettingsConflict);
+            }
+        };
+        let (embed, components) = match SETUP_STEPS.get(i + 1) {
+            Some(next) => render_setup_step(*next, &shown.settings, &id_prefix),
+            None => (
+                render_setup_summary(&shown.settings, "Die Einrichtung ist abgeschlossen."),
+                vec![],
+            ),
+        };
+        press
+            .create_response(
+                ctx,
+                CreateInteractionResponse::UpdateMessage(
+                    embed_mode
+                        .message(CreateInteractionResponseMessage::new(), embed.embed(ctx))
+                        .components(components),
+                ),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Your personal preferences, in every server
+#[poise::command(
+    slash_command,
+    subcommands(
+        "preferences_language",
+        "preferences_anonymous",
+        "preferences_public",
+        "preferences_accessible"
+    ),
+    subcommand_required
+)]
+pub async fn preferences(_ctx: CommandContext<'_>) -> Result<(), CommandError> {
+    Ok(())
+}
+
+/// Sets the language of the responses to you
+#[poise::command(
+    rename = "language",
+    slash_command,
+    description_localized(
+        "de",
+        "Legt die Sprache der Antworten an dich fest, auch wenn der Server eine andere nutzt"
+    )
+)]
+pub async fn preferences_language(
+    ctx: CommandContext<'_>,
+    #[description = "Your language, empty to use the one of the server or your client"]
+    #[description_localized(
+        "de",
+        "Deine Sprache, leer um die des Servers oder deines Clients zu verwenden"
+    )]
+    language: Option<Locale>,
+) -> Result<(), CommandError> {
+    get_user_preferences(ctx.serenity_context())
+        .await
+        .update(ctx.author().id, |preferences| preferences.locale = language);
+
+    let response_details = match language {
+        Some(locale) => format!("Deine Sprache ist jetzt {}", locale.name()),
+        None => "Es wird die Sprache des Servers oder deines Clients verwendet".to_owned(),
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Hides your name on tracks you requested in public messages
+#[poise::command(
+    rename = "anonymous",
+    slash_command,
+    description_localized(
+        "de",
+        "Versteckt deinen Namen bei deinen Liedern in öffentlichen Nachrichten"
+    )
+)]
+pub async fn preferences_anonymous(
+    ctx: CommandContext<'_>,
+    #[description = "Whether public messages show \"a user\" instead of your name"]
+    #[description_localized(
+        "de",
+        "Ob öffentliche Nachrichten „ein Nutzer“ statt deines Namens zeigen"
+    )]
+    enabled: bool,
+) -> Result<(), CommandError> {
+    get_user_preferences(ctx.serenity_context())
+        .await
+        .update(ctx.author().id, |preferences| {
+            preferences.anonymous = enabled
+        });
+
+    let response_details = if enabled {
+        "Öffentliche Nachrichten zeigen bei deinen Liedern „ein Nutzer“"
+    } else {
+        "Öffentliche Nachrichten zeigen bei deinen Liedern deinen Namen"
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Whether info commands like /now_playing answer publicly
+#[poise::command(
+    rename = "public",
+    slash_command,
+    description_localized(
+        "de",
+        "Ob Info-Commands wie /now_playing öffentlich statt nur für dich antworten"
+    )
+)]
+pub async fn preferences_public(
+    ctx: CommandContext<'_>,
+    #[description = "Whether the responses are visible to everyone"]
+    #[description_localized("de", "Ob die Antworten für alle sichtbar sind")]
+    enabled: bool,
+) -> Result<(), CommandError> {
+    get_user_preferences(ctx.serenity_context())
+        .await
+        .update(ctx.author().id, |preferences| {
+            preferences.public_info = enabled
+        });
+
+    let response_details = if enabled {
+        "Info-Commands antworten dir jetzt öffentlich"
+    } else {
+        "Info-Commands antworten jetzt nur für dich sichtbar"
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
+
+/// Turns your private responses into plain sentences for screen readers
+#[poise::command(
+    rename = "accessible",
+    slash_command,
+    description_localized(
+        "de",
+        "Macht Antworten nur für dich zu einfachen Sätzen für Screenreader"
+    )
+)]
+pub async fn preferences_accessible(
+    ctx: CommandContext<'_>,
+    #[description = "Whether private responses are plain sentences without markdown and emoji"]
+    #[description_localized(
+        "de",
+        "Ob Antworten nur für dich einfache Sätze ohne Formatierung und Emoji sind"
+    )]
+    enabled: bool,
+) -> Result<(), CommandError> {
+    get_user_preferences(ctx.serenity_context())
+        .await
+        .update(ctx.author().id, |preferences| {
+            preferences.accessible = enabled
+        });
+
+    let response_details = if enabled {
+        "Antworten nur für dich sind jetzt einfache Sätze ohne Formatierung und Emoji"
+    } else {
+        "Antworten nur für dich sind wieder normal formatiert"
+    };
+    _ = respond_success(&ctx, "Einstellungen", response_details, true).await?;
+
+    Ok(())
+}
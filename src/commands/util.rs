@@ -0,0 +1,2009 @@
+use crate::audit_log::{AuditAction, AuditLog, EnqueueOrigin};
+use crate::auto_pause::AutoPauses;
+use crate::autoplay::{self, Autoplay};
+use crate::blocklist::Blocklist;
+use crate::command_schema::CommandSchemas;
+use crate::diagnostics::DriverDiagnostics;
+use crate::end_reason::{describe_play_error, EndMarkers, EndReason};
+use crate::error_rates::{ErrorRates, ErrorSource};
+use crate::lifecycle::GuildLifecycle;
+use crate::notice_channel::{select_notice_channel, InvokingChannel, NoticeTarget};
+use crate::outbound::OutboundScheduler;
+use crate::party_mode::PartyModes;
+use crate::persistence::PersistenceHealth;
+use crate::schedule::ScheduleStore;
+use crate::undo::UndoSlots;
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use poise::ReplyHandle;
+use reqwest::{Client as HttpClient, Url};
+use serenity::all::{ButtonStyle, ChannelId, GuildId, Member, RoleId, UserId};
+use serenity::builder::{AutocompleteChoice, CreateActionRow, CreateButton};
+use serenity::futures::future::join_all;
+use songbird::error::JoinError;
+use songbird::events::{Event, EventContext, EventHandler as VoiceEventHandler, TrackEvent};
+use songbird::tracks::{PlayError, PlayMode, Queued, TrackHandle, TrackQueue, TrackState};
+use songbird::{Call, Songbird};
+use std::borrow::Cow;
+use std::ops::Deref;
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use tokio::sync::{Mutex, Semaphore};
+use uuid::Uuid;
+
+use crate::commands::util::GetCallError::{NotInCall, NotInGuild, SongbirdNotFound};
+use crate::events::{PlaybackEvent, PlaybackEventBus};
+use crate::free_notices::FreeNotices;
+use crate::guild_settings::{DurationVerdict, GuildSettingsStore};
+use crate::history::{PlayHistory, TrackEnd};
+use crate::locale::Locale;
+use crate::loudness::{gain_factor, probe_gain};
+use crate::metadata::{Playability, TrackMetadata, TrackMetadataKey, TrackSource};
+use crate::playback_mode::{fair_insert_position, AfterTrack, ModeChange, PlaybackModes};
+use crate::playlist_sync::PlaylistSyncStore;
+use crate::position_cache::PositionCache;
+use crate::preflight::check_direct_link;
+use crate::queue_ops;
+use crate::report::TrackReports;
+use crate::resolution::ResolutionPath::{AutocompleteUrl, RawSearch, UrlFastpath, UrlYtDlp};
+use crate::resolution::ResolutionTelemetry;
+use crate::response::BotResponse;
+use crate::resume::ResumePoints;
+use crate::saved_playlists::SavedPlaylistStore;
+use crate::staging::StagingStore;
+use crate::start_latency::StartLatency;
+use crate::stats::{PlayOutcome, StatsStore};
+use crate::tts::{announce_next, announcement_text, TtsConfig};
+use crate::user_preferences::UserPreferencesStore;
+use crate::validator;
+use crate::voice_sessions::{SessionsFull, VoiceSessions};
+use crate::voice_state::{is_occupied, listener_count};
+use crate::youtube::{YoutubeClient, YtApiError, YtResource, YtSearchFilter};
+use crate::ytdlp::{YtDlpConfig, YtDlpInput};
+use crate::CommandError::QueueEmpty;
+use crate::{CommandContext, CommandError};
+
+// ======== Util functions ========
+
+pub async fn get_http_client(ctx: &serenity::client::Context) -> HttpClient {
+    let data = ctx.data.read().await;
+    data.get::<crate::HttpKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_youtube_client(ctx: &serenity::client::Context) -> YoutubeClient {
+    let data = ctx.data.read().await;
+    data.get::<crate::YoutubeKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_ytdlp_permits(ctx: &serenity::client::Context) -> Arc<Semaphore> {
+    let data = ctx.data.read().await;
+    data.get::<crate::YtDlpPermitsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+/// Starts the background playability check for the guild of the command, if enabled
+pub async fn start_track_validator(
+    ctx: CommandContext<'_>,
+    songbird: Arc<Songbird>,
+    guild_id: GuildId,
+) {
+    let validator = {
+        let data = ctx.serenity_context().data.read().await;
+        data.get::<crate::TrackValidatorKey>()
+            .cloned()
+            .expect("Guaranteed to exist in the typemap")
+    };
+
+    let notify_channel = select_notice_channel(
+        NoticeTarget::Announcements,
+        &InvokingChannel::of_command(ctx),
+        get_guild_settings(ctx.serenity_context())
+            .await
+            .get(guild_id)
+            .announce_channel,
+    );
+    validator.spawn_for(
+        ctx.serenity_context().http.clone(),
+        songbird,
+        get_ytdlp_permits(ctx.serenity_context()).await,
+        guild_id,
+        notify_channel,
+    );
+}
+
+/// Members who may move others or have the DJ role of the guild may control the playback of
+/// others
+pub fn has_dj_rights(member: Option<&Member>, dj_role: Option<RoleId>) -> bool {
+    member.is_some_and(|member| {
+        member.permissions.is_some_and(|p| p.move_members())
+            || dj_role.is_some_and(|role| member.roles.contains(&role))
+    })
+}
+
+pub async fn get_guild_settings(ctx: &serenity::client::Context) -> Arc<GuildSettingsStore> {
+    let data = ctx.data.read().await;
+    data.get::<crate::GuildSettingsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_history(ctx: &serenity::client::Context) -> Arc<PlayHistory> {
+    let data = ctx.data.read().await;
+    data.get::<crate::HistoryKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_command_schemas(ctx: &serenity::client::Context) -> Arc<CommandSchemas> {
+    let data = ctx.data.read().await;
+    data.get::<crate::CommandSchemasKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_driver_diagnostics(ctx: &serenity::client::Context) -> Arc<DriverDiagnostics> {
+    let data = ctx.data.read().await;
+    data.get::<crate::DriverDiagnosticsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_outbound(ctx: &serenity::client::Context) -> Arc<OutboundScheduler> {
+    let data = ctx.data.read().await;
+    data.get::<crate::OutboundKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_audit_log(ctx: &serenity::client::Context) -> Arc<AuditLog> {
+    let data = ctx.data.read().await;
+    data.get::<crate::AuditLogKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_undo_slots(ctx: &serenity::client::Context) -> Arc<UndoSlots> {
+    let data = ctx.data.read().await;
+    data.get::<crate::UndoSlotsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_max_queue_length(ctx: &serenity::client::Context) -> usize {
+    let data = ctx.data.read().await;
+    *data
+        .get::<crate::MaxQueueLengthKey>()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_tts(ctx: &serenity::client::Context) -> Option<Arc<TtsConfig>> {
+    let data = ctx.data.read().await;
+    data.get::<crate::TtsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_free_notices(ctx: &serenity::client::Context) -> Arc<FreeNotices> {
+    let data = ctx.data.read().await;
+    data.get::<crate::FreeNoticesKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_resume_points(ctx: &serenity::client::Context) -> Arc<ResumePoints> {
+    let data = ctx.data.read().await;
+    data.get::<crate::ResumePointsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_schedules(ctx: &serenity::client::Context) -> Arc<ScheduleStore> {
+    let data = ctx.data.read().await;
+    data.get::<crate::ScheduleKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_guild_lifecycle(ctx: &serenity::client::Context) -> Arc<GuildLifecycle> {
+    let data = ctx.data.read().await;
+    data.get::<crate::GuildLifecycleKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_auto_pauses(ctx: &serenity::client::Context) -> Arc<AutoPauses> {
+    let data = ctx.data.read().await;
+    data.get::<crate::AutoPausesKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_playlist_syncs(ctx: &serenity::client::Context) -> Arc<PlaylistSyncStore> {
+    let data = ctx.data.read().await;
+    data.get::<crate::PlaylistSyncsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_saved_playlists(ctx: &serenity::client::Context) -> Arc<SavedPlaylistStore> {
+    let data = ctx.data.read().await;
+    data.get::<crate::SavedPlaylistsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_position_cache(ctx: &serenity::client::Context) -> Arc<PositionCache> {
+    let data = ctx.data.read().await;
+    data.get::<crate::PositionCacheKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_end_markers(ctx: &serenity::client::Context) -> Arc<EndMarkers> {
+    let data = ctx.data.read().await;
+    data.get::<crate::EndMarkersKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_blocklist(ctx: &serenity::client::Context) -> Arc<Blocklist> {
+    let data = ctx.data.read().await;
+    data.get::<crate::BlocklistKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_track_reports(ctx: &serenity::client::Context) -> Arc<TrackReports> {
+    let data = ctx.data.read().await;
+    data.get::<crate::TrackReportsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_voice_sessions(ctx: &serenity::client::Context) -> Arc<VoiceSessions> {
+    let data = ctx.data.read().await;
+    data.get::<crate::VoiceSessionsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_autoplay(ctx: &serenity::client::Context) -> Arc<Autoplay> {
+    let data = ctx.data.read().await;
+    data.get::<crate::AutoplayKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_start_latency(ctx: &serenity::client::Context) -> Arc<StartLatency> {
+    let data = ctx.data.read().await;
+    data.get::<crate::StartLatencyKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_error_rates(ctx: &serenity::client::Context) -> Arc<ErrorRates> {
+    let data = ctx.data.read().await;
+    data.get::<crate::ErrorRatesKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_party_modes(ctx: &serenity::client::Context) -> Arc<PartyModes> {
+    let data = ctx.data.read().await;
+    data.get::<crate::PartyModesKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_persistence_health(ctx: &serenity::client::Context) -> Arc<PersistenceHealth> {
+    let data = ctx.data.read().await;
+    data.get::<crate::PersistenceHealthKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_resolution_telemetry(ctx: &serenity::client::Context) -> Arc<ResolutionTelemetry> {
+    let data = ctx.data.read().await;
+    data.get::<crate::ResolutionTelemetryKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_stats(ctx: &serenity::client::Context) -> Arc<StatsStore> {
+    let data = ctx.data.read().await;
+    data.get::<crate::StatsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_user_preferences(ctx: &serenity::client::Context) -> Arc<UserPreferencesStore> {
+    let data = ctx.data.read().await;
+    data.get::<crate::UserPreferencesKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+/// Locale for responses to a command. A personal preference of the author wins over the guild
+/// setting, which wins over the interaction locale.
+pub async fn get_locale(ctx: CommandContext<'_>) -> Locale {
+    let user_locale = get_user_preferences(ctx.serenity_context())
+        .await
+        .get(ctx.author().id)
+        .locale;
+    if let Some(locale) = user_locale {
+        return locale;
+    }
+
+    let guild_locale = match ctx.guild_id() {
+        Some(guild_id) => {
+            get_guild_settings(ctx.serenity_context())
+                .await
+                .get(guild_id)
+                .locale
+        }
+        None => None,
+    };
+    Locale::resolve(guild_locale, ctx.locale())
+}
+
+/// Whether info commands should only be visible to the author, which is the default
+pub async fn info_is_ephemeral(ctx: CommandContext<'_>) -> bool {
+    !get_user_preferences(ctx.serenity_context())
+        .await
+        .get(ctx.author().id)
+        .public_info
+}
+
+pub async fn get_staging(ctx: &serenity::client::Context) -> Arc<StagingStore> {
+    let data = ctx.data.read().await;
+    data.get::<crate::StagingKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_playback_events(ctx: &serenity::client::Context) -> Arc<PlaybackEventBus> {
+    let data = ctx.data.read().await;
+    data.get::<crate::PlaybackEventsKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_playback_modes(ctx: &serenity::client::Context) -> Arc<PlaybackModes> {
+    let data = ctx.data.read().await;
+    data.get::<crate::PlaybackModesKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub async fn get_ytdlp_config(ctx: &serenity::client::Context) -> Arc<YtDlpConfig> {
+    let data = ctx.data.read().await;
+    data.get::<crate::YtDlpConfigKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+pub fn get_author_voice_state(ctx: CommandContext<'_>) -> (GuildId, Option<ChannelId>) {
+    let guild = ctx.guild().expect("Guild not in cache");
+    let channel_id = guild
+        .voice_states
+        .get(&ctx.author().id)
+        .and_then(|voice_state| voice_state.channel_id);
+
+    (guild.id, channel_id)
+}
+
+pub async fn get_metadata(track: &TrackHandle) -> Arc<TrackMetadata> {
+    track
+        .typemap()
+        .read()
+        .await
+        .get::<TrackMetadataKey>()
+        .expect("Every track is added with Metadata in the typemap")
+        .clone()
+}
+
+/// Locks the call for a change of its queue. The queue is saved to the undo slot of the guild
+/// before, so the change can be reverted with `/undo`, even if the command panics halfway.
+pub async fn with_queue_lock<T>(
+    ctx: CommandContext<'_>,
+    call: &Mutex<Call>,
+    change: impl FnOnce(&TrackQueue) -> T,
+) -> Result<T, CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let undo_slots = get_undo_slots(ctx.serenity_context()).await;
+    let call = call.lock().await;
+
+    Ok(undo_slots
+        .save_before(
+            guild_id,
+            ctx.id(),
+            &ctx.command().qualified_name,
+            call.queue(),
+            change,
+        )
+        .await)
+}
+
+/// Clears the queue, or only the upcoming tracks if the current one should play to the end, and
+/// returns the event to publish once the call is unlocked. Shared by /stop and the control socket.
+pub fn stop_queue(
+    queue: &TrackQueue,
+    guild_id: GuildId,
+    end_markers: &EndMarkers,
+    actor: Option<UserId>,
+    finish_current: bool,
+) -> PlaybackEvent {
+    if finish_current {
+        clear_upcoming(queue, guild_id, end_markers, actor);
+        if let Some(current) = queue.current() {
+            _ = current.disable_loop();
+        }
+        PlaybackEvent::QueueChanged
+    } else {
+        for track in queue.current_queue() {
+            end_markers.mark_by(guild_id, track.uuid(), EndReason::Stopped, actor);
+        }
+        queue.stop();
+        PlaybackEvent::Stopped
+    }
+}
+
+/// Removes and stops the upcoming tracks, the current one plays on. Returns the removed tracks.
+pub fn clear_upcoming(
+    queue: &TrackQueue,
+    guild_id: GuildId,
+    end_markers: &EndMarkers,
+    actor: Option<UserId>,
+) -> Vec<Queued> {
+    let removed = queue.modify_queue(queue_ops::drain_upcoming);
+    for track in &removed {
+        end_markers.mark_by(guild_id, track.uuid(), EndReason::Stopped, actor);
+        _ = track.stop();
+    }
+    removed
+}
+
+/// Skips the current track and returns it. A looping track has its loop disabled first, so it
+/// does not come back.
+pub fn skip_current(
+    queue: &TrackQueue,
+    guild_id: GuildId,
+    end_markers: &EndMarkers,
+    actor: Option<UserId>,
+    looping: bool,
+) -> Option<TrackHandle> {
+    let skipped = queue.current()?;
+    end_markers.mark_by(guild_id, skipped.uuid(), EndReason::Skipped, actor);
+    if looping {
+        _ = skipped.disable_loop();
+    }
+    _ = queue.skip();
+    Some(skipped)
+}
+
+/// The current track with its state. A handle whose track was dropped, e.g. after a driver
+/// restart, counts as finished: It is removed from the queue and the next track is tried once.
+pub async fn live_current_track(
+    call: &Mutex<Call>,
+) -> Result<(TrackHandle, TrackState), CommandError> {
+    for _ in 0..2 {
+        let current = call.lock().await.queue().current().ok_or(QueueEmpty)?;
+        match current.get_info().await {
+            Ok(info) => return Ok((current, info)),
+            Err(e) => {
+                warn!(
+                    "Current track {} is dead ({e}), advancing the queue",
+                    current.uuid()
+                );
+                let call = call.lock().await;
+                let queue = call.queue();
+                queue.modify_queue(|raw_queue| {
+                    // Another command may have advanced the queue in the meantime
+                    if raw_queue
+                        .front()
+                        .is_some_and(|t| t.uuid() == current.uuid())
+                    {
+                        raw_queue.pop_front();
+                    }
+                });
+                _ = queue.resume();
+            }
+        }
+    }
+    Err(CommandError::DeadTrack)
+}
+
+pub struct YtUrlIds {
+    pub video_id: Option<String>,
+    pub playlist_id: Option<String>,
+    /// 1-based position of the video in the playlist, if the link was opened from a playlist
+    pub playlist_index: Option<usize>,
+}
+
+/// Longer text is no link anybody pasted, and is not parsed at all
+const MAX_URL_LEN: usize = 2048;
+/// Video ids have 11 characters and playlist ids about 34, with some room for other kinds
+const MAX_YT_ID_LEN: usize = 64;
+
+/// Ids of a YouTube link. Ids that contain anything but the characters YouTube uses are dropped,
+/// as they end up in api urls and yt-dlp arguments.
+pub fn get_yt_id_from_url(url: &str) -> YtUrlIds {
+    let query = |url: &Url, key: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned())
+            .filter(|id| is_valid_yt_id(id))
+    };
+    let url = Some(url)
+        .filter(|url| url.len() <= MAX_URL_LEN)
+        .and_then(|url| Url::parse(url).ok());
+
+    match url {
+        Some(url) if url.domain().is_some_and(|d| d == "youtu.be") => YtUrlIds {
+            video_id: url
+                .path_segments()
+                .and_then(|mut segments| segments.next())
+                .filter(|id| is_valid_yt_id(id))
+                .map(str::to_owned),
+            playlist_id: query(&url, "list"),
+            playlist_index: query(&url, "index").and_then(|i| i.parse().ok()),
+        },
+        Some(url)
+            if url
+                .domain()
+                .is_some_and(|d| d == "youtube.com" || d.ends_with(".youtube.com")) =>
+        {
+            YtUrlIds {
+                video_id: query(&url, "v"),
+                playlist_id: query(&url, "list"),
+                playlist_index: query(&url, "index").and_then(|i| i.parse().ok()),
+            }
+        }
+        _ => YtUrlIds {
+            video_id: None,
+            playlist_id: None,
+            playlist_index: None,
+        },
+    }
+}
+
+fn is_valid_yt_id(id: &str) -> bool {
+    (1..=MAX_YT_ID_LEN).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// What the autocomplete of a YouTube option suggests, before the parts that need the command
+/// context
+pub enum YtSuggestions {
+    /// Choices to show as they are, for short input and links
+    Choices(Vec<AutocompleteChoice>),
+    /// Search results for the input
+    Results(Vec<YtResource>),
+    /// Search is paused to save quota, or failed with the error
+    Unavailable(Option<YtApiError>),
+}
+
+/// Suggestions for the input of a video (`YtSearchFilter::Videos`) or playlist
+/// (`YtSearchFilter::Playlists`) option. Links keep the input as the value and show the title.
+pub async fn yt_suggestions(
+    youtube_client: &YoutubeClient,
+    partial: &str,
+    filter: YtSearchFilter,
+) -> YtSuggestions {
+    let input_choice =
+        || AutocompleteChoice::new(truncate_chars(partial, AUTOCOMPLETE_NAME_CHARS), partial);
+    if partial.len() < 3 {
+        // Discord doesn't like 0-length options
+        return YtSuggestions::Choices(vec![AutocompleteChoice::new(
+            "Tippe weiter, um Suchvorschläge zu erhalten",
+            partial,
+        )]);
+    }
+
+    // YouTube URL
+    let ids = get_yt_id_from_url(partial);
+    let title = match filter {
+        YtSearchFilter::Playlists => match ids.playlist_id {
+            Some(id) => Some(
+                youtube_client
+                    .get_playlist(&id, Some(1))
+                    .await
+                    .map(|playlist| playlist.title)
+                    .map_err(|e| error!("YT playlist lookup for id {} failed: {:?}", id, e)),
+            ),
+            None => None,
+        },
+        _ => match ids.video_id {
+            Some(id) => Some(
+                youtube_client
+                    .get_video(&id)
+                    .await
+                    .map(|video| video.title)
+                    .map_err(|e| error!("YT video lookup for id {} failed: {:?}", id, e)),
+            ),
+            None => None,
+        },
+    };
+    match title {
+        Some(Ok(title)) => {
+            return YtSuggestions::Choices(vec![AutocompleteChoice::new(
+                truncate_chars(&title, AUTOCOMPLETE_NAME_CHARS),
+                partial,
+            )])
+        }
+        Some(Err(())) => return YtSuggestions::Choices(vec![input_choice()]),
+        None => {}
+    }
+
+    // Other URL (include ':' to allow searches that start with "http")
+    if matches!(filter, YtSearchFilter::Videos)
+        && (partial.starts_with("https:") || partial.starts_with("http:"))
+    {
+        return YtSuggestions::Choices(vec![input_choice()]);
+    }
+
+    // Searches are expensive, the remaining quota is left to /play
+    if youtube_client.autocomplete_degraded() {
+        return YtSuggestions::Unavailable(None);
+    }
+
+    // Random text -> search
+    match youtube_client.search(partial, filter, 5).await {
+        Ok(results) => YtSuggestions::Results(results),
+        Err(e) => {
+            error!("YT search failed: {:?}", e);
+            YtSuggestions::Unavailable(Some(e))
+        }
+    }
+}
+
+// ======== Text limits ========
+
+/// Discord rejects autocomplete choice names longer than this
+pub const AUTOCOMPLETE_NAME_CHARS: usize = 100;
+
+/// Cuts a text to at most `max_chars` characters including an ellipsis that marks the cut.
+/// Never cuts within a character, and keeps combining marks and joined emoji with their base.
+pub fn truncate_chars(text: &str, max_chars: usize) -> Cow<'_, str> {
+    match text.char_indices().nth(max_chars) {
+        None => Cow::Borrowed(text),
+        // The ellipsis takes the place of the last character
+        Some(_) => {
+            let end = text
+                .char_indices()
+                .nth(max_chars.saturating_sub(1))
+                .map_or(text.len(), |(i, _)| i);
+            Cow::Owned(cut_before(text, end))
+        }
+    }
+}
+
+const ELLIPSIS: char = '…';
+
+/// The text up to the byte index with an ellipsis. The index is moved back until it does not
+/// split a character from the marks, selectors or joiners that belong to it.
+fn cut_before(text: &str, mut end: usize) -> String {
+    let splits_cluster = |end: usize| {
+        text[end..].chars().next().is_some_and(continues_cluster)
+            || text[..end].ends_with('\u{200D}')
+    };
+    while end > 0 && splits_cluster(end) {
+        end = text[..end].char_indices().next_back().map_or(0, |(i, _)| i);
+    }
+    let kept = text[..end].trim_end();
+    format!("{kept}{ELLIPSIS}")
+}
+
+/// Characters that are drawn together with the one before them
+fn continues_cluster(c: char) -> bool {
+    matches!(
+        c,
+        // Combining diacritical marks
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE20}'..='\u{FE2F}'
+            // Variation selectors, emoji joiner and skin tones
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{200D}'
+            | '\u{1F3FB}'..='\u{1F3FF}'
+    )
+}
+
+// ======== Shared components ========
+
+/// Entries per page of paginated lists
+pub const QUEUE_PAGE_SIZE: usize = 10;
+
+/// Pages of a paginated list with `len` entries, an empty list still has one
+pub fn page_count(len: usize) -> usize {
+    len.div_ceil(QUEUE_PAGE_SIZE).max(1)
+}
+
+/// Turns a page number as users type it, counted from 1, into the index of an existing page of a
+/// list with `len` entries. Returns whether the page had to be clamped.
+pub fn clamp_page(page: usize, len: usize) -> (usize, bool) {
+    let index = page.saturating_sub(1);
+    let last = page_count(len) - 1;
+    (index.min(last), page == 0 || index > last)
+}
+
+/// Buttons to reject the autoplay suggestion or to add it to the queue
+pub fn autoplay_buttons(id_prefix: &str, disabled: bool) -> CreateActionRow {
+    CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("{id_prefix}autoreject"))
+            .label("Anderer Vorschlag")
+            .style(ButtonStyle::Secondary)
+            .disabled(disabled),
+        CreateButton::new(format!("{id_prefix}autopromote"))
+            .label("Vorschlag einreihen")
+            .style(ButtonStyle::Secondary)
+            .disabled(disabled),
+    ])
+}
+
+/// Runs the action of a button from [autoplay_buttons], identified by the id without prefix.
+/// Returns false for other buttons.
+pub async fn press_autoplay_button(
+    ctx: CommandContext<'_>,
+    button: &str,
+    pressed_by: UserId,
+) -> Result<bool, CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    let autoplay = get_autoplay(ctx.serenity_context()).await;
+    match button {
+        "autoreject" => {
+            autoplay.reject(guild_id);
+            autoplay::refresh(ctx.serenity_context(), &autoplay, guild_id).await;
+        }
+        "autopromote" => {
+            let (_, call) = get_call(ctx).await?;
+            if let Some(suggestion) = autoplay.take(guild_id) {
+                let metadata = Arc::new(TrackMetadata::from_with_request(
+                    (*suggestion.metadata).clone(),
+                    pressed_by,
+                ));
+                enqueue_resolved(ctx, call, metadata, EnqueueOrigin::Autoplay).await?;
+            }
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+/// Shorthand for the common response with only a title and a description
+pub async fn respond_success<'a>(
+    ctx: &'a CommandContext<'a>,
+    title: impl Into<String>,
+    details: impl Into<String>,
+    ephemeral: bool,
+) -> Result<ReplyHandle<'a>, serenity::Error> {
+    BotResponse::success(title)
+        .description(details)
+        .ephemeral(ephemeral)
+        .send(ctx)
+        .await
+}
+
+#[derive(Error, Debug)]
+pub enum JoinVoiceError {
+    #[error("Failed to join")]
+    Join(#[from] JoinError),
+    #[error(
+        "Did not join because the bot is used in channel {channel} for {listeners:?} listeners"
+    )]
+    Occupied {
+        channel: ChannelId,
+        /// Users other than bots in the channel, unknown if the guild is not cached
+        listeners: Option<usize>,
+        /// Until the queue runs out, see [remaining_playback]
+        remaining: Option<Duration>,
+    },
+    #[error("Did not join because {} of {} voice connections are in use", .0.active, .0.limit)]
+    Busy(SessionsFull),
+}
+
+/// Time until the queue runs out. Unknown with live streams and while a loop or autoplay keeps
+/// the queue going.
+async fn remaining_playback(
+    ctx: &serenity::client::Context,
+    guild_id: GuildId,
+    handles: &[TrackHandle],
+) -> Option<Duration> {
+    let modes = get_playback_modes(ctx).await.get(guild_id);
+    if modes.loop_track || modes.loop_queue || modes.autoplay {
+        return None;
+    }
+    let mut remaining = Duration::ZERO;
+    for handle in handles {
+        let metadata = get_metadata(handle).await;
+        if metadata.source == TrackSource::Stream || metadata.duration.is_zero() {
+            return None;
+        }
+        remaining += metadata.duration;
+    }
+    let position = match handles.first() {
+        Some(current) => current.get_info().await.map(|info| info.position).ok(),
+        None => None,
+    };
+    Some(remaining.saturating_sub(position.unwrap_or_default()))
+}
+
+/// Makes the bot join a specific voice channel, if it is not already in use in a different one
+pub async fn join_voice(
+    ctx: &serenity::client::Context,
+    songbird: impl Deref<Target = Songbird>,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Result<Arc<Mutex<Call>>, JoinVoiceError> {
+    if let Some(call) = songbird.get(guild_id) {
+        let current_channel = call.lock().await.current_channel();
+
+        // Already in the channel
+        if current_channel.is_some_and(|c| c == channel_id.into()) {
+            return Ok(call);
+        }
+
+        // Used in a different channel
+        if let Some(current_channel) = current_channel {
+            let channel = ChannelId::new(current_channel.0.get());
+            let listeners = ctx
+                .cache
+                .guild(guild_id)
+                .map(|guild| listener_count(&guild, channel));
+            let handles = call.lock().await.queue().current_queue();
+            if is_occupied(listeners, handles.len()) {
+                let remaining = remaining_playback(ctx, guild_id, &handles).await;
+                return Err(JoinVoiceError::Occupied {
+                    channel,
+                    listeners,
+                    remaining,
+                });
+            }
+            info!("Moving idle bot from channel {channel} to {channel_id} in guild {guild_id}");
+        }
+    }
+
+    // Bot not in a channel or idle in an empty one -> join, which moves an existing call
+    let sessions = get_voice_sessions(ctx).await;
+    let new_session = sessions
+        .start(guild_id, Instant::now())
+        .map_err(JoinVoiceError::Busy)?;
+    let call = match songbird.join(guild_id, channel_id).await {
+        Ok(call) => call,
+        Err(e) => {
+            if new_session {
+                sessions.end(guild_id);
+            }
+            return Err(e.into());
+        }
+    };
+    get_driver_diagnostics(ctx)
+        .await
+        .spawn_for(guild_id, call.clone())
+        .await;
+    // A new session starts over, /resumesession takes the point before it joins
+    if new_session {
+        get_resume_points(ctx).await.invalidate(guild_id);
+    }
+    Ok(call)
+}
+
+#[derive(Error, Debug)]
+pub enum GetCallError {
+    #[error("The command was not called from a guild (this should never happen)")]
+    NotInGuild,
+    #[error("Songbird instance could not be retrieved from the command context")]
+    SongbirdNotFound,
+    #[error("The author is not in a voice channel with the bot")]
+    NotInCall,
+}
+
+/// Shared boilerplate for getting the active call for a command and correctly mapping all the error cases
+pub async fn get_call(
+    ctx: CommandContext<'_>,
+) -> Result<(ChannelId, Arc<Mutex<Call>>), GetCallError> {
+    let guild_id = ctx.guild_id().ok_or(NotInGuild)?;
+    let songbird = songbird::get(ctx.serenity_context())
+        .await
+        .ok_or(SongbirdNotFound)?;
+    let call = songbird.get(guild_id).ok_or(NotInCall)?;
+    let bot_channel = call.lock().await.current_channel().ok_or(NotInCall)?;
+    let user_channel = get_author_voice_state(ctx).1.ok_or(NotInCall)?;
+
+    if bot_channel != user_channel.into() {
+        return Err(NotInCall);
+    }
+
+    Ok((user_channel, call))
+}
+
+/// Free entries of a queue under the global limit
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueCapacity {
+    pub free: usize,
+    pub limit: usize,
+}
+
+impl QueueCapacity {
+    pub fn new(queued: usize, limit: usize) -> Self {
+        Self {
+            free: limit.saturating_sub(queued),
+            limit,
+        }
+    }
+
+    /// How many of the requested tracks fit
+    pub fn fit(&self, requested: usize) -> usize {
+        requested.min(self.free)
+    }
+
+    /// Why some of the requested tracks were left out, if any were
+    pub fn notice(&self, requested: usize) -> Option<String> {
+        let dropped = requested.saturating_sub(self.free);
+        (dropped > 0).then(|| {
+            format!(
+                "{dropped} Lieder wurden nicht hinzugefügt, die Warteschlange kann höchstens {} Einträge haben",
+                self.limit
+            )
+        })
+    }
+
+    /// Fails if not a single track fits anymore
+    #[allow(clippy::result_large_err)]
+    pub fn ensure_free(&self) -> Result<(), CommandError> {
+        match self.free {
+            0 => Err(CommandError::QueueFull { limit: self.limit }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Shown for [CommandError::QueueFull]
+    pub fn full_message(limit: usize) -> String {
+        format!("Die Warteschlange ist voll, sie kann höchstens {limit} Einträge haben")
+    }
+}
+
+pub async fn queue_capacity(ctx: &serenity::client::Context, call: &Mutex<Call>) -> QueueCapacity {
+    let queued = call.lock().await.queue().len();
+    QueueCapacity::new(queued, get_max_queue_length(ctx).await)
+}
+
+/// Checked by every enqueue, so no path can grow a queue past the limit. Loads of many tracks
+/// cut themselves to the [QueueCapacity] up front instead.
+async fn ensure_capacity(
+    ctx: &serenity::client::Context,
+    call: &Mutex<Call>,
+) -> Result<(), CommandError> {
+    queue_capacity(ctx, call).await.ensure_free()
+}
+
+pub async fn enqueue_track(
+    ctx: CommandContext<'_>,
+    call: Arc<Mutex<Call>>,
+    source: &str,
+    origin: EnqueueOrigin,
+) -> Result<Arc<TrackMetadata>, CommandError> {
+    ensure_capacity(ctx.serenity_context(), &call).await?;
+    let (track, metadata) = resolve_track(ctx, source).await?;
+    add_to_queue(
+        &queue_context(ctx).await?,
+        &call,
+        track,
+        metadata.clone(),
+        Some(origin),
+    )
+    .await;
+
+    Ok(metadata)
+}
+
+/// Resolves and enqueues a source without an interaction, like a scheduled one
+pub async fn enqueue_track_for(
+    ctx: &serenity::client::Context,
+    guild_id: GuildId,
+    call: Arc<Mutex<Call>>,
+    source: &str,
+    requested_by: UserId,
+    origin: EnqueueOrigin,
+) -> Result<Arc<TrackMetadata>, CommandError> {
+    ensure_capacity(ctx, &call).await?;
+    let (track, metadata) = resolve_track_for(ctx, guild_id, source, requested_by).await?;
+    add_to_queue(
+        &queue_context_for(ctx, guild_id).await,
+        &call,
+        track,
+        metadata.clone(),
+        Some(origin),
+    )
+    .await;
+
+    Ok(metadata)
+}
+
+/// Enqueues a track that was resolved earlier, like a staged one
+pub async fn enqueue_resolved(
+    ctx: CommandContext<'_>,
+    call: Arc<Mutex<Call>>,
+    metadata: Arc<TrackMetadata>,
+    origin: EnqueueOrigin,
+) -> Result<(), CommandError> {
+    ensure_capacity(ctx.serenity_context(), &call).await?;
+    let track = YtDlpInput::new(
+        get_http_client(ctx.serenity_context()).await,
+        get_ytdlp_config(ctx.serenity_context()).await,
+        metadata.source_url.to_string(),
+    );
+    add_to_queue(
+        &queue_context(ctx).await?,
+        &call,
+        track,
+        metadata,
+        Some(origin),
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Loads the metadata of a source for the author of the command, which also validates it
+pub async fn resolve_track(
+    ctx: CommandContext<'_>,
+    source: &str,
+) -> Result<(YtDlpInput, Arc<TrackMetadata>), CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    resolve_track_for(ctx.serenity_context(), guild_id, source, ctx.author().id).await
+}
+
+/// Loads the metadata of a source for any requester, also without an interaction
+pub async fn resolve_track_for(
+    ctx: &serenity::client::Context,
+    guild_id: GuildId,
+    source: &str,
+    requested_by: UserId,
+) -> Result<(YtDlpInput, Arc<TrackMetadata>), CommandError> {
+    let http_client = get_http_client(ctx).await;
+    let youtube_client = get_youtube_client(ctx).await;
+    let telemetry = get_resolution_telemetry(ctx).await;
+
+    let (url, path) = match Url::parse(source) {
+        Ok(url) if telemetry.was_offered(requested_by, source) => (Some(url), AutocompleteUrl),
+        Ok(url) if get_yt_id_from_url(url.as_ref()).video_id.is_some() => (Some(url), UrlFastpath),
+        Ok(url) => (Some(url), UrlYtDlp),
+        // The search of the YouTube client ranks better than the one of yt-dlp
+        Err(_) => (search_top_video(&youtube_client, source).await, RawSearch),
+    };
+    // Extract youtube video id from url
+    let youtube_id = url
+        .as_ref()
+        .and_then(|url| get_yt_id_from_url(url.as_ref()).video_id);
+
+    let ytdlp_config = get_ytdlp_config(ctx).await;
+    let max_file_size = ytdlp_config.max_file_size;
+    let mut track = match &url {
+        Some(url) => YtDlpInput::new(http_client.clone(), ytdlp_config, url.to_string()),
+        // This only available as a fallback for when autocomplete and search fail completely
+        None => YtDlpInput::new_search(http_client.clone(), ytdlp_config, source.to_owned()),
+    };
+
+    let mut metadata = match youtube_id {
+        Some(video_id) => TrackMetadata::from_with_request(
+            youtube_client
+                .get_video(&video_id)
+                .await
+                .map(TrackMetadata::from)
+                // Keep the url, so the entry can still be refreshed with /refreshmeta
+                .unwrap_or_else(|_| {
+                    TrackMetadata::unresolved(url.as_ref().map_or(source, Url::as_str))
+                }),
+            requested_by,
+        ),
+        None => {
+            // Direct links are checked before a process is spawned for them
+            if let Some(url) = url.as_ref().filter(|url| {
+                matches!(
+                    TrackSource::from_url(url),
+                    TrackSource::Stream | TrackSource::Attachment
+                )
+            }) {
+                check_direct_link(&http_client, url, max_file_size).await?;
+            }
+            let _permit = get_ytdlp_permits(ctx)
+                .await
+                .acquire_owned()
+                .await
+                .expect("The semaphore is never closed");
+
+            let aux_metadata = track
+                .metadata()
+                .await
+                .map_err(|e| CommandError::YtDlp(e.failure()))?;
+
+            let mut metadata = TrackMetadata::from_with_request(aux_metadata, requested_by);
+            // Direct files carry loudness tags, sites found by yt-dlp are streamed without them
+            if let (Some(url), TrackSource::Stream | TrackSource::Attachment) =
+                (&url, metadata.source)
+            {
+                metadata.gain_db = probe_gain(url.as_str()).await.unwrap_or_else(|e| {
+                    debug!("No loudness tags for {url}: {e}");
+                    None
+                });
+            }
+            metadata
+        }
+    };
+    metadata.resolution = Some(path);
+
+    if get_blocklist(ctx)
+        .await
+        .blocking(guild_id, &metadata)
+        .is_some()
+    {
+        return Err(CommandError::Blocked {
+            title: metadata.title,
+        });
+    }
+    // The global queue limit is checked separately and still applies during a party
+    let verdict = match get_party_modes(ctx).await.is_active(guild_id) {
+        true => DurationVerdict::Allowed,
+        false => get_guild_settings(ctx)
+            .await
+            .get(guild_id)
+            .duration_verdict(metadata.duration),
+    };
+    match verdict {
+        DurationVerdict::Allowed => {}
+        DurationVerdict::OverSoftLimit { .. } => metadata.over_soft_limit = true,
+        DurationVerdict::Rejected { limit } => {
+            return Err(CommandError::TrackTooLong {
+                title: metadata.title,
+                limit,
+            })
+        }
+    }
+    let metadata = Arc::new(metadata);
+
+    telemetry.record(path);
+    Ok((track, metadata))
+}
+
+/// Link of the best video search result, if any provider could search
+async fn search_top_video(youtube_client: &YoutubeClient, query: &str) -> Option<Url> {
+    match youtube_client
+        .search(query, YtSearchFilter::Videos, 1)
+        .await
+    {
+        Ok(results) => results.first().map(YtResource::get_yt_url),
+        Err(e) => {
+            warn!("Search for `{query}` failed, falling back to yt-dlp: {e}");
+            None
+        }
+    }
+}
+
+/// Everything needed to add tracks to the queue of a guild, also from track event handlers
+#[derive(Clone)]
+struct QueueContext {
+    guild_id: GuildId,
+    http_client: HttpClient,
+    ytdlp_config: Arc<YtDlpConfig>,
+    modes: Arc<PlaybackModes>,
+    events: Arc<PlaybackEventBus>,
+    stats: Arc<StatsStore>,
+    history: Arc<PlayHistory>,
+    audit_log: Arc<AuditLog>,
+    start_latency: Arc<StartLatency>,
+    end_markers: Arc<EndMarkers>,
+    autoplay: Arc<Autoplay>,
+    guild_settings: Arc<GuildSettingsStore>,
+    tts: Option<Arc<TtsConfig>>,
+    error_rates: Arc<ErrorRates>,
+}
+
+async fn queue_context(ctx: CommandContext<'_>) -> Result<QueueContext, CommandError> {
+    let guild_id = ctx.guild_id().ok_or(CommandError::NotInGuild)?;
+    Ok(queue_context_for(ctx.serenity_context(), guild_id).await)
+}
+
+async fn queue_context_for(ctx: &serenity::client::Context, guild_id: GuildId) -> QueueContext {
+    QueueContext {
+        guild_id,
+        http_client: get_http_client(ctx).await,
+        ytdlp_config: get_ytdlp_config(ctx).await,
+        modes: get_playback_modes(ctx).await,
+        events: get_playback_events(ctx).await,
+        stats: get_stats(ctx).await,
+        history: get_history(ctx).await,
+        audit_log: get_audit_log(ctx).await,
+        start_latency: get_start_latency(ctx).await,
+        end_markers: get_end_markers(ctx).await,
+        autoplay: get_autoplay(ctx).await,
+        guild_settings: get_guild_settings(ctx).await,
+        tts: get_tts(ctx).await,
+        error_rates: get_error_rates(ctx).await,
+    }
+}
+
+/// Time before the end of a track at which the next one is loaded
+const PRELOAD_LEAD: Duration = Duration::from_secs(5);
+
+/// Loads a track that was moved right behind the current one. The preload of the current track
+/// loads whatever is second when it fires, this covers the moves after it fired.
+pub async fn preload_new_next(call: &Mutex<Call>, next: &TrackHandle) {
+    let Some(current) = call.lock().await.queue().current() else {
+        return;
+    };
+    let Ok(info) = current.get_info().await else {
+        return;
+    };
+    let remaining = get_metadata(&current)
+        .await
+        .duration
+        .saturating_sub(info.position);
+    if remaining <= PRELOAD_LEAD {
+        drop(next.make_playable());
+    }
+}
+
+/// Enqueues a track with its metadata and event handlers, respecting fair mode. Every track added
+/// with an origin is written to the audit log, only loop-queue re-adds have none.
+async fn add_to_queue(
+    queue_ctx: &QueueContext,
+    call: &Arc<Mutex<Call>>,
+    input: YtDlpInput,
+    metadata: Arc<TrackMetadata>,
+    origin: Option<EnqueueOrigin>,
+) -> TrackHandle {
+    if let Some(origin) = origin {
+        queue_ctx.audit_log.record(
+            queue_ctx.guild_id,
+            metadata.requested_by,
+            AuditAction::Enqueued {
+                origin,
+                title: metadata.title.clone(),
+                url: metadata.source_url.to_string(),
+                original_title: metadata.original_title.clone(),
+            },
+        );
+    }
+
+    let mut call_guard = call.lock().await;
+    let track_handle = call_guard.enqueue_with_preload(
+        input.into(),
+        Some(metadata.duration.saturating_sub(PRELOAD_LEAD)),
+    );
+
+    track_handle
+        .typemap()
+        .write()
+        .await
+        .insert::<TrackMetadataKey>(metadata.clone());
+
+    _ = track_handle.add_event(
+        Event::Track(TrackEvent::Play),
+        TrackStartHandler {
+            queue_ctx: queue_ctx.clone(),
+            call: Arc::downgrade(call),
+        },
+    );
+    _ = track_handle.add_event(
+        Event::Track(TrackEvent::Playable),
+        TrackPlayableHandler {
+            start_latency: queue_ctx.start_latency.clone(),
+            error_rates: queue_ctx.error_rates.clone(),
+        },
+    );
+    _ = track_handle.add_event(
+        Event::Track(TrackEvent::Error),
+        TrackErrorHandler {
+            error_rates: queue_ctx.error_rates.clone(),
+        },
+    );
+    _ = track_handle.add_event(
+        Event::Track(TrackEvent::End),
+        TrackEndHandler {
+            queue_ctx: queue_ctx.clone(),
+            call: Arc::downgrade(call),
+        },
+    );
+
+    if queue_ctx.modes.get(queue_ctx.guild_id).fair {
+        let queue = call_guard.queue();
+        let handles = queue.current_queue();
+        let (_new, existing) = handles.split_last().expect("The track was just added");
+        let requesters = join_all(
+            existing
+                .iter()
+                .map(|t| async move { get_metadata(t).await.requested_by }),
+        )
+        .await;
+
+        let position = fair_insert_position(&requesters, &metadata.requested_by);
+        queue.modify_queue(|raw_queue| {
+            // Only move the track if nobody modified the queue in the meantime
+            if raw_queue.len() == handles.len()
+                && raw_queue
+                    .back()
+                    .is_some_and(|t| t.uuid() == track_handle.uuid())
+            {
+                queue_ops::move_last_to(raw_queue, position);
+            }
+        });
+    }
+
+    queue_ctx
+        .events
+        .publish(queue_ctx.guild_id, PlaybackEvent::QueueChanged);
+    track_handle
+}
+
+/// Replaces a stalled track with a new input of the same source that continues at `position`.
+/// Returns the id of the replacement.
+pub async fn restart_track(
+    ctx: &serenity::client::Context,
+    guild_id: GuildId,
+    call: &Arc<Mutex<Call>>,
+    stalled: &TrackHandle,
+    position: Duration,
+) -> Uuid {
+    let queue_ctx = queue_context_for(ctx, guild_id).await;
+    let metadata = get_metadata(stalled).await;
+    let input = YtDlpInput::new(
+        queue_ctx.http_client.clone(),
+        queue_ctx.ytdlp_config.clone(),
+        metadata.source_url.to_string(),
+    );
+    let replacement = add_to_queue(&queue_ctx, call, input, metadata, None).await;
+    // Loads the input right away, so it is ready when the stalled track is stopped
+    _ = replacement.seek(position);
+    call.lock().await.queue().modify_queue(|raw_queue| {
+        if let Some(index) = raw_queue
+            .iter()
+            .position(|track| track.uuid() == replacement.uuid())
+        {
+            queue_ops::move_to_next(raw_queue, index);
+        }
+    });
+
+    queue_ctx
+        .end_markers
+        .mark(guild_id, stalled.uuid(), EndReason::Errored);
+    _ = stalled.stop();
+    replacement.uuid()
+}
+
+/// Metadata is read from the track handle on every event, so entries refreshed with /refreshmeta
+/// are recorded with their new data. Entries flagged by the validator are skipped before their
+/// turn comes.
+struct TrackStartHandler {
+    queue_ctx: QueueContext,
+    call: Weak<Mutex<Call>>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for TrackStartHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(&[(_, handle), ..]) = ctx else {
+            return None;
+        };
+        let metadata = get_metadata(handle).await;
+        // Only reached if the entry was moved to the front after it was flagged
+        if metadata.playability() == Playability::Unplayable {
+            self.queue_ctx.end_markers.mark(
+                self.queue_ctx.guild_id,
+                handle.uuid(),
+                EndReason::Skipped,
+            );
+            _ = handle.stop();
+            return None;
+        }
+        if let Some(call) = self.call.upgrade() {
+            if validator::skip_flagged(&call, self.queue_ctx.guild_id).await > 0 {
+                self.queue_ctx
+                    .events
+                    .publish(self.queue_ctx.guild_id, PlaybackEvent::QueueChanged);
+            }
+        }
+        if metadata.gain_db.is_some() {
+            _ = handle.set_volume(gain_factor(metadata.gain_db));
+        }
+
+        self.queue_ctx
+            .modes
+            .apply(self.queue_ctx.guild_id, ModeChange::TrackStarted);
+        self.queue_ctx
+            .events
+            .publish(self.queue_ctx.guild_id, PlaybackEvent::TrackStarted);
+        self.queue_ctx.history.record(
+            self.queue_ctx.guild_id,
+            handle.uuid(),
+            &metadata.title,
+            metadata.source_url.as_str(),
+        );
+        None
+    }
+}
+
+/// Ends the start latency measurement, playable is when the audio actually starts, while play
+/// is already reached before the input is extracted. Also counts the extraction for the error
+/// rates.
+struct TrackPlayableHandler {
+    start_latency: Arc<StartLatency>,
+    error_rates: Arc<ErrorRates>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for TrackPlayableHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(&[(_, handle), ..]) = ctx else {
+            return None;
+        };
+        self.start_latency.observe(handle.uuid(), Instant::now());
+        self.error_rates.record(ErrorSource::YtDlp, true);
+        None
+    }
+}
+
+/// Counts tracks whose input could not be created for the error rates. Errors while decoding
+/// are left to the driver diagnostics.
+struct TrackErrorHandler {
+    error_rates: Arc<ErrorRates>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for TrackErrorHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(&[(state, _), ..]) = ctx else {
+            return None;
+        };
+        if let PlayMode::Errored(PlayError::Create(_)) = state.playing {
+            self.error_rates.record(ErrorSource::YtDlp, false);
+        }
+        None
+    }
+}
+
+/// Publishes why a track ended, records play statistics and puts finished tracks back at the end
+/// of the queue while loop-queue is active
+struct TrackEndHandler {
+    queue_ctx: QueueContext,
+    call: Weak<Mutex<Call>>,
+}
+
+#[async_trait]
+impl VoiceEventHandler for TrackEndHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let EventContext::Track(tracks) = ctx else {
+            return None;
+        };
+        let (state, handle) = tracks.first()?;
+        let metadata = get_metadata(handle).await;
+        self.queue_ctx.start_latency.abandon(handle.uuid());
+
+        let marker = self
+            .queue_ctx
+            .end_markers
+            .take(self.queue_ctx.guild_id, handle.uuid());
+        let reason = EndReason::classify(marker.map(|marker| marker.reason), &state.playing);
+        self.queue_ctx.events.publish(
+            self.queue_ctx.guild_id,
+            PlaybackEvent::TrackEnded { reason },
+        );
+        let error = match (&state.playing, reason) {
+            (PlayMode::Errored(e), _) => Some(describe_play_error(e)),
+            // Only the stall watchdog marks errors itself
+            (_, EndReason::Errored) => Some("Wiedergabe hing fest"),
+            _ => None,
+        };
+        self.queue_ctx.history.finish(
+            self.queue_ctx.guild_id,
+            handle.uuid(),
+            TrackEnd {
+                reason,
+                actor: marker.and_then(|marker| marker.actor),
+                position: state.position,
+                error,
+            },
+        );
+
+        // Tracks that never started were removed together with the queue and are not counted
+        if let Some((state, _)) = tracks.iter().find(|(state, _)| !state.play_time.is_zero()) {
+            if let Some(outcome) = PlayOutcome::classify(state.position, metadata.duration, reason)
+            {
+                self.queue_ctx.stats.record(
+                    self.queue_ctx.guild_id,
+                    &metadata.source_url,
+                    &metadata.title,
+                    outcome,
+                );
+            }
+        }
+
+        let after = self
+            .queue_ctx
+            .modes
+            .get(self.queue_ctx.guild_id)
+            .after_track(reason);
+
+        if let (AfterTrack::Requeue, Some(call)) = (after, self.call.upgrade()) {
+            let input = YtDlpInput::new(
+                self.queue_ctx.http_client.clone(),
+                self.queue_ctx.ytdlp_config.clone(),
+                metadata.source_url.to_string(),
+            );
+            let metadata = Arc::new((*metadata).clone());
+            add_to_queue(&self.queue_ctx, &call, input, metadata, None).await;
+        }
+
+        if let (AfterTrack::Autoplay, Some(call)) = (after, self.call.upgrade()) {
+            let ran_out = call
+                .lock()
+                .await
+                .queue()
+                .current_queue()
+                .iter()
+                .all(|track| track.uuid() == handle.uuid());
+            if let Some(suggestion) = ran_out
+                .then(|| self.queue_ctx.autoplay.take(self.queue_ctx.guild_id))
+                .flatten()
+            {
+                let input = YtDlpInput::new(
+                    self.queue_ctx.http_client.clone(),
+                    self.queue_ctx.ytdlp_config.clone(),
+                    suggestion.metadata.source_url.to_string(),
+                );
+                add_to_queue(
+                    &self.queue_ctx,
+                    &call,
+                    input,
+                    suggestion.metadata,
+                    Some(EnqueueOrigin::Autoplay),
+                )
+                .await;
+            }
+        }
+
+        // Only tracks that ran out are followed by an announcement, a skip wants the next now
+        if let (EndReason::Finished, Some(tts), Some(call)) =
+            (reason, &self.queue_ctx.tts, self.call.upgrade())
+        {
+            let settings = self.queue_ctx.guild_settings.get(self.queue_ctx.guild_id);
+            let next = call
+                .lock()
+                .await
+                .queue()
+                .current_queue()
+                .into_iter()
+                .find(|track| track.uuid() != handle.uuid());
+            if let (true, Some(next)) = (settings.tts_announcements, next) {
+                let text = announcement_text(
+                    &*get_metadata(&next).await,
+                    settings.locale.unwrap_or_default(),
+                );
+                let tts = tts.clone();
+                // Synthesizing takes a moment, other events of the call should not wait for it
+                tokio::spawn(async move { announce_next(&tts, &call, next, text).await });
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::end_reason::EndMarker;
+
+    fn ids(url: &str) -> (Option<String>, Option<String>, Option<usize>) {
+        let ids = get_yt_id_from_url(url);
+        (ids.video_id, ids.playlist_id, ids.playlist_index)
+    }
+
+    fn some(id: &str) -> Option<String> {
+        Some(id.to_owned())
+    }
+
+    #[test]
+    fn video_links() {
+        for url in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com/watch?v=dQw4w9WgXcQ&t=42",
+            "https://m.youtube.com/watch?feature=share&v=dQw4w9WgXcQ",
+            "https://music.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ",
+            "https://youtu.be/dQw4w9WgXcQ?si=tracking",
+        ] {
+            assert_eq!(ids(url), (some("dQw4w9WgXcQ"), None, None), "{url}");
+        }
+    }
+
+    #[test]
+    fn playlist_links() {
+        assert_eq!(
+            ids("https://www.youtube.com/playlist?list=PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"),
+            (None, some("PLrAXtmErZgOeiKm4sgNOknGvNjby9efdf"), None)
+        );
+        assert_eq!(
+            ids("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLabc_-123&index=7"),
+            (some("dQw4w9WgXcQ"), some("PLabc_-123"), Some(7))
+        );
+        assert_eq!(
+            ids("https://youtu.be/dQw4w9WgXcQ?list=PLabc&index=2"),
+            (some("dQw4w9WgXcQ"), some("PLabc"), Some(2))
+        );
+        // An index that is no number is ignored, the link still works
+        assert_eq!(
+            ids("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PLabc&index=first"),
+            (some("dQw4w9WgXcQ"), some("PLabc"), None)
+        );
+    }
+
+    #[test]
+    fn other_hosts_and_search_terms_have_no_ids() {
+        for url in [
+            "never gonna give you up",
+            "https://soundcloud.com/artist/track?v=dQw4w9WgXcQ",
+            "https://notyoutube.com/watch?v=dQw4w9WgXcQ",
+            "https://youtube.com.evil.example/watch?v=dQw4w9WgXcQ",
+            "",
+        ] {
+            assert_eq!(ids(url), (None, None, None), "{url}");
+        }
+    }
+
+    #[test]
+    fn ids_with_foreign_characters_are_dropped() {
+        assert_eq!(
+            ids("https://www.youtube.com/watch?v=abc%20--exec"),
+            (None, None, None)
+        );
+        assert_eq!(
+            ids("https://www.youtube.com/watch?v=abc;rm&list=PL$(id)"),
+            (None, None, None)
+        );
+        let long_id = "a".repeat(MAX_YT_ID_LEN + 1);
+        assert_eq!(
+            ids(&format!("https://www.youtube.com/watch?v={long_id}")),
+            (None, None, None)
+        );
+    }
+
+    #[test]
+    fn overlong_links_are_not_parsed() {
+        let url = format!(
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&pad={}",
+            "x".repeat(MAX_URL_LEN)
+        );
+        assert_eq!(ids(&url), (None, None, None));
+    }
+
+    #[test]
+    fn short_links_without_a_video() {
+        for url in [
+            "https://youtu.be",
+            "https://youtu.be/",
+            "https://youtu.be//dQw4w9WgXcQ",
+            "https://youtu.be/?v=dQw4w9WgXcQ",
+        ] {
+            assert_eq!(ids(url), (None, None, None), "{url}");
+        }
+        assert_eq!(
+            ids("https://youtu.be/?list=PLabc"),
+            (None, some("PLabc"), None)
+        );
+    }
+
+    #[test]
+    fn generated_links_never_panic() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let parts = [
+            "https://",
+            "youtu.be",
+            "youtube.com",
+            "www.",
+            "/",
+            "watch",
+            "?",
+            "&",
+            "v=",
+            "list=",
+            "index=",
+            "%",
+            "%2",
+            "%ZZ",
+            "#",
+            ":",
+            "@",
+            "ü",
+            "👍",
+            "-1",
+            "99999999999999999999",
+            "dQw4w9WgXcQ",
+            "..",
+            "\\",
+            " ",
+        ];
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..5000 {
+            let url = (0..rng.gen_range(0..12))
+                .map(|_| parts[rng.gen_range(0..parts.len())])
+                .collect::<String>();
+            let ids = get_yt_id_from_url(&url);
+            for id in [&ids.video_id, &ids.playlist_id].into_iter().flatten() {
+                assert!(is_valid_yt_id(id), "{url:?} gave {id:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn capacity_is_what_is_left_under_the_limit() {
+        assert_eq!(QueueCapacity::new(0, 1000).free, 1000);
+        assert_eq!(QueueCapacity::new(990, 1000).free, 10);
+        // A queue over a limit that was lowered since has no room, not negative room
+        assert_eq!(QueueCapacity::new(1200, 1000).free, 0);
+    }
+
+    #[test]
+    fn loads_are_cut_to_the_free_entries() {
+        let capacity = QueueCapacity::new(990, 1000);
+        assert_eq!(capacity.fit(5), 5);
+        assert_eq!(capacity.fit(10), 10);
+        assert_eq!(capacity.fit(50), 10);
+        assert_eq!(capacity.fit(0), 0);
+
+        // Only a load that had to be cut is mentioned
+        assert_eq!(capacity.notice(10), None);
+        assert_eq!(
+            capacity.notice(50).as_deref(),
+            Some("40 Lieder wurden nicht hinzugefügt, die Warteschlange kann höchstens 1000 Einträge haben")
+        );
+    }
+
+    #[test]
+    fn full_queue_is_refused_with_its_limit() {
+        assert!(QueueCapacity::new(999, 1000).ensure_free().is_ok());
+        for queued in [1000, 1200] {
+            let capacity = QueueCapacity::new(queued, 1000);
+            assert!(matches!(
+                capacity.ensure_free(),
+                Err(CommandError::QueueFull { limit: 1000 })
+            ));
+            assert_eq!(capacity.fit(3), 0);
+            assert!(capacity.notice(3).unwrap().starts_with("3 Lieder"));
+        }
+        assert_eq!(
+            QueueCapacity::full_message(1000),
+            "Die Warteschlange ist voll, sie kann höchstens 1000 Einträge haben"
+        );
+    }
+
+    #[test]
+    fn text_at_the_limit_is_kept() {
+        assert!(matches!(truncate_chars("Hallo", 5), Cow::Borrowed("Hallo")));
+        assert_eq!(truncate_chars("Hallo", 4), "Hal…");
+        assert_eq!(truncate_chars("Hallo", 1), "…");
+        assert_eq!(truncate_chars("", 0), "");
+    }
+
+    #[test]
+    fn umlauts_count_as_one_character() {
+        // 10 characters in 13 bytes
+        assert_eq!(truncate_chars("Größenwahn", 10), "Größenwahn");
+        assert_eq!(truncate_chars("Größenwahn", 5), "Größ…");
+        assert_eq!(truncate_chars("Über Äpfel", 6), "Über…");
+    }
+
+    #[test]
+    fn emoji_are_not_split_from_their_modifiers() {
+        // Thumbs up with a skin tone is two characters
+        assert_eq!(truncate_chars("Hi 👍🏽 du", 5), "Hi…");
+        assert_eq!(truncate_chars("Hi 👍🏽 du", 6), "Hi 👍🏽…");
+        // A family joined with zero width joiners is cut as a whole
+        assert_eq!(truncate_chars("👨\u{200D}👩\u{200D}👧 ok", 4), "…");
+        assert_eq!(
+            truncate_chars("❤\u{FE0F} Liebe", 2),
+            "…",
+            "The variation selector belongs to the heart"
+        );
+    }
+
+    #[test]
+    fn combining_marks_stay_with_their_base() {
+        // The accent of the decomposed é is its own character
+        assert_eq!(truncate_chars("Cafe\u{0301} au lait", 5), "Caf…");
+        assert_eq!(truncate_chars("Cafe\u{0301} au lait", 6), "Cafe\u{0301}…");
+    }
+
+    #[test]
+    fn truncated_text_fits_the_limit() {
+        let text = "Größe 👍🏽 Cafe\u{0301} 👨\u{200D}👩\u{200D}👧 Ende";
+        for max_chars in 1..=text.chars().count() + 1 {
+            let truncated = truncate_chars(text, max_chars);
+            assert!(truncated.chars().count() <= max_chars, "{truncated:?}");
+            let kept = truncated.trim_end_matches(ELLIPSIS);
+            assert!(text.starts_with(kept), "{truncated:?}");
+        }
+    }
+
+    #[test]
+    fn cut_before_moves_back_to_a_whole_character() {
+        let text = "a👍🏽b";
+        // The byte index of the skin tone would split the emoji
+        let tone = text.find('🏽').unwrap();
+        assert_eq!(cut_before(text, tone), "a…");
+        assert_eq!(cut_before(text, text.len() - 1), "a👍🏽…");
+        assert_eq!(cut_before(text, 0), "…");
+        // Whitespace before the cut is dropped
+        assert_eq!(cut_before("ab  cd", 4), "ab…");
+    }
+
+    #[test]
+    fn pages_of_a_list() {
+        assert_eq!(page_count(0), 1);
+        assert_eq!(page_count(1), 1);
+        assert_eq!(page_count(QUEUE_PAGE_SIZE), 1);
+        assert_eq!(page_count(QUEUE_PAGE_SIZE + 1), 2);
+        assert_eq!(page_count(3 * QUEUE_PAGE_SIZE), 3);
+    }
+
+    #[test]
+    fn pages_are_counted_from_one() {
+        assert_eq!(clamp_page(1, 25), (0, false));
+        assert_eq!(clamp_page(3, 25), (2, false));
+    }
+
+    #[test]
+    fn pages_out_of_range_are_clamped() {
+        assert_eq!(clamp_page(0, 25), (0, true));
+        assert_eq!(clamp_page(4, 25), (2, true));
+        assert_eq!(clamp_page(usize::MAX, 25), (2, true));
+        // An empty list still shows its only page
+        assert_eq!(clamp_page(1, 0), (0, false));
+        assert_eq!(clamp_page(2, 0), (0, true));
+    }
+
+    const ACTOR: Option<UserId> = Some(UserId::new(5));
+
+    /// A queue of silent tracks. The driver has no connection, so nothing plays or ends.
+    async fn idle_queue(driver: &mut songbird::Driver, len: usize) -> TrackQueue {
+        let queue = TrackQueue::new();
+        for _ in 0..len {
+            queue
+                .add_source(songbird::input::Input::from(vec![0u8; 16]), driver)
+                .await;
+        }
+        queue
+    }
+
+    /// The reasons the tracks of the queue would end with once the driver stops them
+    fn reasons(markers: &EndMarkers, tracks: &[TrackHandle]) -> Vec<EndReason> {
+        tracks
+            .iter()
+            .map(|track| {
+                let marker = markers.take(GuildId::new(1), track.uuid());
+                EndReason::classify(marker.map(|m| m.reason), &PlayMode::Stop)
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn skip_marks_only_the_current_track() {
+        let mut driver = songbird::Driver::default();
+        let queue = idle_queue(&mut driver, 3).await;
+        let tracks = queue.current_queue();
+        let markers = EndMarkers::default();
+
+        let skipped = skip_current(&queue, GuildId::new(1), &markers, ACTOR, false);
+        assert_eq!(skipped.map(|t| t.uuid()), Some(tracks[0].uuid()));
+        assert_eq!(
+            reasons(&markers, &tracks),
+            [EndReason::Skipped, EndReason::Stopped, EndReason::Stopped]
+        );
+
+        let empty = TrackQueue::new();
+        assert!(skip_current(&empty, GuildId::new(1), &markers, ACTOR, true).is_none());
+    }
+
+    #[tokio::test]
+    async fn clear_marks_the_upcoming_tracks_as_stopped() {
+        let mut driver = songbird::Driver::default();
+        let queue = idle_queue(&mut driver, 3).await;
+        let tracks = queue.current_queue();
+        let markers = EndMarkers::default();
+
+        let removed = clear_upcoming(&queue, GuildId::new(1), &markers, ACTOR);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(queue.len(), 1);
+        let marked = tracks
+            .iter()
+            .map(|track| markers.take(GuildId::new(1), track.uuid()))
+            .collect::<Vec<_>>();
+        assert_eq!(marked[0], None);
+        for marker in &marked[1..] {
+            assert_eq!(
+                *marker,
+                Some(EndMarker {
+                    reason: EndReason::Stopped,
+                    actor: ACTOR
+                })
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn stop_marks_every_track_as_stopped() {
+        let mut driver = songbird::Driver::default();
+        let markers = EndMarkers::default();
+
+        let queue = idle_queue(&mut driver, 3).await;
+        let tracks = queue.current_queue();
+        let event = stop_queue(&queue, GuildId::new(1), &markers, ACTOR, false);
+        assert_eq!(event, PlaybackEvent::Stopped);
+        assert_eq!(reasons(&markers, &tracks), [EndReason::Stopped; 3]);
+
+        // Finishing the current track leaves it unmarked, so it ends as finished
+        let queue = idle_queue(&mut driver, 3).await;
+        let tracks = queue.current_queue();
+        let event = stop_queue(&queue, GuildId::new(1), &markers, ACTOR, true);
+        assert_eq!(event, PlaybackEvent::QueueChanged);
+        assert!(markers.take(GuildId::new(1), tracks[0].uuid()).is_none());
+        assert_eq!(
+            reasons(&markers, &tracks[1..]),
+            [EndReason::Stopped, EndReason::Stopped]
+        );
+    }
+
+    /// A call with `count` dead tracks in its queue, whose driver is already gone
+    async fn call_with_dead_tracks(count: usize) -> Mutex<Call> {
+        let call = Call::standalone(GuildId::new(1), UserId::new(2));
+        let mut driver = songbird::Driver::default();
+        for _ in 0..count {
+            call.queue()
+                .add_source(songbird::input::Input::from(vec![0u8; 16]), &mut driver)
+                .await;
+        }
+        drop(driver);
+        // The handles only fail once the mixer of the driver has shut down
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        Mutex::new(call)
+    }
+
+    #[tokio::test]
+    async fn dead_tracks_are_skipped_once_per_retry() {
+        let call = call_with_dead_tracks(3).await;
+        let first = call.lock().await.queue().current_queue();
+
+        let result = live_current_track(&call).await;
+        assert!(matches!(result, Err(CommandError::DeadTrack)));
+        // Both attempts dropped the dead track in front, nothing else
+        let left = call.lock().await.queue().current_queue();
+        assert_eq!(left.len(), 1);
+        assert_eq!(left[0].uuid(), first[2].uuid());
+    }
+
+    #[tokio::test]
+    async fn running_out_of_dead_tracks_is_an_empty_queue() {
+        let call = call_with_dead_tracks(1).await;
+        let result = live_current_track(&call).await;
+        assert!(matches!(result, Err(CommandError::QueueEmpty)));
+        assert!(call.lock().await.queue().is_empty());
+
+        let call = call_with_dead_tracks(0).await;
+        assert!(matches!(
+            live_current_track(&call).await,
+            Err(CommandError::QueueEmpty)
+        ));
+    }
+}
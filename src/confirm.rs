@@ -0,0 +1,185 @@
+use poise::CreateReply;
+use serenity::all::{ButtonStyle, ComponentInteractionCollector};
+use serenity::builder::{
+    CreateActionRow, CreateButton, CreateInteractionResponse, CreateInteractionResponseMessage,
+};
+use std::time::Duration;
+
+use crate::plain_text::EmbedMode;
+use crate::response::BotResponse;
+use crate::{CommandContext, ConfirmThresholdKey};
+
+/// Unanswered prompts count as cancelled after this time
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Whether removing `removed` tracks is destructive enough to ask first
+pub fn needs_confirmation(removed: usize, threshold: usize) -> bool {
+    removed > threshold
+}
+
+/// Asks the invoker to confirm an operation that removes `removed` tracks, if there are more than
+/// the configured threshold. `command` is the name of the command, like `/stop`. Returns whether
+/// the operation may proceed.
+pub async fn confirm_removal(
+    ctx: CommandContext<'_>,
+    removed: usize,
+    command: &str,
+) -> Result<bool, serenity::Error> {
+    let threshold = *ctx
+        .serenity_context()
+        .data
+        .read()
+        .await
+        .get::<ConfirmThresholdKey>()
+        .expect("Guaranteed to exist in the typemap");
+    if !needs_confirmation(removed, threshold) {
+        return Ok(true);
+    }
+
+    let prompt = BotResponse::warning("Bestätigung").description(removal_prompt(command, removed));
+    confirm(ctx, prompt).await
+}
+
+fn removal_prompt(command: &str, removed: usize) -> String {
+    format!("{command} entfernt {removed} Tracks aus der Warteschlange. Fortfahren?")
+}
+
+/// Shows the prompt with buttons to confirm or cancel and returns whether it was confirmed.
+///
+/// Only the invoker can answer. The prompt is updated with the outcome, so commands respond with
+/// a new message afterwards.
+pub async fn confirm(
+    ctx: CommandContext<'_>,
+    prompt: BotResponse,
+) -> Result<bool, serenity::Error> {
+    let id_prefix = ctx.id().to_string();
+    let buttons = vec![CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("{id_prefix}confirm"))
+            .label("Bestätigen")
+            .style(ButtonStyle::Danger),
+        CreateButton::new(format!("{id_prefix}cancel"))
+            .label("Abbrechen")
+            .style(ButtonStyle::Secondary),
+    ])];
+
+    let embed_mode = EmbedMode::of_reply(ctx, true).await;
+    let reply = ctx
+        .send(
+            embed_mode
+                .reply(CreateReply::default(), prompt.embed(ctx))
+                .components(buttons)
+                .ephemeral(true),
+        )
+        .await?;
+
+    let author_id = ctx.author().id;
+    let press = ComponentInteractionCollector::new(ctx.serenity_context())
+        .filter({
+            let id_prefix = id_prefix.clone();
+            move |press| press.data.custom_id.starts_with(&id_prefix) && press.user.id == author_id
+        })
+        .timeout(CONFIRM_TIMEOUT)
+        .await;
+
+    let confirmed = press
+        .as_ref()
+        .is_some_and(|press| press.data.custom_id[id_prefix.len()..] == *"confirm");
+    let outcome = confirm_outcome(press.is_some(), confirmed).embed(ctx);
+
+    match press {
+        Some(press) => {
+            press
+                .create_response(
+                    ctx,
+                    CreateInteractionResponse::UpdateMessage(
+                        embed_mode
+                            .message(CreateInteractionResponseMessage::new(), outcome)
+                            .components(vec![]),
+                    ),
+                )
+                .await?
+        }
+        None => {
+            reply
+                .edit(
+                    ctx,
+                    embed_mode
+                        .reply(CreateReply::default(), outcome)
+                        .components(vec![]),
+                )
+                .await?
+        }
+    }
+
+    Ok(confirmed)
+}
+
+/// The prompt after it was answered or timed out
+fn confirm_outcome(answered: bool, confirmed: bool) -> BotResponse {
+    match (answered, confirmed) {
+        (_, true) => BotResponse::success("Bestätigung").description("Bestätigt"),
+        (true, false) => BotResponse::warning("Bestätigung").description("Abgebrochen"),
+        (false, false) => {
+            BotResponse::warning("Bestätigung").description("Abgebrochen, da keine Antwort kam")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use serenity::all::Timestamp;
+
+    #[test]
+    fn asks_only_above_the_threshold() {
+        assert!(!needs_confirmation(0, 10));
+        assert!(!needs_confirmation(9, 10));
+        assert!(!needs_confirmation(10, 10));
+        assert!(needs_confirmation(11, 10));
+    }
+
+    #[test]
+    fn threshold_zero_asks_for_every_removal() {
+        assert!(!needs_confirmation(0, 0));
+        assert!(needs_confirmation(1, 0));
+    }
+
+    #[test]
+    fn threshold_max_never_asks() {
+        assert!(!needs_confirmation(usize::MAX, usize::MAX));
+    }
+
+    #[test]
+    fn prompt_names_the_command() {
+        assert_eq!(
+            removal_prompt("/stop", 42),
+            "/stop entfernt 42 Tracks aus der Warteschlange. Fortfahren?"
+        );
+    }
+
+    #[test]
+    fn outcome_snapshots() {
+        let timestamp = Timestamp::from_unix_timestamp(0).unwrap();
+        let snapshot = |answered, confirmed| {
+            serde_json::to_value(confirm_outcome(answered, confirmed).to_embed("Gerbot", timestamp))
+                .unwrap()
+        };
+        let expected = |description: &str, color: u32| {
+            json!({
+                "type": "rich",
+                "title": "Bestätigung",
+                "description": description,
+                "timestamp": "1970-01-01T00:00:00Z",
+                "color": color,
+                "footer": { "text": "Gerbot" },
+            })
+        };
+        assert_eq!(snapshot(true, true), expected("Bestätigt", 0x7289DA));
+        assert_eq!(snapshot(true, false), expected("Abgebrochen", 0xE74C3C));
+        assert_eq!(
+            snapshot(false, false),
+            expected("Abgebrochen, da keine Antwort kam", 0xE74C3C)
+        );
+    }
+}
@@ -0,0 +1,429 @@
+use crate::audit_log::AuditAction;
+use crate::commands::util::{get_metadata, stop_queue};
+use crate::departures::{leave_with_reason, LeaveReason};
+use crate::playback_mode::ModeChange;
+use crate::{
+    AuditLogKey, EndMarkersKey, PlaybackEventsKey, PlaybackModesKey, PlaylistSyncsKey,
+    SavedPlaylistsKey, ScheduleKey, VoiceSessionsKey,
+};
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use serenity::all::{Context, GuildId};
+use serenity::prelude::{TypeMap, TypeMapKey};
+use songbird::error::JoinError;
+use songbird::{Call, SongbirdKey};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{Mutex, Notify, RwLock};
+
+/// A line of the control socket
+#[derive(Debug, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Guilds with a voice connection
+    Sessions,
+    /// Like /stop, without the confirmation
+    Stop {
+        guild_id: GuildId,
+        #[serde(default)]
+        finish_current: bool,
+    },
+    /// Like /leave
+    Leave { guild_id: GuildId },
+    /// Reads the schedule, playlist sync and saved playlist files again
+    Reload,
+    /// Leaves all voice channels and exits, like on SIGTERM
+    Shutdown,
+}
+
+#[derive(Debug, Error)]
+pub enum ControlError {
+    #[error("Not connected to voice in guild {0}")]
+    NotConnected(GuildId),
+    #[error("Nothing is playing in guild {0}")]
+    QueueEmpty(GuildId),
+    #[error("Failed to leave voice in guild {0}: {1}")]
+    Leave(GuildId, JoinError),
+}
+
+/// Why the control socket was not started
+#[derive(Debug, Error)]
+enum SetupError {
+    #[error("{0:?} or its directory is writable by everyone")]
+    WorldWritable(PathBuf),
+    #[error("{0:?} exists and is not a socket")]
+    NotASocket(PathBuf),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Runs the control socket for scripts on the host until the process exits. Every line is a JSON
+/// request, every answer a JSON line with `ok` and either `result` or `error`.
+pub async fn serve(path: PathBuf, ctx: Context, shutdown: Arc<Notify>) {
+    let listener = match bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Control socket not started: {e}");
+            return;
+        }
+    };
+    info!("Starting control socket on {path:?}");
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle_connection(
+                    stream,
+                    ctx.data.clone(),
+                    shutdown.clone(),
+                ));
+            }
+            Err(e) => warn!("Failed to accept a control connection: {e}"),
+        }
+    }
+}
+
+/// Anyone who can write to the socket controls the bot, so only the owner may
+fn bind(path: &Path) -> Result<UnixListener, SetupError> {
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if std::fs::metadata(directory)?.permissions().mode() & 0o002 != 0 {
+        return Err(SetupError::WorldWritable(path.to_owned()));
+    }
+    match std::fs::symlink_metadata(path) {
+        // Left behind by the last run
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            if metadata.permissions().mode() & 0o002 != 0 {
+                return Err(SetupError::WorldWritable(path.to_owned()));
+            }
+            std::fs::remove_file(path)?;
+        }
+        Ok(_) => return Err(SetupError::NotASocket(path.to_owned())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+async fn handle_connection(stream: UnixStream, data: Arc<RwLock<TypeMap>>, shutdown: Arc<Notify>) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<ControlRequest>(&line) {
+            Ok(request) => {
+                info!("Control request: {request:?}");
+                match handle_request(&data, &shutdown, request).await {
+                    Ok(result) => json!({ "ok": true, "result": result }),
+                    Err(e) => json!({ "ok": false, "error": e.to_string() }),
+                }
+            }
+            Err(e) => json!({ "ok": false, "error": format!("Invalid request: {e}") }),
+        };
+        if writer
+            .write_all(format!("{response}\n").as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// A store from the typemap of the client
+async fn get<K: TypeMapKey>(data: &RwLock<TypeMap>) -> K::Value
+where
+    K::Value: Clone,
+{
+    data.read()
+        .await
+        .get::<K>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+/// The call of a guild, if the bot is in one
+async fn get_call(data: &RwLock<TypeMap>, guild_id: GuildId) -> Option<Arc<Mutex<Call>>> {
+    let songbird = data.read().await.get::<SongbirdKey>().cloned()?;
+    songbird.get(guild_id)
+}
+
+/// Executes a request with the same functions the slash commands use. Only the typemap of the
+/// client is needed, so the socket keeps working without a gateway connection.
+pub async fn handle_request(
+    data: &RwLock<TypeMap>,
+    shutdown: &Notify,
+    request: ControlRequest,
+) -> Result<Value, ControlError> {
+    match request {
+        ControlRequest::Sessions => Ok(sessions(data).await),
+        ControlRequest::Stop {
+            guild_id,
+            finish_current,
+        } => stop(data, guild_id, finish_current).await,
+        ControlRequest::Leave { guild_id } => {
+            let call = get_call(data, guild_id)
+                .await
+                .ok_or(ControlError::NotConnected(guild_id))?;
+            let mut call = call.lock().await;
+            leave_with_reason(data, guild_id, &mut call, LeaveReason::ControlSocket)
+                .await
+                .map_err(|e| ControlError::Leave(guild_id, e))?;
+            Ok(Value::Null)
+        }
+        ControlRequest::Reload => Ok(json!({
+            "schedules": get::<ScheduleKey>(data).await.reload(),
+            "playlist_syncs": get::<PlaylistSyncsKey>(data).await.reload(),
+            "saved_playlists": get::<SavedPlaylistsKey>(data).await.reload(),
+        })),
+        ControlRequest::Shutdown => {
+            shutdown.notify_one();
+            Ok(Value::Null)
+        }
+    }
+}
+
+async fn sessions(data: &RwLock<TypeMap>) -> Value {
+    let mut sessions = vec![];
+    for (guild_id, age) in get::<VoiceSessionsKey>(data).await.active(Instant::now()) {
+        let call = get_call(data, guild_id).await;
+        let (channel, queue) = match &call {
+            Some(call) => {
+                let call = call.lock().await;
+                (call.current_channel(), call.queue().current_queue())
+            }
+            None => (None, vec![]),
+        };
+        let current = match queue.first() {
+            Some(current) => Some(get_metadata(current).await.title.clone()),
+            None => None,
+        };
+        sessions.push(json!({
+            "guild_id": guild_id,
+            "channel_id": channel.map(|channel| channel.0.get()),
+            "connected_secs": age.as_secs(),
+            "queue_length": queue.len(),
+            "current": current,
+        }));
+    }
+    Value::from(sessions)
+}
+
+async fn stop(
+    data: &RwLock<TypeMap>,
+    guild_id: GuildId,
+    finish_current: bool,
+) -> Result<Value, ControlError> {
+    let call = get_call(data, guild_id)
+        .await
+        .ok_or(ControlError::NotConnected(guild_id))?;
+    let queue_len = call.lock().await.queue().len();
+    if queue_len == 0 {
+        return Err(ControlError::QueueEmpty(guild_id));
+    }
+    let removed = match finish_current {
+        true => queue_len - 1,
+        false => queue_len,
+    };
+
+    get::<PlaybackModesKey>(data)
+        .await
+        .apply(guild_id, ModeChange::stop(finish_current));
+    get::<AuditLogKey>(data)
+        .await
+        .record(guild_id, None, AuditAction::Stopped { removed });
+    let end_markers = get::<EndMarkersKey>(data).await;
+    let event = stop_queue(
+        call.lock().await.queue(),
+        guild_id,
+        &end_markers,
+        None,
+        finish_current,
+    );
+    get::<PlaybackEventsKey>(data)
+        .await
+        .publish(guild_id, event);
+
+    Ok(json!({ "removed": removed }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit_log::AuditLog;
+    use crate::metadata::{TrackMetadata, TrackMetadataKey};
+    use crate::resume::ResumePoints;
+    use crate::voice_sessions::VoiceSessions;
+    use crate::{DeparturesKey, ResumePointsKey};
+    use serenity::all::UserId;
+    use songbird::Songbird;
+    use std::time::Duration;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    /// The typemap of a client that is connected in [GUILD] and has `titles` queued
+    async fn fake_session(titles: &[&str]) -> RwLock<TypeMap> {
+        let songbird = Songbird::serenity();
+        songbird.initialise_client_data(1, UserId::new(2));
+        {
+            let call = songbird.get_or_insert(GUILD);
+            let mut call = call.lock().await;
+            for title in titles {
+                let handle = call.enqueue_input(vec![0u8; 16].into()).await;
+                handle
+                    .typemap()
+                    .write()
+                    .await
+                    .insert::<TrackMetadataKey>(Arc::new(TrackMetadata::announcement(
+                        title.to_string(),
+                    )));
+            }
+        }
+        let sessions = VoiceSessions::new(None);
+        sessions.start(GUILD, Instant::now()).unwrap();
+
+        let mut data = TypeMap::new();
+        data.insert::<SongbirdKey>(songbird);
+        data.insert::<VoiceSessionsKey>(Arc::new(sessions));
+        data.insert::<PlaybackModesKey>(Arc::default());
+        data.insert::<PlaybackEventsKey>(Arc::default());
+        data.insert::<EndMarkersKey>(Arc::default());
+        data.insert::<DeparturesKey>(Arc::default());
+        data.insert::<ResumePointsKey>(Arc::new(ResumePoints::new(Duration::from_secs(60))));
+        data.insert::<AuditLogKey>(Arc::new(AuditLog::load(None, Arc::default())));
+        RwLock::new(data)
+    }
+
+    async fn request(data: &RwLock<TypeMap>, line: &str) -> Result<Value, ControlError> {
+        let request = serde_json::from_str(line).unwrap();
+        handle_request(data, &Notify::new(), request).await
+    }
+
+    async fn queue_len(data: &RwLock<TypeMap>) -> usize {
+        let call = get_call(data, GUILD).await.unwrap();
+        let len = call.lock().await.queue().len();
+        len
+    }
+
+    #[tokio::test]
+    async fn sessions_list_the_fake_call() {
+        let data = fake_session(&["Erstes", "Zweites"]).await;
+        let sessions = request(&data, r#"{"command":"sessions"}"#).await.unwrap();
+        assert_eq!(sessions[0]["guild_id"], json!(GUILD));
+        assert_eq!(sessions[0]["queue_length"], 2);
+        assert_eq!(sessions[0]["current"], "Erstes");
+        assert_eq!(sessions[0]["channel_id"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn stop_can_finish_the_current_track() {
+        let data = fake_session(&["Erstes", "Zweites", "Drittes"]).await;
+        let result = request(
+            &data,
+            r#"{"command":"stop","guild_id":"1","finish_current":true}"#,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, json!({ "removed": 2 }));
+        assert_eq!(queue_len(&data).await, 1);
+        assert!(
+            get::<PlaybackModesKey>(&data)
+                .await
+                .get(GUILD)
+                .stop_after_current
+        );
+
+        let result = request(&data, r#"{"command":"stop","guild_id":"1"}"#)
+            .await
+            .unwrap();
+        assert_eq!(result, json!({ "removed": 1 }));
+        assert_eq!(queue_len(&data).await, 0);
+        assert!(matches!(
+            request(&data, r#"{"command":"stop","guild_id":"1"}"#).await,
+            Err(ControlError::QueueEmpty(GUILD))
+        ));
+    }
+
+    #[tokio::test]
+    async fn leave_ends_the_session_with_its_reason() {
+        let data = fake_session(&["Erstes"]).await;
+        // The fake call has no gateway to send the leave to, the session ends either way
+        _ = request(&data, r#"{"command":"leave","guild_id":"1"}"#).await;
+
+        assert!(!get::<VoiceSessionsKey>(&data).await.is_active(GUILD));
+        assert_eq!(queue_len(&data).await, 0);
+        assert_eq!(
+            get::<DeparturesKey>(&data)
+                .await
+                .get(GUILD)
+                .map(|departure| departure.reason),
+            Some(LeaveReason::ControlSocket)
+        );
+        let sessions = request(&data, r#"{"command":"sessions"}"#).await.unwrap();
+        assert_eq!(sessions, json!([]));
+    }
+
+    #[tokio::test]
+    async fn other_guilds_are_not_connected() {
+        let data = fake_session(&[]).await;
+        let other = GuildId::new(3);
+        assert!(matches!(
+            request(&data, r#"{"command":"stop","guild_id":"3"}"#).await,
+            Err(ControlError::NotConnected(guild_id)) if guild_id == other
+        ));
+        assert!(matches!(
+            request(&data, r#"{"command":"leave","guild_id":"3"}"#).await,
+            Err(ControlError::NotConnected(guild_id)) if guild_id == other
+        ));
+    }
+
+    /// A fresh directory with `mode`, removed by the caller
+    fn directory(name: &str, mode: u32) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("gerbot-control-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::set_permissions(&directory, std::fs::Permissions::from_mode(mode)).unwrap();
+        directory
+    }
+
+    #[tokio::test]
+    async fn bind_refuses_a_world_writable_directory() {
+        let directory = directory("shared", 0o777);
+        let path = directory.join("control.sock");
+        let result = bind(&path);
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(matches!(result, Err(SetupError::WorldWritable(_))));
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn bind_replaces_a_stale_socket_for_the_owner_only() {
+        let directory = directory("private", 0o700);
+        let path = directory.join("control.sock");
+        drop(bind(&path).unwrap());
+        // Left behind like after a crash
+        let listener = bind(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        drop(listener);
+        std::fs::write(directory.join("not-a-socket"), "").unwrap();
+        let not_a_socket = bind(&directory.join("not-a-socket"));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(matches!(not_a_socket, Err(SetupError::NotASocket(_))));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::commands::util::get_metadata;
+use crate::events::PlaybackEvent;
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use crate::playback_mode::ModeChange;
+use crate::resume::ResumePoint;
+use crate::{
+    DeparturesKey, PlaybackEventsKey, PlaybackModesKey, ResumePointsKey, VoiceSessionsKey,
+};
+use log::info;
+use serenity::all::GuildId;
+use serenity::prelude::TypeMap;
+use songbird::error::JoinResult;
+use songbird::Call;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::RwLock;
+
+/// Why the bot left a voice channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeaveReason {
+    ManualLeave,
+    EmptyChannel,
+    ForcedDisconnect,
+    Shutdown,
+    ControlSocket,
+    RemovedFromGuild,
+}
+
+impl LeaveReason {
+    /// Completes "Der Bot hat den Kanal verlassen, weil ..."
+    pub fn describe(&self) -> &'static str {
+        match self {
+            LeaveReason::ManualLeave => "er mit /leave entfernt wurde",
+            LeaveReason::EmptyChannel => "niemand mehr im Kanal war",
+            LeaveReason::ForcedDisconnect => "er von einem Moderator getrennt wurde",
+            LeaveReason::Shutdown => "der Bot neu gestartet wurde",
+            LeaveReason::ControlSocket => "ein Administrator des Hosts ihn getrennt hat",
+            LeaveReason::RemovedFromGuild => "er vom Server entfernt wurde",
+        }
+    }
+
+    /// Whether the queue is kept for /resumesession. Only leaves that interrupt listeners do,
+    /// nobody comes back to an empty channel or a queue that ran out.
+    pub fn keeps_resume_point(&self) -> bool {
+        matches!(
+            self,
+            LeaveReason::ManualLeave | LeaveReason::ForcedDisconnect
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Departure {
+    pub reason: LeaveReason,
+    pub at: SystemTime,
+}
+
+/// Latest departure of every guild
+#[derive(Default)]
+pub struct Departures {
+    latest: GuildStateMap<Departure>,
+}
+
+impl Departures {
+    pub fn get(&self, guild_id: GuildId) -> Option<Departure> {
+        self.latest.get(guild_id)
+    }
+
+    fn record(&self, guild_id: GuildId, reason: LeaveReason) {
+        self.latest.insert(
+            guild_id,
+            Departure {
+                reason,
+                at: SystemTime::now(),
+            },
+        );
+    }
+}
+
+impl GuildScoped for Departures {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::Departures
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.latest.entry_counts(|_| 1)
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.latest.remove_on_leave(guild_id);
+    }
+}
+
+/// Clears the queue and leaves the voice channel. Every leave path goes through here, so the
+/// reason is always recorded.
+pub async fn leave_with_reason(
+    data: &RwLock<TypeMap>,
+    guild_id: GuildId,
+    call: &mut Call,
+    reason: LeaveReason,
+) -> JoinResult<()> {
+    let (modes, events, departures, sessions, resume_points) = {
+        let data = data.read().await;
+        (
+            data.get::<PlaybackModesKey>()
+                .cloned()
+                .expect("Guaranteed to exist in the typemap"),
+            data.get::<PlaybackEventsKey>()
+                .cloned()
+                .expect("Guaranteed to exist in the typemap"),
+            data.get::<DeparturesKey>()
+                .cloned()
+                .expect("Guaranteed to exist in the typemap"),
+            data.get::<VoiceSessionsKey>()
+                .cloned()
+                .expect("Guaranteed to exist in the typemap"),
+            data.get::<ResumePointsKey>()
+                .cloned()
+                .expect("Guaranteed to exist in the typemap"),
+        )
+    };
+
+    info!("Leaving voice channel in guild {guild_id}: {reason:?}");
+    departures.record(guild_id, reason);
+    if reason.keeps_resume_point() {
+        if let Some(point) = resume_point(call).await {
+            resume_points.save(guild_id, point);
+        }
+    }
+    modes.apply(guild_id, ModeChange::QueueCleared);
+    call.queue().stop();
+    call.stop();
+    let result = call.leave().await;
+    sessions.end(guild_id);
+    events.publish(guild_id, PlaybackEvent::Stopped);
+
+    result
+}
+
+/// The queue of the call with the position of the current track, if a track is playing
+async fn resume_point(call: &Call) -> Option<ResumePoint> {
+    let handles = call.queue().current_queue();
+    let current = handles.first()?;
+    // A dead track starts over
+    let position = current
+        .get_info()
+        .await
+        .map(|info| info.position)
+        .unwrap_or(Duration::ZERO);
+
+    let mut tracks = Vec::with_capacity(handles.len());
+    for handle in &handles {
+        tracks.push(get_metadata(handle).await);
+    }
+    Some(ResumePoint {
+        tracks,
+        position,
+        saved_at: Instant::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_interrupting_leaves_keep_a_resume_point() {
+        assert!(LeaveReason::ManualLeave.keeps_resume_point());
+        assert!(LeaveReason::ForcedDisconnect.keeps_resume_point());
+        assert!(!LeaveReason::EmptyChannel.keeps_resume_point());
+        assert!(!LeaveReason::Shutdown.keeps_resume_point());
+        assert!(!LeaveReason::ControlSocket.keeps_resume_point());
+        assert!(!LeaveReason::RemovedFromGuild.keeps_resume_point());
+    }
+
+    #[test]
+    fn latest_departure_wins() {
+        let departures = Departures::default();
+        let guild_id = GuildId::new(1);
+        assert!(departures.get(guild_id).is_none());
+
+        departures.record(guild_id, LeaveReason::ManualLeave);
+        departures.record(guild_id, LeaveReason::ForcedDisconnect);
+        assert_eq!(
+            departures.get(guild_id).map(|departure| departure.reason),
+            Some(LeaveReason::ForcedDisconnect)
+        );
+        assert!(departures.get(GuildId::new(2)).is_none());
+    }
+}
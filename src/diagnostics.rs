@@ -0,0 +1,374 @@
+use crate::commands::util::get_metadata;
+use crate::guild_state::{GuildScoped, GuildStateKind};
+use crate::position_cache::{PositionCache, PositionSample};
+use crate::stall::{StallAction, StallDetector, StallVerdict};
+use async_trait::async_trait;
+use log::warn;
+use serenity::all::GuildId;
+use songbird::events::context_data::DisconnectData;
+use songbird::events::{
+    CoreEvent, Event, EventContext, EventHandler as VoiceEventHandler, TrackEvent,
+};
+use songbird::tracks::{PlayMode, TrackHandle};
+use songbird::Call;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::interval;
+use uuid::Uuid;
+
+/// Interval of the playback position sampling
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+/// A playing track that advanced less than this between two samples had a gap
+const MIN_ADVANCE: Duration = Duration::from_millis(SAMPLE_INTERVAL.as_millis() as u64 / 2);
+
+/// Counts samples where the playing track did not advance by at least [MIN_ADVANCE]
+#[derive(Default)]
+struct GapCounter {
+    last: Option<(Uuid, Duration)>,
+}
+
+impl GapCounter {
+    /// Takes the playing track and its position, None if nothing is playing. Returns whether
+    /// there was a gap since the previous sample of the same track.
+    fn observe(&mut self, sample: Option<(Uuid, Duration)>) -> bool {
+        let gap = match (self.last, sample) {
+            (Some((last_uuid, last_position)), Some((uuid, position))) => {
+                last_uuid == uuid && position.saturating_sub(last_position) < MIN_ADVANCE
+            }
+            _ => false,
+        };
+        self.last = sample;
+        gap
+    }
+}
+
+/// Counters of a voice connection for diagnosing stutters
+#[derive(Clone, Debug, Default)]
+pub struct CallDiagnostics {
+    pub reconnects: u32,
+    pub playback_gaps: u32,
+    /// Tracks that started buffering mid-song
+    pub stalls: u32,
+    pub last_error: Option<(String, SystemTime)>,
+}
+
+/// Driver events and playback gaps of all calls, counted since the bot started. The samples of
+/// the gap detection also keep the position cache up to date.
+pub struct DriverDiagnostics {
+    positions: Arc<PositionCache>,
+    stall_limit: Duration,
+    stall_actions: UnboundedSender<StallAction>,
+    calls: Mutex<HashMap<GuildId, CallDiagnostics>>,
+    /// Calls are kept by songbird after leaving, so their handlers must only be added once. A
+    /// call that was removed from songbird is replaced by a new one without handlers.
+    instrumented: Mutex<HashMap<GuildId, Weak<AsyncMutex<Call>>>>,
+    sampling: Mutex<HashSet<GuildId>>,
+}
+
+impl DriverDiagnostics {
+    /// Tracks that stall for longer than `stall_limit` are sent to `stall_actions`
+    pub fn new(
+        positions: Arc<PositionCache>,
+        stall_limit: Duration,
+        stall_actions: UnboundedSender<StallAction>,
+    ) -> Self {
+        Self {
+            positions,
+            stall_limit,
+            stall_actions,
+            calls: Mutex::new(HashMap::new()),
+            instrumented: Mutex::new(HashMap::new()),
+            sampling: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn get(&self, guild_id: GuildId) -> CallDiagnostics {
+        self.calls
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Sum over all guilds, with the most recent error
+    pub fn totals(&self) -> CallDiagnostics {
+        let calls = self.calls.lock().unwrap();
+        let mut totals = CallDiagnostics::default();
+        for diagnostics in calls.values() {
+            totals.reconnects += diagnostics.reconnects;
+            totals.playback_gaps += diagnostics.playback_gaps;
+            totals.stalls += diagnostics.stalls;
+            if let Some((error, at)) = &diagnostics.last_error {
+                let newer = match &totals.last_error {
+                    Some((_, last)) => at > last,
+                    None => true,
+                };
+                if newer {
+                    totals.last_error = Some((error.clone(), *at));
+                }
+            }
+        }
+        totals
+    }
+
+    fn update(&self, guild_id: GuildId, update: impl FnOnce(&mut CallDiagnostics)) {
+        update(self.calls.lock().unwrap().entry(guild_id).or_default());
+    }
+
+    fn record_error(&self, guild_id: GuildId, error: String) {
+        warn!("Voice error in guild {guild_id}: {error}");
+        self.update(guild_id, |d| {
+            d.last_error = Some((error, SystemTime::now()))
+        });
+    }
+
+    /// Whether the call did not get the driver event handlers yet, marks it as instrumented
+    fn instrument(&self, guild_id: GuildId, call: &Arc<AsyncMutex<Call>>) -> bool {
+        let call = Arc::downgrade(call);
+        let mut instrumented = self.instrumented.lock().unwrap();
+        if instrumented
+            .get(&guild_id)
+            .is_some_and(|known| known.ptr_eq(&call))
+        {
+            return false;
+        }
+        instrumented.insert(guild_id, call);
+        true
+    }
+
+    /// Adds the driver event handlers to a call and samples its playback until it is left
+    pub async fn spawn_for(self: Arc<Self>, guild_id: GuildId, call: Arc<AsyncMutex<Call>>) {
+        if self.instrument(guild_id, &call) {
+            let mut call = call.lock().await;
+            for event in [
+                Event::Core(CoreEvent::DriverReconnect),
+                Event::Core(CoreEvent::DriverDisconnect),
+                Event::Track(TrackEvent::Error),
+            ] {
+                call.add_global_event(
+                    event,
+                    DriverEventHandler {
+                        diagnostics: self.clone(),
+                        guild_id,
+                    },
+                );
+            }
+        }
+
+        if !self.sampling.lock().unwrap().insert(guild_id) {
+            return;
+        }
+        tokio::spawn(async move {
+            self.sample(guild_id, call).await;
+            self.sampling.lock().unwrap().remove(&guild_id);
+        });
+    }
+
+    /// Counts samples where the current track is playing, but its position did not advance, and
+    /// flags tracks that stay frozen as buffering
+    async fn sample(&self, guild_id: GuildId, call: Arc<AsyncMutex<Call>>) {
+        let mut gaps = GapCounter::default();
+        let mut stalls = StallDetector::new(self.stall_limit);
+        let mut interval = interval(SAMPLE_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let current = {
+                let call = call.lock().await;
+                if call.current_channel().is_none() {
+                    self.positions.invalidate(guild_id);
+                    return;
+                }
+                call.queue().current()
+            };
+            let Some(current) = current else {
+                gaps.observe(None);
+                continue;
+            };
+            let sample = match current.get_info().await {
+                Ok(info) => {
+                    let sample = PositionSample::new(current.uuid(), &info, Instant::now());
+                    self.positions.record(guild_id, sample);
+                    self.check_stall(guild_id, &current, stalls.observe(&sample))
+                        .await;
+                    (info.playing == PlayMode::Play).then_some((current.uuid(), info.position))
+                }
+                Err(_) => None,
+            };
+
+            if gaps.observe(sample) {
+                self.update(guild_id, |d| d.playback_gaps += 1);
+            }
+        }
+    }
+
+    async fn check_stall(&self, guild_id: GuildId, current: &TrackHandle, verdict: StallVerdict) {
+        let position = match verdict {
+            StallVerdict::Fine => {
+                self.positions.set_buffering(guild_id, false);
+                return;
+            }
+            StallVerdict::Buffering { stalled_for } => {
+                if self.positions.set_buffering(guild_id, true) {
+                    self.update(guild_id, |d| d.stalls += 1);
+                    warn!(
+                        "Playback of {} in guild {guild_id} is buffering for {stalled_for:?}",
+                        get_metadata(current).await.source_url
+                    );
+                }
+                return;
+            }
+            StallVerdict::Stalled { position } => position,
+        };
+        warn!(
+            "Playback of {} in guild {guild_id} stalled for longer than {:?} at {position:?}",
+            get_metadata(current).await.source_url,
+            self.stall_limit
+        );
+        // Only fails if the recovery stopped, which happens with the process
+        _ = self.stall_actions.send(StallAction {
+            guild_id,
+            track: current.uuid(),
+            position,
+        });
+    }
+}
+
+impl GuildScoped for DriverDiagnostics {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::Diagnostics
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        let calls = self.calls.lock().unwrap();
+        calls.keys().map(|id| (*id, 1)).collect()
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.calls.lock().unwrap().remove(&guild_id);
+        self.instrumented.lock().unwrap().remove(&guild_id);
+    }
+}
+
+struct DriverEventHandler {
+    diagnostics: Arc<DriverDiagnostics>,
+    guild_id: GuildId,
+}
+
+#[async_trait]
+impl VoiceEventHandler for DriverEventHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        match ctx {
+            EventContext::DriverReconnect(_) => {
+                self.diagnostics
+                    .update(self.guild_id, |d| d.reconnects += 1);
+            }
+            // Disconnects without a reason were requested by the bot itself
+            EventContext::DriverDisconnect(DisconnectData {
+                reason: Some(reason),
+                ..
+            }) => {
+                self.diagnostics
+                    .record_error(self.guild_id, format!("Verbindung getrennt: {reason:?}"));
+            }
+            EventContext::Track(tracks) => {
+                for (state, _) in tracks.iter() {
+                    if let PlayMode::Errored(e) = &state.playing {
+                        self.diagnostics
+                            .record_error(self.guild_id, format!("Wiedergabefehler: {e}"));
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::all::UserId;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    fn at(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn diagnostics() -> DriverDiagnostics {
+        let (sender, _) = tokio::sync::mpsc::unbounded_channel();
+        DriverDiagnostics::new(Arc::new(PositionCache::default()), at(30), sender)
+    }
+
+    fn call() -> Arc<AsyncMutex<Call>> {
+        Arc::new(AsyncMutex::new(Call::standalone(GUILD, UserId::new(2))))
+    }
+
+    #[test]
+    fn advancing_playback_has_no_gaps() {
+        let track = Uuid::from_u128(1);
+        let mut gaps = GapCounter::default();
+        for position in [0, 5, 10, 15] {
+            assert!(!gaps.observe(Some((track, at(position)))));
+        }
+    }
+
+    #[test]
+    fn frozen_position_is_a_gap() {
+        let track = Uuid::from_u128(1);
+        let mut gaps = GapCounter::default();
+        assert!(!gaps.observe(Some((track, at(10)))));
+        assert!(gaps.observe(Some((track, at(10)))));
+        assert!(gaps.observe(Some((track, at(10) + MIN_ADVANCE / 2))));
+        assert!(!gaps.observe(Some((track, at(20)))));
+    }
+
+    #[test]
+    fn track_change_resets_the_gap_detection() {
+        let mut gaps = GapCounter::default();
+        assert!(!gaps.observe(Some((Uuid::from_u128(1), at(10)))));
+        assert!(!gaps.observe(Some((Uuid::from_u128(2), at(0)))));
+        assert!(!gaps.observe(Some((Uuid::from_u128(2), at(5)))));
+    }
+
+    #[test]
+    fn paused_playback_resets_the_gap_detection() {
+        let track = Uuid::from_u128(1);
+        let mut gaps = GapCounter::default();
+        assert!(!gaps.observe(Some((track, at(10)))));
+        assert!(!gaps.observe(None));
+        assert!(!gaps.observe(Some((track, at(10)))));
+    }
+
+    #[tokio::test]
+    async fn calls_are_instrumented_once() {
+        let diagnostics = diagnostics();
+        let call = call();
+        assert!(diagnostics.instrument(GUILD, &call));
+        assert!(!diagnostics.instrument(GUILD, &call));
+    }
+
+    #[tokio::test]
+    async fn replaced_calls_are_instrumented_again() {
+        let diagnostics = diagnostics();
+        let removed = call();
+        assert!(diagnostics.instrument(GUILD, &removed));
+        drop(removed);
+        assert!(diagnostics.instrument(GUILD, &call()));
+    }
+
+    #[tokio::test]
+    async fn forgotten_guilds_are_instrumented_again() {
+        let diagnostics = diagnostics();
+        let call = call();
+        assert!(diagnostics.instrument(GUILD, &call));
+        diagnostics.forget(GUILD);
+        assert!(diagnostics.instrument(GUILD, &call));
+    }
+}
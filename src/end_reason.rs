@@ -0,0 +1,177 @@
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use crate::ytdlp::{YtDlpError, YtDlpFailure};
+use serenity::all::{GuildId, UserId};
+use songbird::input::AudioStreamError;
+use songbird::tracks::{PlayError, PlayMode};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Why a track left the queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq, poise::ChoiceParameter)]
+pub enum EndReason {
+    /// Played to the end
+    #[name = "Zu Ende gespielt"]
+    Finished,
+    /// Skipped by a user, the queue continues
+    #[name = "Übersprungen"]
+    Skipped,
+    /// Stopped or removed together with the queue, or the bot left
+    #[name = "Gestoppt"]
+    Stopped,
+    /// Playback failed
+    #[name = "Fehler"]
+    Errored,
+}
+
+impl EndReason {
+    /// The reason of an ended track. Commands mark the tracks they end before stopping them,
+    /// unmarked tracks are classified by their final play mode.
+    pub fn classify(marker: Option<EndReason>, playing: &PlayMode) -> Self {
+        match (marker, playing) {
+            (_, PlayMode::Errored(_)) => EndReason::Errored,
+            (Some(marker), _) => marker,
+            (None, PlayMode::End) => EndReason::Finished,
+            (None, _) => EndReason::Stopped,
+        }
+    }
+}
+
+/// Few words on why playback failed, for lists like the history
+pub fn describe_play_error(error: &PlayError) -> &'static str {
+    match error {
+        PlayError::Create(e) => match &**e {
+            AudioStreamError::Fail(e) => e
+                .downcast_ref::<YtDlpError>()
+                .map_or(YtDlpFailure::Unknown.label(), |e| e.failure().label()),
+            _ => YtDlpFailure::Unknown.label(),
+        },
+        PlayError::Parse(_) => "Format nicht lesbar",
+        PlayError::Decode(_) => "Wiedergabe abgebrochen",
+        PlayError::Seek(_) => "Spulen fehlgeschlagen",
+        _ => "Wiedergabe fehlgeschlagen",
+    }
+}
+
+/// The reason a command set for a track it ends
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EndMarker {
+    pub reason: EndReason,
+    /// The user who ran the command, none for the bot itself
+    pub actor: Option<UserId>,
+}
+
+/// Tracks that a command is about to end, until their end event consumes the marker
+#[derive(Default)]
+pub struct EndMarkers {
+    marked: GuildStateMap<HashMap<Uuid, EndMarker>>,
+}
+
+impl EndMarkers {
+    /// Marks a track the bot ends by itself
+    pub fn mark(&self, guild_id: GuildId, track: Uuid, reason: EndReason) {
+        self.mark_by(guild_id, track, reason, None);
+    }
+
+    pub fn mark_by(
+        &self,
+        guild_id: GuildId,
+        track: Uuid,
+        reason: EndReason,
+        actor: Option<UserId>,
+    ) {
+        self.marked.with_mut(guild_id, |marked| {
+            marked.insert(track, EndMarker { reason, actor })
+        });
+    }
+
+    pub fn take(&self, guild_id: GuildId, track: Uuid) -> Option<EndMarker> {
+        self.marked
+            .with_mut(guild_id, |marked| marked.remove(&track))
+    }
+}
+
+impl GuildScoped for EndMarkers {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::EndMarkers
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.marked.entry_counts(HashMap::len)
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.marked.remove_on_leave(guild_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    fn errored() -> PlayMode {
+        PlayMode::Errored(PlayError::Create(Arc::new(AudioStreamError::Unsupported)))
+    }
+
+    #[test]
+    fn unmarked_tracks_are_classified_by_their_play_mode() {
+        assert_eq!(
+            EndReason::classify(None, &PlayMode::End),
+            EndReason::Finished
+        );
+        assert_eq!(
+            EndReason::classify(None, &PlayMode::Stop),
+            EndReason::Stopped
+        );
+        assert_eq!(EndReason::classify(None, &errored()), EndReason::Errored);
+    }
+
+    #[test]
+    fn markers_win_over_the_play_mode_except_for_errors() {
+        for marker in [EndReason::Skipped, EndReason::Stopped] {
+            for mode in [PlayMode::Stop, PlayMode::End] {
+                assert_eq!(EndReason::classify(Some(marker), &mode), marker);
+            }
+            // A track that failed while being skipped still failed
+            assert_eq!(
+                EndReason::classify(Some(marker), &errored()),
+                EndReason::Errored
+            );
+        }
+    }
+
+    #[test]
+    fn markers_are_consumed_once() {
+        let markers = EndMarkers::default();
+        let track = Uuid::new_v4();
+        let actor = Some(UserId::new(5));
+        markers.mark_by(GUILD, track, EndReason::Skipped, actor);
+
+        assert!(markers.take(GuildId::new(2), track).is_none());
+        assert_eq!(
+            markers.take(GUILD, track),
+            Some(EndMarker {
+                reason: EndReason::Skipped,
+                actor
+            })
+        );
+        assert!(markers.take(GUILD, track).is_none());
+    }
+
+    #[test]
+    fn the_latest_marker_of_a_track_counts() {
+        let markers = EndMarkers::default();
+        let track = Uuid::new_v4();
+        markers.mark_by(GUILD, track, EndReason::Skipped, Some(UserId::new(5)));
+        markers.mark(GUILD, track, EndReason::Stopped);
+        assert_eq!(
+            markers.take(GUILD, track),
+            Some(EndMarker {
+                reason: EndReason::Stopped,
+                actor: None
+            })
+        );
+    }
+}
@@ -0,0 +1,444 @@
+use log::{info, warn};
+use reqwest::Client as HttpClient;
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::time::interval;
+
+/// The short window, which also decides about incidents
+pub const SHORT_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// The long window, also the history shown as a sparkline
+pub const LONG_WINDOW: Duration = Duration::from_secs(30 * 60);
+/// Calls are counted per minute, older minutes fall out of the windows
+const BUCKET: Duration = Duration::from_secs(60);
+/// An incident starts once this share of calls in the short window failed
+const INCIDENT_RATE: f64 = 0.5;
+/// An incident ends once the failed share in the short window is below this again
+const RECOVERY_RATE: f64 = 0.2;
+/// A handful of failed calls is no incident, however high their share
+const MIN_INCIDENT_CALLS: u32 = 10;
+/// How often the windows are checked for incidents
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// From no failed calls to all of them, one per minute of the long window
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// Outside services whose calls are counted
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorSource {
+    /// Requests to the official YouTube api, without the ones refused for quota
+    YoutubeApi,
+    /// Stream extraction of queued tracks
+    YtDlp,
+    /// Responses to commands and background messages
+    Discord,
+}
+
+impl ErrorSource {
+    pub const ALL: [ErrorSource; 3] = [
+        ErrorSource::YoutubeApi,
+        ErrorSource::YtDlp,
+        ErrorSource::Discord,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorSource::YoutubeApi => "YouTube-API",
+            ErrorSource::YtDlp => "yt-dlp",
+            ErrorSource::Discord => "Discord",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            ErrorSource::YoutubeApi => "youtube_api",
+            ErrorSource::YtDlp => "ytdlp",
+            ErrorSource::Discord => "discord",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Bucket {
+    minute: u64,
+    calls: u32,
+    failures: u32,
+}
+
+/// Calls and failures of one window
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WindowCount {
+    pub calls: u32,
+    pub failures: u32,
+}
+
+impl WindowCount {
+    /// Share of failed calls, none without calls
+    pub fn rate(&self) -> Option<f64> {
+        (self.calls > 0).then(|| f64::from(self.failures) / f64::from(self.calls))
+    }
+}
+
+/// The calls of one source per minute, for the last [LONG_WINDOW]
+#[derive(Debug, Default)]
+struct RollingWindow {
+    buckets: VecDeque<Bucket>,
+}
+
+impl RollingWindow {
+    fn record(&mut self, minute: u64, ok: bool) {
+        if !matches!(self.buckets.back(), Some(b) if b.minute == minute) {
+            self.buckets.push_back(Bucket {
+                minute,
+                ..Default::default()
+            });
+        }
+        let bucket = self.buckets.back_mut().expect("A bucket was just added");
+        bucket.calls += 1;
+        if !ok {
+            bucket.failures += 1;
+        }
+        self.prune(minute);
+    }
+
+    fn prune(&mut self, minute: u64) {
+        let oldest = minute.saturating_sub(minutes(LONG_WINDOW) - 1);
+        while self.buckets.front().is_some_and(|b| b.minute < oldest) {
+            self.buckets.pop_front();
+        }
+    }
+
+    fn count(&self, minute: u64, window: Duration) -> WindowCount {
+        let oldest = minute.saturating_sub(minutes(window) - 1);
+        self.buckets
+            .iter()
+            .filter(|b| b.minute >= oldest && b.minute <= minute)
+            .fold(WindowCount::default(), |count, b| WindowCount {
+                calls: count.calls + b.calls,
+                failures: count.failures + b.failures,
+            })
+    }
+
+    /// One character per minute of the long window, oldest first. Minutes without calls are dots.
+    fn sparkline(&self, minute: u64) -> String {
+        let oldest = minute.saturating_sub(minutes(LONG_WINDOW) - 1);
+        (oldest..=minute)
+            .map(|m| match self.buckets.iter().find(|b| b.minute == m) {
+                Some(b) if b.calls > 0 => {
+                    let rate = f64::from(b.failures) / f64::from(b.calls);
+                    SPARKS[((rate * (SPARKS.len() - 1) as f64).round() as usize)
+                        .min(SPARKS.len() - 1)]
+                }
+                _ => '·',
+            })
+            .collect()
+    }
+}
+
+fn minutes(window: Duration) -> u64 {
+    (window.as_secs() / BUCKET.as_secs()).max(1)
+}
+
+/// An incident of one source that was not recovered from yet
+#[derive(Clone, Copy, Debug)]
+pub struct Incident {
+    pub since: SystemTime,
+    /// Failed share in the short window when the incident started
+    pub rate: f64,
+}
+
+/// Rolling error rates of the outside services, shared by /status, the metrics endpoint and the
+/// incident check
+#[derive(Debug)]
+pub struct ErrorRates {
+    started: Instant,
+    windows: Mutex<HashMap<ErrorSource, RollingWindow>>,
+    incidents: Mutex<HashMap<ErrorSource, Incident>>,
+}
+
+impl Default for ErrorRates {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            windows: Mutex::default(),
+            incidents: Mutex::default(),
+        }
+    }
+}
+
+/// Change of the incident state of a source after a check
+#[derive(Clone, Copy, Debug)]
+enum IncidentChange {
+    Started(Incident),
+    Recovered(Incident),
+}
+
+impl ErrorRates {
+    pub fn record(&self, source: ErrorSource, ok: bool) {
+        let minute = self.minute();
+        self.windows
+            .lock()
+            .unwrap()
+            .entry(source)
+            .or_default()
+            .record(minute, ok);
+    }
+
+    /// Records the outcome of a call and passes it on
+    pub fn track<T, E>(&self, source: ErrorSource, result: Result<T, E>) -> Result<T, E> {
+        self.record(source, result.is_ok());
+        result
+    }
+
+    pub fn count(&self, source: ErrorSource, window: Duration) -> WindowCount {
+        self.count_at(source, window, self.minute())
+    }
+
+    fn count_at(&self, source: ErrorSource, window: Duration, minute: u64) -> WindowCount {
+        self.windows
+            .lock()
+            .unwrap()
+            .get(&source)
+            .map(|w| w.count(minute, window))
+            .unwrap_or_default()
+    }
+
+    pub fn sparkline(&self, source: ErrorSource) -> String {
+        let minute = self.minute();
+        let windows = self.windows.lock().unwrap();
+        match windows.get(&source) {
+            Some(window) => window.sparkline(minute),
+            None => RollingWindow::default().sparkline(minute),
+        }
+    }
+
+    pub fn incident(&self, source: ErrorSource) -> Option<Incident> {
+        self.incidents.lock().unwrap().get(&source).copied()
+    }
+
+    /// Starts and ends incidents by the short window. Between the two rates nothing changes, so
+    /// a rate around the threshold does not cause a notice every minute.
+    fn check(&self, source: ErrorSource) -> Option<IncidentChange> {
+        self.check_at(source, self.minute())
+    }
+
+    fn check_at(&self, source: ErrorSource, minute: u64) -> Option<IncidentChange> {
+        let count = self.count_at(source, SHORT_WINDOW, minute);
+        let rate = count.rate().unwrap_or(0.0);
+        let mut incidents = self.incidents.lock().unwrap();
+        match incidents.get(&source).copied() {
+            None if count.calls >= MIN_INCIDENT_CALLS && rate >= INCIDENT_RATE => {
+                let incident = Incident {
+                    since: SystemTime::now(),
+                    rate,
+                };
+                incidents.insert(source, incident);
+                Some(IncidentChange::Started(incident))
+            }
+            // Without calls in the short window nothing is failing anymore
+            Some(incident) if rate < RECOVERY_RATE => {
+                incidents.remove(&source);
+                Some(IncidentChange::Recovered(incident))
+            }
+            _ => None,
+        }
+    }
+
+    /// Both windows of every source in the Prometheus text format
+    pub fn render_prometheus(&self) -> String {
+        let name = "gerbot_error_rate";
+        let calls_name = "gerbot_api_calls";
+        let mut rates = format!(
+            "# HELP {name} Share of failed calls to outside services\n# TYPE {name} gauge\n"
+        );
+        let mut calls = format!(
+            "# HELP {calls_name} Calls to outside services in the window\n# TYPE {calls_name} gauge\n"
+        );
+        for source in ErrorSource::ALL {
+            for (window, window_label) in [(SHORT_WINDOW, "5m"), (LONG_WINDOW, "30m")] {
+                let count = self.count(source, window);
+                let labels = format!("source=\"{}\",window=\"{window_label}\"", source.label());
+                _ = writeln!(rates, "{name}{{{labels}}} {}", count.rate().unwrap_or(0.0));
+                _ = writeln!(calls, "{calls_name}{{{labels}}} {}", count.calls);
+            }
+        }
+        rates + &calls
+    }
+
+    fn minute(&self) -> u64 {
+        self.started.elapsed().as_secs() / BUCKET.as_secs()
+    }
+}
+
+/// Checks the error rates every minute and posts each started and ended incident once to the
+/// owner webhook, if one is configured. Runs until the process exits.
+pub async fn run_incident_check(
+    error_rates: Arc<ErrorRates>,
+    http_client: HttpClient,
+    webhook_url: Option<String>,
+) {
+    let mut ticks = interval(CHECK_INTERVAL);
+    loop {
+        ticks.tick().await;
+        for source in ErrorSource::ALL {
+            let Some(change) = error_rates.check(source) else {
+                continue;
+            };
+            let content = match change {
+                IncidentChange::Started(incident) => {
+                    warn!(
+                        "Incident: {:.0} % of the calls to {} failed in the last {} minutes",
+                        incident.rate * 100.0,
+                        source.name(),
+                        minutes(SHORT_WINDOW)
+                    );
+                    format!(
+                        ":rotating_light: Störung bei {}: {:.0} % der Aufrufe sind in den letzten {} Minuten fehlgeschlagen",
+                        source.name(),
+                        incident.rate * 100.0,
+                        minutes(SHORT_WINDOW)
+                    )
+                }
+                IncidentChange::Recovered(incident) => {
+                    let duration = incident.since.elapsed().unwrap_or_default();
+                    info!("Incident of {} ended", source.name());
+                    format!(
+                        ":white_check_mark: Die Störung bei {} ist nach {} Minuten vorbei",
+                        source.name(),
+                        duration.as_secs() / 60
+                    )
+                }
+            };
+            if let Some(url) = &webhook_url {
+                let sent = http_client
+                    .post(url)
+                    .json(&json!({ "content": content }))
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                if let Err(e) = sent {
+                    warn!("Failed to post the incident notice to the owner webhook: {e}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: ErrorSource = ErrorSource::YtDlp;
+
+    /// Records `calls` calls in `minute`, the first `failures` of them failed
+    fn record(rates: &ErrorRates, minute: u64, calls: u32, failures: u32) {
+        let mut windows = rates.windows.lock().unwrap();
+        let window = windows.entry(SOURCE).or_default();
+        for i in 0..calls {
+            window.record(minute, i >= failures);
+        }
+    }
+
+    fn count(calls: u32, failures: u32) -> WindowCount {
+        WindowCount { calls, failures }
+    }
+
+    #[test]
+    fn minutes_roll_out_of_the_windows() {
+        let mut window = RollingWindow::default();
+        window.record(0, false);
+        window.record(0, true);
+        window.record(3, true);
+
+        assert_eq!(window.count(3, SHORT_WINDOW), count(3, 1));
+        // Minute 0 is the oldest of the short window at minute 4, and gone at minute 5
+        assert_eq!(window.count(4, SHORT_WINDOW), count(3, 1));
+        assert_eq!(window.count(5, SHORT_WINDOW), count(1, 0));
+        assert_eq!(window.count(5, LONG_WINDOW), count(3, 1));
+        assert_eq!(window.count(8, SHORT_WINDOW), count(0, 0));
+    }
+
+    #[test]
+    fn buckets_older_than_the_long_window_are_dropped() {
+        let mut window = RollingWindow::default();
+        for minute in 0..40 {
+            window.record(minute, minute % 2 == 0);
+        }
+        assert_eq!(window.buckets.len(), minutes(LONG_WINDOW) as usize);
+        assert_eq!(window.buckets.front().map(|b| b.minute), Some(10));
+        assert_eq!(window.count(39, LONG_WINDOW), count(30, 15));
+        // A gap of more than the long window leaves only the new minute
+        window.record(100, true);
+        assert_eq!(window.buckets.len(), 1);
+    }
+
+    #[test]
+    fn sparkline_has_one_character_per_minute() {
+        let mut window = RollingWindow::default();
+        window.record(29, false);
+        window.record(28, true);
+        let sparkline = window.sparkline(29);
+        assert_eq!(sparkline.chars().count(), 30);
+        assert!(sparkline.ends_with("▁█"), "{sparkline}");
+        assert!(sparkline.starts_with('·'));
+    }
+
+    #[test]
+    fn rate_needs_calls() {
+        assert_eq!(count(0, 0).rate(), None);
+        assert_eq!(count(4, 1).rate(), Some(0.25));
+    }
+
+    #[test]
+    fn incident_starts_at_the_threshold_with_enough_calls() {
+        let rates = ErrorRates::default();
+        // All failed, but too few calls
+        record(&rates, 0, MIN_INCIDENT_CALLS - 1, MIN_INCIDENT_CALLS - 1);
+        assert!(rates.check_at(SOURCE, 0).is_none());
+
+        // Ten of twenty calls failed, exactly the incident rate
+        record(&rates, 1, 11, 1);
+        assert!(matches!(
+            rates.check_at(SOURCE, 1),
+            Some(IncidentChange::Started(Incident { rate, .. })) if rate == 0.5
+        ));
+        assert!(rates.incident(SOURCE).is_some());
+        // Reported once
+        assert!(rates.check_at(SOURCE, 1).is_none());
+        assert!(rates.incident(ErrorSource::Discord).is_none());
+    }
+
+    #[test]
+    fn incident_ends_below_the_recovery_rate() {
+        let rates = ErrorRates::default();
+        record(&rates, 0, 10, 10);
+        assert!(rates.check_at(SOURCE, 0).is_some());
+
+        // Between both rates the incident goes on
+        record(&rates, 1, 30, 0);
+        assert!((0.2..0.5).contains(&rates.count_at(SOURCE, SHORT_WINDOW, 1).rate().unwrap()));
+        assert!(rates.check_at(SOURCE, 1).is_none());
+        assert!(rates.incident(SOURCE).is_some());
+
+        record(&rates, 2, 20, 0);
+        assert!(matches!(
+            rates.check_at(SOURCE, 2),
+            Some(IncidentChange::Recovered(_))
+        ));
+        assert!(rates.incident(SOURCE).is_none());
+    }
+
+    #[test]
+    fn incident_ends_once_its_calls_left_the_window() {
+        let rates = ErrorRates::default();
+        record(&rates, 0, 10, 10);
+        assert!(rates.check_at(SOURCE, 0).is_some());
+        assert!(rates.check_at(SOURCE, 4).is_none());
+        // No calls in the short window anymore
+        assert!(matches!(
+            rates.check_at(SOURCE, 5),
+            Some(IncidentChange::Recovered(_))
+        ));
+    }
+}
@@ -0,0 +1,95 @@
+use crate::end_reason::EndReason;
+use crate::guild_state::{GuildScoped, GuildStateKind};
+use serenity::all::GuildId;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tokio::sync::broadcast::{channel, Receiver, Sender};
+
+/// Capacity of the event buffer of each subscriber. Slow subscribers skip older events.
+const EVENT_BUFFER: usize = 64;
+/// Number of events per guild that are kept for debugging
+const RECENT_EVENTS: usize = 10;
+
+/// Something that happened to the playback of a guild
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaybackEvent {
+    TrackStarted,
+    QueueChanged,
+    /// Playback stopped and the queue was cleared
+    Stopped,
+    /// The current track was paused or resumed
+    PlayStateChanged,
+    TrackEnded {
+        reason: EndReason,
+    },
+}
+
+/// Broadcasts playback events of all guilds to everyone interested in them
+pub struct PlaybackEventBus {
+    sender: Sender<(GuildId, PlaybackEvent)>,
+    recent: Mutex<HashMap<GuildId, VecDeque<(SystemTime, PlaybackEvent)>>>,
+    /// Number of published events, so messages can tell which state is newer
+    sequence: AtomicU64,
+}
+
+impl Default for PlaybackEventBus {
+    fn default() -> Self {
+        Self {
+            sender: channel(EVENT_BUFFER).0,
+            recent: Mutex::new(HashMap::new()),
+            sequence: AtomicU64::new(0),
+        }
+    }
+}
+
+impl PlaybackEventBus {
+    pub fn publish(&self, guild_id: GuildId, event: PlaybackEvent) {
+        {
+            let mut recent = self.recent.lock().unwrap();
+            self.sequence.fetch_add(1, Ordering::Relaxed);
+            let recent = recent.entry(guild_id).or_default();
+            recent.truncate(RECENT_EVENTS - 1);
+            recent.push_front((SystemTime::now(), event));
+        }
+
+        // Sending only fails without subscribers, which is fine
+        _ = self.sender.send((guild_id, event));
+    }
+
+    /// Increases with every published event. A message rendered from the playback state carries
+    /// the sequence from before rendering, so it is never older than the state it shows.
+    pub fn sequence(&self) -> u64 {
+        self.sequence.load(Ordering::Relaxed)
+    }
+
+    pub fn subscribe(&self) -> Receiver<(GuildId, PlaybackEvent)> {
+        self.sender.subscribe()
+    }
+
+    /// The last events of a guild, newest first
+    pub fn recent(&self, guild_id: GuildId) -> Vec<(SystemTime, PlaybackEvent)> {
+        self.recent
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .map(|recent| recent.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl GuildScoped for PlaybackEventBus {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::Events
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        let recent = self.recent.lock().unwrap();
+        recent.iter().map(|(id, r)| (*id, r.len())).collect()
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.recent.lock().unwrap().remove(&guild_id);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::commands::util::{
+    get_free_notices, get_guild_settings, get_outbound, get_playback_events,
+};
+use crate::events::PlaybackEvent;
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use crate::notice_channel::{select_notice_channel, InvokingChannel, NoticeTarget};
+use serenity::all::{
+    ButtonStyle, ChannelId, ComponentInteraction, Context, CreateActionRow, CreateAllowedMentions,
+    CreateButton, CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    GuildId, Mentionable, UserId,
+};
+use serenity::Error as SerenityError;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+
+/// Custom id of the button on the occupied error. It is handled globally, the error message
+/// outlives the command that sent it.
+pub const NOTIFY_FREE_BUTTON_ID: &str = "notifyfree";
+/// Registrations are dropped after this time, a session that long is not waited for anymore
+const NOTICE_TTL: Duration = Duration::from_secs(3 * 60 * 60);
+
+pub fn notify_free_button() -> CreateActionRow {
+    CreateActionRow::Buttons(vec![CreateButton::new(NOTIFY_FREE_BUTTON_ID)
+        .label("Benachrichtigen, wenn frei")
+        .style(ButtonStyle::Secondary)])
+}
+
+/// A user waiting for the bot, pinged in the channel they asked in
+#[derive(Clone, Debug)]
+struct FreeNotice {
+    user_id: UserId,
+    channel_id: ChannelId,
+    registered_at: Instant,
+}
+
+/// Users who want to know when the bot is free again in a guild
+#[derive(Default)]
+pub struct FreeNotices {
+    guilds: GuildStateMap<Vec<FreeNotice>>,
+}
+
+impl FreeNotices {
+    /// Returns false if the user was already waiting. A repeated registration only moves the
+    /// ping to the new channel and restarts its expiry.
+    pub fn register(&self, guild_id: GuildId, user_id: UserId, channel_id: ChannelId) -> bool {
+        self.guilds.with_mut(guild_id, |notices| {
+            notices.retain(|notice| notice.registered_at.elapsed() < NOTICE_TTL);
+            let repeated = notices.iter().any(|notice| notice.user_id == user_id);
+            notices.retain(|notice| notice.user_id != user_id);
+            notices.push(FreeNotice {
+                user_id,
+                channel_id,
+                registered_at: Instant::now(),
+            });
+            !repeated
+        })
+    }
+
+    fn has_waiting(&self, guild_id: GuildId) -> bool {
+        self.guilds.contains(guild_id)
+    }
+
+    /// Removes all registrations of the guild and returns the users to ping per channel
+    fn take(&self, guild_id: GuildId) -> HashMap<ChannelId, Vec<UserId>> {
+        let mut channels: HashMap<ChannelId, Vec<UserId>> = HashMap::new();
+        for notice in self.guilds.remove(guild_id).unwrap_or_default() {
+            if notice.registered_at.elapsed() < NOTICE_TTL {
+                channels
+                    .entry(notice.channel_id)
+                    .or_default()
+                    .push(notice.user_id);
+            }
+        }
+        channels
+    }
+}
+
+impl GuildScoped for FreeNotices {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::FreeNotices
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.guilds.entry_counts(Vec::len)
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.guilds.remove_on_leave(guild_id);
+    }
+}
+
+/// Pings the waiting users of a guild once the bot left or its queue ran out. Runs until the
+/// process exits.
+pub async fn run_free_notices(ctx: Context, notices: Arc<FreeNotices>) {
+    let mut events = get_playback_events(&ctx).await.subscribe();
+    loop {
+        let guild_id = match events.recv().await {
+            Ok((guild_id, PlaybackEvent::Stopped)) => guild_id,
+            // The queue handler already removed the ended track
+            Ok((guild_id, PlaybackEvent::TrackEnded { .. }))
+                if notices.has_waiting(guild_id) && queue_empty(&ctx, guild_id).await =>
+            {
+                guild_id
+            }
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return,
+        };
+
+        for (channel_id, users) in notices.take(guild_id) {
+            let mentions = users
+                .iter()
+                .map(|user_id| user_id.mention().to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            let message = CreateMessage::new()
+                .content(format!(
+                    "{mentions} Der Bot ist jetzt frei und kann in deinen Sprachkanal geholt werden"
+                ))
+                .allowed_mentions(CreateAllowedMentions::new().users(users));
+            get_outbound(&ctx)
+                .await
+                .post(&ctx.http, guild_id, channel_id, None, message);
+        }
+    }
+}
+
+/// Registers the user for a ping in the channel they asked in, or the announce channel if the bot
+/// cannot post there, and returns the response for them. Nobody is registered while the bot is
+/// free anyway.
+pub async fn register_free_notice(
+    ctx: &Context,
+    guild_id: GuildId,
+    user_id: UserId,
+    invoking: &InvokingChannel,
+) -> String {
+    if queue_empty(ctx, guild_id).await {
+        return "Der Bot spielt gerade nichts und kann direkt geholt werden".to_owned();
+    }
+    let announce_channel = get_guild_settings(ctx).await.get(guild_id).announce_channel;
+    let Some(channel_id) = select_notice_channel(NoticeTarget::Invoker, invoking, announce_channel)
+    else {
+        return "Der Bot kann in diesem Kanal nicht schreiben und es ist kein Ankündigungskanal eingerichtet".to_owned();
+    };
+    match get_free_notices(ctx)
+        .await
+        .register(guild_id, user_id, channel_id)
+    {
+        true => format!(
+            "Du wirst in {} erwähnt, sobald der Bot frei ist",
+            channel_id.mention()
+        ),
+        false => format!(
+            "Du wirst bereits benachrichtigt, jetzt in {}",
+            channel_id.mention()
+        ),
+    }
+}
+
+/// Handles a press of the button on the occupied error
+pub async fn on_notify_free_pressed(
+    ctx: &Context,
+    press: &ComponentInteraction,
+) -> Result<(), SerenityError> {
+    let Some(guild_id) = press.guild_id else {
+        return Ok(());
+    };
+    let invoking = InvokingChannel::of_interaction(
+        &ctx.cache,
+        Some(guild_id),
+        press.channel_id,
+        press.channel.as_ref(),
+        press.app_permissions,
+    );
+    let details = register_free_notice(ctx, guild_id, press.user.id, &invoking).await;
+    press
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(details)
+                    .ephemeral(true),
+            ),
+        )
+        .await
+}
+
+async fn queue_empty(ctx: &Context, guild_id: GuildId) -> bool {
+    let call = match songbird::get(ctx).await {
+        Some(songbird) => songbird.get(guild_id),
+        None => None,
+    };
+    match call {
+        Some(call) => call.lock().await.queue().is_empty(),
+        None => true,
+    }
+}
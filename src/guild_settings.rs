@@ -0,0 +1,469 @@
+use crate::alias::AliasSet;
+use crate::lifecycle::GuildPersisted;
+use crate::locale::Locale;
+use crate::persistence::{PersistedFile, PersistenceHealth};
+use crate::settings_transfer::ExportedSettings;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, RoleId};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use time::UtcOffset;
+
+/// How visible the bot is in the text channels of a guild
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum QuietLevel {
+    #[default]
+    #[name = "Normal"]
+    Normal,
+    /// Every response is only visible to the author
+    #[name = "Leise"]
+    Quiet,
+    /// Like quiet, and nothing is posted without a command, like announcements
+    #[name = "Stumm"]
+    Silent,
+}
+
+impl QuietLevel {
+    pub fn private_responses(&self) -> bool {
+        *self != QuietLevel::Normal
+    }
+
+    pub fn allows_posts(&self) -> bool {
+        *self != QuietLevel::Silent
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            QuietLevel::Normal => "Normal",
+            QuietLevel::Quiet => "Leise, alle Antworten sind nur für den Nutzer sichtbar",
+            QuietLevel::Silent => {
+                "Stumm, alle Antworten sind nur für den Nutzer sichtbar und es gibt keine Ankündigungen"
+            }
+        }
+    }
+}
+
+/// Preferences of a guild that are changed by its moderators
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GuildSettings {
+    /// Whether /now_playing offers a button to share the track publicly
+    pub share_button: bool,
+    /// Overrides the interaction locale, also used for messages without an interaction
+    pub locale: Option<Locale>,
+    /// Offset for times entered in commands like /schedule, UTC if not set
+    pub utc_offset: Option<UtcOffset>,
+    /// Channel for messages without an interaction, instead of the channel of the command
+    pub announce_channel: Option<ChannelId>,
+    /// Members with this role may control the playback of others, like with "Move Members"
+    pub dj_role: Option<RoleId>,
+    /// Longer tracks are rejected, or only flagged with a soft limit
+    pub max_track_duration: Option<Duration>,
+    /// Whether tracks over `max_track_duration` are still added with a warning
+    pub soft_duration_limit: bool,
+    /// With a soft limit, longer tracks are rejected anyway
+    pub duration_hard_cap: Option<Duration>,
+    /// Voice channel the bot stays in even when nobody is listening
+    pub always_on_channel: Option<ChannelId>,
+    /// Playback pauses after this long if everyone in the channel is deafened
+    pub deafened_pause_delay: Option<Duration>,
+    /// Short command names that can be used on this server
+    pub aliases: AliasSet,
+    /// Whether the next track is announced in voice, if the host configured text-to-speech
+    pub tts_announcements: bool,
+    /// Enforced by [BotResponse](crate::response::BotResponse) and the outbound scheduler
+    pub quiet: QuietLevel,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        Self {
+            share_button: true,
+            locale: None,
+            utc_offset: None,
+            announce_channel: None,
+            dj_role: None,
+            max_track_duration: None,
+            soft_duration_limit: false,
+            duration_hard_cap: None,
+            always_on_channel: None,
+            deafened_pause_delay: None,
+            aliases: AliasSet::default(),
+            tts_announcements: false,
+            quiet: QuietLevel::Normal,
+        }
+    }
+}
+
+/// How the duration limits of a guild treat a track
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DurationVerdict {
+    Allowed,
+    /// Over the soft limit, added with a warning
+    OverSoftLimit {
+        limit: Duration,
+    },
+    Rejected {
+        limit: Duration,
+    },
+}
+
+impl GuildSettings {
+    /// Unknown durations of zero, like of live streams, are never limited
+    pub fn duration_verdict(&self, duration: Duration) -> DurationVerdict {
+        if duration.is_zero() {
+            return DurationVerdict::Allowed;
+        }
+        if !self.soft_duration_limit {
+            return match self.max_track_duration {
+                Some(limit) if duration > limit => DurationVerdict::Rejected { limit },
+                _ => DurationVerdict::Allowed,
+            };
+        }
+        match (self.max_track_duration, self.duration_hard_cap) {
+            (_, Some(cap)) if duration > cap => DurationVerdict::Rejected { limit: cap },
+            (Some(limit), _) if duration > limit => DurationVerdict::OverSoftLimit { limit },
+            _ => DurationVerdict::Allowed,
+        }
+    }
+}
+
+/// Settings of a guild together with the number of changes made to them, so a change that was
+/// prepared from older settings can be detected
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct VersionedSettings {
+    pub settings: GuildSettings,
+    pub version: u64,
+}
+
+/// The settings were changed since they were read
+#[derive(Clone, Copy, Debug)]
+pub struct SettingsConflict {
+    pub current: VersionedSettings,
+}
+
+/// The settings of a guild as they are written to the file
+#[derive(Serialize, Deserialize)]
+struct StoredSettings {
+    guild_id: GuildId,
+    settings: ExportedSettings,
+}
+
+/// Settings of all guilds, written to a file if one is configured
+pub struct GuildSettingsStore {
+    settings: Mutex<HashMap<GuildId, VersionedSettings>>,
+    file: PersistedFile,
+}
+
+impl GuildSettingsStore {
+    /// Loads the settings from the file, starts with the defaults if it does not exist yet or
+    /// can not be read. The versions start over, nothing prepared before a restart is pending.
+    pub fn load(file: Option<PathBuf>, health: Arc<PersistenceHealth>) -> Self {
+        let file = PersistedFile::new("guild settings", file, health);
+        let mut settings = HashMap::new();
+        for stored in file.load::<StoredSettings>() {
+            match stored.settings.into_settings() {
+                Ok(guild_settings) => {
+                    settings.insert(
+                        stored.guild_id,
+                        VersionedSettings {
+                            settings: guild_settings,
+                            version: 0,
+                        },
+                    );
+                }
+                Err(e) => warn!(
+                    "Dropping the stored settings of guild {}: {e}",
+                    stored.guild_id
+                ),
+            }
+        }
+        Self {
+            settings: Mutex::new(settings),
+            file,
+        }
+    }
+
+    fn persist(&self, settings: &HashMap<GuildId, VersionedSettings>) {
+        let stored = settings
+            .iter()
+            .map(|(guild_id, versioned)| StoredSettings {
+                guild_id: *guild_id,
+                settings: ExportedSettings::from(&versioned.settings),
+            })
+            .collect::<Vec<_>>();
+        self.file.save(&stored);
+    }
+
+    pub fn get(&self, guild_id: GuildId) -> GuildSettings {
+        self.get_versioned(guild_id).settings
+    }
+
+    pub fn get_versioned(&self, guild_id: GuildId) -> VersionedSettings {
+        self.settings
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Changes the settings of a guild and returns the new settings. For changes that only set
+    /// values, which can not be based on outdated settings.
+    pub fn update(&self, guild_id: GuildId, f: impl FnOnce(&mut GuildSettings)) -> GuildSettings {
+        let mut settings = self.settings.lock().unwrap();
+        let guild_settings = settings.entry(guild_id).or_default();
+        f(&mut guild_settings.settings);
+        guild_settings.version += 1;
+        let updated = guild_settings.settings;
+        self.persist(&settings);
+        updated
+    }
+
+    /// Changes the settings only if they are still at the version `expected`, for changes that
+    /// were prepared from settings shown to a user
+    pub fn compare_and_update(
+        &self,
+        guild_id: GuildId,
+        expected: u64,
+        f: impl FnOnce(&mut GuildSettings),
+    ) -> Result<VersionedSettings, SettingsConflict> {
+        let mut settings = self.settings.lock().unwrap();
+        let guild_settings = settings.entry(guild_id).or_default();
+        if guild_settings.version != expected {
+            return Err(SettingsConflict {
+                current: *guild_settings,
+            });
+        }
+        f(&mut guild_settings.settings);
+        guild_settings.version += 1;
+        let updated = *guild_settings;
+        self.persist(&settings);
+        Ok(updated)
+    }
+}
+
+impl GuildPersisted for GuildSettingsStore {
+    fn purge(&self, guild_id: GuildId) {
+        let mut settings = self.settings.lock().unwrap();
+        if settings.remove(&guild_id).is_some() {
+            self.persist(&settings);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alias::CommandAlias;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    fn limited(soft: bool, cap: Option<u64>) -> GuildSettings {
+        GuildSettings {
+            max_track_duration: Some(minutes(30)),
+            soft_duration_limit: soft,
+            duration_hard_cap: cap.map(minutes),
+            ..GuildSettings::default()
+        }
+    }
+
+    #[test]
+    fn no_limit_allows_everything() {
+        let settings = GuildSettings::default();
+        assert_eq!(
+            settings.duration_verdict(minutes(600)),
+            DurationVerdict::Allowed
+        );
+    }
+
+    #[test]
+    fn hard_limit_rejects_longer_tracks() {
+        let settings = limited(false, None);
+        assert_eq!(
+            settings.duration_verdict(minutes(30)),
+            DurationVerdict::Allowed
+        );
+        assert_eq!(
+            settings.duration_verdict(minutes(30) + Duration::from_secs(1)),
+            DurationVerdict::Rejected { limit: minutes(30) }
+        );
+        // The hard cap only applies to soft limits
+        assert_eq!(
+            limited(false, Some(60)).duration_verdict(minutes(45)),
+            DurationVerdict::Rejected { limit: minutes(30) }
+        );
+    }
+
+    #[test]
+    fn soft_limit_warns_up_to_the_hard_cap() {
+        let settings = limited(true, Some(60));
+        assert_eq!(
+            settings.duration_verdict(minutes(20)),
+            DurationVerdict::Allowed
+        );
+        assert_eq!(
+            settings.duration_verdict(minutes(32)),
+            DurationVerdict::OverSoftLimit { limit: minutes(30) }
+        );
+        assert_eq!(
+            settings.duration_verdict(minutes(60)),
+            DurationVerdict::OverSoftLimit { limit: minutes(30) }
+        );
+        assert_eq!(
+            settings.duration_verdict(minutes(61)),
+            DurationVerdict::Rejected { limit: minutes(60) }
+        );
+    }
+
+    #[test]
+    fn soft_limit_without_a_cap_never_rejects() {
+        assert_eq!(
+            limited(true, None).duration_verdict(minutes(600)),
+            DurationVerdict::OverSoftLimit { limit: minutes(30) }
+        );
+    }
+
+    #[test]
+    fn hard_cap_without_a_soft_limit_still_rejects() {
+        let settings = GuildSettings {
+            soft_duration_limit: true,
+            duration_hard_cap: Some(minutes(60)),
+            ..GuildSettings::default()
+        };
+        assert_eq!(
+            settings.duration_verdict(minutes(45)),
+            DurationVerdict::Allowed
+        );
+        assert_eq!(
+            settings.duration_verdict(minutes(61)),
+            DurationVerdict::Rejected { limit: minutes(60) }
+        );
+    }
+
+    #[test]
+    fn live_streams_are_never_limited() {
+        for settings in [limited(false, None), limited(true, Some(60))] {
+            assert_eq!(
+                settings.duration_verdict(Duration::ZERO),
+                DurationVerdict::Allowed
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn settings_survive_a_restart() {
+        let path =
+            std::env::temp_dir().join(format!("gerbot-guild-settings-{}.json", std::process::id()));
+        let health = Arc::new(PersistenceHealth::default());
+
+        let store = GuildSettingsStore::load(Some(path.clone()), health.clone());
+        let saved = store.update(GUILD, |settings| {
+            settings.share_button = false;
+            settings.utc_offset = Some(UtcOffset::from_hms(2, 0, 0).unwrap());
+            settings.max_track_duration = Some(Duration::from_secs(600));
+            settings.dj_role = Some(RoleId::new(5));
+            settings.aliases.set(CommandAlias::P, true);
+            settings.quiet = QuietLevel::Quiet;
+        });
+
+        let reloaded = GuildSettingsStore::load(Some(path.clone()), health);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.get(GUILD), saved);
+        assert_eq!(reloaded.get(GuildId::new(2)), GuildSettings::default());
+    }
+
+    #[test]
+    fn changes_from_the_same_version_conflict() {
+        let store = GuildSettingsStore::load(None, Arc::default());
+        let shown = store.get_versioned(GUILD);
+
+        let first = store
+            .compare_and_update(GUILD, shown.version, |settings| {
+                settings.dj_role = Some(RoleId::new(5))
+            })
+            .unwrap();
+        let conflict = store
+            .compare_and_update(GUILD, shown.version, |settings| {
+                settings.tts_announcements = true
+            })
+            .unwrap_err();
+        assert_eq!(conflict.current, first);
+        assert!(!store.get(GUILD).tts_announcements);
+
+        // Retried from the settings it conflicted with, the first change stays
+        let retried = store
+            .compare_and_update(GUILD, conflict.current.version, |settings| {
+                settings.tts_announcements = true
+            })
+            .unwrap();
+        assert_eq!(retried.version, first.version + 1);
+        assert_eq!(retried.settings.dj_role, Some(RoleId::new(5)));
+        assert!(retried.settings.tts_announcements);
+    }
+
+    #[test]
+    fn concurrent_retries_never_revert_a_field() {
+        let store = GuildSettingsStore::load(None, Arc::default());
+        let changes: [fn(&mut GuildSettings); 4] = [
+            |settings| settings.share_button = false,
+            |settings| settings.soft_duration_limit = true,
+            |settings| settings.tts_announcements = true,
+            |settings| settings.quiet = QuietLevel::Silent,
+        ];
+        std::thread::scope(|scope| {
+            for change in changes {
+                let store = &store;
+                scope.spawn(move || {
+                    let mut shown = store.get_versioned(GUILD);
+                    // Every attempt starts from what the user would be shown after a conflict
+                    while let Err(conflict) = store.compare_and_update(GUILD, shown.version, change)
+                    {
+                        shown = conflict.current;
+                    }
+                });
+            }
+        });
+
+        let versioned = store.get_versioned(GUILD);
+        assert_eq!(versioned.version, changes.len() as u64);
+        let settings = versioned.settings;
+        assert!(!settings.share_button);
+        assert!(settings.soft_duration_limit);
+        assert!(settings.tts_announcements);
+        assert_eq!(settings.quiet, QuietLevel::Silent);
+    }
+
+    #[test]
+    fn plain_updates_count_as_changes() {
+        let store = GuildSettingsStore::load(None, Arc::default());
+        let shown = store.get_versioned(GUILD);
+        store.update(GUILD, |settings| settings.tts_announcements = true);
+        assert!(store
+            .compare_and_update(GUILD, shown.version, |settings| {
+                settings.tts_announcements = false
+            })
+            .is_err());
+        assert!(store.get(GUILD).tts_announcements);
+    }
+
+    #[tokio::test]
+    async fn purge_resets_only_the_guild() {
+        let store = GuildSettingsStore::load(None, Arc::default());
+        let other = GuildId::new(2);
+        store.update(GUILD, |settings| settings.tts_announcements = true);
+        store.update(other, |settings| settings.tts_announcements = true);
+        store.purge(GUILD);
+        assert_eq!(store.get(GUILD), GuildSettings::default());
+        assert!(store.get(other).tts_announcements);
+    }
+}
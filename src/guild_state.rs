@@ -0,0 +1,511 @@
+use crate::lifecycle::GuildLifecycle;
+use log::info;
+use serenity::all::GuildId;
+use songbird::Songbird;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::interval;
+
+/// Guilds without commands or an active call for this long lose their in-memory state
+pub const IDLE_STATE_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+const JANITOR_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Every kind of in-memory state kept per guild. Each kind has exactly one store, which is
+/// checked when the [GuildState] is created, so no store can be forgotten by the janitor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GuildStateKind {
+    Events,
+    Departures,
+    History,
+    Modes,
+    EmbedHints,
+    Staging,
+    Diagnostics,
+    Undo,
+    Outages,
+    AutoPauses,
+    Positions,
+    EndMarkers,
+    Autoplay,
+    Reports,
+    ResumePoints,
+    FreeNotices,
+}
+
+impl GuildStateKind {
+    pub const ALL: [GuildStateKind; 16] = [
+        GuildStateKind::Events,
+        GuildStateKind::Departures,
+        GuildStateKind::History,
+        GuildStateKind::Modes,
+        GuildStateKind::EmbedHints,
+        GuildStateKind::Staging,
+        GuildStateKind::Diagnostics,
+        GuildStateKind::Undo,
+        GuildStateKind::Outages,
+        GuildStateKind::AutoPauses,
+        GuildStateKind::Positions,
+        GuildStateKind::EndMarkers,
+        GuildStateKind::Autoplay,
+        GuildStateKind::Reports,
+        GuildStateKind::ResumePoints,
+        GuildStateKind::FreeNotices,
+    ];
+
+    /// Name for the status output
+    pub fn name(&self) -> &'static str {
+        match self {
+            GuildStateKind::Events => "Ereignisse",
+            GuildStateKind::Departures => "Verlassen",
+            GuildStateKind::History => "Verlauf",
+            GuildStateKind::Modes => "Modi",
+            GuildStateKind::EmbedHints => "Embed-Hinweise",
+            GuildStateKind::Staging => "Vorgemerkt",
+            GuildStateKind::Diagnostics => "Diagnose",
+            GuildStateKind::Undo => "Rückgängig",
+            GuildStateKind::Outages => "Ausfälle",
+            GuildStateKind::AutoPauses => "Auto-Pausen",
+            GuildStateKind::Positions => "Positionen",
+            GuildStateKind::EndMarkers => "Endgründe",
+            GuildStateKind::Autoplay => "Autoplay",
+            GuildStateKind::Reports => "Meldungen",
+            GuildStateKind::ResumePoints => "Fortsetzungspunkte",
+            GuildStateKind::FreeNotices => "Frei-Benachrichtigungen",
+        }
+    }
+}
+
+/// In-memory state that is kept separately for every guild and can be dropped when the guild
+/// is no longer used. Persistent configuration like the guild settings is not part of this.
+pub trait GuildScoped: Send + Sync {
+    fn kind(&self) -> GuildStateKind;
+    /// Number of stored entries for each guild with state
+    fn entry_counts(&self) -> HashMap<GuildId, usize>;
+    /// Drops everything stored for the guild
+    fn forget(&self, guild_id: GuildId);
+}
+
+/// Map from a guild to its state, for stores with at most one value per guild. Only hands out
+/// the value of the requested guild, so state can never be read or changed across guilds.
+pub struct GuildStateMap<T> {
+    values: Mutex<HashMap<GuildId, T>>,
+}
+
+impl<T> Default for GuildStateMap<T> {
+    fn default() -> Self {
+        Self {
+            values: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T> GuildStateMap<T> {
+    pub fn contains(&self, guild_id: GuildId) -> bool {
+        self.values.lock().unwrap().contains_key(&guild_id)
+    }
+
+    /// Returns the previous value
+    pub fn insert(&self, guild_id: GuildId, value: T) -> Option<T> {
+        self.values.lock().unwrap().insert(guild_id, value)
+    }
+
+    pub fn remove(&self, guild_id: GuildId) -> Option<T> {
+        self.values.lock().unwrap().remove(&guild_id)
+    }
+
+    /// Runs `f` on the value of the guild, without creating one
+    pub fn with<R>(&self, guild_id: GuildId, f: impl FnOnce(Option<&T>) -> R) -> R {
+        f(self.values.lock().unwrap().get(&guild_id))
+    }
+
+    /// Number of entries of every guild with a value, as counted by `count`
+    pub fn entry_counts(&self, count: impl Fn(&T) -> usize) -> HashMap<GuildId, usize> {
+        let values = self.values.lock().unwrap();
+        values.iter().map(|(id, v)| (*id, count(v))).collect()
+    }
+
+    /// Drops the values of all guilds
+    pub fn clear(&self) {
+        self.values.lock().unwrap().clear();
+    }
+
+    /// Drops the value of a guild that removed the bot or was evicted by the janitor
+    pub fn remove_on_leave(&self, guild_id: GuildId) {
+        self.values.lock().unwrap().remove(&guild_id);
+    }
+}
+
+impl<T: Clone> GuildStateMap<T> {
+    pub fn get(&self, guild_id: GuildId) -> Option<T> {
+        self.values.lock().unwrap().get(&guild_id).cloned()
+    }
+}
+
+impl<T: Default> GuildStateMap<T> {
+    /// Runs `f` on the value of the guild, starting from the default
+    pub fn with_mut<R>(&self, guild_id: GuildId, f: impl FnOnce(&mut T) -> R) -> R {
+        f(self.values.lock().unwrap().entry(guild_id).or_default())
+    }
+}
+
+impl<T: Clone + Default> GuildStateMap<T> {
+    /// The value of the guild, or the default without storing it
+    pub fn get_or_default(&self, guild_id: GuildId) -> T {
+        self.get(guild_id).unwrap_or_default()
+    }
+
+    /// Changes the value of the guild, starting from the default, and returns the new value
+    pub fn update(&self, guild_id: GuildId, f: impl FnOnce(&mut T)) -> T {
+        let mut values = self.values.lock().unwrap();
+        let value = values.entry(guild_id).or_default();
+        f(value);
+        value.clone()
+    }
+}
+
+/// Guilds with live state and their approximate number of entries
+#[derive(Clone, Debug, Default)]
+pub struct GuildStateGauge {
+    pub guilds: usize,
+    pub entries: usize,
+    pub per_store: Vec<(&'static str, usize)>,
+}
+
+/// All per-guild stores, together with the last activity of each guild
+pub struct GuildState {
+    stores: Vec<Arc<dyn GuildScoped>>,
+    last_activity: Mutex<HashMap<GuildId, Instant>>,
+}
+
+impl GuildState {
+    /// Panics unless every [GuildStateKind] has exactly one store
+    pub fn new(stores: Vec<Arc<dyn GuildScoped>>) -> Self {
+        for kind in GuildStateKind::ALL {
+            let registered = stores.iter().filter(|store| store.kind() == kind).count();
+            assert_eq!(
+                registered, 1,
+                "Guild state {kind:?} must have exactly one store"
+            );
+        }
+        assert_eq!(stores.len(), GuildStateKind::ALL.len());
+
+        Self {
+            stores,
+            last_activity: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn touch(&self, guild_id: GuildId) {
+        self.last_activity
+            .lock()
+            .unwrap()
+            .insert(guild_id, Instant::now());
+    }
+
+    /// Drops everything stored for the guild in all stores
+    pub fn forget(&self, guild_id: GuildId) {
+        self.last_activity.lock().unwrap().remove(&guild_id);
+        for store in &self.stores {
+            store.forget(guild_id);
+        }
+    }
+
+    pub fn gauge(&self) -> GuildStateGauge {
+        let mut guilds = HashSet::new();
+        let mut gauge = GuildStateGauge::default();
+        for store in &self.stores {
+            let counts = store.entry_counts();
+            let entries = counts.values().sum();
+            guilds.extend(counts.into_keys());
+            gauge.entries += entries;
+            gauge.per_store.push((store.kind().name(), entries));
+        }
+        gauge.guilds = guilds.len();
+        gauge
+    }
+
+    /// Number of entries of every kind of state stored for the guild, including empty ones
+    pub fn entries_of(&self, guild_id: GuildId) -> Vec<(GuildStateKind, usize)> {
+        self.stores
+            .iter()
+            .map(|store| {
+                let entries = store
+                    .entry_counts()
+                    .get(&guild_id)
+                    .copied()
+                    .unwrap_or_default();
+                (store.kind(), entries)
+            })
+            .collect()
+    }
+
+    /// Drops the state of all guilds that were idle for longer than `timeout`. Guilds in
+    /// `active` are never touched. Guilds with state but without any recorded activity count as
+    /// active from now on, so nothing is dropped before it had the chance to be used.
+    pub fn evict_idle(
+        &self,
+        now: Instant,
+        timeout: Duration,
+        active: &HashSet<GuildId>,
+    ) -> Vec<GuildId> {
+        let with_state = self
+            .stores
+            .iter()
+            .flat_map(|store| store.entry_counts().into_keys())
+            .collect::<HashSet<_>>();
+
+        let mut last_activity = self.last_activity.lock().unwrap();
+        for guild_id in active {
+            last_activity.insert(*guild_id, now);
+        }
+        for guild_id in &with_state {
+            last_activity.entry(*guild_id).or_insert(now);
+        }
+
+        let idle = last_activity
+            .iter()
+            .filter(|(_, at)| now.saturating_duration_since(**at) >= timeout)
+            .map(|(guild_id, _)| *guild_id)
+            .collect::<Vec<_>>();
+        drop(last_activity);
+        for guild_id in &idle {
+            self.forget(*guild_id);
+        }
+
+        idle
+    }
+}
+
+/// Periodically drops the state of idle guilds and the data of guilds that removed the bot.
+/// Runs until the process exits.
+pub async fn run_janitor(
+    state: Arc<GuildState>,
+    lifecycle: Arc<GuildLifecycle>,
+    songbird: Arc<Songbird>,
+) {
+    let mut ticks = interval(JANITOR_INTERVAL);
+    loop {
+        ticks.tick().await;
+
+        let mut active = HashSet::new();
+        for (guild_id, call) in songbird.iter() {
+            if call.lock().await.current_channel().is_some() {
+                active.insert(GuildId::new(guild_id.0.get()));
+            }
+        }
+
+        let evicted = state.evict_idle(Instant::now(), IDLE_STATE_TIMEOUT, &active);
+        if !evicted.is_empty() {
+            info!("Dropped the state of {} idle guilds", evicted.len());
+        }
+        let purged = lifecycle.purge_due(Instant::now());
+        if !purged.is_empty() {
+            info!("Deleted the data of {} removed guilds", purged.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const OTHER_GUILD: GuildId = GuildId::new(2);
+
+    /// A store of one kind counting entries per guild
+    struct FakeStore {
+        kind: GuildStateKind,
+        entries: GuildStateMap<usize>,
+    }
+
+    impl GuildScoped for FakeStore {
+        fn kind(&self) -> GuildStateKind {
+            self.kind
+        }
+
+        fn entry_counts(&self) -> HashMap<GuildId, usize> {
+            self.entries.entry_counts(|count| *count)
+        }
+
+        fn forget(&self, guild_id: GuildId) {
+            self.entries.remove_on_leave(guild_id);
+        }
+    }
+
+    /// A state with one fake store for every kind
+    fn fake_state() -> (GuildState, Vec<Arc<FakeStore>>) {
+        let stores = GuildStateKind::ALL
+            .into_iter()
+            .map(|kind| {
+                Arc::new(FakeStore {
+                    kind,
+                    entries: GuildStateMap::default(),
+                })
+            })
+            .collect::<Vec<_>>();
+        let state = GuildState::new(
+            stores
+                .iter()
+                .map(|store| store.clone() as Arc<dyn GuildScoped>)
+                .collect(),
+        );
+        (state, stores)
+    }
+
+    #[test]
+    fn idle_guilds_lose_their_state_after_the_timeout() {
+        let (state, stores) = fake_state();
+        stores[0].entries.insert(GUILD, 3);
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+
+        // The first run only notices the guild
+        assert!(state.evict_idle(start, timeout, &HashSet::new()).is_empty());
+        assert!(state
+            .evict_idle(start + timeout / 2, timeout, &HashSet::new())
+            .is_empty());
+        assert_eq!(
+            state.evict_idle(start + timeout, timeout, &HashSet::new()),
+            [GUILD]
+        );
+        assert!(!stores[0].entries.contains(GUILD));
+    }
+
+    #[test]
+    fn activity_postpones_the_eviction() {
+        let (state, stores) = fake_state();
+        stores[0].entries.insert(GUILD, 3);
+        let timeout = Duration::from_millis(50);
+
+        state.touch(GUILD);
+        assert!(state
+            .evict_idle(Instant::now(), timeout, &HashSet::new())
+            .is_empty());
+        assert_eq!(
+            state.evict_idle(Instant::now() + timeout, timeout, &HashSet::new()),
+            [GUILD]
+        );
+    }
+
+    #[test]
+    fn guilds_with_an_active_call_are_never_evicted() {
+        let (state, stores) = fake_state();
+        stores[0].entries.insert(GUILD, 3);
+        stores[0].entries.insert(OTHER_GUILD, 1);
+        let active = HashSet::from([GUILD]);
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+
+        state.evict_idle(start, timeout, &active);
+        for hours in 1..48 {
+            let now = start + Duration::from_secs(hours * 60 * 60);
+            let evicted = state.evict_idle(now, timeout, &active);
+            assert!(!evicted.contains(&GUILD));
+        }
+        assert!(stores[0].entries.contains(GUILD));
+        assert!(!stores[0].entries.contains(OTHER_GUILD));
+    }
+
+    /// Puts an entry of every kind into both guilds
+    fn fill(stores: &[Arc<FakeStore>]) {
+        for store in stores {
+            store.entries.insert(GUILD, 2);
+            store.entries.insert(OTHER_GUILD, 1);
+        }
+    }
+
+    #[test]
+    fn leaving_clears_every_kind_at_once() {
+        let (state, stores) = fake_state();
+        fill(&stores);
+
+        state.forget(GUILD);
+        for store in &stores {
+            assert!(!store.entries.contains(GUILD), "{:?}", store.kind);
+            assert_eq!(store.entries.get(OTHER_GUILD), Some(1), "{:?}", store.kind);
+        }
+        assert!(state.entries_of(GUILD).iter().all(|(_, count)| *count == 0));
+        assert_eq!(state.entries_of(GUILD).len(), GuildStateKind::ALL.len());
+    }
+
+    #[test]
+    fn eviction_clears_every_kind_of_the_idle_guild() {
+        let (state, stores) = fake_state();
+        fill(&stores);
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+
+        state.evict_idle(start, timeout, &HashSet::new());
+        // The other guild was used in the meantime
+        let evicted = state.evict_idle(start + timeout, timeout, &HashSet::from([OTHER_GUILD]));
+        assert_eq!(evicted, [GUILD]);
+        for store in &stores {
+            assert!(!store.entries.contains(GUILD), "{:?}", store.kind);
+            assert!(store.entries.contains(OTHER_GUILD), "{:?}", store.kind);
+        }
+    }
+
+    #[test]
+    fn gauge_counts_guilds_once() {
+        let (state, stores) = fake_state();
+        fill(&stores);
+        let gauge = state.gauge();
+        assert_eq!(gauge.guilds, 2);
+        assert_eq!(gauge.entries, 3 * GuildStateKind::ALL.len());
+        assert_eq!(gauge.per_store.len(), GuildStateKind::ALL.len());
+    }
+
+    #[test]
+    fn maps_keep_guilds_apart() {
+        let map = GuildStateMap::<Vec<u32>>::default();
+        map.update(GUILD, |values| values.push(1));
+        map.update(OTHER_GUILD, |values| values.push(2));
+        assert_eq!(map.get_or_default(GUILD), [1]);
+        assert_eq!(map.get_or_default(OTHER_GUILD), [2]);
+        assert!(map.get_or_default(GuildId::new(3)).is_empty());
+        // Reading a default does not store it
+        assert!(!map.contains(GuildId::new(3)));
+
+        map.remove_on_leave(GUILD);
+        assert!(!map.contains(GUILD));
+        assert_eq!(map.get(OTHER_GUILD), Some(vec![2]));
+    }
+
+    #[test]
+    #[should_panic(expected = "must have exactly one store")]
+    fn every_kind_needs_a_store() {
+        let (_, mut stores) = fake_state();
+        stores.pop();
+        GuildState::new(
+            stores
+                .into_iter()
+                .map(|store| store as Arc<dyn GuildScoped>)
+                .collect(),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "must have exactly one store")]
+    fn no_kind_may_have_two_stores() {
+        let (_, stores) = fake_state();
+        let mut stores = stores
+            .into_iter()
+            .map(|store| store as Arc<dyn GuildScoped>)
+            .collect::<Vec<_>>();
+        stores.push(Arc::new(FakeStore {
+            kind: GuildStateKind::ALL[0],
+            entries: GuildStateMap::default(),
+        }));
+        GuildState::new(stores);
+    }
+
+    #[test]
+    fn guilds_without_state_are_not_reported() {
+        let (state, _) = fake_state();
+        let start = Instant::now();
+        assert!(state
+            .evict_idle(start, Duration::ZERO, &HashSet::new())
+            .is_empty());
+    }
+}
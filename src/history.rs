@@ -0,0 +1,209 @@
+use crate::canonical_url::same_track;
+use crate::end_reason::EndReason;
+use crate::guild_state::{GuildScoped, GuildStateKind};
+use serenity::all::{GuildId, UserId};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Number of remembered tracks per guild
+const HISTORY_SIZE: usize = 100;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub title: String,
+    pub url: String,
+    /// The queue entry that played, to attach how it ended
+    pub track: Uuid,
+    /// None while the track is still playing
+    pub ended: Option<TrackEnd>,
+}
+
+/// How a played track ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrackEnd {
+    pub reason: EndReason,
+    /// Who skipped or stopped it, none if it ended by itself or the bot ended it
+    pub actor: Option<UserId>,
+    pub position: Duration,
+    /// Why playback failed, only for errors
+    pub error: Option<&'static str>,
+}
+
+/// Recently played tracks of every guild, newest first
+#[derive(Default)]
+pub struct PlayHistory {
+    guilds: Mutex<HashMap<GuildId, VecDeque<HistoryEntry>>>,
+}
+
+impl PlayHistory {
+    /// Adds a track that started playing. Replays move the track to the front.
+    pub fn record(&self, guild_id: GuildId, track: Uuid, title: &str, url: &str) {
+        let mut guilds = self.guilds.lock().unwrap();
+        let history = guilds.entry(guild_id).or_default();
+
+        history.retain(|entry| !same_track(&entry.url, url));
+        history.truncate(HISTORY_SIZE - 1);
+        history.push_front(HistoryEntry {
+            title: title.to_owned(),
+            url: url.to_owned(),
+            track,
+            ended: None,
+        });
+    }
+
+    /// Attaches how a track ended to its entry. Tracks that never started have none.
+    pub fn finish(&self, guild_id: GuildId, track: Uuid, end: TrackEnd) {
+        let mut guilds = self.guilds.lock().unwrap();
+        if let Some(entry) = guilds
+            .get_mut(&guild_id)
+            .and_then(|history| history.iter_mut().find(|entry| entry.track == track))
+        {
+            entry.ended = Some(end);
+        }
+    }
+
+    /// The last played tracks, newest first
+    pub fn recent(&self, guild_id: GuildId, count: usize) -> Vec<HistoryEntry> {
+        let guilds = self.guilds.lock().unwrap();
+        let Some(history) = guilds.get(&guild_id) else {
+            return vec![];
+        };
+        history.iter().take(count).cloned().collect()
+    }
+
+    /// Entries whose title contains `partial`, case-insensitive. Prefix matches come before
+    /// other matches, both sorted by recency.
+    pub fn suggest(&self, guild_id: GuildId, partial: &str, count: usize) -> Vec<HistoryEntry> {
+        let guilds = self.guilds.lock().unwrap();
+        let Some(history) = guilds.get(&guild_id) else {
+            return vec![];
+        };
+
+        rank_matches(history.iter(), partial)
+            .into_iter()
+            .take(count)
+            .cloned()
+            .collect()
+    }
+}
+
+impl GuildScoped for PlayHistory {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::History
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        let guilds = self.guilds.lock().unwrap();
+        guilds.iter().map(|(id, h)| (*id, h.len())).collect()
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.guilds.lock().unwrap().remove(&guild_id);
+    }
+}
+
+/// Case-insensitive title matches in the order of `entries`, with prefix matches first
+pub fn rank_matches<'a>(
+    entries: impl Iterator<Item = &'a HistoryEntry>,
+    partial: &str,
+) -> Vec<&'a HistoryEntry> {
+    let partial = partial.to_lowercase();
+    let (mut prefix, substring): (Vec<_>, Vec<_>) = entries
+        .filter_map(|entry| {
+            let title = entry.title.to_lowercase();
+            if title.starts_with(&partial) {
+                Some((true, entry))
+            } else if title.contains(&partial) {
+                Some((false, entry))
+            } else {
+                None
+            }
+        })
+        .partition(|(is_prefix, _)| *is_prefix);
+
+    prefix.extend(substring);
+    prefix.into_iter().map(|(_, entry)| entry).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    /// A history with the given titles, the last one played most recently
+    fn history(titles: &[&str]) -> PlayHistory {
+        let history = PlayHistory::default();
+        for (i, title) in titles.iter().enumerate() {
+            let url = format!("https://www.youtube.com/watch?v=video{i:05}");
+            history.record(GUILD, Uuid::new_v4(), title, &url);
+        }
+        history
+    }
+
+    fn titles(entries: Vec<HistoryEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.title).collect()
+    }
+
+    #[test]
+    fn prefix_matches_come_before_substring_matches() {
+        let history = history(&["Rock Anthem", "Best of Rock", "Rocket Man", "Jazz"]);
+        assert_eq!(
+            titles(history.suggest(GUILD, "rock", 5)),
+            ["Rocket Man", "Rock Anthem", "Best of Rock"]
+        );
+    }
+
+    #[test]
+    fn most_recent_first_within_a_group() {
+        let history = history(&["Live Song A", "Song B", "Live Song C", "Song D"]);
+        assert_eq!(
+            titles(history.suggest(GUILD, "song", 5)),
+            ["Song D", "Song B", "Live Song C", "Live Song A"]
+        );
+    }
+
+    #[test]
+    fn matching_ignores_case() {
+        let history = history(&["ÄRZTE - Schrei nach Liebe", "Other"]);
+        assert_eq!(
+            titles(history.suggest(GUILD, "ärzte", 5)),
+            ["ÄRZTE - Schrei nach Liebe"]
+        );
+        assert_eq!(
+            titles(history.suggest(GUILD, "LIEBE", 5)),
+            ["ÄRZTE - Schrei nach Liebe"]
+        );
+    }
+
+    #[test]
+    fn suggestions_are_limited_and_per_guild() {
+        let history = history(&["a 1", "a 2", "a 3", "a 4"]);
+        assert_eq!(titles(history.suggest(GUILD, "a", 2)), ["a 4", "a 3"]);
+        assert!(history.suggest(GUILD, "zzz", 5).is_empty());
+        assert!(history.suggest(GuildId::new(2), "a", 5).is_empty());
+    }
+
+    #[test]
+    fn replays_move_to_the_front() {
+        let history = history(&["Song A", "Song B"]);
+        history.record(
+            GUILD,
+            Uuid::new_v4(),
+            "Song A",
+            "https://www.youtube.com/watch?v=video00000",
+        );
+        assert_eq!(
+            titles(history.suggest(GUILD, "song", 5)),
+            ["Song A", "Song B"]
+        );
+    }
+
+    #[test]
+    fn empty_input_matches_everything_newest_first() {
+        let history = history(&["First", "Second"]);
+        assert_eq!(titles(history.suggest(GUILD, "", 5)), ["Second", "First"]);
+    }
+}
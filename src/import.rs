@@ -0,0 +1,349 @@
+use crate::commands::util::{get_yt_id_from_url, truncate_chars};
+use crate::saved_playlists::PlaylistOptions;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+
+/// Formats listed when a file is not recognized
+pub const SUPPORTED_FORMATS: &str = "- Textdatei mit einem Link pro Zeile\n- Export der gespeicherten Playlists dieses Bots (`[{\"name\", \"playlist_id\", \"options\"}]`)\n- JSON mit Liedern (`{\"tracks\": [{\"url\", \"title\"}]}`)";
+
+/// Invalid input is only shown in part, a whole line could be anything
+const SHOWN_INPUT_CHARS: usize = 80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    UrlList,
+    /// The file of the saved playlists, as written with `SAVED_PLAYLISTS_FILE`
+    SavedPlaylists,
+    /// `{"tracks": [{"url": ..., "title": ...}]}` as exported by most other bots
+    Tracks,
+}
+
+impl ImportFormat {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            ImportFormat::UrlList => "Liste von Links",
+            ImportFormat::SavedPlaylists => "Gespeicherte Playlists",
+            ImportFormat::Tracks => "JSON mit Liedern",
+        }
+    }
+
+    /// Rows of text files are lines, rows of JSON files the entries of their list
+    pub fn row_name(&self) -> &'static str {
+        match self {
+            ImportFormat::UrlList => "Zeile",
+            ImportFormat::SavedPlaylists | ImportFormat::Tracks => "Eintrag",
+        }
+    }
+}
+
+/// A valid row of an imported file
+#[derive(Clone, Debug)]
+pub struct ImportedRow {
+    /// Line or entry, counted from 1
+    pub row: usize,
+    pub url: Url,
+    pub title: Option<String>,
+    /// Only set by the saved playlists format
+    pub name: Option<String>,
+    pub options: PlaylistOptions,
+}
+
+impl ImportedRow {
+    fn new(row: usize, url: Url) -> Self {
+        Self {
+            row,
+            url,
+            title: None,
+            name: None,
+            options: PlaylistOptions::default(),
+        }
+    }
+
+    /// The YouTube playlist the row links to, if any
+    pub fn playlist_id(&self) -> Option<String> {
+        get_yt_id_from_url(self.url.as_str()).playlist_id
+    }
+}
+
+/// A row that was skipped and why
+#[derive(Clone, Debug)]
+pub struct ImportProblem {
+    pub row: usize,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub struct ParsedImport {
+    pub format: ImportFormat,
+    pub rows: Vec<ImportedRow>,
+    pub problems: Vec<ImportProblem>,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown import format")]
+pub struct UnknownFormat;
+
+#[derive(Deserialize)]
+struct ExportedPlaylist {
+    name: String,
+    playlist_id: String,
+    #[serde(default)]
+    options: PlaylistOptions,
+}
+
+#[derive(Deserialize)]
+struct ExportedTrack {
+    url: String,
+    title: Option<String>,
+}
+
+/// Detects the format of the file and collects its valid rows. Invalid rows are reported with
+/// their line or entry instead of failing the whole file.
+pub fn parse_import(content: &str) -> Result<ParsedImport, UnknownFormat> {
+    let trimmed = content.trim_start_matches('\u{feff}').trim();
+    match trimmed.chars().next() {
+        Some('[' | '{') => parse_json(trimmed),
+        Some(_) => parse_url_list(trimmed),
+        None => Err(UnknownFormat),
+    }
+}
+
+fn parse_url_list(content: &str) -> Result<ParsedImport, UnknownFormat> {
+    let mut rows = vec![];
+    let mut problems = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_url(line) {
+            Ok(url) => rows.push(ImportedRow::new(i + 1, url)),
+            Err(reason) => problems.push(ImportProblem { row: i + 1, reason }),
+        }
+    }
+    // A text file without a single link is something else entirely
+    if rows.is_empty() {
+        return Err(UnknownFormat);
+    }
+
+    Ok(ParsedImport {
+        format: ImportFormat::UrlList,
+        rows,
+        problems,
+    })
+}
+
+fn parse_json(content: &str) -> Result<ParsedImport, UnknownFormat> {
+    let entries = match serde_json::from_str::<Value>(content).map_err(|_| UnknownFormat)? {
+        Value::Array(entries) => entries,
+        Value::Object(mut object) => match object.remove("tracks") {
+            Some(Value::Array(entries)) => entries,
+            _ => return Err(UnknownFormat),
+        },
+        _ => return Err(UnknownFormat),
+    };
+    // Saved playlists are the only format with playlist ids
+    let format = match entries.iter().find_map(Value::as_object) {
+        Some(first) if first.contains_key("playlist_id") => ImportFormat::SavedPlaylists,
+        Some(first) if first.contains_key("url") => ImportFormat::Tracks,
+        _ => return Err(UnknownFormat),
+    };
+
+    let mut rows = vec![];
+    let mut problems = vec![];
+    for (i, entry) in entries.into_iter().enumerate() {
+        let row = i + 1;
+        let parsed = match format {
+            ImportFormat::SavedPlaylists => parse_playlist_entry(row, entry),
+            _ => parse_track_entry(row, entry),
+        };
+        match parsed {
+            Ok(parsed) => rows.push(parsed),
+            Err(reason) => problems.push(ImportProblem { row, reason }),
+        }
+    }
+
+    Ok(ParsedImport {
+        format,
+        rows,
+        problems,
+    })
+}
+
+fn parse_playlist_entry(row: usize, entry: Value) -> Result<ImportedRow, String> {
+    let playlist = serde_json::from_value::<ExportedPlaylist>(entry)
+        .map_err(|_| "braucht `name` und `playlist_id`".to_owned())?;
+    let url = format!(
+        "https://www.youtube.com/playlist?list={}",
+        playlist.playlist_id
+    );
+    let url = Url::parse(&url).map_err(|_| "hat eine ungültige `playlist_id`".to_owned())?;
+    Ok(ImportedRow {
+        name: Some(playlist.name),
+        options: playlist.options,
+        ..ImportedRow::new(row, url)
+    })
+}
+
+fn parse_track_entry(row: usize, entry: Value) -> Result<ImportedRow, String> {
+    let track = serde_json::from_value::<ExportedTrack>(entry)
+        .map_err(|_| "braucht eine `url`".to_owned())?;
+    Ok(ImportedRow {
+        title: track.title,
+        ..ImportedRow::new(row, parse_url(&track.url)?)
+    })
+}
+
+/// Only web links, everything else would be a search
+fn parse_url(input: &str) -> Result<Url, String> {
+    match Url::parse(input.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(url),
+        _ => Err(format!(
+            "`{}` ist kein Link",
+            truncate_chars(input.trim(), SHOWN_INPUT_CHARS)
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::saved_playlists::LoadAnnouncement;
+
+    fn fixture(name: &str) -> ParsedImport {
+        let path = format!(
+            "{}/tests/fixtures/import/{name}",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        parse_import(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    fn rows(parsed: &ParsedImport) -> Vec<(usize, &str)> {
+        parsed
+            .rows
+            .iter()
+            .map(|row| (row.row, row.url.as_str()))
+            .collect()
+    }
+
+    fn problem_rows(parsed: &ParsedImport) -> Vec<usize> {
+        parsed.problems.iter().map(|problem| problem.row).collect()
+    }
+
+    #[test]
+    fn plain_list_counts_lines_of_the_file() {
+        let parsed = fixture("url_list.txt");
+        assert_eq!(parsed.format, ImportFormat::UrlList);
+        assert!(parsed.problems.is_empty());
+        // The byte order mark, the comment and the blank line are skipped, not renumbered
+        assert_eq!(
+            rows(&parsed),
+            [
+                (2, "https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+                (4, "https://youtu.be/9bZkp7q19f0"),
+                (
+                    5,
+                    "https://www.youtube.com/playlist?list=PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI"
+                ),
+                (6, "https://cdn.example.com/audio/jingle.mp3"),
+            ]
+        );
+        assert_eq!(
+            parsed.rows[2].playlist_id().as_deref(),
+            Some("PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI")
+        );
+    }
+
+    #[test]
+    fn own_export_keeps_names_and_options() {
+        let parsed = fixture("saved_playlists.json");
+        assert_eq!(parsed.format, ImportFormat::SavedPlaylists);
+        assert!(parsed.problems.is_empty());
+        assert_eq!(parsed.rows.len(), 2);
+
+        let chill = &parsed.rows[0];
+        assert_eq!(chill.name.as_deref(), Some("Chill"));
+        assert_eq!(
+            chill.playlist_id().as_deref(),
+            Some("PLFgquLnL59alCl_2TQvOiD5Vgm1hCaGSI")
+        );
+        assert_eq!(
+            chill.options,
+            PlaylistOptions {
+                shuffle: true,
+                announce: LoadAnnouncement::EachTrack,
+                max_items: Some(50),
+            }
+        );
+        // Exported before options existed
+        assert_eq!(parsed.rows[1].options, PlaylistOptions::default());
+    }
+
+    #[test]
+    fn generic_tracks_keep_their_titles() {
+        let parsed = fixture("tracks.json");
+        assert_eq!(parsed.format, ImportFormat::Tracks);
+        assert!(parsed.problems.is_empty());
+        let titles = parsed
+            .rows
+            .iter()
+            .map(|row| (row.row, row.title.as_deref()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            [(1, Some("Never Gonna Give You Up")), (2, None), (3, None)]
+        );
+        assert_eq!(
+            parsed.rows[1].url.as_str(),
+            "https://soundcloud.com/artist/track"
+        );
+    }
+
+    #[test]
+    fn invalid_lines_are_reported_by_line() {
+        let parsed = fixture("partially_invalid.txt");
+        assert_eq!(parsed.format, ImportFormat::UrlList);
+        assert_eq!(
+            rows(&parsed),
+            [
+                (1, "https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+                (6, "https://youtu.be/9bZkp7q19f0"),
+            ]
+        );
+        assert_eq!(problem_rows(&parsed), [2, 5, 7]);
+        assert_eq!(
+            parsed.problems[0].reason,
+            "`never gonna give you up` ist kein Link"
+        );
+    }
+
+    #[test]
+    fn invalid_entries_are_reported_by_entry() {
+        let parsed = fixture("partially_invalid.json");
+        assert_eq!(parsed.format, ImportFormat::Tracks);
+        assert_eq!(
+            rows(&parsed),
+            [
+                (1, "https://www.youtube.com/watch?v=dQw4w9WgXcQ"),
+                (5, "https://youtu.be/9bZkp7q19f0"),
+            ]
+        );
+        assert_eq!(problem_rows(&parsed), [2, 3, 4]);
+        assert_eq!(parsed.problems[0].reason, "braucht eine `url`");
+    }
+
+    #[test]
+    fn unknown_files_are_rejected() {
+        for content in [
+            "",
+            "   \n",
+            "# only a comment\nhello",
+            "{\"name\": 1}",
+            "[1, 2]",
+        ] {
+            assert!(parse_import(content).is_err(), "{content:?}");
+        }
+    }
+}
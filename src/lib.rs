@@ -0,0 +1,1073 @@
+use crate::audit_log::AuditLog;
+use crate::auto_pause::AutoPauses;
+use crate::autoplay::Autoplay;
+use crate::blocklist::Blocklist;
+use crate::command_schema::CommandSchemas;
+use crate::commands::util::{
+    get_author_voice_state, get_command_schemas, get_guild_settings, get_outbound, get_undo_slots,
+    has_dj_rights, GetCallError, JoinVoiceError, QueueCapacity,
+};
+use crate::departures::Departures;
+use crate::diagnostics::DriverDiagnostics;
+use crate::end_reason::EndMarkers;
+use crate::error_rates::ErrorRates;
+use crate::events::PlaybackEventBus;
+use crate::free_notices::FreeNotices;
+use crate::guild_settings::GuildSettingsStore;
+use crate::guild_state::GuildState;
+use crate::history::PlayHistory;
+use crate::lifecycle::GuildLifecycle;
+use crate::load_guard::{LoadGuard, LoadGuardError};
+use crate::outage::GuildOutages;
+use crate::outbound::OutboundScheduler;
+use crate::overlay::OverlayTokens;
+use crate::party_mode::PartyModes;
+use crate::persistence::{PersistenceHealth, StorageUnavailable};
+use crate::plain_text::{EmbedHints, EmbedMode};
+use crate::playback_mode::PlaybackModes;
+use crate::playlist_sync::PlaylistSyncStore;
+use crate::position_cache::PositionCache;
+use crate::preflight::PreflightError;
+use crate::report::TrackReports;
+use crate::resolution::ResolutionTelemetry;
+use crate::response::BotResponse;
+use crate::resume::ResumePoints;
+use crate::saved_playlists::SavedPlaylistStore;
+use crate::schedule::ScheduleStore;
+use crate::settings_transfer::TransferError;
+use crate::staging::StagingStore;
+use crate::start_latency::StartLatency;
+use crate::stats::StatsStore;
+use crate::tts::TtsConfig;
+use crate::undo::UndoSlots;
+use crate::user_preferences::UserPreferencesStore;
+use crate::validator::TrackValidator;
+use crate::voice_sessions::VoiceSessions;
+use crate::voice_state::VoiceDebouncer;
+use crate::youtube::{YoutubeClient, YtApiError};
+use crate::ytdlp::{YtDlpConfig, YtDlpFailure};
+use log::{error, info, warn};
+use poise::{CreateReply, FrameworkContext, FrameworkError};
+use reqwest::Client as HttpClient;
+use serenity::all::{
+    ButtonStyle, Colour, ComponentInteractionCollector, CreateActionRow, CreateButton,
+    CreateInteractionResponse, CreateInteractionResponseMessage, Interaction,
+};
+use serenity::client::FullEvent;
+use serenity::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Semaphore;
+
+pub mod alias;
+pub mod audit_log;
+pub mod auto_pause;
+pub mod autoplay;
+pub mod blocklist;
+pub mod canonical_url;
+pub mod command_schema;
+pub mod commands;
+pub mod confirm;
+#[cfg(unix)]
+pub mod control;
+pub mod departures;
+pub mod diagnostics;
+pub mod end_reason;
+pub mod error_rates;
+pub mod events;
+pub mod free_notices;
+pub mod guild_settings;
+pub mod guild_state;
+pub mod history;
+pub mod import;
+pub mod lifecycle;
+pub mod load_guard;
+pub mod locale;
+pub mod loudness;
+pub mod metadata;
+pub mod notice_channel;
+pub mod outage;
+pub mod outbound;
+pub mod overlay;
+pub mod party_mode;
+pub mod persistence;
+pub mod plain_text;
+pub mod playback_mode;
+pub mod playlist_sync;
+pub mod position_cache;
+pub mod preflight;
+pub mod preview;
+pub mod queue_ops;
+pub mod report;
+pub mod resolution;
+pub mod response;
+pub mod resume;
+pub mod saved_playlists;
+pub mod schedule;
+pub mod serde;
+pub mod settings_transfer;
+pub mod staging;
+pub mod stall;
+pub mod start_latency;
+pub mod stats;
+#[cfg(test)]
+mod test_server;
+pub mod title_clean;
+pub mod title_sanitize;
+pub mod tts;
+pub mod undo;
+pub mod user_preferences;
+pub mod validator;
+pub mod voice_sessions;
+pub mod voice_state;
+pub mod web;
+pub mod youtube;
+pub mod ytdlp;
+
+const SUCCESS_COLOUR: Colour = Colour::BLURPLE;
+const ERROR_COLOUR: Colour = Colour::RED;
+
+/// Entries a queue needs for /shuffle, with fewer there is at most one upcoming track
+pub const MIN_SHUFFLE_ENTRIES: usize = 3;
+const SUMMON_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Types used by all command functions
+pub type CommandContext<'a> = poise::Context<'a, GlobalData, CommandError>;
+
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("Serenity error")]
+    Serenity(#[from] SerenityError),
+    #[error("Failed to join a voice channel")]
+    JoinVoice(#[from] JoinVoiceError),
+    #[error("Failed to leave a voice channel")]
+    LeaveVoice,
+    #[error("Guild-only command executed from DMs. This should have been caught by poise")]
+    NotInGuild,
+    #[error("Songbird instance could not be retrieved from the command context")]
+    SongbirdNotFound,
+    #[error("The author is not in a voice channel")]
+    UserNotInVoice,
+    #[error("The author is not in a voice channel with the bot")]
+    NotInCall,
+    #[error("No track is currently playing")]
+    QueueEmpty,
+    #[error("Only the current track is in the queue")]
+    NothingUpcoming,
+    #[error("yt-dlp could not load the source")]
+    YtDlp(YtDlpFailure),
+    #[error("The playlist offset {offset} is past the end ({total} items)")]
+    OffsetOutOfRange { offset: usize, total: usize },
+    #[error("The position {position} is outside of the queue with {total} entries")]
+    PositionOutOfRange { position: usize, total: usize },
+    #[error("The queue has only {total} entries, too few to shuffle")]
+    TooFewToShuffle { total: usize },
+    #[error("The current track at position 1 can not be moved or removed")]
+    CurrentTrackPosition { removal: bool },
+    #[error("The range {0:?} could not be read")]
+    InvalidRange(String),
+    #[error("The settings file is larger than {limit} bytes")]
+    SettingsFileTooLarge { limit: u32 },
+    #[error("The settings file is not text")]
+    SettingsFileNotText,
+    #[error("The settings file could not be imported")]
+    SettingsImport(#[from] TransferError),
+    #[error("The queue is at its limit of {limit} entries")]
+    QueueFull { limit: usize },
+    #[error("A heavy load could not start")]
+    LoadBusy(#[from] LoadGuardError),
+    #[error("A direct link was refused before loading it")]
+    Preflight(#[from] PreflightError),
+    #[error("Multiple links were mixed with search terms")]
+    MixedSources,
+    #[error("The track {title} is blocked in the guild")]
+    Blocked { title: String },
+    #[error("The track {title} is longer than the limit of {limit:?}")]
+    TrackTooLong { title: String, limit: Duration },
+    #[error("No YouTube channel was found for the source")]
+    ChannelNotFound,
+    #[error("The channel {channel} has no public uploads")]
+    NoPublicUploads { channel: String },
+    #[error("The playlist could not be loaded")]
+    PlaylistNotFound,
+    #[error("Every configured YouTube provider failed")]
+    YouTube(#[from] YtApiError),
+    #[error("The current track and the one after it had dead handles")]
+    DeadTrack,
+    #[error("The alias /{alias} is not enabled in this guild")]
+    AliasDisabled {
+        alias: &'static str,
+        target: &'static str,
+    },
+    #[error("The settings were changed concurrently")]
+    SettingsConflict,
+    #[error("A store needed by the command is unavailable")]
+    StorageUnavailable(#[from] StorageUnavailable),
+}
+
+impl From<GetCallError> for CommandError {
+    fn from(value: GetCallError) -> Self {
+        match value {
+            GetCallError::NotInGuild => CommandError::NotInGuild,
+            GetCallError::SongbirdNotFound => CommandError::SongbirdNotFound,
+            GetCallError::NotInCall => CommandError::NotInCall,
+        }
+    }
+}
+
+pub struct HttpKey;
+
+impl TypeMapKey for HttpKey {
+    type Value = HttpClient;
+}
+
+pub struct YoutubeKey;
+
+impl TypeMapKey for YoutubeKey {
+    type Value = YoutubeClient;
+}
+
+/// Limits the number of concurrently running yt-dlp processes
+pub struct YtDlpPermitsKey;
+
+impl TypeMapKey for YtDlpPermitsKey {
+    type Value = Arc<Semaphore>;
+}
+
+pub struct YtDlpConfigKey;
+
+impl TypeMapKey for YtDlpConfigKey {
+    type Value = Arc<YtDlpConfig>;
+}
+
+pub struct OutboundKey;
+
+impl TypeMapKey for OutboundKey {
+    type Value = Arc<OutboundScheduler>;
+}
+
+pub struct PlaybackModesKey;
+
+impl TypeMapKey for PlaybackModesKey {
+    type Value = Arc<PlaybackModes>;
+}
+
+pub struct OverlayTokensKey;
+
+impl TypeMapKey for OverlayTokensKey {
+    type Value = Arc<OverlayTokens>;
+}
+
+/// Number of tracks an operation may remove without asking for confirmation
+pub struct ConfirmThresholdKey;
+
+impl TypeMapKey for ConfirmThresholdKey {
+    type Value = usize;
+}
+
+/// Number of entries a single queue may hold
+pub struct MaxQueueLengthKey;
+
+impl TypeMapKey for MaxQueueLengthKey {
+    type Value = usize;
+}
+
+pub struct VoiceSessionsKey;
+
+impl TypeMapKey for VoiceSessionsKey {
+    type Value = Arc<VoiceSessions>;
+}
+
+pub struct DeparturesKey;
+
+impl TypeMapKey for DeparturesKey {
+    type Value = Arc<Departures>;
+}
+
+pub struct VoiceDebouncerKey;
+
+impl TypeMapKey for VoiceDebouncerKey {
+    type Value = Arc<VoiceDebouncer>;
+}
+
+pub struct GuildSettingsKey;
+
+impl TypeMapKey for GuildSettingsKey {
+    type Value = Arc<GuildSettingsStore>;
+}
+
+pub struct PlaybackEventsKey;
+
+impl TypeMapKey for PlaybackEventsKey {
+    type Value = Arc<PlaybackEventBus>;
+}
+
+pub struct HistoryKey;
+
+impl TypeMapKey for HistoryKey {
+    type Value = Arc<PlayHistory>;
+}
+
+pub struct StatsKey;
+
+impl TypeMapKey for StatsKey {
+    type Value = Arc<StatsStore>;
+}
+
+pub struct TrackValidatorKey;
+
+impl TypeMapKey for TrackValidatorKey {
+    type Value = Arc<TrackValidator>;
+}
+
+pub struct UserPreferencesKey;
+
+impl TypeMapKey for UserPreferencesKey {
+    type Value = Arc<UserPreferencesStore>;
+}
+
+pub struct StagingKey;
+
+impl TypeMapKey for StagingKey {
+    type Value = Arc<StagingStore>;
+}
+
+pub struct LoadGuardKey;
+
+impl TypeMapKey for LoadGuardKey {
+    type Value = Arc<LoadGuard>;
+}
+
+pub struct GuildStateKey;
+
+impl TypeMapKey for GuildStateKey {
+    type Value = Arc<GuildState>;
+}
+
+pub struct EmbedHintsKey;
+
+impl TypeMapKey for EmbedHintsKey {
+    type Value = Arc<EmbedHints>;
+}
+
+pub struct DriverDiagnosticsKey;
+
+impl TypeMapKey for DriverDiagnosticsKey {
+    type Value = Arc<DriverDiagnostics>;
+}
+
+pub struct AuditLogKey;
+
+impl TypeMapKey for AuditLogKey {
+    type Value = Arc<AuditLog>;
+}
+
+pub struct GuildOutagesKey;
+
+impl TypeMapKey for GuildOutagesKey {
+    type Value = Arc<GuildOutages>;
+}
+
+pub struct PositionCacheKey;
+
+impl TypeMapKey for PositionCacheKey {
+    type Value = Arc<PositionCache>;
+}
+
+pub struct EndMarkersKey;
+
+impl TypeMapKey for EndMarkersKey {
+    type Value = Arc<EndMarkers>;
+}
+
+pub struct AutoPausesKey;
+
+impl TypeMapKey for AutoPausesKey {
+    type Value = Arc<AutoPauses>;
+}
+
+pub struct PlaylistSyncsKey;
+
+impl TypeMapKey for PlaylistSyncsKey {
+    type Value = Arc<PlaylistSyncStore>;
+}
+
+pub struct SavedPlaylistsKey;
+
+impl TypeMapKey for SavedPlaylistsKey {
+    type Value = Arc<SavedPlaylistStore>;
+}
+
+pub struct AutoplayKey;
+
+impl TypeMapKey for AutoplayKey {
+    type Value = Arc<Autoplay>;
+}
+
+pub struct BlocklistKey;
+
+impl TypeMapKey for BlocklistKey {
+    type Value = Arc<Blocklist>;
+}
+
+pub struct TrackReportsKey;
+
+impl TypeMapKey for TrackReportsKey {
+    type Value = Arc<TrackReports>;
+}
+
+pub struct StartLatencyKey;
+
+impl TypeMapKey for StartLatencyKey {
+    type Value = Arc<StartLatency>;
+}
+
+pub struct ResolutionTelemetryKey;
+
+impl TypeMapKey for ResolutionTelemetryKey {
+    type Value = Arc<ResolutionTelemetry>;
+}
+
+pub struct GuildLifecycleKey;
+
+impl TypeMapKey for GuildLifecycleKey {
+    type Value = Arc<GuildLifecycle>;
+}
+
+pub struct ScheduleKey;
+
+impl TypeMapKey for ScheduleKey {
+    type Value = Arc<ScheduleStore>;
+}
+
+pub struct UndoSlotsKey;
+
+impl TypeMapKey for UndoSlotsKey {
+    type Value = Arc<UndoSlots>;
+}
+
+pub struct CommandSchemasKey;
+
+impl TypeMapKey for CommandSchemasKey {
+    type Value = Arc<CommandSchemas>;
+}
+
+/// Only set if the host configured a text-to-speech binary
+pub struct TtsKey;
+
+impl TypeMapKey for TtsKey {
+    type Value = Option<Arc<TtsConfig>>;
+}
+
+pub struct ResumePointsKey;
+
+impl TypeMapKey for ResumePointsKey {
+    type Value = Arc<ResumePoints>;
+}
+
+pub struct FreeNoticesKey;
+
+impl TypeMapKey for FreeNoticesKey {
+    type Value = Arc<FreeNotices>;
+}
+
+pub struct ErrorRatesKey;
+
+impl TypeMapKey for ErrorRatesKey {
+    type Value = Arc<ErrorRates>;
+}
+
+pub struct PartyModesKey;
+
+impl TypeMapKey for PartyModesKey {
+    type Value = Arc<PartyModes>;
+}
+
+pub struct PersistenceHealthKey;
+
+impl TypeMapKey for PersistenceHealthKey {
+    type Value = Arc<PersistenceHealth>;
+}
+
+// Custom user data passed to all command functions
+pub struct GlobalData {}
+
+pub async fn on_api_event(
+    ctx: &Context,
+    event: &FullEvent,
+    framework: FrameworkContext<'_, GlobalData, CommandError>,
+    _data: &GlobalData,
+) -> Result<(), CommandError> {
+    match event {
+        FullEvent::CacheReady { guilds } => {
+            // Print startup info
+            info!("Logged in as {}", ctx.cache.current_user().name);
+            if guilds.len() < 10 {
+                info!(
+                    "Active on these guilds: {}",
+                    guilds
+                        .iter()
+                        .map(|g| format!("{}<{}>", ctx.cache.guild(g).unwrap().name, g))
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                );
+            } else {
+                info!("Active on {} guilds", guilds.len())
+            }
+        }
+        // An unavailable guild is an outage, otherwise the bot was kicked
+        FullEvent::GuildDelete { incomplete, .. } => {
+            if incomplete.unavailable {
+                outage::on_guild_unavailable(ctx, incomplete.id).await;
+            } else {
+                outage::on_guild_removed(ctx, incomplete.id).await;
+                lifecycle::on_guild_left(ctx, incomplete.id).await;
+            }
+        }
+        FullEvent::GuildCreate { guild, is_new } => {
+            if *is_new == Some(true) {
+                lifecycle::on_guild_joined(ctx, guild).await;
+            }
+            outage::on_guild_available(ctx, guild.id).await;
+        }
+        FullEvent::ThreadDelete { thread, .. } => {
+            get_outbound(ctx)
+                .await
+                .thread_deleted(thread.guild_id, thread.id, thread.parent_id);
+        }
+        // Leave empty voice channels automatically
+        FullEvent::VoiceStateUpdate { old, new } => {
+            voice_state::on_voice_state_update(ctx, old.as_ref(), new, framework.bot_id).await;
+        }
+        // Report buttons stay on their messages, so they are handled here instead of a collector
+        FullEvent::InteractionCreate {
+            interaction: Interaction::Component(press),
+        } if press.data.custom_id == report::REPORT_BUTTON_ID => {
+            let ctx = ctx.clone();
+            let press = press.clone();
+            // Waiting for the reason must not block other events
+            tokio::spawn(async move {
+                if let Err(e) = report::on_report_pressed(&ctx, &press).await {
+                    error!("Failed to handle a report: {e}");
+                }
+            });
+        }
+        // The button is on error messages, which outlive the command that failed
+        FullEvent::InteractionCreate {
+            interaction: Interaction::Component(press),
+        } if press.data.custom_id == free_notices::NOTIFY_FREE_BUTTON_ID => {
+            if let Err(e) = free_notices::on_notify_free_pressed(ctx, press).await {
+                error!("Failed to register a free notice: {e}");
+            }
+        }
+        _ => {}
+    };
+
+    Ok(())
+}
+
+// ======== Error handling ========
+
+async fn respond_err(ctx: &CommandContext<'_>, details: impl Into<String>) {
+    if let Err(e) = BotResponse::error(details).send(ctx).await {
+        error!("Error while sending error response: {}", e);
+    }
+}
+
+/// Moving the bot away from a channel is limited to users who could also move its members
+async fn can_summon(ctx: &CommandContext<'_>) -> bool {
+    let dj_role = match ctx.guild_id() {
+        Some(guild_id) => {
+            get_guild_settings(ctx.serenity_context())
+                .await
+                .get(guild_id)
+                .dj_role
+        }
+        None => None,
+    };
+    match ctx {
+        poise::Context::Application(ctx) => {
+            has_dj_rights(ctx.interaction.member.as_deref(), dj_role)
+        }
+        poise::Context::Prefix(_) => false,
+    }
+}
+
+/// Error response for an occupied bot without listeners, with a button to move the bot to the
+/// channel of the author anyway
+async fn respond_err_with_summon(
+    ctx: &CommandContext<'_>,
+    details: String,
+) -> Result<(), CommandError> {
+    let summon_id = format!("{}summon", ctx.id());
+    let button = |disabled: bool| {
+        vec![CreateActionRow::Buttons(vec![CreateButton::new(
+            summon_id.clone(),
+        )
+        .label("Trotzdem herholen")
+        .style(ButtonStyle::Danger)
+        .disabled(disabled)])]
+    };
+    let response = BotResponse::error(details);
+    let embed = response.embed(*ctx);
+    // Errors are only shown to the author
+    let embed_mode = EmbedMode::of_reply(*ctx, true).await;
+
+    let reply = ctx
+        .send(response.reply(*ctx).await.components(button(false)))
+        .await?;
+
+    let author_id = ctx.author().id;
+    let press = ComponentInteractionCollector::new(ctx.serenity_context())
+        .filter({
+            let summon_id = summon_id.clone();
+            move |press| press.data.custom_id == summon_id && press.user.id == author_id
+        })
+        .timeout(SUMMON_TIMEOUT)
+        .await;
+
+    let Some(press) = press else {
+        reply
+            .edit(
+                *ctx,
+                embed_mode
+                    .reply(CreateReply::default(), embed)
+                    .components(button(true)),
+            )
+            .await?;
+        return Ok(());
+    };
+
+    // The author may have switched channels in the meantime
+    let (guild_id, channel_id) = get_author_voice_state(*ctx);
+    let songbird = songbird::get(ctx.serenity_context())
+        .await
+        .ok_or(CommandError::SongbirdNotFound)?;
+    let outcome = match channel_id {
+        Some(channel_id) => match songbird.join(guild_id, channel_id).await {
+            Ok(_) => format!(
+                "Der Bot ist jetzt in {}. Führe deinen Befehl erneut aus.",
+                channel_id.mention()
+            ),
+            Err(e) => {
+                error!("Failed to move to voice channel: {}", e);
+                "Der Bot konnte deinem Sprachkanal nicht beitreten".to_owned()
+            }
+        },
+        None => "Du bist nicht in einem Sprachkanal in diesem Server".to_owned(),
+    };
+
+    press
+        .create_response(
+            ctx,
+            CreateInteractionResponse::UpdateMessage(
+                embed_mode
+                    .message(
+                        CreateInteractionResponseMessage::new(),
+                        BotResponse::success("Herholen")
+                            .description(outcome)
+                            .embed(*ctx),
+                    )
+                    .components(vec![]),
+            ),
+        )
+        .await?;
+
+    Ok(())
+}
+
+async fn handle_command_error(ctx: &CommandContext<'_>, error: CommandError) {
+    match error {
+        CommandError::Serenity(inner) => {
+            error!("Serenity error: {}", inner);
+            respond_err(ctx, "Ein unerwarteter Fehler ist aufgetreten").await;
+        }
+        CommandError::JoinVoice(inner) => match inner {
+            JoinVoiceError::Join(inner) => {
+                error!("Failed to join voice channel: {}", inner);
+                respond_err(ctx, "Der Bot konnte deinem Sprachkanal nicht beitreten").await;
+            }
+            JoinVoiceError::Busy(full) => {
+                let details = format!(
+                    "Der Bot ist momentan ausgelastet ({}/{} Server), versuche es später",
+                    full.active, full.limit
+                );
+                respond_err(ctx, details).await;
+            }
+            JoinVoiceError::Occupied {
+                channel,
+                listeners,
+                remaining,
+            } => {
+                let mut details = match listeners {
+                    Some(0) => format!(
+                        "Der Bot ist gerade in {}, aber dort hört niemand mehr zu",
+                        channel.mention()
+                    ),
+                    Some(1) => format!(
+                        "Der Bot spielt gerade in {} für 1 Person",
+                        channel.mention()
+                    ),
+                    Some(n) => format!(
+                        "Der Bot spielt gerade in {} für {n} Personen",
+                        channel.mention()
+                    ),
+                    None => format!("Der Bot spielt gerade in {}", channel.mention()),
+                };
+                // One call per guild, so the only way to the bot is waiting for it
+                if let Some(remaining) = remaining {
+                    details += &format!(
+                        "\nDie Warteschlange läuft voraussichtlich noch {} Minuten",
+                        remaining.as_secs().div_ceil(60).max(1)
+                    );
+                }
+                if listeners == Some(0) && can_summon(ctx).await {
+                    if let Err(e) = respond_err_with_summon(ctx, details).await {
+                        error!("Error while sending error response: {}", e);
+                    }
+                } else {
+                    let response = BotResponse::error(details);
+                    let reply = response
+                        .reply(*ctx)
+                        .await
+                        .components(vec![free_notices::notify_free_button()]);
+                    if let Err(e) = ctx.send(reply).await {
+                        error!("Error while sending error response: {}", e);
+                    }
+                }
+            }
+        },
+        CommandError::LeaveVoice => {
+            error!("Failed to leave voice channel: {}", error);
+            respond_err(ctx, "Ein unerwarteter Fehler ist aufgetreten").await;
+        }
+        CommandError::NotInGuild => {
+            // This should never happen as it is caught by the poise attribute
+        }
+        CommandError::SongbirdNotFound => {
+            error!("Songbird instance could not be retrieved from the typemap");
+            respond_err(ctx, "Ein unerwarteter Fehler ist aufgetreten").await;
+        }
+        CommandError::UserNotInVoice => {
+            respond_err(ctx, "Du bist nicht in einem Sprachkanal in diesem Server").await;
+        }
+        CommandError::NotInCall => {
+            respond_err(ctx, "Du bist nicht in einem Sprachkanal mit dem Bot").await;
+        }
+        CommandError::QueueEmpty => respond_err(ctx, "Momentan wird nichts abgespielt").await,
+        CommandError::NothingUpcoming => {
+            respond_err(
+                ctx,
+                "Nach dem aktuellen Lied kommt nichts mehr. Um auch das aktuelle zu beenden, nutze /stop oder /skip",
+            )
+            .await;
+        }
+        CommandError::AliasDisabled { alias, target } => {
+            let msg = format!(
+                "`/{alias}` ist auf diesem Server nicht aktiviert, verwende stattdessen `/{target}`"
+            );
+            respond_err(ctx, msg).await;
+        }
+        CommandError::SettingsConflict => {
+            warn!(
+                "Settings of guild {:?} were changed concurrently",
+                ctx.guild_id()
+            );
+            respond_err(
+                ctx,
+                "Einstellungen wurden gleichzeitig geändert, bitte erneut versuchen",
+            )
+            .await;
+        }
+        CommandError::StorageUnavailable(e) => {
+            warn!("Refused a command in guild {:?}: {e}", ctx.guild_id());
+            respond_err(ctx, "Speicher momentan nicht verfügbar").await;
+        }
+        CommandError::DeadTrack => {
+            error!("Dead track handles in guild {:?}", ctx.guild_id());
+            respond_err(
+                ctx,
+                "Die Wiedergabe ist hängen geblieben. Bitte versuche es gleich noch einmal",
+            )
+            .await;
+        }
+        CommandError::YtDlp(failure) => respond_err(ctx, failure.user_message()).await,
+        CommandError::Preflight(PreflightError::NotMedia { content_type }) => {
+            let details = format!(
+                "Der Link führt zu keiner Audio- oder Videodatei, sondern zu `{content_type}`"
+            );
+            respond_err(ctx, details).await;
+        }
+        CommandError::Preflight(PreflightError::TooLarge { size, limit }) => {
+            let details = format!(
+                "Die Datei ist mit {} MB größer als die erlaubten {} MB",
+                size.div_ceil(1024 * 1024),
+                limit / (1024 * 1024)
+            );
+            respond_err(ctx, details).await;
+        }
+        CommandError::OffsetOutOfRange { offset, total } => {
+            let details = format!(
+                "Die Playlist hat nur {total} Lieder, es können nicht {offset} übersprungen werden"
+            );
+            respond_err(ctx, details).await;
+        }
+        CommandError::PositionOutOfRange { position, total } => {
+            let details = format!(
+                "Die Warteschlange hat nur {total} Einträge, Position {position} gibt es nicht"
+            );
+            respond_err(ctx, details).await;
+        }
+        CommandError::MixedSources => {
+            respond_err(
+                ctx,
+                "Links und Suchbegriffe können nicht gemischt werden. Gib entweder mehrere Links oder einen Suchbegriff an",
+            )
+            .await;
+        }
+        CommandError::Blocked { title } => {
+            respond_err(ctx, format!("`{title}` ist auf diesem Server gesperrt")).await;
+        }
+        CommandError::TrackTooLong { title, limit } => {
+            let details = format!(
+                "`{title}` ist länger als die auf diesem Server erlaubten {} Minuten",
+                limit.as_secs() / 60
+            );
+            respond_err(ctx, details).await;
+        }
+        CommandError::PlaylistNotFound => {
+            respond_err(ctx, "Die Playlist konnte nicht geladen werden").await;
+        }
+        CommandError::YouTube(inner) => {
+            let details = match inner {
+                YtApiError::QuotaExceeded => {
+                    "Das YouTube-Kontingent für heute ist aufgebraucht, versuche es morgen erneut"
+                }
+                YtApiError::InvalidKey => {
+                    error!("YouTube request failed because of an invalid API key");
+                    "YouTube ist für diesen Bot nicht richtig eingerichtet"
+                }
+                YtApiError::InvalidId => "Auf YouTube wurde dazu nichts gefunden",
+                e => {
+                    warn!("YouTube request failed with every provider: {e:?}");
+                    "YouTube ist gerade nicht erreichbar, versuche es später erneut"
+                }
+            };
+            respond_err(ctx, details).await;
+        }
+        CommandError::TooFewToShuffle { total } => {
+            let details = format!(
+                "Die Warteschlange hat nur {total} Einträge, gemischt wird erst ab {MIN_SHUFFLE_ENTRIES}"
+            );
+            respond_err(ctx, details).await;
+        }
+        CommandError::CurrentTrackPosition { removal: false } => {
+            respond_err(
+                ctx,
+                "Position 1 ist das aktuelle Lied und kann nicht verschoben werden. Nutze Positionen ab 2",
+            )
+            .await;
+        }
+        // The current track is never removed, skipping it is the job of /skip
+        CommandError::CurrentTrackPosition { removal: true } => {
+            respond_err(
+                ctx,
+                "Position 1 ist das aktuelle Lied und wird nicht entfernt. Nutze dafür /skip",
+            )
+            .await;
+        }
+        CommandError::SettingsFileTooLarge { limit } => {
+            let details = format!(
+                "Die Datei ist zu groß, ein Export hat höchstens {} KB",
+                limit / 1024
+            );
+            respond_err(ctx, details).await;
+        }
+        CommandError::SettingsFileNotText => {
+            respond_err(ctx, "Die Datei ist keine Textdatei").await;
+        }
+        // The messages of the transfer errors are meant for users
+        CommandError::SettingsImport(inner) => {
+            respond_err(ctx, inner.to_string()).await;
+        }
+        CommandError::InvalidRange(range) => {
+            respond_err(
+                ctx,
+                format!("Ungültiger Bereich `{range}`. Gib ihn wie `3-10` oder `5` an"),
+            )
+            .await;
+        }
+        CommandError::QueueFull { limit } => {
+            respond_err(ctx, QueueCapacity::full_message(limit)).await;
+        }
+        CommandError::ChannelNotFound => {
+            respond_err(ctx, "Es wurde kein passender YouTube-Kanal gefunden").await;
+        }
+        CommandError::NoPublicUploads { channel } => {
+            let details = format!("Der Kanal `{channel}` hat keine öffentlichen Videos");
+            respond_err(ctx, details).await;
+        }
+        CommandError::LoadBusy(inner) => match inner {
+            LoadGuardError::GuildBusy => {
+                respond_err(
+                    ctx,
+                    "Es wird gerade bereits eine Playlist geladen, bitte warte",
+                )
+                .await
+            }
+            LoadGuardError::GloballyBusy => {
+                respond_err(
+                    ctx,
+                    "Gerade werden zu viele Playlists geladen, bitte versuche es später erneut",
+                )
+                .await
+            }
+        },
+    }
+}
+
+pub async fn on_poise_error(error: poise::FrameworkError<'_, GlobalData, CommandError>) {
+    match error {
+        FrameworkError::Setup { error, .. } => error!("Error in data setup: {}", error),
+        FrameworkError::EventHandler { error, event, .. } => {
+            error!(
+                "Error in {} event handler: {}",
+                event.snake_case_name(),
+                error
+            )
+        }
+        FrameworkError::Command { ctx, error, .. } => {
+            handle_command_error(&ctx, error).await;
+        }
+        FrameworkError::CommandPanic { ctx, payload, .. } => {
+            match payload {
+                Some(payload) => error!("Command panicked. Details:\n{}", payload),
+                None => error!("Command panicked"),
+            }
+            // Commands that changed the queue saved it before, so the half-done change can be reverted
+            let restorable = match ctx.guild_id() {
+                Some(guild_id) => get_undo_slots(ctx.serenity_context())
+                    .await
+                    .has_snapshot_of(guild_id, ctx.id()),
+                None => false,
+            };
+            let msg = if restorable {
+                "Ein unerwarteter Fehler ist aufgetreten. Mit `/undo` kann die vorherige Warteliste wiederhergestellt werden"
+            } else {
+                "Ein unerwarteter Fehler ist aufgetreten"
+            };
+            respond_err(&ctx, msg).await;
+        }
+        FrameworkError::ArgumentParse {
+            ctx, input, error, ..
+        } => {
+            let msg = match input {
+                Some(arg) => {
+                    error!("Error while parsing command argument {arg}: {error}");
+                    format!("Fehler beim Lesen des Command-Arguments {arg}")
+                }
+                None => {
+                    error!("Error while parsing command arguments: {error}");
+                    "Fehler beim Lesen eines Command-Arguments".to_owned()
+                }
+            };
+
+            respond_err(&ctx, msg).await;
+        }
+        FrameworkError::CommandStructureMismatch {
+            ctx, description, ..
+        } => {
+            let command_schemas = get_command_schemas(ctx.serenity_context).await;
+            let outdated = !command_schemas.is_current(ctx.interaction.data.id)
+                || !command_schema::matches_current(ctx.command, ctx.args);
+            warn!(
+                "Failed to deserialize interaction for /{} (registration {}): {}",
+                ctx.command.qualified_name,
+                if outdated { "outdated" } else { "current" },
+                description,
+            );
+
+            // Old registrations can still be used by clients for a while, so try the previous shape
+            if let (Some(args), Some(action)) = (
+                command_schema::adapt_args(ctx.command, ctx.args),
+                ctx.command.slash_action,
+            ) {
+                info!(
+                    "Running /{} with adapted arguments, mismatch fallbacks: {}",
+                    ctx.command.qualified_name,
+                    command_schemas.count_fallback(&ctx.command.qualified_name),
+                );
+                match action(poise::ApplicationContext { args: &args, ..ctx }).await {
+                    Ok(()) => return,
+                    Err(FrameworkError::CommandStructureMismatch { description, .. }) => {
+                        error!(
+                            "Fallback for /{} failed too: {}",
+                            ctx.command.qualified_name, description
+                        );
+                    }
+                    Err(error) => return Box::pin(on_poise_error(error)).await,
+                }
+            }
+
+            let msg = if outdated {
+                "Dieser Command wurde aktualisiert und dein Discord kennt noch die alte Version. Starte Discord neu oder warte ein paar Minuten."
+            } else {
+                "Ein unerwarteter Fehler ist aufgetreten. Du kannst versuchen, Discord neu zu starten oder ein paar Minuten zu warten."
+            };
+            respond_err(&CommandContext::Application(ctx), msg).await;
+        }
+        FrameworkError::CooldownHit {
+            ctx,
+            remaining_cooldown,
+            ..
+        } => {
+            let msg = format!(
+                "Nicht so schnell. Bitte warte {} Sekunden vor dem nächsten Versuch",
+                remaining_cooldown.as_secs()
+            );
+            respond_err(&ctx, msg).await;
+        }
+        FrameworkError::MissingBotPermissions {
+            ctx,
+            missing_permissions,
+            ..
+        } => {
+            let msg = format!(
+                "Der Command konnte nicht ausgeführt werden, weil dem Bot folgende Berechtigungen fehlen: {}",
+                missing_permissions,
+            );
+            respond_err(&ctx, msg).await;
+        }
+        FrameworkError::NotAnOwner { ctx, .. } => {
+            let msg = "Dieser Command kann nur von den Besitzern des Bots verwendet werden";
+            respond_err(&ctx, msg).await;
+        }
+        FrameworkError::GuildOnly { ctx, .. } => {
+            let msg = "Dieser Command kann nur in einem Server verwendet werden";
+            respond_err(&ctx, msg).await;
+        }
+        FrameworkError::DmOnly { ctx, .. } => {
+            let msg = "Dieser Command kann nur in DMs verwendet werden";
+            respond_err(&ctx, msg).await;
+        }
+        FrameworkError::NsfwOnly { ctx, .. } => {
+            let msg = "Dieser Command kann nur in NSFW Kanälen verwendet werden";
+            respond_err(&ctx, msg).await;
+        }
+        FrameworkError::CommandCheckFailed { ctx, error, .. } => match error {
+            Some(e) => {
+                handle_command_error(&ctx, e).await;
+            }
+            None => {
+                respond_err(&ctx, "Der Command wurde abgebrochen").await;
+            }
+        },
+        FrameworkError::UnknownInteraction { interaction, .. } => {
+            warn!("Unknown interaction received: {:?}", interaction);
+        }
+        _ => {
+            // Anything else is only relevant for prefix commands
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::commands::util::{get_guild_lifecycle, get_guild_settings, get_outbound};
+use crate::SUCCESS_COLOUR;
+use log::info;
+use serenity::all::{Context, CreateEmbed, CreateMessage, Guild, GuildId};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Data of a guild the bot was removed from is kept this long, in case it was an accident
+pub const PURGE_GRACE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// Data that is kept beyond the runtime state of a guild, like its settings. It is only removed
+/// some time after the bot was removed from the guild.
+pub trait GuildPersisted: Send + Sync {
+    /// Drops everything stored for the guild
+    fn purge(&self, guild_id: GuildId);
+}
+
+/// Counters of guild joins and removals since the start
+#[derive(Debug, Default)]
+pub struct LifecycleStats {
+    pub joined: AtomicU64,
+    pub removed: AtomicU64,
+    pub purged: AtomicU64,
+}
+
+/// Guilds the bot was removed from, with the time their data is purged
+pub struct GuildLifecycle {
+    stores: Vec<Arc<dyn GuildPersisted>>,
+    pending: Mutex<HashMap<GuildId, Instant>>,
+    pub stats: LifecycleStats,
+}
+
+impl GuildLifecycle {
+    pub fn new(stores: Vec<Arc<dyn GuildPersisted>>) -> Self {
+        Self {
+            stores,
+            pending: Mutex::new(HashMap::new()),
+            stats: LifecycleStats::default(),
+        }
+    }
+
+    pub fn pending_purges(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    /// Purges the data of all guilds whose grace period ended
+    pub fn purge_due(&self, now: Instant) -> Vec<GuildId> {
+        let due = {
+            let mut pending = self.pending.lock().unwrap();
+            let due = pending
+                .iter()
+                .filter(|(_, at)| **at <= now)
+                .map(|(guild_id, _)| *guild_id)
+                .collect::<Vec<_>>();
+            for guild_id in &due {
+                pending.remove(guild_id);
+            }
+            due
+        };
+
+        for guild_id in &due {
+            for store in &self.stores {
+                store.purge(*guild_id);
+            }
+        }
+        self.stats
+            .purged
+            .fetch_add(due.len() as u64, Ordering::Relaxed);
+        due
+    }
+}
+
+/// Greets a guild that just added the bot in its system channel, if the bot may write there
+pub async fn on_guild_joined(ctx: &Context, guild: &Guild) {
+    let lifecycle = get_guild_lifecycle(ctx).await;
+    lifecycle.stats.joined.fetch_add(1, Ordering::Relaxed);
+    // A guild that kicked the bot by accident keeps its data
+    let rejoined = lifecycle
+        .pending
+        .lock()
+        .unwrap()
+        .remove(&guild.id)
+        .is_some();
+    info!(
+        "Joined guild {}<{}>{}",
+        guild.name,
+        guild.id,
+        if rejoined { ", keeping its data" } else { "" }
+    );
+    // Stores the default settings, those of a guild that rejoined stay as they were
+    get_guild_settings(ctx).await.update(guild.id, |_| {});
+
+    let bot_id = ctx.cache.current_user().id;
+    let can_write = |channel_id| {
+        let channel = guild.channels.get(&channel_id)?;
+        let member = guild.members.get(&bot_id)?;
+        let permissions = guild.user_permissions_in(channel, member);
+        Some(permissions.send_messages() && permissions.embed_links())
+    };
+    let Some(channel_id) = guild
+        .system_channel_id
+        .filter(|channel_id| can_write(*channel_id).unwrap_or(false))
+    else {
+        return;
+    };
+
+    let embed = CreateEmbed::new()
+        .title("Hallo!")
+        .colour(SUCCESS_COLOUR)
+        .description(
+            "Danke für die Einladung. Mit /help siehst du alle Befehle, mit /setup lässt sich der Bot für diesen Server einrichten.",
+        );
+    get_outbound(ctx).await.post(
+        &ctx.http,
+        guild.id,
+        channel_id,
+        None,
+        CreateMessage::new().embed(embed),
+    );
+}
+
+/// Schedules the data of a guild that removed the bot for deletion
+pub async fn on_guild_left(ctx: &Context, guild_id: GuildId) {
+    let lifecycle = get_guild_lifecycle(ctx).await;
+    lifecycle.stats.removed.fetch_add(1, Ordering::Relaxed);
+    lifecycle
+        .pending
+        .lock()
+        .unwrap()
+        .insert(guild_id, Instant::now() + PURGE_GRACE);
+    info!(
+        "Removed from guild {guild_id}, its data is deleted in {} days",
+        PURGE_GRACE.as_secs() / (24 * 60 * 60)
+    );
+}
@@ -0,0 +1,136 @@
+use serenity::all::GuildId;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard, OwnedSemaphorePermit, Semaphore};
+use tokio::time::timeout;
+
+/// Waiting is only worth it if the other load is about to finish
+const GUILD_WAIT: Duration = Duration::from_secs(3);
+/// Loads in other guilds are usually done within this time
+const GLOBAL_WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Error, Debug)]
+pub enum LoadGuardError {
+    #[error("Another heavy load is running in this guild")]
+    GuildBusy,
+    #[error("Too many heavy loads are running at the same time")]
+    GloballyBusy,
+}
+
+/// Held for the duration of a heavy load. Dropping it lets the next load start.
+pub struct LoadPermit {
+    _guild: OwnedMutexGuard<()>,
+    _global: OwnedSemaphorePermit,
+}
+
+/// Limits heavy loads that enqueue many tracks (playlists, imports, restores): one per guild,
+/// so their tracks do not interleave, and a fixed number across all guilds to protect the quota.
+pub struct LoadGuard {
+    guilds: Mutex<HashMap<GuildId, Arc<AsyncMutex<()>>>>,
+    global: Arc<Semaphore>,
+}
+
+impl LoadGuard {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            guilds: Mutex::new(HashMap::new()),
+            global: Arc::new(Semaphore::new(max_concurrent)),
+        }
+    }
+
+    /// Waits shortly for a running load of the guild and longer for a free global slot
+    pub async fn acquire(&self, guild_id: GuildId) -> Result<LoadPermit, LoadGuardError> {
+        let guild_lock = self
+            .guilds
+            .lock()
+            .unwrap()
+            .entry(guild_id)
+            .or_default()
+            .clone();
+
+        let guild = timeout(GUILD_WAIT, guild_lock.lock_owned())
+            .await
+            .map_err(|_| LoadGuardError::GuildBusy)?;
+        let global = timeout(GLOBAL_WAIT, self.global.clone().acquire_owned())
+            .await
+            .map_err(|_| LoadGuardError::GloballyBusy)?
+            .expect("The semaphore is never closed");
+
+        Ok(LoadPermit {
+            _guild: guild,
+            _global: global,
+        })
+    }
+
+    pub fn is_loading(&self, guild_id: GuildId) -> bool {
+        self.guilds
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .is_some_and(|lock| lock.try_lock().is_err())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const OTHER_GUILD: GuildId = GuildId::new(2);
+
+    #[tokio::test(start_paused = true)]
+    async fn second_load_in_a_guild_is_refused() {
+        let guard = LoadGuard::new(4);
+        let _permit = guard.acquire(GUILD).await.unwrap();
+        assert!(guard.is_loading(GUILD));
+
+        assert!(matches!(
+            guard.acquire(GUILD).await,
+            Err(LoadGuardError::GuildBusy)
+        ));
+        // Other guilds load independently
+        assert!(guard.acquire(OTHER_GUILD).await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn short_waits_are_queued() {
+        let guard = Arc::new(LoadGuard::new(4));
+        let permit = guard.acquire(GUILD).await.unwrap();
+
+        let waiting = tokio::spawn({
+            let guard = guard.clone();
+            async move { guard.acquire(GUILD).await.is_ok() }
+        });
+        tokio::time::sleep(GUILD_WAIT / 2).await;
+        drop(permit);
+        assert!(waiting.await.unwrap());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn global_limit_is_honoured() {
+        let guard = LoadGuard::new(2);
+        let _first = guard.acquire(GuildId::new(1)).await.unwrap();
+        let _second = guard.acquire(GuildId::new(2)).await.unwrap();
+
+        assert!(matches!(
+            guard.acquire(GuildId::new(3)).await,
+            Err(LoadGuardError::GloballyBusy)
+        ));
+        // A refused load does not block its guild
+        assert!(!guard.is_loading(GuildId::new(3)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dropping_the_permit_releases_both_slots() {
+        let guard = LoadGuard::new(1);
+        let permit = guard.acquire(GUILD).await.unwrap();
+        drop(permit);
+        assert!(!guard.is_loading(GUILD));
+
+        let again = guard.acquire(GUILD).await.unwrap();
+        drop(again);
+        assert!(guard.acquire(OTHER_GUILD).await.is_ok());
+    }
+}
@@ -0,0 +1,220 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Language of messages and formatting rules for numbers and times
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+pub enum Locale {
+    #[default]
+    #[name = "Deutsch"]
+    #[serde(rename = "de")]
+    German,
+    #[name = "English"]
+    #[serde(rename = "en")]
+    English,
+}
+
+impl Locale {
+    /// Maps a discord locale like `de` or `en-US`
+    pub fn from_discord(locale: &str) -> Option<Self> {
+        match locale.split('-').next() {
+            Some("de") => Some(Locale::German),
+            Some("en") => Some(Locale::English),
+            _ => None,
+        }
+    }
+
+    /// Locale for a message. The guild setting wins over the interaction locale, so replies
+    /// and messages without an interaction (announcements) look the same.
+    pub fn resolve(guild_locale: Option<Locale>, interaction_locale: Option<&str>) -> Self {
+        guild_locale
+            .or_else(|| interaction_locale.and_then(Self::from_discord))
+            .unwrap_or_default()
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Locale::German => "Deutsch",
+            Locale::English => "English",
+        }
+    }
+
+    /// Number with thousands separators, like `1.234.567` or `1,234,567`
+    pub fn format_number(&self, number: u64) -> String {
+        let separator = match self {
+            Locale::German => '.',
+            Locale::English => ',',
+        };
+
+        let digits = number.to_string();
+        let groups = digits
+            .as_bytes()
+            .rchunks(3)
+            .rev()
+            .map(|group| std::str::from_utf8(group).expect("Digits are ascii"))
+            .collect::<Vec<&str>>();
+        groups.join(&separator.to_string())
+    }
+
+    /// Track length or position, like `03:25` or `01:03:25`
+    pub fn format_duration(&self, duration: Duration) -> String {
+        let mut secs = duration.as_secs();
+        let hours = secs / 3600;
+        secs -= hours * 3600;
+        let mins = secs / 60;
+        secs -= mins * 60;
+
+        let hours_str = if hours != 0 {
+            format!("{:02}:", hours)
+        } else {
+            "".to_owned()
+        };
+        format!("{}{:02}:{:02}", hours_str, mins, secs)
+    }
+
+    /// Duration in words for screen readers, like `3 Minuten 12 Sekunden`. Zero parts are left
+    /// out, a duration of zero is read as zero seconds.
+    pub fn format_duration_words(&self, duration: Duration) -> String {
+        let secs = duration.as_secs();
+        let parts = [(secs / 3600, 0), ((secs / 60) % 60, 1), (secs % 60, 2)];
+        let unit = |index: usize, count: u64| match (self, index, count == 1) {
+            (Locale::German, 0, true) => "Stunde",
+            (Locale::German, 0, false) => "Stunden",
+            (Locale::German, 1, true) => "Minute",
+            (Locale::German, 1, false) => "Minuten",
+            (Locale::German, _, true) => "Sekunde",
+            (Locale::German, _, false) => "Sekunden",
+            (Locale::English, 0, true) => "hour",
+            (Locale::English, 0, false) => "hours",
+            (Locale::English, 1, true) => "minute",
+            (Locale::English, 1, false) => "minutes",
+            (Locale::English, _, true) => "second",
+            (Locale::English, _, false) => "seconds",
+        };
+        let words = parts
+            .iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, index)| format!("{count} {}", unit(*index, *count)))
+            .collect::<Vec<_>>();
+        match words.is_empty() {
+            true => format!("0 {}", unit(2, 0)),
+            false => words.join(" "),
+        }
+    }
+
+    /// Time of day, like `14:05` or `2:05 PM`
+    pub fn format_clock(&self, hour: u8, minute: u8) -> String {
+        match self {
+            Locale::German => format!("{hour:02}:{minute:02}"),
+            Locale::English => {
+                let period = if hour < 12 { "AM" } else { "PM" };
+                let hour = match hour % 12 {
+                    0 => 12,
+                    h => h,
+                };
+                format!("{hour}:{minute:02} {period}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOTH: [Locale; 2] = [Locale::German, Locale::English];
+
+    #[test]
+    fn numbers() {
+        let cases = [
+            (0, "0", "0"),
+            (999, "999", "999"),
+            (1_000, "1.000", "1,000"),
+            (1_234_567, "1.234.567", "1,234,567"),
+            (100_000, "100.000", "100,000"),
+            (
+                u64::MAX,
+                "18.446.744.073.709.551.615",
+                "18,446,744,073,709,551,615",
+            ),
+        ];
+        for (number, german, english) in cases {
+            assert_eq!(Locale::German.format_number(number), german);
+            assert_eq!(Locale::English.format_number(number), english);
+        }
+    }
+
+    #[test]
+    fn durations_are_the_same_in_both_locales() {
+        let cases = [
+            (0, "00:00"),
+            (59, "00:59"),
+            (205, "03:25"),
+            (3_600, "01:00:00"),
+            (3_805, "01:03:25"),
+            (90_000, "25:00:00"),
+        ];
+        for locale in BOTH {
+            for (secs, expected) in cases {
+                assert_eq!(locale.format_duration(Duration::from_secs(secs)), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn durations_in_words() {
+        let cases = [
+            (0, "0 Sekunden", "0 seconds"),
+            (1, "1 Sekunde", "1 second"),
+            (192, "3 Minuten 12 Sekunden", "3 minutes 12 seconds"),
+            (60, "1 Minute", "1 minute"),
+            (3_601, "1 Stunde 1 Sekunde", "1 hour 1 second"),
+            (7_380, "2 Stunden 3 Minuten", "2 hours 3 minutes"),
+        ];
+        for (secs, german, english) in cases {
+            let duration = Duration::from_secs(secs);
+            assert_eq!(Locale::German.format_duration_words(duration), german);
+            assert_eq!(Locale::English.format_duration_words(duration), english);
+        }
+    }
+
+    #[test]
+    fn clock() {
+        let cases = [
+            (0, 0, "00:00", "12:00 AM"),
+            (9, 5, "09:05", "9:05 AM"),
+            (12, 0, "12:00", "12:00 PM"),
+            (14, 5, "14:05", "2:05 PM"),
+            (23, 59, "23:59", "11:59 PM"),
+        ];
+        for (hour, minute, german, english) in cases {
+            assert_eq!(Locale::German.format_clock(hour, minute), german);
+            assert_eq!(Locale::English.format_clock(hour, minute), english);
+        }
+    }
+
+    #[test]
+    fn discord_locales() {
+        assert_eq!(Locale::from_discord("de"), Some(Locale::German));
+        assert_eq!(Locale::from_discord("en-US"), Some(Locale::English));
+        assert_eq!(Locale::from_discord("en-GB"), Some(Locale::English));
+        assert_eq!(Locale::from_discord("fr"), None);
+    }
+
+    #[test]
+    fn guild_locale_wins_over_the_interaction() {
+        assert_eq!(
+            Locale::resolve(Some(Locale::German), Some("en-US")),
+            Locale::German
+        );
+        assert_eq!(Locale::resolve(None, Some("en-US")), Locale::English);
+        assert_eq!(Locale::resolve(None, Some("fr")), Locale::German);
+        // Announcements have no interaction
+        assert_eq!(
+            Locale::resolve(Some(Locale::English), None),
+            Locale::English
+        );
+        assert_eq!(Locale::resolve(None, None), Locale::default());
+    }
+}
@@ -0,0 +1,183 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io;
+use std::process::Stdio;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+const FFPROBE_COMMAND: &str = "ffprobe";
+/// The tags are in the header, so a stream that needs longer is skipped rather than delaying /play
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bytes ffprobe reads at most, enough for the tag headers of common containers
+const PROBE_SIZE: &str = "262144";
+/// Gains outside of this range are clamped, broken tags must not blow out the call
+const MIN_GAIN_DB: f32 = -30.0;
+const MAX_GAIN_DB: f32 = 10.0;
+/// R128 gains are relative to -23 LUFS, ReplayGain to about -18 LUFS
+const R128_TO_REPLAYGAIN_DB: f32 = 5.0;
+
+#[derive(Debug, Error)]
+pub enum LoudnessError {
+    #[error("ffprobe could not be started")]
+    Spawn(#[from] io::Error),
+    #[error("ffprobe took longer than {PROBE_TIMEOUT:?}")]
+    Timeout,
+    #[error("ffprobe failed: {0}")]
+    Failed(String),
+    #[error("ffprobe returned invalid json")]
+    Json(#[from] serde_json::Error),
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    format: Option<Tagged>,
+    #[serde(default)]
+    streams: Vec<Tagged>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Tagged {
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+/// Reads the loudness tags of a direct file or stream. Returns the gain in dB, none if it has no
+/// tags. The caller has to hold a permit of the process limit.
+pub async fn probe_gain(url: &str) -> Result<Option<f32>, LoudnessError> {
+    let output = Command::new(FFPROBE_COMMAND)
+        .args([
+            "-v",
+            "error",
+            "-probesize",
+            PROBE_SIZE,
+            "-print_format",
+            "json",
+            "-show_entries",
+            "format_tags:stream_tags",
+        ])
+        .arg(url)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = timeout(PROBE_TIMEOUT, output)
+        .await
+        .map_err(|_| LoudnessError::Timeout)??;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(LoudnessError::Failed(stderr.trim().to_owned()));
+    }
+    Ok(parse_gain(&serde_json::from_slice(&output.stdout)?))
+}
+
+/// The track gain of the ffprobe output, from ReplayGain tags or else from R128 tags. Container
+/// tags win over stream tags, tag names are compared case-insensitively.
+fn parse_gain(output: &ProbeOutput) -> Option<f32> {
+    let tags = output
+        .format
+        .iter()
+        .chain(&output.streams)
+        .flat_map(|tagged| &tagged.tags);
+    let find = |name: &str| {
+        tags.clone()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    };
+
+    let gain = find("replaygain_track_gain")
+        .and_then(parse_replaygain)
+        .or_else(|| find("r128_track_gain").and_then(parse_r128))?;
+    Some(gain.clamp(MIN_GAIN_DB, MAX_GAIN_DB))
+}
+
+/// Values like `-6.48 dB`
+fn parse_replaygain(value: &str) -> Option<f32> {
+    let number = value.trim();
+    let number = number
+        .strip_suffix("dB")
+        .or_else(|| number.strip_suffix("db"))
+        .unwrap_or(number);
+    number
+        .trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|db| db.is_finite())
+}
+
+/// Q7.8 fixed point in dB, as written by Opus encoders
+fn parse_r128(value: &str) -> Option<f32> {
+    let fixed = value.trim().parse::<i16>().ok()?;
+    Some(f32::from(fixed) / 256.0 + R128_TO_REPLAYGAIN_DB)
+}
+
+/// The volume of a track for its gain, 1 without one
+pub fn gain_factor(gain_db: Option<f32>) -> f32 {
+    gain_db.map_or(1.0, |db| 10f32.powf(db / 20.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gain_of(fixture: &str) -> Option<f32> {
+        let path = format!(
+            "{}/tests/fixtures/loudness/{fixture}",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let output = std::fs::read(path).unwrap();
+        parse_gain(&serde_json::from_slice(&output).unwrap())
+    }
+
+    #[test]
+    fn replaygain_tags() {
+        assert_eq!(gain_of("replaygain_flac.json"), Some(-6.48));
+        // Lower case names and unit as written by some mp3 taggers
+        assert_eq!(gain_of("replaygain_mp3.json"), Some(2.15));
+    }
+
+    #[test]
+    fn r128_tags_are_converted_to_replaygain() {
+        // -2560 / 256 = -10 dB relative to -23 LUFS
+        assert_eq!(gain_of("r128_opus.json"), Some(-5.0));
+    }
+
+    #[test]
+    fn container_replaygain_wins() {
+        assert_eq!(gain_of("replaygain_and_r128.json"), Some(-3.5));
+    }
+
+    #[test]
+    fn missing_tags_have_no_gain() {
+        // An album gain alone is not used for single tracks
+        assert_eq!(gain_of("missing_tags.json"), None);
+        assert_eq!(gain_of("no_entries.json"), None);
+        assert_eq!(parse_gain(&serde_json::from_str("{}").unwrap()), None);
+    }
+
+    #[test]
+    fn broken_replaygain_falls_back_to_r128() {
+        assert_eq!(gain_of("broken_gains.json"), Some(0.0));
+    }
+
+    #[test]
+    fn gains_are_clamped() {
+        assert_eq!(gain_of("out_of_range.json"), Some(MAX_GAIN_DB));
+        let quietest = serde_json::json!({
+            "streams": [{ "tags": { "R128_TRACK_GAIN": "-32768" } }]
+        });
+        assert_eq!(
+            parse_gain(&serde_json::from_value(quietest).unwrap()),
+            Some(MIN_GAIN_DB)
+        );
+    }
+
+    #[test]
+    fn gain_factors() {
+        assert_eq!(gain_factor(None), 1.0);
+        assert_eq!(gain_factor(Some(0.0)), 1.0);
+        assert!((gain_factor(Some(-6.0)) - 0.501).abs() < 0.001);
+        assert!((gain_factor(Some(6.0)) - 1.995).abs() < 0.001);
+    }
+}
@@ -1,71 +1,80 @@
-use crate::music_commands::{GetCallError, JoinVoiceError};
-use crate::youtube::YoutubeClient;
-use log::{error, info, warn, LevelFilter};
-use poise::{CreateReply, FrameworkContext, FrameworkError};
+use gerbot::audit_log::AuditLog;
+use gerbot::auto_pause::AutoPauses;
+use gerbot::autoplay::Autoplay;
+use gerbot::blocklist::Blocklist;
+use gerbot::command_schema::{self, CommandSchemas};
+#[cfg(unix)]
+use gerbot::control;
+use gerbot::departures::{leave_with_reason, Departures, LeaveReason};
+use gerbot::diagnostics::DriverDiagnostics;
+use gerbot::end_reason::EndMarkers;
+use gerbot::error_rates::ErrorRates;
+use gerbot::events::PlaybackEventBus;
+use gerbot::free_notices::FreeNotices;
+use gerbot::guild_settings::GuildSettingsStore;
+use gerbot::guild_state::GuildState;
+use gerbot::history::PlayHistory;
+use gerbot::lifecycle::GuildLifecycle;
+use gerbot::load_guard::LoadGuard;
+use gerbot::outage::GuildOutages;
+use gerbot::outbound::OutboundScheduler;
+use gerbot::overlay::OverlayTokens;
+use gerbot::party_mode::PartyModes;
+use gerbot::persistence::PersistenceHealth;
+use gerbot::plain_text::EmbedHints;
+use gerbot::playback_mode::PlaybackModes;
+use gerbot::playlist_sync::PlaylistSyncStore;
+use gerbot::position_cache::PositionCache;
+use gerbot::report::TrackReports;
+use gerbot::resolution::ResolutionTelemetry;
+use gerbot::resume::{ResumePoints, DEFAULT_RESUME_TTL};
+use gerbot::saved_playlists::SavedPlaylistStore;
+use gerbot::schedule::ScheduleStore;
+use gerbot::staging::StagingStore;
+use gerbot::stall::DEFAULT_STALL_LIMIT;
+use gerbot::start_latency::StartLatency;
+use gerbot::stats::StatsStore;
+use gerbot::tts::{TtsConfig, DEFAULT_TTS_ARGS};
+use gerbot::undo::UndoSlots;
+use gerbot::user_preferences::UserPreferencesStore;
+use gerbot::validator::TrackValidator;
+use gerbot::voice_sessions::VoiceSessions;
+use gerbot::voice_state::VoiceDebouncer;
+use gerbot::web::WebState;
+use gerbot::youtube::quota::{QuotaEstimator, DEFAULT_AUTOCOMPLETE_THRESHOLD, DEFAULT_DAILY_QUOTA};
+use gerbot::youtube::{parse_provider_order, YoutubeClient, YtProvider};
+use gerbot::ytdlp::YtDlpConfig;
+use gerbot::{
+    autoplay, commands, error_rates, free_notices, guild_state, on_api_event, on_poise_error,
+    position_cache, report, schedule, stall, web, AuditLogKey, AutoPausesKey, AutoplayKey,
+    BlocklistKey, CommandSchemasKey, ConfirmThresholdKey, DeparturesKey, DriverDiagnosticsKey,
+    EmbedHintsKey, EndMarkersKey, ErrorRatesKey, FreeNoticesKey, GlobalData, GuildLifecycleKey,
+    GuildOutagesKey, GuildSettingsKey, GuildStateKey, HistoryKey, HttpKey, LoadGuardKey,
+    MaxQueueLengthKey, OutboundKey, OverlayTokensKey, PartyModesKey, PersistenceHealthKey,
+    PlaybackEventsKey, PlaybackModesKey, PlaylistSyncsKey, PositionCacheKey,
+    ResolutionTelemetryKey, ResumePointsKey, SavedPlaylistsKey, ScheduleKey, StagingKey,
+    StartLatencyKey, StatsKey, TrackReportsKey, TrackValidatorKey, TtsKey, UndoSlotsKey,
+    UserPreferencesKey, VoiceDebouncerKey, VoiceSessionsKey, YoutubeKey, YtDlpConfigKey,
+    YtDlpPermitsKey,
+};
+use log::{error, info, LevelFilter};
 use reqwest::Client as HttpClient;
-use serenity::all::{Colour, CreateEmbed};
-use serenity::client::FullEvent;
+use serenity::all::GuildId;
 use serenity::prelude::*;
 use serenity::Client;
-use songbird::SerenityInit;
+use songbird::{SerenityInit, Songbird};
 use std::env;
-use thiserror::Error;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::unbounded_channel;
+use tokio::sync::{Notify, Semaphore};
 
-mod metadata;
-mod music_commands;
-mod serde;
-mod youtube;
-
-const SUCCESS_COLOUR: Colour = Colour::BLURPLE;
-const ERROR_COLOUR: Colour = Colour::RED;
-
-// Types used by all command functions
-type CommandContext<'a> = poise::Context<'a, GlobalData, CommandError>;
-
-#[derive(Error, Debug)]
-enum CommandError {
-    #[error("Serenity error")]
-    Serenity(#[from] SerenityError),
-    #[error("Failed to join a voice channel")]
-    JoinVoice(#[from] JoinVoiceError),
-    #[error("Failed to leave a voice channel")]
-    LeaveVoice,
-    #[error("Guild-only command executed from DMs. This should have been caught by poise")]
-    NotInGuild,
-    #[error("Songbird instance could not be retrieved from the command context")]
-    SongbirdNotFound,
-    #[error("The author is not in a voice channel")]
-    UserNotInVoice,
-    #[error("The author is not in a voice channel with the bot")]
-    NotInCall,
-    #[error("No track is currently playing")]
-    QueueEmpty,
-}
-
-impl From<GetCallError> for CommandError {
-    fn from(value: GetCallError) -> Self {
-        match value {
-            GetCallError::NotInGuild => CommandError::NotInGuild,
-            GetCallError::SongbirdNotFound => CommandError::SongbirdNotFound,
-            GetCallError::NotInCall => CommandError::NotInCall,
-        }
-    }
-}
-
-struct HttpKey;
-
-impl TypeMapKey for HttpKey {
-    type Value = HttpClient;
-}
-
-struct YoutubeKey;
-
-impl TypeMapKey for YoutubeKey {
-    type Value = YoutubeClient;
-}
-
-// Custom user data passed to all command functions
-pub struct GlobalData {}
+const DEFAULT_MAX_YTDLP_PROCESSES: usize = 4;
+const DEFAULT_CONFIRM_THRESHOLD: usize = 10;
+const DEFAULT_MAX_PLAYLIST_LOADS: usize = 3;
+const DEFAULT_MAX_QUEUE_LENGTH: usize = 1000;
+const DEFAULT_MAX_FILE_SIZE_MB: u64 = 500;
 
 #[tokio::main]
 async fn main() {
@@ -78,20 +87,200 @@ async fn main() {
     }
 
     let token = env::var("DISCORD_TOKEN").expect("Missing `DISCORD_TOKEN` env var");
+    let youtube_quota = QuotaEstimator::new(
+        env::var("YOUTUBE_DAILY_QUOTA")
+            .ok()
+            .map(|v| v.parse().expect("`YOUTUBE_DAILY_QUOTA` is not a number"))
+            .unwrap_or(DEFAULT_DAILY_QUOTA),
+        env::var("YOUTUBE_AUTOCOMPLETE_QUOTA_PERCENT")
+            .ok()
+            .map(|v| {
+                v.parse()
+                    .expect("`YOUTUBE_AUTOCOMPLETE_QUOTA_PERCENT` is not a number")
+            })
+            .unwrap_or(DEFAULT_AUTOCOMPLETE_THRESHOLD),
+    );
+    let max_ytdlp_processes = env::var("YTDLP_MAX_PROCESSES")
+        .ok()
+        .map(|v| v.parse().expect("`YTDLP_MAX_PROCESSES` is not a number"))
+        .unwrap_or(DEFAULT_MAX_YTDLP_PROCESSES);
+    let confirm_threshold = env::var("CONFIRM_THRESHOLD")
+        .ok()
+        .map(|v| v.parse().expect("`CONFIRM_THRESHOLD` is not a number"))
+        .unwrap_or(DEFAULT_CONFIRM_THRESHOLD);
+    let max_playlist_loads = env::var("MAX_PLAYLIST_LOADS")
+        .ok()
+        .map(|v| v.parse().expect("`MAX_PLAYLIST_LOADS` is not a number"))
+        .unwrap_or(DEFAULT_MAX_PLAYLIST_LOADS);
+    let max_queue_length = env::var("MAX_QUEUE_LENGTH")
+        .ok()
+        .map(|v| v.parse().expect("`MAX_QUEUE_LENGTH` is not a number"))
+        .unwrap_or(DEFAULT_MAX_QUEUE_LENGTH);
+    let stall_limit = env::var("STALL_RESTART_SECS")
+        .ok()
+        .map(|v| Duration::from_secs(v.parse().expect("`STALL_RESTART_SECS` is not a number")))
+        .unwrap_or(DEFAULT_STALL_LIMIT);
+    let resume_ttl = env::var("RESUME_TTL_MINUTES")
+        .ok()
+        .map(|v| {
+            Duration::from_secs(
+                60 * v
+                    .parse::<u64>()
+                    .expect("`RESUME_TTL_MINUTES` is not a number"),
+            )
+        })
+        .unwrap_or(DEFAULT_RESUME_TTL);
+    // Unlimited unless configured
+    let max_voice_connections = env::var("MAX_VOICE_CONNECTIONS")
+        .ok()
+        .map(|v| v.parse().expect("`MAX_VOICE_CONNECTIONS` is not a number"));
+    let prevalidate_tracks = env::var("PREVALIDATE_TRACKS").is_ok_and(|v| v == "true");
+    let ytdlp_config = Arc::new(YtDlpConfig {
+        cookies_file: env::var("YTDLP_COOKIES").ok().map(Into::into),
+        po_token: env::var("YTDLP_PO_TOKEN").ok(),
+        max_file_size: Some(
+            env::var("MAX_FILE_SIZE_MB")
+                .ok()
+                .map(|v| v.parse().expect("`MAX_FILE_SIZE_MB` is not a number"))
+                .unwrap_or(DEFAULT_MAX_FILE_SIZE_MB)
+                * 1024
+                * 1024,
+        ),
+        preview_clips: !env::var("PREVIEW_CLIPS").is_ok_and(|v| v == "false"),
+    });
+    let youtube_providers = env::var("YOUTUBE_PROVIDERS")
+        .map(|v| parse_provider_order(&v).expect("`YOUTUBE_PROVIDERS` is invalid"))
+        .unwrap_or_else(|_| vec![YtProvider::Api]);
+    // Files that can not be loaded or written degrade their store instead of stopping the bot
+    let persistence = Arc::new(PersistenceHealth::default());
+    // Scheduled playbacks are only kept across restarts with a file
+    let schedules = Arc::new(ScheduleStore::load(
+        env::var("SCHEDULE_FILE").ok().map(Into::into),
+        persistence.clone(),
+    ));
+    // Synced playlists would add everything again after a restart without a file
+    let playlist_syncs = Arc::new(PlaylistSyncStore::load(
+        env::var("PLAYLIST_SYNC_FILE").ok().map(Into::into),
+        persistence.clone(),
+    ));
+    let saved_playlists = Arc::new(SavedPlaylistStore::load(
+        env::var("SAVED_PLAYLISTS_FILE").ok().map(Into::into),
+        persistence.clone(),
+    ));
+    // Leaderboards start over with every restart without a file
+    let stats = Arc::new(StatsStore::load(
+        env::var("STATS_FILE").ok().map(Into::into),
+        persistence.clone(),
+    ));
+    // Users have to set their preferences again after a restart without a file
+    let user_preferences = Arc::new(UserPreferencesStore::load(
+        env::var("USER_PREFERENCES_FILE").ok().map(Into::into),
+        persistence.clone(),
+    ));
+    // Moderators can only look up actions since the last restart without a file
+    let audit_log = Arc::new(AuditLog::load(
+        env::var("AUDIT_LOG_FILE").ok().map(Into::into),
+        persistence.clone(),
+    ));
+    // Without a file a restart ends every party mode early
+    let party_modes = Arc::new(PartyModes::load(
+        env::var("PARTY_MODE_FILE").ok().map(Into::into),
+        persistence.clone(),
+    ));
+    // Off unless configured, announcements also have to be enabled per guild
+    let tts = env::var("TTS_COMMAND").ok().map(|command| {
+        Arc::new(TtsConfig {
+            command: command.into(),
+            args: env::var("TTS_ARGS")
+                .unwrap_or_else(|_| DEFAULT_TTS_ARGS.to_owned())
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect(),
+        })
+    });
+    let http_bind = env::var("HTTP_BIND")
+        .ok()
+        .map(|v| v.parse().expect("`HTTP_BIND` is not a socket address"));
+    // Incidents are only shown in /status without it
+    let owner_webhook = env::var("OWNER_WEBHOOK_URL").ok();
+    #[cfg(unix)]
+    let control_socket = env::var("CONTROL_SOCKET").ok().map(PathBuf::from);
+    // Set by the control socket to shut down like on SIGTERM
+    let shutdown = Arc::new(Notify::new());
+    // Moderators have to configure their guild again after a restart without a file
+    let guild_settings = Arc::new(GuildSettingsStore::load(
+        env::var("GUILD_SETTINGS_FILE").ok().map(Into::into),
+        persistence.clone(),
+    ));
+    let error_rates = Arc::new(ErrorRates::default());
+    let outbound = Arc::new(OutboundScheduler::new(
+        guild_settings.clone(),
+        error_rates.clone(),
+    ));
+    let overlay_tokens = Arc::new(OverlayTokens::default());
+    let playback_events = Arc::new(PlaybackEventBus::default());
+    let start_latency = Arc::new(StartLatency::default());
+    let departures = Arc::new(Departures::default());
+    let history = Arc::new(PlayHistory::default());
+    let playback_modes = Arc::new(PlaybackModes::default());
+    let embed_hints = Arc::new(EmbedHints::default());
+    let staging = Arc::new(StagingStore::default());
+    let command_schemas = Arc::new(CommandSchemas::default());
+    let position_cache = Arc::new(PositionCache::default());
+    let end_markers = Arc::new(EndMarkers::default());
+    let autoplay = Arc::new(Autoplay::default());
+    let track_reports = Arc::new(TrackReports::default());
+    let (stall_actions, stall_receiver) = unbounded_channel();
+    let driver_diagnostics = Arc::new(DriverDiagnostics::new(
+        position_cache.clone(),
+        stall_limit,
+        stall_actions,
+    ));
+    let undo_slots = Arc::new(UndoSlots::default());
+    let outages = Arc::new(GuildOutages::default());
+    let auto_pauses = Arc::new(AutoPauses::default());
+    let resume_points = Arc::new(ResumePoints::new(resume_ttl));
+    let free_notices = Arc::new(FreeNotices::default());
+    let guild_state = Arc::new(GuildState::new(vec![
+        playback_events.clone(),
+        departures.clone(),
+        history.clone(),
+        playback_modes.clone(),
+        embed_hints.clone(),
+        staging.clone(),
+        driver_diagnostics.clone(),
+        undo_slots.clone(),
+        outages.clone(),
+        auto_pauses.clone(),
+        position_cache.clone(),
+        end_markers.clone(),
+        autoplay.clone(),
+        track_reports.clone(),
+        resume_points.clone(),
+        free_notices.clone(),
+    ]));
+    let blocklist = Arc::new(Blocklist::default());
+    let lifecycle = Arc::new(GuildLifecycle::new(vec![
+        guild_settings.clone(),
+        schedules.clone(),
+        playlist_syncs.clone(),
+        saved_playlists.clone(),
+        party_modes.clone(),
+        blocklist.clone(),
+        stats.clone(),
+        audit_log.clone(),
+    ]));
+    let songbird = Songbird::serenity();
+    if let Some(cookies_file) = &ytdlp_config.cookies_file {
+        std::fs::File::open(cookies_file).expect("`YTDLP_COOKIES` file is not readable");
+    }
+    if let Some(tts) = &tts {
+        tts.check().expect("`TTS_COMMAND` is not an existing file");
+    }
 
     // Create framework configuration
     let options = poise::FrameworkOptions {
-        commands: vec![
-            music_commands::help(),
-            music_commands::play(),
-            music_commands::playlist(),
-            music_commands::now_playing(),
-            music_commands::queue(),
-            music_commands::loop_command(),
-            music_commands::skip(),
-            music_commands::stop(),
-            music_commands::leave(),
-        ],
+        commands: commands::all(),
         on_error: |error| Box::pin(on_poise_error(error)),
         // This code is run before every command
         pre_command: |ctx| {
@@ -102,6 +291,15 @@ async fn main() {
                     ctx.author().name,
                     ctx.author().id
                 );
+                if let Some(guild_id) = ctx.guild_id() {
+                    ctx.serenity_context()
+                        .data
+                        .read()
+                        .await
+                        .get::<GuildStateKey>()
+                        .expect("Guaranteed to exist in the typemap")
+                        .touch(guild_id);
+                }
             })
         },
         event_handler: |ctx, event, framework, data| {
@@ -112,11 +310,36 @@ async fn main() {
 
     // Build framework
     let framework = poise::Framework::builder()
-        .setup(move |ctx, _ready, framework| {
-            Box::pin(async move {
-                poise::builtins::register_globally(ctx, &framework.options().commands).await?;
-                Ok(GlobalData {})
-            })
+        .setup({
+            let command_schemas = command_schemas.clone();
+            let schedules = schedules.clone();
+            let autoplay = autoplay.clone();
+            let track_reports = track_reports.clone();
+            let free_notices = free_notices.clone();
+            let shutdown = shutdown.clone();
+            move |ctx, _ready, framework| {
+                Box::pin(async move {
+                    // Needs the context, so it can only start here
+                    #[cfg(unix)]
+                    if let Some(path) = control_socket {
+                        tokio::spawn(control::serve(path, ctx.clone(), shutdown));
+                    }
+                    #[cfg(not(unix))]
+                    drop(shutdown);
+                    tokio::spawn(schedule::run_scheduler(ctx.clone(), schedules));
+                    tokio::spawn(autoplay::run_autoplay(ctx.clone(), autoplay));
+                    tokio::spawn(report::run_report_sessions(ctx.clone(), track_reports));
+                    tokio::spawn(free_notices::run_free_notices(ctx.clone(), free_notices));
+                    tokio::spawn(stall::run_recovery(ctx.clone(), stall_receiver));
+                    let registered = serenity::all::Command::set_global_commands(
+                        ctx,
+                        command_schema::registration(&framework.options().commands),
+                    )
+                    .await?;
+                    command_schemas.record(&registered);
+                    Ok(GlobalData {})
+                })
+            }
         })
         .options(options)
         .build();
@@ -125,244 +348,139 @@ async fn main() {
     let mut client = Client::builder(&token, GatewayIntents::empty())
         .intents(GatewayIntents::non_privileged())
         .framework(framework)
-        .register_songbird()
+        .register_songbird_with(songbird.clone())
         .type_map_insert::<HttpKey>(HttpClient::new())
         .type_map_insert::<YoutubeKey>(YoutubeClient::new(
             HttpClient::new(),
             std::env::var("YOUTUBE_API_KEY").ok(),
+            ytdlp_config.clone(),
+            youtube_providers,
+            youtube_quota,
+            error_rates.clone(),
         ))
+        .type_map_insert::<YtDlpPermitsKey>(Arc::new(Semaphore::new(max_ytdlp_processes)))
+        .type_map_insert::<YtDlpConfigKey>(ytdlp_config.clone())
+        .type_map_insert::<OutboundKey>(outbound.clone())
+        .type_map_insert::<OverlayTokensKey>(overlay_tokens.clone())
+        .type_map_insert::<PlaybackEventsKey>(playback_events.clone())
+        .type_map_insert::<ConfirmThresholdKey>(confirm_threshold)
+        .type_map_insert::<MaxQueueLengthKey>(max_queue_length)
+        .type_map_insert::<VoiceDebouncerKey>(Arc::new(VoiceDebouncer::default()))
+        .type_map_insert::<DeparturesKey>(departures)
+        .type_map_insert::<VoiceSessionsKey>(Arc::new(VoiceSessions::new(max_voice_connections)))
+        .type_map_insert::<HistoryKey>(history)
+        .type_map_insert::<StatsKey>(stats)
+        .type_map_insert::<GuildSettingsKey>(guild_settings)
+        .type_map_insert::<UserPreferencesKey>(user_preferences)
+        .type_map_insert::<PlaybackModesKey>(playback_modes)
+        .type_map_insert::<EmbedHintsKey>(embed_hints)
+        .type_map_insert::<CommandSchemasKey>(command_schemas)
+        .type_map_insert::<DriverDiagnosticsKey>(driver_diagnostics)
+        .type_map_insert::<AuditLogKey>(audit_log)
+        .type_map_insert::<UndoSlotsKey>(undo_slots)
+        .type_map_insert::<ResumePointsKey>(resume_points)
+        .type_map_insert::<FreeNoticesKey>(free_notices)
+        .type_map_insert::<TtsKey>(tts)
+        .type_map_insert::<ErrorRatesKey>(error_rates.clone())
+        .type_map_insert::<PersistenceHealthKey>(persistence.clone())
+        .type_map_insert::<PartyModesKey>(party_modes)
+        .type_map_insert::<GuildOutagesKey>(outages)
+        .type_map_insert::<ScheduleKey>(schedules)
+        .type_map_insert::<EndMarkersKey>(end_markers)
+        .type_map_insert::<PositionCacheKey>(position_cache.clone())
+        .type_map_insert::<StartLatencyKey>(start_latency.clone())
+        .type_map_insert::<PlaylistSyncsKey>(playlist_syncs)
+        .type_map_insert::<SavedPlaylistsKey>(saved_playlists)
+        .type_map_insert::<AutoplayKey>(autoplay)
+        .type_map_insert::<BlocklistKey>(blocklist)
+        .type_map_insert::<TrackReportsKey>(track_reports)
+        .type_map_insert::<AutoPausesKey>(auto_pauses)
+        .type_map_insert::<GuildLifecycleKey>(lifecycle.clone())
+        .type_map_insert::<ResolutionTelemetryKey>(Arc::new(ResolutionTelemetry::default()))
+        .type_map_insert::<StagingKey>(staging)
+        .type_map_insert::<LoadGuardKey>(Arc::new(LoadGuard::new(max_playlist_loads)))
+        .type_map_insert::<GuildStateKey>(guild_state.clone())
+        .type_map_insert::<TrackValidatorKey>(Arc::new(TrackValidator::new(
+            prevalidate_tracks,
+            ytdlp_config,
+            outbound,
+            playback_events.clone(),
+        )))
         .await
         .expect("Error creating client");
 
-    // Start client
-    client.start().await.unwrap();
-}
-
-async fn on_api_event(
-    ctx: &Context,
-    event: &FullEvent,
-    framework: FrameworkContext<'_, GlobalData, CommandError>,
-    _data: &GlobalData,
-) -> Result<(), CommandError> {
-    match event {
-        FullEvent::CacheReady { guilds } => {
-            // Print startup info
-            info!("Logged in as {}", ctx.cache.current_user().name);
-            if guilds.len() < 10 {
-                info!(
-                    "Active on these guilds: {}",
-                    guilds
-                        .iter()
-                        .map(|g| format!("{}<{}>", ctx.cache.guild(g).unwrap().name, g))
-                        .collect::<Vec<String>>()
-                        .join(", ")
-                );
-            } else {
-                info!("Active on {} guilds", guilds.len())
+    // Leave all voice channels before exiting
+    tokio::spawn({
+        let data = client.data.clone();
+        let shard_manager = client.shard_manager.clone();
+        let songbird = songbird.clone();
+        async move {
+            tokio::select! {
+                _ = shutdown_signal() => {}
+                _ = shutdown.notified() => {}
             }
-        }
-        // Leave empty voice channels automatically
-        FullEvent::VoiceStateUpdate { new, .. } => {
-            let songbird = songbird::get(ctx)
-                .await
-                .ok_or(CommandError::SongbirdNotFound)?;
-
-            let Some(guild_id) = new.guild_id else {
-                error!("The bot is in a private call (How???)");
-                return Ok(());
-            };
-
-            let Some(call_lock) = songbird.get(guild_id) else {
-                // Not in a call
-                return Ok(());
-            };
-            let mut call = call_lock.lock().await;
-
-            // Clear queue when forcefully disconnected by a moderator
-            if call.current_channel().is_none() && !call.queue().is_empty() {
-                info!(
-                    "Bot got disconnected from a voice channel in guild {}",
-                    guild_id
-                );
-                call.queue().stop();
-                call.stop();
-            }
-
-            // Check if the bot is the only one left in its channel
-            let should_leave = call.current_channel().is_some_and(|channel_id| {
-                let guild = guild_id.to_guild_cached(ctx).expect("Guild not in cache");
-
-                !guild.voice_states.iter().any(|(_, state)| {
-                    state.channel_id == Some(channel_id.0.into())
-                        && state.user_id != framework.bot_id
-                })
-            });
-
-            if should_leave {
-                call.queue().stop();
-                call.stop();
-                call.leave().await.map_err(|_| CommandError::LeaveVoice)?;
+            info!("Shutting down");
+            for (guild_id, call) in songbird.iter() {
+                let mut call = call.lock().await;
+                let guild_id = GuildId::new(guild_id.0.get());
+                if let Err(e) =
+                    leave_with_reason(&data, guild_id, &mut call, LeaveReason::Shutdown).await
+                {
+                    error!("Failed to leave voice in guild {}: {}", guild_id, e);
+                }
             }
+            shard_manager.shutdown_all().await;
         }
-        _ => {}
-    };
-
-    Ok(())
-}
-
-// ======== Error handling ========
+    });
 
-async fn respond_err(ctx: &CommandContext<'_>, details: impl Into<String>) {
-    let embed = CreateEmbed::new()
-        .title("Fehler")
-        .colour(ERROR_COLOUR)
-        .field("Details", details, false);
+    tokio::spawn(position_cache::run_invalidation(
+        position_cache.clone(),
+        playback_events.clone(),
+    ));
+    tokio::spawn(error_rates::run_incident_check(
+        error_rates.clone(),
+        HttpClient::new(),
+        owner_webhook,
+    ));
+    tokio::spawn(guild_state::run_janitor(
+        guild_state,
+        lifecycle,
+        songbird.clone(),
+    ));
 
-    if let Err(e) = ctx
-        .send(CreateReply::default().embed(embed).ephemeral(true))
-        .await
-    {
-        error!("Error while sending error response: {}", e);
+    // Start the optional http server
+    if let Some(addr) = http_bind {
+        tokio::spawn(web::serve(
+            addr,
+            Arc::new(WebState {
+                songbird,
+                overlay_tokens,
+                playback_events,
+                start_latency,
+                position_cache,
+                error_rates,
+                persistence,
+            }),
+        ));
     }
-}
 
-async fn handle_command_error(ctx: &CommandContext<'_>, error: CommandError) {
-    match error {
-        CommandError::Serenity(inner) => {
-            error!("Serenity error: {}", inner);
-            respond_err(ctx, "Ein unerwarteter Fehler ist aufgetreten").await;
-        }
-        CommandError::JoinVoice(inner) => match inner {
-            JoinVoiceError::Join(inner) => {
-                error!("Failed to join voice channel: {}", inner);
-                respond_err(ctx, "Der Bot konnte deinem Sprachkanal nicht beitreten").await;
-            }
-            JoinVoiceError::Occupied => {
-                respond_err(
-                    ctx,
-                    "Der Bot wird bereits in einem anderen Sprachkanal verwendet",
-                )
-                .await;
-            }
-        },
-        CommandError::LeaveVoice => {
-            error!("Failed to leave voice channel: {}", error);
-            respond_err(ctx, "Ein unerwarteter Fehler ist aufgetreten").await;
-        }
-        CommandError::NotInGuild => {
-            // This should never happen as it is caught by the poise attribute
-        }
-        CommandError::SongbirdNotFound => {
-            error!("Songbird instance could not be retrieved from the typemap");
-            respond_err(ctx, "Ein unerwarteter Fehler ist aufgetreten").await;
-        }
-        CommandError::UserNotInVoice => {
-            respond_err(ctx, "Du bist nicht in einem Sprachkanal in diesem Server").await;
-        }
-        CommandError::NotInCall => {
-            respond_err(ctx, "Du bist nicht in einem Sprachkanal mit dem Bot").await;
-        }
-        CommandError::QueueEmpty => respond_err(ctx, "Momentan wird nichts abgespielt").await,
-    }
+    // Start client
+    client.start().await.unwrap();
 }
 
-async fn on_poise_error(error: poise::FrameworkError<'_, GlobalData, CommandError>) {
-    match error {
-        FrameworkError::Setup { error, .. } => error!("Error in data setup: {}", error),
-        FrameworkError::EventHandler { error, event, .. } => {
-            error!(
-                "Error in {} event handler: {}",
-                event.snake_case_name(),
-                error
-            )
-        }
-        FrameworkError::Command { ctx, error, .. } => {
-            handle_command_error(&ctx, error).await;
-        }
-        FrameworkError::CommandPanic { ctx, payload, .. } => {
-            match payload {
-                Some(payload) => error!("Command panicked. Details:\n{}", payload),
-                None => error!("Command panicked"),
-            }
-            respond_err(&ctx, "Ein unerwarteter Fehler ist aufgetreten").await;
-        }
-        FrameworkError::ArgumentParse {
-            ctx, input, error, ..
-        } => {
-            let msg = match input {
-                Some(arg) => {
-                    error!("Error while parsing command argument {arg}: {error}");
-                    format!("Fehler beim Lesen des Command-Arguments {arg}")
-                }
-                None => {
-                    error!("Error while parsing command arguments: {error}");
-                    "Fehler beim Lesen eines Command-Arguments".to_owned()
-                }
-            };
-
-            respond_err(&ctx, msg).await;
-        }
-        FrameworkError::CommandStructureMismatch {
-            ctx, description, ..
-        } => {
-            error!(
-                "Failed to deserialize interaction for /{} (Maybe the command hasn't fully updated?): {}",
-                ctx.command.name, description,
-            );
-            let msg = "Ein unerwarteter Fehler ist aufgetreten. Du kannst versuchen, Discord neu zu starten oder ein paar Minuten zu warten.";
-            respond_err(&CommandContext::Application(ctx), msg).await;
-        }
-        FrameworkError::CooldownHit {
-            ctx,
-            remaining_cooldown,
-            ..
-        } => {
-            let msg = format!(
-                "Nicht so schnell. Bitte warte {} Sekunden vor dem nächsten Versuch",
-                remaining_cooldown.as_secs()
-            );
-            respond_err(&ctx, msg).await;
-        }
-        FrameworkError::MissingBotPermissions {
-            ctx,
-            missing_permissions,
-            ..
-        } => {
-            let msg = format!(
-                "Der Command konnte nicht ausgeführt werden, weil dem Bot folgende Berechtigungen fehlen: {}",
-                missing_permissions,
-            );
-            respond_err(&ctx, msg).await;
-        }
-        FrameworkError::NotAnOwner { ctx, .. } => {
-            let msg = "Dieser Command kann nur von den Besitzern des Bots verwendet werden";
-            respond_err(&ctx, msg).await;
-        }
-        FrameworkError::GuildOnly { ctx, .. } => {
-            let msg = "Dieser Command kann nur in einem Server verwendet werden";
-            respond_err(&ctx, msg).await;
-        }
-        FrameworkError::DmOnly { ctx, .. } => {
-            let msg = "Dieser Command kann nur in DMs verwendet werden";
-            respond_err(&ctx, msg).await;
-        }
-        FrameworkError::NsfwOnly { ctx, .. } => {
-            let msg = "Dieser Command kann nur in NSFW Kanälen verwendet werden";
-            respond_err(&ctx, msg).await;
-        }
-        FrameworkError::CommandCheckFailed { ctx, error, .. } => match error {
-            Some(e) => {
-                handle_command_error(&ctx, e).await;
-            }
-            None => {
-                respond_err(&ctx, "Der Command wurde abgebrochen").await;
-            }
-        },
-        FrameworkError::UnknownInteraction { interaction, .. } => {
-            warn!("Unknown interaction received: {:?}", interaction);
-        }
-        _ => {
-            // Anything else is only relevant for prefix commands
+/// Resolves on ctrl-c, or when the container is stopped
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
         }
     }
+    #[cfg(not(unix))]
+    {
+        _ = tokio::signal::ctrl_c().await;
+    }
 }
@@ -1,18 +1,83 @@
+use crate::resolution::ResolutionPath;
+use crate::title_clean::{clean_title, CleanTitle};
+use crate::title_sanitize::sanitize_title;
 use crate::youtube::YtVideo;
 use reqwest::Url;
 use serenity::all::UserId;
 use serenity::prelude::TypeMapKey;
 use songbird::input::AuxMetadata;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+/// The platform a track is streamed from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackSource {
+    YouTube,
+    SoundCloud,
+    Stream,
+    Attachment,
+}
+
+impl TrackSource {
+    pub fn from_url(url: &Url) -> Self {
+        match url.domain() {
+            Some(d) if d == "youtu.be" || d.ends_with("youtube.com") => TrackSource::YouTube,
+            Some(d) if d.ends_with("soundcloud.com") => TrackSource::SoundCloud,
+            Some("cdn.discordapp.com" | "media.discordapp.net") => TrackSource::Attachment,
+            _ => TrackSource::Stream,
+        }
+    }
+
+    /// Short icon for prefixing list entries
+    pub fn icon(&self) -> &'static str {
+        match self {
+            TrackSource::YouTube => "▶️",
+            TrackSource::SoundCloud => "☁️",
+            TrackSource::Stream => "📻",
+            TrackSource::Attachment => "📎",
+        }
+    }
+
+    /// Display name of the platform
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrackSource::YouTube => "YouTube",
+            TrackSource::SoundCloud => "SoundCloud",
+            TrackSource::Stream => "Stream",
+            TrackSource::Attachment => "Datei",
+        }
+    }
+}
+
+/// Result of the background playability check
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Playability {
+    Unchecked = 0,
+    Playable = 1,
+    Unplayable = 2,
+}
+
 /// Minimal metadata required by the music commands
 pub struct TrackMetadata {
+    /// Sanitized, safe to show in embeds
     pub title: String,
     pub author: String,
     pub duration: Duration,
     pub source_url: Url,
+    pub source: TrackSource,
     pub requested_by: Option<UserId>,
+    /// How the track was found, only set for tracks resolved from a command
+    pub resolution: Option<ResolutionPath>,
+    /// Longer than the soft duration limit of the guild when it was added
+    pub over_soft_limit: bool,
+    /// Title before it was sanitized, only for the audit log. Unset if sanitizing changed nothing.
+    pub original_title: Option<String>,
+    /// Spoken announcement between two tracks, not listed and not counted
+    pub announcement: bool,
+    /// Loudness gain in dB from the tags of direct files and streams, none for unity
+    pub gain_db: Option<f32>,
+    playability: AtomicU8,
 }
 
 impl Default for TrackMetadata {
@@ -22,12 +87,77 @@ impl Default for TrackMetadata {
             author: "Unknown".to_string(),
             duration: Duration::default(),
             source_url: Url::parse("https://example.com").unwrap(),
+            source: TrackSource::Stream,
             requested_by: None,
+            resolution: None,
+            over_soft_limit: false,
+            original_title: None,
+            announcement: false,
+            gain_db: None,
+            playability: AtomicU8::new(Playability::Unchecked as u8),
+        }
+    }
+}
+
+impl Clone for TrackMetadata {
+    fn clone(&self) -> Self {
+        Self {
+            title: self.title.clone(),
+            author: self.author.clone(),
+            duration: self.duration,
+            source_url: self.source_url.clone(),
+            source: self.source,
+            requested_by: self.requested_by,
+            resolution: self.resolution,
+            over_soft_limit: self.over_soft_limit,
+            original_title: self.original_title.clone(),
+            announcement: self.announcement,
+            gain_db: self.gain_db,
+            playability: AtomicU8::new(self.playability.load(Ordering::Relaxed)),
         }
     }
 }
 
 impl TrackMetadata {
+    pub fn playability(&self) -> Playability {
+        match self.playability.load(Ordering::Relaxed) {
+            1 => Playability::Playable,
+            2 => Playability::Unplayable,
+            _ => Playability::Unchecked,
+        }
+    }
+
+    pub fn set_playability(&self, playability: Playability) {
+        self.playability.store(playability as u8, Ordering::Relaxed);
+    }
+
+    /// Artist and song without upload qualifiers, for lookups by name
+    pub fn clean_title(&self) -> CleanTitle {
+        clean_title(&self.title, &self.author)
+    }
+
+    /// Placeholder for tracks whose metadata could not be loaded, keeping the url if it is valid
+    pub fn unresolved(source: &str) -> TrackMetadata {
+        match Url::parse(source) {
+            Ok(source_url) => TrackMetadata {
+                source: TrackSource::from_url(&source_url),
+                source_url,
+                ..Default::default()
+            },
+            Err(_) => TrackMetadata::default(),
+        }
+    }
+
+    /// Metadata of a text-to-speech announcement with its text as the title
+    pub fn announcement(text: String) -> TrackMetadata {
+        TrackMetadata {
+            title: text,
+            author: "Ansage".to_owned(),
+            announcement: true,
+            ..Default::default()
+        }
+    }
+
     pub fn from_with_request(value: impl Into<Self>, requested_by: UserId) -> TrackMetadata {
         TrackMetadata {
             requested_by: Some(requested_by),
@@ -38,34 +168,137 @@ impl TrackMetadata {
 
 impl From<AuxMetadata> for TrackMetadata {
     fn from(value: AuxMetadata) -> Self {
+        let source_url = value
+            .source_url
+            .and_then(|url| Url::parse(&url).ok())
+            .unwrap_or(Url::parse("https://example.com").unwrap());
+
+        let (title, original_title) = sanitize(value.title.as_deref().unwrap_or("Unknown"));
         TrackMetadata {
-            title: value.title.unwrap_or_else(|| "Unknown".to_owned()),
-            author: value.artist.unwrap_or_else(|| "Unknown".to_owned()),
+            title,
+            author: sanitize_title(value.artist.as_deref().unwrap_or("Unknown")),
             duration: value.duration.unwrap_or_default(),
-            source_url: value
-                .source_url
-                .and_then(|url| Url::parse(&url).ok())
-                .unwrap_or(Url::parse("https://example.com").unwrap()),
+            source: TrackSource::from_url(&source_url),
+            source_url,
             requested_by: None,
+            resolution: None,
+            over_soft_limit: false,
+            original_title,
+            announcement: false,
+            gain_db: None,
+            playability: AtomicU8::new(Playability::Unchecked as u8),
         }
     }
 }
 
 impl From<YtVideo> for TrackMetadata {
     fn from(value: YtVideo) -> Self {
+        let (title, original_title) = sanitize(&value.title);
         Self {
-            source_url: value.get_yt_url(), // This is first to make the borrow checker happy
-            title: value.title,
-            author: value.channel_title,
+            source_url: value.get_yt_url(),
+            title,
+            author: sanitize_title(&value.channel_title),
             duration: value.duration,
+            source: TrackSource::YouTube,
             requested_by: None,
+            resolution: None,
+            over_soft_limit: false,
+            original_title,
+            announcement: false,
+            gain_db: None,
+            playability: AtomicU8::new(Playability::Unchecked as u8),
         }
     }
 }
 
+/// Sanitized title and the original if it was changed
+fn sanitize(raw: &str) -> (String, Option<String>) {
+    let title = sanitize_title(raw);
+    let original = (title != raw).then(|| raw.to_owned());
+    (title, original)
+}
+
 /// Key type for using TrackMetadata in a TypeMap
 pub struct TrackMetadataKey;
 
 impl TypeMapKey for TrackMetadataKey {
     type Value = Arc<TrackMetadata>;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every variant, the match fails to compile when one is added without being listed here
+    fn all_sources() -> [TrackSource; 4] {
+        let sources = [
+            TrackSource::YouTube,
+            TrackSource::SoundCloud,
+            TrackSource::Stream,
+            TrackSource::Attachment,
+        ];
+        for source in sources {
+            match source {
+                TrackSource::YouTube
+                | TrackSource::SoundCloud
+                | TrackSource::Stream
+                | TrackSource::Attachment => {}
+            }
+        }
+        sources
+    }
+
+    #[test]
+    fn every_source_has_an_icon_and_a_name() {
+        for source in all_sources() {
+            assert!(!source.icon().trim().is_empty(), "{source:?} has no icon");
+            assert!(!source.name().trim().is_empty(), "{source:?} has no name");
+        }
+    }
+
+    #[test]
+    fn sources_have_distinct_labels() {
+        let sources = all_sources();
+        for (i, a) in sources.iter().enumerate() {
+            for b in &sources[i + 1..] {
+                assert_ne!(a.icon(), b.icon());
+                assert_ne!(a.name(), b.name());
+            }
+        }
+    }
+
+    #[test]
+    fn source_from_url() {
+        let cases = [
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+                TrackSource::YouTube,
+            ),
+            (
+                "https://music.youtube.com/watch?v=dQw4w9WgXcQ",
+                TrackSource::YouTube,
+            ),
+            ("https://youtu.be/dQw4w9WgXcQ", TrackSource::YouTube),
+            (
+                "https://soundcloud.com/artist/track",
+                TrackSource::SoundCloud,
+            ),
+            (
+                "https://cdn.discordapp.com/attachments/1/2/a.mp3",
+                TrackSource::Attachment,
+            ),
+            (
+                "https://media.discordapp.net/attachments/1/2/a.mp3",
+                TrackSource::Attachment,
+            ),
+            ("https://radio.example.com/live.mp3", TrackSource::Stream),
+        ];
+        for (url, expected) in cases {
+            assert_eq!(
+                TrackSource::from_url(&Url::parse(url).unwrap()),
+                expected,
+                "{url}"
+            );
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use crate::CommandContext;
+use serenity::all::{Cache, ChannelId, ChannelType, GuildId, PartialChannel, Permissions};
+
+/// Where a command can be used from, as far as sending messages there is concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// Text and announcement channels
+    Text,
+    /// The chat built into a voice or stage channel
+    VoiceText,
+    /// A thread of a text channel
+    Thread,
+    /// A post of a forum or media channel, which is a thread as well
+    ForumPost,
+    /// Channels that take no messages from the bot, like categories or directories
+    Other,
+}
+
+impl ChannelKind {
+    /// `parent` is the kind of the channel a thread belongs to, it tells forum posts apart
+    pub fn of(kind: ChannelType, parent: Option<ChannelType>) -> Self {
+        match kind {
+            ChannelType::Text | ChannelType::News => ChannelKind::Text,
+            ChannelType::Voice | ChannelType::Stage => ChannelKind::VoiceText,
+            ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::NewsThread => {
+                match parent {
+                    // Media channels (16) are forums with a different layout
+                    Some(ChannelType::Forum | ChannelType::Unknown(16)) => ChannelKind::ForumPost,
+                    _ => ChannelKind::Thread,
+                }
+            }
+            _ => ChannelKind::Other,
+        }
+    }
+
+    fn is_thread(self) -> bool {
+        matches!(self, ChannelKind::Thread | ChannelKind::ForumPost)
+    }
+}
+
+/// The channel a command or button was used in
+#[derive(Clone, Copy, Debug)]
+pub struct InvokingChannel {
+    pub id: ChannelId,
+    pub kind: ChannelKind,
+    /// Whether the bot may post there. Assumed when Discord sent no permissions.
+    pub can_send: bool,
+}
+
+impl InvokingChannel {
+    pub fn new(id: ChannelId, kind: ChannelKind, permissions: Option<Permissions>) -> Self {
+        // Threads have a permission of their own, the one for the parent does not matter there
+        let needed = match kind.is_thread() {
+            true => Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES_IN_THREADS,
+            false => Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+        };
+        Self {
+            id,
+            kind,
+            can_send: !matches!(permissions, Some(p) if !p.contains(needed)),
+        }
+    }
+
+    /// Whether messages that are not replies can be sent there
+    pub fn can_receive(&self) -> bool {
+        self.kind != ChannelKind::Other && self.can_send
+    }
+
+    /// The channel of a command. Interactions carry the kind and the permissions of the bot,
+    /// prefix commands only have the cache.
+    pub fn of_command(ctx: CommandContext<'_>) -> Self {
+        let cache = &ctx.serenity_context().cache;
+        match ctx {
+            poise::Context::Application(app) => Self::of_interaction(
+                cache,
+                app.interaction.guild_id,
+                app.interaction.channel_id,
+                app.interaction.channel.as_ref(),
+                app.interaction.app_permissions,
+            ),
+            poise::Context::Prefix(_) => ctx
+                .guild_id()
+                .and_then(|guild_id| cached_channel(cache, guild_id, ctx.channel_id()))
+                .unwrap_or_else(|| Self::new(ctx.channel_id(), ChannelKind::Text, None)),
+        }
+    }
+
+    /// The channel of an interaction. The kind of a parent channel is only known from the cache,
+    /// a thread of an uncached channel counts as a plain thread.
+    pub fn of_interaction(
+        cache: &Cache,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        channel: Option<&PartialChannel>,
+        permissions: Option<Permissions>,
+    ) -> Self {
+        let Some(channel) = channel else {
+            // Discord does not send the channel with every interaction
+            return guild_id
+                .and_then(|guild_id| cached_channel(cache, guild_id, channel_id))
+                .unwrap_or_else(|| Self::new(channel_id, ChannelKind::Text, permissions));
+        };
+        let parent = match (guild_id, channel.parent_id) {
+            (Some(guild_id), Some(parent_id)) => cache
+                .guild(guild_id)
+                .and_then(|guild| guild.channels.get(&parent_id).map(|parent| parent.kind)),
+            _ => None,
+        };
+        Self::new(
+            channel_id,
+            ChannelKind::of(channel.kind, parent),
+            permissions,
+        )
+    }
+}
+
+/// A channel as the cache knows it, with the permissions of the bot there
+pub fn cached_channel(
+    cache: &Cache,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Option<InvokingChannel> {
+    let bot_id = cache.current_user().id;
+    let guild = cache.guild(guild_id)?;
+    let channel = guild
+        .channels
+        .get(&channel_id)
+        .or_else(|| guild.threads.iter().find(|thread| thread.id == channel_id))?;
+    let parent = channel
+        .parent_id
+        .and_then(|parent_id| guild.channels.get(&parent_id))
+        .map(|parent| parent.kind);
+    let permissions = guild
+        .members
+        .get(&bot_id)
+        .map(|member| guild.user_permissions_in(channel, member));
+    Some(InvokingChannel::new(
+        channel_id,
+        ChannelKind::of(channel.kind, parent),
+        permissions,
+    ))
+}
+
+/// Who a notice is for, which decides between the channel of the command and the announce
+/// channel of the guild
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoticeTarget {
+    /// Playback notices, they belong in the announce channel if one is configured
+    Announcements,
+    /// Pings of the user of the command, they belong where the user asked
+    Invoker,
+}
+
+/// The channel a notice is posted in, if any. The preferred channel of the target is used if
+/// the bot can post there, otherwise the other one.
+pub fn select_notice_channel(
+    target: NoticeTarget,
+    invoking: &InvokingChannel,
+    announce_channel: Option<ChannelId>,
+) -> Option<ChannelId> {
+    let invoking = invoking.can_receive().then_some(invoking.id);
+    match target {
+        NoticeTarget::Announcements => announce_channel.or(invoking),
+        NoticeTarget::Invoker => invoking.or(announce_channel),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INVOKING: ChannelId = ChannelId::new(1);
+    const ANNOUNCE: ChannelId = ChannelId::new(2);
+
+    /// Permissions of a bot that may post everywhere
+    fn all_allowed() -> Option<Permissions> {
+        Some(
+            Permissions::VIEW_CHANNEL
+                | Permissions::SEND_MESSAGES
+                | Permissions::SEND_MESSAGES_IN_THREADS,
+        )
+    }
+
+    fn channel(kind: ChannelType, parent: Option<ChannelType>) -> InvokingChannel {
+        InvokingChannel::new(INVOKING, ChannelKind::of(kind, parent), all_allowed())
+    }
+
+    #[test]
+    fn channel_kinds() {
+        let table = [
+            (ChannelType::Text, None, ChannelKind::Text),
+            (ChannelType::News, None, ChannelKind::Text),
+            (ChannelType::Voice, None, ChannelKind::VoiceText),
+            (ChannelType::Stage, None, ChannelKind::VoiceText),
+            (
+                ChannelType::PublicThread,
+                Some(ChannelType::Text),
+                ChannelKind::Thread,
+            ),
+            (ChannelType::PrivateThread, None, ChannelKind::Thread),
+            (
+                ChannelType::PublicThread,
+                Some(ChannelType::Forum),
+                ChannelKind::ForumPost,
+            ),
+            (
+                ChannelType::PublicThread,
+                Some(ChannelType::Unknown(16)),
+                ChannelKind::ForumPost,
+            ),
+            (ChannelType::Forum, None, ChannelKind::Other),
+            (ChannelType::Category, None, ChannelKind::Other),
+        ];
+        for (kind, parent, expected) in table {
+            assert_eq!(
+                ChannelKind::of(kind, parent),
+                expected,
+                "{kind:?} in {parent:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn every_postable_kind_receives_notices() {
+        let kinds = [
+            (ChannelType::Text, None),
+            (ChannelType::Voice, None),
+            (ChannelType::PublicThread, Some(ChannelType::Text)),
+            (ChannelType::PublicThread, Some(ChannelType::Forum)),
+        ];
+        for (kind, parent) in kinds {
+            let invoking = channel(kind, parent);
+            assert_eq!(
+                select_notice_channel(NoticeTarget::Invoker, &invoking, Some(ANNOUNCE)),
+                Some(INVOKING),
+                "{kind:?}"
+            );
+            // Playback notices prefer the announce channel, wherever the command was used
+            assert_eq!(
+                select_notice_channel(NoticeTarget::Announcements, &invoking, Some(ANNOUNCE)),
+                Some(ANNOUNCE),
+                "{kind:?}"
+            );
+            assert_eq!(
+                select_notice_channel(NoticeTarget::Announcements, &invoking, None),
+                Some(INVOKING),
+                "{kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn channels_without_messages_fall_back_to_the_announce_channel() {
+        let forum = channel(ChannelType::Forum, None);
+        assert!(!forum.can_receive());
+        assert_eq!(
+            select_notice_channel(NoticeTarget::Invoker, &forum, Some(ANNOUNCE)),
+            Some(ANNOUNCE)
+        );
+        assert_eq!(
+            select_notice_channel(NoticeTarget::Invoker, &forum, None),
+            None
+        );
+    }
+
+    #[test]
+    fn threads_need_the_thread_permission() {
+        // May post in the text channel, but not in its threads
+        let text_only = Some(Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES);
+        for parent in [ChannelType::Text, ChannelType::Forum] {
+            let thread = InvokingChannel::new(
+                INVOKING,
+                ChannelKind::of(ChannelType::PublicThread, Some(parent)),
+                text_only,
+            );
+            assert!(!thread.can_send, "{parent:?}");
+            assert_eq!(
+                select_notice_channel(NoticeTarget::Invoker, &thread, Some(ANNOUNCE)),
+                Some(ANNOUNCE)
+            );
+        }
+        // And the other way around for voice channels
+        let threads_only = Some(Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES_IN_THREADS);
+        let voice = InvokingChannel::new(INVOKING, ChannelKind::VoiceText, threads_only);
+        assert!(!voice.can_send);
+        let thread = InvokingChannel::new(INVOKING, ChannelKind::Thread, threads_only);
+        assert!(thread.can_send);
+    }
+
+    #[test]
+    fn missing_permissions_are_assumed() {
+        let invoking = InvokingChannel::new(INVOKING, ChannelKind::VoiceText, None);
+        assert!(invoking.can_receive());
+        let hidden = InvokingChannel::new(INVOKING, ChannelKind::Text, Some(Permissions::empty()));
+        assert_eq!(
+            select_notice_channel(NoticeTarget::Announcements, &hidden, None),
+            None
+        );
+    }
+}
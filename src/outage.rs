@@ -0,0 +1,245 @@
+use crate::departures::{leave_with_reason, LeaveReason};
+use crate::events::PlaybackEvent;
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use crate::{GuildOutagesKey, GuildStateKey, PlaybackEventsKey};
+use log::{info, warn};
+use serenity::all::{ChannelId, GuildId};
+use serenity::client::Context;
+use songbird::tracks::PlayMode;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Session of a guild that is frozen while Discord reports it as unavailable
+#[derive(Clone, Copy, Debug)]
+pub struct FrozenSession {
+    pub since: Instant,
+    /// Channel of the call when the outage started, to rejoin if the connection did not survive
+    pub channel: Option<ChannelId>,
+    /// Whether playback was paused by the freeze and has to be resumed afterwards
+    pub paused: bool,
+}
+
+impl FrozenSession {
+    /// The channel to join again once the guild is available, if the connection did not survive
+    /// the outage
+    fn rejoin_channel(&self, connected: bool) -> Option<ChannelId> {
+        self.channel.filter(|_| !connected)
+    }
+}
+
+/// Guilds that are currently unavailable because of a Discord outage. Their queues are kept and
+/// voice events are ignored until they are available again.
+#[derive(Default)]
+pub struct GuildOutages {
+    frozen: GuildStateMap<FrozenSession>,
+}
+
+impl GuildOutages {
+    pub fn is_frozen(&self, guild_id: GuildId) -> bool {
+        self.frozen.contains(guild_id)
+    }
+
+    /// Returns false if the guild was already frozen
+    fn freeze(&self, guild_id: GuildId, session: FrozenSession) -> bool {
+        if self.frozen.contains(guild_id) {
+            return false;
+        }
+        self.frozen.insert(guild_id, session);
+        true
+    }
+
+    fn thaw(&self, guild_id: GuildId) -> Option<FrozenSession> {
+        self.frozen.remove(guild_id)
+    }
+}
+
+impl GuildScoped for GuildOutages {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::Outages
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.frozen.entry_counts(|_| 1)
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.frozen.remove_on_leave(guild_id);
+    }
+}
+
+async fn get_outages(ctx: &Context) -> Arc<GuildOutages> {
+    ctx.data
+        .read()
+        .await
+        .get::<GuildOutagesKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+}
+
+/// Freezes the session of a guild that went offline. Playback is paused if the voice connection
+/// survived, the queue is kept either way.
+pub async fn on_guild_unavailable(ctx: &Context, guild_id: GuildId) {
+    let mut session = FrozenSession {
+        since: Instant::now(),
+        channel: None,
+        paused: false,
+    };
+
+    if let Some(call) = songbird::get(ctx).await.and_then(|s| s.get(guild_id)) {
+        let call = call.lock().await;
+        session.channel = call.current_channel().map(|c| ChannelId::new(c.0.get()));
+        if let Some(current) = call.queue().current() {
+            let playing = current
+                .get_info()
+                .await
+                .is_ok_and(|info| info.playing == PlayMode::Play);
+            session.paused = playing && call.queue().pause().is_ok();
+        }
+    }
+
+    if session.paused {
+        publish_play_state_changed(ctx, guild_id).await;
+    }
+    if get_outages(ctx).await.freeze(guild_id, session) {
+        warn!(
+            "Guild {guild_id} became unavailable, freezing its session (channel: {:?}, paused: {})",
+            session.channel, session.paused
+        );
+    }
+}
+
+/// Resumes the frozen session of a guild that is available again
+pub async fn on_guild_available(ctx: &Context, guild_id: GuildId) {
+    let Some(session) = get_outages(ctx).await.thaw(guild_id) else {
+        return;
+    };
+    info!(
+        "Guild {guild_id} is available again after {:?}, resuming its session",
+        session.since.elapsed()
+    );
+
+    let Some(songbird) = songbird::get(ctx).await else {
+        return;
+    };
+    let Some(call) = songbird.get(guild_id) else {
+        return;
+    };
+
+    let connected = call.lock().await.current_channel().is_some();
+    if let Some(channel) = session.rejoin_channel(connected) {
+        // The call keeps its queue when joining again
+        if let Err(e) = songbird.join(guild_id, channel).await {
+            warn!("Failed to rejoin voice channel in guild {guild_id} after an outage: {e}");
+            return;
+        }
+    }
+
+    if session.paused {
+        _ = call.lock().await.queue().resume();
+        publish_play_state_changed(ctx, guild_id).await;
+    }
+}
+
+async fn publish_play_state_changed(ctx: &Context, guild_id: GuildId) {
+    ctx.data
+        .read()
+        .await
+        .get::<PlaybackEventsKey>()
+        .expect("Guaranteed to exist in the typemap")
+        .publish(guild_id, PlaybackEvent::PlayStateChanged);
+}
+
+/// Drops all runtime state of a guild the bot was removed from. Persistent settings are kept in
+/// case the bot is invited again.
+pub async fn on_guild_removed(ctx: &Context, guild_id: GuildId) {
+    info!("Removed from guild {guild_id}, dropping its runtime state");
+
+    if let Some(songbird) = songbird::get(ctx).await {
+        if let Some(call) = songbird.get(guild_id) {
+            let mut call = call.lock().await;
+            // Discord already dropped the connection, so leaving usually fails
+            _ = leave_with_reason(
+                &ctx.data,
+                guild_id,
+                &mut call,
+                LeaveReason::RemovedFromGuild,
+            )
+            .await;
+        }
+        // Fails if there was no call, which is fine
+        _ = songbird.remove(guild_id).await;
+    }
+
+    ctx.data
+        .read()
+        .await
+        .get::<GuildStateKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+        .forget(guild_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const CHANNEL: ChannelId = ChannelId::new(10);
+
+    fn session(channel: Option<ChannelId>, paused: bool) -> FrozenSession {
+        FrozenSession {
+            since: Instant::now(),
+            channel,
+            paused,
+        }
+    }
+
+    #[test]
+    fn outage_freezes_and_thaws_the_session() {
+        let outages = GuildOutages::default();
+        assert!(!outages.is_frozen(GUILD));
+
+        assert!(outages.freeze(GUILD, session(Some(CHANNEL), true)));
+        assert!(outages.is_frozen(GUILD));
+        assert!(!outages.is_frozen(GuildId::new(2)));
+
+        let thawed = outages.thaw(GUILD).unwrap();
+        assert_eq!(thawed.channel, Some(CHANNEL));
+        assert!(thawed.paused);
+        assert!(!outages.is_frozen(GUILD));
+        assert!(outages.thaw(GUILD).is_none());
+    }
+
+    #[test]
+    fn repeated_unavailability_keeps_the_first_session() {
+        let outages = GuildOutages::default();
+        assert!(outages.freeze(GUILD, session(Some(CHANNEL), true)));
+        // The second event sees the session paused by the first one
+        assert!(!outages.freeze(GUILD, session(None, false)));
+
+        let thawed = outages.thaw(GUILD).unwrap();
+        assert_eq!(thawed.channel, Some(CHANNEL));
+        assert!(thawed.paused);
+    }
+
+    #[test]
+    fn removal_drops_a_frozen_session() {
+        let outages = GuildOutages::default();
+        outages.freeze(GUILD, session(Some(CHANNEL), false));
+        outages.forget(GUILD);
+        assert!(!outages.is_frozen(GUILD));
+        assert!(outages.entry_counts().is_empty());
+    }
+
+    #[test]
+    fn only_lost_connections_are_rejoined() {
+        assert_eq!(
+            session(Some(CHANNEL), false).rejoin_channel(false),
+            Some(CHANNEL)
+        );
+        assert_eq!(session(Some(CHANNEL), false).rejoin_channel(true), None);
+        // The bot was not in a channel when the outage started
+        assert_eq!(session(None, false).rejoin_channel(false), None);
+    }
+}
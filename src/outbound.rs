@@ -0,0 +1,770 @@
+use crate::error_rates::{ErrorRates, ErrorSource};
+use crate::guild_settings::GuildSettingsStore;
+use log::{debug, warn};
+use serenity::all::{
+    ChannelId, CreateMessage, EditMessage, EditThread, GuildId, Http, HttpError, Mentionable,
+    MessageId, StatusCode,
+};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{sleep_until, Instant};
+
+/// Minimum time between two edits of the same message
+const MIN_EDIT_INTERVAL: Duration = Duration::from_secs(5);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Posts waiting per guild. Beyond this the oldest post that shows playback state is dropped,
+/// newer state replaces it anyway.
+const MAX_PENDING_POSTS: usize = 10;
+/// Discord error code for sends to an archived thread
+const THREAD_ARCHIVED: isize = 50083;
+
+/// Counters for the metrics of background messages
+#[derive(Debug, Default)]
+pub struct OutboundStats {
+    pub sent: AtomicU64,
+    /// Edits that were replaced by a newer edit before being sent
+    pub coalesced: AtomicU64,
+    /// Messages that were dropped after the api returned an error
+    pub failed: AtomicU64,
+    pub rate_limited: AtomicU64,
+    /// Messages that were dropped because newer playback state was already sent or too many
+    /// were waiting
+    pub outdated: AtomicU64,
+    /// Messages that were not sent because the guild is silent
+    pub suppressed: AtomicU64,
+    /// Posts that went to the parent channel of an archived or deleted thread
+    pub redirected: AtomicU64,
+}
+
+/// Pending edits of messages. Only the newest edit of a message is kept and every message is
+/// edited at most once per [MIN_EDIT_INTERVAL].
+///
+/// All functions take the current time so the logic is independent of the real clock.
+pub struct EditQueue<T> {
+    pending: HashMap<MessageId, T>,
+    last_sent: HashMap<MessageId, Instant>,
+}
+
+impl<T> Default for EditQueue<T> {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+            last_sent: HashMap::new(),
+        }
+    }
+}
+
+impl<T> EditQueue<T> {
+    /// Adds an edit, returns true if it replaced an older pending edit of the same message
+    pub fn push(&mut self, message_id: MessageId, edit: T) -> bool {
+        self.pending.insert(message_id, edit).is_some()
+    }
+
+    pub fn pending(&self, message_id: MessageId) -> Option<&T> {
+        self.pending.get(&message_id)
+    }
+
+    /// Time at which the next pending edit may be sent
+    pub fn next_due(&self, now: Instant) -> Option<Instant> {
+        self.pending.keys().map(|id| self.due_at(id, now)).min()
+    }
+
+    /// Removes an edit that may be sent at `now`
+    pub fn pop_due(&mut self, now: Instant) -> Option<(MessageId, T)> {
+        let message_id = *self.pending.keys().find(|id| self.due_at(id, now) <= now)?;
+        self.last_sent.insert(message_id, now);
+        self.pending
+            .remove(&message_id)
+            .map(|edit| (message_id, edit))
+    }
+
+    fn due_at(&self, message_id: &MessageId, now: Instant) -> Instant {
+        match self.last_sent.get(message_id) {
+            Some(last) => *last + MIN_EDIT_INTERVAL,
+            None => now,
+        }
+    }
+}
+
+/// A message that is ready to be sent
+#[derive(Debug, PartialEq, Eq)]
+pub enum Next<P, E> {
+    Post(P),
+    Edit(MessageId, E),
+}
+
+/// Posts and edits of one guild in the order they are sent. Messages that show playback state
+/// carry the [sequence](crate::events::PlaybackEventBus::sequence) of the state they show. Once a
+/// message was sent, nothing with an older sequence is sent after it, so a burst of skips can
+/// not end with an outdated message.
+///
+/// All functions take the current time so the logic is independent of the real clock.
+pub struct OutboundQueue<P, E> {
+    posts: VecDeque<(Option<u64>, P)>,
+    edits: EditQueue<(Option<u64>, E)>,
+    /// Sequence of the newest state that was sent
+    sent_sequence: Option<u64>,
+    /// Messages dropped since the last [OutboundQueue::take_outdated]
+    outdated: u64,
+}
+
+impl<P, E> Default for OutboundQueue<P, E> {
+    fn default() -> Self {
+        Self {
+            posts: VecDeque::new(),
+            edits: EditQueue::default(),
+            sent_sequence: None,
+            outdated: 0,
+        }
+    }
+}
+
+impl<P, E> OutboundQueue<P, E> {
+    /// Queues a post. If too many are waiting, the oldest one with playback state is dropped,
+    /// or the oldest one at all if none has.
+    pub fn push_post(&mut self, sequence: Option<u64>, post: P) {
+        if self.is_outdated(sequence) {
+            self.outdated += 1;
+            return;
+        }
+        self.posts.push_back((sequence, post));
+        if self.posts.len() > MAX_PENDING_POSTS {
+            let oldest = self
+                .posts
+                .iter()
+                .position(|(sequence, _)| sequence.is_some())
+                .unwrap_or(0);
+            self.posts.remove(oldest);
+            self.outdated += 1;
+        }
+    }
+
+    /// Queues an edit, returns true if it replaced an older pending edit of the same message.
+    /// An edit with older state than the pending one is dropped instead.
+    pub fn push_edit(&mut self, message_id: MessageId, sequence: Option<u64>, edit: E) -> bool {
+        let pending_is_newer = self
+            .edits
+            .pending(message_id)
+            .is_some_and(|(pending, _)| is_older(sequence, *pending));
+        if pending_is_newer || self.is_outdated(sequence) {
+            self.outdated += 1;
+            return false;
+        }
+        self.edits.push(message_id, (sequence, edit))
+    }
+
+    /// Time at which the next message may be sent. Posts are sent right away.
+    pub fn next_due(&self, now: Instant) -> Option<Instant> {
+        match self.posts.is_empty() {
+            true => self.edits.next_due(now),
+            false => Some(now),
+        }
+    }
+
+    /// Removes the next message that may be sent at `now`, posts first. Messages that became
+    /// outdated while waiting are dropped.
+    pub fn pop_due(&mut self, now: Instant) -> Option<Next<P, E>> {
+        while let Some((sequence, post)) = self.posts.pop_front() {
+            if self.mark_sent(sequence) {
+                return Some(Next::Post(post));
+            }
+        }
+        while let Some((message_id, (sequence, edit))) = self.edits.pop_due(now) {
+            if self.mark_sent(sequence) {
+                return Some(Next::Edit(message_id, edit));
+            }
+        }
+        None
+    }
+
+    /// Number of dropped messages since the last call
+    pub fn take_outdated(&mut self) -> u64 {
+        std::mem::take(&mut self.outdated)
+    }
+
+    fn is_outdated(&self, sequence: Option<u64>) -> bool {
+        is_older(sequence, self.sent_sequence)
+    }
+
+    /// Returns false and drops the message if it is outdated
+    fn mark_sent(&mut self, sequence: Option<u64>) -> bool {
+        if self.is_outdated(sequence) {
+            self.outdated += 1;
+            return false;
+        }
+        self.sent_sequence = self.sent_sequence.max(sequence);
+        true
+    }
+}
+
+/// Messages without playback state are never outdated
+fn is_older(sequence: Option<u64>, than: Option<u64>) -> bool {
+    matches!((sequence, than), (Some(sequence), Some(than)) if sequence < than)
+}
+
+enum Outbound {
+    Post(Option<u64>, ChannelId, CreateMessage),
+    Edit(Option<u64>, ChannelId, MessageId, EditMessage),
+    /// A thread of the guild was deleted, with its parent channel
+    ThreadDeleted(ChannelId, ChannelId),
+}
+
+/// Why posts to a thread go to its parent channel instead
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RedirectReason {
+    /// The thread could not be unarchived
+    Archived,
+    Deleted,
+}
+
+/// Threads of one guild whose posts go to their parent channel
+#[derive(Debug, Default)]
+struct ThreadRedirects {
+    /// Channels that got posts, only their deletion matters
+    posted: HashSet<ChannelId>,
+    /// Thread to parent, with whether the parent was already told about the move
+    redirects: HashMap<ChannelId, (ChannelId, RedirectReason, bool)>,
+}
+
+impl ThreadRedirects {
+    fn thread_deleted(&mut self, thread: ChannelId, parent: ChannelId) {
+        if self.posted.contains(&thread) {
+            self.redirects
+                .insert(thread, (parent, RedirectReason::Deleted, false));
+        }
+    }
+
+    fn redirect(&mut self, thread: ChannelId, parent: ChannelId, reason: RedirectReason) {
+        self.redirects.insert(thread, (parent, reason, false));
+    }
+
+    /// The channel a post to `channel` goes to, and the notice to post there first if the
+    /// channel was redirected since the last post
+    fn resolve(&mut self, channel: ChannelId) -> (ChannelId, Option<CreateMessage>) {
+        self.posted.insert(channel);
+        let Some((parent, reason, noticed)) = self.redirects.get_mut(&channel) else {
+            return (channel, None);
+        };
+        let notice = (!*noticed).then(|| {
+            let reason = match reason {
+                RedirectReason::Archived => "ist archiviert",
+                RedirectReason::Deleted => "wurde gelöscht",
+            };
+            CreateMessage::new().content(format!(
+                "Der Thread {} {reason}, Nachrichten des Bots kommen jetzt hierher",
+                channel.mention()
+            ))
+        });
+        *noticed = true;
+        (*parent, notice)
+    }
+}
+
+/// Sends background messages (announcements, status panels) per guild without ever blocking
+/// the caller. Silent guilds get none, see [QuietLevel](crate::guild_settings::QuietLevel).
+pub struct OutboundScheduler {
+    workers: Mutex<HashMap<GuildId, UnboundedSender<Outbound>>>,
+    guild_settings: Arc<GuildSettingsStore>,
+    error_rates: Arc<ErrorRates>,
+    pub stats: Arc<OutboundStats>,
+}
+
+impl OutboundScheduler {
+    pub fn new(guild_settings: Arc<GuildSettingsStore>, error_rates: Arc<ErrorRates>) -> Self {
+        Self {
+            workers: Mutex::new(HashMap::new()),
+            guild_settings,
+            error_rates,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Posts a message. `sequence` is the event sequence of the playback state it shows, if any,
+    /// see [OutboundQueue].
+    pub fn post(
+        &self,
+        http: &Arc<Http>,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        sequence: Option<u64>,
+        message: CreateMessage,
+    ) {
+        self.send(
+            http,
+            guild_id,
+            Outbound::Post(sequence, channel_id, message),
+        );
+    }
+
+    /// Sends later posts to a deleted thread to its parent channel. Guilds without background
+    /// messages since the start are not affected.
+    pub fn thread_deleted(&self, guild_id: GuildId, thread: ChannelId, parent: ChannelId) {
+        if let Some(sender) = self.workers.lock().unwrap().get(&guild_id) {
+            _ = sender.send(Outbound::ThreadDeleted(thread, parent));
+        }
+    }
+
+    /// Edits a message. Edits that arrive faster than the message may be edited are coalesced.
+    pub fn edit(
+        &self,
+        http: &Arc<Http>,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        message_id: MessageId,
+        sequence: Option<u64>,
+        edit: EditMessage,
+    ) {
+        self.send(
+            http,
+            guild_id,
+            Outbound::Edit(sequence, channel_id, message_id, edit),
+        );
+    }
+
+    fn send(&self, http: &Arc<Http>, guild_id: GuildId, outbound: Outbound) {
+        if !self.guild_settings.get(guild_id).quiet.allows_posts() {
+            self.stats.suppressed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let mut workers = self.workers.lock().unwrap();
+        let sender = workers.entry(guild_id).or_insert_with(|| {
+            let (sender, receiver) = unbounded_channel();
+            tokio::spawn(run_worker(
+                http.clone(),
+                self.stats.clone(),
+                self.error_rates.clone(),
+                receiver,
+            ));
+            sender
+        });
+
+        if let Err(e) = sender.send(outbound) {
+            // Worker is gone, start a fresh one on the next message
+            workers.remove(&guild_id);
+            drop(workers);
+            self.send(http, guild_id, e.0);
+        }
+    }
+}
+
+async fn run_worker(
+    http: Arc<Http>,
+    stats: Arc<OutboundStats>,
+    error_rates: Arc<ErrorRates>,
+    mut receiver: UnboundedReceiver<Outbound>,
+) {
+    let mut queue =
+        OutboundQueue::<(ChannelId, CreateMessage), (ChannelId, EditMessage)>::default();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut blocked_until = Instant::now();
+    let mut threads = ThreadRedirects::default();
+
+    loop {
+        stats
+            .outdated
+            .fetch_add(queue.take_outdated(), Ordering::Relaxed);
+        let next_due = queue
+            .next_due(Instant::now())
+            .map(|due| due.max(blocked_until));
+
+        tokio::select! {
+            // Everything that arrived is queued before sending, so outdated messages are dropped
+            biased;
+            outbound = receiver.recv() => match outbound {
+                Some(Outbound::Post(sequence, channel_id, message)) => {
+                    queue.push_post(sequence, (channel_id, message));
+                    continue;
+                }
+                Some(Outbound::Edit(sequence, channel_id, message_id, edit)) => {
+                    if queue.push_edit(message_id, sequence, (channel_id, edit)) {
+                        stats.coalesced.fetch_add(1, Ordering::Relaxed);
+                    }
+                    continue;
+                }
+                Some(Outbound::ThreadDeleted(thread, parent)) => {
+                    threads.thread_deleted(thread, parent);
+                    continue;
+                }
+                None => return,
+            },
+            _ = sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {}
+        }
+
+        let result = match queue.pop_due(Instant::now()) {
+            Some(Next::Post((channel_id, message))) => {
+                send_post(&http, &stats, &mut threads, channel_id, message).await
+            }
+            Some(Next::Edit(message_id, (channel_id, edit))) => {
+                send_edit(&http, channel_id, message_id, edit).await
+            }
+            None => continue,
+        };
+
+        match result {
+            Ok(()) => {
+                error_rates.record(ErrorSource::Discord, true);
+                stats.sent.fetch_add(1, Ordering::Relaxed);
+                backoff = INITIAL_BACKOFF;
+            }
+            // Expected under load, so they do not count as failed calls
+            Err(e) if is_rate_limit(&e) => {
+                stats.rate_limited.fetch_add(1, Ordering::Relaxed);
+                debug!(
+                    "Rate limited while sending a background message, backing off for {backoff:?}"
+                );
+                blocked_until = Instant::now() + backoff;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            Err(e) => {
+                error_rates.record(ErrorSource::Discord, false);
+                stats.failed.fetch_add(1, Ordering::Relaxed);
+                warn!("Failed to send background message: {}", e);
+            }
+        }
+    }
+}
+
+/// Posts a message. A thread that was archived in the meantime is unarchived, if that is not
+/// allowed the post and all later ones go to its parent channel.
+async fn send_post(
+    http: &Http,
+    stats: &OutboundStats,
+    threads: &mut ThreadRedirects,
+    channel_id: ChannelId,
+    message: CreateMessage,
+) -> Result<(), serenity::Error> {
+    let (target, notice) = threads.resolve(channel_id);
+    if target != channel_id {
+        stats.redirected.fetch_add(1, Ordering::Relaxed);
+    }
+    if let Some(notice) = notice {
+        target.send_message(http, notice).await?;
+    }
+    let error = match target.send_message(http, message.clone()).await {
+        Ok(_) => return Ok(()),
+        Err(e) if is_archived_thread(&e) => e,
+        Err(e) => return Err(e),
+    };
+
+    match unarchive(http, target).await {
+        Ok(()) => target.send_message(http, message).await.map(|_| ()),
+        Err(Some(parent)) => {
+            debug!("Thread {target} could not be unarchived, posting in {parent} instead");
+            threads.redirect(target, parent, RedirectReason::Archived);
+            Box::pin(send_post(http, stats, threads, target, message)).await
+        }
+        Err(None) => Err(error),
+    }
+}
+
+/// Edits a message, unarchiving its thread if needed. Messages cannot move, so edits of a thread
+/// that stays archived are dropped.
+async fn send_edit(
+    http: &Http,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    edit: EditMessage,
+) -> Result<(), serenity::Error> {
+    match channel_id
+        .edit_message(http, message_id, edit.clone())
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) if is_archived_thread(&e) && unarchive(http, channel_id).await.is_ok() => channel_id
+            .edit_message(http, message_id, edit)
+            .await
+            .map(|_| ()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Unarchives a thread. Returns its parent channel if that failed, e.g. because it is locked.
+async fn unarchive(http: &Http, thread: ChannelId) -> Result<(), Option<ChannelId>> {
+    match thread
+        .edit_thread(http, EditThread::new().archived(false))
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            debug!("Failed to unarchive thread {thread}: {e}");
+            let channel = thread.to_channel(http).await.ok();
+            Err(channel
+                .and_then(|channel| channel.guild())
+                .and_then(|channel| channel.parent_id))
+        }
+    }
+}
+
+fn is_archived_thread(error: &serenity::Error) -> bool {
+    matches!(
+        error,
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response))
+            if response.error.code == THREAD_ARCHIVED
+    )
+}
+
+fn is_rate_limit(error: &serenity::Error) -> bool {
+    matches!(
+        error,
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response))
+            if response.status_code == StatusCode::TOO_MANY_REQUESTS
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: MessageId = MessageId::new(1);
+    const OTHER_MESSAGE: MessageId = MessageId::new(2);
+
+    #[test]
+    fn first_edit_is_due_right_away() {
+        let start = Instant::now();
+        let mut edits = EditQueue::default();
+        assert!(!edits.push(MESSAGE, "a"));
+        assert_eq!(edits.next_due(start), Some(start));
+        assert_eq!(edits.pop_due(start), Some((MESSAGE, "a")));
+        assert_eq!(edits.next_due(start), None);
+    }
+
+    #[test]
+    fn edits_within_the_interval_are_coalesced() {
+        let start = Instant::now();
+        let mut edits = EditQueue::default();
+        edits.push(MESSAGE, 0);
+        edits.pop_due(start);
+
+        // Ten edits during the interval only send the newest one, once it is over
+        for (i, offset) in (1..=10).zip(0..) {
+            let replaced = edits.push(MESSAGE, i);
+            assert_eq!(replaced, i > 1);
+            assert_eq!(
+                edits.pop_due(start + Duration::from_millis(offset * 400)),
+                None
+            );
+        }
+        assert_eq!(edits.next_due(start), Some(start + MIN_EDIT_INTERVAL));
+        assert_eq!(
+            edits.pop_due(start + MIN_EDIT_INTERVAL),
+            Some((MESSAGE, 10))
+        );
+        assert_eq!(edits.pop_due(start + MIN_EDIT_INTERVAL * 2), None);
+    }
+
+    #[test]
+    fn interval_is_per_message() {
+        let start = Instant::now();
+        let mut edits = EditQueue::default();
+        edits.push(MESSAGE, "a");
+        edits.pop_due(start);
+        edits.push(MESSAGE, "b");
+        edits.push(OTHER_MESSAGE, "c");
+
+        assert_eq!(edits.pop_due(start), Some((OTHER_MESSAGE, "c")));
+        assert_eq!(edits.pop_due(start), None);
+        assert_eq!(
+            edits.pop_due(start + MIN_EDIT_INTERVAL),
+            Some((MESSAGE, "b"))
+        );
+    }
+
+    /// Everything the queue sends at `now`, in order
+    fn drain(queue: &mut OutboundQueue<u64, u64>, now: Instant) -> Vec<Next<u64, u64>> {
+        std::iter::from_fn(|| queue.pop_due(now)).collect()
+    }
+
+    /// The skips of a burst each announce the new track and edit the now playing message
+    fn skip(queue: &mut OutboundQueue<u64, u64>, sequence: u64) {
+        queue.push_post(Some(sequence), sequence);
+        queue.push_edit(MESSAGE, Some(sequence), sequence);
+    }
+
+    #[test]
+    fn skip_burst_sends_only_the_newest_state() {
+        let start = Instant::now();
+        let mut queue = OutboundQueue::default();
+        // All skips arrive before the worker sends anything
+        for sequence in 1..=20 {
+            skip(&mut queue, sequence);
+        }
+
+        let sent = drain(&mut queue, start);
+        assert!(sent.len() <= MAX_PENDING_POSTS + 1, "{sent:?}");
+        assert_eq!(sent.first(), Some(&Next::Post(11)));
+        assert_eq!(sent.last(), Some(&Next::Edit(MESSAGE, 20)));
+        assert_eq!(queue.take_outdated(), 10);
+        assert_eq!(queue.next_due(start), None);
+    }
+
+    #[test]
+    fn spread_out_skips_edit_at_most_once_per_interval() {
+        let start = Instant::now();
+        let mut queue = OutboundQueue::default();
+        let mut sent = Vec::new();
+        // A skip every 100 ms for two seconds, the worker sends between them
+        for sequence in 1..=20 {
+            let now = start + Duration::from_millis((sequence - 1) * 100);
+            skip(&mut queue, sequence);
+            sent.extend(drain(&mut queue, now));
+        }
+        let due = queue.next_due(start).unwrap();
+        assert_eq!(due, start + MIN_EDIT_INTERVAL);
+        sent.extend(drain(&mut queue, due));
+
+        let edits = sent
+            .iter()
+            .filter_map(|next| match next {
+                Next::Edit(_, edit) => Some(*edit),
+                Next::Post(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(edits, [1, 20]);
+        // Every announcement is sent, none of them out of order
+        let posts = sent
+            .iter()
+            .filter_map(|next| match next {
+                Next::Post(post) => Some(*post),
+                Next::Edit(..) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(posts, (1..=20).collect::<Vec<_>>());
+        assert_eq!(sent.last(), Some(&Next::Edit(MESSAGE, 20)));
+    }
+
+    #[test]
+    fn older_state_is_never_sent_after_newer_state() {
+        let start = Instant::now();
+        let mut queue = OutboundQueue::<u64, u64>::default();
+        queue.push_edit(MESSAGE, Some(5), 5);
+        assert_eq!(drain(&mut queue, start), [Next::Edit(MESSAGE, 5)]);
+
+        // A late announcement of a track that was skipped in the meantime
+        queue.push_post(Some(3), 3);
+        queue.push_edit(OTHER_MESSAGE, Some(4), 4);
+        // Messages without state always go out
+        queue.push_post(None, 0);
+        assert_eq!(drain(&mut queue, start), [Next::Post(0)]);
+        assert_eq!(queue.take_outdated(), 2);
+    }
+
+    #[test]
+    fn pending_edits_are_not_replaced_by_older_state() {
+        let start = Instant::now();
+        let mut queue = OutboundQueue::<u64, u64>::default();
+        assert!(!queue.push_edit(MESSAGE, Some(7), 7));
+        assert!(!queue.push_edit(MESSAGE, Some(6), 6));
+        assert!(queue.push_edit(MESSAGE, Some(8), 8));
+        assert_eq!(drain(&mut queue, start), [Next::Edit(MESSAGE, 8)]);
+        assert_eq!(queue.take_outdated(), 1);
+    }
+
+    #[test]
+    fn overflowing_posts_drop_the_oldest_state_first() {
+        let start = Instant::now();
+        let mut queue = OutboundQueue::<u64, u64>::default();
+        queue.push_post(None, 0);
+        for sequence in 1..=MAX_PENDING_POSTS as u64 {
+            queue.push_post(Some(sequence), sequence);
+        }
+        let sent = drain(&mut queue, start);
+        assert_eq!(sent.len(), MAX_PENDING_POSTS);
+        // The post without state stays, the oldest state made room
+        assert_eq!(sent[0], Next::Post(0));
+        assert_eq!(sent[1], Next::Post(2));
+    }
+
+    const THREAD: ChannelId = ChannelId::new(30);
+    const PARENT: ChannelId = ChannelId::new(31);
+
+    fn notice_text(notice: Option<CreateMessage>) -> Option<String> {
+        notice.map(|notice| {
+            serde_json::to_value(notice).unwrap()["content"]
+                .as_str()
+                .unwrap()
+                .to_owned()
+        })
+    }
+
+    #[test]
+    fn deleted_thread_that_got_posts_redirects_with_one_notice() {
+        let mut threads = ThreadRedirects::default();
+        assert_eq!(notice_text(threads.resolve(THREAD).1), None);
+        threads.thread_deleted(THREAD, PARENT);
+
+        let (target, notice) = threads.resolve(THREAD);
+        assert_eq!(target, PARENT);
+        assert_eq!(
+            notice_text(notice).as_deref(),
+            Some("Der Thread <#30> wurde gelöscht, Nachrichten des Bots kommen jetzt hierher")
+        );
+        // Later posts follow silently
+        let (target, notice) = threads.resolve(THREAD);
+        assert_eq!((target, notice_text(notice)), (PARENT, None));
+    }
+
+    #[test]
+    fn deleting_a_thread_without_posts_changes_nothing() {
+        let mut threads = ThreadRedirects::default();
+        threads.thread_deleted(THREAD, PARENT);
+        let (target, notice) = threads.resolve(THREAD);
+        assert_eq!((target, notice_text(notice)), (THREAD, None));
+    }
+
+    #[test]
+    fn archived_thread_redirects_only_itself() {
+        let mut threads = ThreadRedirects::default();
+        let other = ChannelId::new(32);
+        threads.resolve(other);
+        threads.redirect(THREAD, PARENT, RedirectReason::Archived);
+
+        let (target, notice) = threads.resolve(THREAD);
+        assert_eq!(target, PARENT);
+        assert!(notice_text(notice).unwrap().contains("ist archiviert"));
+        let (target, notice) = threads.resolve(other);
+        assert_eq!((target, notice_text(notice)), (other, None));
+    }
+
+    #[test]
+    fn deletion_after_archiving_is_noticed_again() {
+        let mut threads = ThreadRedirects::default();
+        threads.redirect(THREAD, PARENT, RedirectReason::Archived);
+        assert!(threads.resolve(THREAD).1.is_some());
+        threads.thread_deleted(THREAD, PARENT);
+        assert!(notice_text(threads.resolve(THREAD).1)
+            .unwrap()
+            .contains("wurde gelöscht"));
+    }
+
+    async fn http_error(status: u16, body: &str) -> serenity::Error {
+        let response = hyper::http::Response::builder()
+            .status(status)
+            .body(body.to_owned())
+            .unwrap();
+        let response = serenity::http::ErrorResponse::from_response(
+            reqwest::Response::from(response),
+            reqwest::Method::POST,
+        )
+        .await;
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response))
+    }
+
+    #[tokio::test]
+    async fn archived_threads_and_rate_limits_are_told_apart() {
+        let archived = http_error(400, r#"{"code":50083,"message":"Thread is archived"}"#).await;
+        assert!(is_archived_thread(&archived));
+        assert!(!is_rate_limit(&archived));
+
+        let limited =
+            http_error(429, r#"{"code":0,"message":"You are being rate limited."}"#).await;
+        assert!(is_rate_limit(&limited));
+        assert!(!is_archived_thread(&limited));
+
+        let missing = http_error(404, r#"{"code":10003,"message":"Unknown Channel"}"#).await;
+        assert!(!is_archived_thread(&missing) && !is_rate_limit(&missing));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::commands::util::get_metadata;
+use crate::position_cache::PositionCache;
+use rand::distributions::{Alphanumeric, DistString};
+use rand::thread_rng;
+use serde::Serialize;
+use serenity::all::GuildId;
+use songbird::Songbird;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Number of upcoming tracks included in a snapshot
+const UP_NEXT_COUNT: usize = 5;
+
+/// Access tokens for the read-only now-playing overlay of each guild
+#[derive(Default)]
+pub struct OverlayTokens {
+    tokens: Mutex<HashMap<GuildId, String>>,
+}
+
+impl OverlayTokens {
+    /// Creates a new token for a guild, invalidating the previous one
+    pub fn regenerate(&self, guild_id: GuildId) -> String {
+        let token = Alphanumeric.sample_string(&mut thread_rng(), 32);
+        self.tokens.lock().unwrap().insert(guild_id, token.clone());
+        token
+    }
+
+    pub fn is_valid(&self, guild_id: GuildId, token: &str) -> bool {
+        self.tokens
+            .lock()
+            .unwrap()
+            .get(&guild_id)
+            .is_some_and(|t| t == token)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OverlayTrack {
+    pub title: String,
+    pub author: String,
+    /// Artist and song without upload qualifiers, for overlays that show them separately
+    pub artist: Option<String>,
+    pub song: String,
+    pub url: String,
+    pub source: &'static str,
+    pub duration_secs: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OverlayNowPlaying {
+    #[serde(flatten)]
+    pub track: OverlayTrack,
+    pub position_secs: u64,
+    /// Playing, but the stream does not deliver audio fast enough
+    pub buffering: bool,
+}
+
+/// Read-only view of the playback state of a guild
+#[derive(Clone, Debug, Serialize)]
+pub struct OverlaySnapshot {
+    pub now_playing: Option<OverlayNowPlaying>,
+    pub up_next: Vec<OverlayTrack>,
+}
+
+impl OverlaySnapshot {
+    /// Reads the live call state of a guild, the position from the cache
+    pub async fn read(songbird: &Songbird, positions: &PositionCache, guild_id: GuildId) -> Self {
+        let queue = match songbird.get(guild_id) {
+            Some(call) => call.lock().await.queue().current_queue(),
+            None => vec![],
+        };
+
+        let mut tracks = Vec::new();
+        for handle in queue.iter().take(UP_NEXT_COUNT + 1) {
+            let metadata = get_metadata(handle).await;
+            let clean = metadata.clean_title();
+            tracks.push(OverlayTrack {
+                title: metadata.title.clone(),
+                author: metadata.author.clone(),
+                artist: clean.artist,
+                song: clean.title,
+                url: metadata.source_url.to_string(),
+                source: metadata.source.name(),
+                duration_secs: metadata.duration.as_secs(),
+            });
+        }
+
+        let now_playing = match (queue.first(), tracks.is_empty()) {
+            (Some(current), false) => Some(OverlayNowPlaying {
+                track: tracks.remove(0),
+                position_secs: positions
+                    .read(guild_id, current)
+                    .await
+                    .map(|sample| sample.position_at(Instant::now()).as_secs())
+                    .unwrap_or_default(),
+                buffering: positions.is_buffering(guild_id),
+            }),
+            _ => None,
+        };
+
+        Self {
+            now_playing,
+            up_next: tracks,
+        }
+    }
+
+    /// Minimal self-refreshing page for browser sources
+    pub fn to_html(&self) -> String {
+        let escape = |s: &str| {
+            s.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        };
+
+        let now_playing = match &self.now_playing {
+            Some(np) => format!(
+                "<div class=\"now\"><span class=\"title\">{}</span> <span class=\"author\">{}</span>{}</div>",
+                escape(&np.track.title),
+                escape(&np.track.author),
+                match np.buffering {
+                    true => " <span class=\"buffering\">⏳ puffert…</span>",
+                    false => "",
+                }
+            ),
+            None => "<div class=\"now idle\">Momentan wird nichts abgespielt</div>".to_owned(),
+        };
+        let up_next = self
+            .up_next
+            .iter()
+            .map(|t| format!("<li>{} – {}</li>", escape(&t.title), escape(&t.author)))
+            .collect::<String>();
+
+        format!(
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"5\">\
+            <title>Gerbot</title></head><body>{now_playing}<ol class=\"next\">{up_next}</ol></body></html>"
+        )
+    }
+}
@@ -0,0 +1,133 @@
+use crate::lifecycle::GuildPersisted;
+use crate::persistence::{PersistedFile, PersistenceHealth};
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use time::OffsetDateTime;
+
+/// Longest party mode, a forgotten one ends by itself the next day
+pub const MAX_PARTY_DURATION: time::Duration = time::Duration::hours(24);
+
+/// Time until the limits of a guild apply again
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct PartyMode {
+    guild_id: GuildId,
+    /// A point in time rather than a duration, so a restart can not extend it
+    #[serde(with = "time::serde::rfc3339")]
+    until: OffsetDateTime,
+}
+
+/// What /partymode was asked to do
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartyChange {
+    Start(time::Duration),
+    End,
+}
+
+/// Guilds whose duration limits are lifted until a set time, written to a file if one is
+/// configured. The global queue limit and the protections of the host still apply.
+pub struct PartyModes {
+    guilds: Mutex<Vec<PartyMode>>,
+    file: PersistedFile,
+}
+
+impl PartyModes {
+    /// Loads the party modes from the file, ones that ended while the bot was offline are dropped
+    pub fn load(file: Option<PathBuf>, health: Arc<PersistenceHealth>) -> Self {
+        let file = PersistedFile::new("party modes", file, health);
+        let now = OffsetDateTime::now_utc();
+        let mut guilds = file.load::<PartyMode>();
+        guilds.retain(|party| party.until > now);
+        Self {
+            guilds: Mutex::new(guilds),
+            file,
+        }
+    }
+
+    /// Starts or extends the party mode of a guild
+    pub fn start(&self, guild_id: GuildId, until: OffsetDateTime) {
+        let mut guilds = self.guilds.lock().unwrap();
+        guilds.retain(|party| party.guild_id != guild_id);
+        guilds.push(PartyMode { guild_id, until });
+        self.file.save(&guilds);
+    }
+
+    /// Returns whether the guild had an active party mode
+    pub fn end(&self, guild_id: GuildId) -> bool {
+        let active = self.until(guild_id).is_some();
+        let mut guilds = self.guilds.lock().unwrap();
+        let before = guilds.len();
+        guilds.retain(|party| party.guild_id != guild_id);
+        if guilds.len() != before {
+            self.file.save(&guilds);
+        }
+        active
+    }
+
+    /// End of the party mode of the guild, none if it has none or it is over. Ended ones are
+    /// only checked here, so nothing has to run at the end.
+    pub fn until(&self, guild_id: GuildId) -> Option<OffsetDateTime> {
+        let now = OffsetDateTime::now_utc();
+        self.guilds
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|party| party.guild_id == guild_id && party.until > now)
+            .map(|party| party.until)
+    }
+
+    pub fn is_active(&self, guild_id: GuildId) -> bool {
+        self.until(guild_id).is_some()
+    }
+}
+
+impl GuildPersisted for PartyModes {
+    fn purge(&self, guild_id: GuildId) {
+        let mut guilds = self.guilds.lock().unwrap();
+        guilds.retain(|party| party.guild_id != guild_id);
+        self.file.save(&guilds);
+    }
+}
+
+/// The indicator shown while a party mode is active, in the time zone of each reader
+pub fn describe_party(until: OffsetDateTime) -> String {
+    format!("🎉 Party-Modus bis <t:{}:t>", until.unix_timestamp())
+}
+
+/// Parses `off`, or a duration like `3h`, `90m` or `1h30m`. A number without unit is in hours.
+pub fn parse_party_change(input: &str) -> Option<PartyChange> {
+    let input = input.trim().to_lowercase();
+    if matches!(input.as_str(), "off" | "aus" | "ende" | "stop") {
+        return Some(PartyChange::End);
+    }
+    if let Ok(hours) = input.parse::<u32>() {
+        return hours_and_minutes(u64::from(hours), 0);
+    }
+
+    let (mut hours, mut minutes) = (0u64, 0u64);
+    let mut rest = input.as_str();
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .filter(|end| *end > 0)?;
+        let value = rest[..digits].parse::<u64>().ok()?;
+        rest = rest[digits..].trim_start();
+        let unit_end = rest
+            .find(|c: char| !c.is_alphabetic())
+            .unwrap_or(rest.len());
+        match &rest[..unit_end] {
+            "h" | "std" | "stunde" | "stunden" => hours = hours.checked_add(value)?,
+            "m" | "min" | "minute" | "minuten" => minutes = minutes.checked_add(value)?,
+            _ => return None,
+        }
+        rest = rest[unit_end..].trim_start();
+    }
+    hours_and_minutes(hours, minutes)
+}
+
+fn hours_and_minutes(hours: u64, minutes: u64) -> Option<PartyChange> {
+    let total = hours.checked_mul(60)?.checked_add(minutes)?;
+    let total = time::Duration::minutes(i64::try_from(total).ok()?);
+    (total.is_positive() && total <= MAX_PARTY_DURATION).then_some(PartyChange::Start(total))
+}
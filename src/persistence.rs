@@ -0,0 +1,494 @@
+use log::{error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::Notify;
+use tokio::time::sleep;
+
+/// Failed writes in a row after which a store counts as degraded
+const DEGRADED_AFTER: u32 = 5;
+/// Delay before the first retry of a failed write, doubled with every further failure
+const FIRST_RETRY: Duration = Duration::from_secs(1);
+/// A store that stays unwritable is retried at this pace until it works again
+const MAX_RETRY: Duration = Duration::from_secs(60);
+
+/// Where a store keeps its entries. Every write replaces all of them.
+pub trait StoreBackend: Send + Sync {
+    /// Where the entries are, for logs
+    fn describe(&self) -> String;
+    /// None if nothing was stored yet
+    fn read(&self) -> io::Result<Option<String>>;
+    fn write(&self, content: &str) -> io::Result<()>;
+}
+
+/// A json file, replaced through a temporary file so a crash never leaves half of it
+pub struct JsonFile(pub PathBuf);
+
+impl StoreBackend for JsonFile {
+    fn describe(&self) -> String {
+        format!("{:?}", self.0)
+    }
+
+    fn read(&self) -> io::Result<Option<String>> {
+        match std::fs::read_to_string(&self.0) {
+            Ok(content) => Ok(Some(content)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(&self, content: &str) -> io::Result<()> {
+        let tmp = self.0.with_extension("tmp");
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &self.0)
+    }
+}
+
+/// A store refused a change because its entries could not be loaded
+#[derive(Debug, Error)]
+#[error("The {store} store could not be loaded")]
+pub struct StorageUnavailable {
+    pub store: &'static str,
+}
+
+/// State of one store as shown in /status and the health endpoint
+#[derive(Clone, Debug, Default)]
+pub struct StoreHealth {
+    /// Why the entries could not be loaded. The store then runs empty and writes nothing, so
+    /// the broken data stays for the operator to fix.
+    pub unavailable: Option<String>,
+    /// Failed writes in a row, reset by the next successful one
+    pub failed_writes: u32,
+    pub last_error: Option<String>,
+}
+
+impl StoreHealth {
+    pub fn is_degraded(&self) -> bool {
+        self.unavailable.is_some() || self.failed_writes >= DEGRADED_AFTER
+    }
+}
+
+/// Health of all stores that are backed by a file
+#[derive(Debug, Default)]
+pub struct PersistenceHealth {
+    stores: Mutex<BTreeMap<&'static str, StoreHealth>>,
+}
+
+impl PersistenceHealth {
+    pub fn is_degraded(&self) -> bool {
+        self.stores
+            .lock()
+            .unwrap()
+            .values()
+            .any(|h| h.is_degraded())
+    }
+
+    /// Every store backed by a file, by name
+    pub fn stores(&self) -> Vec<(&'static str, StoreHealth)> {
+        self.stores
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, health)| (*name, health.clone()))
+            .collect()
+    }
+
+    fn update<T>(&self, store: &'static str, f: impl FnOnce(&mut StoreHealth) -> T) -> T {
+        f(self.stores.lock().unwrap().entry(store).or_default())
+    }
+}
+
+/// Writes snapshots of one store and retries the ones that failed
+struct Writer {
+    store: &'static str,
+    backend: Arc<dyn StoreBackend>,
+    health: Arc<PersistenceHealth>,
+    /// The newest snapshot that could not be written yet. A snapshot contains all entries, so a
+    /// newer one replaces a waiting older one and at most one is kept. Locked during writes, so
+    /// a retry never overwrites a newer snapshot.
+    pending: Mutex<Option<String>>,
+    retry: Notify,
+}
+
+impl Writer {
+    fn write(&self, content: String) {
+        let mut pending = self.pending.lock().unwrap();
+        self.attempt(&mut pending, content);
+    }
+
+    /// Writes the waiting snapshot again. Returns false if it failed again.
+    fn retry_pending(&self) -> bool {
+        let mut pending = self.pending.lock().unwrap();
+        match pending.take() {
+            Some(content) => self.attempt(&mut pending, content),
+            None => true,
+        }
+    }
+
+    fn attempt(&self, pending: &mut Option<String>, content: String) -> bool {
+        match self.backend.write(&content) {
+            Ok(()) => {
+                *pending = None;
+                let failed = self.health.update(self.store, |health| {
+                    health.last_error = None;
+                    std::mem::take(&mut health.failed_writes)
+                });
+                if failed > 0 {
+                    info!(
+                        "Writing {} to {} works again after {failed} failed attempts",
+                        self.store,
+                        self.backend.describe()
+                    );
+                }
+                true
+            }
+            Err(e) => {
+                *pending = Some(content);
+                let failed = self.health.update(self.store, |health| {
+                    health.failed_writes += 1;
+                    health.last_error = Some(e.to_string());
+                    health.failed_writes
+                });
+                match failed {
+                    DEGRADED_AFTER => error!(
+                        "Writing {} to {} failed {failed} times in a row, changes are only kept in memory until it works again: {e}",
+                        self.store,
+                        self.backend.describe()
+                    ),
+                    _ => warn!(
+                        "Failed to write {} to {}, retrying: {e}",
+                        self.store,
+                        self.backend.describe()
+                    ),
+                }
+                self.retry.notify_one();
+                false
+            }
+        }
+    }
+}
+
+/// Retries the failed writes of a store with growing delays. Runs until the process exits.
+async fn retry_writes(writer: Arc<Writer>) {
+    loop {
+        writer.retry.notified().await;
+        let mut delay = FIRST_RETRY;
+        loop {
+            sleep(delay).await;
+            if writer.retry_pending() {
+                break;
+            }
+            delay = (delay * 2).min(MAX_RETRY);
+        }
+    }
+}
+
+/// The persisted entries of one store. Without a backend everything is kept in memory only,
+/// which is not counted as degraded.
+pub struct PersistedFile {
+    store: &'static str,
+    writer: Option<Arc<Writer>>,
+    available: AtomicBool,
+    pretty: bool,
+}
+
+impl PersistedFile {
+    /// A store in a json file, if one is configured
+    pub fn new(store: &'static str, file: Option<PathBuf>, health: Arc<PersistenceHealth>) -> Self {
+        let backend = file.map(|path| Arc::new(JsonFile(path)) as Arc<dyn StoreBackend>);
+        Self::with_backend(store, backend, health)
+    }
+
+    pub fn with_backend(
+        store: &'static str,
+        backend: Option<Arc<dyn StoreBackend>>,
+        health: Arc<PersistenceHealth>,
+    ) -> Self {
+        let writer = backend.map(|backend| {
+            health.update(store, |_| {});
+            let writer = Arc::new(Writer {
+                store,
+                backend,
+                health,
+                pending: Mutex::new(None),
+                retry: Notify::new(),
+            });
+            tokio::spawn(retry_writes(writer.clone()));
+            writer
+        });
+        Self {
+            store,
+            writer,
+            available: AtomicBool::new(true),
+            pretty: false,
+        }
+    }
+
+    /// Writes indented json, for files that are edited by hand
+    pub fn pretty(self) -> Self {
+        Self {
+            pretty: true,
+            ..self
+        }
+    }
+
+    /// Reads the entries, empty if nothing was stored yet. Entries that can not be read make the
+    /// store unavailable until a later load succeeds, it then runs empty.
+    pub fn load<T: DeserializeOwned>(&self) -> Vec<T> {
+        let Some(writer) = &self.writer else {
+            return vec![];
+        };
+        let loaded = writer
+            .backend
+            .read()
+            .map_err(|e| e.to_string())
+            .and_then(|content| {
+                content
+                    .map(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+                    .transpose()
+            });
+        let (entries, unavailable) = match loaded {
+            Ok(entries) => (entries.unwrap_or_default(), None),
+            Err(e) => {
+                error!(
+                    "The {} in {} could not be loaded, they are unavailable until the file is fixed and reloaded: {e}",
+                    self.store,
+                    writer.backend.describe()
+                );
+                (vec![], Some(e))
+            }
+        };
+        self.available
+            .store(unavailable.is_none(), Ordering::Relaxed);
+        writer
+            .health
+            .update(self.store, |health| health.unavailable = unavailable);
+        entries
+    }
+
+    /// Writes all entries of the store. Failed writes are retried in the background, a store
+    /// that could not be loaded writes nothing.
+    pub fn save<T: Serialize>(&self, entries: &[T]) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        if !self.is_available() {
+            return;
+        }
+        let content = match self.pretty {
+            true => serde_json::to_string_pretty(entries),
+            false => serde_json::to_string(entries),
+        };
+        match content {
+            Ok(content) => writer.write(content),
+            Err(e) => error!("Failed to serialize the {}: {e}", self.store),
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.available.load(Ordering::Relaxed)
+    }
+
+    /// For changes that must not be lost, which are refused while the store is unavailable
+    pub fn check_available(&self) -> Result<(), StorageUnavailable> {
+        match self.is_available() {
+            true => Ok(()),
+            false => Err(StorageUnavailable { store: self.store }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A backend in memory that fails on demand
+    #[derive(Default)]
+    struct FakeBackend {
+        content: Mutex<Option<String>>,
+        broken_reads: AtomicBool,
+        broken_writes: AtomicBool,
+        /// Every write that was attempted, also the failed ones
+        attempts: Mutex<Vec<String>>,
+    }
+
+    impl FakeBackend {
+        fn with_content(content: &str) -> Arc<Self> {
+            let backend = Self::default();
+            *backend.content.lock().unwrap() = Some(content.to_owned());
+            Arc::new(backend)
+        }
+
+        fn content(&self) -> Option<String> {
+            self.content.lock().unwrap().clone()
+        }
+    }
+
+    impl StoreBackend for FakeBackend {
+        fn describe(&self) -> String {
+            "the fake backend".to_owned()
+        }
+
+        fn read(&self) -> io::Result<Option<String>> {
+            match self.broken_reads.load(Ordering::SeqCst) {
+                true => Err(io::Error::other("volume not mounted")),
+                false => Ok(self.content()),
+            }
+        }
+
+        fn write(&self, content: &str) -> io::Result<()> {
+            self.attempts.lock().unwrap().push(content.to_owned());
+            match self.broken_writes.load(Ordering::SeqCst) {
+                true => Err(io::Error::other("disk full")),
+                false => {
+                    *self.content.lock().unwrap() = Some(content.to_owned());
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    fn store(backend: &Arc<FakeBackend>, health: &Arc<PersistenceHealth>) -> PersistedFile {
+        PersistedFile::with_backend(
+            "test entries",
+            Some(backend.clone() as Arc<dyn StoreBackend>),
+            health.clone(),
+        )
+    }
+
+    fn health_of(health: &PersistenceHealth) -> StoreHealth {
+        health.stores().into_iter().next().unwrap().1
+    }
+
+    #[tokio::test]
+    async fn entries_are_read_back() {
+        let backend = FakeBackend::with_content("[1,2,3]");
+        let health = Arc::default();
+        let file = store(&backend, &health);
+
+        assert_eq!(file.load::<u32>(), [1, 2, 3]);
+        file.save(&[4]);
+        assert_eq!(backend.content().unwrap(), "[4]");
+        assert!(file.check_available().is_ok());
+        assert!(!health.is_degraded());
+    }
+
+    #[tokio::test]
+    async fn empty_backends_start_empty() {
+        let backend = Arc::new(FakeBackend::default());
+        let health = Arc::default();
+        let file = store(&backend, &health);
+
+        assert!(file.load::<u32>().is_empty());
+        assert!(file.is_available());
+        assert_eq!(health.stores().len(), 1);
+        assert!(!health.is_degraded());
+    }
+
+    #[tokio::test]
+    async fn startup_without_storage_runs_empty_and_writes_nothing() {
+        let backend = FakeBackend::with_content("[1]");
+        backend.broken_reads.store(true, Ordering::SeqCst);
+        let health = Arc::default();
+        let file = store(&backend, &health);
+
+        assert!(file.load::<u32>().is_empty());
+        assert!(health.is_degraded());
+        assert_eq!(
+            health_of(&health).unavailable.as_deref(),
+            Some("volume not mounted")
+        );
+        let refused = file.check_available().unwrap_err();
+        assert_eq!(refused.store, "test entries");
+
+        // The stored entries are kept for the operator, even once the backend could be written
+        file.save(&[2]);
+        assert!(backend.attempts.lock().unwrap().is_empty());
+        assert_eq!(backend.content().unwrap(), "[1]");
+
+        // Reloading after the fix makes the store available again
+        backend.broken_reads.store(false, Ordering::SeqCst);
+        assert_eq!(file.load::<u32>(), [1]);
+        assert!(file.is_available());
+        assert!(!health.is_degraded());
+    }
+
+    #[tokio::test]
+    async fn corrupt_entries_make_the_store_unavailable() {
+        let backend = FakeBackend::with_content("[1, oops");
+        let health = Arc::default();
+        let file = store(&backend, &health);
+
+        assert!(file.load::<u32>().is_empty());
+        assert!(!file.is_available());
+        assert!(health_of(&health).unavailable.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_writes_are_retried_until_they_work() {
+        let backend = Arc::new(FakeBackend::default());
+        backend.broken_writes.store(true, Ordering::SeqCst);
+        let health = Arc::default();
+        let file = store(&backend, &health);
+        file.load::<u32>();
+
+        file.save(&[1]);
+        file.save(&[2]);
+        assert_eq!(health_of(&health).failed_writes, 2);
+        assert_eq!(health_of(&health).last_error.as_deref(), Some("disk full"));
+        assert!(!health.is_degraded());
+
+        // Retries after 1, 2 and 4 seconds reach the limit
+        sleep(Duration::from_millis(1500)).await;
+        assert_eq!(health_of(&health).failed_writes, 3);
+        sleep(Duration::from_secs(6)).await;
+        assert_eq!(health_of(&health).failed_writes, DEGRADED_AFTER);
+        assert!(health.is_degraded());
+
+        // Only the newest snapshot is retried
+        backend.broken_writes.store(false, Ordering::SeqCst);
+        sleep(Duration::from_secs(8)).await;
+        assert_eq!(backend.content().unwrap(), "[2]");
+        let attempts = backend.attempts.lock().unwrap().clone();
+        assert_eq!(attempts, ["[1]", "[2]", "[2]", "[2]", "[2]", "[2]"]);
+        assert_eq!(health_of(&health).failed_writes, 0);
+        assert_eq!(health_of(&health).last_error, None);
+        assert!(!health.is_degraded());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn newer_writes_replace_a_waiting_snapshot() {
+        let backend = Arc::new(FakeBackend::default());
+        backend.broken_writes.store(true, Ordering::SeqCst);
+        let health = Arc::default();
+        let file = store(&backend, &health);
+
+        file.save(&[1]);
+        backend.broken_writes.store(false, Ordering::SeqCst);
+        file.save(&[2]);
+        assert_eq!(backend.content().unwrap(), "[2]");
+        assert_eq!(health_of(&health).failed_writes, 0);
+
+        // The retry finds nothing left to write and never brings back the older snapshot
+        sleep(Duration::from_secs(5)).await;
+        assert_eq!(backend.content().unwrap(), "[2]");
+        assert_eq!(backend.attempts.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn stores_without_a_backend_are_not_tracked() {
+        let health = Arc::new(PersistenceHealth::default());
+        let file = PersistedFile::with_backend("memory only", None, health.clone());
+
+        assert!(file.load::<u32>().is_empty());
+        file.save(&[1]);
+        assert!(file.is_available());
+        assert!(health.stores().is_empty());
+    }
+}
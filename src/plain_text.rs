@@ -0,0 +1,436 @@
+use log::warn;
+use poise::CreateReply;
+use serde_json::Value;
+use serenity::all::{GuildId, Permissions};
+use serenity::builder::{CreateEmbed, CreateInteractionResponseMessage, EditMessage};
+use std::collections::HashMap;
+
+use crate::commands::util::get_user_preferences;
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use crate::{CommandContext, EmbedHintsKey};
+
+/// Icons that carry meaning, read as words in accessible output. Other emoji are decoration and
+/// dropped.
+const ICON_WORDS: [(&str, &str); 8] = [
+    (":repeat:", "wird wiederholt"),
+    ("🔁", "wird wiederholt"),
+    (":warning:", "Achtung:"),
+    ("⚠️", "Achtung:"),
+    (":hourglass:", "über der Längengrenze"),
+    ("⏳", ""),
+    ("🎉", ""),
+    ("→", "steht für"),
+];
+
+const MISSING_EMBED_HINT: &str = "-# Dem Bot fehlt in diesem Kanal die Berechtigung „Links einbetten“. Ein Admin kann sie erteilen, damit Antworten richtig angezeigt werden.";
+
+/// Guilds that were already told about the missing embed permission since the last restart
+#[derive(Default)]
+pub struct EmbedHints {
+    hinted: GuildStateMap<()>,
+}
+
+impl EmbedHints {
+    /// Returns true only on the first call for a guild
+    fn first_hint(&self, guild_id: GuildId) -> bool {
+        self.hinted.insert(guild_id, ()).is_none()
+    }
+}
+
+impl GuildScoped for EmbedHints {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::EmbedHints
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.hinted.entry_counts(|_| 1)
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.hinted.remove_on_leave(guild_id);
+    }
+}
+
+/// How embeds are sent in the channel of a command
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EmbedMode {
+    Embed,
+    /// The bot lacks Embed Links, so embeds are rendered as plain text
+    PlainText {
+        hint: bool,
+    },
+    /// The author prefers accessible output, so private responses are plain sentences
+    Accessible,
+}
+
+impl EmbedMode {
+    /// Checks the permissions of the bot in the channel of the command. Consumes the per-guild
+    /// hint, so the first response in plain text should show it.
+    pub async fn of(ctx: CommandContext<'_>) -> Self {
+        let permissions = match ctx {
+            poise::Context::Application(ctx) => ctx.interaction.app_permissions,
+            poise::Context::Prefix(_) => None,
+        };
+        // Without information about the permissions embeds are assumed to work
+        match permissions {
+            Some(p) if !p.contains(Permissions::EMBED_LINKS) => {}
+            _ => return EmbedMode::Embed,
+        }
+
+        let hint = match ctx.guild_id() {
+            Some(guild_id) => ctx
+                .serenity_context()
+                .data
+                .read()
+                .await
+                .get::<EmbedHintsKey>()
+                .expect("Guaranteed to exist in the typemap")
+                .first_hint(guild_id),
+            None => false,
+        };
+        if hint {
+            warn!(
+                "Missing Embed Links in channel {} of guild {:?}, falling back to plain text",
+                ctx.channel_id(),
+                ctx.guild_id()
+            );
+        }
+        EmbedMode::PlainText { hint }
+    }
+
+    /// Like [EmbedMode::of], but responses only the author sees follow their preference for
+    /// accessible output
+    pub async fn of_reply(ctx: CommandContext<'_>, ephemeral: bool) -> Self {
+        if ephemeral && prefers_accessible(ctx).await {
+            return EmbedMode::Accessible;
+        }
+        Self::of(ctx).await
+    }
+
+    fn render(self, embed: &CreateEmbed) -> String {
+        match self {
+            EmbedMode::PlainText { hint: true } => {
+                format!("{}\n{MISSING_EMBED_HINT}", embed_to_text(embed))
+            }
+            EmbedMode::Accessible => accessible_text(embed),
+            _ => embed_to_text(embed),
+        }
+    }
+
+    pub fn reply(self, reply: CreateReply, embed: CreateEmbed) -> CreateReply {
+        match self {
+            EmbedMode::Embed => reply.embed(embed),
+            _ => reply.content(self.render(&embed)),
+        }
+    }
+
+    pub fn message(
+        self,
+        message: CreateInteractionResponseMessage,
+        embed: CreateEmbed,
+    ) -> CreateInteractionResponseMessage {
+        match self {
+            EmbedMode::Embed => message.embed(embed),
+            _ => message.content(self.render(&embed)),
+        }
+    }
+
+    pub fn edit(self, edit: EditMessage, embed: CreateEmbed) -> EditMessage {
+        match self {
+            EmbedMode::Embed => edit.embed(embed),
+            _ => edit.content(self.render(&embed)),
+        }
+    }
+}
+
+/// Plain text form of an embed with the same content: author, title, description, fields and
+/// footer, in the order Discord shows them
+pub fn embed_to_text(embed: &CreateEmbed) -> String {
+    let Ok(value) = serde_json::to_value(embed) else {
+        return String::new();
+    };
+    let text = |value: &Value, key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .filter(|s| !s.is_empty())
+            .map(str::to_owned)
+    };
+
+    let mut lines = Vec::new();
+    if let Some(author) = value.get("author").and_then(|a| text(a, "name")) {
+        lines.push(format!("*{author}*"));
+    }
+    if let Some(title) = text(&value, "title") {
+        lines.push(format!("**{title}**"));
+    }
+    if let Some(description) = text(&value, "description") {
+        lines.push(description);
+    }
+    for field in value
+        .get("fields")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        if let Some(name) = text(field, "name") {
+            lines.push(format!("__{name}__"));
+        }
+        if let Some(value) = text(field, "value") {
+            lines.push(value);
+        }
+    }
+    if let Some(footer) = value.get("footer").and_then(|f| text(f, "text")) {
+        lines.push(format!("-# {footer}"));
+    }
+
+    lines.join("\n")
+}
+
+/// Whether the author of the command asked for accessible output
+pub async fn prefers_accessible(ctx: CommandContext<'_>) -> bool {
+    get_user_preferences(ctx.serenity_context())
+        .await
+        .get(ctx.author().id)
+        .accessible
+}
+
+/// The content of an embed for screen readers: one line per part, without markdown, links or
+/// emoji. Icons that carry meaning are read as words.
+pub fn accessible_text(embed: &CreateEmbed) -> String {
+    let Ok(value) = serde_json::to_value(embed) else {
+        return String::new();
+    };
+    let text = |value: &Value, key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .map(accessible_line)
+            .filter(|s| !s.is_empty())
+    };
+
+    let mut lines = Vec::new();
+    lines.extend(value.get("author").and_then(|a| text(a, "name")));
+    lines.extend(text(&value, "title"));
+    lines.extend(text(&value, "description"));
+    for field in value
+        .get("fields")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        match (text(field, "name"), text(field, "value")) {
+            (Some(name), Some(value)) => lines.push(format!("{name}: {value}")),
+            (name, value) => lines.extend(name.or(value)),
+        }
+    }
+    lines.extend(value.get("footer").and_then(|f| text(f, "text")));
+    lines.join("\n")
+}
+
+/// Markdown and emoji removed from every line of `text`, empty lines dropped
+pub fn accessible_line(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let mut line = strip_links(line);
+            for (icon, words) in ICON_WORDS {
+                line = line.replace(icon, words);
+            }
+            // Single underscores are common in titles, only the markdown pairs are dropped
+            let line = strip_shortcodes(&line.replace("__", "").replace("~~", ""))
+                .chars()
+                .filter(|c| !matches!(c, '*' | '`') && !is_emoji(*c))
+                .collect::<String>();
+            let line = line.trim_start();
+            let line = ["-# ", "> ", "- "].iter().fold(line, |line, prefix| {
+                line.strip_prefix(prefix).unwrap_or(line)
+            });
+            line.split_whitespace().collect::<Vec<_>>().join(" ")
+        })
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `[text](url)` becomes `text`
+fn strip_links(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find('[') {
+        let Some(link) = rest[start..].find("](").map(|middle| start + middle) else {
+            break;
+        };
+        let Some(end) = rest[link..].find(')').map(|end| link + end) else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(&rest[start + 1..link]);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Drops Discord shortcodes like `:white_check_mark:`, times like `12:30` are kept
+fn strip_shortcodes(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find(':') {
+        let name_len = rest[start + 1..]
+            .find(|c: char| !(c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_'))
+            .unwrap_or(rest.len() - start - 1);
+        let name = &rest[start + 1..start + 1 + name_len];
+        let closed = rest[start + 1 + name_len..].starts_with(':');
+        if closed && name.chars().any(|c| c.is_ascii_lowercase()) {
+            result.push_str(&rest[..start]);
+            rest = &rest[start + name_len + 2..];
+        } else {
+            result.push_str(&rest[..=start]);
+            rest = &rest[start + 1..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Pictographs, symbols and the joiners and selectors emoji are built from
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1F000..=0x1FAFF
+            | 0x2300..=0x23FF
+            | 0x25A0..=0x25FF
+            | 0x2600..=0x27BF
+            | 0x2B00..=0x2BFF
+            | 0xFE0F
+            | 0x200D
+    )
+}
+
+/// Fails if `text` still contains markdown or emoji, for tests of accessible output
+#[cfg(test)]
+pub(crate) fn assert_accessible(text: &str) {
+    for line in text.lines() {
+        assert!(
+            !line.contains(['*', '`']) && !["~~", "__", "]("].iter().any(|m| line.contains(m)),
+            "Markdown in {line:?}"
+        );
+        assert!(
+            !["-# ", "> ", "- "].iter().any(|p| line.starts_with(p)),
+            "Markdown in {line:?}"
+        );
+        assert_eq!(strip_shortcodes(line), line, "Shortcode in {line:?}");
+        assert!(!line.chars().any(is_emoji), "Emoji in {line:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::builder::{CreateEmbedAuthor, CreateEmbedFooter};
+
+    fn full_embed() -> CreateEmbed {
+        CreateEmbed::new()
+            .author(CreateEmbedAuthor::new("YouTube"))
+            .title("Warteschlange")
+            .description("1. Song A\n2. Song B")
+            .field("Gesamtlänge", "7:30", true)
+            .field("Wiederholung", "Aus", true)
+            .footer(CreateEmbedFooter::new("Seite 1/2"))
+    }
+
+    #[test]
+    fn all_parts_in_the_order_discord_shows_them() {
+        assert_eq!(
+            embed_to_text(&full_embed()),
+            "*YouTube*\n**Warteschlange**\n1. Song A\n2. Song B\n__Gesamtlänge__\n7:30\n__Wiederholung__\nAus\n-# Seite 1/2"
+        );
+    }
+
+    #[test]
+    fn missing_and_empty_parts_are_skipped() {
+        assert_eq!(embed_to_text(&CreateEmbed::new()), "");
+        assert_eq!(
+            embed_to_text(&CreateEmbed::new().title("Fehler").description("")),
+            "**Fehler**"
+        );
+        assert_eq!(
+            embed_to_text(&CreateEmbed::new().field("", "Nur ein Wert", false)),
+            "Nur ein Wert"
+        );
+    }
+
+    #[test]
+    fn colors_and_images_have_no_text() {
+        let embed = CreateEmbed::new()
+            .title("Jetzt läuft")
+            .color(0xff0000)
+            .thumbnail("https://i.ytimg.com/vi/id/default.jpg")
+            .url("https://www.youtube.com/watch?v=id");
+        assert_eq!(embed_to_text(&embed), "**Jetzt läuft**");
+    }
+
+    #[test]
+    fn hint_is_appended_only_when_asked_for() {
+        let embed = CreateEmbed::new().title("Pausiert");
+        assert_eq!(
+            EmbedMode::PlainText { hint: false }.render(&embed),
+            "**Pausiert**"
+        );
+        assert_eq!(
+            EmbedMode::PlainText { hint: true }.render(&embed),
+            format!("**Pausiert**\n{MISSING_EMBED_HINT}")
+        );
+    }
+
+    #[test]
+    fn replies_keep_the_embed_only_when_embeds_work() {
+        let embed = CreateEmbed::new().title("Pausiert");
+        let with_embed = EmbedMode::Embed.reply(CreateReply::default(), embed.clone());
+        assert_eq!(with_embed.embeds.len(), 1);
+        assert_eq!(with_embed.content, None);
+
+        let plain = EmbedMode::PlainText { hint: false }.reply(CreateReply::default(), embed);
+        assert!(plain.embeds.is_empty());
+        assert_eq!(plain.content.as_deref(), Some("**Pausiert**"));
+    }
+
+    #[test]
+    fn accessible_text_reads_icons_as_words() {
+        let embed = CreateEmbed::new()
+            .author(CreateEmbedAuthor::new("🎵 YouTube"))
+            .title("**Queue**")
+            .description(
+                "1. [Song A](https://example.com/a) :repeat:\n- ~~Song B~~ *(entfernt)*\n> ⚠️ `Song C`",
+            )
+            .field("__Kurzformen__", "`/p` → `/play`", false)
+            .footer(CreateEmbedFooter::new("-# Seite 1/2 – 🎉 Party bis 12:30"));
+        let text = accessible_text(&embed);
+        assert_eq!(
+            text,
+            "YouTube\nQueue\n1. Song A wird wiederholt\nSong B (entfernt)\nAchtung: Song C\nKurzformen: /p steht für /play\nSeite 1/2 – Party bis 12:30"
+        );
+        assert_accessible(&text);
+    }
+
+    #[test]
+    fn accessible_lines_keep_plain_text() {
+        for line in ["Song_mit_Unterstrich 2:30", "Track 3 von 10: läuft gerade"] {
+            assert_eq!(accessible_line(line), line);
+            assert_accessible(line);
+        }
+        assert_eq!(
+            accessible_line(":white_check_mark: Erledigt ✅"),
+            "Erledigt"
+        );
+        assert_eq!(accessible_line("\n  \n"), "");
+    }
+
+    #[test]
+    fn every_guild_is_hinted_once() {
+        let hints = EmbedHints::default();
+        assert!(hints.first_hint(GuildId::new(1)));
+        assert!(!hints.first_hint(GuildId::new(1)));
+        assert!(hints.first_hint(GuildId::new(2)));
+    }
+}
@@ -0,0 +1,415 @@
+use crate::end_reason::EndReason;
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use serenity::all::GuildId;
+use std::collections::HashMap;
+
+/// Active queue modes of a guild.
+///
+/// When a track ends, loop-track takes precedence over loop-queue, because a looping track
+/// never ends on its own. Fair mode only decides where new tracks are inserted and shuffling
+/// always works on the queue as it is, regardless of the other modes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PlaybackMode {
+    pub loop_track: bool,
+    pub loop_queue: bool,
+    pub fair: bool,
+    /// Plays a suggested track when the queue runs out
+    pub autoplay: bool,
+    /// Set by `/stop finish_current`: nothing follows the current track, not even a suggestion,
+    /// until the next track starts
+    pub stop_after_current: bool,
+}
+
+/// What follows a track that ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AfterTrack {
+    /// The queue continues as it is
+    Continue,
+    /// The track goes back to the end of the queue
+    Requeue,
+    /// A suggestion is played if the queue ran out
+    Autoplay,
+}
+
+/// Everything that can change the playback mode of a guild
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModeChange {
+    SetLoopTrack(bool),
+    SetLoopQueue(bool),
+    SetFair(bool),
+    SetAutoplay(bool),
+    /// Toggles are read and written under the lock of [PlaybackModes], so concurrent toggles
+    /// never get lost
+    ToggleLoopTrack,
+    ToggleLoopQueue,
+    ToggleFair,
+    ToggleAutoplay,
+    /// A new track started playing, so a track loop of the previous one is over
+    TrackStarted,
+    /// The queue was stopped or the bot left. Fair mode and autoplay are preferences and stay.
+    QueueCleared,
+    /// The upcoming tracks were removed and the current one plays to its end
+    StopAfterCurrent,
+}
+
+impl ModeChange {
+    /// The change for /stop, which ends loops either way so the current track can finish
+    pub fn stop(finish_current: bool) -> Self {
+        match finish_current {
+            true => ModeChange::StopAfterCurrent,
+            false => ModeChange::QueueCleared,
+        }
+    }
+}
+
+impl PlaybackMode {
+    pub fn apply(self, change: ModeChange) -> Self {
+        match change {
+            ModeChange::SetLoopTrack(loop_track) => Self { loop_track, ..self },
+            ModeChange::SetLoopQueue(loop_queue) => Self { loop_queue, ..self },
+            ModeChange::SetFair(fair) => Self { fair, ..self },
+            ModeChange::SetAutoplay(autoplay) => Self { autoplay, ..self },
+            ModeChange::ToggleLoopTrack => Self {
+                loop_track: !self.loop_track,
+                ..self
+            },
+            ModeChange::ToggleLoopQueue => Self {
+                loop_queue: !self.loop_queue,
+                ..self
+            },
+            ModeChange::ToggleFair => Self {
+                fair: !self.fair,
+                ..self
+            },
+            ModeChange::ToggleAutoplay => Self {
+                autoplay: !self.autoplay,
+                ..self
+            },
+            ModeChange::TrackStarted => Self {
+                loop_track: false,
+                stop_after_current: false,
+                ..self
+            },
+            ModeChange::QueueCleared => Self {
+                loop_track: false,
+                loop_queue: false,
+                stop_after_current: false,
+                ..self
+            },
+            ModeChange::StopAfterCurrent => Self {
+                loop_track: false,
+                loop_queue: false,
+                stop_after_current: true,
+                ..self
+            },
+        }
+    }
+
+    /// What follows a track that ended for `reason`. Loop-track takes precedence over
+    /// loop-queue, which takes precedence over autoplay. Stopped and failed tracks and the last
+    /// track before a stop are never followed by anything.
+    pub fn after_track(&self, reason: EndReason) -> AfterTrack {
+        match reason {
+            EndReason::Stopped | EndReason::Errored => AfterTrack::Continue,
+            _ if self.stop_after_current => AfterTrack::Continue,
+            // The track repeats itself, nothing is added behind it
+            EndReason::Finished if self.loop_track => AfterTrack::Continue,
+            // Skipped tracks are not looped
+            EndReason::Finished if self.loop_queue => AfterTrack::Requeue,
+            // A looping queue never runs out
+            _ if self.loop_queue => AfterTrack::Continue,
+            _ if self.autoplay => AfterTrack::Autoplay,
+            _ => AfterTrack::Continue,
+        }
+    }
+
+    /// Human readable list of all active modes
+    pub fn describe(&self) -> String {
+        let mut modes = Vec::new();
+        if self.loop_track {
+            modes.push("Lied wiederholen");
+        }
+        if self.loop_queue {
+            if self.loop_track {
+                modes.push("Warteschlange wiederholen (nach dem Lied-Loop)");
+            } else {
+                modes.push("Warteschlange wiederholen");
+            }
+        }
+        if self.fair {
+            modes.push("Fair");
+        }
+        if self.autoplay {
+            modes.push("Autoplay");
+        }
+        if self.stop_after_current {
+            modes.push("Stopp nach dem aktuellen Lied");
+        }
+
+        if modes.is_empty() {
+            "Normal".to_owned()
+        } else {
+            modes.join(", ")
+        }
+    }
+}
+
+/// Playback modes of all guilds. Every mode change goes through [PlaybackModes::apply].
+#[derive(Default)]
+pub struct PlaybackModes {
+    modes: GuildStateMap<PlaybackMode>,
+}
+
+impl PlaybackModes {
+    pub fn get(&self, guild_id: GuildId) -> PlaybackMode {
+        self.modes.get_or_default(guild_id)
+    }
+
+    /// Applies a change and returns the new mode
+    pub fn apply(&self, guild_id: GuildId, change: ModeChange) -> PlaybackMode {
+        self.modes
+            .update(guild_id, |mode| *mode = mode.apply(change))
+    }
+}
+
+impl GuildScoped for PlaybackModes {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::Modes
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.modes.entry_counts(|_| 1)
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.modes.remove_on_leave(guild_id);
+    }
+}
+
+/// Index at which a new track of `requester` is inserted in fair mode, so that requesters take
+/// turns. `queue` contains the requesters of all queued tracks, including the current one.
+pub fn fair_insert_position<T: PartialEq>(queue: &[T], requester: &T) -> usize {
+    let Some((_current, upcoming)) = queue.split_first() else {
+        return 0;
+    };
+
+    // Round in which the new track plays, counting from 0
+    let round = upcoming.iter().filter(|r| *r == requester).count();
+
+    // Insert before the first track of a later round
+    upcoming
+        .iter()
+        .enumerate()
+        .position(|(i, r)| upcoming[..i].iter().filter(|other| *other == r).count() > round)
+        .map(|pos| pos + 1)
+        .unwrap_or(queue.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_ON: PlaybackMode = PlaybackMode {
+        loop_track: true,
+        loop_queue: true,
+        fair: true,
+        autoplay: true,
+        stop_after_current: false,
+    };
+
+    #[test]
+    fn loop_track_takes_precedence() {
+        assert_eq!(
+            ALL_ON.after_track(EndReason::Finished),
+            AfterTrack::Continue
+        );
+    }
+
+    #[test]
+    fn loop_queue_takes_precedence_over_autoplay() {
+        let mode = PlaybackMode {
+            loop_track: false,
+            ..ALL_ON
+        };
+        assert_eq!(mode.after_track(EndReason::Finished), AfterTrack::Requeue);
+        // A skipped track is not looped, and the looping queue does not run out
+        assert_eq!(mode.after_track(EndReason::Skipped), AfterTrack::Continue);
+    }
+
+    #[test]
+    fn autoplay_follows_finished_and_skipped_tracks() {
+        let mode = PlaybackMode {
+            autoplay: true,
+            ..Default::default()
+        };
+        assert_eq!(mode.after_track(EndReason::Finished), AfterTrack::Autoplay);
+        assert_eq!(mode.after_track(EndReason::Skipped), AfterTrack::Autoplay);
+        assert_eq!(
+            PlaybackMode::default().after_track(EndReason::Finished),
+            AfterTrack::Continue
+        );
+    }
+
+    #[test]
+    fn stopped_and_failed_tracks_are_never_followed() {
+        for reason in [EndReason::Stopped, EndReason::Errored] {
+            assert_eq!(ALL_ON.after_track(reason), AfterTrack::Continue);
+        }
+    }
+
+    #[test]
+    fn queue_cleared_ends_loops_and_keeps_preferences() {
+        let mode = ALL_ON.apply(ModeChange::QueueCleared);
+        assert_eq!(
+            mode,
+            PlaybackMode {
+                loop_track: false,
+                loop_queue: false,
+                fair: true,
+                autoplay: true,
+                stop_after_current: false,
+            }
+        );
+        assert_eq!(mode.apply(ModeChange::QueueCleared), mode);
+    }
+
+    #[test]
+    fn stop_finishing_the_current_track_ends_loops_and_autoplay() {
+        let mode = ALL_ON.apply(ModeChange::stop(true));
+        assert!(!mode.loop_track && !mode.loop_queue);
+        // Autoplay stays a preference, it only skips the end of this track
+        assert!(mode.autoplay && mode.fair);
+        for reason in [EndReason::Finished, EndReason::Skipped] {
+            assert_eq!(mode.after_track(reason), AfterTrack::Continue);
+        }
+    }
+
+    #[test]
+    fn next_track_ends_the_stop() {
+        let mode = ALL_ON
+            .apply(ModeChange::stop(true))
+            .apply(ModeChange::TrackStarted);
+        assert!(!mode.stop_after_current);
+        assert_eq!(mode.after_track(EndReason::Finished), AfterTrack::Autoplay);
+    }
+
+    #[test]
+    fn full_stop_also_ends_a_pending_stop() {
+        let mode = ALL_ON
+            .apply(ModeChange::stop(true))
+            .apply(ModeChange::stop(false));
+        assert_eq!(mode, ALL_ON.apply(ModeChange::QueueCleared));
+        assert!(!mode.stop_after_current);
+    }
+
+    #[test]
+    fn loops_enabled_during_a_stop_wait_for_the_next_track() {
+        let mode = PlaybackMode::default()
+            .apply(ModeChange::stop(true))
+            .apply(ModeChange::SetLoopQueue(true));
+        assert_eq!(mode.after_track(EndReason::Finished), AfterTrack::Continue);
+        assert_eq!(
+            mode.apply(ModeChange::TrackStarted)
+                .after_track(EndReason::Finished),
+            AfterTrack::Requeue
+        );
+    }
+
+    #[test]
+    fn track_started_only_ends_the_track_loop() {
+        let mode = ALL_ON.apply(ModeChange::TrackStarted);
+        assert!(!mode.loop_track);
+        assert!(mode.loop_queue && mode.fair && mode.autoplay);
+    }
+
+    #[test]
+    fn setters_only_change_their_mode() {
+        let mode = PlaybackMode::default()
+            .apply(ModeChange::SetLoopQueue(true))
+            .apply(ModeChange::SetFair(true));
+        assert_eq!(
+            mode,
+            PlaybackMode {
+                loop_queue: true,
+                fair: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            mode.apply(ModeChange::SetLoopQueue(false)),
+            PlaybackMode {
+                fair: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn toggles_only_change_their_mode() {
+        let mode = PlaybackMode::default()
+            .apply(ModeChange::ToggleLoopTrack)
+            .apply(ModeChange::ToggleAutoplay);
+        assert_eq!(
+            mode,
+            PlaybackMode {
+                loop_track: true,
+                autoplay: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            ALL_ON
+                .apply(ModeChange::ToggleLoopQueue)
+                .apply(ModeChange::ToggleFair),
+            PlaybackMode {
+                loop_queue: false,
+                fair: false,
+                ..ALL_ON
+            }
+        );
+    }
+
+    #[test]
+    fn concurrent_toggles_are_not_lost() {
+        let guild_id = GuildId::new(1);
+        let modes = PlaybackModes::default();
+        // Every mode is toggled an even number of times, a lost toggle would leave it switched on
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..250 {
+                        for change in [
+                            ModeChange::ToggleLoopTrack,
+                            ModeChange::ToggleLoopQueue,
+                            ModeChange::ToggleFair,
+                            ModeChange::ToggleAutoplay,
+                        ] {
+                            modes.apply(guild_id, change);
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(modes.get(guild_id), PlaybackMode::default());
+
+        for change in [
+            ModeChange::ToggleLoopTrack,
+            ModeChange::ToggleLoopQueue,
+            ModeChange::ToggleFair,
+            ModeChange::ToggleAutoplay,
+        ] {
+            modes.apply(guild_id, change);
+        }
+        assert_eq!(modes.get(guild_id), ALL_ON);
+    }
+
+    #[test]
+    fn fair_insert_takes_turns() {
+        // Current track, then a, a, b. A new requester plays before the second track of a.
+        assert_eq!(fair_insert_position(&['x', 'a', 'a', 'b'], &'c'), 2);
+        assert_eq!(fair_insert_position(&['x', 'a', 'a', 'b'], &'b'), 4);
+        assert_eq!(fair_insert_position(&['x', 'a', 'a', 'b'], &'a'), 4);
+        assert_eq!(fair_insert_position(&['x'], &'a'), 1);
+        assert_eq!(fair_insert_position::<char>(&[], &'a'), 0);
+    }
+}
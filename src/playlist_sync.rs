@@ -0,0 +1,115 @@
+use crate::lifecycle::GuildPersisted;
+use crate::persistence::{PersistedFile, PersistenceHealth, StorageUnavailable};
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Video ids remembered per synced playlist. The oldest are forgotten first, they are the least
+/// likely to be removed from and added to the playlist again.
+pub const MAX_SEEN_PER_PLAYLIST: usize = 5000;
+
+/// Videos of a playlist that were already enqueued by a sync in a guild
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct SyncedPlaylist {
+    guild_id: GuildId,
+    playlist_id: String,
+    /// Oldest first
+    seen: VecDeque<String>,
+}
+
+/// Memory of `/playlistsync`, written to a file if one is configured
+pub struct PlaylistSyncStore {
+    playlists: Mutex<Vec<SyncedPlaylist>>,
+    file: PersistedFile,
+}
+
+impl PlaylistSyncStore {
+    /// Loads the synced playlists from the file, starts empty if it does not exist yet or can not be read
+    pub fn load(file: Option<PathBuf>, health: Arc<PersistenceHealth>) -> Self {
+        let file = PersistedFile::new("synced playlists", file, health);
+        Self {
+            playlists: Mutex::new(file.load()),
+            file,
+        }
+    }
+
+    /// Replaces the playlists with the content of the file, for edits made while the bot runs
+    pub fn reload(&self) -> usize {
+        let playlists = self.file.load();
+        let count = playlists.len();
+        *self.playlists.lock().unwrap() = playlists;
+        count
+    }
+
+    /// Syncs are refused while the file could not be loaded, they would add everything again
+    pub fn check_available(&self) -> Result<(), StorageUnavailable> {
+        self.file.check_available()
+    }
+
+    /// Video ids of the playlist that were enqueued by earlier syncs
+    pub fn seen(&self, guild_id: GuildId, playlist_id: &str) -> HashSet<String> {
+        self.playlists
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|p| p.guild_id == guild_id && p.playlist_id == playlist_id)
+            .map(|p| p.seen.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn mark_seen(&self, guild_id: GuildId, playlist_id: &str, video_ids: Vec<String>) {
+        if video_ids.is_empty() {
+            return;
+        }
+        let mut playlists = self.playlists.lock().unwrap();
+        let index = match playlists
+            .iter()
+            .position(|p| p.guild_id == guild_id && p.playlist_id == playlist_id)
+        {
+            Some(index) => index,
+            None => {
+                playlists.push(SyncedPlaylist {
+                    guild_id,
+                    playlist_id: playlist_id.to_owned(),
+                    seen: VecDeque::new(),
+                });
+                playlists.len() - 1
+            }
+        };
+
+        let seen = &mut playlists[index].seen;
+        seen.extend(video_ids);
+        let overflow = seen.len().saturating_sub(MAX_SEEN_PER_PLAYLIST);
+        seen.drain(..overflow);
+        self.persist(&playlists);
+    }
+
+    /// Forgets the playlist, so the next sync adds all of it again. Returns the number of
+    /// forgotten videos.
+    pub fn reset(&self, guild_id: GuildId, playlist_id: &str) -> usize {
+        let mut playlists = self.playlists.lock().unwrap();
+        let Some(index) = playlists
+            .iter()
+            .position(|p| p.guild_id == guild_id && p.playlist_id == playlist_id)
+        else {
+            return 0;
+        };
+        let removed = playlists.remove(index);
+        self.persist(&playlists);
+        removed.seen.len()
+    }
+
+    fn persist(&self, playlists: &[SyncedPlaylist]) {
+        self.file.save(playlists);
+    }
+}
+
+impl GuildPersisted for PlaylistSyncStore {
+    fn purge(&self, guild_id: GuildId) {
+        let mut playlists = self.playlists.lock().unwrap();
+        playlists.retain(|p| p.guild_id != guild_id);
+        self.persist(&playlists);
+    }
+}
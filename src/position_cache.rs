@@ -0,0 +1,253 @@
+use crate::diagnostics::SAMPLE_INTERVAL;
+use crate::events::{PlaybackEvent, PlaybackEventBus};
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use serenity::all::GuildId;
+use songbird::tracks::{LoopState, PlayMode, TrackHandle, TrackState};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
+use uuid::Uuid;
+
+/// Samples older than this are not used anymore. While a track plays the sampler refreshes the
+/// cache every [SAMPLE_INTERVAL], so the bound is only reached if a sample was missed.
+pub const MAX_STALENESS: Duration = Duration::from_secs(10);
+const _: () = assert!(MAX_STALENESS.as_secs() >= 2 * SAMPLE_INTERVAL.as_secs());
+
+/// State of the current track of a guild at the time it was sampled
+#[derive(Clone, Copy, Debug)]
+pub struct PositionSample {
+    pub track: Uuid,
+    pub position: Duration,
+    pub playing: bool,
+    pub looping: bool,
+    pub sampled_at: Instant,
+}
+
+impl PositionSample {
+    pub fn new(track: Uuid, info: &TrackState, sampled_at: Instant) -> Self {
+        Self {
+            track,
+            position: info.position,
+            playing: info.playing == PlayMode::Play,
+            looping: info.loops != LoopState::Finite(0),
+            sampled_at,
+        }
+    }
+
+    /// Position at `now`, assuming a playing track kept playing since the sample
+    pub fn position_at(&self, now: Instant) -> Duration {
+        match self.playing {
+            true => self.position + now.saturating_duration_since(self.sampled_at),
+            false => self.position,
+        }
+    }
+
+    pub fn is_fresh(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.sampled_at) <= MAX_STALENESS
+    }
+}
+
+/// Last sampled position of the current track of every guild, so renders do not have to ask the
+/// driver for it. Dropped whenever a playback event makes it outdated.
+#[derive(Default)]
+pub struct PositionCache {
+    samples: GuildStateMap<PositionSample>,
+    /// Guilds whose current track is playing, but its position froze
+    buffering: GuildStateMap<()>,
+    /// Reads that missed the cache and went to the driver
+    driver_reads: AtomicU64,
+}
+
+impl PositionCache {
+    pub fn record(&self, guild_id: GuildId, sample: PositionSample) {
+        self.samples.insert(guild_id, sample);
+    }
+
+    pub fn invalidate(&self, guild_id: GuildId) {
+        self.samples.remove(guild_id);
+        self.buffering.remove(guild_id);
+    }
+
+    /// Returns whether the flag changed
+    pub fn set_buffering(&self, guild_id: GuildId, buffering: bool) -> bool {
+        match buffering {
+            true => self.buffering.insert(guild_id, ()).is_none(),
+            false => self.buffering.remove(guild_id).is_some(),
+        }
+    }
+
+    /// How often [PositionCache::read] had to ask the driver
+    pub fn driver_reads(&self) -> u64 {
+        self.driver_reads.load(Ordering::Relaxed)
+    }
+
+    pub fn is_buffering(&self, guild_id: GuildId) -> bool {
+        self.buffering.contains(guild_id)
+    }
+
+    /// The cached sample of the track, if it is still fresh at `now`
+    pub fn get(&self, guild_id: GuildId, track: Uuid, now: Instant) -> Option<PositionSample> {
+        self.samples
+            .get(guild_id)
+            .filter(|sample| sample.track == track && sample.is_fresh(now))
+    }
+
+    /// The sample of the current track, from the cache or from the driver if there is no fresh
+    /// one. `None` if the track is no longer alive.
+    pub async fn read(&self, guild_id: GuildId, current: &TrackHandle) -> Option<PositionSample> {
+        let now = Instant::now();
+        if let Some(sample) = self.get(guild_id, current.uuid(), now) {
+            return Some(sample);
+        }
+        self.driver_reads.fetch_add(1, Ordering::Relaxed);
+        let info = current.get_info().await.ok()?;
+        let sample = PositionSample::new(current.uuid(), &info, now);
+        self.record(guild_id, sample);
+        Some(sample)
+    }
+}
+
+impl GuildScoped for PositionCache {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::Positions
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        let mut counts = self.samples.entry_counts(|_| 1);
+        for (guild_id, count) in self.buffering.entry_counts(|_| 1) {
+            *counts.entry(guild_id).or_default() += count;
+        }
+        counts
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.samples.remove_on_leave(guild_id);
+        self.buffering.remove_on_leave(guild_id);
+    }
+}
+
+/// Drops cached positions that playback events made outdated. Runs until the process exits.
+pub async fn run_invalidation(cache: Arc<PositionCache>, events: Arc<PlaybackEventBus>) {
+    let mut events = events.subscribe();
+    loop {
+        match events.recv().await {
+            // Samples belong to a track, so a reordered queue does not outdate them
+            Ok((_, PlaybackEvent::QueueChanged)) => {}
+            Ok((guild_id, _)) => cache.invalidate(guild_id),
+            // Missed events could have been about any guild
+            Err(RecvError::Lagged(_)) => {
+                cache.samples.clear();
+                cache.buffering.clear();
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    fn sample(track: Uuid, playing: bool, sampled_at: Instant) -> PositionSample {
+        PositionSample {
+            track,
+            position: Duration::from_secs(30),
+            playing,
+            looping: false,
+            sampled_at,
+        }
+    }
+
+    #[test]
+    fn samples_are_fresh_up_to_the_bound() {
+        let start = Instant::now();
+        let sample = sample(Uuid::new_v4(), true, start);
+        assert!(sample.is_fresh(start));
+        assert!(sample.is_fresh(start + MAX_STALENESS));
+        assert!(!sample.is_fresh(start + MAX_STALENESS + Duration::from_millis(1)));
+        // A sample taken after `now` by another task is not stale
+        assert!(sample.is_fresh(start - Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn stale_samples_are_not_returned() {
+        let cache = PositionCache::default();
+        let track = Uuid::new_v4();
+        let start = Instant::now();
+        cache.record(GUILD, sample(track, true, start));
+
+        assert!(cache.get(GUILD, track, start + MAX_STALENESS).is_some());
+        assert!(cache
+            .get(GUILD, track, start + MAX_STALENESS + Duration::from_secs(1))
+            .is_none());
+    }
+
+    #[test]
+    fn samples_belong_to_one_track_and_guild() {
+        let cache = PositionCache::default();
+        let track = Uuid::new_v4();
+        let start = Instant::now();
+        cache.record(GUILD, sample(track, true, start));
+
+        assert!(cache.get(GUILD, Uuid::new_v4(), start).is_none());
+        assert!(cache.get(GuildId::new(2), track, start).is_none());
+    }
+
+    #[test]
+    fn only_playing_tracks_advance() {
+        let start = Instant::now();
+        let later = start + Duration::from_secs(5);
+        assert_eq!(
+            sample(Uuid::new_v4(), true, start).position_at(later),
+            Duration::from_secs(35)
+        );
+        assert_eq!(
+            sample(Uuid::new_v4(), false, start).position_at(later),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn buffering_reports_changes_and_is_invalidated() {
+        let cache = PositionCache::default();
+        assert!(cache.set_buffering(GUILD, true));
+        assert!(!cache.set_buffering(GUILD, true));
+        assert!(cache.is_buffering(GUILD));
+
+        cache.record(GUILD, sample(Uuid::new_v4(), true, Instant::now()));
+        assert_eq!(cache.entry_counts()[&GUILD], 2);
+        cache.invalidate(GUILD);
+        assert!(!cache.is_buffering(GUILD));
+        assert!(cache.entry_counts().is_empty());
+        assert!(!cache.set_buffering(GUILD, false));
+    }
+
+    #[tokio::test]
+    async fn events_invalidate_all_but_queue_changes() {
+        let cache = Arc::new(PositionCache::default());
+        let events = Arc::new(PlaybackEventBus::default());
+        let task = tokio::spawn(run_invalidation(cache.clone(), events.clone()));
+        // Lets the task subscribe
+        tokio::task::yield_now().await;
+
+        let track = Uuid::new_v4();
+        let now = Instant::now();
+        cache.record(GUILD, sample(track, true, now));
+        events.publish(GUILD, PlaybackEvent::QueueChanged);
+        tokio::task::yield_now().await;
+        assert!(cache.get(GUILD, track, now).is_some());
+
+        events.publish(GuildId::new(2), PlaybackEvent::PlayStateChanged);
+        tokio::task::yield_now().await;
+        assert!(cache.get(GUILD, track, now).is_some());
+
+        events.publish(GUILD, PlaybackEvent::PlayStateChanged);
+        tokio::task::yield_now().await;
+        assert!(cache.get(GUILD, track, now).is_none());
+        task.abort();
+    }
+}
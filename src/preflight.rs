@@ -0,0 +1,288 @@
+use log::info;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::{Client as HttpClient, Url};
+use std::time::Duration;
+use thiserror::Error;
+
+/// A server that needs longer for a HEAD request is left to yt-dlp
+const PREFLIGHT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Extensions of links that point at a media file rather than a page for an extractor
+const MEDIA_EXTENSIONS: [&str; 14] = [
+    "mp3", "ogg", "oga", "opus", "flac", "wav", "m4a", "aac", "mp4", "m4v", "webm", "mkv", "mov",
+    "m3u8",
+];
+
+/// Why a direct link is refused before yt-dlp is started
+#[derive(Debug, Error)]
+pub enum PreflightError {
+    #[error("The link serves {content_type}, which is neither audio nor video")]
+    NotMedia { content_type: String },
+    #[error("The file has {size} bytes, more than the limit of {limit}")]
+    TooLarge { size: u64, limit: u64 },
+}
+
+/// Checks a link that is not handled by a known platform with a HEAD request. Files that are
+/// neither audio nor video or larger than `max_bytes` are refused. Pages are left to the
+/// extractors of yt-dlp, unless the link looks like a media file. Servers that do not answer HEAD
+/// properly are let through.
+pub async fn check_direct_link(
+    http_client: &HttpClient,
+    url: &Url,
+    max_bytes: Option<u64>,
+) -> Result<(), PreflightError> {
+    let response = match http_client
+        .head(url.clone())
+        .timeout(PREFLIGHT_TIMEOUT)
+        .send()
+        .await
+    {
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) => {
+            info!(
+                "HEAD for {url} answered {}, leaving the link to yt-dlp",
+                response.status()
+            );
+            return Ok(());
+        }
+        Err(e) => {
+            info!("HEAD for {url} failed, leaving the link to yt-dlp: {e}");
+            return Ok(());
+        }
+    };
+    let headers = response.headers();
+
+    if let Some(content_type) = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    {
+        // Parameters like the charset do not matter
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let allowed = match classify(&essence) {
+            ContentKind::Media => true,
+            ContentKind::Page => !looks_like_file(url),
+            ContentKind::Other => false,
+        };
+        if !allowed {
+            return Err(PreflightError::NotMedia {
+                content_type: essence,
+            });
+        }
+    }
+
+    let size = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    match (size, max_bytes) {
+        (Some(size), Some(limit)) if size > limit => Err(PreflightError::TooLarge { size, limit }),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ContentKind {
+    /// Audio, video, playlists of streams and types servers use when they do not know better
+    Media,
+    /// A web page, which may be one yt-dlp has an extractor for
+    Page,
+    Other,
+}
+
+fn classify(essence: &str) -> ContentKind {
+    let (kind, subtype) = essence.split_once('/').unwrap_or((essence, ""));
+    match (kind, subtype) {
+        ("audio" | "video", _) => ContentKind::Media,
+        (
+            "application",
+            "ogg" | "octet-stream" | "vnd.apple.mpegurl" | "x-mpegurl" | "dash+xml" | "mp4",
+        ) => ContentKind::Media,
+        ("binary", "octet-stream") => ContentKind::Media,
+        ("text", "html") | ("application", "xhtml+xml") => ContentKind::Page,
+        _ => ContentKind::Other,
+    }
+}
+
+/// Whether the path of the link ends in the extension of a media file
+fn looks_like_file(url: &Url) -> bool {
+    url.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .and_then(|name| name.rsplit_once('.'))
+        .is_some_and(|(_, extension)| {
+            MEDIA_EXTENSIONS
+                .iter()
+                .any(|media| media.eq_ignore_ascii_case(extension))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::TestServer;
+    use hyper::{Body, Method, Response, StatusCode};
+
+    #[test]
+    fn content_types() {
+        let table = [
+            ("audio/mpeg", ContentKind::Media),
+            ("audio/ogg", ContentKind::Media),
+            ("video/mp4", ContentKind::Media),
+            ("video/x-matroska", ContentKind::Media),
+            ("application/ogg", ContentKind::Media),
+            ("application/octet-stream", ContentKind::Media),
+            ("binary/octet-stream", ContentKind::Media),
+            ("application/vnd.apple.mpegurl", ContentKind::Media),
+            ("application/x-mpegurl", ContentKind::Media),
+            ("application/dash+xml", ContentKind::Media),
+            ("text/html", ContentKind::Page),
+            ("application/xhtml+xml", ContentKind::Page),
+            ("text/plain", ContentKind::Other),
+            ("application/json", ContentKind::Other),
+            ("application/pdf", ContentKind::Other),
+            ("image/png", ContentKind::Other),
+            // Broken types still name their kind
+            ("audio", ContentKind::Media),
+            ("", ContentKind::Other),
+        ];
+        for (essence, kind) in table {
+            assert_eq!(classify(essence), kind, "{essence}");
+        }
+    }
+
+    #[test]
+    fn file_links() {
+        let looks_like_file = |url: &str| looks_like_file(&Url::parse(url).unwrap());
+        assert!(looks_like_file("https://example.com/song.mp3"));
+        assert!(looks_like_file("https://example.com/a/b/Song.FLAC"));
+        assert!(looks_like_file(
+            "https://example.com/live/index.m3u8?token=1"
+        ));
+        assert!(looks_like_file("https://example.com/my.song.opus#t=10"));
+        assert!(!looks_like_file("https://example.com/"));
+        assert!(!looks_like_file("https://example.com/watch"));
+        assert!(!looks_like_file("https://example.com/song.mp3/"));
+        assert!(!looks_like_file("https://example.com/page.html"));
+        assert!(!looks_like_file("https://example.com/mp3"));
+        assert!(!looks_like_file("https://example.com/?file=song.mp3"));
+    }
+
+    /// Answers HEAD requests with the given headers, and the status for everything
+    fn head_server(status: StatusCode, headers: Vec<(&'static str, &'static str)>) -> TestServer {
+        TestServer::start(move |request| {
+            assert_eq!(request.method(), Method::HEAD);
+            let mut response = Response::builder().status(status);
+            for (name, value) in &headers {
+                response = response.header(*name, *value);
+            }
+            response.body(Body::empty()).unwrap()
+        })
+    }
+
+    async fn check(
+        server: &TestServer,
+        path: &str,
+        max_bytes: Option<u64>,
+    ) -> Result<(), PreflightError> {
+        let url = Url::parse(&server.url(path)).unwrap();
+        check_direct_link(&HttpClient::new(), &url, max_bytes).await
+    }
+
+    #[tokio::test]
+    async fn media_within_the_limit_is_let_through() {
+        let server = head_server(
+            StatusCode::OK,
+            vec![
+                ("content-type", "audio/mpeg"),
+                ("content-length", "5000000"),
+            ],
+        );
+        assert!(check(&server, "/song.mp3", Some(10_000_000)).await.is_ok());
+        assert!(check(&server, "/song.mp3", None).await.is_ok());
+        assert_eq!(server.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn enormous_files_are_refused() {
+        let server = head_server(
+            StatusCode::OK,
+            vec![
+                ("content-type", "video/mp4"),
+                ("content-length", "4294967296"),
+            ],
+        );
+        let result = check(&server, "/movie.mp4", Some(500 * 1024 * 1024)).await;
+        assert!(matches!(
+            result,
+            Err(PreflightError::TooLarge {
+                size: 4294967296,
+                limit: 524288000
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn other_content_is_refused() {
+        for content_type in [
+            "application/pdf",
+            "image/png; charset=binary",
+            "Text/Plain; charset=UTF-8",
+        ] {
+            let server = head_server(StatusCode::OK, vec![("content-type", content_type)]);
+            let result = check(&server, "/file", None).await;
+            let expected = content_type.split(';').next().unwrap().to_ascii_lowercase();
+            assert!(
+                matches!(&result, Err(PreflightError::NotMedia { content_type }) if *content_type == expected),
+                "{content_type}: {result:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn pages_are_left_to_the_extractors_unless_they_should_be_files() {
+        let server = head_server(
+            StatusCode::OK,
+            vec![("content-type", "text/html; charset=utf-8")],
+        );
+        assert!(check(&server, "/watch/123", None).await.is_ok());
+        // A media link that serves a page is an error page or a login wall
+        assert!(matches!(
+            check(&server, "/song.mp3", None).await,
+            Err(PreflightError::NotMedia { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn missing_headers_are_let_through() {
+        let server = head_server(StatusCode::OK, vec![]);
+        assert!(check(&server, "/stream", Some(1)).await.is_ok());
+        let server = head_server(
+            StatusCode::OK,
+            vec![("content-type", "audio/ogg"), ("content-length", "unknown")],
+        );
+        assert!(check(&server, "/stream", Some(1)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn servers_without_proper_head_support_are_let_through() {
+        for status in [
+            StatusCode::METHOD_NOT_ALLOWED,
+            StatusCode::FORBIDDEN,
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ] {
+            let server = head_server(status, vec![("content-type", "application/pdf")]);
+            assert!(
+                check(&server, "/file.pdf", Some(1)).await.is_ok(),
+                "{status}"
+            );
+        }
+        // Nothing listens on the port
+        let closed = Url::parse("http://127.0.0.1:1/song.mp3").unwrap();
+        assert!(check_direct_link(&HttpClient::new(), &closed, Some(1))
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,76 @@
+use crate::ytdlp::{spawn_download, YtDlpConfig};
+use std::io;
+use std::process::Stdio;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, BufReader};
+use tokio::process::Command;
+use tokio::time::timeout;
+
+const FFMPEG_COMMAND: &str = "ffmpeg";
+/// Length of a clip, enough to recognize a track
+const CLIP_SECONDS: &str = "30";
+/// Live streams need the full length of the clip in real time, so this leaves room for them
+const CLIP_TIMEOUT: Duration = Duration::from_secs(60);
+/// Far below the upload limit of Discord, a clip at the used bitrate has about 350 KB
+const MAX_CLIP_BYTES: usize = 2 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum PreviewError {
+    #[error("yt-dlp or ffmpeg could not be started")]
+    Spawn(#[from] io::Error),
+    #[error("The clip took longer than {CLIP_TIMEOUT:?}")]
+    Timeout,
+    #[error("ffmpeg failed: {0}")]
+    Failed(String),
+    #[error("The clip is larger than {MAX_CLIP_BYTES} bytes")]
+    TooLarge,
+}
+
+/// Renders the first seconds of a source as a small mp3. yt-dlp is piped into ffmpeg, so nothing
+/// is written to disk, and both processes are killed when this returns or times out. The caller
+/// has to hold a permit of the process limit.
+pub async fn render_clip(config: &YtDlpConfig, url: &str) -> Result<Vec<u8>, PreviewError> {
+    timeout(CLIP_TIMEOUT, run_clip(config, url))
+        .await
+        .map_err(|_| PreviewError::Timeout)?
+}
+
+async fn run_clip(config: &YtDlpConfig, url: &str) -> Result<Vec<u8>, PreviewError> {
+    let mut download = spawn_download(config, url)?;
+    let audio: Stdio = download
+        .stdout
+        .take()
+        .expect("The stdout of the download is piped")
+        .try_into()?;
+    let mut encode = Command::new(FFMPEG_COMMAND)
+        .args(["-v", "error", "-i", "pipe:0", "-t", CLIP_SECONDS, "-vn"])
+        .args(["-c:a", "libmp3lame", "-b:a", "96k", "-f", "mp3", "pipe:1"])
+        .stdin(audio)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // One byte more than allowed tells a clip at the limit apart from a larger one
+    let mut clip = Vec::new();
+    BufReader::new(encode.stdout.take().expect("The stdout of ffmpeg is piped"))
+        .take(MAX_CLIP_BYTES as u64 + 1)
+        .read_to_end(&mut clip)
+        .await?;
+    if clip.len() > MAX_CLIP_BYTES {
+        return Err(PreviewError::TooLarge);
+    }
+    // ffmpeg is done with the input, the rest of the download is not needed
+    _ = download.start_kill();
+
+    let mut stderr = String::new();
+    if let Some(mut pipe) = encode.stderr.take() {
+        _ = pipe.read_to_string(&mut stderr).await;
+    }
+    let status = encode.wait().await?;
+    if !status.success() || clip.is_empty() {
+        return Err(PreviewError::Failed(stderr.trim().to_owned()));
+    }
+    Ok(clip)
+}
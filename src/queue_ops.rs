@@ -0,0 +1,441 @@
+// Index manipulations of the raw queue, shared by all commands that reorder or remove tracks.
+// Position 0 is always the current track and is only touched where stated.
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::ops::RangeInclusive;
+
+/// Moves the last `count` entries right behind the current track, keeping their order.
+/// Does nothing if the queue has no other entries than those.
+pub fn move_back_to_front<T>(queue: &mut VecDeque<T>, count: usize) {
+    if count == 0 || queue.len() <= count {
+        return;
+    }
+    let moved = queue.split_off(queue.len() - count);
+    for (i, entry) in moved.into_iter().enumerate() {
+        queue.insert(i + 1, entry);
+    }
+}
+
+/// Moves the last entry to `position`, clamped to the end of the queue
+pub fn move_last_to<T>(queue: &mut VecDeque<T>, position: usize) {
+    let Some(last) = queue.pop_back() else {
+        return;
+    };
+    queue.insert(position.min(queue.len()), last);
+}
+
+/// Moves the entry at `index` right behind the current track. Does nothing for the current
+/// track itself or an index outside of the queue.
+pub fn move_to_next<T>(queue: &mut VecDeque<T>, index: usize) {
+    if index == 0 {
+        return;
+    }
+    if let Some(entry) = queue.remove(index) {
+        queue.insert(1, entry);
+    }
+}
+
+/// Moves the entry at index `from` to index `to`, shifting the entries in between. Returns false
+/// without changing the queue if either index is the current track or outside of the queue.
+pub fn move_entry<T>(queue: &mut VecDeque<T>, from: usize, to: usize) -> bool {
+    if from == 0 || to == 0 || from >= queue.len() || to >= queue.len() {
+        return false;
+    }
+    if let Some(entry) = queue.remove(from) {
+        queue.insert(to, entry);
+    }
+    true
+}
+
+/// Removes the entries at the 1-based, inclusive positions. Returns `None` without changing the
+/// queue if the range does not fit the queue.
+pub fn remove_range<T>(queue: &mut VecDeque<T>, range: &RangeInclusive<usize>) -> Option<Vec<T>> {
+    if *range.start() == 0 || range.is_empty() || *range.end() > queue.len() {
+        return None;
+    }
+    Some(queue.drain(range.start() - 1..*range.end()).collect())
+}
+
+/// Removes everything but the current track
+pub fn drain_upcoming<T>(queue: &mut VecDeque<T>) -> Vec<T> {
+    match queue.len() {
+        0 => Vec::new(),
+        _ => queue.drain(1..).collect(),
+    }
+}
+
+/// Shuffles everything but the current track
+pub fn shuffle_upcoming<T>(queue: &mut VecDeque<T>, rng: &mut impl Rng) {
+    if queue.len() > 1 {
+        queue.make_contiguous()[1..].shuffle(rng);
+    }
+}
+
+/// Shuffles everything but the current track like [spread_shuffle], so entries of the same
+/// author do not follow each other
+pub fn spread_shuffle_upcoming<T, A: Eq + Hash>(
+    queue: &mut VecDeque<T>,
+    author: impl Fn(&T) -> A,
+    rng: &mut impl Rng,
+) {
+    if queue.len() <= 1 {
+        return;
+    }
+    let upcoming = Vec::from(queue.split_off(1));
+    let authors = upcoming.iter().map(author).enumerate().collect();
+    let order = spread_shuffle(authors, rng);
+    queue.extend(apply_order(upcoming, &order));
+}
+
+/// Removes the upcoming entries matching the predicate, never the current track
+pub fn remove_upcoming_where<T>(
+    queue: &mut VecDeque<T>,
+    mut predicate: impl FnMut(&T) -> bool,
+) -> Vec<T> {
+    let mut removed = Vec::new();
+    let mut i = 1;
+    while i < queue.len() {
+        if predicate(&queue[i]) {
+            removed.extend(queue.remove(i));
+        } else {
+            i += 1;
+        }
+    }
+    removed
+}
+
+/// Shuffles `(index, author)` pairs so that entries of the same author are spread out, and
+/// returns the indices in their new order. The author with the most entries left is always
+/// picked next unless it was the last one, which never puts two entries of an author next to
+/// each other if that can be avoided. If one author has more than half of the entries it can
+/// not be, and the entries are shuffled plainly instead.
+pub fn spread_shuffle<A: Eq + Hash>(entries: Vec<(usize, A)>, rng: &mut impl Rng) -> Vec<usize> {
+    let count = entries.len();
+    let mut groups = HashMap::<A, Vec<usize>>::new();
+    for (index, author) in entries {
+        groups.entry(author).or_default().push(index);
+    }
+    let mut groups = groups.into_values().collect::<Vec<_>>();
+    if groups.iter().any(|group| group.len() > count.div_ceil(2)) {
+        let mut indices = groups.into_iter().flatten().collect::<Vec<_>>();
+        indices.shuffle(rng);
+        return indices;
+    }
+
+    // Shuffled groups break ties between authors with as many entries left randomly
+    groups.shuffle(rng);
+    for group in &mut groups {
+        group.shuffle(rng);
+    }
+    let mut order = Vec::with_capacity(count);
+    let mut last = None;
+    while order.len() < count {
+        let next = groups
+            .iter()
+            .enumerate()
+            .filter(|(i, group)| Some(*i) != last && !group.is_empty())
+            .fold(
+                None,
+                |best: Option<(usize, usize)>, (i, group)| match best {
+                    Some((_, len)) if len >= group.len() => best,
+                    _ => Some((i, group.len())),
+                },
+            )
+            .map(|(i, _)| i)
+            // Only the last author is left, which the check above rules out
+            .or(last)
+            .expect("The queue is not empty");
+        order.extend(groups[next].pop());
+        last = Some(next);
+    }
+    order
+}
+
+/// Reorders the entries by their indices as returned by [spread_shuffle]
+pub fn apply_order<T>(entries: Vec<T>, order: &[usize]) -> Vec<T> {
+    let mut entries = entries.into_iter().map(Some).collect::<Vec<_>>();
+    order
+        .iter()
+        .filter_map(|index| entries.get_mut(*index).and_then(Option::take))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    /// A queue of the numbers up to `len`, 0 being the current track
+    fn queue(len: usize) -> VecDeque<usize> {
+        (0..len).collect()
+    }
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(7)
+    }
+
+    fn sorted(mut entries: Vec<usize>) -> Vec<usize> {
+        entries.sort_unstable();
+        entries
+    }
+
+    #[test]
+    fn move_back_to_front_keeps_the_order() {
+        let mut q = queue(5);
+        move_back_to_front(&mut q, 2);
+        assert_eq!(q, [0, 3, 4, 1, 2]);
+    }
+
+    #[test]
+    fn move_back_to_front_without_other_entries_changes_nothing() {
+        for (len, count) in [(0, 0), (0, 1), (1, 1), (3, 3), (3, 4), (3, 0)] {
+            let mut q = queue(len);
+            move_back_to_front(&mut q, count);
+            assert_eq!(q, queue(len), "len {len}, count {count}");
+        }
+        // Everything behind the current track is already in front
+        let mut q = queue(3);
+        move_back_to_front(&mut q, 2);
+        assert_eq!(q, queue(3));
+    }
+
+    #[test]
+    fn move_last_to_is_clamped() {
+        let mut q = queue(4);
+        move_last_to(&mut q, 1);
+        assert_eq!(q, [0, 3, 1, 2]);
+        move_last_to(&mut q, 10);
+        assert_eq!(q, [0, 3, 1, 2]);
+
+        let mut empty = queue(0);
+        move_last_to(&mut empty, 0);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn move_to_next_at_the_edges() {
+        let mut q = queue(4);
+        move_to_next(&mut q, 3);
+        assert_eq!(q, [0, 3, 1, 2]);
+        move_to_next(&mut q, 1);
+        assert_eq!(q, [0, 3, 1, 2]);
+        // The current track and indices outside of the queue
+        move_to_next(&mut q, 0);
+        move_to_next(&mut q, 4);
+        assert_eq!(q, [0, 3, 1, 2]);
+
+        for len in [0, 1] {
+            let mut q = queue(len);
+            move_to_next(&mut q, 0);
+            move_to_next(&mut q, len);
+            assert_eq!(q, queue(len));
+        }
+    }
+
+    #[test]
+    fn move_entry_in_both_directions() {
+        let mut q = queue(5);
+        assert!(move_entry(&mut q, 1, 4));
+        assert_eq!(q, [0, 2, 3, 4, 1]);
+        assert!(move_entry(&mut q, 4, 1));
+        assert_eq!(q, queue(5));
+        assert!(move_entry(&mut q, 2, 2));
+        assert_eq!(q, queue(5));
+    }
+
+    #[test]
+    fn move_entry_rejects_the_current_track_and_the_end() {
+        let mut q = queue(4);
+        assert!(!move_entry(&mut q, 0, 2));
+        assert!(!move_entry(&mut q, 2, 0));
+        assert!(!move_entry(&mut q, 4, 1));
+        assert!(!move_entry(&mut q, 1, 4));
+        assert_eq!(q, queue(4));
+
+        assert!(!move_entry(&mut queue(0), 0, 0));
+        assert!(!move_entry(&mut queue(1), 0, 0));
+        assert!(!move_entry(&mut queue(1), 1, 1));
+    }
+
+    #[test]
+    fn drain_upcoming_keeps_the_current_track() {
+        let mut q = queue(4);
+        assert_eq!(drain_upcoming(&mut q), [1, 2, 3]);
+        assert_eq!(q, [0]);
+        assert!(drain_upcoming(&mut q).is_empty());
+        assert_eq!(q, [0]);
+        assert!(drain_upcoming(&mut queue(0)).is_empty());
+    }
+
+    #[test]
+    fn shuffle_upcoming_keeps_the_current_track_and_the_entries() {
+        let mut q = queue(20);
+        shuffle_upcoming(&mut q, &mut rng());
+        assert_eq!(q[0], 0);
+        assert_eq!(sorted(q.into()), (0..20).collect::<Vec<_>>());
+
+        for len in [0, 1] {
+            let mut q = queue(len);
+            shuffle_upcoming(&mut q, &mut rng());
+            assert_eq!(q, queue(len));
+        }
+    }
+
+    #[test]
+    fn remove_upcoming_where_never_removes_the_current_track() {
+        let mut q = queue(7);
+        assert_eq!(remove_upcoming_where(&mut q, |n| n % 2 == 0), [2, 4, 6]);
+        assert_eq!(q, [0, 1, 3, 5]);
+        // Adjacent matches and the last entry
+        let mut q = VecDeque::from([0, 1, 1, 2, 1]);
+        assert_eq!(remove_upcoming_where(&mut q, |n| *n == 1), [1, 1, 1]);
+        assert_eq!(q, [0, 2]);
+
+        let mut q = queue(1);
+        assert!(remove_upcoming_where(&mut q, |_| true).is_empty());
+        assert_eq!(q, [0]);
+        assert!(remove_upcoming_where(&mut queue(0), |_| true).is_empty());
+    }
+
+    #[test]
+    fn spread_shuffle_returns_every_index_once() {
+        let entries = vec![(0, 'a'), (1, 'a'), (2, 'b'), (3, 'c'), (4, 'b')];
+        assert_eq!(sorted(spread_shuffle(entries, &mut rng())), [0, 1, 2, 3, 4]);
+        assert!(spread_shuffle::<char>(Vec::new(), &mut rng()).is_empty());
+        assert_eq!(spread_shuffle(vec![(0, 'a')], &mut rng()), [0]);
+    }
+
+    /// Random queues of up to 12 entries by up to 4 authors, each with its own seed
+    fn random_queues() -> impl Iterator<Item = (Vec<(usize, u8)>, StdRng)> {
+        let mut cases = rng();
+        (0..500).map(move |seed| {
+            let len = cases.gen_range(1..=12);
+            let authors = cases.gen_range(1..=4);
+            let entries = (0..len)
+                .map(|index| (index, cases.gen_range(0..authors)))
+                .collect();
+            (entries, StdRng::seed_from_u64(seed))
+        })
+    }
+
+    fn largest_group(entries: &[(usize, u8)]) -> usize {
+        let mut counts = HashMap::<u8, usize>::new();
+        for (_, author) in entries {
+            *counts.entry(*author).or_default() += 1;
+        }
+        counts.into_values().max().unwrap_or(0)
+    }
+
+    #[test]
+    fn spread_shuffle_never_puts_an_author_twice_in_a_row_when_possible() {
+        let mut checked = 0;
+        for (entries, mut rng) in random_queues() {
+            if largest_group(&entries) > entries.len().div_ceil(2) {
+                continue;
+            }
+            checked += 1;
+            let authors = entries
+                .iter()
+                .map(|(_, author)| *author)
+                .collect::<Vec<_>>();
+            let order = spread_shuffle(entries.clone(), &mut rng);
+            assert_eq!(queue(entries.len()), sorted(order.clone()), "{entries:?}");
+            assert!(
+                order
+                    .windows(2)
+                    .all(|pair| authors[pair[0]] != authors[pair[1]]),
+                "{entries:?} became {order:?}"
+            );
+        }
+        assert!(checked > 100, "Only {checked} spreadable queues");
+    }
+
+    #[test]
+    fn spread_shuffle_is_a_plain_shuffle_when_one_author_dominates() {
+        let mut checked = 0;
+        for (entries, mut rng) in random_queues() {
+            if largest_group(&entries) <= entries.len().div_ceil(2) {
+                continue;
+            }
+            checked += 1;
+            let order = spread_shuffle(entries.clone(), &mut rng);
+            assert_eq!(queue(entries.len()), sorted(order), "{entries:?}");
+        }
+        assert!(checked > 50, "Only {checked} dominated queues");
+
+        // Spreading would keep the other author in the middle, a plain shuffle puts it anywhere
+        let entries = vec![(0, 'a'), (1, 'a'), (2, 'a'), (3, 'a'), (4, 'b')];
+        let mut positions = [false; 5];
+        for seed in 0..200 {
+            let order = spread_shuffle(entries.clone(), &mut StdRng::seed_from_u64(seed));
+            let position = order.iter().position(|index| *index == 4).unwrap();
+            positions[position] = true;
+        }
+        assert_eq!(positions, [true; 5]);
+    }
+
+    #[test]
+    fn apply_order_reorders_and_ignores_unknown_indices() {
+        assert_eq!(
+            apply_order(vec!['a', 'b', 'c'], &[2, 0, 1]),
+            ['c', 'a', 'b']
+        );
+        assert_eq!(apply_order(vec!['a', 'b'], &[1, 5, 1, 0]), ['b', 'a']);
+        assert!(apply_order(Vec::<char>::new(), &[0]).is_empty());
+    }
+
+    #[test]
+    fn spread_shuffle_upcoming_keeps_the_current_track() {
+        let mut q = VecDeque::from(['x', 'a', 'a', 'b', 'b', 'c']);
+        spread_shuffle_upcoming(&mut q, |entry| *entry, &mut rng());
+        assert_eq!(q[0], 'x');
+        let upcoming = q.iter().skip(1).collect::<Vec<_>>();
+        assert!(upcoming.windows(2).all(|pair| pair[0] != pair[1]), "{q:?}");
+
+        for len in [0, 1] {
+            let mut q = queue(len);
+            spread_shuffle_upcoming(&mut q, |entry| *entry, &mut rng());
+            assert_eq!(q, queue(len));
+        }
+    }
+
+    #[test]
+    fn remove_range_takes_the_inclusive_positions() {
+        let mut q = queue(6);
+        assert_eq!(remove_range(&mut q, &(2..=4)), Some(vec![1, 2, 3]));
+        assert_eq!(q, [0, 4, 5]);
+    }
+
+    #[test]
+    fn remove_range_of_a_single_position() {
+        let mut q = queue(4);
+        assert_eq!(remove_range(&mut q, &(4..=4)), Some(vec![3]));
+        assert_eq!(q, [0, 1, 2]);
+    }
+
+    #[test]
+    fn remove_range_up_to_the_end() {
+        let mut q = queue(4);
+        assert_eq!(remove_range(&mut q, &(2..=4)), Some(vec![1, 2, 3]));
+        assert_eq!(q, [0]);
+    }
+
+    #[test]
+    fn remove_range_outside_of_the_queue_changes_nothing() {
+        let mut q = queue(4);
+        assert_eq!(remove_range(&mut q, &(0..=2)), None);
+        assert_eq!(remove_range(&mut q, &(3..=5)), None);
+        assert_eq!(remove_range(&mut q, &(5..=5)), None);
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = 3..=2;
+        assert_eq!(remove_range(&mut q, &reversed), None);
+        assert_eq!(q, queue(4));
+
+        let mut empty = queue(0);
+        assert_eq!(remove_range(&mut empty, &(1..=1)), None);
+    }
+}
@@ -0,0 +1,319 @@
+use crate::audit_log::AuditAction;
+use crate::blocklist::BlockedEntry;
+use crate::canonical_url::canonical_url;
+use crate::commands::util::{
+    get_audit_log, get_blocklist, get_guild_settings, get_playback_events, get_track_reports,
+    has_dj_rights,
+};
+use crate::events::PlaybackEvent;
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use crate::metadata::TrackMetadata;
+use log::info;
+use serenity::all::{
+    ButtonStyle, ComponentInteraction, ComponentInteractionCollector, Context, CreateActionRow,
+    CreateButton, CreateInputText, CreateInteractionResponse, CreateInteractionResponseMessage,
+    CreateQuickModal, EditInteractionResponse, GuildId, InputTextStyle, MessageId, UserId,
+};
+use serenity::Error as SerenityError;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Custom id of the report button. It is handled globally, because the cards it is on outlive
+/// the commands that sent them.
+pub const REPORT_BUTTON_ID: &str = "report";
+/// Messages per guild whose report button still works, older ones are forgotten
+const REPORTABLE_MESSAGES: usize = 100;
+/// Reasons are as long as an audit log entry can reasonably show
+const MAX_REASON_CHARS: u16 = 300;
+const REASON_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// Ephemeral messages can only be edited as long as the interaction token is valid
+const BLOCK_CHOICE_TIMEOUT: Duration = Duration::from_secs(14 * 60);
+
+pub fn report_button() -> CreateActionRow {
+    CreateActionRow::Buttons(vec![CreateButton::new(REPORT_BUTTON_ID)
+        .label("Melden")
+        .style(ButtonStyle::Secondary)])
+}
+
+/// The track a message with a report button is about
+#[derive(Clone, Debug)]
+pub struct ReportTarget {
+    pub title: String,
+    /// As returned by [canonical_url]
+    pub url: String,
+    pub channel: String,
+}
+
+impl ReportTarget {
+    pub fn of(metadata: &TrackMetadata) -> Self {
+        Self {
+            title: metadata.title.clone(),
+            url: canonical_url(&metadata.source_url),
+            channel: metadata.author.clone(),
+        }
+    }
+}
+
+/// How often a track was reported, after a report was recorded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReportCount {
+    pub reporters: usize,
+    /// Whether the same user already reported the track
+    pub repeated: bool,
+}
+
+#[derive(Default)]
+struct TrackReport {
+    reporters: HashSet<UserId>,
+    /// Whether a report with a reason is in the audit log
+    logged: bool,
+}
+
+#[derive(Default)]
+struct GuildReports {
+    messages: VecDeque<(MessageId, ReportTarget)>,
+    /// Reports of each normalized url, until playback stops
+    tracks: HashMap<String, TrackReport>,
+}
+
+/// Messages that can be reported and the reports of the current session of every guild
+#[derive(Default)]
+pub struct TrackReports {
+    guilds: GuildStateMap<GuildReports>,
+}
+
+impl TrackReports {
+    /// Makes the report button of a sent message work
+    pub fn register(&self, guild_id: GuildId, message_id: MessageId, target: ReportTarget) {
+        self.guilds.with_mut(guild_id, |reports| {
+            reports.messages.truncate(REPORTABLE_MESSAGES - 1);
+            reports.messages.push_front((message_id, target));
+        });
+    }
+
+    pub fn target(&self, guild_id: GuildId, message_id: MessageId) -> Option<ReportTarget> {
+        self.guilds.with(guild_id, |reports| {
+            reports?
+                .messages
+                .iter()
+                .find(|(id, _)| *id == message_id)
+                .map(|(_, target)| target.clone())
+        })
+    }
+
+    /// Counts every user once per track
+    pub fn record(&self, guild_id: GuildId, target: &ReportTarget, user_id: UserId) -> ReportCount {
+        self.guilds.with_mut(guild_id, |reports| {
+            let report = reports.tracks.entry(target.url.clone()).or_default();
+            let repeated = !report.reporters.insert(user_id);
+            ReportCount {
+                reporters: report.reporters.len(),
+                repeated,
+            }
+        })
+    }
+
+    /// Returns true only the first time for a track, so it is logged once per session
+    fn mark_logged(&self, guild_id: GuildId, target: &ReportTarget) -> bool {
+        self.guilds.with_mut(guild_id, |reports| {
+            let report = reports.tracks.entry(target.url.clone()).or_default();
+            !std::mem::replace(&mut report.logged, true)
+        })
+    }
+
+    /// Playback stopped, the next session counts its reports from zero
+    fn end_session(&self, guild_id: GuildId) {
+        self.guilds
+            .with_mut(guild_id, |reports| reports.tracks.clear());
+    }
+}
+
+impl GuildScoped for TrackReports {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::Reports
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.guilds
+            .entry_counts(|reports| reports.messages.len() + reports.tracks.len())
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.guilds.remove_on_leave(guild_id);
+    }
+}
+
+/// Resets the report counts of a guild when its playback stops. Runs until the process exits.
+pub async fn run_report_sessions(ctx: Context, reports: Arc<TrackReports>) {
+    let mut events = get_playback_events(&ctx).await.subscribe();
+    loop {
+        match events.recv().await {
+            Ok((guild_id, PlaybackEvent::Stopped)) => reports.end_session(guild_id),
+            Ok(_) | Err(RecvError::Lagged(_)) => {}
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Handles a press of a report button. Users with DJ rights or Manage Messages give a reason that
+/// goes into the audit log, admins can then block the track or its channel. Everyone else is
+/// only counted, so the button cannot be used to flood the audit log.
+pub async fn on_report_pressed(
+    ctx: &Context,
+    press: &ComponentInteraction,
+) -> Result<(), SerenityError> {
+    let Some(guild_id) = press.guild_id else {
+        return Ok(());
+    };
+    let reports = get_track_reports(ctx).await;
+    let Some(target) = reports.target(guild_id, press.message.id) else {
+        let details = "Dieses Lied kann nicht mehr gemeldet werden";
+        return press.create_response(ctx, ephemeral(details)).await;
+    };
+
+    let permissions = press.member.as_ref().and_then(|m| m.permissions);
+    let dj_role = get_guild_settings(ctx).await.get(guild_id).dj_role;
+    let privileged = has_dj_rights(press.member.as_ref(), dj_role)
+        || permissions.is_some_and(|p| p.manage_messages());
+    if !privileged {
+        let count = reports.record(guild_id, &target, press.user.id);
+        info!(
+            "{} reported {} in guild {guild_id} ({} reporters)",
+            press.user.id, target.url, count.reporters
+        );
+        let details = format!("Danke, deine Meldung von `{}` wurde gezählt", target.title);
+        return press.create_response(ctx, ephemeral(details)).await;
+    }
+
+    let modal = CreateQuickModal::new("Lied melden")
+        .timeout(REASON_TIMEOUT)
+        .field(
+            CreateInputText::new(InputTextStyle::Paragraph, "Grund", "reason")
+                .max_length(MAX_REASON_CHARS),
+        );
+    let Some(response) = press.quick_modal(ctx, modal).await? else {
+        return Ok(());
+    };
+    let reason = response.inputs.first().cloned().unwrap_or_default();
+
+    let count = reports.record(guild_id, &target, press.user.id);
+    // Only the first report of a track is logged, later ones only raise the count
+    if reports.mark_logged(guild_id, &target) {
+        get_audit_log(ctx).await.record(
+            guild_id,
+            Some(press.user.id),
+            AuditAction::Reported {
+                title: target.title.clone(),
+                url: target.url.clone(),
+                reason,
+            },
+        );
+    } else {
+        info!(
+            "{} reported {} in guild {guild_id}, which is already logged ({} reporters): {reason}",
+            press.user.id, target.url, count.reporters
+        );
+    }
+
+    let details = match count {
+        ReportCount { repeated: true, .. } => {
+            format!("Du hast `{}` bereits gemeldet", target.title)
+        }
+        ReportCount { reporters: 1, .. } => format!("`{}` wurde gemeldet", target.title),
+        ReportCount { reporters, .. } => {
+            format!("`{}` wurde bereits {reporters} Mal gemeldet", target.title)
+        }
+    };
+    let is_admin = permissions.is_some_and(|p| p.manage_guild());
+    if !is_admin {
+        return response
+            .interaction
+            .create_response(ctx, ephemeral(details))
+            .await;
+    }
+
+    // Admins can block the track right away
+    let id_prefix = response.interaction.id.to_string();
+    let buttons = |disabled: bool| {
+        vec![CreateActionRow::Buttons(vec![
+            CreateButton::new(format!("{id_prefix}blockurl"))
+                .label("Link sperren")
+                .style(ButtonStyle::Danger)
+                .disabled(disabled),
+            CreateButton::new(format!("{id_prefix}blockchannel"))
+                .label("Kanal sperren")
+                .style(ButtonStyle::Danger)
+                .disabled(disabled || target.channel == "Unknown"),
+        ])]
+    };
+    response
+        .interaction
+        .create_response(
+            ctx,
+            CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(details)
+                    .components(buttons(false))
+                    .ephemeral(true),
+            ),
+        )
+        .await?;
+
+    let admin_id = press.user.id;
+    let block_press = ComponentInteractionCollector::new(ctx)
+        .filter({
+            let id_prefix = id_prefix.clone();
+            move |press| press.data.custom_id.starts_with(&id_prefix) && press.user.id == admin_id
+        })
+        .timeout(BLOCK_CHOICE_TIMEOUT)
+        .await;
+    let Some(block_press) = block_press else {
+        response
+            .interaction
+            .edit_response(
+                ctx,
+                EditInteractionResponse::new().components(buttons(true)),
+            )
+            .await?;
+        return Ok(());
+    };
+
+    let entry = match &block_press.data.custom_id[id_prefix.len()..] {
+        "blockchannel" => BlockedEntry::Channel(target.channel.clone()),
+        _ => BlockedEntry::Url(target.url.clone()),
+    };
+    let added = get_blocklist(ctx).await.add(guild_id, entry.clone());
+    if added {
+        get_audit_log(ctx).await.record(
+            guild_id,
+            Some(admin_id),
+            AuditAction::Blocked {
+                entry: entry.describe(),
+            },
+        );
+    }
+    let details = match added {
+        true => format!("{} ist jetzt auf diesem Server gesperrt", entry.describe()),
+        false => format!("{} war bereits gesperrt", entry.describe()),
+    };
+    block_press
+        .create_response(
+            ctx,
+            CreateInteractionResponse::UpdateMessage(
+                CreateInteractionResponseMessage::new()
+                    .content(details)
+                    .components(buttons(true)),
+            ),
+        )
+        .await
+}
+
+fn ephemeral(details: impl Into<String>) -> CreateInteractionResponse {
+    CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(details)
+            .ephemeral(true),
+    )
+}
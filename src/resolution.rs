@@ -0,0 +1,93 @@
+use serenity::all::UserId;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Autocomplete suggestions are recognized for this long after they were offered
+const OFFER_TTL: Duration = Duration::from_secs(15 * 60);
+/// Suggestions remembered per user, a few rounds of autocomplete
+const MAX_OFFERS_PER_USER: usize = 25;
+
+/// How the source of a /play was turned into a track
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResolutionPath {
+    /// YouTube link, metadata from the YouTube client
+    UrlFastpath,
+    /// Any other link, metadata from yt-dlp
+    UrlYtDlp,
+    /// Link picked from the autocomplete suggestions
+    AutocompleteUrl,
+    /// Free text that was sent without picking a suggestion
+    RawSearch,
+}
+
+impl ResolutionPath {
+    pub const ALL: [ResolutionPath; 4] = [
+        ResolutionPath::UrlFastpath,
+        ResolutionPath::UrlYtDlp,
+        ResolutionPath::AutocompleteUrl,
+        ResolutionPath::RawSearch,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResolutionPath::UrlFastpath => "url-fastpath",
+            ResolutionPath::UrlYtDlp => "url-ytdlp",
+            ResolutionPath::AutocompleteUrl => "autocomplete-url",
+            ResolutionPath::RawSearch => "raw-search",
+        }
+    }
+}
+
+/// Counts of the resolution paths since the start, and the links recently suggested by
+/// autocomplete to tell picked suggestions apart from pasted links
+#[derive(Default)]
+pub struct ResolutionTelemetry {
+    counts: [AtomicU64; 4],
+    offers: Mutex<HashMap<UserId, VecDeque<(String, Instant)>>>,
+}
+
+impl ResolutionTelemetry {
+    pub fn record(&self, path: ResolutionPath) {
+        self.counts[path as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> Vec<(ResolutionPath, u64)> {
+        ResolutionPath::ALL
+            .iter()
+            .map(|path| (*path, self.counts[*path as usize].load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Remembers links that autocomplete suggested to the user
+    pub fn offer(&self, user_id: UserId, urls: impl IntoIterator<Item = String>) {
+        let now = Instant::now();
+        let mut offers = self.offers.lock().unwrap();
+        offers.retain(|_, user_offers| {
+            user_offers.retain(|(_, at)| now.duration_since(*at) < OFFER_TTL);
+            !user_offers.is_empty()
+        });
+
+        let user_offers = offers.entry(user_id).or_default();
+        for url in urls {
+            if user_offers.len() >= MAX_OFFERS_PER_USER {
+                user_offers.pop_front();
+            }
+            user_offers.push_back((url, now));
+        }
+    }
+
+    /// Whether the source is a link autocomplete suggested to the user recently
+    pub fn was_offered(&self, user_id: UserId, source: &str) -> bool {
+        self.offers
+            .lock()
+            .unwrap()
+            .get(&user_id)
+            .is_some_and(|user_offers| {
+                user_offers
+                    .iter()
+                    .any(|(url, at)| url == source && at.elapsed() < OFFER_TTL)
+            })
+    }
+}
@@ -0,0 +1,291 @@
+use poise::{CreateReply, ReplyHandle};
+use serenity::all::{Colour, Timestamp};
+use serenity::builder::{CreateAllowedMentions, CreateEmbed, CreateEmbedFooter};
+
+use crate::commands::util::{get_error_rates, get_guild_settings, truncate_chars};
+use crate::error_rates::ErrorSource;
+use crate::guild_settings::QuietLevel;
+use crate::plain_text::EmbedMode;
+use crate::{CommandContext, ERROR_COLOUR, SUCCESS_COLOUR};
+
+/// Limits of embeds in characters, longer parts are cut instead of failing the response
+const TITLE_CHARS: usize = 256;
+const DESCRIPTION_CHARS: usize = 4096;
+const FIELD_NAME_CHARS: usize = 256;
+const FIELD_VALUE_CHARS: usize = 1024;
+const FOOTER_CHARS: usize = 2048;
+/// Shown when a response the author asked to be public is kept private by the guild
+pub const QUIET_NOTE: &str =
+    "Dieser Server hat den Ruhemodus aktiviert, deshalb ist die Antwort nur für dich sichtbar";
+
+/// A command response as an embed. Every response carries a footer with the bot name and the
+/// time it was sent, the colour follows from whether it reports a success or an error.
+#[derive(Clone, Debug)]
+pub struct BotResponse {
+    title: String,
+    description: Option<String>,
+    fields: Vec<(String, String, bool)>,
+    thumbnail: Option<String>,
+    url: Option<String>,
+    footer: Option<String>,
+    colour: Colour,
+    ephemeral: bool,
+    /// The author asked for a public response, see [QUIET_NOTE]
+    requested_public: bool,
+}
+
+impl BotResponse {
+    pub fn success(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            description: None,
+            fields: Vec::new(),
+            thumbnail: None,
+            url: None,
+            footer: None,
+            colour: SUCCESS_COLOUR,
+            ephemeral: false,
+            requested_public: false,
+        }
+    }
+
+    /// A prompt or outcome that warns the author, in the error colour but with its own title
+    pub fn warning(title: impl Into<String>) -> Self {
+        Self {
+            colour: ERROR_COLOUR,
+            ..Self::success(title)
+        }
+    }
+
+    /// Error responses are only shown to the author
+    pub fn error(details: impl Into<String>) -> Self {
+        Self {
+            colour: ERROR_COLOUR,
+            ephemeral: true,
+            ..Self::success("Fehler")
+        }
+        .field("Details", details, false)
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn field(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+        inline: bool,
+    ) -> Self {
+        self.fields.push((name.into(), value.into(), inline));
+        self
+    }
+
+    pub fn thumbnail(mut self, url: impl Into<String>) -> Self {
+        self.thumbnail = Some(url.into());
+        self
+    }
+
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// Shown in the footer before the bot name
+    pub fn footer(mut self, footer: impl Into<String>) -> Self {
+        self.footer = Some(footer.into());
+        self
+    }
+
+    pub fn ephemeral(mut self, ephemeral: bool) -> Self {
+        self.ephemeral = ephemeral;
+        self
+    }
+
+    pub fn requested_public(mut self, requested_public: bool) -> Self {
+        self.requested_public = requested_public;
+        self
+    }
+
+    /// Embed with a fixed footer and time, independent of the command
+    pub fn to_embed(&self, bot_name: &str, timestamp: Timestamp) -> CreateEmbed {
+        let footer = match &self.footer {
+            Some(footer) => format!("{footer} · {bot_name}"),
+            None => bot_name.to_owned(),
+        };
+        let fields = self.fields.iter().map(|(name, value, inline)| {
+            (
+                truncate_chars(name, FIELD_NAME_CHARS),
+                truncate_chars(value, FIELD_VALUE_CHARS),
+                *inline,
+            )
+        });
+        let mut embed = CreateEmbed::new()
+            .title(truncate_chars(&self.title, TITLE_CHARS))
+            .colour(self.colour)
+            .fields(fields)
+            .footer(CreateEmbedFooter::new(truncate_chars(
+                &footer,
+                FOOTER_CHARS,
+            )))
+            .timestamp(timestamp);
+        if let Some(description) = &self.description {
+            embed = embed.description(truncate_chars(description, DESCRIPTION_CHARS));
+        }
+        if let Some(thumbnail) = &self.thumbnail {
+            embed = embed.thumbnail(thumbnail);
+        }
+        if let Some(url) = &self.url {
+            embed = embed.url(url);
+        }
+        embed
+    }
+
+    /// Embed for the command, with the current time
+    pub fn embed(&self, ctx: CommandContext<'_>) -> CreateEmbed {
+        let bot_name = ctx.cache().current_user().name.clone();
+        self.to_embed(&bot_name, Timestamp::now())
+    }
+
+    /// The reply without sending it, to add components. Mentions never ping users. Guilds in
+    /// a quiet mode only get ephemeral replies.
+    pub async fn reply(&self, ctx: CommandContext<'_>) -> CreateReply {
+        let ephemeral = is_ephemeral(ctx, self.ephemeral).await;
+        let embed = match self.requested_public && ephemeral {
+            true => self.clone().field("Hinweis", QUIET_NOTE, false).embed(ctx),
+            false => self.embed(ctx),
+        };
+        EmbedMode::of_reply(ctx, ephemeral)
+            .await
+            .reply(CreateReply::default(), embed)
+            .ephemeral(ephemeral)
+            .allowed_mentions(CreateAllowedMentions::new().empty_users())
+    }
+
+    /// Sends the response. Poise answers fresh interactions directly and edits the response of
+    /// deferred ones, later responses of a command become follow-ups. The result counts for the
+    /// Discord error rate.
+    pub async fn send<'a>(
+        self,
+        ctx: &'a CommandContext<'a>,
+    ) -> Result<ReplyHandle<'a>, serenity::Error> {
+        let reply = self.reply(*ctx).await;
+        get_error_rates(ctx.serenity_context())
+            .await
+            .track(ErrorSource::Discord, ctx.send(reply).await)
+    }
+}
+
+/// Whether a reply is ephemeral, either as `requested` or because the guild of the command is
+/// in a quiet mode. Replies built without [BotResponse] have to go through this.
+pub async fn is_ephemeral(ctx: CommandContext<'_>, requested: bool) -> bool {
+    if requested {
+        return true;
+    }
+    let quiet = match ctx.guild_id() {
+        Some(guild_id) => {
+            get_guild_settings(ctx.serenity_context())
+                .await
+                .get(guild_id)
+                .quiet
+        }
+        None => QuietLevel::Normal,
+    };
+    quiet.private_responses()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn snapshot(response: &BotResponse) -> Value {
+        let timestamp = Timestamp::from_unix_timestamp(0).unwrap();
+        serde_json::to_value(response.to_embed("Gerbot", timestamp)).unwrap()
+    }
+
+    #[test]
+    fn success_snapshot() {
+        let response = BotResponse::success("Track Found")
+            .description("`Titel` wird jetzt abgespielt")
+            .field("Dauer", "3:12", true)
+            .thumbnail("https://i.ytimg.com/vi/aaaaaaaaaaa/default.jpg")
+            .url("https://www.youtube.com/watch?v=aaaaaaaaaaa")
+            .footer("Seite 1/2");
+        assert_eq!(
+            snapshot(&response),
+            json!({
+                "type": "rich",
+                "title": "Track Found",
+                "description": "`Titel` wird jetzt abgespielt",
+                "url": "https://www.youtube.com/watch?v=aaaaaaaaaaa",
+                "timestamp": "1970-01-01T00:00:00Z",
+                "color": 0x7289DA,
+                "footer": { "text": "Seite 1/2 · Gerbot" },
+                "thumbnail": {
+                    "url": "https://i.ytimg.com/vi/aaaaaaaaaaa/default.jpg",
+                    "proxy_url": null,
+                    "height": null,
+                    "width": null,
+                },
+                "fields": [{ "name": "Dauer", "value": "3:12", "inline": true }],
+            })
+        );
+    }
+
+    #[test]
+    fn error_snapshot() {
+        let response = BotResponse::error("Du bist in keinem Sprachkanal");
+        assert!(response.ephemeral);
+        assert_eq!(
+            snapshot(&response),
+            json!({
+                "type": "rich",
+                "title": "Fehler",
+                "timestamp": "1970-01-01T00:00:00Z",
+                "color": 0xE74C3C,
+                "footer": { "text": "Gerbot" },
+                "fields": [{
+                    "name": "Details",
+                    "value": "Du bist in keinem Sprachkanal",
+                    "inline": false,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn warning_snapshot() {
+        let response = BotResponse::warning("Bestätigung").description("Fortfahren?");
+        assert!(!response.ephemeral);
+        assert_eq!(
+            snapshot(&response),
+            json!({
+                "type": "rich",
+                "title": "Bestätigung",
+                "description": "Fortfahren?",
+                "timestamp": "1970-01-01T00:00:00Z",
+                "color": 0xE74C3C,
+                "footer": { "text": "Gerbot" },
+            })
+        );
+    }
+
+    #[test]
+    fn long_parts_are_cut_to_the_limits() {
+        let long = "ä".repeat(5000);
+        let embed = snapshot(
+            &BotResponse::success(long.as_str())
+                .description(long.as_str())
+                .field(long.as_str(), long.as_str(), false)
+                .footer(long.as_str()),
+        );
+        let chars = |value: &Value| value.as_str().unwrap().chars().count();
+        assert_eq!(chars(&embed["title"]), TITLE_CHARS);
+        assert_eq!(chars(&embed["description"]), DESCRIPTION_CHARS);
+        assert_eq!(chars(&embed["fields"][0]["name"]), FIELD_NAME_CHARS);
+        assert_eq!(chars(&embed["fields"][0]["value"]), FIELD_VALUE_CHARS);
+        assert_eq!(chars(&embed["footer"]["text"]), FOOTER_CHARS);
+    }
+}
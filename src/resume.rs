@@ -0,0 +1,74 @@
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use crate::metadata::{TrackMetadata, TrackSource};
+use serenity::all::GuildId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Resume points are dropped after this time, unless configured otherwise
+pub const DEFAULT_RESUME_TTL: Duration = Duration::from_secs(30 * 60);
+
+/// The queue of a guild when the bot left in the middle of a track
+#[derive(Clone)]
+pub struct ResumePoint {
+    /// Starts with the interrupted track
+    pub tracks: Vec<Arc<TrackMetadata>>,
+    /// Position in the interrupted track
+    pub position: Duration,
+    pub saved_at: Instant,
+}
+
+impl ResumePoint {
+    /// Live streams have no position to return to, and a position at the very end would only
+    /// skip the track
+    pub fn can_seek(&self) -> bool {
+        self.tracks.first().is_some_and(|track| {
+            track.source != TrackSource::Stream && track.duration > self.position
+        })
+    }
+}
+
+/// One resume point per guild, replaced by every leave in the middle of a track and dropped by
+/// every new voice session
+pub struct ResumePoints {
+    ttl: Duration,
+    points: GuildStateMap<ResumePoint>,
+}
+
+impl ResumePoints {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            points: GuildStateMap::default(),
+        }
+    }
+
+    pub fn save(&self, guild_id: GuildId, point: ResumePoint) {
+        self.points.insert(guild_id, point);
+    }
+
+    /// Removes and returns the resume point of the guild if it did not expire yet
+    pub fn take(&self, guild_id: GuildId) -> Option<ResumePoint> {
+        self.points
+            .remove(guild_id)
+            .filter(|point| point.saved_at.elapsed() < self.ttl)
+    }
+
+    pub fn invalidate(&self, guild_id: GuildId) {
+        self.points.remove(guild_id);
+    }
+}
+
+impl GuildScoped for ResumePoints {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::ResumePoints
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.points.entry_counts(|_| 1)
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.points.remove_on_leave(guild_id);
+    }
+}
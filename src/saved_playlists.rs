@@ -0,0 +1,185 @@
+use crate::lifecycle::GuildPersisted;
+use crate::persistence::{PersistedFile, PersistenceHealth, StorageUnavailable};
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Saved playlists per guild
+pub const MAX_SAVED_PER_GUILD: usize = 25;
+
+/// What the channel sees when a playlist is loaded
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, poise::ChoiceParameter,
+)]
+pub enum LoadAnnouncement {
+    /// One message with the number of added tracks
+    #[default]
+    #[name = "Zusammenfassung"]
+    Summary,
+    /// The summary lists every added track
+    #[name = "Jedes Lied"]
+    EachTrack,
+}
+
+impl LoadAnnouncement {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            LoadAnnouncement::Summary => "Zusammenfassung",
+            LoadAnnouncement::EachTrack => "Jedes Lied",
+        }
+    }
+}
+
+/// How a saved playlist is loaded if the command does not say otherwise. Missing options of
+/// older entries default to the behavior of /playlist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaylistOptions {
+    pub shuffle: bool,
+    pub announce: LoadAnnouncement,
+    /// Only the first tracks are loaded
+    pub max_items: Option<u32>,
+}
+
+/// A YouTube playlist saved under a name
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedPlaylist {
+    pub guild_id: GuildId,
+    pub name: String,
+    pub playlist_id: String,
+    #[serde(default)]
+    pub options: PlaylistOptions,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SaveOutcome {
+    Created,
+    Replaced,
+    LimitReached,
+}
+
+/// Playlists saved with `/playlists save`, written to a file if one is configured
+pub struct SavedPlaylistStore {
+    playlists: Mutex<Vec<SavedPlaylist>>,
+    file: PersistedFile,
+}
+
+impl SavedPlaylistStore {
+    /// Loads the saved playlists from the file, starts empty if it does not exist yet or can not be read
+    pub fn load(file: Option<PathBuf>, health: Arc<PersistenceHealth>) -> Self {
+        let file = PersistedFile::new("saved playlists", file, health);
+        Self {
+            playlists: Mutex::new(file.load()),
+            file,
+        }
+    }
+
+    /// Replaces the playlists with the content of the file, for edits made while the bot runs
+    pub fn reload(&self) -> usize {
+        let playlists = self.file.load();
+        let count = playlists.len();
+        *self.playlists.lock().unwrap() = playlists;
+        count
+    }
+
+    /// Changes are refused while the file could not be loaded, they would overwrite it
+    pub fn check_available(&self) -> Result<(), StorageUnavailable> {
+        self.file.check_available()
+    }
+
+    /// Names are compared case insensitively
+    pub fn get(&self, guild_id: GuildId, name: &str) -> Option<SavedPlaylist> {
+        self.playlists
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|p| p.guild_id == guild_id && p.name.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    /// Names of the saved playlists of a guild, sorted
+    pub fn names(&self, guild_id: GuildId) -> Vec<String> {
+        let mut names = self
+            .playlists
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|p| p.guild_id == guild_id)
+            .map(|p| p.name.clone())
+            .collect::<Vec<_>>();
+        names.sort_unstable_by_key(|name| name.to_lowercase());
+        names
+    }
+
+    /// The saved playlists of a guild in the order they were first saved
+    pub fn all(&self, guild_id: GuildId) -> Vec<SavedPlaylist> {
+        self.playlists
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|p| p.guild_id == guild_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Replaces all saved playlists of a guild, for /settings import. The caller keeps them
+    /// within [MAX_SAVED_PER_GUILD].
+    pub fn replace(&self, guild_id: GuildId, replacement: Vec<SavedPlaylist>) {
+        let mut playlists = self.playlists.lock().unwrap();
+        playlists.retain(|p| p.guild_id != guild_id);
+        playlists.extend(replacement);
+        self.persist(&playlists);
+    }
+
+    /// Saves a playlist, replacing one with the same name
+    pub fn save(&self, playlist: SavedPlaylist) -> SaveOutcome {
+        let mut playlists = self.playlists.lock().unwrap();
+        let existing = playlists.iter().position(|p| {
+            p.guild_id == playlist.guild_id && p.name.eq_ignore_ascii_case(&playlist.name)
+        });
+        let outcome = match existing {
+            Some(index) => {
+                playlists[index] = playlist;
+                SaveOutcome::Replaced
+            }
+            None => {
+                let saved = playlists
+                    .iter()
+                    .filter(|p| p.guild_id == playlist.guild_id)
+                    .count();
+                if saved >= MAX_SAVED_PER_GUILD {
+                    return SaveOutcome::LimitReached;
+                }
+                playlists.push(playlist);
+                SaveOutcome::Created
+            }
+        };
+        self.persist(&playlists);
+        outcome
+    }
+
+    /// Returns whether a playlist with the name existed
+    pub fn delete(&self, guild_id: GuildId, name: &str) -> bool {
+        let mut playlists = self.playlists.lock().unwrap();
+        let before = playlists.len();
+        playlists.retain(|p| !(p.guild_id == guild_id && p.name.eq_ignore_ascii_case(name)));
+        let deleted = playlists.len() != before;
+        if deleted {
+            self.persist(&playlists);
+        }
+        deleted
+    }
+
+    fn persist(&self, playlists: &[SavedPlaylist]) {
+        self.file.save(playlists);
+    }
+}
+
+impl GuildPersisted for SavedPlaylistStore {
+    fn purge(&self, guild_id: GuildId) {
+        let mut playlists = self.playlists.lock().unwrap();
+        playlists.retain(|p| p.guild_id != guild_id);
+        self.persist(&playlists);
+    }
+}
@@ -0,0 +1,426 @@
+use crate::audit_log::EnqueueOrigin;
+use crate::commands::util::{
+    enqueue_track_for, get_guild_settings, get_outbound, get_playback_events, get_youtube_client,
+    get_yt_id_from_url, join_voice,
+};
+use crate::lifecycle::GuildPersisted;
+use crate::persistence::{PersistedFile, PersistenceHealth, StorageUnavailable};
+use crate::voice_state::listener_count;
+use crate::{CommandError, ERROR_COLOUR, SUCCESS_COLOUR};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, Context, CreateEmbed, CreateMessage, GuildId, Mentionable, UserId};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset, Weekday};
+use tokio::sync::Notify;
+use tokio::time::sleep;
+
+/// The scheduler wakes up at least this often, so changes of the system clock are noticed
+const MAX_SLEEP: std::time::Duration = std::time::Duration::from_secs(60);
+/// Jobs that were due longer ago than this, e.g. while the bot was offline, are not played late
+const MISSED_GRACE: time::Duration = time::Duration::minutes(10);
+/// Upper bound for the tracks of a scheduled playlist
+const MAX_SCHEDULED_TRACKS: usize = 100;
+
+/// Playback that starts by itself at a set time
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduledJob {
+    pub id: u32,
+    pub guild_id: GuildId,
+    pub voice_channel: ChannelId,
+    /// Channel the job was created in, for notices when it runs. Unset if the bot could not post
+    /// there.
+    #[serde(default)]
+    pub notice_channel: Option<ChannelId>,
+    pub source: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub next_run: OffsetDateTime,
+    /// Repeats every 7 days after each run
+    pub weekly: bool,
+    pub created_by: UserId,
+}
+
+/// Scheduled jobs of all guilds. They are written to a file if one is configured, so they
+/// survive restarts.
+pub struct ScheduleStore {
+    jobs: Mutex<Vec<ScheduledJob>>,
+    file: PersistedFile,
+    changed: Notify,
+}
+
+impl ScheduleStore {
+    /// Loads the jobs from the file, starts empty if it does not exist yet or can not be read
+    pub fn load(file: Option<PathBuf>, health: Arc<PersistenceHealth>) -> Self {
+        let file = PersistedFile::new("scheduled jobs", file, health).pretty();
+        Self {
+            jobs: Mutex::new(file.load()),
+            file,
+            changed: Notify::new(),
+        }
+    }
+
+    /// Replaces the jobs with the content of the file, for edits made while the bot runs
+    pub fn reload(&self) -> usize {
+        let jobs = self.file.load();
+        let count = jobs.len();
+        *self.jobs.lock().unwrap() = jobs;
+        self.changed.notify_one();
+        count
+    }
+
+    /// Changes are refused while the file could not be loaded, they would overwrite it
+    pub fn check_available(&self) -> Result<(), StorageUnavailable> {
+        self.file.check_available()
+    }
+
+    /// Adds a job and returns its id
+    pub fn add(&self, mut job: ScheduledJob) -> u32 {
+        let mut jobs = self.jobs.lock().unwrap();
+        job.id = jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1;
+        let id = job.id;
+        jobs.push(job);
+        self.persist(&jobs);
+        drop(jobs);
+
+        self.changed.notify_one();
+        id
+    }
+
+    /// Jobs of the guild, the next one first
+    pub fn list(&self, guild_id: GuildId) -> Vec<ScheduledJob> {
+        let mut jobs = self
+            .jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|j| j.guild_id == guild_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        jobs.sort_by_key(|j| j.next_run);
+        jobs
+    }
+
+    pub fn remove(&self, guild_id: GuildId, id: u32) -> Option<ScheduledJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let index = jobs
+            .iter()
+            .position(|j| j.guild_id == guild_id && j.id == id)?;
+        let job = jobs.remove(index);
+        self.persist(&jobs);
+        Some(job)
+    }
+
+    fn next_run(&self) -> Option<OffsetDateTime> {
+        self.jobs.lock().unwrap().iter().map(|j| j.next_run).min()
+    }
+
+    /// Removes due one-shot jobs and moves due weekly jobs to their next run. Returns the due jobs
+    /// that are still recent enough to be played.
+    fn take_due(&self, now: OffsetDateTime) -> Vec<ScheduledJob> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let mut due = Vec::new();
+        let mut changed = false;
+
+        jobs.retain_mut(|job| {
+            if job.next_run > now {
+                return true;
+            }
+            changed = true;
+            if now - job.next_run <= MISSED_GRACE {
+                due.push(job.clone());
+            } else {
+                warn!(
+                    "Missed scheduled job {} in guild {} that was due at {}",
+                    job.id, job.guild_id, job.next_run
+                );
+            }
+
+            if !job.weekly {
+                return false;
+            }
+            while job.next_run <= now {
+                job.next_run += time::Duration::weeks(1);
+            }
+            true
+        });
+
+        if changed {
+            self.persist(&jobs);
+        }
+        due
+    }
+
+    fn persist(&self, jobs: &[ScheduledJob]) {
+        self.file.save(jobs);
+    }
+}
+
+impl GuildPersisted for ScheduleStore {
+    fn purge(&self, guild_id: GuildId) {
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.retain(|j| j.guild_id != guild_id);
+        self.persist(&jobs);
+    }
+}
+
+/// Parses a fixed offset like `+02:00`, `UTC+2` or `-5`. Daylight saving time is not
+/// considered, the offset has to be changed with the season.
+pub fn parse_utc_offset(input: &str) -> Option<UtcOffset> {
+    let input = input.trim();
+    let input = input
+        .strip_prefix("UTC")
+        .or_else(|| input.strip_prefix("GMT"))
+        .unwrap_or(input);
+    if input.is_empty() {
+        return Some(UtcOffset::UTC);
+    }
+
+    let (sign, rest) = match (input.strip_prefix('+'), input.strip_prefix('-')) {
+        (Some(rest), _) => (1, rest),
+        (_, Some(rest)) => (-1, rest),
+        _ => (1, input),
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((hours, minutes)) => (hours.parse::<i8>().ok()?, minutes.parse::<i8>().ok()?),
+        None => (rest.parse::<i8>().ok()?, 0),
+    };
+    if !(0..=14).contains(&hours) || !(0..60).contains(&minutes) {
+        return None;
+    }
+    UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()
+}
+
+/// Parses `HH:MM`, `<weekday> HH:MM`, `DD.MM.YYYY HH:MM` or `YYYY-MM-DD HH:MM` in the given
+/// offset. Times without a date are the next time they occur. Returns None for invalid or past
+/// times.
+pub fn parse_when(input: &str, offset: UtcOffset, now: OffsetDateTime) -> Option<OffsetDateTime> {
+    let now = now.to_offset(offset);
+    let tokens = input.split_whitespace().collect::<Vec<&str>>();
+    let (day, time) = match tokens.as_slice() {
+        [time] => (None, *time),
+        [day, time] => (Some(*day), *time),
+        _ => return None,
+    };
+
+    let (hour, minute) = time.split_once(':')?;
+    let time = Time::from_hms(hour.parse().ok()?, minute.parse().ok()?, 0).ok()?;
+    let at = |date: Date| PrimitiveDateTime::new(date, time).assume_offset(offset);
+
+    let date = match day {
+        None if at(now.date()) > now => now.date(),
+        None => now.date().next_day()?,
+        Some(day) => match parse_weekday(day) {
+            Some(weekday) => {
+                let mut date = now.date();
+                while date.weekday() != weekday || at(date) <= now {
+                    date = date.next_day()?;
+                }
+                date
+            }
+            None => parse_date(day)?,
+        },
+    };
+
+    Some(at(date)).filter(|at| *at > now)
+}
+
+fn parse_weekday(input: &str) -> Option<Weekday> {
+    let input = input.to_lowercase();
+    let weekday = match input.trim_end_matches('.') {
+        "mo" | "montag" | "mon" | "monday" => Weekday::Monday,
+        "di" | "dienstag" | "tue" | "tuesday" => Weekday::Tuesday,
+        "mi" | "mittwoch" | "wed" | "wednesday" => Weekday::Wednesday,
+        "do" | "donnerstag" | "thu" | "thursday" => Weekday::Thursday,
+        "fr" | "freitag" | "fri" | "friday" => Weekday::Friday,
+        "sa" | "samstag" | "sat" | "saturday" => Weekday::Saturday,
+        "so" | "sonntag" | "sun" | "sunday" => Weekday::Sunday,
+        _ => return None,
+    };
+    Some(weekday)
+}
+
+fn parse_date(input: &str) -> Option<Date> {
+    let (year, month, day) = if let Some((day, rest)) = input.split_once('.') {
+        let (month, year) = rest.split_once('.')?;
+        (year, month, day)
+    } else {
+        let mut parts = input.splitn(3, '-');
+        (parts.next()?, parts.next()?, parts.next()?)
+    };
+
+    Date::from_calendar_date(
+        year.parse().ok()?,
+        Month::try_from(month.parse::<u8>().ok()?).ok()?,
+        day.parse().ok()?,
+    )
+    .ok()
+}
+
+/// Starts due jobs until the process exits
+pub async fn run_scheduler(ctx: Context, store: Arc<ScheduleStore>) {
+    loop {
+        let wait = match store.next_run() {
+            Some(at) => std::time::Duration::try_from(at - OffsetDateTime::now_utc())
+                .unwrap_or_default()
+                .min(MAX_SLEEP),
+            None => MAX_SLEEP,
+        };
+        tokio::select! {
+            _ = sleep(wait) => {}
+            // A new job may be due earlier
+            _ = store.changed.notified() => continue,
+        }
+
+        for job in store.take_due(OffsetDateTime::now_utc()) {
+            tokio::spawn(run_job(ctx.clone(), job));
+        }
+    }
+}
+
+/// Joins the channel of the job and enqueues its source, if anybody is there to listen
+async fn run_job(ctx: Context, job: ScheduledJob) {
+    let outbound = get_outbound(&ctx).await;
+    let notice_channel = get_guild_settings(&ctx)
+        .await
+        .get(job.guild_id)
+        .announce_channel
+        .or(job.notice_channel);
+    // Only the announcement of the started playback shows playback state
+    let notice = |colour, sequence: Option<u64>, details: String| {
+        let Some(notice_channel) = notice_channel else {
+            return;
+        };
+        let embed = CreateEmbed::new()
+            .title("Geplante Wiedergabe")
+            .colour(colour)
+            .description(details);
+        outbound.post(
+            &ctx.http,
+            job.guild_id,
+            notice_channel,
+            sequence,
+            CreateMessage::new().embed(embed),
+        );
+    };
+
+    let listeners = ctx
+        .cache
+        .guild(job.guild_id)
+        .map(|guild| listener_count(&guild, job.voice_channel))
+        .unwrap_or_default();
+    if listeners == 0 {
+        info!(
+            "Skipped scheduled job {} in guild {}, nobody is in the channel",
+            job.id, job.guild_id
+        );
+        notice(
+            ERROR_COLOUR,
+            None,
+            format!(
+                "`{}` wurde übersprungen, weil niemand in {} war",
+                job.source,
+                job.voice_channel.mention()
+            ),
+        );
+        return;
+    }
+
+    let Some(songbird) = songbird::get(&ctx).await else {
+        error!("Songbird instance could not be retrieved for a scheduled job");
+        return;
+    };
+    let call = match join_voice(&ctx, songbird, job.guild_id, job.voice_channel).await {
+        Ok(call) => call,
+        Err(e) => {
+            warn!(
+                "Scheduled job {} in guild {} could not join: {e}",
+                job.id, job.guild_id
+            );
+            notice(
+                ERROR_COLOUR,
+                None,
+                format!(
+                    "`{}` konnte nicht gestartet werden, weil der Bot {} nicht beitreten konnte",
+                    job.source,
+                    job.voice_channel.mention()
+                ),
+            );
+            return;
+        }
+    };
+
+    // Playlists are expanded like with /playlist, everything else is a single track
+    let ids = get_yt_id_from_url(&job.source);
+    let sources = match (ids.playlist_id, ids.video_id) {
+        (Some(playlist_id), None) => {
+            match get_youtube_client(&ctx)
+                .await
+                .get_playlist(&playlist_id, Some(MAX_SCHEDULED_TRACKS))
+                .await
+            {
+                Ok(playlist) => playlist
+                    .videos
+                    .iter()
+                    .take(MAX_SCHEDULED_TRACKS)
+                    .map(|video| video.get_yt_url().to_string())
+                    .collect(),
+                Err(e) => {
+                    warn!(
+                        "Failed to load the playlist of scheduled job {}: {e}",
+                        job.id
+                    );
+                    vec![]
+                }
+            }
+        }
+        _ => vec![job.source.clone()],
+    };
+
+    let mut enqueued = 0;
+    for source in &sources {
+        match enqueue_track_for(
+            &ctx,
+            job.guild_id,
+            call.clone(),
+            source,
+            job.created_by,
+            EnqueueOrigin::Scheduled,
+        )
+        .await
+        {
+            Ok(_) => enqueued += 1,
+            Err(e @ CommandError::QueueFull { .. }) => {
+                warn!("Stopped scheduled job {} early: {e}", job.id);
+                break;
+            }
+            Err(e) => warn!(
+                "Failed to enqueue {source} for scheduled job {}: {e}",
+                job.id
+            ),
+        }
+    }
+
+    if enqueued == 0 {
+        notice(
+            ERROR_COLOUR,
+            None,
+            format!("`{}` konnte nicht geladen werden", job.source),
+        );
+        return;
+    }
+    info!(
+        "Started scheduled job {} in guild {} with {enqueued} tracks",
+        job.id, job.guild_id
+    );
+    let sequence = get_playback_events(&ctx).await.sequence();
+    notice(
+        SUCCESS_COLOUR,
+        Some(sequence),
+        format!(
+            "`{}` wird jetzt in {} abgespielt ({enqueued} Titel)",
+            job.source,
+            job.voice_channel.mention()
+        ),
+    );
+}
@@ -43,33 +43,71 @@ pub mod iso_duration {
         }
 
         fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
-            let string = match v.split_once("PT") {
-                Some(v) => v.1,
+            // Live streams and premieres have `P0D`, videos over a day `P1DT2H3M4S`
+            let string = match v.strip_prefix('P') {
+                Some(v) => v,
                 None => {
                     return Err(de::Error::custom(
-                        "no duration specified (does not contain 'PT')",
+                        "no duration specified (does not start with 'P')",
                     ))
                 }
             };
-            let mut duration = Duration::default();
-            let mut val = 0;
+            // The input comes from outside, so every step is checked instead of trusting the format
+            let too_long = || de::Error::custom("duration does not fit into 64 bits of seconds");
+            let mut secs: u64 = 0;
+            let mut val: Option<u64> = None;
+            let mut in_time = false;
+            // Fractions of seconds are parsed, but dropped
+            let mut in_fraction = false;
 
             for c in string.chars() {
-                if c.is_ascii_digit() {
-                    val = val * 10 + c.to_digit(10).unwrap();
-                } else if c == 'H' {
-                    duration += Duration::from_secs((3600 * val) as u64);
-                    val = 0;
-                } else if c == 'M' {
-                    duration += Duration::from_secs((60 * val) as u64);
-                    val = 0;
-                } else if c == 'S' {
-                    duration += Duration::from_secs(val as u64);
-                    val = 0;
+                if let Some(digit) = c.to_digit(10) {
+                    if !in_fraction {
+                        val = Some(
+                            val.unwrap_or(0)
+                                .checked_mul(10)
+                                .and_then(|v| v.checked_add(u64::from(digit)))
+                                .ok_or_else(too_long)?,
+                        );
+                    }
+                    continue;
                 }
+                let unit_secs = match c {
+                    'T' if !in_time && val.is_none() => {
+                        in_time = true;
+                        continue;
+                    }
+                    '.' | ',' if in_time && val.is_some() && !in_fraction => {
+                        in_fraction = true;
+                        continue;
+                    }
+                    'M' if !in_time => {
+                        return Err(de::Error::custom("durations in months are not supported"))
+                    }
+                    'W' if !in_time => 7 * 86400,
+                    'D' if !in_time => 86400,
+                    'H' if in_time => 3600,
+                    'M' if in_time => 60,
+                    'S' if in_time => 1,
+                    _ => return Err(de::Error::custom(format!("unexpected `{c}` in duration"))),
+                };
+                if in_fraction && unit_secs != 1 {
+                    return Err(de::Error::custom("only seconds can have a fraction"));
+                }
+                let value = val
+                    .take()
+                    .ok_or_else(|| de::Error::custom(format!("`{c}` without a number")))?;
+                secs = value
+                    .checked_mul(unit_secs)
+                    .and_then(|v| secs.checked_add(v))
+                    .ok_or_else(too_long)?;
+                in_fraction = false;
+            }
+            if val.is_some() {
+                return Err(de::Error::custom("number without a unit at the end"));
             }
 
-            Ok(duration)
+            Ok(Duration::from_secs(secs))
         }
     }
 }
@@ -108,3 +146,97 @@ pub mod bool_string {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use serde_json::Value;
+    use std::time::Duration;
+
+    fn parse(duration: &str) -> Result<Duration, serde_json::Error> {
+        super::iso_duration::deserialize(Value::from(duration))
+    }
+
+    #[test]
+    fn youtube_durations() {
+        let table = [
+            ("PT3M33S", 213),
+            ("PT1H", 3600),
+            ("PT10S", 10),
+            // Live streams and premieres
+            ("P0D", 0),
+            ("PT0S", 0),
+            // Over a day
+            ("P1DT2H", 26 * 3600),
+            ("P1DT2H3M4S", 26 * 3600 + 184),
+            ("P2W", 14 * 86400),
+            // The fraction is dropped
+            ("PT1.5S", 1),
+            ("PT2,75S", 2),
+        ];
+        for (duration, secs) in table {
+            assert_eq!(
+                parse(duration).unwrap(),
+                Duration::from_secs(secs),
+                "{duration}"
+            );
+        }
+    }
+
+    #[test]
+    fn overflowing_durations_are_errors() {
+        for duration in [
+            "PT99999999999999999999S",
+            "PT18446744073709551615H",
+            "P9999999999999999W",
+            "PT18446744073709551615S1S",
+        ] {
+            assert!(parse(duration).is_err(), "{duration}");
+        }
+    }
+
+    #[test]
+    fn malformed_durations_are_errors() {
+        for duration in [
+            "", "3M", "PT5", "P1M", "PTM", "P1H", "PT1D", "PT1.5M", "PT.5S", "PT1..5S", "PT1S2",
+            "PT-1S", "PT1 S",
+        ] {
+            assert!(parse(duration).is_err(), "{duration}");
+        }
+        // Designators without any parts are simply zero
+        for duration in ["P", "PT", "P1DT"] {
+            assert!(parse(duration).is_ok(), "{duration}");
+        }
+    }
+
+    #[test]
+    fn generated_durations_are_read_back() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..2000 {
+            let (days, hours, minutes, secs) = (
+                rng.gen_range(0..400u64),
+                rng.gen_range(0..24u64),
+                rng.gen_range(0..60u64),
+                rng.gen_range(0..60u64),
+            );
+            let duration = format!("P{days}DT{hours}H{minutes}M{secs}S");
+            let expected = ((days * 24 + hours) * 60 + minutes) * 60 + secs;
+            assert_eq!(parse(&duration).unwrap().as_secs(), expected, "{duration}");
+        }
+    }
+
+    #[test]
+    fn generated_garbage_never_panics() {
+        let alphabet = [
+            'P', 'T', 'W', 'D', 'H', 'M', 'S', '.', ',', '9', '0', '1', '-', 'ü',
+        ];
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..20_000 {
+            let duration = (0..rng.gen_range(0..30))
+                .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+                .collect::<String>();
+            _ = parse(&duration);
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use crate::alias::{AliasSet, CommandAlias};
+use crate::blocklist::BlockedEntry;
+use crate::canonical_url::canonical_url;
+use crate::guild_settings::{GuildSettings, QuietLevel};
+use crate::locale::Locale;
+use crate::saved_playlists::{PlaylistOptions, SavedPlaylist, MAX_SAVED_PER_GUILD};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use serenity::all::{ChannelId, GuildId, RoleId};
+use std::time::Duration;
+use thiserror::Error;
+use time::UtcOffset;
+
+/// Raised with every change that older versions would misread. Settings added later do not need
+/// a new schema, missing ones get their default.
+pub const SETTINGS_SCHEMA: u64 = 1;
+
+/// Everything /settings export writes and /settings import replaces
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuildConfig {
+    pub settings: GuildSettings,
+    pub playlists: Vec<SavedPlaylist>,
+    pub blocklist: Vec<BlockedEntry>,
+}
+
+#[derive(Debug, Error)]
+pub enum TransferError {
+    #[error("Die Datei ist kein Export von /settings export")]
+    NotAnExport,
+    #[error("Die Datei hat Schema {found}, unterstützt wird Schema {SETTINGS_SCHEMA}")]
+    UnsupportedSchema { found: u64 },
+    #[error("Die Datei ist ungültig: {0}")]
+    Invalid(String),
+}
+
+/// The file written by /settings export
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SettingsExport {
+    schema: u64,
+    settings: ExportedSettings,
+    playlists: Vec<ExportedPlaylist>,
+    blocklist: Vec<BlockedEntry>,
+}
+
+/// [GuildSettings] with durations in seconds and aliases by name, also how the settings file
+/// stores them
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ExportedSettings {
+    share_button: bool,
+    locale: Option<Locale>,
+    /// Seconds east of UTC
+    utc_offset: Option<i32>,
+    announce_channel: Option<ChannelId>,
+    dj_role: Option<RoleId>,
+    max_track_duration: Option<u64>,
+    soft_duration_limit: bool,
+    duration_hard_cap: Option<u64>,
+    always_on_channel: Option<ChannelId>,
+    deafened_pause_delay: Option<u64>,
+    aliases: Vec<String>,
+    tts_announcements: bool,
+    quiet: QuietLevel,
+}
+
+/// Name and source of a saved playlist, the tracks are loaded again from YouTube
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExportedPlaylist {
+    name: String,
+    playlist_id: String,
+    #[serde(default)]
+    options: PlaylistOptions,
+}
+
+impl Default for ExportedSettings {
+    fn default() -> Self {
+        Self::from(&GuildSettings::default())
+    }
+}
+
+impl From<&GuildSettings> for ExportedSettings {
+    fn from(settings: &GuildSettings) -> Self {
+        Self {
+            share_button: settings.share_button,
+            locale: settings.locale,
+            utc_offset: settings.utc_offset.map(UtcOffset::whole_seconds),
+            announce_channel: settings.announce_channel,
+            dj_role: settings.dj_role,
+            max_track_duration: settings.max_track_duration.map(|d| d.as_secs()),
+            soft_duration_limit: settings.soft_duration_limit,
+            duration_hard_cap: settings.duration_hard_cap.map(|d| d.as_secs()),
+            always_on_channel: settings.always_on_channel,
+            deafened_pause_delay: settings.deafened_pause_delay.map(|d| d.as_secs()),
+            aliases: settings
+                .aliases
+                .iter()
+                .map(|alias| alias.name().to_owned())
+                .collect(),
+            tts_announcements: settings.tts_announcements,
+            quiet: settings.quiet,
+        }
+    }
+}
+
+impl ExportedSettings {
+    pub(crate) fn into_settings(self) -> Result<GuildSettings, TransferError> {
+        let utc_offset = self
+            .utc_offset
+            .map(|secs| {
+                UtcOffset::from_whole_seconds(secs)
+                    .map_err(|_| TransferError::Invalid(format!("`utc_offset` {secs} ist zu groß")))
+            })
+            .transpose()?;
+        let mut aliases = AliasSet::default();
+        for name in &self.aliases {
+            let alias = CommandAlias::from_name(name)
+                .ok_or_else(|| TransferError::Invalid(format!("`/{name}` ist keine Kurzform")))?;
+            aliases.set(alias, true);
+        }
+
+        Ok(GuildSettings {
+            share_button: self.share_button,
+            locale: self.locale,
+            utc_offset,
+            announce_channel: self.announce_channel,
+            dj_role: self.dj_role,
+            max_track_duration: self.max_track_duration.map(Duration::from_secs),
+            soft_duration_limit: self.soft_duration_limit,
+            duration_hard_cap: self.duration_hard_cap.map(Duration::from_secs),
+            always_on_channel: self.always_on_channel,
+            deafened_pause_delay: self.deafened_pause_delay.map(Duration::from_secs),
+            aliases,
+            tts_announcements: self.tts_announcements,
+            quiet: self.quiet,
+        })
+    }
+}
+
+impl SettingsExport {
+    pub fn new(config: &GuildConfig) -> Self {
+        Self {
+            schema: SETTINGS_SCHEMA,
+            settings: ExportedSettings::from(&config.settings),
+            playlists: config
+                .playlists
+                .iter()
+                .map(|playlist| ExportedPlaylist {
+                    name: playlist.name.clone(),
+                    playlist_id: playlist.playlist_id.clone(),
+                    options: playlist.options,
+                })
+                .collect(),
+            blocklist: config.blocklist.clone(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Exports only contain serializable values")
+    }
+
+    /// The schema is checked before the rest, so a newer file gets a clearer error than a
+    /// missing field
+    pub fn parse(content: &str) -> Result<Self, TransferError> {
+        let value = serde_json::from_str::<Value>(content.trim_start_matches('\u{feff}'))
+            .map_err(|_| TransferError::NotAnExport)?;
+        match value.get("schema").map(Value::as_u64) {
+            Some(Some(SETTINGS_SCHEMA)) => {}
+            Some(Some(found)) => return Err(TransferError::UnsupportedSchema { found }),
+            _ => return Err(TransferError::NotAnExport),
+        }
+        serde_json::from_value(value).map_err(|e| TransferError::Invalid(e.to_string()))
+    }
+
+    /// The configuration for `guild_id`, with ids of channels and roles that do not exist there
+    /// cleared. Returns a notice for every cleared id.
+    pub fn into_config(
+        self,
+        guild_id: GuildId,
+        channel_exists: impl Fn(ChannelId) -> bool,
+        role_exists: impl Fn(RoleId) -> bool,
+    ) -> Result<(GuildConfig, Vec<String>), TransferError> {
+        let mut settings = self.settings.into_settings()?;
+        let mut notices = vec![];
+        if settings
+            .announce_channel
+            .is_some_and(|c| !channel_exists(c))
+        {
+            settings.announce_channel = None;
+            notices
+                .push("Der Ankündigungskanal existiert hier nicht und wurde entfernt".to_owned());
+        }
+        if settings
+            .always_on_channel
+            .is_some_and(|c| !channel_exists(c))
+        {
+            settings.always_on_channel = None;
+            notices.push("Der 24/7-Kanal existiert hier nicht und wurde entfernt".to_owned());
+        }
+        if settings.dj_role.is_some_and(|r| !role_exists(r)) {
+            settings.dj_role = None;
+            notices.push("Die DJ-Rolle existiert hier nicht und wurde entfernt".to_owned());
+        }
+
+        if self.playlists.len() > MAX_SAVED_PER_GUILD {
+            return Err(TransferError::Invalid(format!(
+                "mehr als {MAX_SAVED_PER_GUILD} Playlists"
+            )));
+        }
+        let mut playlists: Vec<SavedPlaylist> = vec![];
+        for playlist in self.playlists {
+            let name = playlist.name.trim();
+            if name.is_empty() || playlist.playlist_id.is_empty() {
+                return Err(TransferError::Invalid(
+                    "eine Playlist ohne Name oder Id".to_owned(),
+                ));
+            }
+            if playlists.iter().any(|p| p.name.eq_ignore_ascii_case(name)) {
+                return Err(TransferError::Invalid(format!(
+                    "die Playlist `{name}` ist doppelt"
+                )));
+            }
+            playlists.push(SavedPlaylist {
+                guild_id,
+                name: name.to_owned(),
+                playlist_id: playlist.playlist_id,
+                options: playlist.options,
+            });
+        }
+
+        let mut blocklist: Vec<BlockedEntry> = vec![];
+        for entry in self.blocklist {
+            // Edited files may contain links in any form, the blocklist compares canonical ones
+            let entry = match entry {
+                BlockedEntry::Url(url) => match Url::parse(&url) {
+                    Ok(url) => BlockedEntry::Url(canonical_url(&url)),
+                    Err(_) => return Err(TransferError::Invalid(format!("`{url}` ist kein Link"))),
+                },
+                channel => channel,
+            };
+            if !blocklist.contains(&entry) {
+                blocklist.push(entry);
+            }
+        }
+
+        Ok((
+            GuildConfig {
+                settings,
+                playlists,
+                blocklist,
+            },
+            notices,
+        ))
+    }
+}
+
+impl GuildConfig {
+    /// One line per kind of change from `self` to `new`, empty if nothing would change
+    pub fn changes(&self, new: &GuildConfig) -> Vec<String> {
+        let mut changes = vec![];
+        let (a, b) = (&self.settings, &new.settings);
+        let changed_settings = [
+            ("Teilen-Knopf", a.share_button != b.share_button),
+            ("Sprache", a.locale != b.locale),
+            ("Zeitverschiebung", a.utc_offset != b.utc_offset),
+            (
+                "Ankündigungskanal",
+                a.announce_channel != b.announce_channel,
+            ),
+            ("DJ-Rolle", a.dj_role != b.dj_role),
+            (
+                "Maximale Länge",
+                a.max_track_duration != b.max_track_duration
+                    || a.soft_duration_limit != b.soft_duration_limit
+                    || a.duration_hard_cap != b.duration_hard_cap,
+            ),
+            ("24/7-Kanal", a.always_on_channel != b.always_on_channel),
+            (
+                "Pause bei Taubheit",
+                a.deafened_pause_delay != b.deafened_pause_delay,
+            ),
+            ("Kurzformen", a.aliases != b.aliases),
+            ("Ansagen", a.tts_announcements != b.tts_announcements),
+            ("Ruhemodus", a.quiet != b.quiet),
+        ]
+        .into_iter()
+        .filter(|(_, changed)| *changed)
+        .map(|(name, _)| name)
+        .collect::<Vec<_>>();
+        if !changed_settings.is_empty() {
+            changes.push(format!("Einstellungen: {}", changed_settings.join(", ")));
+        }
+
+        let find = |playlists: &[SavedPlaylist], name: &str| {
+            playlists
+                .iter()
+                .find(|p| p.name.eq_ignore_ascii_case(name))
+                .cloned()
+        };
+        let mut added = vec![];
+        let mut replaced = vec![];
+        for playlist in &new.playlists {
+            match find(&self.playlists, &playlist.name) {
+                None => added.push(playlist.name.as_str()),
+                Some(old) if old != *playlist => replaced.push(playlist.name.as_str()),
+                Some(_) => {}
+            }
+        }
+        let removed = self
+            .playlists
+            .iter()
+            .filter(|p| find(&new.playlists, &p.name).is_none())
+            .map(|p| p.name.as_str())
+            .collect::<Vec<_>>();
+        for (kind, names) in [
+            ("Neue Playlists", added),
+            ("Ersetzte Playlists", replaced),
+            ("Gelöschte Playlists", removed),
+        ] {
+            if !names.is_empty() {
+                changes.push(format!("{kind}: {}", names.join(", ")));
+            }
+        }
+
+        let blocked = new
+            .blocklist
+            .iter()
+            .filter(|entry| !self.blocklist.contains(entry))
+            .count();
+        let unblocked = self
+            .blocklist
+            .iter()
+            .filter(|entry| !new.blocklist.contains(entry))
+            .count();
+        if blocked + unblocked > 0 {
+            changes.push(format!(
+                "Sperrliste: {blocked} neue, {unblocked} entfernte Einträge"
+            ));
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::saved_playlists::LoadAnnouncement;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const ANNOUNCE: ChannelId = ChannelId::new(10);
+    const ALWAYS_ON: ChannelId = ChannelId::new(11);
+    const DJ: RoleId = RoleId::new(20);
+
+    fn config() -> GuildConfig {
+        let mut aliases = AliasSet::default();
+        aliases.set(CommandAlias::P, true);
+        aliases.set(CommandAlias::Q, true);
+        let settings = GuildSettings {
+            share_button: true,
+            locale: Some(Locale::English),
+            utc_offset: Some(UtcOffset::from_hms(2, 0, 0).unwrap()),
+            announce_channel: Some(ANNOUNCE),
+            dj_role: Some(DJ),
+            max_track_duration: Some(Duration::from_secs(600)),
+            soft_duration_limit: true,
+            duration_hard_cap: Some(Duration::from_secs(3600)),
+            always_on_channel: Some(ALWAYS_ON),
+            deafened_pause_delay: Some(Duration::from_secs(30)),
+            aliases,
+            tts_announcements: true,
+            quiet: QuietLevel::Quiet,
+        };
+        let youtube = Url::parse("https://www.youtube.com/watch?v=dQw4w9WgXcQ").unwrap();
+        GuildConfig {
+            settings,
+            playlists: vec![SavedPlaylist {
+                guild_id: GUILD,
+                name: "Abend".to_owned(),
+                playlist_id: "PL0123456789".to_owned(),
+                options: PlaylistOptions {
+                    shuffle: true,
+                    announce: LoadAnnouncement::EachTrack,
+                    max_items: Some(50),
+                },
+            }],
+            blocklist: vec![
+                BlockedEntry::Url(canonical_url(&youtube)),
+                BlockedEntry::Channel("Spam".to_owned()),
+            ],
+        }
+    }
+
+    #[test]
+    fn exports_are_imported_unchanged() {
+        let config = config();
+        let json = SettingsExport::new(&config).to_json();
+
+        let (imported, notices) = SettingsExport::parse(&json)
+            .unwrap()
+            .into_config(GUILD, |_| true, |_| true)
+            .unwrap();
+        assert!(notices.is_empty());
+        assert!(config.changes(&imported).is_empty());
+        assert_eq!(imported, config);
+    }
+
+    #[test]
+    fn missing_channels_and_roles_are_cleared() {
+        let json = SettingsExport::new(&config()).to_json();
+
+        let (imported, notices) = SettingsExport::parse(&json)
+            .unwrap()
+            .into_config(GUILD, |_| false, |_| false)
+            .unwrap();
+        assert_eq!(imported.settings.announce_channel, None);
+        assert_eq!(imported.settings.always_on_channel, None);
+        assert_eq!(imported.settings.dj_role, None);
+        assert_eq!(
+            notices,
+            [
+                "Der Ankündigungskanal existiert hier nicht und wurde entfernt",
+                "Der 24/7-Kanal existiert hier nicht und wurde entfernt",
+                "Die DJ-Rolle existiert hier nicht und wurde entfernt",
+            ]
+        );
+    }
+
+    #[test]
+    fn only_missing_ids_are_cleared() {
+        let json = SettingsExport::new(&config()).to_json();
+
+        let (imported, notices) = SettingsExport::parse(&json)
+            .unwrap()
+            .into_config(GUILD, |channel| channel == ALWAYS_ON, |_| true)
+            .unwrap();
+        assert_eq!(imported.settings.announce_channel, None);
+        assert_eq!(imported.settings.always_on_channel, Some(ALWAYS_ON));
+        assert_eq!(imported.settings.dj_role, Some(DJ));
+        assert_eq!(notices.len(), 1);
+    }
+
+    #[test]
+    fn newer_schemas_are_rejected() {
+        let mut export = serde_json::to_value(SettingsExport::new(&config())).unwrap();
+        export["schema"] = (SETTINGS_SCHEMA + 1).into();
+
+        assert!(matches!(
+            SettingsExport::parse(&export.to_string()),
+            Err(TransferError::UnsupportedSchema { found }) if found == SETTINGS_SCHEMA + 1
+        ));
+    }
+
+    #[test]
+    fn other_files_are_not_exports() {
+        for content in ["", "Einstellungen", "{}", r#"{"schema": "1"}"#, "[1, 2]"] {
+            assert!(
+                matches!(
+                    SettingsExport::parse(content),
+                    Err(TransferError::NotAnExport)
+                ),
+                "{content}"
+            );
+        }
+    }
+}
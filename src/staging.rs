@@ -0,0 +1,95 @@
+use crate::guild_state::{GuildScoped, GuildStateKind};
+use crate::metadata::TrackMetadata;
+use serenity::all::{GuildId, UserId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Staged tracks are forgotten after this time
+pub const STAGING_TTL: Duration = Duration::from_secs(30 * 60);
+/// Staged tracks per user, so the list can not be used to store arbitrary amounts of tracks
+pub const MAX_STAGED_TRACKS: usize = 25;
+
+#[derive(Clone)]
+pub struct StagedTrack {
+    pub metadata: Arc<TrackMetadata>,
+    pub staged_at: Instant,
+}
+
+/// Tracks that users prepared with /play before joining a voice channel, per guild and user
+#[derive(Default)]
+pub struct StagingStore {
+    staged: Mutex<HashMap<(GuildId, UserId), Vec<StagedTrack>>>,
+}
+
+impl StagingStore {
+    /// Adds a track to the end of the staging list of the user. Returns false if the list is full.
+    pub fn stage(&self, guild_id: GuildId, user_id: UserId, metadata: Arc<TrackMetadata>) -> bool {
+        let mut staged = self.staged.lock().unwrap();
+        let tracks = staged.entry((guild_id, user_id)).or_default();
+        tracks.retain(|t| t.staged_at.elapsed() < STAGING_TTL);
+        if tracks.len() >= MAX_STAGED_TRACKS {
+            return false;
+        }
+
+        tracks.push(StagedTrack {
+            metadata,
+            staged_at: Instant::now(),
+        });
+        true
+    }
+
+    /// Staged tracks of the user that did not expire yet, oldest first
+    pub fn list(&self, guild_id: GuildId, user_id: UserId) -> Vec<StagedTrack> {
+        self.staged
+            .lock()
+            .unwrap()
+            .get(&(guild_id, user_id))
+            .map(|tracks| {
+                tracks
+                    .iter()
+                    .filter(|t| t.staged_at.elapsed() < STAGING_TTL)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Removes and returns all staged tracks of the user that did not expire yet
+    pub fn take(&self, guild_id: GuildId, user_id: UserId) -> Vec<StagedTrack> {
+        self.staged
+            .lock()
+            .unwrap()
+            .remove(&(guild_id, user_id))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|t| t.staged_at.elapsed() < STAGING_TTL)
+            .collect()
+    }
+
+    /// Removes all staged tracks of the user and returns how many were still valid
+    pub fn clear(&self, guild_id: GuildId, user_id: UserId) -> usize {
+        self.take(guild_id, user_id).len()
+    }
+}
+
+impl GuildScoped for StagingStore {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::Staging
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        let mut counts = HashMap::new();
+        for ((guild_id, _), tracks) in self.staged.lock().unwrap().iter() {
+            *counts.entry(*guild_id).or_default() += tracks.len();
+        }
+        counts
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.staged
+            .lock()
+            .unwrap()
+            .retain(|(staged_guild, _), _| *staged_guild != guild_id);
+    }
+}
@@ -0,0 +1,317 @@
+use crate::commands::util::{
+    get_end_markers, get_guild_settings, get_metadata, get_outbound, restart_track,
+};
+use crate::diagnostics::SAMPLE_INTERVAL;
+use crate::end_reason::EndReason;
+use crate::notice_channel::{cached_channel, InvokingChannel};
+use crate::position_cache::PositionSample;
+use crate::ERROR_COLOUR;
+use log::{info, warn};
+use serenity::all::{ChannelId, Context, CreateEmbed, CreateMessage, GuildId};
+use songbird::tracks::TrackHandle;
+use songbird::Call;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// A playing track whose position did not advance for this long is shown as buffering. Shorter
+/// than the sample interval, so a single frozen interval is enough.
+pub const BUFFERING_AFTER: Duration = Duration::from_secs(3);
+const _: () = assert!(BUFFERING_AFTER.as_secs() < SAMPLE_INTERVAL.as_secs());
+/// Stalls longer than this restart the track, unless configured otherwise
+pub const DEFAULT_STALL_LIMIT: Duration = Duration::from_secs(30);
+/// A playing track that advanced less than this between two samples is considered frozen
+const MIN_ADVANCE: Duration = Duration::from_millis(500);
+
+/// What the sampler should do about the current track
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StallVerdict {
+    /// Playing, paused or between tracks
+    Fine,
+    /// Frozen, but not long enough to act
+    Buffering { stalled_for: Duration },
+    /// Frozen beyond the limit at the position
+    Stalled { position: Duration },
+}
+
+/// Detects stalls of the current track of one call from its position samples. Pure logic, the
+/// sampler feeds it and acts on the verdicts.
+#[derive(Debug)]
+pub struct StallDetector {
+    limit: Duration,
+    /// The last sample
+    last: Option<PositionSample>,
+    /// Time of the last sample before the position froze
+    frozen_since: Option<Instant>,
+}
+
+impl StallDetector {
+    pub fn new(limit: Duration) -> Self {
+        Self {
+            limit,
+            last: None,
+            frozen_since: None,
+        }
+    }
+
+    pub fn observe(&mut self, sample: &PositionSample) -> StallVerdict {
+        // A new track starts fresh, a resumed one could not have moved since the last sample
+        let Some(last) = self
+            .last
+            .replace(*sample)
+            .filter(|last| last.track == sample.track && last.playing)
+        else {
+            self.frozen_since = None;
+            return StallVerdict::Fine;
+        };
+        if !sample.playing || sample.position.saturating_sub(last.position) >= MIN_ADVANCE {
+            self.frozen_since = None;
+            return StallVerdict::Fine;
+        }
+
+        let frozen_since = *self.frozen_since.get_or_insert(last.sampled_at);
+        let stalled_for = sample.sampled_at.saturating_duration_since(frozen_since);
+        if stalled_for < BUFFERING_AFTER {
+            StallVerdict::Fine
+        } else if stalled_for < self.limit {
+            StallVerdict::Buffering { stalled_for }
+        } else {
+            // Acted on, the next verdict is about a new stall
+            self.frozen_since = None;
+            StallVerdict::Stalled {
+                position: sample.position,
+            }
+        }
+    }
+}
+
+/// A track that stalled for longer than the limit
+#[derive(Clone, Copy, Debug)]
+pub struct StallAction {
+    pub guild_id: GuildId,
+    pub track: Uuid,
+    pub position: Duration,
+}
+
+/// Restarts stalled tracks at their position once and skips them if the restarted track stalls
+/// again. Runs until the process exits.
+pub async fn run_recovery(ctx: Context, mut actions: UnboundedReceiver<StallAction>) {
+    // The last restarted track of every guild
+    let mut replacements = HashMap::<GuildId, Uuid>::new();
+    while let Some(action) = actions.recv().await {
+        let Some(call) = songbird::get(&ctx)
+            .await
+            .and_then(|songbird| songbird.get(action.guild_id))
+        else {
+            continue;
+        };
+        // Skipped or stopped in the meantime
+        let current = call.lock().await.queue().current();
+        let Some(current) = current.filter(|current| current.uuid() == action.track) else {
+            continue;
+        };
+
+        if replacements.get(&action.guild_id) == Some(&action.track) {
+            replacements.remove(&action.guild_id);
+            skip_stalled(&ctx, action.guild_id, &call, &current).await;
+            continue;
+        }
+        let replacement =
+            restart_track(&ctx, action.guild_id, &call, &current, action.position).await;
+        info!(
+            "Restarted the stalled track {} in guild {} at {:?}",
+            action.track, action.guild_id, action.position
+        );
+        replacements.insert(action.guild_id, replacement);
+    }
+}
+
+/// Skips a track that stalled again after a restart and tells the channel why
+async fn skip_stalled(ctx: &Context, guild_id: GuildId, call: &Mutex<Call>, stalled: &TrackHandle) {
+    let metadata = get_metadata(stalled).await;
+    warn!(
+        "Skipped {} in guild {guild_id}, it stalled again after a restart",
+        metadata.source_url
+    );
+    get_end_markers(ctx)
+        .await
+        .mark(guild_id, stalled.uuid(), EndReason::Errored);
+    _ = stalled.stop();
+
+    // Voice channels have a text chat of their own, used if the bot may post there
+    let voice_channel = call
+        .lock()
+        .await
+        .current_channel()
+        .map(|channel| ChannelId::new(channel.0.get()))
+        .and_then(|channel_id| cached_channel(&ctx.cache, guild_id, channel_id))
+        .filter(InvokingChannel::can_receive)
+        .map(|channel| channel.id);
+    let channel = get_guild_settings(ctx)
+        .await
+        .get(guild_id)
+        .announce_channel
+        .or(voice_channel);
+    let Some(channel) = channel else {
+        return;
+    };
+    let embed = CreateEmbed::new()
+        .title("Nicht abspielbar")
+        .colour(ERROR_COLOUR)
+        .description(format!(
+            "[{}]({}) lädt nicht weiter und wird übersprungen",
+            metadata.title, metadata.source_url
+        ));
+    get_outbound(ctx).await.post(
+        &ctx.http,
+        guild_id,
+        channel,
+        None,
+        CreateMessage::new().embed(embed),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: Duration = Duration::from_secs(30);
+
+    /// Feeds samples of one track taken every [SAMPLE_INTERVAL]
+    struct Samples {
+        detector: StallDetector,
+        track: Uuid,
+        start: Instant,
+        taken: u32,
+    }
+
+    impl Samples {
+        fn new() -> Self {
+            Self {
+                detector: StallDetector::new(LIMIT),
+                track: Uuid::new_v4(),
+                start: Instant::now(),
+                taken: 0,
+            }
+        }
+
+        fn observe(&mut self, position_secs: u64, playing: bool) -> StallVerdict {
+            let sample = PositionSample {
+                track: self.track,
+                position: Duration::from_secs(position_secs),
+                playing,
+                looping: false,
+                sampled_at: self.start + SAMPLE_INTERVAL * self.taken,
+            };
+            self.taken += 1;
+            self.detector.observe(&sample)
+        }
+    }
+
+    #[test]
+    fn advancing_tracks_are_fine() {
+        let mut samples = Samples::new();
+        let step = SAMPLE_INTERVAL.as_secs();
+        for i in 0..20 {
+            assert_eq!(samples.observe(i * step, true), StallVerdict::Fine);
+        }
+    }
+
+    #[test]
+    fn frozen_position_buffers_then_stalls() {
+        let mut samples = Samples::new();
+        assert_eq!(samples.observe(42, true), StallVerdict::Fine);
+        let intervals = LIMIT.as_secs() / SAMPLE_INTERVAL.as_secs();
+        for i in 1..intervals as u32 {
+            assert_eq!(
+                samples.observe(42, true),
+                StallVerdict::Buffering {
+                    stalled_for: SAMPLE_INTERVAL * i
+                }
+            );
+        }
+        assert_eq!(
+            samples.observe(42, true),
+            StallVerdict::Stalled {
+                position: Duration::from_secs(42)
+            }
+        );
+    }
+
+    #[test]
+    fn stall_is_reported_once_at_its_position() {
+        let mut samples = Samples::new();
+        let mut stalls = Vec::new();
+        for _ in 0..=LIMIT.as_secs() / SAMPLE_INTERVAL.as_secs() + 1 {
+            if let StallVerdict::Stalled { position } = samples.observe(42, true) {
+                stalls.push(position);
+            }
+        }
+        assert_eq!(stalls, [Duration::from_secs(42)]);
+    }
+
+    #[test]
+    fn moving_again_ends_the_buffering() {
+        let mut samples = Samples::new();
+        samples.observe(42, true);
+        assert!(matches!(
+            samples.observe(42, true),
+            StallVerdict::Buffering { .. }
+        ));
+        assert_eq!(samples.observe(47, true), StallVerdict::Fine);
+        // A new freeze starts counting from zero
+        assert_eq!(
+            samples.observe(47, true),
+            StallVerdict::Buffering {
+                stalled_for: SAMPLE_INTERVAL
+            }
+        );
+    }
+
+    #[test]
+    fn paused_tracks_never_stall() {
+        let mut samples = Samples::new();
+        for _ in 0..20 {
+            assert_eq!(samples.observe(42, false), StallVerdict::Fine);
+        }
+        // Resuming does not count the pause as a freeze
+        assert_eq!(samples.observe(42, true), StallVerdict::Fine);
+    }
+
+    #[test]
+    fn a_new_track_starts_fresh() {
+        let mut samples = Samples::new();
+        samples.observe(42, true);
+        samples.observe(42, true);
+        samples.track = Uuid::new_v4();
+        assert_eq!(samples.observe(0, true), StallVerdict::Fine);
+        assert!(matches!(
+            samples.observe(0, true),
+            StallVerdict::Buffering { .. }
+        ));
+    }
+
+    #[test]
+    fn short_freezes_are_not_buffering() {
+        let mut detector = StallDetector::new(LIMIT);
+        let track = Uuid::new_v4();
+        let start = Instant::now();
+        let sample = |position_ms, after_ms| PositionSample {
+            track,
+            position: Duration::from_millis(position_ms),
+            playing: true,
+            looping: false,
+            sampled_at: start + Duration::from_millis(after_ms),
+        };
+        detector.observe(&sample(1000, 0));
+        assert_eq!(detector.observe(&sample(1100, 1000)), StallVerdict::Fine);
+        assert_eq!(
+            detector.observe(&sample(1200, BUFFERING_AFTER.as_millis() as u64)),
+            StallVerdict::Buffering {
+                stalled_for: BUFFERING_AFTER
+            }
+        );
+    }
+}
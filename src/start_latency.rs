@@ -0,0 +1,138 @@
+use crate::resolution::ResolutionPath;
+use log::warn;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Upper bounds of the histogram buckets in seconds
+const BUCKETS: [f64; 10] = [0.5, 1.0, 2.0, 3.0, 5.0, 8.0, 13.0, 20.0, 30.0, 60.0];
+/// Starts slower than this are logged with their source
+const SLOW_START_THRESHOLD: Duration = Duration::from_secs(10);
+/// Tracks that did not start after this long are not measured anymore
+const PENDING_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// A /play whose track was enqueued as the current track and did not start playing yet
+#[derive(Clone, Debug)]
+pub struct PendingStart {
+    pub requested_at: Instant,
+    pub path: Option<ResolutionPath>,
+    /// Whether the bot had to join the voice channel first
+    pub joined_voice: bool,
+    pub source_url: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct Labels {
+    path: Option<ResolutionPath>,
+    joined_voice: bool,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    /// Not cumulative, one count per bucket plus one for everything above the last bucket
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+    }
+}
+
+/// Time from a /play until its track becomes playable, by resolution path and whether the bot
+/// had to join first. Tracks are identified by the uuid of their handle.
+#[derive(Default)]
+pub struct StartLatency {
+    pending: Mutex<HashMap<Uuid, PendingStart>>,
+    histograms: Mutex<HashMap<Labels, Histogram>>,
+}
+
+impl StartLatency {
+    /// Starts measuring a track. Must be called before the track can become playable, which
+    /// takes at least one extraction.
+    pub fn begin(&self, track: Uuid, start: PendingStart) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, p| p.requested_at.elapsed() < PENDING_TTL);
+        pending.insert(track, start);
+    }
+
+    /// Records the latency of a track that started playing, if it is measured
+    pub fn observe(&self, track: Uuid, now: Instant) -> Option<Duration> {
+        let start = self.pending.lock().unwrap().remove(&track)?;
+        let latency = now.saturating_duration_since(start.requested_at);
+        if latency > SLOW_START_THRESHOLD {
+            warn!(
+                "Track took {:.1}s to start ({}, joined voice: {}): {}",
+                latency.as_secs_f64(),
+                path_label(start.path),
+                start.joined_voice,
+                start.source_url
+            );
+        }
+
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry(Labels {
+                path: start.path,
+                joined_voice: start.joined_voice,
+            })
+            .or_default()
+            .observe(latency.as_secs_f64());
+        Some(latency)
+    }
+
+    /// Stops measuring a track that ended or failed before it started
+    pub fn abandon(&self, track: Uuid) {
+        self.pending.lock().unwrap().remove(&track);
+    }
+
+    /// The histogram in the Prometheus text format
+    pub fn render_prometheus(&self) -> String {
+        let name = "gerbot_track_start_latency_seconds";
+        let mut out = format!(
+            "# HELP {name} Time from /play until the track is playable\n# TYPE {name} histogram\n"
+        );
+
+        let histograms = self.histograms.lock().unwrap();
+        let mut labels = histograms.keys().copied().collect::<Vec<_>>();
+        labels.sort_by_key(|l| (path_label(l.path), l.joined_voice));
+        for label in labels {
+            let histogram = &histograms[&label];
+            let label_str = format!(
+                "path=\"{}\",joined_voice=\"{}\"",
+                path_label(label.path),
+                label.joined_voice
+            );
+            let mut cumulative = 0;
+            for (bound, count) in BUCKETS.iter().zip(histogram.counts) {
+                cumulative += count;
+                _ = writeln!(
+                    out,
+                    "{name}_bucket{{{label_str},le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let total = histogram.counts.iter().sum::<u64>();
+            _ = writeln!(out, "{name}_bucket{{{label_str},le=\"+Inf\"}} {total}");
+            _ = writeln!(out, "{name}_sum{{{label_str}}} {}", histogram.sum);
+            _ = writeln!(out, "{name}_count{{{label_str}}} {total}");
+        }
+        out
+    }
+}
+
+/// Tracks enqueued without a /play, like staged ones, have no resolution path
+fn path_label(path: Option<ResolutionPath>) -> &'static str {
+    match path {
+        Some(path) => path.name(),
+        None => "other",
+    }
+}
@@ -0,0 +1,231 @@
+use crate::canonical_url::canonical_url;
+use crate::end_reason::EndReason;
+use crate::lifecycle::GuildPersisted;
+use crate::persistence::{PersistedFile, PersistenceHealth};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serenity::all::GuildId;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Shorter tracks are not counted, they are mostly jingles and sound effects
+const MIN_COUNTED_DURATION: Duration = Duration::from_secs(30);
+/// Tracks that end before this share of their duration count as skipped
+const EARLY_SKIP_RATIO: f64 = 0.3;
+/// Tracks with stats per guild, the least counted ones are dropped first
+const MAX_TRACKS_PER_GUILD: usize = 1000;
+
+/// How a track left the queue, as far as the statistics are concerned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayOutcome {
+    PlayedThrough,
+    SkippedEarly,
+}
+
+impl PlayOutcome {
+    /// Classifies a track by why it ended and its final position. Only skips count against a
+    /// track, stopping the whole queue or a failure say nothing about it. Returns None for
+    /// tracks that are not counted.
+    pub fn classify(position: Duration, duration: Duration, reason: EndReason) -> Option<Self> {
+        if duration < MIN_COUNTED_DURATION {
+            return None;
+        }
+        match reason {
+            EndReason::Finished => Some(PlayOutcome::PlayedThrough),
+            EndReason::Skipped
+                if position.as_secs_f64() < duration.as_secs_f64() * EARLY_SKIP_RATIO =>
+            {
+                Some(PlayOutcome::SkippedEarly)
+            }
+            EndReason::Skipped | EndReason::Stopped | EndReason::Errored => None,
+        }
+    }
+}
+
+/// Aggregated outcomes of a single source
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TrackStats {
+    pub title: String,
+    pub url: String,
+    pub played_through: u32,
+    pub skipped: u32,
+}
+
+/// The stats of a track as they are written to the file
+#[derive(Serialize, Deserialize)]
+struct StoredStats {
+    guild_id: GuildId,
+    #[serde(flatten)]
+    stats: TrackStats,
+}
+
+/// Play statistics of all guilds, written to a file if one is configured
+pub struct StatsStore {
+    guilds: Mutex<HashMap<GuildId, HashMap<String, TrackStats>>>,
+    file: PersistedFile,
+}
+
+impl StatsStore {
+    /// Loads the statistics from the file, starts empty if it does not exist yet or can not be
+    /// read
+    pub fn load(file: Option<PathBuf>, health: Arc<PersistenceHealth>) -> Self {
+        let file = PersistedFile::new("play statistics", file, health);
+        let mut guilds = HashMap::<_, HashMap<_, _>>::new();
+        for stored in file.load::<StoredStats>() {
+            guilds
+                .entry(stored.guild_id)
+                .or_default()
+                .insert(stored.stats.url.clone(), stored.stats);
+        }
+        Self {
+            guilds: Mutex::new(guilds),
+            file,
+        }
+    }
+
+    fn persist(&self, guilds: &HashMap<GuildId, HashMap<String, TrackStats>>) {
+        let stored = guilds
+            .iter()
+            .flat_map(|(guild_id, tracks)| {
+                tracks.values().map(|stats| StoredStats {
+                    guild_id: *guild_id,
+                    stats: stats.clone(),
+                })
+            })
+            .collect::<Vec<_>>();
+        self.file.save(&stored);
+    }
+
+    pub fn record(&self, guild_id: GuildId, url: &Url, title: &str, outcome: PlayOutcome) {
+        // The same track is counted once
+        let url = canonical_url(url);
+        let mut guilds = self.guilds.lock().unwrap();
+        let guild = guilds.entry(guild_id).or_default();
+
+        if guild.len() >= MAX_TRACKS_PER_GUILD && !guild.contains_key(&url) {
+            let least_counted = guild
+                .values()
+                .min_by_key(|s| s.played_through + s.skipped)
+                .map(|s| s.url.clone());
+            if let Some(least_counted) = least_counted {
+                guild.remove(&least_counted);
+            }
+        }
+
+        let stats = guild.entry(url.clone()).or_insert_with(|| TrackStats {
+            url,
+            ..TrackStats::default()
+        });
+
+        // Titles can change, the newest one is shown
+        stats.title = title.to_owned();
+        match outcome {
+            PlayOutcome::PlayedThrough => stats.played_through += 1,
+            PlayOutcome::SkippedEarly => stats.skipped += 1,
+        }
+        self.persist(&guilds);
+    }
+
+    /// Tracks that were played through the most
+    pub fn bangers(&self, guild_id: GuildId, count: usize) -> Vec<TrackStats> {
+        self.top(guild_id, count, |s| s.played_through)
+    }
+
+    /// Tracks that were skipped early the most
+    pub fn most_skipped(&self, guild_id: GuildId, count: usize) -> Vec<TrackStats> {
+        self.top(guild_id, count, |s| s.skipped)
+    }
+
+    fn top(&self, guild_id: GuildId, count: usize, key: fn(&TrackStats) -> u32) -> Vec<TrackStats> {
+        let guilds = self.guilds.lock().unwrap();
+        let mut stats = guilds
+            .get(&guild_id)
+            .map(|g| {
+                g.values()
+                    .filter(|s| key(s) > 0)
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        stats.sort_by(|a, b| key(b).cmp(&key(a)).then_with(|| a.title.cmp(&b.title)));
+        stats.truncate(count);
+        stats
+    }
+}
+
+impl GuildPersisted for StatsStore {
+    fn purge(&self, guild_id: GuildId) {
+        let mut guilds = self.guilds.lock().unwrap();
+        if guilds.remove(&guild_id).is_some() {
+            self.persist(&guilds);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const MINUTES_3: Duration = Duration::from_secs(180);
+
+    fn url(id: &str) -> Url {
+        Url::parse(&format!("https://www.youtube.com/watch?v={id}")).unwrap()
+    }
+
+    #[test]
+    fn classify_by_reason_and_position() {
+        let classify =
+            |secs, reason| PlayOutcome::classify(Duration::from_secs(secs), MINUTES_3, reason);
+        assert_eq!(
+            classify(180, EndReason::Finished),
+            Some(PlayOutcome::PlayedThrough)
+        );
+        assert_eq!(
+            classify(10, EndReason::Skipped),
+            Some(PlayOutcome::SkippedEarly)
+        );
+        // 30% of three minutes
+        assert_eq!(classify(54, EndReason::Skipped), None);
+        assert_eq!(classify(10, EndReason::Stopped), None);
+        assert_eq!(classify(10, EndReason::Errored), None);
+        assert_eq!(
+            PlayOutcome::classify(Duration::ZERO, Duration::from_secs(10), EndReason::Finished),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn stats_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!("gerbot-stats-{}.json", std::process::id()));
+        let health = Arc::new(PersistenceHealth::default());
+
+        let store = StatsStore::load(Some(path.clone()), health.clone());
+        store.record(GUILD, &url("aaaaaaaaaaa"), "A", PlayOutcome::PlayedThrough);
+        store.record(GUILD, &url("aaaaaaaaaaa"), "A", PlayOutcome::PlayedThrough);
+        store.record(GUILD, &url("bbbbbbbbbbb"), "B", PlayOutcome::SkippedEarly);
+
+        let reloaded = StatsStore::load(Some(path.clone()), health);
+        std::fs::remove_file(&path).unwrap();
+        let bangers = reloaded.bangers(GUILD, 10);
+        assert_eq!(bangers.len(), 1);
+        assert_eq!(
+            (bangers[0].title.as_str(), bangers[0].played_through),
+            ("A", 2)
+        );
+        assert_eq!(reloaded.most_skipped(GUILD, 10)[0].title, "B");
+    }
+
+    #[tokio::test]
+    async fn purge_drops_only_the_guild() {
+        let store = StatsStore::load(None, Arc::default());
+        let other = GuildId::new(2);
+        store.record(GUILD, &url("aaaaaaaaaaa"), "A", PlayOutcome::PlayedThrough);
+        store.record(other, &url("aaaaaaaaaaa"), "A", PlayOutcome::PlayedThrough);
+        store.purge(GUILD);
+        assert!(store.bangers(GUILD, 10).is_empty());
+        assert_eq!(store.bangers(other, 10).len(), 1);
+    }
+}
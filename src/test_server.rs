@@ -0,0 +1,48 @@
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// A local http server answering every request with `respond`, for code that talks to real
+/// hosts
+pub struct TestServer {
+    addr: SocketAddr,
+    requests: Arc<AtomicUsize>,
+}
+
+impl TestServer {
+    pub fn start(
+        respond: impl Fn(&Request<Body>) -> Response<Body> + Send + Sync + 'static,
+    ) -> Self {
+        let respond = Arc::new(respond);
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let respond = respond.clone();
+            let counter = counter.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    let response = respond(&request);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        Self { addr, requests }
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
+
+    /// Number of requests answered so far
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::SeqCst)
+    }
+}
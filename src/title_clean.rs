@@ -0,0 +1,296 @@
+/// Words that mark a bracketed part of a title as a qualifier of the upload instead of the song
+const NOISE_WORDS: &[&str] = &[
+    "official",
+    "video",
+    "audio",
+    "lyric",
+    "lyrics",
+    "visualizer",
+    "visualiser",
+    "mv",
+    "hd",
+    "hq",
+    "4k",
+    "1080p",
+    "720p",
+    "remaster",
+    "remastered",
+    "explicit",
+    "clean",
+];
+
+/// Suffixes of channel names that are not part of the artist name
+const CHANNEL_SUFFIXES: &[&str] = &[" - Topic", "VEVO", " Official"];
+
+/// Artist and song of a track, for lookups that search by name. The raw title stays in the
+/// metadata for display.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CleanTitle {
+    pub artist: Option<String>,
+    pub title: String,
+}
+
+/// Strips upload qualifiers like "(Official 4K Video)" from a raw title and splits an
+/// "Artist - Title" pattern. Without such a pattern, the channel name is used as the artist.
+pub fn clean_title(raw: &str, channel: &str) -> CleanTitle {
+    let stripped = strip_noise(raw);
+
+    let (artist, title) = match [" - ", " – ", " — ", " | "]
+        .iter()
+        .find_map(|separator| stripped.split_once(separator))
+    {
+        Some((artist, title)) if !artist.trim().is_empty() && !title.trim().is_empty() => {
+            (Some(artist.trim().to_owned()), title.trim().to_owned())
+        }
+        _ => (clean_channel(channel), stripped.clone()),
+    };
+
+    let title = title
+        .trim_matches(|c: char| c == '"' || c == '\'' || c.is_whitespace())
+        .to_owned();
+    CleanTitle {
+        artist,
+        // Titles that only consisted of noise are better than nothing
+        title: if title.is_empty() {
+            raw.trim().to_owned()
+        } else {
+            title
+        },
+    }
+}
+
+/// Removes bracketed qualifiers and trailing "| Official Video" style parts
+fn strip_noise(raw: &str) -> String {
+    let mut result = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(open) = rest.find(['(', '[', '【']) {
+        let close_char = match rest[open..].chars().next() {
+            Some('(') => ')',
+            Some('[') => ']',
+            _ => '】',
+        };
+        let inner_start = open + rest[open..].chars().next().map_or(1, char::len_utf8);
+        let Some(close) = rest[inner_start..].find(close_char) else {
+            break;
+        };
+        let inner = &rest[inner_start..inner_start + close];
+
+        result.push_str(&rest[..open]);
+        if !is_noise(inner) {
+            result.push_str(&rest[open..inner_start + close + close_char.len_utf8()]);
+        }
+        rest = &rest[inner_start + close + close_char.len_utf8()..];
+    }
+    result.push_str(rest);
+
+    // "Song | Official Video" has the qualifier without brackets
+    let result = match result.rsplit_once('|') {
+        Some((head, tail)) if is_noise(tail) => head.to_owned(),
+        _ => result,
+    };
+    result.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// A qualifier is noise if it mentions one of the noise words, like "2011 Remaster"
+fn is_noise(qualifier: &str) -> bool {
+    qualifier
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| NOISE_WORDS.contains(&word.to_lowercase().as_str()))
+}
+
+fn clean_channel(channel: &str) -> Option<String> {
+    let mut channel = channel.trim();
+    for suffix in CHANNEL_SUFFIXES {
+        channel = channel.strip_suffix(suffix).unwrap_or(channel).trim();
+    }
+    match channel {
+        "" | "Unknown" => None,
+        channel => Some(channel.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Raw title, channel, expected artist, expected title
+    const CASES: &[(&str, &str, Option<&str>, &str)] = &[
+        (
+            "Rick Astley - Never Gonna Give You Up (Official Music Video)",
+            "Rick Astley",
+            Some("Rick Astley"),
+            "Never Gonna Give You Up",
+        ),
+        (
+            "Daft Punk - Get Lucky (Official Audio) ft. Pharrell Williams, Nile Rodgers",
+            "Daft Punk",
+            Some("Daft Punk"),
+            "Get Lucky ft. Pharrell Williams, Nile Rodgers",
+        ),
+        (
+            "Queen – Bohemian Rhapsody [Official Video Remastered]",
+            "Queen Official",
+            Some("Queen"),
+            "Bohemian Rhapsody",
+        ),
+        (
+            "Bohemian Rhapsody (Remastered 2011)",
+            "Queen - Topic",
+            Some("Queen"),
+            "Bohemian Rhapsody",
+        ),
+        ("Numb", "Linkin Park", Some("Linkin Park"), "Numb"),
+        (
+            "Adele - Hello (Live at the NRJ Awards)",
+            "AdeleVEVO",
+            Some("Adele"),
+            "Hello (Live at the NRJ Awards)",
+        ),
+        (
+            "Taylor Swift - Shake It Off (Taylor's Version) (Lyric Video)",
+            "Taylor Swift",
+            Some("Taylor Swift"),
+            "Shake It Off (Taylor's Version)",
+        ),
+        (
+            "Billie Eilish — bad guy (4K)",
+            "",
+            Some("Billie Eilish"),
+            "bad guy",
+        ),
+        (
+            "Song Title | Official Video",
+            "Some Channel",
+            Some("Some Channel"),
+            "Song Title",
+        ),
+        ("Artist | Song", "Channel", Some("Artist"), "Song"),
+        (
+            "【MV】YOASOBI「アイドル」",
+            "Ayase / YOASOBI",
+            Some("Ayase / YOASOBI"),
+            "YOASOBI「アイドル」",
+        ),
+        (
+            "\"Weird Al\" Yankovic - White & Nerdy (Official Music Video)",
+            "alyankovicVEVO",
+            Some("\"Weird Al\" Yankovic"),
+            "White & Nerdy",
+        ),
+        (
+            "Nirvana - Smells Like Teen Spirit (Official Music Video) [HD]",
+            "NirvanaVEVO",
+            Some("Nirvana"),
+            "Smells Like Teen Spirit",
+        ),
+        (
+            "Mozart: Requiem in D minor, K. 626 (Clean Version)",
+            "Classical Music",
+            Some("Classical Music"),
+            "Mozart: Requiem in D minor, K. 626",
+        ),
+        (
+            "Video Games",
+            "Lana Del Rey",
+            Some("Lana Del Rey"),
+            "Video Games",
+        ),
+        (
+            "Lana Del Rey - Video Games (Official Video)",
+            "Lana Del Rey",
+            Some("Lana Del Rey"),
+            "Video Games",
+        ),
+        (
+            "Coldplay - Yellow (Official Video) | Official",
+            "Coldplay",
+            Some("Coldplay"),
+            "Yellow",
+        ),
+        (
+            "Song (feat. Someone)",
+            "Artist - Topic",
+            Some("Artist"),
+            "Song (feat. Someone)",
+        ),
+        ("Artist - Song (1080p)", "", Some("Artist"), "Song"),
+        ("Artist - Song [Explicit]", "", Some("Artist"), "Song"),
+        ("Artist - Song [MV]", "", Some("Artist"), "Song"),
+        ("Artist - Song (Visualiser)", "", Some("Artist"), "Song"),
+        ("Artist - Song (HQ Audio)", "", Some("Artist"), "Song"),
+        ("Artist - Song (4K Remaster)", "", Some("Artist"), "Song"),
+        ("Track 01", "Unknown", None, "Track 01"),
+        ("Track 02", "   ", None, "Track 02"),
+        (
+            "   Spaces   everywhere   - Song  ",
+            "",
+            Some("Spaces everywhere"),
+            "Song",
+        ),
+        ("'Quoted Title'", "Channel", Some("Channel"), "Quoted Title"),
+        // Only whole words are noise
+        (
+            "Artist - Song (Videoclip)",
+            "",
+            Some("Artist"),
+            "Song (Videoclip)",
+        ),
+        (
+            "Artist - Song (Audiophile Mix)",
+            "",
+            Some("Artist"),
+            "Song (Audiophile Mix)",
+        ),
+        (
+            "Artist - Song (Official Video) (Live)",
+            "",
+            Some("Artist"),
+            "Song (Live)",
+        ),
+        (
+            "Artist - Part 1 - Part 2",
+            "",
+            Some("Artist"),
+            "Part 1 - Part 2",
+        ),
+        ("ARTIST - SONG (OFFICIAL VIDEO)", "", Some("ARTIST"), "SONG"),
+        (
+            "AC/DC - Back In Black (Official 4K Video)",
+            "acdcVEVO",
+            Some("AC/DC"),
+            "Back In Black",
+        ),
+        // Titles that are only noise are kept as they are
+        (
+            "(Official Video)",
+            "Channel",
+            Some("Channel"),
+            "(Official Video)",
+        ),
+        ("[HD] [4K]", "", None, "[HD] [4K]"),
+        // Unclosed brackets are no qualifiers
+        (
+            "Artist - Song (Official Video",
+            "",
+            Some("Artist"),
+            "Song (Official Video",
+        ),
+        // A separator without an artist or title is no pattern
+        (" - Song", "Channel", Some("Channel"), "- Song"),
+        ("Artist - ", "Channel", Some("Channel"), "Artist -"),
+    ];
+
+    #[test]
+    fn messy_titles() {
+        for (raw, channel, artist, title) in CASES {
+            assert_eq!(
+                clean_title(raw, channel),
+                CleanTitle {
+                    artist: artist.map(str::to_owned),
+                    title: (*title).to_owned(),
+                },
+                "{raw:?} by {channel:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::commands::util::truncate_chars;
+
+/// Longest title in characters that is shown, like the limit of YouTube itself
+pub const MAX_TITLE_CHARS: usize = 100;
+const _: () = assert!(
+    MAX_TITLE_CHARS <= 256,
+    "Embed titles and field names are limited to 256"
+);
+
+/// Makes a title from an external source safe to show in embeds: drops control, bidi and
+/// zero-width characters, collapses whitespace and cuts it to [MAX_TITLE_CHARS] with an ellipsis.
+/// Zero-width joiners are kept between emoji, where they build a single symbol.
+pub fn sanitize_title(raw: &str) -> String {
+    let mut kept = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{200D}' {
+            let joins_emoji = kept.chars().next_back().is_some_and(is_emoji)
+                && chars.peek().copied().is_some_and(is_emoji);
+            if joins_emoji {
+                kept.push(c);
+            }
+            continue;
+        }
+        if is_invisible(c) {
+            // Line breaks and tabs still separate words
+            if c.is_whitespace() {
+                kept.push(' ');
+            }
+            continue;
+        }
+        kept.push(c);
+    }
+
+    let collapsed = kept.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.is_empty() {
+        return "Unknown".to_owned();
+    }
+    truncate_chars(&collapsed, MAX_TITLE_CHARS).into_owned()
+}
+
+/// Characters that change the layout of the text around them instead of being shown
+fn is_invisible(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            // Bidi marks, embeddings, overrides and isolates
+            '\u{061C}'
+                | '\u{200E}'
+                | '\u{200F}'
+                | '\u{202A}'..='\u{202E}'
+                | '\u{2066}'..='\u{2069}'
+                // Zero-width spaces and the byte order mark
+                | '\u{200B}'
+                | '\u{200C}'
+                | '\u{2060}'..='\u{2064}'
+                | '\u{FEFF}'
+        )
+}
+
+/// Rough emoji ranges, enough to tell joined emoji sequences from joiners in text
+fn is_emoji(c: char) -> bool {
+    matches!(
+        c,
+        '\u{2600}'..='\u{27BF}' | '\u{FE0F}' | '\u{1F000}'..='\u{1FAFF}'
+    )
+}
+
+/// The original title for the audit log, with the characters [sanitize_title] drops written as
+/// escapes so they can be seen without affecting the layout
+pub fn escape_invisible(raw: &str, max_chars: usize) -> String {
+    let escaped = raw
+        .chars()
+        .map(|c| match is_invisible(c) || c == '\u{200D}' {
+            true => c.escape_unicode().to_string(),
+            false => c.to_string(),
+        })
+        .collect::<String>();
+    truncate_chars(&escaped, max_chars).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bidi_overrides_can_not_spoof_the_title() {
+        // Would show as "Official Video gpj.exe" reversed, or flip the text after it
+        assert_eq!(
+            sanitize_title("Official Video \u{202E}gpj.exe"),
+            "Official Video gpj.exe"
+        );
+        assert_eq!(
+            sanitize_title("\u{2067}Song\u{2069} \u{200F}- Artist\u{200E}"),
+            "Song - Artist"
+        );
+        assert_eq!(
+            sanitize_title("\u{202A}\u{202B}\u{202C}\u{202D}"),
+            "Unknown"
+        );
+    }
+
+    #[test]
+    fn emoji_only_titles_are_kept() {
+        assert_eq!(sanitize_title("🎵🎶🔥"), "🎵🎶🔥");
+        assert_eq!(sanitize_title(" ❤️ "), "❤️");
+    }
+
+    #[test]
+    fn joiners_are_only_kept_within_emoji_sequences() {
+        let family = "👨\u{200D}👩\u{200D}👧";
+        assert_eq!(sanitize_title(family), family);
+        let rainbow_flag = "🏳️\u{200D}🌈";
+        assert_eq!(sanitize_title(rainbow_flag), rainbow_flag);
+        // Between letters or at the edges a joiner only hides text differences
+        assert_eq!(sanitize_title("Ly\u{200D}rics"), "Lyrics");
+        assert_eq!(sanitize_title("\u{200D}👍"), "👍");
+        assert_eq!(sanitize_title("👍\u{200D}"), "👍");
+        assert_eq!(sanitize_title("\u{200D}"), "Unknown");
+    }
+
+    #[test]
+    fn zero_width_and_control_characters_are_dropped() {
+        assert_eq!(sanitize_title("Ly\u{200B}ri\u{FEFF}cs\u{0007}"), "Lyrics");
+        assert_eq!(sanitize_title("Line\nbreak\ttab"), "Line break tab");
+        assert_eq!(sanitize_title("  spaced   out  "), "spaced out");
+    }
+
+    #[test]
+    fn long_input_is_cut_with_an_ellipsis() {
+        let sanitized = sanitize_title(&"a".repeat(1000));
+        assert_eq!(sanitized.chars().count(), MAX_TITLE_CHARS);
+        assert!(sanitized.ends_with('…'));
+
+        // Invisible characters do not count, and a cut never splits an emoji sequence
+        let padded = format!("{}{}", "\u{200B}".repeat(900), "x".repeat(50));
+        assert_eq!(sanitize_title(&padded), "x".repeat(50));
+        let emoji = "👨\u{200D}👩\u{200D}👧 ".repeat(200);
+        let sanitized = sanitize_title(&emoji);
+        assert!(sanitized.chars().count() <= MAX_TITLE_CHARS);
+        assert!(
+            sanitized.trim_end_matches('…').ends_with('👧'),
+            "{sanitized}"
+        );
+    }
+
+    #[test]
+    fn escapes_show_what_was_dropped() {
+        assert_eq!(
+            escape_invisible("a\u{202E}b\u{200D}c", 100),
+            "a\\u{202e}b\\u{200d}c"
+        );
+        assert_eq!(escape_invisible("plain", 100), "plain");
+    }
+}
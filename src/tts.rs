@@ -0,0 +1,129 @@
+use crate::locale::Locale;
+use crate::metadata::{TrackMetadata, TrackMetadataKey};
+use crate::queue_ops;
+use log::warn;
+use songbird::input::Input;
+use songbird::tracks::{Track, TrackHandle};
+use songbird::Call;
+use std::io;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+/// Arguments for espeak-ng, which writes a WAV file to stdout
+pub const DEFAULT_TTS_ARGS: &str = "--stdout -v de";
+/// The next track waits for the announcement, so a hanging binary must not hold it for long
+const SYNTHESIS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The binary that turns announcements into speech. It is called with the configured arguments
+/// and the text as the last one, and has to write audio in a common format like WAV to stdout.
+#[derive(Clone, Debug)]
+pub struct TtsConfig {
+    pub command: PathBuf,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum TtsError {
+    #[error("The text-to-speech binary could not be started")]
+    Spawn(#[from] io::Error),
+    #[error("The text-to-speech binary took longer than {SYNTHESIS_TIMEOUT:?}")]
+    Timeout,
+    #[error("The text-to-speech binary failed: {0}")]
+    Failed(String),
+    #[error("The text-to-speech binary returned no audio")]
+    Empty,
+}
+
+impl TtsConfig {
+    /// Checked at startup, so a wrong path does not only show up at the first announcement
+    pub fn check(&self) -> io::Result<()> {
+        match std::fs::metadata(&self.command)?.is_file() {
+            true => Ok(()),
+            false => Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file")),
+        }
+    }
+
+    async fn synthesize(&self, text: &str) -> Result<Vec<u8>, TtsError> {
+        let output = Command::new(&self.command)
+            .args(&self.args)
+            .arg(text)
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = timeout(SYNTHESIS_TIMEOUT, output)
+            .await
+            .map_err(|_| TtsError::Timeout)??;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(TtsError::Failed(stderr.trim().to_owned()));
+        }
+        if output.stdout.is_empty() {
+            return Err(TtsError::Empty);
+        }
+        Ok(output.stdout)
+    }
+}
+
+/// What is said before a track, with the cleaned title so upload qualifiers are not read out
+pub fn announcement_text(next: &TrackMetadata, locale: Locale) -> String {
+    let clean = next.clean_title();
+    match (locale, clean.artist) {
+        (Locale::German, Some(artist)) => format!("Als Nächstes: {} von {artist}", clean.title),
+        (Locale::German, None) => format!("Als Nächstes: {}", clean.title),
+        (Locale::English, Some(artist)) => format!("Up next: {} by {artist}", clean.title),
+        (Locale::English, None) => format!("Up next: {}", clean.title),
+    }
+}
+
+/// Plays an announcement in front of the next track, which is held paused until the
+/// announcement ended. The music never waits on a failing binary, the announcement is skipped.
+pub async fn announce_next(
+    config: &TtsConfig,
+    call: &Mutex<Call>,
+    next: TrackHandle,
+    text: String,
+) {
+    _ = next.pause();
+    let audio = match config.synthesize(&text).await {
+        Ok(audio) => audio,
+        Err(e) => {
+            warn!("Skipped the announcement of {}: {e}", next.uuid());
+            _ = next.play();
+            return;
+        }
+    };
+
+    let mut call = call.lock().await;
+    let announcement = call.enqueue_with_preload(Track::from(Input::from(audio)), None);
+    // Every queue entry needs metadata, the flag keeps it out of listings
+    announcement
+        .typemap()
+        .write()
+        .await
+        .insert::<TrackMetadataKey>(Arc::new(TrackMetadata::announcement(text)));
+    let queue = call.queue();
+    let placed = queue.modify_queue(|raw_queue| {
+        // Skipped, removed or moved while the announcement was synthesized
+        if raw_queue.front().map(|track| track.uuid()) != Some(next.uuid()) {
+            return false;
+        }
+        queue_ops::move_last_to(raw_queue, 0);
+        true
+    });
+
+    if placed {
+        // The queue plays the next track once the announcement ended
+        _ = announcement.play();
+    } else {
+        queue.modify_queue(|raw_queue| {
+            raw_queue.retain(|track| track.uuid() != announcement.uuid());
+        });
+        _ = announcement.stop();
+    }
+}
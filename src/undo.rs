@@ -0,0 +1,188 @@
+use crate::commands::util::get_metadata;
+use crate::guild_state::{GuildScoped, GuildStateKind, GuildStateMap};
+use crate::metadata::TrackMetadata;
+use serenity::all::GuildId;
+use serenity::futures::future::join_all;
+use songbird::tracks::TrackQueue;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Snapshots older than this are not restored anymore, the queue has usually moved on
+pub const UNDO_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// The queue of a guild before a command changed it
+#[derive(Clone)]
+pub struct UndoSnapshot {
+    /// Interaction that took the snapshot
+    pub invocation: u64,
+    pub command: String,
+    pub tracks: Vec<Arc<TrackMetadata>>,
+    pub taken_at: Instant,
+}
+
+/// One undo slot per guild, overwritten by every queue-changing command
+#[derive(Default)]
+pub struct UndoSlots {
+    slots: GuildStateMap<UndoSnapshot>,
+}
+
+impl UndoSlots {
+    pub fn save(&self, guild_id: GuildId, snapshot: UndoSnapshot) {
+        self.slots.insert(guild_id, snapshot);
+    }
+
+    /// Saves the queue before `change` modifies it, so the change can be undone even if it
+    /// panics halfway
+    pub async fn save_before<T>(
+        &self,
+        guild_id: GuildId,
+        invocation: u64,
+        command: &str,
+        queue: &TrackQueue,
+        change: impl FnOnce(&TrackQueue) -> T,
+    ) -> T {
+        let tracks = join_all(queue.current_queue().iter().map(get_metadata)).await;
+        self.save(
+            guild_id,
+            UndoSnapshot {
+                invocation,
+                command: command.to_owned(),
+                tracks,
+                taken_at: Instant::now(),
+            },
+        );
+        change(queue)
+    }
+
+    /// Whether the interaction left a snapshot, e.g. before it panicked
+    pub fn has_snapshot_of(&self, guild_id: GuildId, invocation: u64) -> bool {
+        self.slots.with(guild_id, |slot| {
+            slot.is_some_and(|s| s.invocation == invocation)
+        })
+    }
+
+    /// Removes and returns the snapshot of the guild if it did not expire yet
+    pub fn take(&self, guild_id: GuildId) -> Option<UndoSnapshot> {
+        self.slots
+            .remove(guild_id)
+            .filter(|s| s.taken_at.elapsed() < UNDO_TTL)
+    }
+}
+
+impl GuildScoped for UndoSlots {
+    fn kind(&self) -> GuildStateKind {
+        GuildStateKind::Undo
+    }
+
+    fn entry_counts(&self) -> HashMap<GuildId, usize> {
+        self.slots.entry_counts(|_| 1)
+    }
+
+    fn forget(&self, guild_id: GuildId) {
+        self.slots.remove_on_leave(guild_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::TrackMetadataKey;
+    use songbird::input::Input;
+    use songbird::Driver;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    /// A queue of silent tracks with the titles. The driver has no connection, so nothing plays.
+    async fn queue(driver: &mut Driver, titles: &[&str]) -> TrackQueue {
+        let queue = TrackQueue::new();
+        for title in titles {
+            let mut metadata = TrackMetadata::default();
+            metadata.title = (*title).to_owned();
+            let handle = queue.add_source(Input::from(vec![0u8; 16]), driver).await;
+            handle
+                .typemap()
+                .write()
+                .await
+                .insert::<TrackMetadataKey>(Arc::new(metadata));
+        }
+        queue
+    }
+
+    #[tokio::test]
+    async fn panicking_change_leaves_a_restorable_snapshot() {
+        let mut driver = Driver::default();
+        let queue = queue(&mut driver, &["a", "b", "c"]).await;
+        let slots = Arc::new(UndoSlots::default());
+
+        let result = tokio::spawn({
+            let slots = slots.clone();
+            let queue = queue.clone();
+            async move {
+                slots
+                    .save_before(GUILD, 7, "remove", &queue, |queue| {
+                        queue.modify_queue(|raw| raw.truncate(1));
+                        panic!("Simulated panic halfway through the change");
+                    })
+                    .await
+            }
+        })
+        .await;
+        assert!(result.unwrap_err().is_panic());
+
+        // The queue was left half-changed, the snapshot has it as it was before
+        assert_eq!(queue.len(), 1);
+        assert!(slots.has_snapshot_of(GUILD, 7));
+        assert!(!slots.has_snapshot_of(GUILD, 8));
+        let snapshot = slots.take(GUILD).unwrap();
+        assert_eq!(snapshot.command, "remove");
+        let titles = snapshot
+            .tracks
+            .iter()
+            .map(|track| track.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(titles, ["a", "b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn every_change_overwrites_the_slot() {
+        let mut driver = Driver::default();
+        let queue = queue(&mut driver, &["a", "b"]).await;
+        let slots = UndoSlots::default();
+
+        slots.save_before(GUILD, 1, "shuffle", &queue, |_| {}).await;
+        let removed = slots
+            .save_before(GUILD, 2, "clear", &queue, |queue| {
+                queue.modify_queue(|raw| raw.split_off(1).len())
+            })
+            .await;
+        assert_eq!(removed, 1);
+
+        assert!(!slots.has_snapshot_of(GUILD, 1));
+        let snapshot = slots.take(GUILD).unwrap();
+        assert_eq!(
+            (snapshot.command.as_str(), snapshot.tracks.len()),
+            ("clear", 2)
+        );
+        // Taking empties the slot
+        assert!(slots.take(GUILD).is_none());
+    }
+
+    #[test]
+    fn expired_snapshots_are_not_restored() {
+        let slots = UndoSlots::default();
+        let Some(taken_at) = Instant::now().checked_sub(UNDO_TTL) else {
+            return;
+        };
+        slots.save(
+            GUILD,
+            UndoSnapshot {
+                invocation: 1,
+                command: "clear".to_owned(),
+                tracks: vec![],
+                taken_at,
+            },
+        );
+        assert!(slots.take(GUILD).is_none());
+    }
+}
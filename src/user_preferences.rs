@@ -0,0 +1,177 @@
+use crate::locale::Locale;
+use crate::persistence::{PersistedFile, PersistenceHealth};
+use serde::{Deserialize, Serialize};
+use serenity::all::UserId;
+use serenity::prelude::Mentionable;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Personal preferences of a user that apply in every guild. Preferences missing in older
+/// files keep their default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserPreferences {
+    /// Overrides the guild and interaction locale for responses to this user
+    pub locale: Option<Locale>,
+    /// Whether public embeds show "ein Nutzer" instead of a mention for requests of this user
+    pub anonymous: bool,
+    /// Whether info commands like /now_playing respond publicly instead of only to the user
+    pub public_info: bool,
+    /// Whether responses only this user sees are plain sentences without markdown and emoji,
+    /// for screen readers
+    pub accessible: bool,
+}
+
+/// The preferences of a user as they are written to the file
+#[derive(Serialize, Deserialize)]
+struct StoredPreferences {
+    user_id: UserId,
+    #[serde(flatten)]
+    preferences: UserPreferences,
+}
+
+/// Preferences of all users, written to a file if one is configured
+pub struct UserPreferencesStore {
+    preferences: Mutex<HashMap<UserId, UserPreferences>>,
+    file: PersistedFile,
+}
+
+impl UserPreferencesStore {
+    /// Loads the preferences from the file, starts empty if it does not exist yet or can not be
+    /// read
+    pub fn load(file: Option<PathBuf>, health: Arc<PersistenceHealth>) -> Self {
+        let file = PersistedFile::new("user preferences", file, health);
+        let preferences = file
+            .load::<StoredPreferences>()
+            .into_iter()
+            .map(|stored| (stored.user_id, stored.preferences))
+            .collect();
+        Self {
+            preferences: Mutex::new(preferences),
+            file,
+        }
+    }
+
+    pub fn get(&self, user_id: UserId) -> UserPreferences {
+        self.preferences
+            .lock()
+            .unwrap()
+            .get(&user_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Changes the preferences of a user and returns the new preferences
+    pub fn update(&self, user_id: UserId, f: impl FnOnce(&mut UserPreferences)) -> UserPreferences {
+        let mut preferences = self.preferences.lock().unwrap();
+        let user_preferences = preferences.entry(user_id).or_default();
+        f(user_preferences);
+        let updated = *user_preferences;
+
+        // Users back at the defaults are not stored
+        if updated == UserPreferences::default() {
+            preferences.remove(&user_id);
+        }
+        let stored = preferences
+            .iter()
+            .map(|(user_id, preferences)| StoredPreferences {
+                user_id: *user_id,
+                preferences: *preferences,
+            })
+            .collect::<Vec<_>>();
+        self.file.save(&stored);
+        updated
+    }
+
+    /// Looks up the preferences of all requesters at once, so rendering a list of tracks only
+    /// locks the store a single time
+    pub fn requesters(&self, users: impl IntoIterator<Item = UserId>) -> Requesters {
+        let preferences = self.preferences.lock().unwrap();
+        Requesters {
+            anonymous: users
+                .into_iter()
+                .filter(|user| preferences.get(user).is_some_and(|p| p.anonymous))
+                .collect(),
+        }
+    }
+}
+
+/// Renders requesters of tracks, respecting their anonymity preference
+pub struct Requesters {
+    anonymous: HashSet<UserId>,
+}
+
+impl Requesters {
+    /// Mention of the requester, or a neutral placeholder in public messages if they wish so
+    pub fn render(&self, user: Option<UserId>, public: bool) -> String {
+        match user {
+            Some(user) if public && self.anonymous.contains(&user) => "ein Nutzer".to_owned(),
+            Some(user) => user.mention().to_string(),
+            None => "unbekannt".to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER: UserId = UserId::new(1);
+    const OTHER_USER: UserId = UserId::new(2);
+
+    #[tokio::test]
+    async fn preferences_survive_a_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "gerbot-user-preferences-{}.json",
+            std::process::id()
+        ));
+        let health = Arc::new(PersistenceHealth::default());
+
+        let store = UserPreferencesStore::load(Some(path.clone()), health.clone());
+        store.update(USER, |p| {
+            p.locale = Some(Locale::English);
+            p.anonymous = true;
+        });
+        store.update(OTHER_USER, |p| p.accessible = true);
+
+        let reloaded = UserPreferencesStore::load(Some(path.clone()), health);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reloaded.get(USER), store.get(USER));
+        assert_eq!(reloaded.get(OTHER_USER), store.get(OTHER_USER));
+        assert_eq!(reloaded.get(UserId::new(3)), UserPreferences::default());
+    }
+
+    #[test]
+    fn missing_preferences_keep_their_default() {
+        let stored: StoredPreferences =
+            serde_json::from_str(r#"{"user_id": "1", "anonymous": true}"#).unwrap();
+        assert_eq!(
+            stored.preferences,
+            UserPreferences {
+                anonymous: true,
+                ..UserPreferences::default()
+            }
+        );
+    }
+
+    #[test]
+    fn users_back_at_the_defaults_are_dropped() {
+        let store = UserPreferencesStore::load(None, Arc::default());
+        store.update(USER, |p| p.public_info = true);
+        store.update(USER, |p| p.public_info = false);
+        assert!(store.preferences.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn anonymous_requesters_are_hidden_only_in_public() {
+        let store = UserPreferencesStore::load(None, Arc::default());
+        store.update(USER, |p| p.anonymous = true);
+        let requesters = store.requesters([USER, OTHER_USER]);
+
+        assert_eq!(requesters.render(Some(USER), true), "ein Nutzer");
+        assert_eq!(requesters.render(Some(USER), false), "<@1>");
+        assert_eq!(requesters.render(Some(OTHER_USER), true), "<@2>");
+        assert_eq!(requesters.render(None, true), "unbekannt");
+    }
+}
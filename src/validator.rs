@@ -0,0 +1,360 @@
+use crate::commands::util::get_metadata;
+use crate::events::{PlaybackEvent, PlaybackEventBus};
+use crate::metadata::{Playability, TrackMetadata};
+use crate::outbound::OutboundScheduler;
+use crate::queue_ops;
+use crate::ytdlp::{self, YtDlpConfig, YtDlpError};
+use crate::ERROR_COLOUR;
+use log::{info, warn};
+use reqwest::Url;
+use serenity::all::{ChannelId, CreateEmbed, CreateMessage, GuildId, Http};
+use serenity::futures::future::join_all;
+use songbird::{Call, Songbird};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{Mutex as AsyncMutex, Semaphore};
+use tokio::time::{sleep, timeout};
+
+/// How far ahead of playback queued tracks are checked
+const LOOKAHEAD: Duration = Duration::from_secs(5 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Queue indices of the upcoming entries that start within [LOOKAHEAD] and were not checked yet.
+/// `upcoming` are the duration and playability of every entry after the current track, which is
+/// never probed.
+fn due_for_probe(remaining: Duration, upcoming: &[(Duration, Playability)]) -> Vec<usize> {
+    let mut starts_in = remaining;
+    let mut due = Vec::new();
+    for (i, (duration, playability)) in upcoming.iter().enumerate() {
+        if starts_in > LOOKAHEAD {
+            break;
+        }
+        starts_in += *duration;
+        if *playability == Playability::Unchecked {
+            due.push(i + 1);
+        }
+    }
+    due
+}
+
+/// Number of flagged entries directly after the current track, which is never counted
+fn flagged_up_next(queue: &[Playability]) -> usize {
+    queue
+        .iter()
+        .skip(1)
+        .take_while(|playability| **playability == Playability::Unplayable)
+        .count()
+}
+
+/// Optional background task that probes upcoming tracks with yt-dlp before their turn comes.
+/// Flagged entries stay in the queue with a marker until they are next, see [skip_flagged].
+pub struct TrackValidator {
+    enabled: bool,
+    ytdlp_config: Arc<YtDlpConfig>,
+    outbound: Arc<OutboundScheduler>,
+    events: Arc<PlaybackEventBus>,
+    running: Mutex<HashSet<GuildId>>,
+}
+
+impl TrackValidator {
+    pub fn new(
+        enabled: bool,
+        ytdlp_config: Arc<YtDlpConfig>,
+        outbound: Arc<OutboundScheduler>,
+        events: Arc<PlaybackEventBus>,
+    ) -> Self {
+        Self {
+            enabled,
+            ytdlp_config,
+            outbound,
+            events,
+            running: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn is_running(&self, guild_id: GuildId) -> bool {
+        self.running.lock().unwrap().contains(&guild_id)
+    }
+
+    /// Starts the validator for a guild, if it is enabled and not already running there
+    pub fn spawn_for(
+        self: Arc<Self>,
+        http: Arc<Http>,
+        songbird: Arc<Songbird>,
+        ytdlp_permits: Arc<Semaphore>,
+        guild_id: GuildId,
+        notify_channel: Option<ChannelId>,
+    ) {
+        if !self.enabled || !self.running.lock().unwrap().insert(guild_id) {
+            return;
+        }
+
+        tokio::spawn(async move {
+            self.run(http, songbird, ytdlp_permits, guild_id, notify_channel)
+                .await;
+            self.running.lock().unwrap().remove(&guild_id);
+        });
+    }
+
+    async fn run(
+        &self,
+        http: Arc<Http>,
+        songbird: Arc<Songbird>,
+        ytdlp_permits: Arc<Semaphore>,
+        guild_id: GuildId,
+        notify_channel: Option<ChannelId>,
+    ) {
+        loop {
+            sleep(CHECK_INTERVAL).await;
+
+            // Stop when the bot left or the queue ran out
+            let Some(call) = songbird.get(guild_id) else {
+                return;
+            };
+            let queue = {
+                let call = call.lock().await;
+                if call.current_channel().is_none() {
+                    return;
+                }
+                call.queue().current_queue()
+            };
+            let Some(current) = queue.first() else {
+                return;
+            };
+
+            let position = current
+                .get_info()
+                .await
+                .map(|info| info.position)
+                .unwrap_or_default();
+            let remaining = get_metadata(current)
+                .await
+                .duration
+                .saturating_sub(position);
+            let upcoming = join_all(queue.iter().skip(1).map(get_metadata)).await;
+            let due = due_for_probe(
+                remaining,
+                &upcoming
+                    .iter()
+                    .map(|metadata| (metadata.duration, metadata.playability()))
+                    .collect::<Vec<_>>(),
+            );
+
+            let mut failed = Vec::new();
+            for index in due {
+                let (track, metadata) = (&queue[index], &upcoming[index - 1]);
+
+                // Low priority: Only probe while no other yt-dlp process has to wait for it
+                let Ok(_permit) = ytdlp_permits.clone().try_acquire_owned() else {
+                    break;
+                };
+
+                if self.probe(&metadata.source_url).await {
+                    metadata.set_playability(Playability::Playable);
+                } else {
+                    warn!("Pre-validation failed for {}", metadata.source_url);
+                    metadata.set_playability(Playability::Unplayable);
+                    failed.push((track.uuid(), metadata.clone()));
+                }
+            }
+
+            if !failed.is_empty() {
+                // The marker shows in /queue until a flagged entry is next
+                skip_flagged(&call, guild_id).await;
+                self.events.publish(guild_id, PlaybackEvent::QueueChanged);
+                if let Some(channel_id) = notify_channel {
+                    self.notify_failed(&http, guild_id, channel_id, &failed);
+                }
+            }
+        }
+    }
+
+    /// Sends a single notice for all tracks that were flagged in one pass
+    fn notify_failed(
+        &self,
+        http: &Arc<Http>,
+        guild_id: GuildId,
+        channel_id: ChannelId,
+        failed: &[(uuid::Uuid, Arc<TrackMetadata>)],
+    ) {
+        let track_list = failed
+            .iter()
+            .map(|(_, meta)| format!("⚠️ [{}]({})", meta.title, meta.source_url))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let embed = CreateEmbed::new()
+            .title("Nicht abspielbar")
+            .colour(ERROR_COLOUR)
+            .description(format!(
+                "Diese Titel konnten nicht geladen werden und werden übersprungen:\n{}",
+                track_list
+            ));
+
+        self.outbound.post(
+            http,
+            guild_id,
+            channel_id,
+            None,
+            CreateMessage::new().embed(embed),
+        );
+    }
+
+    /// Returns false if yt-dlp reported that the source can't be played
+    async fn probe(&self, url: &Url) -> bool {
+        match timeout(
+            PROBE_TIMEOUT,
+            ytdlp::simulate(&self.ytdlp_config, url.as_str()),
+        )
+        .await
+        {
+            Ok(Ok(())) => true,
+            Ok(Err(YtDlpError::Failed { .. })) => false,
+            Ok(Err(e)) => {
+                warn!("Failed to run yt-dlp for pre-validation: {}", e);
+                // Don't flag tracks just because the check itself is broken
+                true
+            }
+            Err(_) => true,
+        }
+    }
+}
+
+/// Removes the flagged entries that are next in the queue, before their input is loaded. Called
+/// when a track starts and when the validator flagged entries. Returns the number of removed
+/// entries.
+pub async fn skip_flagged(call: &AsyncMutex<Call>, guild_id: GuildId) -> usize {
+    let upcoming = call.lock().await.queue().current_queue();
+    let playability = join_all(upcoming.iter().map(get_metadata))
+        .await
+        .iter()
+        .map(|metadata| metadata.playability())
+        .collect::<Vec<_>>();
+    let flagged = upcoming
+        .iter()
+        .skip(1)
+        .take(flagged_up_next(&playability))
+        .map(|track| track.uuid())
+        .collect::<HashSet<_>>();
+    if flagged.is_empty() {
+        return 0;
+    }
+
+    // Never touch the currently playing track
+    let removed = call.lock().await.queue().modify_queue(|raw_queue| {
+        queue_ops::remove_upcoming_where(raw_queue, |track| flagged.contains(&track.uuid()))
+    });
+    for track in &removed {
+        _ = track.stop();
+    }
+    info!(
+        "Skipped {} unplayable tracks in the queue of guild {}",
+        removed.len(),
+        guild_id
+    );
+    removed.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Playability::{Playable, Unchecked, Unplayable};
+
+    fn minutes(minutes: u64) -> Duration {
+        Duration::from_secs(minutes * 60)
+    }
+
+    #[test]
+    fn only_entries_within_the_lookahead_are_probed() {
+        let upcoming = [(minutes(2), Unchecked); 10];
+        // Starting in 1, 3 and 5 minutes, the entry after starts beyond the lookahead
+        assert_eq!(due_for_probe(minutes(1), &upcoming), [1, 2, 3]);
+        assert_eq!(due_for_probe(minutes(6), &upcoming), Vec::<usize>::new());
+        assert_eq!(due_for_probe(Duration::ZERO, &[]), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn checked_entries_are_not_probed_again() {
+        let upcoming = [
+            (minutes(1), Playable),
+            (minutes(1), Unchecked),
+            (minutes(1), Unplayable),
+            (minutes(1), Unchecked),
+        ];
+        assert_eq!(due_for_probe(Duration::ZERO, &upcoming), [2, 4]);
+    }
+
+    #[test]
+    fn checked_entries_still_count_for_the_lookahead() {
+        let upcoming = [(minutes(10), Playable), (minutes(1), Unchecked)];
+        assert_eq!(
+            due_for_probe(Duration::ZERO, &upcoming),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn only_flagged_entries_up_next_are_skipped() {
+        assert_eq!(
+            flagged_up_next(&[Playable, Unplayable, Unplayable, Playable]),
+            2
+        );
+        assert_eq!(flagged_up_next(&[Playable, Playable, Unplayable]), 0);
+        assert_eq!(flagged_up_next(&[Playable]), 0);
+        assert_eq!(flagged_up_next(&[]), 0);
+    }
+
+    #[test]
+    fn current_track_is_never_skipped() {
+        assert_eq!(flagged_up_next(&[Unplayable]), 0);
+        assert_eq!(flagged_up_next(&[Unplayable, Unplayable]), 1);
+        assert_eq!(flagged_up_next(&[Unplayable, Unchecked, Unplayable]), 0);
+    }
+
+    #[tokio::test]
+    async fn flagged_entries_are_removed_once_they_are_next() {
+        let call = AsyncMutex::new(Call::standalone(
+            GuildId::new(1),
+            serenity::all::UserId::new(2),
+        ));
+        let mut ids = Vec::new();
+        {
+            let mut call = call.lock().await;
+            for (i, playability) in [
+                Unplayable, Playable, Unplayable, Unplayable, Playable, Unplayable,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                let track = call.enqueue_input(vec![0u8; 16].into()).await;
+                _ = track.pause();
+                let metadata = TrackMetadata::unresolved(&format!("https://example.com/{i}"));
+                metadata.set_playability(playability);
+                track
+                    .typemap()
+                    .write()
+                    .await
+                    .insert::<crate::metadata::TrackMetadataKey>(Arc::new(metadata));
+                ids.push(track.uuid());
+            }
+        }
+        // Entry 1 is playable, so nothing is next
+        assert_eq!(skip_flagged(&call, GuildId::new(1)).await, 0);
+
+        call.lock().await.queue().modify_queue(|queue| {
+            queue.remove(1);
+        });
+        assert_eq!(skip_flagged(&call, GuildId::new(1)).await, 2);
+        let remaining = call
+            .lock()
+            .await
+            .queue()
+            .current_queue()
+            .iter()
+            .map(|track| track.uuid())
+            .collect::<Vec<_>>();
+        assert_eq!(remaining, [ids[0], ids[4], ids[5]]);
+    }
+}
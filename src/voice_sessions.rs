@@ -0,0 +1,154 @@
+use serenity::all::GuildId;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The limit of concurrent voice connections was reached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SessionsFull {
+    pub active: usize,
+    pub limit: usize,
+}
+
+/// Guilds with a voice connection since when, optionally limited so small hosts do not run out
+/// of memory or file descriptors. Guilds are counted once no matter how often they join, so a
+/// leave path that ends a session twice can not free a slot it does not own.
+pub struct VoiceSessions {
+    limit: Option<usize>,
+    started: Mutex<HashMap<GuildId, Instant>>,
+}
+
+impl VoiceSessions {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            started: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Counts the guild as connected. Returns whether it was not already, a connected guild
+    /// moving to another channel never hits the limit.
+    pub fn start(&self, guild_id: GuildId, now: Instant) -> Result<bool, SessionsFull> {
+        let mut started = self.started.lock().unwrap();
+        if started.contains_key(&guild_id) {
+            return Ok(false);
+        }
+        if let Some(limit) = self.limit.filter(|limit| started.len() >= *limit) {
+            return Err(SessionsFull {
+                active: started.len(),
+                limit,
+            });
+        }
+        started.insert(guild_id, now);
+        Ok(true)
+    }
+
+    pub fn is_active(&self, guild_id: GuildId) -> bool {
+        self.started.lock().unwrap().contains_key(&guild_id)
+    }
+
+    /// Returns whether the guild was connected
+    pub fn end(&self, guild_id: GuildId) -> bool {
+        self.started.lock().unwrap().remove(&guild_id).is_some()
+    }
+
+    /// Connected guilds with the age of their session, oldest first
+    pub fn active(&self, now: Instant) -> Vec<(GuildId, Duration)> {
+        let mut active = self
+            .started
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(guild_id, started)| (*guild_id, now.saturating_duration_since(*started)))
+            .collect::<Vec<_>>();
+        active.sort_unstable_by_key(|(_, age)| std::cmp::Reverse(*age));
+        active
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    #[test]
+    fn start_and_end_pair_up() {
+        let sessions = VoiceSessions::new(None);
+        let now = Instant::now();
+        assert_eq!(sessions.start(GUILD, now), Ok(true));
+        assert!(sessions.is_active(GUILD));
+        assert!(sessions.end(GUILD));
+        assert!(!sessions.is_active(GUILD));
+        assert!(sessions.active(now).is_empty());
+    }
+
+    #[test]
+    fn joining_again_counts_once() {
+        let sessions = VoiceSessions::new(Some(1));
+        let now = Instant::now();
+        assert_eq!(sessions.start(GUILD, now), Ok(true));
+        // Moving to another channel neither hits the limit nor needs a second end
+        assert_eq!(sessions.start(GUILD, now), Ok(false));
+        assert_eq!(sessions.active(now).len(), 1);
+        assert!(sessions.end(GUILD));
+        assert!(!sessions.is_active(GUILD));
+    }
+
+    #[test]
+    fn ending_twice_frees_nothing_else() {
+        let sessions = VoiceSessions::new(Some(2));
+        let now = Instant::now();
+        let other = GuildId::new(2);
+        sessions.start(GUILD, now).unwrap();
+        sessions.start(other, now).unwrap();
+
+        assert!(sessions.end(GUILD));
+        assert!(!sessions.end(GUILD));
+        // The other guild keeps its slot, only the one that was freed is available
+        assert!(sessions.is_active(other));
+        assert_eq!(sessions.start(GuildId::new(3), now), Ok(true));
+        assert_eq!(
+            sessions.start(GuildId::new(4), now),
+            Err(SessionsFull {
+                active: 2,
+                limit: 2
+            })
+        );
+    }
+
+    #[test]
+    fn ending_frees_a_slot_at_the_limit() {
+        let sessions = VoiceSessions::new(Some(1));
+        let now = Instant::now();
+        let other = GuildId::new(2);
+        sessions.start(GUILD, now).unwrap();
+        assert!(sessions.start(other, now).is_err());
+        sessions.end(GUILD);
+        assert_eq!(sessions.start(other, now), Ok(true));
+    }
+
+    #[test]
+    fn oldest_sessions_come_first() {
+        let sessions = VoiceSessions::new(None);
+        let start = Instant::now();
+        let other = GuildId::new(2);
+        sessions
+            .start(other, start + Duration::from_secs(60))
+            .unwrap();
+        sessions.start(GUILD, start).unwrap();
+
+        let now = start + Duration::from_secs(90);
+        assert_eq!(
+            sessions.active(now),
+            vec![
+                (GUILD, Duration::from_secs(90)),
+                (other, Duration::from_secs(30))
+            ]
+        );
+    }
+}
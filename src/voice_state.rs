@@ -0,0 +1,425 @@
+use crate::auto_pause::AutoPauseReason;
+use crate::commands::util::{get_guild_settings, get_voice_sessions};
+use crate::departures::{leave_with_reason, LeaveReason};
+use crate::events::PlaybackEvent;
+use crate::{AutoPausesKey, CommandError, GuildOutagesKey, PlaybackEventsKey, VoiceDebouncerKey};
+use log::{error, info, warn};
+use serenity::all::{ChannelId, Guild, GuildId, UserId, VoiceState};
+use serenity::client::Context;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Bursts of voice state updates within this window are evaluated once
+const DEBOUNCE_WINDOW: Duration = Duration::from_secs(2);
+
+/// Whether an update moved a user between channels or changed whether they can hear anything.
+/// Mute, camera and stream toggles don't change who is listening.
+pub fn affects_audience(old: Option<&VoiceState>, new: &VoiceState) -> bool {
+    match old {
+        Some(old) => old.channel_id != new.channel_id || is_deafened(old) != is_deafened(new),
+        None => true,
+    }
+}
+
+fn is_deafened(state: &VoiceState) -> bool {
+    state.self_deaf || state.deaf
+}
+
+/// Who is in the channel of the bot besides itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Audience {
+    /// Nobody, or only other bots
+    Alone,
+    /// Only users who deafened themselves or were deafened
+    Deafened,
+    Listening,
+}
+
+/// Classifies the users in the channel. Other bots, like a second music bot, are never counted.
+pub fn audience(
+    voice_states: &HashMap<UserId, VoiceState>,
+    channel_id: ChannelId,
+    bot_id: UserId,
+    is_bot: impl Fn(&VoiceState) -> bool,
+) -> Audience {
+    let users = voice_states
+        .values()
+        .filter(|state| state.channel_id == Some(channel_id) && state.user_id != bot_id)
+        .filter(|state| !is_bot(state))
+        .collect::<Vec<_>>();
+
+    if users.is_empty() {
+        Audience::Alone
+    } else if users.iter().all(|state| is_deafened(state)) {
+        Audience::Deafened
+    } else {
+        Audience::Listening
+    }
+}
+
+/// Bot flag of the user from the cached member or the member in the voice state. Unknown if
+/// neither is available.
+fn cached_bot_flag(guild: &Guild, state: &VoiceState) -> Option<bool> {
+    match guild.members.get(&state.user_id) {
+        Some(member) => Some(member.user.bot),
+        None => state.member.as_ref().map(|m| m.user.bot),
+    }
+}
+
+/// Users with an unknown bot flag are assumed to be humans, so the bot rather stays too long
+/// than leaving a listener
+fn is_bot(guild: &Guild, state: &VoiceState, fetched_bots: &HashSet<UserId>) -> bool {
+    cached_bot_flag(guild, state).unwrap_or_else(|| fetched_bots.contains(&state.user_id))
+}
+
+/// Fetches the members in voice channels whose bot flag is not cached and returns the bots
+async fn fetch_unknown_bots(ctx: &Context, guild_id: GuildId) -> HashSet<UserId> {
+    let unknown = match guild_id.to_guild_cached(ctx) {
+        Some(guild) => guild
+            .voice_states
+            .values()
+            .filter(|state| cached_bot_flag(&guild, state).is_none())
+            .map(|state| state.user_id)
+            .collect::<Vec<_>>(),
+        None => return HashSet::new(),
+    };
+
+    let mut bots = HashSet::new();
+    for user_id in unknown {
+        match guild_id.member(ctx, user_id).await {
+            Ok(member) if member.user.bot => {
+                bots.insert(user_id);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to fetch member {user_id} in guild {guild_id}: {e}"),
+        }
+    }
+    bots
+}
+
+/// Number of users in the channel that are not bots. Users who just left are not counted, even
+/// if the bot did not leave yet because of the grace period.
+pub fn listener_count(guild: &Guild, channel_id: ChannelId) -> usize {
+    guild
+        .voice_states
+        .values()
+        .filter(|state| state.channel_id == Some(channel_id))
+        .filter(|state| !is_bot(guild, state, &HashSet::new()))
+        .count()
+}
+
+/// Whether the bot is in use in its channel and must not be moved somewhere else. An idle bot
+/// waiting for its grace period to run out in an empty channel can follow the next user.
+/// Unknown listeners (guild not cached) count as occupied.
+pub fn is_occupied(listeners: Option<usize>, queue_len: usize) -> bool {
+    match listeners {
+        Some(listeners) => listeners > 0 || queue_len > 0,
+        None => true,
+    }
+}
+
+/// Guilds with a scheduled occupancy evaluation
+#[derive(Default)]
+pub struct VoiceDebouncer {
+    pending: Mutex<HashSet<GuildId>>,
+}
+
+impl VoiceDebouncer {
+    /// Returns false if an evaluation is already scheduled for the guild
+    fn schedule(&self, guild_id: GuildId) -> bool {
+        self.pending.lock().unwrap().insert(guild_id)
+    }
+
+    pub fn is_pending(&self, guild_id: GuildId) -> bool {
+        self.pending.lock().unwrap().contains(&guild_id)
+    }
+
+    fn finish(&self, guild_id: GuildId) {
+        self.pending.lock().unwrap().remove(&guild_id);
+    }
+}
+
+/// Schedules an occupancy evaluation for the guild, unless one is already waiting
+pub async fn on_voice_state_update(
+    ctx: &Context,
+    old: Option<&VoiceState>,
+    new: &VoiceState,
+    bot_id: UserId,
+) {
+    if !affects_audience(old, new) {
+        return;
+    }
+    let Some(guild_id) = new.guild_id else {
+        error!("The bot is in a private call (How???)");
+        return;
+    };
+
+    let debouncer = ctx
+        .data
+        .read()
+        .await
+        .get::<VoiceDebouncerKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap");
+    if !debouncer.schedule(guild_id) {
+        return;
+    }
+
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        sleep(DEBOUNCE_WINDOW).await;
+        // Updates arriving from now on need a new evaluation
+        debouncer.finish(guild_id);
+        if let Err(e) = evaluate_occupancy(&ctx, guild_id, bot_id).await {
+            error!(
+                "Failed to evaluate voice occupancy in guild {}: {}",
+                guild_id, e
+            );
+        }
+    });
+}
+
+/// Leaves when the bot was disconnected by a moderator or is alone in its channel, pauses while
+/// everyone is deafened if the guild wants that
+async fn evaluate_occupancy(
+    ctx: &Context,
+    guild_id: GuildId,
+    bot_id: UserId,
+) -> Result<(), CommandError> {
+    // Voice states are unreliable during an outage, the session is resumed afterwards
+    let frozen = ctx
+        .data
+        .read()
+        .await
+        .get::<GuildOutagesKey>()
+        .cloned()
+        .expect("Guaranteed to exist in the typemap")
+        .is_frozen(guild_id);
+    if frozen {
+        return Ok(());
+    }
+
+    let songbird = songbird::get(ctx)
+        .await
+        .ok_or(CommandError::SongbirdNotFound)?;
+
+    let Some(call_lock) = songbird.get(guild_id) else {
+        // Not in a call
+        return Ok(());
+    };
+    // Before locking the call, fetching can take a moment
+    let fetched_bots = fetch_unknown_bots(ctx, guild_id).await;
+    let mut call = call_lock.lock().await;
+
+    // Clear queue when forcefully disconnected by a moderator. An idle bot has no queue, but its
+    // session is still counted until it leaves.
+    let disconnected = call.current_channel().is_none()
+        && (!call.queue().is_empty() || get_voice_sessions(ctx).await.is_active(guild_id));
+    if disconnected {
+        info!(
+            "Bot got disconnected from a voice channel in guild {}",
+            guild_id
+        );
+        leave_with_reason(
+            &ctx.data,
+            guild_id,
+            &mut call,
+            LeaveReason::ForcedDisconnect,
+        )
+        .await
+        .map_err(|_| CommandError::LeaveVoice)?;
+    }
+
+    let Some(channel_id) = call.current_channel() else {
+        return Ok(());
+    };
+    let channel_id = ChannelId::from(channel_id.0);
+    let settings = get_guild_settings(ctx).await.get(guild_id);
+    let audience = {
+        let guild = guild_id.to_guild_cached(ctx).expect("Guild not in cache");
+        audience(&guild.voice_states, channel_id, bot_id, |state| {
+            is_bot(&guild, state, &fetched_bots)
+        })
+    };
+
+    // Check if the bot is the only one left in its channel, except in the 24/7 channel
+    if audience == Audience::Alone && settings.always_on_channel != Some(channel_id) {
+        leave_with_reason(&ctx.data, guild_id, &mut call, LeaveReason::EmptyChannel)
+            .await
+            .map_err(|_| CommandError::LeaveVoice)?;
+        return Ok(());
+    }
+
+    let (auto_pauses, events) = {
+        let data = ctx.data.read().await;
+        (
+            data.get::<AutoPausesKey>()
+                .cloned()
+                .expect("Guaranteed to exist in the typemap"),
+            data.get::<PlaybackEventsKey>()
+                .cloned()
+                .expect("Guaranteed to exist in the typemap"),
+        )
+    };
+    match (audience, settings.deafened_pause_delay) {
+        (Audience::Deafened, Some(delay)) => {
+            if auto_pauses.reason(guild_id).is_some() || !auto_pauses.schedule(guild_id) {
+                return Ok(());
+            }
+            // Undeafening before the delay runs out cancels the pause
+            let call_lock = call_lock.clone();
+            tokio::spawn(async move {
+                sleep(delay).await;
+                let call = call_lock.lock().await;
+                if auto_pauses
+                    .pause(guild_id, call.queue(), AutoPauseReason::NobodyListening)
+                    .await
+                {
+                    events.publish(guild_id, PlaybackEvent::PlayStateChanged);
+                }
+            });
+        }
+        _ => {
+            if auto_pauses.resume(guild_id, call.queue()) {
+                events.publish(guild_id, PlaybackEvent::PlayStateChanged);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const BOT: UserId = UserId::new(1);
+    const OTHER_BOT: UserId = UserId::new(2);
+    const CHANNEL: ChannelId = ChannelId::new(10);
+    const OTHER_CHANNEL: ChannelId = ChannelId::new(11);
+
+    fn state(user_id: UserId, channel_id: Option<ChannelId>, self_deaf: bool) -> VoiceState {
+        serde_json::from_value(json!({
+            "channel_id": channel_id,
+            "user_id": user_id,
+            "session_id": "session",
+            "deaf": false,
+            "mute": false,
+            "self_deaf": self_deaf,
+            "self_mute": false,
+            "self_video": false,
+            "suppress": false,
+            "request_to_speak_timestamp": null,
+        }))
+        .unwrap()
+    }
+
+    fn states(states: impl IntoIterator<Item = VoiceState>) -> HashMap<UserId, VoiceState> {
+        states
+            .into_iter()
+            .map(|state| (state.user_id, state))
+            .collect()
+    }
+
+    fn audience_of(voice_states: &HashMap<UserId, VoiceState>) -> Audience {
+        audience(voice_states, CHANNEL, BOT, |state| {
+            state.user_id == OTHER_BOT
+        })
+    }
+
+    #[test]
+    fn joins_leaves_and_moves_affect_the_audience() {
+        let user = UserId::new(100);
+        let joined = state(user, Some(CHANNEL), false);
+        assert!(affects_audience(None, &joined));
+        assert!(affects_audience(
+            Some(&joined),
+            &state(user, Some(OTHER_CHANNEL), false)
+        ));
+        assert!(affects_audience(Some(&joined), &state(user, None, false)));
+    }
+
+    #[test]
+    fn deafening_affects_the_audience_but_muting_does_not() {
+        let user = UserId::new(100);
+        let joined = state(user, Some(CHANNEL), false);
+        assert!(affects_audience(
+            Some(&joined),
+            &state(user, Some(CHANNEL), true)
+        ));
+
+        let mut muted = joined.clone();
+        muted.self_mute = true;
+        muted.self_video = true;
+        assert!(!affects_audience(Some(&joined), &muted));
+
+        // Deafened by a moderator counts like deafening oneself
+        let mut server_deafened = joined.clone();
+        server_deafened.deaf = true;
+        assert!(affects_audience(Some(&joined), &server_deafened));
+    }
+
+    #[test]
+    fn bot_alone_in_its_channel() {
+        assert_eq!(audience_of(&states([])), Audience::Alone);
+        assert_eq!(
+            audience_of(&states([state(BOT, Some(CHANNEL), false)])),
+            Audience::Alone
+        );
+    }
+
+    #[test]
+    fn users_in_other_channels_are_not_counted() {
+        let voice_states = states([
+            state(BOT, Some(CHANNEL), false),
+            state(UserId::new(100), Some(OTHER_CHANNEL), false),
+            state(UserId::new(101), None, false),
+        ]);
+        assert_eq!(audience_of(&voice_states), Audience::Alone);
+    }
+
+    #[test]
+    fn channel_with_only_other_bots_is_alone() {
+        let voice_states = states([
+            state(BOT, Some(CHANNEL), false),
+            state(OTHER_BOT, Some(CHANNEL), false),
+        ]);
+        assert_eq!(audience_of(&voice_states), Audience::Alone);
+    }
+
+    #[test]
+    fn deafened_users_do_not_listen() {
+        let mut server_deafened = state(UserId::new(101), Some(CHANNEL), false);
+        server_deafened.deaf = true;
+        let voice_states = states([
+            state(BOT, Some(CHANNEL), false),
+            state(OTHER_BOT, Some(CHANNEL), false),
+            state(UserId::new(100), Some(CHANNEL), true),
+            server_deafened,
+        ]);
+        assert_eq!(audience_of(&voice_states), Audience::Deafened);
+    }
+
+    #[test]
+    fn one_hearing_user_is_enough() {
+        let voice_states = states([
+            state(BOT, Some(CHANNEL), true),
+            state(UserId::new(100), Some(CHANNEL), true),
+            state(UserId::new(101), Some(CHANNEL), false),
+        ]);
+        assert_eq!(audience_of(&voice_states), Audience::Listening);
+    }
+
+    #[test]
+    fn occupied_with_listeners_or_a_queue() {
+        assert!(!is_occupied(Some(0), 0));
+        assert!(is_occupied(Some(0), 3));
+        assert!(is_occupied(Some(2), 0));
+        assert!(is_occupied(Some(2), 3));
+        // Without a cached guild the listeners are unknown
+        assert!(is_occupied(None, 0));
+    }
+}
@@ -0,0 +1,418 @@
+use crate::error_rates::ErrorRates;
+use crate::events::{PlaybackEvent, PlaybackEventBus};
+use crate::overlay::{OverlaySnapshot, OverlayTokens};
+use crate::persistence::PersistenceHealth;
+use crate::position_cache::PositionCache;
+use crate::start_latency::StartLatency;
+use hyper::header::{
+    ACCEPT, CONNECTION, CONTENT_TYPE, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, UPGRADE,
+};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use log::{debug, error, info};
+use reqwest::Url;
+use serde_json::json;
+use serenity::all::GuildId;
+use serenity::futures::{SinkExt, StreamExt};
+use songbird::Songbird;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::interval;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Interval of the position updates on overlay sockets
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Shared state of the optional http server
+pub struct WebState {
+    pub songbird: Arc<Songbird>,
+    pub overlay_tokens: Arc<OverlayTokens>,
+    pub playback_events: Arc<PlaybackEventBus>,
+    pub start_latency: Arc<StartLatency>,
+    pub position_cache: Arc<PositionCache>,
+    pub error_rates: Arc<ErrorRates>,
+    pub persistence: Arc<PersistenceHealth>,
+}
+
+/// Runs the read-only http server until the process exits
+pub async fn serve(addr: SocketAddr, state: Arc<WebState>) {
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(state, request).await) }
+            }))
+        }
+    });
+
+    info!("Starting http server on {}", addr);
+    if let Err(e) = Server::bind(&addr).serve(make_service).await {
+        error!("Http server failed: {}", e);
+    }
+}
+
+async fn handle(state: Arc<WebState>, request: Request<Body>) -> Response<Body> {
+    if request.method() != Method::GET {
+        return status_response(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    // The uri only contains path and query, so a dummy base is needed for parsing
+    let Ok(url) = Url::parse(&format!("http://localhost{}", request.uri())) else {
+        return status_response(StatusCode::BAD_REQUEST);
+    };
+    let segments = url
+        .path_segments()
+        .map(|s| s.collect::<Vec<_>>())
+        .unwrap_or_default();
+
+    // Aggregated over all guilds, so it needs no token
+    if segments == ["metrics"] {
+        let mut metrics = state.start_latency.render_prometheus();
+        metrics += &render_queue_gauge(&state.songbird).await;
+        metrics += &state.error_rates.render_prometheus();
+        return content_response("text/plain; version=0.0.4", metrics);
+    }
+    // A degraded store is reported, but the bot keeps serving, so it is no reason for a restart
+    if segments == ["health"] {
+        return content_response("application/json", render_health(&state.persistence));
+    }
+
+    let ["guilds", guild_id, endpoint] = segments.as_slice() else {
+        return status_response(StatusCode::NOT_FOUND);
+    };
+    let Some(guild_id) = guild_id.parse::<u64>().ok().filter(|id| *id != 0) else {
+        return status_response(StatusCode::NOT_FOUND);
+    };
+    let guild_id = GuildId::new(guild_id);
+    let token = url
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .map(|(_, v)| v.into_owned())
+        .unwrap_or_default();
+
+    // Unknown guilds and wrong tokens are indistinguishable on purpose
+    if !state.overlay_tokens.is_valid(guild_id, &token) {
+        return status_response(StatusCode::NOT_FOUND);
+    }
+
+    match *endpoint {
+        "now" => {
+            let snapshot =
+                OverlaySnapshot::read(&state.songbird, &state.position_cache, guild_id).await;
+            let wants_html = request
+                .headers()
+                .get(ACCEPT)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.contains("text/html"));
+
+            if wants_html {
+                content_response("text/html; charset=utf-8", snapshot.to_html())
+            } else {
+                match serde_json::to_string(&snapshot) {
+                    Ok(json) => content_response("application/json", json),
+                    Err(_) => status_response(StatusCode::INTERNAL_SERVER_ERROR),
+                }
+            }
+        }
+        "ws" => upgrade_websocket(state, guild_id, request),
+        _ => status_response(StatusCode::NOT_FOUND),
+    }
+}
+
+/// Overall state and the state of every store backed by a file
+fn render_health(persistence: &PersistenceHealth) -> String {
+    let stores = persistence
+        .stores()
+        .into_iter()
+        .map(|(name, health)| {
+            let store = json!({
+                "degraded": health.is_degraded(),
+                "loaded": health.unavailable.is_none(),
+                "failed_writes": health.failed_writes,
+                "error": health.unavailable.or(health.last_error),
+            });
+            (name.to_owned(), store)
+        })
+        .collect::<serde_json::Map<_, _>>();
+    let status = match persistence.is_degraded() {
+        true => "degraded",
+        false => "ok",
+    };
+    json!({ "status": status, "stores": stores }).to_string()
+}
+
+/// Entries of all queues together, to see how close the host is to its memory limits
+async fn render_queue_gauge(songbird: &Songbird) -> String {
+    let mut queued = 0;
+    for (_, call) in songbird.iter() {
+        queued += call.lock().await.queue().len();
+    }
+    let name = "gerbot_queued_tracks";
+    format!(
+        "# HELP {name} Tracks in the queues of all guilds\n# TYPE {name} gauge\n{name} {queued}\n"
+    )
+}
+
+/// Accepts a websocket handshake and pushes the playback events of the guild to the client
+fn upgrade_websocket(
+    state: Arc<WebState>,
+    guild_id: GuildId,
+    request: Request<Body>,
+) -> Response<Body> {
+    let Some(key) = request.headers().get(SEC_WEBSOCKET_KEY) else {
+        return status_response(StatusCode::BAD_REQUEST);
+    };
+    let accept = derive_accept_key(key.as_bytes());
+
+    tokio::spawn(async move {
+        match hyper::upgrade::on(request).await {
+            Ok(upgraded) => {
+                let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                push_events(&state, guild_id, socket).await;
+            }
+            Err(e) => debug!("Websocket upgrade failed: {}", e),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(CONNECTION, "upgrade")
+        .header(UPGRADE, "websocket")
+        .header(SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Body::empty())
+        .expect("Static response parts are valid")
+}
+
+/// Runs until the client disconnects. Only reads the playback state, so a dropped socket never
+/// affects playback.
+async fn push_events<S: AsyncRead + AsyncWrite + Unpin>(
+    state: &WebState,
+    guild_id: GuildId,
+    mut socket: WebSocketStream<S>,
+) {
+    let mut events = state.playback_events.subscribe();
+    let mut heartbeat = interval(HEARTBEAT_INTERVAL);
+
+    let initial = snapshot_message(state, guild_id, None).await;
+    if socket.send(initial).await.is_err() {
+        return;
+    }
+
+    loop {
+        let message = tokio::select! {
+            event = events.recv() => match event {
+                Ok((event_guild, event)) if event_guild == guild_id => {
+                    snapshot_message(state, guild_id, Some(event)).await
+                }
+                Ok(_) => continue,
+                // Missed events are covered by sending the current state
+                Err(RecvError::Lagged(_)) => snapshot_message(state, guild_id, None).await,
+                Err(RecvError::Closed) => return,
+            },
+            _ = heartbeat.tick() => heartbeat_message(state, guild_id).await,
+            incoming = socket.next() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                // Pings are answered by tungstenite, everything else is ignored
+                Some(Ok(_)) => continue,
+            },
+        };
+
+        if socket.send(message).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn snapshot_message(
+    state: &WebState,
+    guild_id: GuildId,
+    event: Option<PlaybackEvent>,
+) -> Message {
+    let snapshot = OverlaySnapshot::read(&state.songbird, &state.position_cache, guild_id).await;
+    let event = event_name(event, snapshot.now_playing.is_none());
+    Message::Text(json!({ "event": event, "state": snapshot }).to_string())
+}
+
+/// Name of an event on overlay sockets. Everything is idle once nothing plays.
+fn event_name(event: Option<PlaybackEvent>, idle: bool) -> &'static str {
+    match event {
+        _ if idle => "idle",
+        Some(PlaybackEvent::TrackStarted) => "track_started",
+        Some(PlaybackEvent::QueueChanged) => "queue_changed",
+        Some(PlaybackEvent::Stopped) => "stopped",
+        Some(PlaybackEvent::PlayStateChanged) => "play_state_changed",
+        Some(PlaybackEvent::TrackEnded { .. }) => "track_ended",
+        None => "state",
+    }
+}
+
+/// Position of the current track and whether it is paused. Guilds without an active call only
+/// get a cheap idle message.
+async fn heartbeat_message(state: &WebState, guild_id: GuildId) -> Message {
+    let current = match state.songbird.get(guild_id) {
+        Some(call) => call.lock().await.queue().current(),
+        None => None,
+    };
+    let sample = match current {
+        Some(current) => state.position_cache.read(guild_id, &current).await,
+        None => None,
+    };
+
+    let message = match sample {
+        Some(sample) => json!({
+            "event": "heartbeat",
+            "position_secs": sample.position_at(Instant::now()).as_secs(),
+            "paused": !sample.playing,
+            "buffering": state.position_cache.is_buffering(guild_id),
+        }),
+        None => json!({ "event": "idle" }),
+    };
+    Message::Text(message.to_string())
+}
+
+fn content_response(content_type: &'static str, body: String) -> Response<Body> {
+    Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .expect("Static response parts are valid")
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .expect("Static response parts are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::end_reason::EndReason;
+    use serde_json::Value;
+    use tokio::io::{duplex, DuplexStream};
+    use tokio::task::JoinHandle;
+    use tokio::time::timeout;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const OTHER_GUILD: GuildId = GuildId::new(2);
+
+    fn test_state() -> Arc<WebState> {
+        Arc::new(WebState {
+            songbird: Songbird::serenity(),
+            overlay_tokens: Arc::default(),
+            playback_events: Arc::default(),
+            start_latency: Arc::default(),
+            position_cache: Arc::default(),
+            error_rates: Arc::default(),
+            persistence: Arc::default(),
+        })
+    }
+
+    /// Runs [push_events] on one end of an in-memory socket and returns the client end
+    async fn connect(state: &Arc<WebState>) -> (WebSocketStream<DuplexStream>, JoinHandle<()>) {
+        let (server, client) = duplex(64 * 1024);
+        let state = state.clone();
+        let pusher = tokio::spawn(async move {
+            let socket = WebSocketStream::from_raw_socket(server, Role::Server, None).await;
+            push_events(&state, GUILD, socket).await;
+        });
+        let client = WebSocketStream::from_raw_socket(client, Role::Client, None).await;
+        (client, pusher)
+    }
+
+    /// The next snapshot message, heartbeats are skipped
+    async fn next_snapshot(client: &mut WebSocketStream<DuplexStream>) -> Option<Value> {
+        loop {
+            let message = timeout(Duration::from_millis(200), client.next())
+                .await
+                .ok()??
+                .ok()?;
+            let Message::Text(text) = message else {
+                continue;
+            };
+            let value: Value = serde_json::from_str(&text).unwrap();
+            if value.get("state").is_some() {
+                return Some(value);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_the_state_on_connect_and_on_events_of_the_guild() {
+        let state = test_state();
+        let (mut client, _pusher) = connect(&state).await;
+
+        let initial = next_snapshot(&mut client).await.expect("initial state");
+        assert_eq!(initial["event"], "idle");
+        assert!(initial["state"]["now_playing"].is_null());
+
+        state
+            .playback_events
+            .publish(OTHER_GUILD, PlaybackEvent::TrackStarted);
+        state
+            .playback_events
+            .publish(GUILD, PlaybackEvent::QueueChanged);
+        assert!(next_snapshot(&mut client).await.is_some());
+        // The event of the other guild did not produce a message
+        assert!(next_snapshot(&mut client).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn missed_events_are_covered_by_one_state() {
+        let state = test_state();
+        let (mut client, _pusher) = connect(&state).await;
+        next_snapshot(&mut client).await.expect("initial state");
+
+        for _ in 0..200 {
+            state
+                .playback_events
+                .publish(GUILD, PlaybackEvent::PlayStateChanged);
+        }
+        let mut received = 0;
+        while next_snapshot(&mut client).await.is_some() {
+            received += 1;
+        }
+        assert!(received > 0);
+        assert!(received <= 200);
+    }
+
+    #[tokio::test]
+    async fn stops_when_the_client_closes() {
+        let state = test_state();
+        let (mut client, pusher) = connect(&state).await;
+        next_snapshot(&mut client).await.expect("initial state");
+
+        client.close(None).await.unwrap();
+        timeout(Duration::from_secs(1), pusher)
+            .await
+            .expect("push_events returned")
+            .unwrap();
+    }
+
+    #[test]
+    fn event_names() {
+        assert_eq!(
+            event_name(Some(PlaybackEvent::TrackStarted), false),
+            "track_started"
+        );
+        assert_eq!(
+            event_name(
+                Some(PlaybackEvent::TrackEnded {
+                    reason: EndReason::Skipped
+                }),
+                false
+            ),
+            "track_ended"
+        );
+        assert_eq!(event_name(None, false), "state");
+        assert_eq!(event_name(Some(PlaybackEvent::TrackStarted), true), "idle");
+    }
+}
@@ -1,16 +1,27 @@
 #![allow(dead_code)]
 
+use crate::error_rates::{ErrorRates, ErrorSource};
 use crate::youtube::YtResourceId::{Channel, Playlist, Video};
+use crate::ytdlp::{YtDlpConfig, YtDlpError};
+use log::warn;
 use reqwest::{Client as HttpClient, Url};
-use std::collections::HashMap;
-use std::sync::Arc;
+use serenity::futures::future::BoxFuture;
+use serenity::futures::FutureExt;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use thiserror::Error;
 use time::OffsetDateTime;
 
+pub mod quota;
 mod yt_api;
+mod yt_dlp;
 
+use crate::youtube::quota::{QuotaEstimator, QuotaMode, QuotaStatus};
 use crate::youtube::yt_api::YtApiClient;
+use crate::youtube::yt_dlp::YtDlpProvider;
 pub use yt_api::models::YtLiveBroadcastContent;
 pub use yt_api::models::YtThumbnailInfo;
 pub use yt_api::models::YtThumbnailSize;
@@ -35,6 +46,8 @@ pub struct YtResource {
     pub channel_id: String,
     pub channel_title: String,
     pub thumbnails: HashMap<YtThumbnailSize, YtThumbnailInfo>,
+    /// Only known for videos, and only if the source includes it
+    pub duration: Option<Duration>,
 }
 
 impl YtResource {
@@ -59,6 +72,7 @@ impl From<YtVideo> for YtResource {
             channel_id: value.channel_id,
             channel_title: value.channel_title,
             thumbnails: value.thumbnails,
+            duration: Some(value.duration),
         }
     }
 }
@@ -73,6 +87,7 @@ impl From<YtPlaylist> for YtResource {
             channel_id: value.channel_id,
             channel_title: value.channel_title,
             thumbnails: value.thumbnails,
+            duration: None,
         }
     }
 }
@@ -105,7 +120,19 @@ pub struct YtPlaylist {
     pub channel_id: String,
     pub channel_title: String,
     pub thumbnails: HashMap<YtThumbnailSize, YtThumbnailInfo>,
+    /// Number of items including unavailable ones, if known
+    pub item_count: Option<u32>,
     pub videos: Vec<YtResource>,
+    /// Set if loading the items stopped before the end of the playlist
+    pub truncated: Option<YtPlaylistTruncation>,
+}
+
+/// Why only the first items of a playlist were loaded
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YtPlaylistTruncation {
+    QuotaExceeded,
+    PageLimit,
+    Failed,
 }
 
 impl YtPlaylist {
@@ -132,8 +159,13 @@ pub enum YtApiError {
     InvalidId,
     #[error("The youtube api quota for today are used up")]
     QuotaExceeded,
+    #[error("The youtube api key is invalid")]
+    InvalidKey,
+    #[error("yt-dlp error")]
+    YtDlp(#[from] YtDlpError),
 }
 
+#[derive(Clone, Copy, Debug)]
 pub enum YtSearchFilter {
     Videos,
     Playlists,
@@ -141,15 +173,177 @@ pub enum YtSearchFilter {
     Any,
 }
 
+/// A backend for YouTube metadata and search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YtProvider {
+    Api,
+    YtDlp,
+}
+
+impl FromStr for YtProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "api" => Ok(YtProvider::Api),
+            "ytdlp" => Ok(YtProvider::YtDlp),
+            "invidious" | "oembed" => Err(format!("provider `{s}` is not supported yet")),
+            _ => Err(format!("unknown provider `{s}`")),
+        }
+    }
+}
+
+impl Display for YtProvider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            YtProvider::Api => "api",
+            YtProvider::YtDlp => "ytdlp",
+        })
+    }
+}
+
+/// Parses a provider order, either comma separated (`api,ytdlp`) or as json list
+/// (`["api", "ytdlp"]`)
+pub fn parse_provider_order(s: &str) -> Result<Vec<YtProvider>, String> {
+    let names = if s.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<String>>(s).map_err(|e| e.to_string())?
+    } else {
+        s.split(',').map(|n| n.trim().to_owned()).collect()
+    };
+
+    let mut order = Vec::new();
+    for name in names {
+        let provider = name.parse::<YtProvider>()?;
+        if order.contains(&provider) {
+            return Err(format!("provider `{provider}` is listed twice"));
+        }
+        order.push(provider);
+    }
+
+    if order.is_empty() {
+        Err("no provider configured".to_owned())
+    } else {
+        Ok(order)
+    }
+}
+
+/// Kind of request sent to a provider
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YtOperation {
+    Search,
+    Video,
+    Playlist,
+}
+
+/// Number of served requests that are remembered for the status output
+const RECENT_PROVIDERS: usize = 10;
+
 #[derive(Clone, Debug)]
 pub struct YoutubeClient {
     pub yt_api_client: Option<Arc<YtApiClient>>,
+    yt_dlp: YtDlpProvider,
+    provider_order: Arc<[YtProvider]>,
+    recent: Arc<Mutex<VecDeque<(YtOperation, YtProvider)>>>,
+    error_rates: Arc<ErrorRates>,
 }
 
 impl YoutubeClient {
-    pub fn new(http_client: HttpClient, yt_api_key: Option<String>) -> Self {
+    pub fn new(
+        http_client: HttpClient,
+        yt_api_key: Option<String>,
+        ytdlp_config: Arc<YtDlpConfig>,
+        provider_order: Vec<YtProvider>,
+        quota: QuotaEstimator,
+        error_rates: Arc<ErrorRates>,
+    ) -> Self {
         Self {
-            yt_api_client: yt_api_key.map(|key| Arc::new(YtApiClient::new(http_client, key))),
+            yt_api_client: yt_api_key
+                .map(|key| Arc::new(YtApiClient::new(http_client, key, quota))),
+            yt_dlp: YtDlpProvider::new(ytdlp_config),
+            provider_order: provider_order.into(),
+            recent: Arc::new(Mutex::new(VecDeque::with_capacity(RECENT_PROVIDERS))),
+            error_rates,
+        }
+    }
+
+    /// Sends the requests of the official api to `base_url` instead, for a local fake of the api
+    pub fn with_api_base_url(mut self, base_url: &str) -> Self {
+        if let Some(client) = self.yt_api_client.as_mut().and_then(Arc::get_mut) {
+            client.set_base_url(base_url.to_owned());
+        }
+        self
+    }
+
+    pub fn provider_order(&self) -> &[YtProvider] {
+        &self.provider_order
+    }
+
+    /// Which provider served the last requests, newest first
+    pub fn recent_providers(&self) -> Vec<(YtOperation, YtProvider)> {
+        self.recent.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Estimated quota usage of the official api, if it is configured
+    pub fn quota_status(&self) -> Option<QuotaStatus> {
+        self.yt_api_client
+            .as_ref()
+            .map(|client| client.quota.status())
+    }
+
+    /// Whether autocomplete should not search anymore, to leave the remaining quota to commands
+    pub fn autocomplete_degraded(&self) -> bool {
+        self.yt_api_client
+            .as_ref()
+            .is_some_and(|client| client.quota.mode() == QuotaMode::AutocompleteDegraded)
+    }
+
+    /// Asks the providers in the configured order until one of them succeeds
+    async fn first_success<'a, T>(
+        &'a self,
+        operation: YtOperation,
+        request: impl Fn(YtProvider) -> BoxFuture<'a, Result<T, YtApiError>>,
+    ) -> Result<T, YtApiError> {
+        let mut last_error = None;
+        for &provider in self.provider_order.iter() {
+            let result = request(provider).await;
+            if provider == YtProvider::Api {
+                self.record_api_call(&result);
+            }
+            match result {
+                Ok(result) => {
+                    let mut recent = self.recent.lock().unwrap();
+                    recent.truncate(RECENT_PROVIDERS - 1);
+                    recent.push_front((operation, provider));
+                    return Ok(result);
+                }
+                // Other providers would not find it either
+                Err(YtApiError::InvalidId) => return Err(YtApiError::InvalidId),
+                Err(e) => {
+                    warn!("YouTube provider {provider} failed for {operation:?}: {e}");
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or(YtApiError::QuotaExceeded))
+    }
+
+    /// Counts a call of the official api for the error rates. Missing ids are a normal answer,
+    /// and calls refused for quota or the key either never reached the api or are no outage.
+    fn record_api_call<T>(&self, result: &Result<T, YtApiError>) {
+        match result {
+            Ok(_) | Err(YtApiError::InvalidId) => {
+                self.error_rates.record(ErrorSource::YoutubeApi, true)
+            }
+            Err(YtApiError::QuotaExceeded | YtApiError::InvalidKey) => {}
+            Err(_) => self.error_rates.record(ErrorSource::YoutubeApi, false),
+        }
+    }
+
+    /// The official api client, if it is configured and has quota left
+    async fn available_api(&self) -> Result<&YtApiClient, YtApiError> {
+        match &self.yt_api_client {
+            Some(yt_api_client) if !yt_api_client.is_ratelimited().await => Ok(yt_api_client),
+            _ => Err(YtApiError::QuotaExceeded),
         }
     }
 
@@ -159,38 +353,198 @@ impl YoutubeClient {
         filter: YtSearchFilter,
         n_results: u8,
     ) -> Result<Vec<YtResource>, YtApiError> {
-        match &self.yt_api_client {
-            Some(yt_api_client) if !yt_api_client.is_ratelimited().await => {
-                yt_api_client.search(query, filter, n_results).await
-            }
-            _ => {
-                //TODO: Implement Invidious as youtube api fallback
-                Err(YtApiError::QuotaExceeded)
+        self.first_success(YtOperation::Search, |provider| {
+            async move {
+                match provider {
+                    YtProvider::Api => {
+                        self.available_api()
+                            .await?
+                            .search(query, filter, n_results)
+                            .await
+                    }
+                    YtProvider::YtDlp => self.yt_dlp.search(query, filter, n_results).await,
+                }
             }
-        }
+            .boxed()
+        })
+        .await
     }
 
     pub async fn get_video(&self, id: &str) -> Result<YtVideo, YtApiError> {
-        match &self.yt_api_client {
-            Some(yt_api_client) if !yt_api_client.is_ratelimited().await => {
-                yt_api_client.get_video(id).await
-            }
-            _ => {
-                //TODO: Implement Invidious as youtube api fallback
-                Err(YtApiError::QuotaExceeded)
+        self.first_success(YtOperation::Video, |provider| {
+            async move {
+                match provider {
+                    YtProvider::Api => self.available_api().await?.get_video(id).await,
+                    YtProvider::YtDlp => self.yt_dlp.get_video(id).await,
+                }
             }
-        }
+            .boxed()
+        })
+        .await
     }
 
-    pub async fn get_playlist(&self, id: &str) -> Result<YtPlaylist, YtApiError> {
-        match &self.yt_api_client {
-            Some(yt_api_client) if !yt_api_client.is_ratelimited().await => {
-                yt_api_client.get_playlist(id).await
-            }
-            _ => {
-                //TODO: Implement Invidious as youtube api fallback
-                Err(YtApiError::QuotaExceeded)
+    /// Fetches a playlist. With `max_items`, providers may stop loading items after that many.
+    pub async fn get_playlist(
+        &self,
+        id: &str,
+        max_items: Option<usize>,
+    ) -> Result<YtPlaylist, YtApiError> {
+        self.first_success(YtOperation::Playlist, |provider| {
+            async move {
+                match provider {
+                    YtProvider::Api => {
+                        self.available_api()
+                            .await?
+                            .get_playlist(id, max_items)
+                            .await
+                    }
+                    YtProvider::YtDlp => self.yt_dlp.get_playlist(id, max_items).await,
+                }
             }
+            .boxed()
+        })
+        .await
+    }
+
+    /// Newest public upload of a channel, from the uploads playlist every channel has.
+    /// None if the channel did not upload anything yet.
+    pub async fn latest_upload(&self, channel_id: &str) -> Result<Option<YtResource>, YtApiError> {
+        let Some(suffix) = channel_id.strip_prefix("UC") else {
+            return Err(YtApiError::InvalidId);
+        };
+        match self.get_playlist(&format!("UU{suffix}"), Some(1)).await {
+            Ok(uploads) => Ok(uploads.videos.into_iter().next()),
+            // The uploads playlist does not exist for channels without public videos
+            Err(YtApiError::InvalidId) => Ok(None),
+            Err(e) => Err(e),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::youtube::quota::QuotaEstimator;
+
+    fn client(order: Vec<YtProvider>) -> YoutubeClient {
+        let ytdlp_config = Arc::new(YtDlpConfig {
+            cookies_file: None,
+            po_token: None,
+            max_file_size: None,
+            preview_clips: false,
+        });
+        YoutubeClient::new(
+            HttpClient::new(),
+            None,
+            ytdlp_config,
+            order,
+            QuotaEstimator::new(10_000, 80),
+            Arc::default(),
+        )
+    }
+
+    /// Runs [YoutubeClient::first_success] with mocked providers, returns the result and the
+    /// providers that were asked
+    async fn run_chain(
+        order: Vec<YtProvider>,
+        answer: impl Fn(YtProvider) -> Result<u32, YtApiError>,
+    ) -> (Result<u32, YtApiError>, Vec<YtProvider>, YoutubeClient) {
+        let client = client(order);
+        let asked = Mutex::new(Vec::new());
+        let result = client
+            .first_success(YtOperation::Search, |provider| {
+                asked.lock().unwrap().push(provider);
+                let result = answer(provider);
+                async move { result }.boxed()
+            })
+            .await;
+        (result, asked.into_inner().unwrap(), client)
+    }
+
+    #[tokio::test]
+    async fn first_provider_that_succeeds_serves_the_request() {
+        let (result, asked, client) =
+            run_chain(vec![YtProvider::Api, YtProvider::YtDlp], |_| Ok(1)).await;
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(asked, [YtProvider::Api]);
+        assert_eq!(
+            client.recent_providers(),
+            [(YtOperation::Search, YtProvider::Api)]
+        );
+    }
+
+    #[tokio::test]
+    async fn falls_back_through_the_chain() {
+        let (result, asked, client) = run_chain(
+            vec![YtProvider::Api, YtProvider::YtDlp],
+            |provider| match provider {
+                YtProvider::Api => Err(YtApiError::QuotaExceeded),
+                YtProvider::YtDlp => Ok(2),
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(asked, [YtProvider::Api, YtProvider::YtDlp]);
+        assert_eq!(
+            client.recent_providers(),
+            [(YtOperation::Search, YtProvider::YtDlp)]
+        );
+    }
+
+    #[tokio::test]
+    async fn configured_order_is_kept() {
+        let (result, asked, _) =
+            run_chain(
+                vec![YtProvider::YtDlp, YtProvider::Api],
+                |provider| match provider {
+                    YtProvider::YtDlp => Err(YtApiError::Api),
+                    YtProvider::Api => Ok(3),
+                },
+            )
+            .await;
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(asked, [YtProvider::YtDlp, YtProvider::Api]);
+    }
+
+    #[tokio::test]
+    async fn all_providers_failing_returns_the_last_error() {
+        let (result, asked, client) = run_chain(
+            vec![YtProvider::Api, YtProvider::YtDlp],
+            |provider| match provider {
+                YtProvider::Api => Err(YtApiError::QuotaExceeded),
+                YtProvider::YtDlp => Err(YtApiError::Api),
+            },
+        )
+        .await;
+        assert!(matches!(result, Err(YtApiError::Api)));
+        assert_eq!(asked.len(), 2);
+        assert!(client.recent_providers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn missing_ids_are_not_retried() {
+        let (result, asked, _) = run_chain(vec![YtProvider::Api, YtProvider::YtDlp], |_| {
+            Err(YtApiError::InvalidId)
+        })
+        .await;
+        assert!(matches!(result, Err(YtApiError::InvalidId)));
+        assert_eq!(asked, [YtProvider::Api]);
+    }
+
+    #[test]
+    fn provider_order_formats() {
+        let both = [YtProvider::Api, YtProvider::YtDlp];
+        assert_eq!(parse_provider_order("api,ytdlp").unwrap(), both);
+        assert_eq!(parse_provider_order(" api , ytdlp ").unwrap(), both);
+        assert_eq!(parse_provider_order(r#"["api", "ytdlp"]"#).unwrap(), both);
+        assert_eq!(parse_provider_order("ytdlp").unwrap(), [YtProvider::YtDlp]);
+    }
+
+    #[test]
+    fn invalid_provider_orders() {
+        assert!(parse_provider_order("api,api").is_err());
+        assert!(parse_provider_order("[]").is_err());
+        assert!(parse_provider_order("invidious").is_err());
+        assert!(parse_provider_order("something").is_err());
+    }
+}
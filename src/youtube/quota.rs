@@ -0,0 +1,111 @@
+use log::info;
+use std::sync::Mutex;
+use time::OffsetDateTime;
+
+/// Units of a search.list call
+pub const SEARCH_COST: u32 = 100;
+/// Units of a videos.list, playlists.list or playlistItems.list call
+pub const LIST_COST: u32 = 1;
+
+/// Quota of a project without an extension
+pub const DEFAULT_DAILY_QUOTA: u32 = 10_000;
+/// Share of the daily quota in percent after which autocomplete stops searching
+pub const DEFAULT_AUTOCOMPLETE_THRESHOLD: u32 = 80;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaMode {
+    Normal,
+    /// Autocomplete suggests from the history only, commands still use the api
+    AutocompleteDegraded,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct QuotaStatus {
+    pub used: u32,
+    pub daily_limit: u32,
+    pub threshold_percent: u32,
+    pub mode: QuotaMode,
+    /// Unset if the mode did not change since the start
+    pub mode_changed_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug)]
+struct QuotaState {
+    /// Julian day of the usage, the quota resets every day
+    day: i32,
+    used: u32,
+    mode: QuotaMode,
+    mode_changed_at: Option<OffsetDateTime>,
+}
+
+/// Estimates the api quota used today from the calls the bot made. Calls of other clients with
+/// the same key are not known, so the hard limit can still be hit earlier.
+#[derive(Debug)]
+pub struct QuotaEstimator {
+    daily_limit: u32,
+    threshold_percent: u32,
+    state: Mutex<QuotaState>,
+}
+
+impl QuotaEstimator {
+    pub fn new(daily_limit: u32, threshold_percent: u32) -> Self {
+        Self {
+            daily_limit,
+            threshold_percent,
+            state: Mutex::new(QuotaState {
+                day: OffsetDateTime::now_utc().to_julian_day(),
+                used: 0,
+                mode: QuotaMode::Normal,
+                mode_changed_at: None,
+            }),
+        }
+    }
+
+    pub fn record(&self, units: u32) {
+        let mut state = self.state.lock().unwrap();
+        self.roll_day(&mut state);
+        state.used = state.used.saturating_add(units);
+        self.update_mode(&mut state);
+    }
+
+    pub fn status(&self) -> QuotaStatus {
+        let mut state = self.state.lock().unwrap();
+        self.roll_day(&mut state);
+        QuotaStatus {
+            used: state.used,
+            daily_limit: self.daily_limit,
+            threshold_percent: self.threshold_percent,
+            mode: state.mode,
+            mode_changed_at: state.mode_changed_at,
+        }
+    }
+
+    pub fn mode(&self) -> QuotaMode {
+        self.status().mode
+    }
+
+    fn roll_day(&self, state: &mut QuotaState) {
+        let today = OffsetDateTime::now_utc().to_julian_day();
+        if state.day < today {
+            state.day = today;
+            state.used = 0;
+            self.update_mode(state);
+        }
+    }
+
+    fn update_mode(&self, state: &mut QuotaState) {
+        let threshold = u64::from(self.daily_limit) * u64::from(self.threshold_percent) / 100;
+        let mode = match u64::from(state.used) >= threshold {
+            true => QuotaMode::AutocompleteDegraded,
+            false => QuotaMode::Normal,
+        };
+        if mode != state.mode {
+            info!(
+                "YouTube quota mode changed from {:?} to {mode:?} at an estimated {} of {} units",
+                state.mode, state.used, self.daily_limit
+            );
+            state.mode = mode;
+            state.mode_changed_at = Some(OffsetDateTime::now_utc());
+        }
+    }
+}
@@ -1,8 +1,11 @@
 #![allow(dead_code)]
 
+use crate::youtube::quota::{QuotaEstimator, LIST_COST, SEARCH_COST};
 use crate::youtube::YtResourceId::{Channel, Playlist, Video};
-use crate::youtube::{YtApiError, YtPlaylist, YtResource, YtSearchFilter, YtVideo};
-use log::info;
+use crate::youtube::{
+    YtApiError, YtPlaylist, YtPlaylistTruncation, YtResource, YtSearchFilter, YtVideo,
+};
+use log::{error, info, warn};
 use reqwest::{Client as HttpClient, Response, StatusCode};
 use serde::de::DeserializeOwned;
 use time::OffsetDateTime;
@@ -86,8 +89,9 @@ pub(super) mod models {
     #[derive(Clone, Debug, Deserialize)]
     pub struct YtThumbnailInfo {
         pub url: Url,
-        pub width: u32,
-        pub height: u32,
+        // Missing for some older uploads
+        pub width: Option<u32>,
+        pub height: Option<u32>,
     }
 
     #[derive(Clone, Debug, Deserialize)]
@@ -120,6 +124,8 @@ pub(super) mod models {
         pub description: String,
         pub thumbnails: HashMap<YtThumbnailSize, YtThumbnailInfo>,
         pub channel_title: String,
+        // Missing if the uploader set none
+        #[serde(default)]
         pub tags: Vec<String>,
         pub category_id: String,
         pub live_broadcast_content: YtLiveBroadcastContent,
@@ -161,7 +167,10 @@ pub(super) mod models {
     #[derive(Clone, Debug, Deserialize)]
     #[serde(rename_all = "camelCase")]
     pub struct YtVideoRegionRestriction {
+        // Only one of both is set
+        #[serde(default)]
         pub allowed: Vec<String>,
+        #[serde(default)]
         pub blocked: Vec<String>,
     }
 
@@ -183,6 +192,13 @@ pub(super) mod models {
         pub etag: String,
         pub id: String,
         pub snippet: YtPlaylistSnippet, // Optional, but there is no point in not requesting it
+        pub content_details: Option<YtPlaylistContentDetails>,
+    }
+
+    #[derive(Clone, Debug, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct YtPlaylistContentDetails {
+        pub item_count: u32,
     }
 
     #[derive(Clone, Debug, Deserialize)]
@@ -218,8 +234,9 @@ pub(super) mod models {
         pub description: String,
         pub thumbnails: HashMap<YtThumbnailSize, YtThumbnailInfo>,
         pub channel_title: String,
-        pub video_owner_channel_title: String,
-        pub video_owner_channel_id: String,
+        // Missing for deleted and private videos
+        pub video_owner_channel_title: Option<String>,
+        pub video_owner_channel_id: Option<String>,
         pub playlist_id: String,
         pub position: u32,
         pub resource_id: YtPlaylistItemResourceId,
@@ -237,8 +254,16 @@ pub(super) mod models {
     pub struct YtPlaylistItemContentDetails {
         pub video_id: String,
         pub note: Option<String>,
-        #[serde(with = "time::serde::iso8601")]
-        pub video_published_at: OffsetDateTime,
+        // Missing for deleted and private videos
+        #[serde(default, with = "time::serde::iso8601::option")]
+        pub video_published_at: Option<OffsetDateTime>,
+    }
+
+    impl YtPlaylistItem {
+        /// Deleted and private videos stay in playlists, but can not be played
+        pub fn is_available(&self) -> bool {
+            self.snippet.video_owner_channel_id.is_some()
+        }
     }
 }
 
@@ -257,6 +282,7 @@ impl From<models::YtSearchResult> for YtResource {
             channel_id: value.snippet.channel_id,
             channel_title: value.snippet.channel_title,
             thumbnails: value.snippet.thumbnails,
+            duration: None,
         }
     }
 }
@@ -267,10 +293,14 @@ impl From<models::YtPlaylistItem> for YtResource {
             id: Video(value.content_details.video_id),
             title: value.snippet.title,
             description: value.snippet.description,
-            published_at: value.content_details.video_published_at,
-            channel_id: value.snippet.video_owner_channel_id,
-            channel_title: value.snippet.video_owner_channel_title,
+            published_at: value
+                .content_details
+                .video_published_at
+                .unwrap_or(value.snippet.published_at),
+            channel_id: value.snippet.video_owner_channel_id.unwrap_or_default(),
+            channel_title: value.snippet.video_owner_channel_title.unwrap_or_default(),
             thumbnails: value.snippet.thumbnails,
+            duration: None,
         }
     }
 }
@@ -301,7 +331,9 @@ impl From<models::YtPlaylist> for YtPlaylist {
             channel_id: value.snippet.channel_id,
             channel_title: value.snippet.channel_title,
             thumbnails: value.snippet.thumbnails,
+            item_count: value.content_details.map(|c| c.item_count),
             videos: vec![],
+            truncated: None,
         }
     }
 }
@@ -310,19 +342,45 @@ impl From<models::YtPlaylist> for YtPlaylist {
 // ======== Functions ========
 // ===========================
 
+/// Upper bound for the quota a single playlist can use, 50 items per page
+const MAX_PLAYLIST_PAGES: usize = 20;
+
+const API_BASE_URL: &str = "https://www.googleapis.com/youtube/v3";
+
 #[derive(Debug)]
 pub struct YtApiClient {
     http_client: HttpClient,
     yt_api_key: String,
+    base_url: String,
     rate_limited_day: RwLock<Option<i32>>,
+    pub quota: QuotaEstimator,
 }
 
 impl YtApiClient {
-    pub fn new(http_client: HttpClient, yt_api_key: String) -> Self {
+    pub fn new(http_client: HttpClient, yt_api_key: String, quota: QuotaEstimator) -> Self {
         Self {
             http_client,
             yt_api_key,
+            base_url: API_BASE_URL.to_owned(),
             rate_limited_day: RwLock::new(None),
+            quota,
+        }
+    }
+
+    pub(super) fn set_base_url(&mut self, base_url: String) {
+        self.base_url = base_url;
+    }
+
+    /// A client talking to a local fake of the api
+    #[cfg(test)]
+    fn with_base_url(base_url: String) -> Self {
+        Self {
+            base_url,
+            ..Self::new(
+                HttpClient::new(),
+                "key".to_owned(),
+                QuotaEstimator::new(10_000, 80),
+            )
         }
     }
 
@@ -339,8 +397,12 @@ impl YtApiClient {
             YtSearchFilter::Channels => "channel",
             YtSearchFilter::Any => "channel,playlist,video",
         };
-        let url = format!("https://www.googleapis.com/youtube/v3/search?part=snippet&type={type_str}&q={query}&maxResults={n_results}&key={}", self.yt_api_key);
+        let url = format!(
+            "{}/search?part=snippet&type={type_str}&q={query}&maxResults={n_results}&key={}",
+            self.base_url, self.yt_api_key
+        );
 
+        self.quota.record(SEARCH_COST);
         let response = self.http_client.get(url).send().await?;
 
         self.process_api_response::<models::YtList<models::YtSearchResult>>(response)
@@ -349,8 +411,12 @@ impl YtApiClient {
     }
 
     pub async fn get_video(&self, id: &str) -> Result<YtVideo, YtApiError> {
-        let url = format!("https://www.googleapis.com/youtube/v3/videos?part=contentDetails,snippet&id={id}&key={}", self.yt_api_key);
+        let url = format!(
+            "{}/videos?part=contentDetails,snippet&id={id}&key={}",
+            self.base_url, self.yt_api_key
+        );
 
+        self.quota.record(LIST_COST);
         let response = self.http_client.get(url).send().await?;
 
         self.process_api_response::<models::YtList<models::YtVideo>>(response)
@@ -364,15 +430,23 @@ impl YtApiClient {
             })
     }
 
-    pub async fn get_playlist(&self, id: &str) -> Result<YtPlaylist, YtApiError> {
+    pub async fn get_playlist(
+        &self,
+        id: &str,
+        max_items: Option<usize>,
+    ) -> Result<YtPlaylist, YtApiError> {
         let meta_url = format!(
-            "https://www.googleapis.com/youtube/v3/playlists?part=snippet&id={id}&key={}",
-            self.yt_api_key
+            "{}/playlists?part=snippet,contentDetails&id={id}&key={}",
+            self.base_url, self.yt_api_key
+        );
+        let items_url = format!(
+            "{}/playlistItems?part=snippet,contentDetails&playlistId={id}&maxResults=50&key={}",
+            self.base_url, self.yt_api_key
         );
-        let items_url = format!("https://www.googleapis.com/youtube/v3/playlistItems?part=snippet,contentDetails&playlistId={id}&maxResults=50&key={}", self.yt_api_key);
 
+        self.quota.record(2 * LIST_COST);
         let meta_future = self.http_client.get(meta_url).send();
-        let items_future = self.http_client.get(items_url).send();
+        let items_future = self.http_client.get(&items_url).send();
         let (meta_response, items_response) = try_join!(meta_future, items_future)?;
 
         let mut playlist = self
@@ -386,10 +460,57 @@ impl YtApiClient {
                     .ok_or(YtApiError::InvalidId)
             })?;
 
-        let items = self
+        let mut page = self
             .process_api_response::<models::YtList<models::YtPlaylistItem>>(items_response)
-            .await
-            .map(|list| list.items.into_iter().map(YtResource::from).collect())?;
+            .await?;
+        let mut items = Vec::new();
+        let mut page_count = 1;
+        loop {
+            items.extend(
+                page.items
+                    .into_iter()
+                    .filter(models::YtPlaylistItem::is_available)
+                    .map(YtResource::from),
+            );
+            let more_needed = match max_items {
+                Some(max_items) => items.len() < max_items,
+                None => true,
+            };
+            let Some(page_token) = page.next_page_token.filter(|_| more_needed) else {
+                break;
+            };
+            if page_count >= MAX_PLAYLIST_PAGES {
+                playlist.truncated = Some(YtPlaylistTruncation::PageLimit);
+                break;
+            }
+            page_count += 1;
+
+            // Items that were already fetched are kept if a later page fails
+            self.quota.record(LIST_COST);
+            let next_page = match self
+                .http_client
+                .get(format!("{items_url}&pageToken={page_token}"))
+                .send()
+                .await
+            {
+                Ok(response) => self.process_api_response(response).await,
+                Err(e) => Err(e.into()),
+            };
+            match next_page {
+                Ok(next_page) => page = next_page,
+                Err(e) => {
+                    warn!(
+                        "Loading page {page_count} of playlist {id} failed, keeping {} items: {e}",
+                        items.len()
+                    );
+                    playlist.truncated = Some(match e {
+                        YtApiError::QuotaExceeded => YtPlaylistTruncation::QuotaExceeded,
+                        _ => YtPlaylistTruncation::Failed,
+                    });
+                    break;
+                }
+            }
+        }
 
         playlist.videos = items;
 
@@ -431,7 +552,293 @@ impl YtApiClient {
                 info!("Encountered rate limit from YouTube API. Switching to fallback proxy");
                 Err(YtApiError::QuotaExceeded)
             }
+            // Invalid keys are not an outage, retrying with them never works
+            StatusCode::BAD_REQUEST
+                if response
+                    .text()
+                    .await
+                    .is_ok_and(|body| body.contains("API_KEY_INVALID")) =>
+            {
+                error!("The YouTube API key is invalid");
+                Err(YtApiError::InvalidKey)
+            }
             _ => Err(YtApiError::Api),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::models::YtLiveBroadcastContent;
+    use super::*;
+    use crate::test_server::TestServer;
+    use hyper::{Body, Response as HttpResponse, StatusCode as HttpStatus};
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    fn list(items: Vec<Value>, next_page_token: Option<&str>) -> Value {
+        json!({
+            "etag": "etag",
+            "nextPageToken": next_page_token,
+            "pageInfo": { "totalResults": 120, "resultsPerPage": 50 },
+            "items": items,
+        })
+    }
+
+    fn playlist_meta() -> Value {
+        list(
+            vec![json!({
+                "etag": "etag",
+                "id": "PLtest",
+                "snippet": {
+                    "publishedAt": "2020-01-01T00:00:00Z",
+                    "channelId": "UCowner",
+                    "title": "Test playlist",
+                    "description": "",
+                    "thumbnails": {},
+                    "channelTitle": "Owner",
+                },
+                "contentDetails": { "itemCount": 120 },
+            })],
+            None,
+        )
+    }
+
+    fn playlist_page(first: u32, count: u32, next_page_token: Option<&str>) -> Value {
+        let items = (first..first + count)
+            .map(|position| {
+                json!({
+                    "etag": "etag",
+                    "id": format!("item{position}"),
+                    "snippet": {
+                        "publishedAt": "2020-01-01T00:00:00Z",
+                        "channelId": "UCowner",
+                        "title": format!("Video {position}"),
+                        "description": "",
+                        "thumbnails": {},
+                        "channelTitle": "Owner",
+                        "videoOwnerChannelTitle": "Uploader",
+                        "videoOwnerChannelId": "UCuploader",
+                        "playlistId": "PLtest",
+                        "position": position,
+                        "resourceId": { "kind": "youtube#video", "videoId": format!("video{position}") },
+                    },
+                    "contentDetails": {
+                        "videoId": format!("video{position}"),
+                        "videoPublishedAt": "2019-01-01T00:00:00Z",
+                    },
+                })
+            })
+            .collect();
+        list(items, next_page_token)
+    }
+
+    fn json_response(value: &Value) -> HttpResponse<Body> {
+        HttpResponse::new(Body::from(value.to_string()))
+    }
+
+    fn status(status: HttpStatus) -> HttpResponse<Body> {
+        let mut response = HttpResponse::new(Body::empty());
+        *response.status_mut() = status;
+        response
+    }
+
+    /// Serves the playlist with two pages, the second one answered with `second_page`
+    fn playlist_server(second_page: HttpStatus) -> TestServer {
+        TestServer::start(move |request| {
+            let path = request.uri().path();
+            let query = request.uri().query().unwrap_or_default();
+            match path {
+                "/playlists" => json_response(&playlist_meta()),
+                "/playlistItems" if query.contains("pageToken=page2") => match second_page {
+                    HttpStatus::OK => json_response(&playlist_page(50, 50, None)),
+                    status_code => status(status_code),
+                },
+                "/playlistItems" => json_response(&playlist_page(0, 50, Some("page2"))),
+                _ => status(HttpStatus::NOT_FOUND),
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn playlist_pages_are_joined() {
+        let server = playlist_server(HttpStatus::OK);
+        let client = YtApiClient::with_base_url(server.url(""));
+
+        let playlist = client.get_playlist("PLtest", None).await.unwrap();
+        assert_eq!(playlist.videos.len(), 100);
+        assert_eq!(playlist.truncated, None);
+        assert_eq!(playlist.item_count, Some(120));
+        assert_eq!(server.requests(), 3);
+    }
+
+    #[tokio::test]
+    async fn quota_running_out_mid_pagination_keeps_the_fetched_pages() {
+        let server = playlist_server(HttpStatus::FORBIDDEN);
+        let client = YtApiClient::with_base_url(server.url(""));
+
+        let playlist = client.get_playlist("PLtest", None).await.unwrap();
+        assert_eq!(playlist.videos.len(), 50);
+        assert!(matches!(&playlist.videos[49].id, Video(id) if id == "video49"));
+        assert_eq!(
+            playlist.truncated,
+            Some(YtPlaylistTruncation::QuotaExceeded)
+        );
+        // The api is not used again until the next day
+        assert!(client.is_ratelimited().await);
+    }
+
+    #[tokio::test]
+    async fn failing_later_page_keeps_the_fetched_pages() {
+        let server = playlist_server(HttpStatus::INTERNAL_SERVER_ERROR);
+        let client = YtApiClient::with_base_url(server.url(""));
+
+        let playlist = client.get_playlist("PLtest", None).await.unwrap();
+        assert_eq!(playlist.videos.len(), 50);
+        assert_eq!(playlist.truncated, Some(YtPlaylistTruncation::Failed));
+        assert!(!client.is_ratelimited().await);
+    }
+
+    #[tokio::test]
+    async fn pages_after_the_selection_are_not_fetched() {
+        let server = playlist_server(HttpStatus::OK);
+        let client = YtApiClient::with_base_url(server.url(""));
+
+        let playlist = client.get_playlist("PLtest", Some(20)).await.unwrap();
+        assert_eq!(playlist.videos.len(), 50);
+        assert_eq!(server.requests(), 2);
+    }
+
+    #[tokio::test]
+    async fn unknown_playlist_is_an_invalid_id() {
+        // Unknown ids are answered with an empty list
+        let server = TestServer::start(|_| json_response(&list(vec![], None)));
+        let client = YtApiClient::with_base_url(server.url(""));
+
+        assert!(matches!(
+            client.get_playlist("PLmissing", None).await,
+            Err(YtApiError::InvalidId)
+        ));
+    }
+
+    /// A sanitized response of the api from `tests/fixtures/youtube`
+    fn fixture(name: &str) -> String {
+        let path = format!(
+            "{}/tests/fixtures/youtube/{name}.json",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    fn video_fixture(name: &str) -> YtVideo {
+        let list = serde_json::from_str::<models::YtList<models::YtVideo>>(&fixture(name)).unwrap();
+        assert_eq!(list.items.len(), 1, "{name}");
+        list.items.into_iter().next().map(YtVideo::from).unwrap()
+    }
+
+    #[test]
+    fn live_video_has_no_duration() {
+        let video = video_fixture("video_live");
+        assert_eq!(video.id, "liveStream01");
+        assert_eq!(video.duration, Duration::ZERO);
+        assert!(matches!(video.live_status, YtLiveBroadcastContent::Live));
+    }
+
+    #[test]
+    fn video_over_a_day_counts_the_days() {
+        let video = video_fixture("video_over_a_day");
+        assert_eq!(video.duration, Duration::from_secs(26 * 60 * 60));
+        assert!(matches!(video.live_status, YtLiveBroadcastContent::None));
+    }
+
+    #[test]
+    fn region_restricted_video_is_read() {
+        let list = serde_json::from_str::<models::YtList<models::YtVideo>>(&fixture(
+            "video_region_restricted",
+        ))
+        .unwrap();
+        let restriction = list.items[0]
+            .content_details
+            .region_restriction
+            .clone()
+            .unwrap();
+        assert_eq!(restriction.blocked, ["DE", "AT"]);
+        assert!(restriction.allowed.is_empty());
+
+        let video = YtVideo::from(list.items[0].clone());
+        assert_eq!(video.title, "Licensed song");
+        assert_eq!(video.duration, Duration::from_secs(213));
+    }
+
+    #[test]
+    fn video_without_tags_is_read() {
+        let list =
+            serde_json::from_str::<models::YtList<models::YtVideo>>(&fixture("video_missing_tags"))
+                .unwrap();
+        assert!(list.items[0].snippet.tags.is_empty());
+
+        let video = YtVideo::from(list.items[0].clone());
+        assert_eq!(video.channel_title, "Sanitized Channel");
+        assert_eq!(video.duration, Duration::from_secs(242));
+        assert_eq!(
+            video.get_yt_url().as_str(),
+            "https://www.youtube.com/watch?v=noTagsVid01"
+        );
+    }
+
+    #[test]
+    fn deleted_and_private_playlist_items_are_skipped() {
+        let meta = serde_json::from_str::<models::YtList<models::YtPlaylist>>(&fixture("playlist"))
+            .unwrap();
+        let playlist = YtPlaylist::from(meta.items[0].clone());
+        assert_eq!(playlist.title, "Mixed playlist");
+        // The count includes the unavailable items
+        assert_eq!(playlist.item_count, Some(4));
+
+        let items = serde_json::from_str::<models::YtList<models::YtPlaylistItem>>(&fixture(
+            "playlist_items_unavailable",
+        ))
+        .unwrap();
+        assert_eq!(items.items.len(), 4);
+        let available = items
+            .items
+            .into_iter()
+            .filter(models::YtPlaylistItem::is_available)
+            .map(YtResource::from)
+            .collect::<Vec<_>>();
+        let ids = available
+            .iter()
+            .map(|video| match &video.id {
+                Video(id) => id.as_str(),
+                other => panic!("{other:?} is no video"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids, ["availVid001", "availVid002"]);
+        // The uploader and upload date of the video, not of the playlist entry
+        assert_eq!(available[0].channel_title, "Uploader");
+        assert_eq!(available[0].published_at.year(), 2018);
+    }
+
+    #[tokio::test]
+    async fn fixtures_are_served_through_the_client() {
+        let server = TestServer::start(|request| {
+            let name = match request.uri().path() {
+                "/videos" => "video_over_a_day",
+                "/playlists" => "playlist",
+                "/playlistItems" => "playlist_items_unavailable",
+                _ => return status(HttpStatus::NOT_FOUND),
+            };
+            HttpResponse::new(Body::from(fixture(name)))
+        });
+        let client = YtApiClient::with_base_url(server.url(""));
+
+        let video = client.get_video("longVideo01").await.unwrap();
+        assert_eq!(video.duration, Duration::from_secs(26 * 60 * 60));
+        let playlist = client
+            .get_playlist("PLsanitizedPlaylist000000000000000", None)
+            .await
+            .unwrap();
+        assert_eq!(playlist.videos.len(), 2);
+        assert_eq!(playlist.truncated, None);
+    }
+}
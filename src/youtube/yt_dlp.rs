@@ -0,0 +1,167 @@
+use crate::youtube::YtResourceId::{Channel, Playlist, Video};
+use crate::youtube::{
+    YtApiError, YtLiveBroadcastContent, YtPlaylist, YtResource, YtSearchFilter, YtVideo,
+};
+use crate::ytdlp::{self, YtDlpConfig};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+pub(super) mod models {
+    use serde::Deserialize;
+
+    /// Subset of the `-J` output of yt-dlp for videos and flat playlists (including searches)
+    #[derive(Clone, Debug, Deserialize)]
+    pub struct YtDlpInfo {
+        pub id: String,
+        pub title: Option<String>,
+        pub description: Option<String>,
+        pub duration: Option<f64>,
+        pub timestamp: Option<i64>,
+        pub channel_id: Option<String>,
+        pub channel: Option<String>,
+        pub uploader: Option<String>,
+        pub live_status: Option<String>,
+        /// Extractor that handles the entry, only present on flat playlist entries
+        pub ie_key: Option<String>,
+        pub url: Option<String>,
+        pub playlist_count: Option<u32>,
+        #[serde(default)]
+        pub entries: Vec<YtDlpInfo>,
+    }
+}
+
+impl models::YtDlpInfo {
+    fn published_at(&self) -> OffsetDateTime {
+        self.timestamp
+            .and_then(|t| OffsetDateTime::from_unix_timestamp(t).ok())
+            .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+    }
+
+    fn channel_title(&self) -> String {
+        self.channel
+            .clone()
+            .or_else(|| self.uploader.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl From<models::YtDlpInfo> for YtResource {
+    fn from(value: models::YtDlpInfo) -> Self {
+        let url = value.url.clone().unwrap_or_default();
+        Self {
+            id: match value.ie_key.as_deref() {
+                Some("YoutubeTab") if url.contains("list=") => Playlist(value.id.clone()),
+                Some("YoutubeTab") => Channel(value.id.clone()),
+                _ => Video(value.id.clone()),
+            },
+            published_at: value.published_at(),
+            channel_title: value.channel_title(),
+            title: value.title.unwrap_or_default(),
+            description: value.description.unwrap_or_default(),
+            channel_id: value.channel_id.unwrap_or_default(),
+            thumbnails: HashMap::new(),
+            duration: value.duration.map(|d| Duration::from_secs_f64(d.max(0.0))),
+        }
+    }
+}
+
+impl From<models::YtDlpInfo> for YtVideo {
+    fn from(value: models::YtDlpInfo) -> Self {
+        Self {
+            duration: Duration::from_secs_f64(value.duration.unwrap_or_default().max(0.0)),
+            published_at: value.published_at(),
+            channel_title: value.channel_title(),
+            live_status: match value.live_status.as_deref() {
+                Some("is_live") => YtLiveBroadcastContent::Live,
+                Some("is_upcoming") => YtLiveBroadcastContent::Upcoming,
+                _ => YtLiveBroadcastContent::None,
+            },
+            id: value.id,
+            title: value.title.unwrap_or_default(),
+            description: value.description.unwrap_or_default(),
+            channel_id: value.channel_id.unwrap_or_default(),
+            thumbnails: HashMap::new(),
+        }
+    }
+}
+
+impl From<models::YtDlpInfo> for YtPlaylist {
+    fn from(value: models::YtDlpInfo) -> Self {
+        Self {
+            published_at: value.published_at(),
+            channel_title: value.channel_title(),
+            id: value.id,
+            title: value.title.unwrap_or_default(),
+            description: value.description.unwrap_or_default(),
+            channel_id: value.channel_id.unwrap_or_default(),
+            thumbnails: HashMap::new(),
+            item_count: value.playlist_count,
+            videos: value.entries.into_iter().map(YtResource::from).collect(),
+            truncated: None,
+        }
+    }
+}
+
+/// Metadata and search through yt-dlp. Slower than the api, but without quota.
+#[derive(Clone, Debug)]
+pub struct YtDlpProvider {
+    config: Arc<YtDlpConfig>,
+}
+
+impl YtDlpProvider {
+    pub fn new(config: Arc<YtDlpConfig>) -> Self {
+        Self { config }
+    }
+
+    pub async fn search(
+        &self,
+        query: &str,
+        filter: YtSearchFilter,
+        n_results: u8,
+    ) -> Result<Vec<YtResource>, YtApiError> {
+        // Same filters as the search page of the website
+        let filter_param = match filter {
+            YtSearchFilter::Videos => "&sp=EgIQAQ%3D%3D",
+            YtSearchFilter::Playlists => "&sp=EgIQAw%3D%3D",
+            YtSearchFilter::Channels => "&sp=EgIQAg%3D%3D",
+            YtSearchFilter::Any => "",
+        };
+        let mut url = reqwest::Url::parse("https://www.youtube.com/results").unwrap();
+        url.query_pairs_mut().append_pair("search_query", query);
+        let url = format!("{url}{filter_param}");
+        let end = n_results.to_string();
+
+        let result = ytdlp::dump_json::<models::YtDlpInfo>(
+            &self.config,
+            &["--flat-playlist", "--playlist-end", &end],
+            &url,
+        )
+        .await?;
+        Ok(result.entries.into_iter().map(YtResource::from).collect())
+    }
+
+    pub async fn get_video(&self, id: &str) -> Result<YtVideo, YtApiError> {
+        let url = format!("https://www.youtube.com/watch?v={id}");
+        let result =
+            ytdlp::dump_json::<models::YtDlpInfo>(&self.config, &["--no-playlist"], &url).await?;
+        Ok(result.into())
+    }
+
+    pub async fn get_playlist(
+        &self,
+        id: &str,
+        max_items: Option<usize>,
+    ) -> Result<YtPlaylist, YtApiError> {
+        let url = format!("https://www.youtube.com/playlist?list={id}");
+        let end = max_items.map(|m| m.to_string());
+        let mut args = vec!["--flat-playlist"];
+        if let Some(end) = &end {
+            args.extend(["--playlist-end", end]);
+        }
+
+        let result = ytdlp::dump_json::<models::YtDlpInfo>(&self.config, &args, &url).await?;
+        Ok(result.into())
+    }
+}
@@ -0,0 +1,590 @@
+use async_trait::async_trait;
+use log::warn;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Client as HttpClient;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use songbird::input::core::io::MediaSource;
+use songbird::input::{
+    AudioStream, AudioStreamError, AuxMetadata, Compose, HlsRequest, HttpRequest, Input,
+};
+use std::collections::HashMap;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::process::{Child, Command};
+
+const YTDLP_COMMAND: &str = "yt-dlp";
+
+/// Operator settings that are passed to every yt-dlp invocation
+#[derive(Clone, Debug, Default)]
+pub struct YtDlpConfig {
+    /// Netscape formatted cookies file
+    pub cookies_file: Option<PathBuf>,
+    /// Proof of origin token for the YouTube web client
+    pub po_token: Option<String>,
+    /// Largest direct file that is played, in bytes. Streams of direct links end once they read
+    /// that much. Unlimited if unset.
+    pub max_file_size: Option<u64>,
+    /// Whether /preview attaches a clip, which costs a yt-dlp and an ffmpeg process each time
+    pub preview_clips: bool,
+}
+
+impl YtDlpConfig {
+    fn extra_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(cookies_file) = &self.cookies_file {
+            args.push("--cookies".to_owned());
+            args.push(cookies_file.to_string_lossy().into_owned());
+        }
+        if let Some(po_token) = &self.po_token {
+            args.push("--extractor-args".to_owned());
+            args.push(format!("youtube:po_token=web+{po_token}"));
+        }
+        args
+    }
+
+    /// Removes configured secrets and paths from yt-dlp output
+    fn redact(&self, message: &str) -> String {
+        let mut message = message.to_owned();
+        if let Some(cookies_file) = &self.cookies_file {
+            message = message.replace(&*cookies_file.to_string_lossy(), "<cookies>");
+        }
+        if let Some(po_token) = &self.po_token {
+            message = message.replace(po_token, "<po_token>");
+        }
+        message
+    }
+}
+
+/// Known reasons for yt-dlp refusing to load a source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YtDlpFailure {
+    BotCheck,
+    AgeRestricted,
+    GeoBlocked,
+    Unavailable,
+    Throttled,
+    Unknown,
+}
+
+impl YtDlpFailure {
+    /// Classifies a failure by the error message yt-dlp printed to stderr
+    pub fn classify(stderr: &str) -> Self {
+        let stderr = stderr.to_lowercase();
+        let contains_any = |patterns: &[&str]| patterns.iter().any(|p| stderr.contains(p));
+
+        if contains_any(&[
+            "confirm your age",
+            "age-restricted",
+            "inappropriate for some users",
+        ]) {
+            YtDlpFailure::AgeRestricted
+        } else if contains_any(&["not a bot", "captcha", "consent"]) {
+            YtDlpFailure::BotCheck
+        } else if contains_any(&["your country", "geo restriction", "geo-restricted"]) {
+            YtDlpFailure::GeoBlocked
+        } else if contains_any(&["http error 429", "too many requests", "rate-limit"]) {
+            YtDlpFailure::Throttled
+        } else if contains_any(&[
+            "video unavailable",
+            "private video",
+            "has been removed",
+            "does not exist",
+            "http error 404",
+            "unsupported url",
+        ]) {
+            YtDlpFailure::Unavailable
+        } else {
+            YtDlpFailure::Unknown
+        }
+    }
+
+    /// Message shown to the user
+    pub fn user_message(&self) -> &'static str {
+        match self {
+            YtDlpFailure::BotCheck => {
+                "YouTube blockiert momentan Anfragen des Bots. Bitte versuche es später erneut"
+            }
+            YtDlpFailure::AgeRestricted => {
+                "Dieses Video ist altersbeschränkt und kann nicht abgespielt werden"
+            }
+            YtDlpFailure::GeoBlocked => "Dieses Video ist in der Region des Bots nicht verfügbar",
+            YtDlpFailure::Unavailable => "Dieses Video ist nicht verfügbar",
+            YtDlpFailure::Throttled => {
+                "Die Quelle drosselt momentan die Anfragen des Bots. Bitte versuche es später erneut"
+            }
+            YtDlpFailure::Unknown => "Die Quelle konnte nicht geladen werden",
+        }
+    }
+
+    /// Few words for lists like the history
+    pub fn label(&self) -> &'static str {
+        match self {
+            YtDlpFailure::BotCheck => "von YouTube blockiert",
+            YtDlpFailure::AgeRestricted => "Video altersbeschränkt",
+            YtDlpFailure::GeoBlocked => "in der Region nicht verfügbar",
+            YtDlpFailure::Unavailable => "Video nicht verfügbar",
+            YtDlpFailure::Throttled => "Quelle drosselt",
+            YtDlpFailure::Unknown => "Quelle nicht ladbar",
+        }
+    }
+
+    /// Recommended fix for the operator, if there is one
+    pub fn remedy(&self) -> Option<&'static str> {
+        match self {
+            YtDlpFailure::BotCheck => Some(
+                "Configure a cookies file with `YTDLP_COOKIES` or route yt-dlp through a proxy",
+            ),
+            YtDlpFailure::AgeRestricted => {
+                Some("Configure a cookies file of an age-verified account with `YTDLP_COOKIES`")
+            }
+            YtDlpFailure::GeoBlocked => Some("Route yt-dlp through a proxy in a different region"),
+            YtDlpFailure::Throttled => {
+                Some("Lower `YTDLP_MAX_PROCESSES` or route yt-dlp through a proxy")
+            }
+            YtDlpFailure::Unavailable | YtDlpFailure::Unknown => None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum YtDlpError {
+    #[error("yt-dlp could not be started")]
+    Spawn(#[from] std::io::Error),
+    #[error("yt-dlp failed ({failure:?}): {message}")]
+    Failed {
+        failure: YtDlpFailure,
+        message: String,
+    },
+    #[error("yt-dlp returned invalid json")]
+    Parse(#[from] serde_json::Error),
+    #[error("yt-dlp did not find any results")]
+    NoResults,
+}
+
+impl YtDlpError {
+    pub fn failure(&self) -> YtDlpFailure {
+        match self {
+            YtDlpError::Failed { failure, .. } => *failure,
+            _ => YtDlpFailure::Unknown,
+        }
+    }
+
+    fn from_stderr(config: &YtDlpConfig, query: &str, stderr: &[u8]) -> Self {
+        let stderr = config.redact(&String::from_utf8_lossy(stderr));
+        // The last error line is the relevant one, everything before is mostly warnings
+        let message = stderr
+            .lines()
+            .rev()
+            .find(|l| l.starts_with("ERROR"))
+            .or_else(|| stderr.lines().last())
+            .unwrap_or("<no error message>")
+            .to_owned();
+        let failure = YtDlpFailure::classify(&message);
+
+        match failure.remedy() {
+            Some(remedy) => warn!("yt-dlp failed for {query}: {message}. Recommended: {remedy}"),
+            None => warn!("yt-dlp failed for {query}: {message}"),
+        }
+
+        YtDlpError::Failed { failure, message }
+    }
+}
+
+/// Subset of the `--dump-json` output of yt-dlp
+#[derive(Clone, Debug, Deserialize)]
+struct YtDlpOutput {
+    url: String,
+    protocol: Option<String>,
+    /// `generic` for direct links that no site specific extractor handles
+    extractor: Option<String>,
+    filesize: Option<u64>,
+    http_headers: Option<HashMap<String, String>>,
+    title: Option<String>,
+    artist: Option<String>,
+    uploader: Option<String>,
+    channel: Option<String>,
+    duration: Option<f64>,
+    webpage_url: Option<String>,
+    thumbnail: Option<String>,
+}
+
+impl YtDlpOutput {
+    fn as_aux_metadata(&self) -> AuxMetadata {
+        AuxMetadata {
+            title: self.title.clone(),
+            artist: self.artist.clone().or_else(|| self.uploader.clone()),
+            channel: self.channel.clone(),
+            duration: self.duration.map(Duration::from_secs_f64),
+            source_url: self.webpage_url.clone(),
+            thumbnail: self.thumbnail.clone(),
+            ..AuxMetadata::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Query {
+    Url(String),
+    Search(String),
+}
+
+/// Lazy yt-dlp input like [songbird::input::YoutubeDl], but with the operator configuration
+/// applied and errors that keep the reason yt-dlp reported
+#[derive(Clone, Debug)]
+pub struct YtDlpInput {
+    http_client: HttpClient,
+    config: Arc<YtDlpConfig>,
+    query: Query,
+    metadata: Option<AuxMetadata>,
+}
+
+impl YtDlpInput {
+    pub fn new(http_client: HttpClient, config: Arc<YtDlpConfig>, url: String) -> Self {
+        Self {
+            http_client,
+            config,
+            query: Query::Url(url),
+            metadata: None,
+        }
+    }
+
+    pub fn new_search(http_client: HttpClient, config: Arc<YtDlpConfig>, query: String) -> Self {
+        Self {
+            http_client,
+            config,
+            query: Query::Search(query),
+            metadata: None,
+        }
+    }
+
+    /// Resolves the metadata of the source, caching it for later calls
+    pub async fn metadata(&mut self) -> Result<AuxMetadata, YtDlpError> {
+        if let Some(metadata) = &self.metadata {
+            return Ok(metadata.clone());
+        }
+
+        self.query().await.map(|output| output.as_aux_metadata())
+    }
+
+    async fn query(&mut self) -> Result<YtDlpOutput, YtDlpError> {
+        let query = match &self.query {
+            Query::Url(url) => url.clone(),
+            Query::Search(query) => format!("ytsearch1:{query}"),
+        };
+
+        let output = Command::new(YTDLP_COMMAND)
+            .args(["-j", "-f", "ba[abr>0][vcodec=none]/best", "--no-playlist"])
+            .args(self.config.extra_args())
+            .arg(&query)
+            .stdin(Stdio::null())
+            .output()
+            .await?;
+
+        if !output.status.success() {
+            return Err(YtDlpError::from_stderr(
+                &self.config,
+                &query,
+                &output.stderr,
+            ));
+        }
+
+        let result = output
+            .stdout
+            .split(|&b| b == b'\n')
+            .find(|line| !line.is_empty())
+            .ok_or(YtDlpError::NoResults)?;
+        let result = serde_json::from_slice::<YtDlpOutput>(result)?;
+
+        // Pin searches to the found video, so playback doesn't depend on search ranking
+        if let (Query::Search(_), Some(url)) = (&self.query, &result.webpage_url) {
+            self.query = Query::Url(url.clone());
+        }
+
+        self.metadata = Some(result.as_aux_metadata());
+        Ok(result)
+    }
+}
+
+impl From<YtDlpInput> for Input {
+    fn from(value: YtDlpInput) -> Self {
+        Input::Lazy(Box::new(value))
+    }
+}
+
+#[async_trait]
+impl Compose for YtDlpInput {
+    fn create(&mut self) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        Err(AudioStreamError::Unsupported)
+    }
+
+    async fn create_async(
+        &mut self,
+    ) -> Result<AudioStream<Box<dyn MediaSource>>, AudioStreamError> {
+        // Always query again, because stream urls expire
+        let result = self
+            .query()
+            .await
+            .map_err(|e| AudioStreamError::Fail(Box::new(e)))?;
+
+        let mut headers = HeaderMap::default();
+        if let Some(map) = result.http_headers {
+            headers.extend(map.iter().filter_map(|(k, v)| {
+                Some((
+                    HeaderName::from_bytes(k.as_bytes()).ok()?,
+                    HeaderValue::from_str(v).ok()?,
+                ))
+            }));
+        }
+
+        match result.protocol.as_deref() {
+            Some("m3u8_native") => {
+                HlsRequest::new_with_headers(self.http_client.clone(), result.url, headers).create()
+            }
+            _ => {
+                // Extractors of sites pick their formats, only direct links can be anything
+                let budget = match result.extractor.as_deref() {
+                    Some("generic") => self.config.max_file_size,
+                    _ => None,
+                };
+                if let (Some(size), Some(limit)) = (result.filesize, budget) {
+                    if size > limit {
+                        return Err(AudioStreamError::Fail(
+                            format!("The file has {size} bytes, more than the limit of {limit}")
+                                .into(),
+                        ));
+                    }
+                }
+                let mut request = HttpRequest {
+                    client: self.http_client.clone(),
+                    request: result.url,
+                    headers,
+                    content_length: result.filesize,
+                };
+                let stream = request.create_async().await?;
+                Ok(match budget {
+                    Some(remaining) => AudioStream {
+                        input: Box::new(BudgetedSource {
+                            inner: stream.input,
+                            remaining,
+                        }) as Box<dyn MediaSource>,
+                        hint: stream.hint,
+                    },
+                    None => stream,
+                })
+            }
+        }
+    }
+
+    fn should_create_async(&self) -> bool {
+        true
+    }
+
+    async fn aux_metadata(&mut self) -> Result<AuxMetadata, AudioStreamError> {
+        self.metadata()
+            .await
+            .map_err(|e| AudioStreamError::Fail(Box::new(e)))
+    }
+}
+
+/// A stream that fails once it read more than its budget, which also ends endless streams of
+/// direct links
+struct BudgetedSource {
+    inner: Box<dyn MediaSource>,
+    remaining: u64,
+}
+
+impl Read for BudgetedSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !buf.is_empty() {
+            return Err(io::Error::other("The stream exceeded its byte budget"));
+        }
+        let len = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let read = self.inner.read(&mut buf[..len])?;
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+// Seeking reads the same bytes again, which counts against the budget as well
+impl Seek for BudgetedSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl MediaSource for BudgetedSource {
+    fn is_seekable(&self) -> bool {
+        self.inner.is_seekable()
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.inner.byte_len()
+    }
+}
+
+/// Cheap check whether yt-dlp can resolve a source without downloading it
+pub async fn simulate(config: &YtDlpConfig, url: &str) -> Result<(), YtDlpError> {
+    let output = Command::new(YTDLP_COMMAND)
+        .args(["--simulate", "--quiet", "--no-warnings", "--no-playlist"])
+        .args(config.extra_args())
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(YtDlpError::from_stderr(config, url, &output.stderr))
+    }
+}
+
+/// Runs yt-dlp with `-J` and parses the single json document it prints
+pub async fn dump_json<T: DeserializeOwned>(
+    config: &YtDlpConfig,
+    args: &[&str],
+    target: &str,
+) -> Result<T, YtDlpError> {
+    let output = Command::new(YTDLP_COMMAND)
+        .arg("-J")
+        .args(args)
+        .args(config.extra_args())
+        .arg(target)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await?;
+
+    if !output.status.success() {
+        return Err(YtDlpError::from_stderr(config, target, &output.stderr));
+    }
+
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Spawns yt-dlp writing the audio of a source to its stdout, for piping into another process.
+/// It is killed when dropped, so a reader that stops early ends the download.
+pub fn spawn_download(config: &YtDlpConfig, url: &str) -> io::Result<Child> {
+    Command::new(YTDLP_COMMAND)
+        .args([
+            "-f",
+            "ba/best",
+            "--quiet",
+            "--no-warnings",
+            "--no-playlist",
+            "-o",
+            "-",
+        ])
+        .args(config.extra_args())
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_are_classified_by_the_error_line() {
+        let cases = [
+            (
+                "ERROR: [youtube] dQw4w9WgXcQ: Sign in to confirm you’re not a bot. Use --cookies-from-browser or --cookies for the authentication.",
+                YtDlpFailure::BotCheck,
+            ),
+            (
+                "ERROR: [youtube] dQw4w9WgXcQ: Sign in to confirm you're not a bot. This helps protect our community. Learn more",
+                YtDlpFailure::BotCheck,
+            ),
+            (
+                "ERROR: [youtube] dQw4w9WgXcQ: Sign in to confirm your age. This video may be inappropriate for some users.",
+                YtDlpFailure::AgeRestricted,
+            ),
+            (
+                "ERROR: [youtube] dQw4w9WgXcQ: Video unavailable. The uploader has not made this video available in your country",
+                YtDlpFailure::GeoBlocked,
+            ),
+            (
+                "ERROR: [youtube] dQw4w9WgXcQ: Unable to download API page: HTTP Error 429: Too Many Requests",
+                YtDlpFailure::Throttled,
+            ),
+            (
+                "ERROR: [youtube] dQw4w9WgXcQ: Private video. Sign in if you've been granted access to this video",
+                YtDlpFailure::Unavailable,
+            ),
+            (
+                "ERROR: [youtube] dQw4w9WgXcQ: Video unavailable. This video has been removed by the uploader",
+                YtDlpFailure::Unavailable,
+            ),
+            (
+                "ERROR: [youtube] dQw4w9WgXcQ: Video unavailable",
+                YtDlpFailure::Unavailable,
+            ),
+            (
+                "ERROR: Unsupported URL: https://example.com/",
+                YtDlpFailure::Unavailable,
+            ),
+            (
+                "ERROR: [youtube] dQw4w9WgXcQ: Requested format is not available",
+                YtDlpFailure::Unknown,
+            ),
+            ("", YtDlpFailure::Unknown),
+        ];
+        for (stderr, failure) in cases {
+            assert_eq!(YtDlpFailure::classify(stderr), failure, "{stderr}");
+        }
+    }
+
+    fn secret_config() -> YtDlpConfig {
+        YtDlpConfig {
+            cookies_file: Some(PathBuf::from("/run/secrets/youtube-cookies.txt")),
+            po_token: Some("MnQ9c2VjcmV0LXRva2Vu".to_owned()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        let redacted = secret_config().redact(
+            "WARNING: Failed to read /run/secrets/youtube-cookies.txt\nERROR: invalid po_token=web+MnQ9c2VjcmV0LXRva2Vu",
+        );
+        assert_eq!(
+            redacted,
+            "WARNING: Failed to read <cookies>\nERROR: invalid po_token=web+<po_token>"
+        );
+    }
+
+    #[test]
+    fn output_without_secrets_is_kept() {
+        let message = "ERROR: [youtube] dQw4w9WgXcQ: Video unavailable";
+        assert_eq!(secret_config().redact(message), message);
+        assert_eq!(YtDlpConfig::default().redact(message), message);
+    }
+
+    #[test]
+    fn failure_messages_never_contain_secrets() {
+        let stderr = b"WARNING: cookies from /run/secrets/youtube-cookies.txt are expired\n\
+            ERROR: [youtube] dQw4w9WgXcQ: Sign in to confirm you're not a bot (po_token MnQ9c2VjcmV0LXRva2Vu, cookies /run/secrets/youtube-cookies.txt)";
+        let error = YtDlpError::from_stderr(&secret_config(), "dQw4w9WgXcQ", stderr);
+
+        let YtDlpError::Failed { failure, message } = error else {
+            panic!("Expected a classified failure");
+        };
+        assert_eq!(failure, YtDlpFailure::BotCheck);
+        assert!(!message.contains("youtube-cookies.txt"), "{message}");
+        assert!(!message.contains("MnQ9c2VjcmV0LXRva2Vu"), "{message}");
+        assert!(message.starts_with("ERROR: "), "{message}");
+    }
+}
@@ -0,0 +1,180 @@
+//! Autocomplete of the YouTube options against a local fake of the YouTube api
+
+use gerbot::commands::util::{yt_suggestions, YtSuggestions, AUTOCOMPLETE_NAME_CHARS};
+use gerbot::error_rates::ErrorRates;
+use gerbot::youtube::quota::QuotaEstimator;
+use gerbot::youtube::{YoutubeClient, YtApiError, YtProvider, YtSearchFilter};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use reqwest::Client as HttpClient;
+use serde_json::Value;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+fn fixture(name: &str) -> String {
+    let path = format!(
+        "{}/tests/fixtures/youtube/{name}",
+        env!("CARGO_MANIFEST_DIR")
+    );
+    std::fs::read_to_string(path).unwrap()
+}
+
+/// Answers like the api would for the fixtures, and forbids searches if `quota_exceeded`
+fn fake_api(request: &Request<Body>, quota_exceeded: bool) -> Response<Body> {
+    let query = request.uri().query().unwrap_or_default();
+    let (status, body) = match request.uri().path() {
+        "/search" if quota_exceeded => (StatusCode::FORBIDDEN, String::new()),
+        "/search" => (StatusCode::OK, fixture("search_videos.json")),
+        "/videos" if query.contains("id=liveStream01") => {
+            (StatusCode::OK, fixture("video_live.json"))
+        }
+        "/playlists" => (StatusCode::OK, fixture("playlist.json")),
+        "/playlistItems" => (StatusCode::OK, fixture("playlist_items_unavailable.json")),
+        _ => (StatusCode::NOT_FOUND, String::new()),
+    };
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// A client that only asks the fake api, and the number of requests it made
+fn client(quota_exceeded: bool) -> (YoutubeClient, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let make_service = make_service_fn(move |_| {
+        let counter = counter.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                let response = fake_api(&request, quota_exceeded);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let base_url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+
+    let client = YoutubeClient::new(
+        HttpClient::new(),
+        Some("key".to_owned()),
+        Arc::default(),
+        vec![YtProvider::Api],
+        QuotaEstimator::new(1_000, 50),
+        Arc::new(ErrorRates::default()),
+    )
+    .with_api_base_url(&base_url);
+    (client, requests)
+}
+
+fn choices(suggestions: YtSuggestions) -> Vec<(String, Value)> {
+    match suggestions {
+        YtSuggestions::Choices(choices) => choices
+            .into_iter()
+            .map(|choice| {
+                let choice = serde_json::to_value(choice).unwrap();
+                (
+                    choice["name"].as_str().unwrap().to_owned(),
+                    choice["value"].clone(),
+                )
+            })
+            .collect(),
+        _ => panic!("expected final choices"),
+    }
+}
+
+#[tokio::test]
+async fn short_input_asks_for_more() {
+    let (client, requests) = client(false);
+    let choices = choices(yt_suggestions(&client, "lo", YtSearchFilter::Videos).await);
+    assert_eq!(
+        choices,
+        [(
+            "Tippe weiter, um Suchvorschläge zu erhalten".to_owned(),
+            Value::from("lo")
+        )]
+    );
+    assert_eq!(requests.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn video_links_show_the_title() {
+    let (client, _) = client(false);
+    let link = "https://youtu.be/liveStream01";
+    let choices = choices(yt_suggestions(&client, link, YtSearchFilter::Videos).await);
+    assert_eq!(choices, [("Lofi radio 24/7".to_owned(), Value::from(link))]);
+}
+
+#[tokio::test]
+async fn unknown_video_links_keep_the_input() {
+    let (client, _) = client(false);
+    let link = "https://www.youtube.com/watch?v=deletedVid0";
+    let choices = choices(yt_suggestions(&client, link, YtSearchFilter::Videos).await);
+    assert_eq!(choices, [(link.to_owned(), Value::from(link))]);
+}
+
+#[tokio::test]
+async fn playlist_links_show_the_title() {
+    let (client, _) = client(false);
+    let link = "https://www.youtube.com/playlist?list=PLsanitizedPlaylist000000000000000";
+    let choices = choices(yt_suggestions(&client, link, YtSearchFilter::Playlists).await);
+    assert_eq!(choices, [("Mixed playlist".to_owned(), Value::from(link))]);
+}
+
+#[tokio::test]
+async fn other_links_are_not_searched() {
+    let (client, requests) = client(false);
+    let link = "https://example.com/song.mp3";
+    let choices = choices(yt_suggestions(&client, link, YtSearchFilter::Videos).await);
+    assert_eq!(choices, [(link.to_owned(), Value::from(link))]);
+    assert_eq!(requests.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn text_is_searched() {
+    let (client, _) = client(false);
+    let YtSuggestions::Results(results) =
+        yt_suggestions(&client, "lofi", YtSearchFilter::Videos).await
+    else {
+        panic!("expected search results");
+    };
+    let urls = results
+        .iter()
+        .map(|video| video.get_yt_url().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        urls,
+        [
+            "https://www.youtube.com/watch?v=searchVid01",
+            "https://www.youtube.com/watch?v=searchVid02",
+            "https://www.youtube.com/watch?v=liveStream01",
+        ]
+    );
+    assert!(results[1].title.chars().count() > AUTOCOMPLETE_NAME_CHARS);
+}
+
+#[tokio::test]
+async fn failed_searches_are_unavailable() {
+    let (client, _) = client(true);
+    let suggestions = yt_suggestions(&client, "lofi", YtSearchFilter::Videos).await;
+    assert!(matches!(
+        suggestions,
+        YtSuggestions::Unavailable(Some(YtApiError::QuotaExceeded))
+    ));
+}
+
+#[tokio::test]
+async fn searches_pause_when_the_quota_runs_low() {
+    let (client, requests) = client(false);
+    // Each search costs 100 units, 5 of them reach half of the daily 1000
+    for _ in 0..5 {
+        yt_suggestions(&client, "lofi", YtSearchFilter::Videos).await;
+    }
+    let before = requests.load(Ordering::SeqCst);
+    let suggestions = yt_suggestions(&client, "lofi", YtSearchFilter::Videos).await;
+    assert!(matches!(suggestions, YtSuggestions::Unavailable(None)));
+    assert_eq!(requests.load(Ordering::SeqCst), before);
+}
@@ -0,0 +1,127 @@
+//! The commands as they are registered with Discord, built without connecting to it
+
+use gerbot::alias::CommandAlias;
+use gerbot::{commands, CommandError, GlobalData};
+use serde_json::Value;
+use std::collections::HashSet;
+
+fn framework_options() -> poise::FrameworkOptions<GlobalData, CommandError> {
+    poise::FrameworkOptions {
+        commands: commands::all(),
+        ..Default::default()
+    }
+}
+
+/// The commands as json, the way they are sent to Discord
+fn registered() -> Vec<Value> {
+    gerbot::command_schema::registration(&framework_options().commands)
+        .into_iter()
+        .map(|command| serde_json::to_value(command).unwrap())
+        .collect()
+}
+
+/// Every option of a command, including those of its subcommands
+fn options(command: &Value) -> Vec<&Value> {
+    let mut options = Vec::new();
+    let mut pending = vec![command];
+    while let Some(value) = pending.pop() {
+        for option in value["options"].as_array().into_iter().flatten() {
+            options.push(option);
+            pending.push(option);
+        }
+    }
+    options
+}
+
+fn is_valid_name(name: &str) -> bool {
+    (1..=32).contains(&name.chars().count())
+        && name
+            .chars()
+            .all(|c| c.is_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+#[test]
+fn names_are_unique_and_valid() {
+    let options = framework_options();
+    let mut names = HashSet::new();
+    for command in &options.commands {
+        assert!(is_valid_name(&command.name), "/{}", command.name);
+        assert!(
+            names.insert(&command.name),
+            "/{} exists twice",
+            command.name
+        );
+    }
+}
+
+#[test]
+fn descriptions_fit_the_limits() {
+    for command in registered() {
+        let name = command["name"].as_str().unwrap();
+        let descriptions = std::iter::once(&command).chain(options(&command));
+        for value in descriptions {
+            let description = value["description"].as_str().unwrap();
+            assert!(
+                (1..=100).contains(&description.chars().count()),
+                "/{name}: {description:?}"
+            );
+            for localized in value["description_localizations"]
+                .as_object()
+                .into_iter()
+                .flat_map(|localizations| localizations.values())
+            {
+                let localized = localized.as_str().unwrap();
+                assert!(
+                    (1..=100).contains(&localized.chars().count()),
+                    "/{name}: {localized:?}"
+                );
+            }
+        }
+        for option in options(&command) {
+            assert!(
+                is_valid_name(option["name"].as_str().unwrap()),
+                "/{name}: {option}"
+            );
+        }
+    }
+}
+
+#[test]
+fn at_most_25_options_and_choices() {
+    for command in registered() {
+        let name = command["name"].as_str().unwrap();
+        for value in std::iter::once(&command).chain(options(&command)) {
+            for list in ["options", "choices"] {
+                let len = value[list].as_array().map_or(0, Vec::len);
+                assert!(len <= 25, "/{name} has {len} {list}");
+            }
+        }
+    }
+}
+
+#[test]
+fn aliases_copy_their_target_with_a_check() {
+    let commands = commands::all();
+    for alias in CommandAlias::ALL {
+        let command = commands.iter().find(|c| c.name == alias.name()).unwrap();
+        let target = commands.iter().find(|c| c.name == alias.target()).unwrap();
+        assert!(command.hide_in_help, "/{}", alias.name());
+        assert_eq!(command.checks.len(), target.checks.len() + 1);
+        assert_eq!(command.parameters.len(), target.parameters.len());
+        assert_eq!(command.guild_only, target.guild_only);
+        assert_eq!(
+            command.required_bot_permissions,
+            target.required_bot_permissions
+        );
+    }
+}
+
+#[test]
+fn help_lists_the_aliases_last() {
+    let commands = commands::all();
+    let first_alias = commands
+        .iter()
+        .position(|c| CommandAlias::from_name(&c.name).is_some())
+        .unwrap();
+    assert_eq!(first_alias, commands.len() - CommandAlias::ALL.len());
+}
@@ -0,0 +1,100 @@
+//! The guild settings store with and without a settings file
+
+use gerbot::alias::CommandAlias;
+use gerbot::guild_settings::{GuildSettings, GuildSettingsStore, QuietLevel};
+use gerbot::lifecycle::GuildPersisted;
+use gerbot::persistence::PersistenceHealth;
+use serenity::all::{GuildId, RoleId};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+const GUILD: GuildId = GuildId::new(1);
+const OTHER_GUILD: GuildId = GuildId::new(2);
+
+fn settings_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "gerbot-settings-store-{name}-{}.json",
+        std::process::id()
+    ))
+}
+
+#[tokio::test]
+async fn changes_survive_a_restart_with_new_versions() {
+    let path = settings_file("restart");
+    let health = Arc::new(PersistenceHealth::default());
+
+    let store = GuildSettingsStore::load(Some(path.clone()), health.clone());
+    store.update(GUILD, |settings| settings.dj_role = Some(RoleId::new(5)));
+    let saved = store.update(GUILD, |settings| {
+        settings.aliases.set(CommandAlias::Q, true);
+        settings.max_track_duration = Some(Duration::from_secs(600));
+    });
+    assert_eq!(store.get_versioned(GUILD).version, 2);
+
+    let reloaded = GuildSettingsStore::load(Some(path.clone()), health.clone());
+    std::fs::remove_file(&path).unwrap();
+    let versioned = reloaded.get_versioned(GUILD);
+    assert_eq!(versioned.settings, saved);
+    assert_eq!(versioned.version, 0);
+    assert!(!health.is_degraded());
+}
+
+#[tokio::test]
+async fn unreadable_files_are_kept_and_the_store_runs_on_defaults() {
+    let path = settings_file("unreadable");
+    std::fs::write(&path, "{ not json").unwrap();
+    let health = Arc::new(PersistenceHealth::default());
+
+    let store = GuildSettingsStore::load(Some(path.clone()), health.clone());
+    assert_eq!(store.get(GUILD), GuildSettings::default());
+    assert!(health.is_degraded());
+
+    // Changes work for the runtime, but never replace the file that needs fixing
+    let changed = store.update(GUILD, |settings| settings.quiet = QuietLevel::Quiet);
+    assert_eq!(store.get(GUILD), changed);
+    let content = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(content, "{ not json");
+}
+
+#[tokio::test]
+async fn purged_guilds_are_removed_from_the_file() {
+    let path = settings_file("purge");
+    let health = Arc::new(PersistenceHealth::default());
+
+    let store = GuildSettingsStore::load(Some(path.clone()), health.clone());
+    store.update(GUILD, |settings| settings.tts_announcements = true);
+    let kept = store.update(OTHER_GUILD, |settings| settings.share_button = false);
+    store.purge(GUILD);
+
+    let reloaded = GuildSettingsStore::load(Some(path.clone()), health);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(reloaded.get(GUILD), GuildSettings::default());
+    assert_eq!(reloaded.get(OTHER_GUILD), kept);
+}
+
+#[test]
+fn without_a_file_changes_stay_in_memory() {
+    let health = Arc::new(PersistenceHealth::default());
+    let store = GuildSettingsStore::load(None, health.clone());
+    let changed = store.update(GUILD, |settings| settings.soft_duration_limit = true);
+
+    assert_eq!(store.get(GUILD), changed);
+    assert_eq!(store.get(OTHER_GUILD), GuildSettings::default());
+    assert!(health.stores().is_empty());
+    assert!(!health.is_degraded());
+}
+
+#[test]
+fn stale_changes_are_refused() {
+    let store = GuildSettingsStore::load(None, Arc::default());
+    let shown = store.get_versioned(GUILD);
+    store.update(GUILD, |settings| settings.dj_role = Some(RoleId::new(7)));
+
+    let conflict = store
+        .compare_and_update(GUILD, shown.version, |settings| settings.dj_role = None)
+        .unwrap_err();
+    assert_eq!(conflict.current.settings.dj_role, Some(RoleId::new(7)));
+    assert_eq!(store.get(GUILD).dj_role, Some(RoleId::new(7)));
+}